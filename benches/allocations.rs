@@ -0,0 +1,137 @@
+//! Counts the heap allocations made while parsing instead of timing it, to keep an eye on the
+//! cloning of regions and other per-token allocations
+//!
+//! Only allocations through the Rust allocator are counted. Oniguruma allocates its regions with
+//! `malloc` directly, so run this with `--no-default-features --features default-fancy` to see
+//! the regions too.
+
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{criterion_group, criterion_main, Bencher, Criterion, Throughput};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use syntect::parsing::{ParseState, SyntaxReference, SyntaxSet};
+
+mod utils;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Measures the number of allocations and reallocations
+struct Allocations;
+
+impl Measurement for Allocations {
+    type Intermediate = u64;
+    type Value = u64;
+
+    fn start(&self) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: u64) -> u64 {
+        ALLOCATIONS.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &u64, v2: &u64) -> u64 {
+        v1 + v2
+    }
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn to_f64(&self, value: &u64) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &AllocationsFormatter
+    }
+}
+
+struct AllocationsFormatter;
+
+impl ValueFormatter for AllocationsFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+
+    fn scale_throughputs(
+        &self,
+        _typical_value: f64,
+        throughput: &Throughput,
+        values: &mut [f64],
+    ) -> &'static str {
+        let (count, unit) = match *throughput {
+            Throughput::Bytes(bytes) => (bytes, "allocs/byte"),
+            Throughput::Elements(elements) => (elements, "allocs/elem"),
+        };
+        for value in values {
+            *value /= count as f64;
+        }
+        unit
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "allocs"
+    }
+}
+
+fn do_parse(s: &str, ss: &SyntaxSet, syntax: &SyntaxReference) -> usize {
+    let mut state = ParseState::new(syntax);
+    let mut count = 0;
+    for line in s.lines() {
+        let ops = state.parse_line(line, ss).unwrap();
+        count += ops.len();
+    }
+    count
+}
+
+fn parse_file(b: &mut Bencher<'_, Allocations>, file: &str) {
+    let path = utils::get_test_file_path(file);
+    let ss = SyntaxSet::load_defaults_nonewlines();
+    let syntax = ss.find_syntax_for_file(path).unwrap().unwrap();
+    let s = std::fs::read_to_string(path).unwrap();
+    // compile the regexes first, which allocates a lot but only once
+    do_parse(&s, &ss, syntax);
+
+    b.iter(|| do_parse(&s, &ss, syntax));
+}
+
+fn allocations_benchmark(c: &mut Criterion<Allocations>) {
+    let mut parse = c.benchmark_group("parse_allocations");
+    for input in &["highlight_test.erb", "parser.rs"] {
+        parse.bench_with_input(format!("\"{}\"", input), input, |b, s| parse_file(b, s));
+    }
+    parse.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .with_measurement(Allocations)
+        .sample_size(10)
+        .warm_up_time(Duration::from_secs(1));
+    targets = allocations_benchmark
+}
+criterion_main!(benches);
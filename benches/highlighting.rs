@@ -1,8 +1,11 @@
 use criterion::{criterion_group, criterion_main, Bencher, Criterion};
 use std::str::FromStr;
-use syntect::highlighting::ThemeSet;
+use std::sync::Arc;
+use syntect::highlighting::{
+    HighlightIterator, HighlightState, Highlighter, PreparedTheme, Theme, ThemeBuilder, ThemeSet,
+};
 use syntect::html::highlighted_html_for_string;
-use syntect::parsing::{ScopeStack, SyntaxSet};
+use syntect::parsing::{ParseState, ScopeStack, ScopeStackOp, SyntaxSet};
 
 mod highlight_utils;
 mod utils;
@@ -38,9 +41,145 @@ fn highlight_html(b: &mut Bencher) {
     b.iter(|| highlighted_html_for_string(&s, &ss, syntax, &ts.themes["base16-ocean.dark"]));
 }
 
+/// Compares `clone` and `clone_from` for caching a `HighlightState` after every line, which is
+/// what editors typically do.
+fn cache_states(b: &mut Bencher, reuse: bool) {
+    let ss = SyntaxSet::load_defaults_nonewlines();
+    let ts = ThemeSet::load_defaults();
+    let syntax = ss.find_syntax_by_extension("js").unwrap();
+    let s = std::fs::read_to_string(utils::get_test_file_path("jquery.js")).unwrap();
+
+    let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+    let mut parse_state = ParseState::new(syntax);
+    let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+    let mut states = Vec::new();
+    for line in s.lines() {
+        let ops = parse_state.parse_line(line, &ss).unwrap();
+        HighlightIterator::new(&mut highlight_state, &ops, line, &highlighter).for_each(drop);
+        states.push(highlight_state.clone());
+    }
+
+    let mut cache = states.clone();
+    b.iter(|| {
+        for (cached, state) in cache.iter_mut().zip(states.iter().rev()) {
+            if reuse {
+                cached.clone_from(state);
+            } else {
+                *cached = state.clone();
+            }
+        }
+    });
+}
+
+/// Adds lots of rules with selectors made of several scopes, which are the expensive ones to
+/// match, to a theme. Most of them never match, like in big real-world themes.
+fn rule_heavy_theme(base: &Theme) -> Theme {
+    let mut builder = ThemeBuilder::from_theme(base.clone());
+    for i in 0..100 {
+        builder = builder
+            .scope(&format!("source.js meta.unused{} variable", i), |s| {
+                s.bold()
+            })
+            .scope(&format!("meta.block.js string.unused{}", i), |s| s.italic())
+            .scope(
+                &format!("meta.group.js keyword.unused{} - comment", i),
+                |s| s.underline(),
+            );
+    }
+    builder.build().unwrap()
+}
+
+/// Only measures applying the theme, the file is parsed up front.
+fn highlight_rule_heavy_theme(b: &mut Bencher) {
+    let ss = SyntaxSet::load_defaults_nonewlines();
+    let ts = ThemeSet::load_defaults();
+    let syntax = ss.find_syntax_by_extension("js").unwrap();
+    let s = std::fs::read_to_string(utils::get_test_file_path("jquery.js")).unwrap();
+
+    let mut parse_state = ParseState::new(syntax);
+    let lines: Vec<_> = s
+        .lines()
+        .map(|line| (line, parse_state.parse_line(line, &ss).unwrap()))
+        .collect();
+
+    let theme = rule_heavy_theme(&ts.themes["base16-ocean.dark"]);
+    let highlighter = Highlighter::new(&theme);
+    b.iter(|| {
+        let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+        let mut count = 0;
+        for (line, ops) in &lines {
+            count += HighlightIterator::new(&mut highlight_state, ops, line, &highlighter).count();
+        }
+        count
+    });
+}
+
+/// Highlights a file in small tasks spread over a few threads, like a worker pool highlighting
+/// many files would, either sharing one prepared theme or creating a highlighter in each task.
+/// Only applying the theme is measured, the file is parsed up front.
+fn highlight_in_worker_pool(b: &mut Bencher, share_prepared: bool) {
+    let ss = SyntaxSet::load_defaults_nonewlines();
+    let ts = ThemeSet::load_defaults();
+    let syntax = ss.find_syntax_by_extension("js").unwrap();
+    let s = std::fs::read_to_string(utils::get_test_file_path("jquery.js")).unwrap();
+
+    let mut parse_state = ParseState::new(syntax);
+    let lines: Vec<_> = s
+        .lines()
+        .map(|line| (line, parse_state.parse_line(line, &ss).unwrap()))
+        .collect();
+    let tasks: Vec<_> = lines.chunks(50).collect();
+
+    let theme = rule_heavy_theme(&ts.themes["base16-ocean.dark"]);
+    let prepared = Arc::new(PreparedTheme::new(theme.clone()));
+    let highlight_task = |highlighter: &Highlighter,
+                          task: &[(&str, Vec<(usize, ScopeStackOp)>)]| {
+        let mut highlight_state = HighlightState::new(highlighter, ScopeStack::new());
+        let mut count = 0;
+        for (line, ops) in task {
+            count += HighlightIterator::new(&mut highlight_state, ops, line, highlighter).count();
+        }
+        count
+    };
+    b.iter(|| {
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|worker| {
+                    let (prepared, theme, tasks) = (prepared.clone(), &theme, &tasks);
+                    let highlight_task = &highlight_task;
+                    scope.spawn(move || {
+                        let mut count = 0;
+                        for task in tasks.iter().skip(worker).step_by(4) {
+                            count += if share_prepared {
+                                highlight_task(prepared.highlighter(), task)
+                            } else {
+                                highlight_task(&Highlighter::new(theme), task)
+                            };
+                        }
+                        count
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().unwrap())
+                .sum::<usize>()
+        })
+    });
+}
+
 fn highlighting_benchmark(c: &mut Criterion) {
     c.bench_function("stack_matching", stack_matching);
+    c.bench_function("cache_states_clone", |b| cache_states(b, false));
+    c.bench_function("cache_states_clone_from", |b| cache_states(b, true));
     c.bench_function("highlight_html", highlight_html);
+    c.bench_function("highlight_rule_heavy_theme", highlight_rule_heavy_theme);
+    c.bench_function("worker_pool_new_highlighters", |b| {
+        highlight_in_worker_pool(b, false)
+    });
+    c.bench_function("worker_pool_shared_prepared_theme", |b| {
+        highlight_in_worker_pool(b, true)
+    });
     let mut highlight = c.benchmark_group("highlight");
     for input in &[
         "highlight_test.erb",
@@ -1,20 +1,81 @@
 //! This program is mainly intended for generating the dumps that are compiled in to
 //! syntect, not as a helpful example for beginners.
 //! Although it is a valid example for serializing syntaxes, you probably won't need
-//! to do this yourself unless you want to cache your own compiled grammars.
+//! to do this yourself unless you want to cache your own compiled grammars, and then
+//! `build_syntax_dump_from_folders` and `build_theme_dump` can be called directly.
 //!
 //! An example of how this script is used to generate the pack files included
 //! with syntect can be found under `make packs` in the Makefile.
 use std::env;
+use std::path::Path;
 use syntect::dumps::*;
-use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSetBuilder;
 
+/// The packages in each of the `default-syntaxes-*` features
+const FAMILIES: &[(&str, &[&str])] = &[
+    (
+        "web",
+        &[
+            "ASP",
+            "ActionScript",
+            "CSS",
+            "HTML",
+            "JavaScript",
+            "Markdown",
+            "PHP",
+            "RestructuredText",
+            "Textile",
+            "XML",
+            "YAML",
+        ],
+    ),
+    (
+        "systems",
+        &[
+            "C#",
+            "C++",
+            "D",
+            "Go",
+            "Haskell",
+            "Java",
+            "Makefile",
+            "OCaml",
+            "Objective-C",
+            "Pascal",
+            "Rust",
+            "Scala",
+        ],
+    ),
+    (
+        "scripting",
+        &[
+            "AppleScript",
+            "Batch File",
+            "Clojure",
+            "Erlang",
+            "Groovy",
+            "Lisp",
+            "Lua",
+            "Matlab",
+            "Perl",
+            "Python",
+            "R",
+            "Rails",
+            "Regular Expressions",
+            "Ruby",
+            "SQL",
+            "ShellScript",
+            "TCL",
+        ],
+    ),
+];
+
 fn usage_and_exit() -> ! {
     println!(
         "USAGE: gendata synpack source-dir \
               newlines.packdump nonewlines.packdump \
               [metadata.packdump] [metadata extra-source-dir]\n       \
+              gendata synpack-families source-dir output-dir\n       \
               gendata themepack source-dir themepack.themedump"
     );
     ::std::process::exit(2);
@@ -31,40 +92,63 @@ fn main() {
             ref _option_metapath,
             ref _option_metasource,
         ) if cmd == "synpack" => {
-            let mut builder = SyntaxSetBuilder::new();
-            builder.add_plain_text_syntax();
-            builder.add_from_folder(package_dir, true).unwrap();
-            let ss = builder.build();
-            dump_to_uncompressed_file(&ss, packpath_newlines).unwrap();
-
-            let mut builder_nonewlines = SyntaxSetBuilder::new();
-            builder_nonewlines.add_plain_text_syntax();
-            builder_nonewlines
-                .add_from_folder(package_dir, false)
-                .unwrap();
+            let newlines =
+                build_syntax_dump_from_folders(&[package_dir], &SyntaxDumpOptions::default())
+                    .unwrap();
+            std::fs::write(packpath_newlines, newlines.syntaxes).unwrap();
 
+            #[allow(unused_mut)]
+            let mut folders = vec![package_dir];
             #[cfg(feature = "metadata")]
-            {
-                if let Some(metasource) = _option_metasource {
-                    builder_nonewlines
-                        .add_from_folder(metasource, false)
-                        .unwrap();
-                }
-            }
-
-            let ss_nonewlines = builder_nonewlines.build();
-            dump_to_uncompressed_file(&ss_nonewlines, packpath_nonewlines).unwrap();
+            folders.extend(_option_metasource);
+            let nonewlines = build_syntax_dump_from_folders(
+                &folders,
+                &SyntaxDumpOptions {
+                    lines_include_newline: false,
+                    ..SyntaxDumpOptions::default()
+                },
+            )
+            .unwrap();
+            std::fs::write(packpath_nonewlines, nonewlines.syntaxes).unwrap();
 
             #[cfg(feature = "metadata")]
             {
                 if let Some(metapath) = _option_metapath {
-                    dump_to_file(&ss_nonewlines.metadata(), metapath).unwrap();
+                    std::fs::write(metapath, nonewlines.metadata).unwrap();
+                }
+            }
+        }
+        (Some(ref cmd), Some(ref package_dir), Some(ref output_dir), ..)
+            if cmd == "synpack-families" =>
+        {
+            let output_dir = Path::new(output_dir);
+            for (family, packages) in FAMILIES {
+                let folders: Vec<_> = packages
+                    .iter()
+                    .map(|package| Path::new(package_dir).join(package))
+                    .collect();
+                for (lines, lines_include_newline) in [("newlines", true), ("nonewlines", false)] {
+                    // The plain text syntax is added once when the families are loaded, so that
+                    // embedded syntaxes from other families can be linked then
+                    let options = SyntaxDumpOptions {
+                        lines_include_newline,
+                        include_plain_text: false,
+                        ..SyntaxDumpOptions::default()
+                    };
+                    let dump = build_syntax_dump_from_folders(&folders, &options).unwrap();
+                    let path = format!("default_{}_{}.packdump", family, lines);
+                    std::fs::write(output_dir.join(path), dump.syntaxes).unwrap();
                 }
             }
+
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add_plain_text_syntax();
+            let path = output_dir.join("default_plain_text.packdump");
+            dump_to_uncompressed_file(&builder.build(), path).unwrap();
         }
         (Some(ref s), Some(ref theme_dir), Some(ref packpath), ..) if s == "themepack" => {
-            let ts = ThemeSet::load_from_folder(theme_dir).unwrap();
-            dump_to_file(&ts, packpath).unwrap();
+            let dump = build_theme_dump(&[theme_dir]).unwrap();
+            std::fs::write(packpath, dump).unwrap();
         }
         _ => usage_and_exit(),
     }
@@ -0,0 +1,46 @@
+//! Prints the scopes at a position in a file and the patterns they came from,
+//! for finding out why something is highlighted the way it is.
+//!
+//! Usage: `scopesource <file> <line> <column>`, with the line and the byte
+//! column in it counting from 1 like editors do.
+use syntect::parsing::{locate_scope_source, SyntaxSet};
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 4 {
+        println!("Usage: scopesource <file> <line> <column>");
+        return;
+    }
+    let line: usize = args[2].parse().expect("line must be a number");
+    let col: usize = args[3].parse().expect("column must be a number");
+
+    let ss = SyntaxSet::load_defaults_newlines();
+    let text = std::fs::read_to_string(&args[1]).unwrap();
+    let syntax = ss
+        .find_syntax_for_file(&args[1])
+        .unwrap()
+        .unwrap_or_else(|| ss.find_syntax_plain_text());
+
+    let sources = locate_scope_source(
+        &text,
+        line.saturating_sub(1),
+        col.saturating_sub(1),
+        syntax,
+        &ss,
+    )
+    .unwrap();
+    for source in sources {
+        print!("{}", source.scope);
+        match source.trace {
+            Some(trace) => println!(
+                "\n    from {} pattern {}: {}\n    matched at {}:{}",
+                trace.context,
+                trace.pattern_index,
+                trace.regex,
+                source.line + 1,
+                trace.range.start + 1
+            ),
+            None => println!("\n    from the syntax {}", syntax.name),
+        }
+    }
+}
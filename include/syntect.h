@@ -0,0 +1,53 @@
+/*
+ * C interface to syntect, built with the `capi` feature.
+ * See the docs of the `syntect::capi` module for details.
+ */
+#ifndef SYNTECT_H
+#define SYNTECT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque handles, only ever used through pointers */
+typedef struct SyntectSyntaxSet SyntectSyntaxSet;
+typedef struct SyntectThemeSet SyntectThemeSet;
+
+/* A description of the last error on this thread, or NULL. Owned by syntect. */
+const char *syntect_last_error(void);
+
+/* Frees a string returned by syntect. Accepts NULL. */
+void syntect_free_string(char *s);
+
+/* The default syntaxes, free with syntect_syntaxset_free. */
+SyntectSyntaxSet *syntect_syntaxset_load_default(void);
+void syntect_syntaxset_free(SyntectSyntaxSet *ss);
+
+/* The default themes, free with syntect_themeset_free. */
+SyntectThemeSet *syntect_themeset_load_default(void);
+void syntect_themeset_free(SyntectThemeSet *ts);
+
+/* Adds a theme from the contents of a .tmTheme file. Returns 0 on success, -1 on failure. */
+int syntect_themeset_add_theme(SyntectThemeSet *ts,
+                               const char *name,
+                               const uint8_t *data,
+                               size_t len);
+
+/*
+ * Highlights code as an HTML <pre> snippet with inline styles. `lang` is a language name or
+ * file extension. Returns NULL on failure, free the result with syntect_free_string.
+ */
+char *syntect_highlight_to_html(const SyntectSyntaxSet *ss,
+                                const SyntectThemeSet *ts,
+                                const char *code,
+                                const char *lang,
+                                const char *theme_name);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SYNTECT_H */
@@ -0,0 +1,357 @@
+//! `syncat`, a command line tool for highlighting files with syntect
+//!
+//! Built with the `cli` feature: `cargo install syntect --features cli`.
+use getopts::{Matches, Options};
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use syntect::dumps::{dump_to_file, dump_to_uncompressed_file};
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Highlighter, Theme, ThemeSet};
+use syntect::html::{
+    css_for_theme_with_class_style, highlighted_html_for_string_with_lines, ClassStyle,
+    ClassedHTMLGenerator, LineOptions,
+};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use syntect::util::{as_24_bit_terminal_escaped, ops_to_json_tokens, LinesWithEndings};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ansi,
+    Html,
+    ClassedHtml,
+    Json,
+}
+
+impl Format {
+    fn parse(s: &str) -> Result<Format> {
+        match s {
+            "ansi" => Ok(Format::Ansi),
+            "html" => Ok(Format::Html),
+            "classed-html" => Ok(Format::ClassedHtml),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown output format {:?}", s).into()),
+        }
+    }
+}
+
+fn options() -> Options {
+    let mut opts = Options::new();
+    opts.optopt(
+        "t",
+        "theme",
+        "THEME",
+        "Theme to use, either the name of a loaded theme or the path to a .tmTheme file. \
+         Default: base16-ocean.dark",
+    );
+    opts.optopt(
+        "f",
+        "format",
+        "FORMAT",
+        "Output format: ansi, html, classed-html or json. Default: ansi",
+    );
+    opts.optopt(
+        "r",
+        "lines",
+        "START:END",
+        "Only output the lines in the inclusive range, e.g. 10:20, 10: or :20",
+    );
+    opts.optopt(
+        "",
+        "syntax",
+        "SYNTAX",
+        "Syntax to use, by name or extension, instead of detecting it",
+    );
+    opts.optmulti(
+        "s",
+        "extra-syntaxes",
+        "FOLDER",
+        "Additional folder to load .sublime-syntax files from",
+    );
+    opts.optflag(
+        "e",
+        "no-default-syntaxes",
+        "Doesn't load the default syntaxes, intended for use with --extra-syntaxes",
+    );
+    opts.optmulti(
+        "",
+        "extra-themes",
+        "FOLDER",
+        "Additional folder to load .tmTheme files from",
+    );
+    opts.optflag("l", "list-file-types", "Lists the loaded syntaxes");
+    opts.optflag("L", "list-themes", "Lists the loaded themes");
+    opts.optflag(
+        "",
+        "css",
+        "Prints the CSS for the theme, for use with the classed-html format",
+    );
+    opts.optopt(
+        "",
+        "dump-syntaxes",
+        "FILE",
+        "Writes the loaded syntaxes to a .packdump file",
+    );
+    opts.optopt(
+        "",
+        "dump-themes",
+        "FILE",
+        "Writes the loaded themes to a .themedump file",
+    );
+    opts.optflag("h", "help", "Prints this help");
+    opts
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let opts = options();
+    let usage = opts.usage(&format!("USAGE: {} [options] [FILES]", args[0]));
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, usage);
+            std::process::exit(2);
+        }
+    };
+    if matches.opt_present("help") {
+        println!("{}", usage);
+        return;
+    }
+    if let Err(e) = run(&matches) {
+        // e.g. when piped to `head`
+        let broken_pipe = matches!(
+            e.downcast_ref::<io::Error>(),
+            Some(e) if e.kind() == io::ErrorKind::BrokenPipe
+        );
+        if !broken_pipe {
+            eprintln!("syncat: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(matches: &Matches) -> Result<()> {
+    let mut ss = if matches.opt_present("no-default-syntaxes") {
+        SyntaxSet::new()
+    } else {
+        SyntaxSet::load_defaults_newlines()
+    };
+    let extra_syntaxes = matches.opt_strs("extra-syntaxes");
+    if !extra_syntaxes.is_empty() {
+        let mut builder = ss.into_builder();
+        for folder in extra_syntaxes {
+            builder.add_from_folder(folder, true)?;
+        }
+        ss = builder.build();
+    }
+
+    let mut ts = ThemeSet::load_defaults();
+    for folder in matches.opt_strs("extra-themes") {
+        ts.add_from_folder(folder)?;
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut done = false;
+    if let Some(path) = matches.opt_str("dump-syntaxes") {
+        dump_to_uncompressed_file(&ss, path)?;
+        done = true;
+    }
+    if let Some(path) = matches.opt_str("dump-themes") {
+        dump_to_file(&ts, path)?;
+        done = true;
+    }
+    if matches.opt_present("list-file-types") {
+        for sd in ss.syntaxes() {
+            writeln!(out, "- {} (.{})", sd.name, sd.file_extensions.join(", ."))?;
+        }
+        done = true;
+    }
+    if matches.opt_present("list-themes") {
+        for name in ts.themes.keys() {
+            writeln!(out, "- {}", name)?;
+        }
+        done = true;
+    }
+
+    let theme_name = matches
+        .opt_str("theme")
+        .unwrap_or_else(|| "base16-ocean.dark".to_owned());
+    let theme = match ts.themes.get(&theme_name) {
+        Some(theme) => theme.clone(),
+        None => ThemeSet::get_theme(&theme_name)
+            .map_err(|e| format!("can't load theme {:?}: {}", theme_name, e))?,
+    };
+    if matches.opt_present("css") {
+        let css = css_for_theme_with_class_style(&theme, ClassStyle::Spaced)?;
+        write!(out, "{}", css)?;
+        done = true;
+    }
+    if done && matches.free.is_empty() {
+        return Ok(());
+    }
+
+    let format = match matches.opt_str("format") {
+        Some(format) => Format::parse(&format)?,
+        None => Format::Ansi,
+    };
+    let range = match matches.opt_str("lines") {
+        Some(range) => parse_line_range(&range)?,
+        None => 1..=usize::MAX,
+    };
+    let files = if matches.free.is_empty() {
+        vec!["-".to_owned()]
+    } else {
+        matches.free.clone()
+    };
+
+    for file in &files {
+        let text = if file == "-" {
+            let mut text = String::new();
+            io::stdin().read_to_string(&mut text)?;
+            text
+        } else {
+            std::fs::read_to_string(file).map_err(|e| format!("{}: {}", file, e))?
+        };
+        let syntax = find_syntax(&ss, matches.opt_str("syntax"), file, &text)?;
+        if files.len() > 1 && format == Format::Ansi {
+            writeln!(out, "==> {} <==", file)?;
+        }
+        let highlighted = Highlighted {
+            text: &text,
+            ss: &ss,
+            syntax,
+            theme: &theme,
+            range: range.clone(),
+        };
+        match format {
+            Format::Ansi => highlighted.write_ansi(&mut out)?,
+            Format::Html => highlighted.write_html(&mut out)?,
+            Format::ClassedHtml => highlighted.write_classed_html(&mut out)?,
+            Format::Json => highlighted.write_json(&mut out, file)?,
+        }
+    }
+    Ok(())
+}
+
+/// Parses `START:END`, `START:`, `:END` or a single line number
+fn parse_line_range(s: &str) -> Result<RangeInclusive<usize>> {
+    let parse = |n: &str, default: usize| -> Result<usize> {
+        if n.is_empty() {
+            Ok(default)
+        } else {
+            n.parse()
+                .map_err(|_| format!("invalid line number {:?}", n).into())
+        }
+    };
+    let (start, end) = match s.split_once(':') {
+        Some((start, end)) => (parse(start, 1)?, parse(end, usize::MAX)?),
+        None => {
+            let line = parse(s, 1)?;
+            (line, line)
+        }
+    };
+    if start == 0 || start > end {
+        return Err(format!("invalid line range {:?}", s).into());
+    }
+    Ok(start..=end)
+}
+
+fn find_syntax<'a>(
+    ss: &'a SyntaxSet,
+    name: Option<String>,
+    file: &str,
+    text: &str,
+) -> Result<&'a SyntaxReference> {
+    if let Some(name) = name {
+        return ss
+            .find_syntax_by_token(&name)
+            .ok_or_else(|| format!("no syntax for {:?}", name).into());
+    }
+    let path = Path::new(file);
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let first_line = text.lines().next().unwrap_or("");
+    Ok(ss
+        .find_syntax_by_extension(file_name)
+        .or_else(|| ss.find_syntax_by_extension(extension))
+        .or_else(|| ss.find_syntax_by_first_line(first_line))
+        .unwrap_or_else(|| ss.find_syntax_plain_text()))
+}
+
+struct Highlighted<'a> {
+    text: &'a str,
+    ss: &'a SyntaxSet,
+    syntax: &'a SyntaxReference,
+    theme: &'a Theme,
+    range: RangeInclusive<usize>,
+}
+
+impl<'a> Highlighted<'a> {
+    /// Lines outside of the range, which still have to be highlighted for the ones after them
+    fn hidden_lines(&self) -> LineOptions {
+        let count = LinesWithEndings::from(self.text).count();
+        LineOptions::new().hide((1..=count).filter(|n| !self.range.contains(n)))
+    }
+
+    fn write_ansi<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut highlighter = HighlightLines::new(self.syntax, self.theme);
+        for (i, line) in LinesWithEndings::from(self.text).enumerate() {
+            let regions = highlighter.highlight_line(line, self.ss)?;
+            if self.range.contains(&(i + 1)) {
+                write!(out, "{}", as_24_bit_terminal_escaped(&regions[..], true))?;
+            }
+        }
+        // Clear the formatting
+        writeln!(out, "\x1b[0m")?;
+        Ok(())
+    }
+
+    fn write_html<W: Write>(&self, out: &mut W) -> Result<()> {
+        let html = highlighted_html_for_string_with_lines(
+            self.text,
+            self.ss,
+            self.syntax,
+            self.theme,
+            &self.hidden_lines(),
+        )?;
+        write!(out, "{}", html)?;
+        Ok(())
+    }
+
+    fn write_classed_html<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut generator =
+            ClassedHTMLGenerator::new_with_class_style(self.syntax, self.ss, ClassStyle::Spaced)
+                .with_line_options(self.hidden_lines());
+        for line in LinesWithEndings::from(self.text) {
+            generator.parse_html_for_line_which_includes_newline(line)?;
+        }
+        writeln!(out, "<pre class=\"code\">{}</pre>", generator.finalize())?;
+        Ok(())
+    }
+
+    /// Writes a JSON object with the tokens of each line, see `syntect::util::to_json_tokens`
+    fn write_json<W: Write>(&self, out: &mut W, file: &str) -> Result<()> {
+        let highlighter = Highlighter::new(self.theme);
+        let mut state = ParseState::new(self.syntax);
+        let mut stack = ScopeStack::new();
+        let mut lines = Vec::new();
+        for (i, line) in LinesWithEndings::from(self.text).enumerate() {
+            let ops = state.parse_line(line, self.ss)?;
+            let tokens = ops_to_json_tokens(line, &ops, &mut stack, Some(&highlighter))?;
+            if self.range.contains(&(i + 1)) {
+                lines.push(serde_json::json!({ "line": i + 1, "tokens": tokens }));
+            }
+        }
+        let json = serde_json::json!({
+            "file": file,
+            "syntax": self.syntax.name,
+            "lines": lines,
+        });
+        writeln!(out, "{}", json)?;
+        Ok(())
+    }
+}
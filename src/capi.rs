@@ -0,0 +1,246 @@
+//! A C ABI for using syntect from other languages, enabled with the `capi` feature
+//!
+//! The functions are declared in `include/syntect.h`. Build a shared library with
+//! `cargo rustc --release --no-default-features --features capi,regex-onig --crate-type cdylib`.
+//!
+//! Syntax and theme sets are passed around as opaque pointers that must be freed with the
+//! matching `_free` function, and strings returned by syntect must be freed with
+//! [`syntect_free_string`]. Functions that can fail return `NULL` or a negative number, and
+//! [`syntect_last_error`] describes what went wrong.
+//!
+//! [`syntect_free_string`]: fn.syntect_free_string.html
+//! [`syntect_last_error`]: fn.syntect_last_error.html
+use crate::highlighting::ThemeSet;
+use crate::html::highlighted_html_for_string;
+use crate::parsing::SyntaxSet;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // the message came from a Rust string, so it can only contain a NUL if we put it there
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, recording its error or panic for `syntect_last_error`
+fn catch<T, F: FnOnce() -> Result<T, String>>(f: F) -> Option<T> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => Some(value),
+        Ok(Err(message)) => {
+            set_last_error(message);
+            None
+        }
+        Err(_) => {
+            set_last_error("syntect panicked".to_owned());
+            None
+        }
+    }
+}
+
+/// # Safety
+///
+/// `s` must be `NULL` or a valid NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| format!("{} isn't valid UTF-8", name))
+}
+
+/// Returns a description of the last error on this thread, or `NULL` if there wasn't one
+///
+/// The string is owned by syntect and stays valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn syntect_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Frees a string returned by syntect
+///
+/// # Safety
+///
+/// `s` must be `NULL` or a string returned by syntect that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn syntect_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Loads the default syntaxes, see [`SyntaxSet::load_defaults_newlines`]
+///
+/// [`SyntaxSet::load_defaults_newlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+#[no_mangle]
+pub extern "C" fn syntect_syntaxset_load_default() -> *mut SyntaxSet {
+    catch(|| Ok(SyntaxSet::load_defaults_newlines()))
+        .map_or(ptr::null_mut(), |ss| Box::into_raw(Box::new(ss)))
+}
+
+/// Frees a syntax set
+///
+/// # Safety
+///
+/// `ss` must be `NULL` or a syntax set returned by syntect that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn syntect_syntaxset_free(ss: *mut SyntaxSet) {
+    if !ss.is_null() {
+        drop(Box::from_raw(ss));
+    }
+}
+
+/// Loads the default themes, see [`ThemeSet::load_defaults`]
+///
+/// [`ThemeSet::load_defaults`]: ../highlighting/struct.ThemeSet.html#method.load_defaults
+#[no_mangle]
+pub extern "C" fn syntect_themeset_load_default() -> *mut ThemeSet {
+    catch(|| Ok(ThemeSet::load_defaults()))
+        .map_or(ptr::null_mut(), |ts| Box::into_raw(Box::new(ts)))
+}
+
+/// Frees a theme set
+///
+/// # Safety
+///
+/// `ts` must be `NULL` or a theme set returned by syntect that wasn't freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn syntect_themeset_free(ts: *mut ThemeSet) {
+    if !ts.is_null() {
+        drop(Box::from_raw(ts));
+    }
+}
+
+/// Adds the theme in the `len` bytes of `.tmTheme` data at `data` to the set as `name`,
+/// replacing any theme with that name. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `ts` must be a live theme set, `name` a NUL-terminated string and `data` must point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn syntect_themeset_add_theme(
+    ts: *mut ThemeSet,
+    name: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let added = catch(|| {
+        let ts = ts.as_mut().ok_or("theme set is NULL")?;
+        let name = str_arg(name, "name")?;
+        if data.is_null() {
+            return Err("data is NULL".to_owned());
+        }
+        let bytes = std::slice::from_raw_parts(data, len);
+        let theme = ThemeSet::load_from_bytes(bytes).map_err(|e| e.to_string())?;
+        ts.themes.insert(name.to_owned(), theme);
+        Ok(())
+    });
+    match added {
+        Some(()) => 0,
+        None => -1,
+    }
+}
+
+/// Highlights `code` as an HTML `<pre>` snippet with inline styles, see
+/// [`highlighted_html_for_string`]
+///
+/// `lang` is a language name or file extension, and unknown languages are shown as plain text.
+/// Returns `NULL` if the theme doesn't exist or an argument is invalid. The result must be freed
+/// with `syntect_free_string`.
+///
+/// # Safety
+///
+/// `ss` and `ts` must be live sets returned by syntect, and the strings must be NUL-terminated.
+///
+/// [`highlighted_html_for_string`]: ../html/fn.highlighted_html_for_string.html
+#[no_mangle]
+pub unsafe extern "C" fn syntect_highlight_to_html(
+    ss: *const SyntaxSet,
+    ts: *const ThemeSet,
+    code: *const c_char,
+    lang: *const c_char,
+    theme_name: *const c_char,
+) -> *mut c_char {
+    let html = catch(|| {
+        let ss = ss.as_ref().ok_or("syntax set is NULL")?;
+        let ts = ts.as_ref().ok_or("theme set is NULL")?;
+        let code = str_arg(code, "code")?;
+        let lang = str_arg(lang, "lang")?;
+        let theme_name = str_arg(theme_name, "theme name")?;
+        let theme = ts
+            .themes
+            .get(theme_name)
+            .ok_or_else(|| format!("no theme named {:?}", theme_name))?;
+        let syntax = ss
+            .find_syntax_by_token(lang)
+            .unwrap_or_else(|| ss.find_syntax_plain_text());
+        let html =
+            highlighted_html_for_string(code, ss, syntax, theme).map_err(|e| e.to_string())?;
+        CString::new(html).map_err(|_| "code contains a NUL character".to_owned())
+    });
+    html.map_or(ptr::null_mut(), CString::into_raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlights_through_the_c_api() {
+        unsafe {
+            let ss = syntect_syntaxset_load_default();
+            let ts = syntect_themeset_load_default();
+            let code = CString::new("fn main() {}\n").unwrap();
+            let lang = CString::new("rs").unwrap();
+            let theme = CString::new("base16-ocean.dark").unwrap();
+            let html =
+                syntect_highlight_to_html(ss, ts, code.as_ptr(), lang.as_ptr(), theme.as_ptr());
+            assert!(!html.is_null());
+            assert!(CStr::from_ptr(html).to_str().unwrap().starts_with("<pre"));
+            syntect_free_string(html);
+
+            let missing = CString::new("missing").unwrap();
+            let html =
+                syntect_highlight_to_html(ss, ts, code.as_ptr(), lang.as_ptr(), missing.as_ptr());
+            assert!(html.is_null());
+            let error = CStr::from_ptr(syntect_last_error()).to_str().unwrap();
+            assert_eq!(error, "no theme named \"missing\"");
+
+            let data = b"not a theme";
+            let added = syntect_themeset_add_theme(ts, missing.as_ptr(), data.as_ptr(), data.len());
+            assert_eq!(added, -1);
+
+            syntect_themeset_free(ts);
+            syntect_syntaxset_free(ss);
+        }
+    }
+
+    #[test]
+    fn header_declares_every_function() {
+        let header = include_str!("../include/syntect.h");
+        for name in [
+            "syntect_last_error",
+            "syntect_free_string",
+            "syntect_syntaxset_load_default",
+            "syntect_syntaxset_free",
+            "syntect_themeset_load_default",
+            "syntect_themeset_free",
+            "syntect_themeset_add_theme",
+            "syntect_highlight_to_html",
+        ] {
+            assert!(
+                header.contains(&format!("{}(", name)),
+                "{} is missing",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Highlighting the code blocks of Markdown parsed by [pulldown-cmark]
+//!
+//! The functions here wrap the stream of events of a [`pulldown_cmark::Parser`] and replace each
+//! code block with an HTML event with the highlighted code, so it can be passed on to
+//! `pulldown_cmark::html::push_html` or any other consumer of events.
+//!
+//! [pulldown-cmark]: https://github.com/pulldown-cmark/pulldown-cmark
+//! [`pulldown_cmark::Parser`]: https://docs.rs/pulldown-cmark/0.13/pulldown_cmark/struct.Parser.html
+
+use crate::easy::info_string_language;
+use crate::escape::Escape;
+use crate::highlighting::Theme;
+use crate::html::{highlighted_html_for_string, ClassStyle, ClassedHTMLGenerator};
+use crate::parsing::{SyntaxReference, SyntaxSet};
+use crate::util::LinesWithEndings;
+use crate::Error;
+use pulldown_cmark::{CodeBlockKind, Event, Tag, TagEnd};
+
+/// An iterator over Markdown events with the code blocks highlighted, created by
+/// [`highlight_code_blocks`] or [`highlight_code_blocks_with_classes`]
+///
+/// [`highlight_code_blocks`]: fn.highlight_code_blocks.html
+/// [`highlight_code_blocks_with_classes`]: fn.highlight_code_blocks_with_classes.html
+#[derive(Debug)]
+pub struct HighlightCodeBlocks<'a, I> {
+    events: I,
+    syntax_set: &'a SyntaxSet,
+    style: CodeBlockStyle<'a>,
+}
+
+#[derive(Debug)]
+enum CodeBlockStyle<'a> {
+    Inline(&'a Theme),
+    Classed(ClassStyle),
+}
+
+/// Highlights the code blocks of Markdown events with inline styles from a theme
+///
+/// Fenced code blocks are highlighted with the syntax found by
+/// [`SyntaxSet::find_syntax_by_token`] for the first word of their info string, and other code
+/// blocks as plain text. Each block becomes an [`Event::Html`] with a `<pre>` element like the
+/// one of [`highlighted_html_for_string`]. The syntax set has to be loaded with newlines.
+///
+/// # Examples
+///
+/// ```
+/// use pulldown_cmark::{html, Parser};
+/// use syntect::cmark::highlight_code_blocks;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let markdown = "Some *Rust*:\n\n```rust\nfn main() {}\n```\n";
+///
+/// let events = highlight_code_blocks(Parser::new(markdown), &ss, &ts.themes["InspiredGitHub"]);
+/// let mut out = String::new();
+/// html::push_html(&mut out, events);
+/// assert!(out.starts_with("<p>Some <em>Rust</em>:</p>\n<pre style="));
+/// ```
+///
+/// [`SyntaxSet::find_syntax_by_token`]: ../parsing/struct.SyntaxSet.html#method.find_syntax_by_token
+/// [`Event::Html`]: https://docs.rs/pulldown-cmark/0.13/pulldown_cmark/enum.Event.html#variant.Html
+/// [`highlighted_html_for_string`]: ../html/fn.highlighted_html_for_string.html
+pub fn highlight_code_blocks<'a, 'e, I>(
+    events: I,
+    syntax_set: &'a SyntaxSet,
+    theme: &'a Theme,
+) -> HighlightCodeBlocks<'a, I::IntoIter>
+where
+    I: IntoIterator<Item = Event<'e>>,
+{
+    HighlightCodeBlocks {
+        events: events.into_iter(),
+        syntax_set,
+        style: CodeBlockStyle::Inline(theme),
+    }
+}
+
+/// Like [`highlight_code_blocks`], but with CSS classes instead of inline styles
+///
+/// The code is in a `<pre>` element with the `code` class, with the prefix of the class style if
+/// it has one, which is what the stylesheets of [`css_for_theme_with_class_style`] expect.
+///
+/// [`highlight_code_blocks`]: fn.highlight_code_blocks.html
+/// [`css_for_theme_with_class_style`]: ../html/fn.css_for_theme_with_class_style.html
+pub fn highlight_code_blocks_with_classes<'a, 'e, I>(
+    events: I,
+    syntax_set: &'a SyntaxSet,
+    class_style: ClassStyle,
+) -> HighlightCodeBlocks<'a, I::IntoIter>
+where
+    I: IntoIterator<Item = Event<'e>>,
+{
+    HighlightCodeBlocks {
+        events: events.into_iter(),
+        syntax_set,
+        style: CodeBlockStyle::Classed(class_style),
+    }
+}
+
+impl<'a, 'e, I> Iterator for HighlightCodeBlocks<'a, I>
+where
+    I: Iterator<Item = Event<'e>>,
+{
+    type Item = Event<'e>;
+
+    fn next(&mut self) -> Option<Event<'e>> {
+        let kind = match self.events.next()? {
+            Event::Start(Tag::CodeBlock(kind)) => kind,
+            event => return Some(event),
+        };
+        let mut code = String::new();
+        for event in self.events.by_ref() {
+            match event {
+                Event::End(TagEnd::CodeBlock) => break,
+                Event::Text(text) => code.push_str(&text),
+                _ => {}
+            }
+        }
+        let syntax = match kind {
+            CodeBlockKind::Fenced(ref info) => info_string_language(info)
+                .and_then(|language| self.syntax_set.find_syntax_by_token(language)),
+            CodeBlockKind::Indented => None,
+        }
+        .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+        // the code is shown without highlighting rather than dropped if it somehow fails
+        let html = self
+            .highlight(&code, syntax)
+            .unwrap_or_else(|_| format!("<pre><code>{}</code></pre>\n", Escape(&code)));
+        Some(Event::Html(html.into()))
+    }
+}
+
+impl<'a, I> HighlightCodeBlocks<'a, I> {
+    fn highlight(&self, code: &str, syntax: &SyntaxReference) -> Result<String, Error> {
+        match self.style {
+            CodeBlockStyle::Inline(theme) => {
+                highlighted_html_for_string(code, self.syntax_set, syntax, theme)
+            }
+            CodeBlockStyle::Classed(class_style) => {
+                let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                    syntax,
+                    self.syntax_set,
+                    class_style,
+                );
+                for line in LinesWithEndings::from(code) {
+                    generator.parse_html_for_line_which_includes_newline(line)?;
+                }
+                let prefix = match class_style {
+                    ClassStyle::Spaced => "",
+                    ClassStyle::SpacedPrefixed { prefix } => prefix,
+                };
+                Ok(format!(
+                    "<pre class=\"{}code\">{}</pre>\n",
+                    prefix,
+                    generator.finalize()
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{html, Parser};
+
+    #[test]
+    fn highlights_code_blocks_with_classes() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let markdown = "# Title\n\n```rust,ignore\nlet x = 1;\n```\n\n    <indented>\n\nEnd\n";
+        let events =
+            highlight_code_blocks_with_classes(Parser::new(markdown), &ss, ClassStyle::Spaced);
+        let mut out = String::new();
+        html::push_html(&mut out, events);
+        assert!(out.starts_with("<h1>Title</h1>\n<pre class=\"code\"><span class=\"source rust\">"));
+        assert!(out.contains("<span class=\"storage type rust\">let</span>"));
+        // indented code blocks are plain text, and escaped
+        assert!(out.contains("<span class=\"text plain\">&lt;indented&gt;\n</span></pre>\n"));
+        assert!(out.ends_with("<p>End</p>\n"));
+    }
+}
@@ -8,14 +8,44 @@
 //! themes. And even your own `serde::Serialize` structures if you want to
 //! be consistent with your format.
 //!
+//! To build your own dumps of syntax and theme folders, e.g. from a build script, see
+//! [`build_syntax_dump_from_folders`] and [`build_theme_dump`]. A syntax set dump can then be
+//! embedded in a binary with [`include_syntax_set!`].
+//!
+//! With the `global-statics` feature, which is on by default, the `load_defaults` methods decode
+//! the default syntaxes and themes once, into [`Defaults::global`], and clone them from there.
+//! Without it syntect keeps no global statics for them: each call to a `load_defaults` method
+//! decodes them again, and a [`Defaults`] handle can be used to decode them once, in whatever
+//! place a program keeps its state.
+//!
+//! Syntax set, theme set and metadata dumps start with a tag of their format, so loading one made
+//! by a version of syntect with a different format fails with an error that says so. Such dumps
+//! have to be made again.
+//!
 //! [`SyntaxSet`]: ../parsing/struct.SyntaxSet.html
 //! [`dump_to_uncompressed_file`]: fn.dump_to_uncompressed_file.html
 //! [`ThemeSet`]: ../highlighting/struct.ThemeSet.html
 //! [`dump_to_file`]: fn.dump_to_file.html
-#[cfg(feature = "default-themes")]
+//! [`build_syntax_dump_from_folders`]: fn.build_syntax_dump_from_folders.html
+//! [`build_theme_dump`]: fn.build_theme_dump.html
+//! [`include_syntax_set!`]: ../macro.include_syntax_set.html
+//! [`Defaults`]: struct.Defaults.html
+//! [`Defaults::global`]: struct.Defaults.html#method.global
+#[cfg(any(feature = "default-themes", feature = "plist-load"))]
 use crate::highlighting::ThemeSet;
-#[cfg(feature = "default-syntaxes")]
+#[cfg(feature = "default-themes")]
+use crate::highlighting::{DefaultThemeName, Theme};
+#[cfg(feature = "parsing")]
 use crate::parsing::SyntaxSet;
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+use crate::parsing::{LoadOptions, SyntaxSetBuilder};
+#[cfg(all(feature = "dump-load", feature = "parsing"))]
+use crate::utils::DUMP_FORMAT_TAG;
+#[cfg(any(
+    all(feature = "yaml-load", feature = "dump-create"),
+    all(feature = "plist-load", feature = "dump-create")
+))]
+use crate::LoadingError;
 #[cfg(feature = "dump-load")]
 use bincode::deserialize_from;
 #[cfg(feature = "dump-create")]
@@ -27,6 +57,12 @@ use flate2::bufread::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 #[cfg(feature = "dump-create")]
 use flate2::Compression;
+#[cfg(any(
+    feature = "default-syntaxes",
+    feature = "default-syntax-families",
+    feature = "default-themes"
+))]
+use once_cell::sync::OnceCell;
 #[cfg(feature = "dump-load")]
 use serde::de::DeserializeOwned;
 #[cfg(feature = "dump-create")]
@@ -41,6 +77,11 @@ use std::path::Path;
 /// Dumps an object to the given writer in a compressed binary format
 ///
 /// The writer is encoded with the `bincode` crate and compressed with `flate2`.
+///
+/// The output is deterministic: dumping equal objects gives the same bytes, so dumps can be
+/// checked in or embedded in reproducible builds. Maps are serialized in sorted order, syntaxes
+/// and themes loaded from folders are in the order of their paths and the compression level is
+/// fixed. The bytes can still change between versions of syntect or of the `flate2` backend.
 #[cfg(feature = "dump-create")]
 pub fn dump_to_writer<T: Serialize, W: Write>(to_dump: &T, output: W) -> Result<()> {
     serialize_to_writer_impl(to_dump, output, true)
@@ -115,6 +156,131 @@ pub fn from_uncompressed_data<T: DeserializeOwned>(v: &[u8]) -> Result<T> {
     deserialize_from_reader_impl(v, false)
 }
 
+/// Options for [`build_syntax_dump_from_folders`]
+///
+/// [`build_syntax_dump_from_folders`]: fn.build_syntax_dump_from_folders.html
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+#[derive(Clone)]
+pub struct SyntaxDumpOptions {
+    /// Whether the syntaxes are for lines including `\n`, see
+    /// [`SyntaxSetBuilder::add_from_folder`]. Defaults to `true`.
+    ///
+    /// [`SyntaxSetBuilder::add_from_folder`]: ../parsing/struct.SyntaxSetBuilder.html#method.add_from_folder
+    pub lines_include_newline: bool,
+    /// Whether to add the plain text syntax, which Sublime's default packages only have as a
+    /// `.tmLanguage` file. Defaults to `true`.
+    pub include_plain_text: bool,
+    /// How the syntax files are loaded
+    pub load_options: LoadOptions,
+}
+
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+impl Default for SyntaxDumpOptions {
+    fn default() -> SyntaxDumpOptions {
+        SyntaxDumpOptions {
+            lines_include_newline: true,
+            include_plain_text: true,
+            load_options: LoadOptions::default(),
+        }
+    }
+}
+
+/// The dumps built by [`build_syntax_dump_from_folders`]
+///
+/// Metadata isn't part of a serialized [`SyntaxSet`], so it gets a dump of its own.
+///
+/// [`build_syntax_dump_from_folders`]: fn.build_syntax_dump_from_folders.html
+/// [`SyntaxSet`]: ../parsing/struct.SyntaxSet.html
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+#[derive(Debug, Clone)]
+pub struct SyntaxDump {
+    /// The syntax set, to be loaded with [`from_uncompressed_data`]
+    ///
+    /// [`from_uncompressed_data`]: fn.from_uncompressed_data.html
+    pub syntaxes: Vec<u8>,
+    /// The metadata from the `.tmPreferences` files, to be loaded with [`from_binary`] and passed
+    /// to [`SyntaxSet::set_metadata`]
+    ///
+    /// [`from_binary`]: fn.from_binary.html
+    /// [`SyntaxSet::set_metadata`]: ../parsing/struct.SyntaxSet.html#method.set_metadata
+    #[cfg(feature = "metadata")]
+    pub metadata: Vec<u8>,
+}
+
+/// Loads all the syntaxes in `folders` and dumps them in the format of syntect's own packs, like
+/// the `gendata` example does
+///
+/// This is meant for build scripts generating a pack to include in a binary with
+/// [`include_bytes!`].
+///
+/// # Examples
+///
+/// ```no_run
+/// use syntect::dumps::{build_syntax_dump_from_folders, from_uncompressed_data, SyntaxDumpOptions};
+/// use syntect::parsing::SyntaxSet;
+///
+/// let dump = build_syntax_dump_from_folders(&["syntaxes"], &SyntaxDumpOptions::default()).unwrap();
+/// std::fs::write("syntaxes.packdump", &dump.syntaxes).unwrap();
+///
+/// // later, e.g. with the bytes from `include_bytes!`
+/// let ss: SyntaxSet = from_uncompressed_data(&dump.syntaxes).unwrap();
+/// ```
+///
+/// [`include_bytes!`]: https://doc.rust-lang.org/std/macro.include_bytes.html
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+pub fn build_syntax_dump_from_folders<P: AsRef<Path>>(
+    folders: &[P],
+    options: &SyntaxDumpOptions,
+) -> std::result::Result<SyntaxDump, LoadingError> {
+    let mut builder = SyntaxSetBuilder::new();
+    if options.include_plain_text {
+        builder.add_plain_text_syntax();
+    }
+    for folder in folders {
+        builder.add_from_folder_with_options(
+            folder,
+            options.lines_include_newline,
+            &options.load_options,
+        )?;
+    }
+    let ss = builder.build();
+    Ok(SyntaxDump {
+        syntaxes: dump_uncompressed_binary(&ss),
+        #[cfg(feature = "metadata")]
+        metadata: dump_binary(&ss.metadata()),
+    })
+}
+
+/// Loads all the `.tmTheme` files in `folders` and dumps them as a [`ThemeSet`], to be loaded
+/// with [`from_binary`]
+///
+/// Themes are named after their file name, and later folders win when two have the same name.
+///
+/// [`ThemeSet`]: ../highlighting/struct.ThemeSet.html
+/// [`from_binary`]: fn.from_binary.html
+#[cfg(all(feature = "plist-load", feature = "dump-create"))]
+pub fn build_theme_dump<P: AsRef<Path>>(
+    folders: &[P],
+) -> std::result::Result<Vec<u8>, LoadingError> {
+    let mut ts = ThemeSet::new();
+    for folder in folders {
+        ts.add_from_folder(folder)?;
+    }
+    Ok(dump_binary(&ts))
+}
+
+/// Like `dump_binary`, but in the uncompressed format used for syntax sets
+#[cfg(all(feature = "yaml-load", feature = "dump-create"))]
+fn dump_uncompressed_binary<T: Serialize>(o: &T) -> Vec<u8> {
+    let mut v = Vec::new();
+    serialize_to_writer_impl(o, &mut v, false).unwrap();
+    v
+}
+
+/// The compression level of dumps, which is part of what makes them reproducible
+#[cfg(feature = "dump-create")]
+const DUMP_COMPRESSION: Compression = Compression::best();
+
 /// Private low level helper function used to implement the public API.
 #[cfg(feature = "dump-create")]
 fn serialize_to_writer_impl<T: Serialize, W: Write>(
@@ -123,7 +289,7 @@ fn serialize_to_writer_impl<T: Serialize, W: Write>(
     use_compression: bool,
 ) -> Result<()> {
     if use_compression {
-        let mut encoder = std::io::BufWriter::new(ZlibEncoder::new(output, Compression::best()));
+        let mut encoder = std::io::BufWriter::new(ZlibEncoder::new(output, DUMP_COMPRESSION));
         serialize_into(&mut encoder, to_dump)
     } else {
         serialize_into(output, to_dump)
@@ -144,7 +310,7 @@ fn deserialize_from_reader_impl<T: DeserializeOwned, R: BufRead>(
     }
 }
 
-#[cfg(feature = "default-syntaxes")]
+#[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
 impl SyntaxSet {
     /// Instantiates a new syntax set from a binary dump of Sublime Text's default open source
     /// syntax definitions.
@@ -163,22 +329,35 @@ impl SyntaxSet {
     /// the fact that SyntaxDefinitions are serializable with the bincode crate to cache dumps of
     /// additional syntaxes yourself.
     ///
+    /// To make binaries smaller, the `default-syntaxes-web`, `default-syntaxes-systems` and
+    /// `default-syntaxes-scripting` features can be enabled instead of `default-syntaxes`, to only
+    /// include parts of the default syntaxes:
+    ///
+    /// - web: HTML, CSS, JavaScript, JSON, PHP, ASP, ActionScript, XML, YAML, Markdown,
+    ///   reStructuredText and Textile
+    /// - systems: C, C++, C#, D, Go, Haskell, Java, Objective-C, OCaml, Pascal, Rust, Scala and
+    ///   Makefiles
+    /// - scripting: AppleScript, batch files, Clojure, Erlang, Groovy, Lisp, Lua, MATLAB, Perl,
+    ///   Python, R, Ruby and Rails, shell scripts, SQL and Tcl
+    ///
+    /// The enabled parts are loaded together with a plain text syntax. This is slower than loading
+    /// the full set, because the references between the parts have to be linked again, so it's
+    /// worth keeping the set around, e.g. with [`Defaults`]. LaTeX, Diff and Graphviz are only in
+    /// the full set.
+    ///
+    /// With the `global-statics` feature, the set is only decoded the first time, into
+    /// [`Defaults::global`], and cloned from there after that.
+    ///
     /// [`load_defaults_newlines`]: #method.load_defaults_nonewlines
     /// [`SyntaxSetBuilder::add_from_folder`]: struct.SyntaxSetBuilder.html#method.add_from_folder
+    /// [`Defaults`]: ../dumps/struct.Defaults.html
+    /// [`Defaults::global`]: ../dumps/struct.Defaults.html#method.global
     pub fn load_defaults_nonewlines() -> SyntaxSet {
-        #[cfg(feature = "metadata")]
-        {
-            let mut ps: SyntaxSet =
-                from_uncompressed_data(include_bytes!("../assets/default_nonewlines.packdump"))
-                    .unwrap();
-            let metadata = from_binary(include_bytes!("../assets/default_metadata.packdump"));
-            ps.metadata = metadata;
-            ps
-        }
-        #[cfg(not(feature = "metadata"))]
-        {
-            from_uncompressed_data(include_bytes!("../assets/default_nonewlines.packdump")).unwrap()
-        }
+        #[cfg(feature = "global-statics")]
+        let ps = Defaults::global().syntax_set_nonewlines().clone();
+        #[cfg(not(feature = "global-statics"))]
+        let ps = default_syntaxes_nonewlines();
+        ps
     }
 
     /// Same as [`load_defaults_nonewlines`] but for parsing line strings with newlines at the end.
@@ -188,20 +367,74 @@ impl SyntaxSet {
     ///
     /// [`load_defaults_nonewlines`]: #method.load_defaults_nonewlines
     pub fn load_defaults_newlines() -> SyntaxSet {
-        #[cfg(feature = "metadata")]
-        {
-            let mut ps: SyntaxSet =
-                from_uncompressed_data(include_bytes!("../assets/default_newlines.packdump"))
-                    .unwrap();
-            let metadata = from_binary(include_bytes!("../assets/default_metadata.packdump"));
-            ps.metadata = metadata;
-            ps
-        }
-        #[cfg(not(feature = "metadata"))]
-        {
-            from_uncompressed_data(include_bytes!("../assets/default_newlines.packdump")).unwrap()
-        }
+        #[cfg(feature = "global-statics")]
+        let ps = Defaults::global().syntax_set_newlines().clone();
+        #[cfg(not(feature = "global-statics"))]
+        let ps = default_syntaxes_newlines();
+        ps
+    }
+}
+
+/// Decodes the default syntaxes for lines without newlines
+#[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+fn default_syntaxes_nonewlines() -> SyntaxSet {
+    #[cfg(feature = "default-syntaxes")]
+    let ps =
+        from_uncompressed_data(include_bytes!("../assets/default_nonewlines.packdump")).unwrap();
+    #[cfg(not(feature = "default-syntaxes"))]
+    let ps = load_default_families(&[
+        #[cfg(feature = "default-syntaxes-web")]
+        include_bytes!("../assets/default_web_nonewlines.packdump"),
+        #[cfg(feature = "default-syntaxes-systems")]
+        include_bytes!("../assets/default_systems_nonewlines.packdump"),
+        #[cfg(feature = "default-syntaxes-scripting")]
+        include_bytes!("../assets/default_scripting_nonewlines.packdump"),
+    ]);
+    with_default_metadata(ps)
+}
+
+/// Decodes the default syntaxes for lines with newlines
+#[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+fn default_syntaxes_newlines() -> SyntaxSet {
+    #[cfg(feature = "default-syntaxes")]
+    let ps = from_uncompressed_data(include_bytes!("../assets/default_newlines.packdump")).unwrap();
+    #[cfg(not(feature = "default-syntaxes"))]
+    let ps = load_default_families(&[
+        #[cfg(feature = "default-syntaxes-web")]
+        include_bytes!("../assets/default_web_newlines.packdump"),
+        #[cfg(feature = "default-syntaxes-systems")]
+        include_bytes!("../assets/default_systems_newlines.packdump"),
+        #[cfg(feature = "default-syntaxes-scripting")]
+        include_bytes!("../assets/default_scripting_newlines.packdump"),
+    ]);
+    with_default_metadata(ps)
+}
+
+#[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+#[allow(unused_mut)]
+fn with_default_metadata(mut ps: SyntaxSet) -> SyntaxSet {
+    #[cfg(feature = "metadata")]
+    {
+        ps.metadata = from_binary(include_bytes!("../assets/default_metadata.packdump"));
+    }
+    ps
+}
+
+/// Loads the enabled parts of the default syntaxes into one set
+///
+/// Building the set links the references between the parts, e.g. from Markdown to the languages
+/// of its code blocks. References to syntaxes that aren't enabled fall back to plain text, like
+/// for syntaxes that aren't in the default packages at all.
+#[cfg(all(feature = "default-syntax-families", not(feature = "default-syntaxes")))]
+fn load_default_families(packs: &[&[u8]]) -> SyntaxSet {
+    let mut builder = crate::parsing::SyntaxSetBuilder::new();
+    builder.add_syntax_set(
+        from_uncompressed_data(include_bytes!("../assets/default_plain_text.packdump")).unwrap(),
+    );
+    for pack in packs {
+        builder.add_syntax_set(from_uncompressed_data(pack).unwrap());
     }
+    builder.build()
 }
 
 #[cfg(feature = "default-themes")]
@@ -212,11 +445,229 @@ impl ThemeSet {
     /// - `base16-ocean.dark`,`base16-eighties.dark`,`base16-mocha.dark`,`base16-ocean.light`
     /// - `InspiredGitHub` from [here](https://github.com/sethlopezme/InspiredGitHub.tmtheme)
     /// - `Solarized (dark)` and `Solarized (light)`
+    ///
+    /// [`DefaultThemeName`] names them without the strings.
+    ///
+    /// With the `global-statics` feature, the set is only decoded the first time, into
+    /// [`Defaults::global`], and cloned from there after that.
+    ///
+    /// [`DefaultThemeName`]: enum.DefaultThemeName.html
+    /// [`Defaults::global`]: ../dumps/struct.Defaults.html#method.global
     pub fn load_defaults() -> ThemeSet {
-        from_binary(include_bytes!("../assets/default.themedump"))
+        #[cfg(feature = "global-statics")]
+        let ts = Defaults::global().theme_set().clone();
+        #[cfg(not(feature = "global-statics"))]
+        let ts = default_themes();
+        ts
+    }
+
+    /// Loads one of the default themes
+    ///
+    /// This loads the whole set and keeps only the one theme, so use [`load_defaults`] to get
+    /// several of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{DefaultThemeName, ThemeSet};
+    ///
+    /// let theme = ThemeSet::get_default(DefaultThemeName::InspiredGitHub);
+    /// assert_eq!(theme.name.as_deref(), Some("GitHub"));
+    /// ```
+    ///
+    /// [`load_defaults`]: #method.load_defaults
+    pub fn get_default(name: DefaultThemeName) -> Theme {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(name.as_str())
+            .expect("the default themes include all the names")
+    }
+}
+
+/// Decodes the default themes
+#[cfg(feature = "default-themes")]
+fn default_themes() -> ThemeSet {
+    from_binary(include_bytes!("../assets/default.themedump"))
+}
+
+/// A syntax set that's loaded the first time it's used, as returned by [`include_syntax_set!`]
+///
+/// Loading it gives an error if the dump can't be decoded.
+///
+/// [`include_syntax_set!`]: ../macro.include_syntax_set.html
+#[cfg(all(feature = "dump-load", feature = "parsing"))]
+pub type LazySyntaxSet = once_cell::sync::Lazy<Result<SyntaxSet>>;
+
+/// Embeds a syntax set dump in the binary, as a [`LazySyntaxSet`] that's loaded the first time
+/// it's used
+///
+/// This is how a crate can ship its own syntaxes like syntect ships the default ones. The dump
+/// has to be in the uncompressed format of [`dump_to_uncompressed_file`] and
+/// [`build_syntax_dump_from_folders`], and the path is relative to the current file, like for
+/// [`include_bytes!`]. Dumps that are too short or compressed are rejected at compile time.
+/// Dumps made by a version of syntect with a different dump format are rejected at compile time
+/// too, and one that still can't be decoded gives the error on first use.
+///
+/// # Examples
+///
+/// ```ignore
+/// use syntect::dumps::LazySyntaxSet;
+/// use syntect::include_syntax_set;
+///
+/// static SYNTAXES: LazySyntaxSet = include_syntax_set!("../assets/my_syntaxes.packdump");
+///
+/// let syntaxes = SYNTAXES.as_ref().expect("my_syntaxes.packdump can be loaded");
+/// let syntax = syntaxes.find_syntax_by_extension("my").unwrap();
+/// ```
+///
+/// [`LazySyntaxSet`]: dumps/type.LazySyntaxSet.html
+/// [`dump_to_uncompressed_file`]: dumps/fn.dump_to_uncompressed_file.html
+/// [`build_syntax_dump_from_folders`]: dumps/fn.build_syntax_dump_from_folders.html
+/// [`include_bytes!`]: https://doc.rust-lang.org/std/macro.include_bytes.html
+#[cfg(all(feature = "dump-load", feature = "parsing"))]
+#[macro_export]
+macro_rules! include_syntax_set {
+    ($path:expr) => {{
+        const BYTES: &[u8] = include_bytes!($path);
+        const _: () = $crate::dumps::__check_syntax_dump(BYTES);
+        $crate::dumps::LazySyntaxSet::new(|| $crate::dumps::__load_syntax_dump(BYTES))
+    }};
+}
+
+/// Rejects dumps that can't be syntax sets when [`include_syntax_set!`] is compiled
+///
+/// [`include_syntax_set!`]: ../macro.include_syntax_set.html
+#[cfg(all(feature = "dump-load", feature = "parsing"))]
+#[doc(hidden)]
+#[allow(clippy::panic)]
+// These panics are evaluated while compiling the macro, where they're the only way to reject
+// the dump
+pub const fn __check_syntax_dump(bytes: &[u8]) {
+    // the dump format and the length of the list of syntaxes take 8 bytes each
+    if bytes.len() < 16 {
+        panic!("a syntax set dump is at least 16 bytes long");
+    }
+    // a zlib header, which an uncompressed dump never starts with
+    if bytes[0] == 0x78 && matches!(bytes[1], 0x01 | 0x5E | 0x9C | 0xDA) {
+        panic!(
+            "the dump is compressed, syntax sets have to be dumped with dump_to_uncompressed_file"
+        );
+    }
+    let mut i = 0;
+    while i < DUMP_FORMAT_TAG.len() {
+        if bytes[i] != DUMP_FORMAT_TAG[i] {
+            panic!("the dump was made by a different version of syntect and has to be made again");
+        }
+        i += 1;
+    }
+}
+
+#[cfg(all(feature = "dump-load", feature = "parsing"))]
+#[doc(hidden)]
+pub fn __load_syntax_dump(bytes: &[u8]) -> Result<SyntaxSet> {
+    from_uncompressed_data(bytes)
+}
+
+/// The default syntaxes and themes, each decoded the first time it's used and then kept
+///
+/// This is an alternative to calling [`SyntaxSet::load_defaults_newlines`] and friends every
+/// time they're needed, which doesn't need syntect to keep them in global statics, e.g. when it's
+/// built without the `global-statics` feature. Because [`Defaults::new`] is a `const fn`, a
+/// program that does want them in a static can put a `Defaults` in one without any lazy
+/// initialization crate.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::dumps::Defaults;
+///
+/// static DEFAULTS: Defaults = Defaults::new();
+///
+/// let ss = DEFAULTS.syntax_set_newlines();
+/// assert!(ss.find_syntax_by_extension("rs").is_some());
+/// assert!(std::ptr::eq(ss, DEFAULTS.syntax_set_newlines()));
+/// ```
+///
+/// [`SyntaxSet::load_defaults_newlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+/// [`Defaults::new`]: #method.new
+#[cfg(any(
+    feature = "default-syntaxes",
+    feature = "default-syntax-families",
+    feature = "default-themes"
+))]
+#[derive(Debug, Default)]
+pub struct Defaults {
+    #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+    syntaxes_newlines: OnceCell<SyntaxSet>,
+    #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+    syntaxes_nonewlines: OnceCell<SyntaxSet>,
+    #[cfg(feature = "default-themes")]
+    themes: OnceCell<ThemeSet>,
+}
+
+#[cfg(any(
+    feature = "default-syntaxes",
+    feature = "default-syntax-families",
+    feature = "default-themes"
+))]
+impl Defaults {
+    /// Creates a handle which hasn't loaded anything yet
+    pub const fn new() -> Defaults {
+        Defaults {
+            #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+            syntaxes_newlines: OnceCell::new(),
+            #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+            syntaxes_nonewlines: OnceCell::new(),
+            #[cfg(feature = "default-themes")]
+            themes: OnceCell::new(),
+        }
+    }
+
+    /// The handle in a global static that the `load_defaults` methods clone from, only with the
+    /// `global-statics` feature
+    #[cfg(feature = "global-statics")]
+    pub fn global() -> &'static Defaults {
+        &GLOBAL_DEFAULTS
+    }
+
+    /// The default syntaxes for lines with newlines, see [`SyntaxSet::load_defaults_newlines`]
+    ///
+    /// [`SyntaxSet::load_defaults_newlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+    #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+    pub fn syntax_set_newlines(&self) -> &SyntaxSet {
+        self.syntaxes_newlines
+            .get_or_init(default_syntaxes_newlines)
+    }
+
+    /// The default syntaxes for lines without newlines, see
+    /// [`SyntaxSet::load_defaults_nonewlines`]
+    ///
+    /// [`SyntaxSet::load_defaults_nonewlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_nonewlines
+    #[cfg(any(feature = "default-syntaxes", feature = "default-syntax-families"))]
+    pub fn syntax_set_nonewlines(&self) -> &SyntaxSet {
+        self.syntaxes_nonewlines
+            .get_or_init(default_syntaxes_nonewlines)
+    }
+
+    /// The default themes, see [`ThemeSet::load_defaults`]
+    ///
+    /// [`ThemeSet::load_defaults`]: ../highlighting/struct.ThemeSet.html#method.load_defaults
+    #[cfg(feature = "default-themes")]
+    pub fn theme_set(&self) -> &ThemeSet {
+        self.themes.get_or_init(default_themes)
     }
 }
 
+#[cfg(all(
+    feature = "global-statics",
+    any(
+        feature = "default-syntaxes",
+        feature = "default-syntax-families",
+        feature = "default-themes"
+    )
+))]
+static GLOBAL_DEFAULTS: Defaults = Defaults::new();
+
 #[cfg(test)]
 mod tests {
     #[cfg(all(
@@ -264,6 +715,107 @@ mod tests {
         assert_eq!(bin1, bin2);
     }
 
+    #[cfg(all(
+        feature = "yaml-load",
+        feature = "plist-load",
+        feature = "dump-create",
+        feature = "dump-load"
+    ))]
+    #[test]
+    fn dumps_from_folders_are_reproducible() {
+        use super::*;
+        use crate::utils::TempDir;
+
+        let dir = TempDir::new("repro");
+        for (i, package) in ["b", "a", "c/nested"].iter().enumerate() {
+            // Lots of variables and contexts, which are kept in hash maps
+            let mut syntax = format!(
+                "name: Test{0}\nscope: source.test{0}\nfile_extensions: [t{0}]\nvariables:\n",
+                i
+            );
+            for v in 0..20 {
+                syntax.push_str(&format!("  var{0}: 'x{0}'\n", v));
+            }
+            syntax.push_str("contexts:\n  main:\n");
+            for c in 0..20 {
+                syntax.push_str(&format!(
+                    "    - match: '{{{{var{0}}}}}'\n      push: ctx{0}\n",
+                    c
+                ));
+            }
+            for c in 0..20 {
+                syntax.push_str(&format!(
+                    "  ctx{0}:\n    - meta_scope: meta.ctx{0}.test\n    - match: y\n      pop: true\n",
+                    c
+                ));
+            }
+            dir.write(format!("{}/Test{}.sublime-syntax", package, i), syntax);
+        }
+
+        let options = SyntaxDumpOptions::default();
+        let first = build_syntax_dump_from_folders(&[dir.path()], &options).unwrap();
+        let second = build_syntax_dump_from_folders(&[dir.path()], &options).unwrap();
+        assert_eq!(first.syntaxes, second.syntaxes);
+        #[cfg(feature = "metadata")]
+        assert_eq!(first.metadata, second.metadata);
+
+        let ss: SyntaxSet = from_uncompressed_data(&first.syntaxes).unwrap();
+        let names: Vec<&str> = ss.syntaxes().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Plain Text", "Test1", "Test0", "Test2"]);
+        assert_eq!(dump_binary(&ss), dump_binary(&ss));
+    }
+
+    #[cfg(all(
+        feature = "yaml-load",
+        feature = "plist-load",
+        feature = "dump-create",
+        feature = "dump-load"
+    ))]
+    #[test]
+    fn can_build_dumps_from_folders() {
+        use super::*;
+        use crate::utils::TempDir;
+
+        let dir = TempDir::new("dumps");
+        dir.write(
+            "Test.sublime-syntax",
+            "name: Test\nscope: source.test\nfile_extensions: [test]\ncontexts:\n  main:\n    - match: a\n      scope: keyword.test\n",
+        );
+        dir.write(
+            "Test.tmTheme",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Test</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>foreground</key>
+                <string>#112233</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#,
+        );
+
+        let dump =
+            build_syntax_dump_from_folders(&[dir.path()], &SyntaxDumpOptions::default()).unwrap();
+        let ss: SyntaxSet = from_uncompressed_data(&dump.syntaxes).unwrap();
+        let names: Vec<&str> = ss.syntaxes().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Plain Text", "Test"]);
+        #[cfg(feature = "metadata")]
+        let _: crate::parsing::Metadata = from_binary(&dump.metadata);
+
+        let dump = build_theme_dump(&[dir.path()]).unwrap();
+        let ts: ThemeSet = from_binary(&dump);
+        assert_eq!(ts.themes["Test"].settings.foreground.unwrap().r, 0x11);
+    }
+
     #[cfg(feature = "default-themes")]
     #[test]
     fn has_default_themes() {
@@ -271,4 +823,63 @@ mod tests {
         let themes = ThemeSet::load_defaults();
         assert!(themes.themes.len() > 4);
     }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn defaults_are_loaded_once() {
+        use super::*;
+        let defaults = Defaults::new();
+        let ts = defaults.theme_set();
+        assert!(ts.themes.contains_key("base16-ocean.dark"));
+        assert!(std::ptr::eq(ts, defaults.theme_set()));
+        let ss = defaults.syntax_set_nonewlines();
+        assert!(std::ptr::eq(ss, defaults.syntax_set_nonewlines()));
+        assert!(!std::ptr::eq(ss, defaults.syntax_set_newlines()));
+    }
+
+    #[cfg(all(feature = "global-statics", feature = "default-themes"))]
+    #[test]
+    fn load_defaults_goes_through_global_defaults() {
+        use super::*;
+        let ts = ThemeSet::load_defaults();
+        let global = Defaults::global().themes.get().unwrap();
+        assert_eq!(
+            ts.themes.keys().collect::<Vec<_>>(),
+            global.themes.keys().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(all(feature = "dump-load", feature = "parsing"))]
+    #[test]
+    fn can_include_syntax_set() {
+        use super::LazySyntaxSet;
+
+        static PLAIN_TEXT: LazySyntaxSet =
+            include_syntax_set!("../assets/default_plain_text.packdump");
+        let ss = PLAIN_TEXT.as_ref().unwrap();
+        assert_eq!(ss.syntaxes().len(), 1);
+        assert_eq!(ss.syntaxes()[0].name, "Plain Text");
+        assert!(std::ptr::eq(ss, PLAIN_TEXT.as_ref().unwrap()));
+    }
+
+    #[cfg(all(feature = "dump-load", feature = "parsing"))]
+    #[test]
+    fn included_dump_is_checked_on_first_use() {
+        assert!(super::__load_syntax_dump(&[0xFF; 8]).is_err());
+    }
+
+    #[cfg(all(feature = "dump-load", feature = "parsing"))]
+    #[test]
+    fn dumps_of_other_versions_are_rejected() {
+        use super::from_uncompressed_data;
+        use crate::parsing::SyntaxSet;
+
+        let dump = include_bytes!("../assets/default_plain_text.packdump");
+        assert!(from_uncompressed_data::<SyntaxSet>(dump).is_ok());
+        // a dump from before the format was recorded starts right with the syntaxes
+        let error = from_uncompressed_data::<SyntaxSet>(&dump[8..]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("made by a different version of syntect"));
+    }
 }
@@ -2,12 +2,26 @@
 //! files without caring about intermediate semantic representation
 //! and caching.
 
-use crate::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, Theme};
-use crate::parsing::{ParseState, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+use crate::highlighting::{
+    HighlightIterator, HighlightState, Highlighter, RangedHighlightIterator, Style, Theme,
+};
+#[cfg(feature = "html")]
+use crate::html::{append_highlighted_html_for_styled_tokens, IncludeBackground};
+use crate::parsing::syntax_definition::ContextReference;
+use crate::parsing::{LineStats, ParseState, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+use crate::util::{strip_styles, LinesWithEndings};
 use crate::Error;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::{self, BufReader};
+use std::mem;
+use std::ops::Range;
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 // use util::debug_print_ops;
 
 /// Simple way to go directly from lines of text to colored tokens.
@@ -40,15 +54,44 @@ use std::path::Path;
 ///     print!("{}", escaped);
 /// }
 /// ```
+///
+/// Cloning it, or saving its state with [`parse_state`] and [`highlight_state`] and restoring it
+/// with [`set_state`], lets an editor go back to an earlier line or try out a change without
+/// highlighting the file from the start.
+///
+/// [`parse_state`]: #method.parse_state
+/// [`highlight_state`]: #method.highlight_state
+/// [`set_state`]: #method.set_state
+#[derive(Clone)]
 pub struct HighlightLines<'a> {
     highlighter: Highlighter<'a>,
     parse_state: ParseState,
     highlight_state: HighlightState,
 }
 
+impl HighlightLines<'static> {
+    /// Like [`new`], but shares ownership of the theme, so the result doesn't borrow anything and
+    /// can e.g. be moved to another thread, see [`Highlighter::from_shared`]
+    ///
+    /// [`new`]: #method.new
+    /// [`Highlighter::from_shared`]: ../highlighting/struct.Highlighter.html#method.from_shared
+    pub fn from_shared_theme(
+        syntax: &SyntaxReference,
+        theme: Arc<Theme>,
+    ) -> HighlightLines<'static> {
+        HighlightLines::with_highlighter(syntax, Highlighter::from_shared(theme))
+    }
+}
+
 impl<'a> HighlightLines<'a> {
     pub fn new(syntax: &SyntaxReference, theme: &'a Theme) -> HighlightLines<'a> {
-        let highlighter = Highlighter::new(theme);
+        HighlightLines::with_highlighter(syntax, Highlighter::new(theme))
+    }
+
+    fn with_highlighter(
+        syntax: &SyntaxReference,
+        highlighter: Highlighter<'a>,
+    ) -> HighlightLines<'a> {
         let highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
         HighlightLines {
             highlighter,
@@ -82,7 +125,658 @@ impl<'a> HighlightLines<'a> {
         // debug_print_ops(line, &ops);
         let iter =
             HighlightIterator::new(&mut self.highlight_state, &ops[..], line, &self.highlighter);
-        Ok(iter.collect())
+        let regions: Vec<_> = iter.collect();
+        debug_assert_eq!(
+            strip_styles(&regions).0,
+            line,
+            "highlighting dropped or duplicated text"
+        );
+        Ok(regions)
+    }
+
+    /// Like [`highlight_line`], but for a line split into chunks, like the chunks of a rope
+    ///
+    /// The regions borrow from the chunks, so a region that spans a boundary between chunks is
+    /// split in two. See [`ParseState::parse_line_chunks`] for when the chunks are copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::easy::HighlightLines;
+    /// use syntect::highlighting::ThemeSet;
+    /// use syntect::parsing::SyntaxSet;
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let ts = ThemeSet::load_defaults();
+    /// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    /// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+    /// let regions = h.highlight_line_chunks(["fn ma", "in() {}\n"], &ss).unwrap();
+    /// assert_eq!(regions[2].1, "ma");
+    /// assert_eq!(regions[3].1, "in");
+    /// assert_eq!(regions[2].0, regions[3].0);
+    /// ```
+    ///
+    /// [`highlight_line`]: #method.highlight_line
+    /// [`ParseState::parse_line_chunks`]: ../parsing/struct.ParseState.html#method.parse_line_chunks
+    pub fn highlight_line_chunks<'b, I>(
+        &mut self,
+        chunks: I,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(Style, &'b str)>, Error>
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        let chunks: Vec<&'b str> = chunks.into_iter().collect();
+        if let [line] = chunks[..] {
+            return self.highlight_line(line, syntax_set);
+        }
+        let line = chunks.concat();
+        let ops = self.parse_state.parse_line(&line, syntax_set)?;
+        let iter =
+            RangedHighlightIterator::new(&mut self.highlight_state, &ops, &line, &self.highlighter);
+        let mut regions = Vec::with_capacity(ops.len() + chunks.len());
+        // the chunk the next region starts in, and its offset in the line
+        let mut chunk = 0;
+        let mut chunk_start = 0;
+        for (style, _, mut range) in iter {
+            while range.start < range.end {
+                let text = chunks[chunk];
+                let chunk_end = chunk_start + text.len();
+                if range.start >= chunk_end {
+                    chunk += 1;
+                    chunk_start = chunk_end;
+                    continue;
+                }
+                let end = range.end.min(chunk_end);
+                regions.push((style, &text[range.start - chunk_start..end - chunk_start]));
+                range.start = end;
+            }
+        }
+        Ok(regions)
+    }
+
+    /// Highlights a line of a file into HTML with inline styles, like passing the result of
+    /// [`highlight_line`] to [`styled_line_to_highlighted_html`], but without collecting the
+    /// tokens first
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::easy::HighlightLines;
+    /// use syntect::highlighting::ThemeSet;
+    /// use syntect::html::IncludeBackground;
+    /// use syntect::parsing::SyntaxSet;
+    ///
+    /// let ps = SyntaxSet::load_defaults_newlines();
+    /// let ts = ThemeSet::load_defaults();
+    /// let syntax = ps.find_syntax_by_name("Ruby").unwrap();
+    /// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+    /// let html = h.highlight_line_to_html("5", &ps, IncludeBackground::No).unwrap();
+    /// assert_eq!(html, "<span style=\"color:#d08770;\">5</span>");
+    /// ```
+    ///
+    /// [`highlight_line`]: #method.highlight_line
+    /// [`styled_line_to_highlighted_html`]: ../html/fn.styled_line_to_highlighted_html.html
+    #[cfg(feature = "html")]
+    pub fn highlight_line_to_html(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+        bg: IncludeBackground,
+    ) -> Result<String, Error> {
+        let ops = self.parse_state.parse_line(line, syntax_set)?;
+        let iter =
+            HighlightIterator::new(&mut self.highlight_state, &ops[..], line, &self.highlighter);
+        let mut html = String::new();
+        let fonts = &self.highlighter.theme().font_hints;
+        append_highlighted_html_for_styled_tokens(iter, bg, fonts, &mut html)?;
+        Ok(html)
+    }
+
+    /// Highlights a line of a file, but only returns the tokens in a byte range of it
+    ///
+    /// The whole line is still parsed and highlighted so the following lines come out right, but
+    /// only the part of it in `range` is collected, clipping the tokens at its ends. This is
+    /// useful when only a few columns of a very long line are visible. The range is clamped to
+    /// the length of the line.
+    ///
+    /// # Panics
+    ///
+    /// Like slicing the line, this panics if the ends of the range aren't on character boundaries.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::easy::HighlightLines;
+    /// use syntect::parsing::SyntaxSet;
+    /// use syntect::highlighting::ThemeSet;
+    ///
+    /// let ps = SyntaxSet::load_defaults_newlines();
+    /// let ts = ThemeSet::load_defaults();
+    /// let syntax = ps.find_syntax_by_extension("rs").unwrap();
+    /// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+    /// let ranges = h.highlight_range("let answer = 42;\n", 4..10, &ps).unwrap();
+    /// let text: String = ranges.iter().map(|(_, s)| *s).collect();
+    /// assert_eq!(text, "answer");
+    /// ```
+    pub fn highlight_range<'b>(
+        &mut self,
+        line: &'b str,
+        range: Range<usize>,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(Style, &'b str)>, Error> {
+        let end = range.end.min(line.len());
+        let start = range.start.min(end);
+        let ops = self.parse_state.parse_line(line, syntax_set)?;
+        let iter =
+            HighlightIterator::new(&mut self.highlight_state, &ops[..], line, &self.highlighter);
+        let mut ranges = Vec::new();
+        let mut pos = 0;
+        // the iterator has to run to the end of the line to keep the highlight state right
+        for (style, text) in iter {
+            let token_start = pos;
+            pos += text.len();
+            let clipped = token_start.max(start)..pos.min(end);
+            if !clipped.is_empty() {
+                ranges.push((style, &line[clipped]));
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// The state of the parser, after the lines highlighted so far
+    pub fn parse_state(&self) -> &ParseState {
+        &self.parse_state
+    }
+
+    /// The state of the highlighter, after the lines highlighted so far
+    pub fn highlight_state(&self) -> &HighlightState {
+        &self.highlight_state
+    }
+
+    /// Continues highlighting from states saved with [`parse_state`] and [`highlight_state`]
+    ///
+    /// The states have to be from the same point in a file, and the parse state from the same
+    /// syntax set, otherwise the highlighting is wrong or fails with an error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::easy::HighlightLines;
+    /// use syntect::highlighting::ThemeSet;
+    /// use syntect::parsing::SyntaxSet;
+    ///
+    /// let ps = SyntaxSet::load_defaults_newlines();
+    /// let ts = ThemeSet::load_defaults();
+    /// let syntax = ps.find_syntax_by_extension("rs").unwrap();
+    /// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+    /// h.highlight_line("/* a comment\n", &ps).unwrap();
+    /// let checkpoint = (h.parse_state().clone(), h.highlight_state().clone());
+    ///
+    /// let in_comment = h.highlight_line("fn main() {}\n", &ps).unwrap();
+    /// h.set_state(checkpoint.0, checkpoint.1);
+    /// assert_eq!(h.highlight_line("fn main() {}\n", &ps).unwrap(), in_comment);
+    /// ```
+    ///
+    /// [`parse_state`]: #method.parse_state
+    /// [`highlight_state`]: #method.highlight_state
+    pub fn set_state(&mut self, parse_state: ParseState, highlight_state: HighlightState) {
+        self.parse_state = parse_state;
+        self.highlight_state = highlight_state;
+    }
+}
+
+/// The time spent in each phase of highlighting a text, returned by [`highlight_to_nothing`]
+///
+/// [`highlight_to_nothing`]: fn.highlight_to_nothing.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighlightProfile {
+    /// The number of lines of the text
+    pub lines: usize,
+    /// Parsing the lines, including `regex_search`
+    pub parse: Duration,
+    /// Searching for the patterns of the syntax, including compiling their regexes on first use
+    pub regex_search: Duration,
+    /// Applying the operations from the parser to the stack of scopes, not including
+    /// `style_resolution`
+    pub op_application: Duration,
+    /// Computing the styles of the scopes from the theme
+    pub style_resolution: Duration,
+    /// The whole run
+    pub total: Duration,
+}
+
+/// Parses and highlights a text like [`HighlightLines`] would, but discards the result and
+/// returns how long each phase took
+///
+/// This is meant for keeping track of the performance of syntaxes and themes, e.g. in CI,
+/// without a benchmarking setup. Timing the phases adds some overhead, so the total is a bit
+/// higher than highlighting the text normally. Like [`HighlightLines`], this takes the lines of
+/// the text with or without newlines depending on how the syntaxes were loaded. The timings need
+/// a clock, so this panics on targets without one, like `wasm32-unknown-unknown`.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::highlight_to_nothing;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ps = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ps.find_syntax_by_extension("rs").unwrap();
+/// let text = "fn main() {\n    println!(\"hi\");\n}\n";
+/// let profile = highlight_to_nothing(text, syntax, &ps, &ts.themes["base16-ocean.dark"]).unwrap();
+/// assert_eq!(profile.lines, 3);
+/// assert!(profile.regex_search <= profile.parse);
+/// ```
+///
+/// [`HighlightLines`]: struct.HighlightLines.html
+pub fn highlight_to_nothing(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+) -> Result<HighlightProfile, Error> {
+    let start = Instant::now();
+    let highlighter = Highlighter::new(theme);
+    let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+    let mut parse_state = ParseState::new(syntax);
+    let mut profile = HighlightProfile::default();
+    let mut highlight = Duration::ZERO;
+    for line in LinesWithEndings::from(text) {
+        let parse_start = Instant::now();
+        let mut stats = LineStats::default();
+        let ops = parse_state.parse_line_profiled(line, syntax_set, &mut stats)?;
+        profile.parse += parse_start.elapsed();
+        profile.regex_search += stats.search_time;
+
+        let highlight_start = Instant::now();
+        let mut iter = RangedHighlightIterator::new(&mut highlight_state, &ops, line, &highlighter);
+        iter.style_time = Some(Duration::ZERO);
+        for _ in iter.by_ref() {}
+        highlight += highlight_start.elapsed();
+        profile.style_resolution += iter.style_time.unwrap_or_default();
+        profile.lines += 1;
+    }
+    profile.op_application = highlight.saturating_sub(profile.style_resolution);
+    profile.total = start.elapsed();
+    Ok(profile)
+}
+
+/// A part of a Markdown document, returned by [`highlight_markdown`]
+///
+/// [`highlight_markdown`]: fn.highlight_markdown.html
+#[derive(Debug, Clone)]
+pub enum MarkdownSegment<'a> {
+    /// Text outside of fenced code blocks, including the fences themselves, left as it is
+    Prose(&'a str),
+    /// The contents of a fenced code block
+    Code {
+        /// The info string after the opening fence, e.g. `rust,ignore`
+        info: &'a str,
+        /// The syntax the block was highlighted with, plain text if none was found
+        syntax: &'a SyntaxReference,
+        /// The code, without the fences
+        code: &'a str,
+        /// The highlighted code, including the line endings
+        regions: Vec<(Style, &'a str)>,
+    },
+}
+
+/// Highlights the fenced code blocks of a Markdown document, leaving the rest of it untouched
+///
+/// The syntax of each block is found with [`SyntaxSet::find_syntax_by_token`] from the first word
+/// of its info string, so ```` ```rust ````, ```` ```rust,ignore ```` and ```` ```{.rust} ````
+/// all use the Rust syntax. Blocks without a known language are highlighted as plain text. See
+/// [`highlight_markdown_with`] for picking the syntaxes yourself.
+///
+/// The segments are in the order of the document, and joining their text gives back the whole
+/// document. Like [`HighlightLines`] with lines that include newlines, this needs a syntax set
+/// loaded with newlines. Fences follow CommonMark, but code blocks nested in lists or block quotes
+/// aren't recognized.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::{highlight_markdown, MarkdownSegment};
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let doc = "# Example\n\n```rust\nfn main() {}\n```\n\nThe end.\n";
+/// let segments = highlight_markdown(doc, &ss, &ts.themes["base16-ocean.dark"]).unwrap();
+/// assert_eq!(segments.len(), 3);
+/// assert!(matches!(segments[0], MarkdownSegment::Prose("# Example\n\n```rust\n")));
+/// match &segments[1] {
+///     MarkdownSegment::Code { syntax, code, .. } => {
+///         assert_eq!(syntax.name, "Rust");
+///         assert_eq!(*code, "fn main() {}\n");
+///     }
+///     _ => unreachable!(),
+/// }
+/// ```
+///
+/// [`SyntaxSet::find_syntax_by_token`]: ../parsing/struct.SyntaxSet.html#method.find_syntax_by_token
+/// [`highlight_markdown_with`]: fn.highlight_markdown_with.html
+/// [`HighlightLines`]: struct.HighlightLines.html
+pub fn highlight_markdown<'a>(
+    text: &'a str,
+    syntax_set: &'a SyntaxSet,
+    theme: &Theme,
+) -> Result<Vec<MarkdownSegment<'a>>, Error> {
+    highlight_markdown_with(text, syntax_set, theme, |info| {
+        syntax_set.find_syntax_by_token(info_string_language(info)?)
+    })
+}
+
+/// The language of a code block from its info string, like `rust` in `rust,ignore` or `{.rust}`
+pub(crate) fn info_string_language(info: &str) -> Option<&str> {
+    info.split(|c: char| c.is_whitespace() || c == ',' || c == '{' || c == '}')
+        .map(|word| word.trim_start_matches('.'))
+        .find(|word| !word.is_empty())
+}
+
+/// Like [`highlight_markdown`], but calls `find_syntax` with the info string of each code block
+/// to find its syntax, e.g. to support aliases like `sh` for Bash or to use the syntax of the
+/// surrounding document
+///
+/// [`highlight_markdown`]: fn.highlight_markdown.html
+pub fn highlight_markdown_with<'a, F>(
+    text: &'a str,
+    syntax_set: &'a SyntaxSet,
+    theme: &Theme,
+    mut find_syntax: F,
+) -> Result<Vec<MarkdownSegment<'a>>, Error>
+where
+    F: FnMut(&str) -> Option<&'a SyntaxReference>,
+{
+    let mut segments = Vec::new();
+    let mut prose_start = 0;
+    let mut pos = 0;
+    let mut lines = LinesWithEndings::from(text);
+    while let Some(line) = lines.next() {
+        pos += line.len();
+        let fence = match Fence::open(line) {
+            Some(fence) => fence,
+            None => continue,
+        };
+        let code_start = pos;
+        let mut code_end = text.len();
+        for line in lines.by_ref() {
+            if fence.is_closed_by(line) {
+                code_end = pos;
+                pos += line.len();
+                break;
+            }
+            pos += line.len();
+        }
+
+        segments.push(MarkdownSegment::Prose(&text[prose_start..code_start]));
+        let syntax = find_syntax(fence.info).unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+        let code = &text[code_start..code_end];
+        let mut highlighter = HighlightLines::new(syntax, theme);
+        let mut regions = Vec::new();
+        for line in LinesWithEndings::from(code) {
+            regions.extend(highlighter.highlight_line(line, syntax_set)?);
+        }
+        segments.push(MarkdownSegment::Code {
+            info: fence.info,
+            syntax,
+            code,
+            regions,
+        });
+        // the closing fence starts the next prose
+        prose_start = code_end;
+    }
+    if prose_start < text.len() {
+        segments.push(MarkdownSegment::Prose(&text[prose_start..]));
+    }
+    Ok(segments)
+}
+
+/// The opening fence of a fenced code block
+struct Fence<'a> {
+    fence_char: u8,
+    len: usize,
+    info: &'a str,
+}
+
+impl<'a> Fence<'a> {
+    fn open(line: &'a str) -> Option<Fence<'a>> {
+        let rest = strip_fence_indent(line)?;
+        let fence_char = *rest.as_bytes().first()?;
+        if fence_char != b'`' && fence_char != b'~' {
+            return None;
+        }
+        let len = rest.bytes().take_while(|&c| c == fence_char).count();
+        let info = rest[len..].trim();
+        if len < 3 || (fence_char == b'`' && info.contains('`')) {
+            return None;
+        }
+        Some(Fence {
+            fence_char,
+            len,
+            info,
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let rest = match strip_fence_indent(line) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let len = rest.bytes().take_while(|&c| c == self.fence_char).count();
+        len >= self.len && rest[len..].trim().is_empty()
+    }
+}
+
+/// Fences can be indented by up to three spaces
+/// What highlighting a line took, recorded by [`HighlightFileReport`]
+///
+/// [`HighlightFileReport`]: struct.HighlightFileReport.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineReport {
+    /// Parsing the line, including `regex_search`
+    pub parse: Duration,
+    /// Searching for the patterns of the syntax, including compiling their regexes on first use
+    pub regex_search: Duration,
+    /// Applying the operations from the parser to the stack of scopes and computing the styles
+    pub highlight: Duration,
+    /// How often the parser skipped a character because the matching patterns would have looped
+    /// without consuming anything, which usually means the syntax highlights the line wrong
+    pub loop_skips: usize,
+    /// The unresolved context references that were first hit on this line, e.g. the syntaxes of
+    /// embedded code that aren't in the syntax set. Depending on the
+    /// [`UnresolvedContextPolicy`], their text was highlighted as plain text or their patterns
+    /// were skipped.
+    ///
+    /// [`UnresolvedContextPolicy`]: ../parsing/enum.UnresolvedContextPolicy.html
+    pub fallbacks: Vec<ContextReference>,
+    /// The message of the error parsing the line failed with, e.g. because it hit the backtracking
+    /// limit, the timeout or the stack depth limit of the parse state
+    pub error: Option<String>,
+}
+
+/// Highlights a file like [`HighlightLines`] and records what each line took, for bug reports
+/// about slow or wrong highlighting
+///
+/// Besides the time spent on each line, this records the lines where the parser had to skip a
+/// character to avoid looping, the ones that failed, e.g. by hitting the limits set with
+/// [`ParseState::set_backtrack_limit`], [`ParseState::set_regex_timeout`] and
+/// [`ParseState::set_max_stack_depth`], and the contexts that weren't found and fell back to plain
+/// text, see [`ParseState::set_unresolved_context_policy`]. The highlighting is the same as with
+/// the wrapped [`HighlightLines`], and its `Display` is a summary to paste into an issue. Like
+/// [`highlight_to_nothing`], this needs a clock, so it panics on targets without one, like
+/// `wasm32-unknown-unknown`.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::{HighlightFileReport, HighlightLines};
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::{ParseState, SyntaxSet, UnresolvedContextPolicy};
+/// use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+/// // the limits and policies of the parse state are reported too
+/// let mut parse_state = ParseState::new(syntax);
+/// parse_state.set_backtrack_limit(Some(100_000));
+/// parse_state.set_unresolved_context_policy(UnresolvedContextPolicy::PlainText);
+/// h.set_state(parse_state, h.highlight_state().clone());
+///
+/// let mut report = HighlightFileReport::new(h);
+/// for line in LinesWithEndings::from("fn main() {\n    println!(\"hi\");\n}\n") {
+///     let regions = report.highlight_line(line, &ss).unwrap();
+///     print!("{}", as_24_bit_terminal_escaped(&regions[..], false));
+/// }
+/// assert_eq!(report.lines().len(), 3);
+/// assert!(report.lines().iter().all(|line| line.error.is_none()));
+/// println!("{}", report);
+/// ```
+///
+/// [`HighlightLines`]: struct.HighlightLines.html
+/// [`highlight_to_nothing`]: fn.highlight_to_nothing.html
+/// [`ParseState::set_backtrack_limit`]: ../parsing/struct.ParseState.html#method.set_backtrack_limit
+/// [`ParseState::set_regex_timeout`]: ../parsing/struct.ParseState.html#method.set_regex_timeout
+/// [`ParseState::set_max_stack_depth`]: ../parsing/struct.ParseState.html#method.set_max_stack_depth
+/// [`ParseState::set_unresolved_context_policy`]: ../parsing/struct.ParseState.html#method.set_unresolved_context_policy
+#[derive(Clone)]
+pub struct HighlightFileReport<'a> {
+    highlight_lines: HighlightLines<'a>,
+    lines: Vec<LineReport>,
+}
+
+impl<'a> HighlightFileReport<'a> {
+    /// The number of slowest lines listed by the `Display` of the report
+    const SLOWEST_LINES: usize = 5;
+
+    /// Wraps a [`HighlightLines`] to record the lines highlighted with it from now on
+    ///
+    /// [`HighlightLines`]: struct.HighlightLines.html
+    pub fn new(highlight_lines: HighlightLines<'a>) -> HighlightFileReport<'a> {
+        HighlightFileReport {
+            highlight_lines,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Highlights a line of a file like [`HighlightLines::highlight_line`] and records it
+    ///
+    /// A line that fails is recorded too, and the error is returned as usual.
+    ///
+    /// [`HighlightLines::highlight_line`]: struct.HighlightLines.html#method.highlight_line
+    pub fn highlight_line<'b>(
+        &mut self,
+        line: &'b str,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(Style, &'b str)>, Error> {
+        let h = &mut self.highlight_lines;
+        let mut report = LineReport::default();
+        let known_fallbacks = h.parse_state.unresolved_contexts().len();
+        let mut stats = LineStats::default();
+        let parse_start = Instant::now();
+        let parsed = h
+            .parse_state
+            .parse_line_profiled(line, syntax_set, &mut stats);
+        report.parse = parse_start.elapsed();
+        report.regex_search = stats.search_time;
+        report.loop_skips = stats.loop_skips;
+        report.fallbacks = h.parse_state.unresolved_contexts()[known_fallbacks..].to_vec();
+        let ops = match parsed {
+            Ok(ops) => ops,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                self.lines.push(report);
+                return Err(e.into());
+            }
+        };
+
+        let highlight_start = Instant::now();
+        let iter = HighlightIterator::new(&mut h.highlight_state, &ops, line, &h.highlighter);
+        let regions: Vec<_> = iter.collect();
+        report.highlight = highlight_start.elapsed();
+        self.lines.push(report);
+        Ok(regions)
+    }
+
+    /// The reports of the lines highlighted so far, in order
+    pub fn lines(&self) -> &[LineReport] {
+        &self.lines
+    }
+
+    /// The wrapped highlighter, e.g. for its states
+    pub fn highlight_lines(&self) -> &HighlightLines<'a> {
+        &self.highlight_lines
+    }
+
+    /// Returns the wrapped highlighter, to go on without recording
+    pub fn into_highlight_lines(self) -> HighlightLines<'a> {
+        self.highlight_lines
+    }
+}
+
+impl fmt::Display for HighlightFileReport<'_> {
+    /// Summarizes the report, numbering the lines from 1 like editors do
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parse: Duration = self.lines.iter().map(|line| line.parse).sum();
+        let regex_search: Duration = self.lines.iter().map(|line| line.regex_search).sum();
+        let highlight: Duration = self.lines.iter().map(|line| line.highlight).sum();
+        writeln!(
+            f,
+            "{} lines, parsed in {:?} ({:?} searching regexes), highlighted in {:?}",
+            self.lines.len(),
+            parse,
+            regex_search,
+            highlight
+        )?;
+
+        let mut slowest: Vec<_> = self.lines.iter().enumerate().collect();
+        slowest.sort_by_key(|(_, line)| std::cmp::Reverse(line.parse + line.highlight));
+        writeln!(f, "slowest lines:")?;
+        for (index, line) in slowest.into_iter().take(Self::SLOWEST_LINES) {
+            writeln!(
+                f,
+                "  line {}: parsed in {:?}, highlighted in {:?}",
+                index + 1,
+                line.parse,
+                line.highlight
+            )?;
+        }
+
+        let lines = self.lines.iter().enumerate();
+        for (index, line) in lines.clone().filter(|(_, line)| line.loop_skips > 0) {
+            writeln!(
+                f,
+                "line {}: skipped {} characters to avoid looping",
+                index + 1,
+                line.loop_skips
+            )?;
+        }
+        for (index, line) in lines.clone() {
+            for reference in &line.fallbacks {
+                writeln!(f, "line {}: didn't find {:?}", index + 1, reference)?;
+            }
+        }
+        for (index, line) in lines {
+            if let Some(error) = &line.error {
+                writeln!(f, "line {}: failed: {}", index + 1, error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn strip_fence_indent(line: &str) -> Option<&str> {
+    let rest = line.trim_start_matches(' ');
+    if line.len() - rest.len() > 3 {
+        None
+    } else {
+        Some(rest)
     }
 }
 
@@ -271,6 +965,249 @@ impl<'a> Iterator for ScopeRegionIterator<'a> {
     }
 }
 
+/// Highlights whole texts, remembering the results so highlighting the same text again is nearly
+/// free, e.g. for a server that renders the same code blocks over and over
+///
+/// Two caches are kept, each evicting the least recently used entries when it's full:
+///
+/// * The highlighted lines of each text, keyed by the text, the syntax and the theme.
+/// * The parser state and operations after each line, keyed by the line and the parser state
+///   before it. A text that starts with the same lines as a text highlighted before, e.g. after an
+///   edit near its end, only has the lines after the common prefix parsed again, whichever theme
+///   it's highlighted with.
+///
+/// Entries are found by a hash of their key, and the whole key is compared before one is used, so
+/// a hash collision can't return the results for something else. Syntaxes and themes are told
+/// apart by their address, which is why they have to live as long as the cache. The caches are behind a mutex, so the highlighter can be shared between threads.
+///
+/// Texts are split into lines with [`LinesWithEndings`], so the syntax set has to be one loaded
+/// with newlines, see [`SyntaxSet::load_defaults_newlines`].
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::CachedHighlighter;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+/// use std::sync::Arc;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let theme = &ts.themes["base16-ocean.dark"];
+///
+/// let highlighter = CachedHighlighter::new(&ss);
+/// let lines = highlighter.highlight("fn main() {}\n", syntax, theme).unwrap();
+/// assert_eq!(lines[0][0].1, "fn");
+/// let again = highlighter.highlight("fn main() {}\n", syntax, theme).unwrap();
+/// assert!(Arc::ptr_eq(&lines, &again));
+/// ```
+///
+/// [`LinesWithEndings`]: ../util/struct.LinesWithEndings.html
+/// [`SyntaxSet::load_defaults_newlines`]: ../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+pub struct CachedHighlighter<'a> {
+    syntax_set: &'a SyntaxSet,
+    caches: Mutex<Caches>,
+}
+
+/// The highlighted lines of a text, each a list of styled regions
+pub type HighlightedLines = Vec<Vec<(Style, String)>>;
+
+struct Caches {
+    highlighted: Lru<HighlightedText>,
+    parsed: Lru<ParsedLine>,
+}
+
+struct HighlightedText {
+    text: String,
+    syntax: usize,
+    theme: usize,
+    lines: Arc<HighlightedLines>,
+}
+
+struct ParsedLine {
+    line: String,
+    /// The state the line was parsed with, shared with the entry of the line before it
+    state_before: Arc<ParseState>,
+    state: Arc<ParseState>,
+    ops: Vec<(usize, ScopeStackOp)>,
+}
+
+impl<'a> CachedHighlighter<'a> {
+    /// The number of highlighted texts kept by [`new`](#method.new)
+    pub const DEFAULT_TEXT_CAPACITY: usize = 256;
+    /// The number of parsed lines kept by [`new`](#method.new)
+    pub const DEFAULT_LINE_CAPACITY: usize = 16_384;
+
+    /// Creates a highlighter for syntaxes from `syntax_set`, with the default capacities
+    pub fn new(syntax_set: &'a SyntaxSet) -> CachedHighlighter<'a> {
+        Self::with_capacity(
+            syntax_set,
+            Self::DEFAULT_TEXT_CAPACITY,
+            Self::DEFAULT_LINE_CAPACITY,
+        )
+    }
+
+    /// Creates a highlighter that keeps up to `texts` highlighted texts and the parser state
+    /// after up to `lines` lines
+    pub fn with_capacity(
+        syntax_set: &'a SyntaxSet,
+        texts: usize,
+        lines: usize,
+    ) -> CachedHighlighter<'a> {
+        CachedHighlighter {
+            syntax_set,
+            caches: Mutex::new(Caches {
+                highlighted: Lru::new(texts),
+                parsed: Lru::new(lines),
+            }),
+        }
+    }
+
+    /// Highlights `text`, or returns the lines highlighted before for the same text, syntax and
+    /// theme
+    pub fn highlight(
+        &self,
+        text: &str,
+        syntax: &'a SyntaxReference,
+        theme: &'a Theme,
+    ) -> Result<Arc<HighlightedLines>, Error> {
+        let syntax_key = syntax as *const SyntaxReference as usize;
+        let theme_key = theme as *const Theme as usize;
+        let key = hash_of(&(text, syntax_key, theme_key));
+        if let Some(cached) = self.lock().highlighted.get(key) {
+            if cached.text == text && cached.syntax == syntax_key && cached.theme == theme_key {
+                return Ok(cached.lines.clone());
+            }
+        }
+
+        // Each line is found by a hash of the syntax and all the lines up to it, so the ops of the
+        // longest prefix of lines parsed before can be reused
+        let line_keys: Vec<u64> = LinesWithEndings::from(text)
+            .scan(hash_of(&syntax_key), |prefix_key, line| {
+                *prefix_key = hash_of(&(*prefix_key, line));
+                Some(*prefix_key)
+            })
+            .collect();
+        let mut all_ops = Vec::with_capacity(line_keys.len());
+        let mut state = Arc::new(ParseState::new(syntax));
+        {
+            let mut caches = self.lock();
+            for (line, &line_key) in LinesWithEndings::from(text).zip(&line_keys) {
+                match caches.parsed.get(line_key) {
+                    Some(parsed)
+                        if parsed.line == line
+                            && (Arc::ptr_eq(&parsed.state_before, &state)
+                                || parsed.state_before == state) =>
+                    {
+                        all_ops.push(parsed.ops.clone());
+                        state = parsed.state.clone();
+                    }
+                    _ => break,
+                }
+            }
+        }
+        let mut parse_state = (*state).clone();
+        let reused = all_ops.len();
+        let mut parsed = Vec::new();
+        for line in LinesWithEndings::from(text).skip(reused) {
+            let ops = parse_state.parse_line(line, self.syntax_set)?;
+            let state_after = Arc::new(parse_state.clone());
+            parsed.push(ParsedLine {
+                line: line.to_owned(),
+                state_before: mem::replace(&mut state, state_after.clone()),
+                state: state_after,
+                ops: ops.clone(),
+            });
+            all_ops.push(ops);
+        }
+
+        let highlighter = Highlighter::new(theme);
+        let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+        let lines: HighlightedLines = LinesWithEndings::from(text)
+            .zip(&all_ops)
+            .map(|(line, ops)| {
+                HighlightIterator::new(&mut highlight_state, ops, line, &highlighter)
+                    .map(|(style, s)| (style, s.to_owned()))
+                    .collect()
+            })
+            .collect();
+        let lines = Arc::new(lines);
+
+        let mut caches = self.lock();
+        for (&line_key, line) in line_keys[reused..].iter().zip(parsed) {
+            caches.parsed.insert(line_key, line);
+        }
+        caches.highlighted.insert(
+            key,
+            HighlightedText {
+                text: text.to_owned(),
+                syntax: syntax_key,
+                theme: theme_key,
+                lines: lines.clone(),
+            },
+        );
+        Ok(lines)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Caches> {
+        // The caches stay consistent even if a thread panicked while holding the lock
+        self.caches.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A map from hashes to values which drops the least recently used entries beyond its capacity
+struct Lru<V> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<u64, (u64, V)>,
+    /// The keys of the entries by when they were last used
+    order: BTreeMap<u64, u64>,
+}
+
+impl<V> Lru<V> {
+    fn new(capacity: usize) -> Lru<V> {
+        Lru {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: u64) -> Option<&V> {
+        let (used, value) = self.entries.get_mut(&key)?;
+        self.order.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, key);
+        Some(value)
+    }
+
+    fn insert(&mut self, key: u64, value: V) {
+        if let Some((used, _)) = self.entries.remove(&key) {
+            self.order.remove(&used);
+        }
+        while self.entries.len() >= self.capacity {
+            let (&used, &oldest) = match self.order.iter().next() {
+                Some(entry) => entry,
+                None => return,
+            };
+            self.order.remove(&used);
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        self.entries.insert(key, (self.tick, value));
+        self.order.insert(self.tick, key);
+    }
+}
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -279,6 +1216,59 @@ mod tests {
     use crate::parsing::{ParseState, ScopeStack, SyntaxSet};
     use std::str::FromStr;
 
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_highlight_markdown_code_blocks() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let doc =
+            "Text\n~~~~ {.python}\nx = 1\n```\n~~~~~\n    ```\nnot code\n```unknown\nstill code";
+        let segments = highlight_markdown(doc, &ss, theme).unwrap();
+        let text: String = segments
+            .iter()
+            .map(|segment| match segment {
+                MarkdownSegment::Prose(prose) => *prose,
+                MarkdownSegment::Code { code, .. } => code,
+            })
+            .collect();
+        assert_eq!(text, doc);
+
+        let code_blocks: Vec<_> = segments
+            .iter()
+            .filter_map(|segment| match segment {
+                MarkdownSegment::Code {
+                    info,
+                    syntax,
+                    code,
+                    regions,
+                } => Some((*info, syntax.name.as_str(), *code, regions)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(code_blocks.len(), 2);
+        // a shorter fence of a different character doesn't close the block
+        assert_eq!(code_blocks[0].0, "{.python}");
+        assert_eq!(code_blocks[0].1, "Python");
+        assert_eq!(code_blocks[0].2, "x = 1\n```\n");
+        assert!(code_blocks[0].3.len() > 2);
+        // an unclosed block runs to the end
+        assert_eq!(code_blocks[1].1, "Plain Text");
+        assert_eq!(code_blocks[1].2, "still code");
+        assert!(matches!(
+            segments[2],
+            MarkdownSegment::Prose("~~~~~\n    ```\nnot code\n```unknown\n")
+        ));
+
+        let segments = highlight_markdown_with("```py\nx\n```\n", &ss, theme, |_| {
+            ss.find_syntax_by_name("Rust")
+        })
+        .unwrap();
+        assert!(
+            matches!(&segments[1], MarkdownSegment::Code { syntax, .. } if syntax.name == "Rust")
+        );
+    }
+
     #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
     #[test]
     fn can_highlight_lines() {
@@ -292,6 +1282,123 @@ mod tests {
         assert!(ranges.len() > 4);
     }
 
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_highlight_lines_with_shared_theme() {
+        let ss = SyntaxSet::load_defaults_nonewlines();
+        let mut ts = ThemeSet::load_defaults();
+        let theme = Arc::new(ts.themes.remove("base16-ocean.dark").unwrap());
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let mut borrowed = HighlightLines::new(syntax, &theme);
+        let expected: Vec<(Style, String)> = borrowed
+            .highlight_line("pub struct Wow { hi: u64 }", &ss)
+            .unwrap()
+            .into_iter()
+            .map(|(style, text)| (style, text.to_owned()))
+            .collect();
+
+        let mut h = HighlightLines::from_shared_theme(syntax, theme);
+        let ranges = std::thread::spawn(move || {
+            h.highlight_line("pub struct Wow { hi: u64 }", &ss)
+                .unwrap()
+                .into_iter()
+                .map(|(style, text)| (style, text.to_owned()))
+                .collect::<Vec<_>>()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(ranges, expected);
+    }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_highlight_lines_in_chunks() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let lines = ["let s = \"a ☃ string\";\n", "// done\n"];
+
+        let mut whole = HighlightLines::new(syntax, theme);
+        let mut chunked = HighlightLines::new(syntax, theme);
+        for line in lines {
+            let expected = whole.highlight_line(line, &ss).unwrap();
+            // with an empty chunk and a chunk of just the snowman on the first line
+            let splits = [0, 3, 4, 9, 11, 11, 14, line.len()];
+            let splits: Vec<usize> = splits
+                .into_iter()
+                .filter(|&i| line.is_char_boundary(i))
+                .collect();
+            let chunks: Vec<&str> = splits.windows(2).map(|w| &line[w[0]..w[1]]).collect();
+            let regions = chunked.highlight_line_chunks(chunks, &ss).unwrap();
+
+            assert_eq!(strip_styles(&regions).0, line);
+            // the style of every byte is the same
+            let styles = |regions: &[(Style, &str)]| -> Vec<Style> {
+                regions
+                    .iter()
+                    .flat_map(|&(style, text)| std::iter::repeat_n(style, text.len()))
+                    .collect()
+            };
+            assert_eq!(styles(&regions), styles(&expected));
+            assert!(regions.iter().all(|(_, text)| !text.is_empty()));
+            assert_eq!(whole.parse_state(), chunked.parse_state());
+        }
+        let no_chunks: [&str; 0] = [];
+        assert!(chunked
+            .highlight_line_chunks(no_chunks, &ss)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_branch_highlighting_by_cloning() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+        h.highlight_line("let s = \"open\n", &ss).unwrap();
+        let mut branch = h.clone();
+        h.highlight_line("still a string\"\n", &ss).unwrap();
+        assert_ne!(h.parse_state(), branch.parse_state());
+        assert_ne!(h.highlight_state(), branch.highlight_state());
+
+        let branched = branch.highlight_line("still a string\"\n", &ss).unwrap();
+        assert_eq!(h.parse_state(), branch.parse_state());
+        assert_eq!(h.highlight_state(), branch.highlight_state());
+        assert_eq!(branched.len(), 3);
+    }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn can_highlight_ranges() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let mut full = HighlightLines::new(syntax, theme);
+        let mut clipped = HighlightLines::new(syntax, theme);
+
+        let line = "/* a\n";
+        let all = full.highlight_line(line, &ss).unwrap();
+        assert_eq!(
+            clipped.highlight_range(line, 3..100, &ss).unwrap(),
+            [(all[0].0, "a\n")]
+        );
+
+        // the comment continues, so the state has to be kept over the skipped parts
+        let line = "b */ let x = \"hello\";\n";
+        let all = full.highlight_line(line, &ss).unwrap();
+        let ranges = clipped.highlight_range(line, 1..16, &ss).unwrap();
+        let text: String = ranges.iter().map(|(_, s)| *s).collect();
+        assert_eq!(text, &line[1..16]);
+        assert_eq!(&ranges[..2], &[(all[0].0, " "), all[1]]);
+        let string_style = all.iter().find(|(_, s)| s.contains("hello")).unwrap().0;
+        assert_eq!(ranges.last(), Some(&(string_style, "he")));
+        assert!(clipped.highlight_range(line, 5..5, &ss).unwrap().is_empty());
+    }
+
     #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
     #[test]
     fn can_highlight_file() {
@@ -357,4 +1464,162 @@ mod tests {
             assert_eq!(all_ops.count(), iterated_ops.len() - 1); // -1 because we want to ignore the NOOP
         }
     }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn cached_highlighter_reuses_results() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let dark = &ts.themes["base16-ocean.dark"];
+        let light = &ts.themes["InspiredGitHub"];
+        let uncached = |text: &str, theme: &Theme| -> HighlightedLines {
+            let mut h = HighlightLines::new(syntax, theme);
+            LinesWithEndings::from(text)
+                .map(|line| {
+                    let regions = h.highlight_line(line, &ss).unwrap();
+                    regions.iter().map(|&(s, r)| (s, r.to_owned())).collect()
+                })
+                .collect()
+        };
+
+        fn assert_sync<T: Sync>(_: &T) {}
+        let highlighter = CachedHighlighter::new(&ss);
+        assert_sync(&highlighter);
+        let text = "/* a\nb */\nfn main() {}\n";
+        let lines = highlighter.highlight(text, syntax, dark).unwrap();
+        assert_eq!(*lines, uncached(text, dark));
+        let again = highlighter.highlight(text, syntax, dark).unwrap();
+        assert!(Arc::ptr_eq(&lines, &again));
+        assert_eq!(highlighter.lock().parsed.entries.len(), 3);
+
+        // only the changed last line is parsed again
+        let edited = "/* a\nb */\nfn other() {}\n";
+        let lines = highlighter.highlight(edited, syntax, dark).unwrap();
+        assert_eq!(*lines, uncached(edited, dark));
+        assert_eq!(highlighter.lock().parsed.entries.len(), 4);
+
+        // the parsed lines are shared between themes
+        let lines = highlighter.highlight(text, syntax, light).unwrap();
+        assert_eq!(*lines, uncached(text, light));
+        assert!(!Arc::ptr_eq(&lines, &again));
+        assert_eq!(highlighter.lock().parsed.entries.len(), 4);
+
+        // entries whose hash collides with the key aren't used
+        let other = "fn collides() {}\n";
+        let syntax_key = syntax as *const SyntaxReference as usize;
+        let theme_key = dark as *const Theme as usize;
+        let key = hash_of(&(other, syntax_key, theme_key));
+        let line_key = hash_of(&(hash_of(&syntax_key), other));
+        {
+            let mut caches = highlighter.lock();
+            caches.highlighted.insert(
+                key,
+                HighlightedText {
+                    text: other.to_owned(),
+                    syntax: syntax_key,
+                    theme: light as *const Theme as usize,
+                    lines: again.clone(),
+                },
+            );
+            let mut state = ParseState::new(syntax);
+            state.parse_line("/* a\n", &ss).unwrap();
+            caches.parsed.insert(
+                line_key,
+                ParsedLine {
+                    line: other.to_owned(),
+                    state_before: Arc::new(state.clone()),
+                    ops: state.parse_line(other, &ss).unwrap(),
+                    state: Arc::new(state),
+                },
+            );
+        }
+        let lines = highlighter.highlight(other, syntax, dark).unwrap();
+        assert_eq!(*lines, uncached(other, dark));
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn reports_loops_fallbacks_and_errors() {
+        use crate::highlighting::Theme;
+        use crate::parsing::{Scope, SyntaxDefinition, SyntaxSetBuilder, UnresolvedContextPolicy};
+        let syntax = SyntaxDefinition::load_from_str(
+            r"
+name: Test
+scope: source.test
+contexts:
+  main:
+    - match: (?=hello)
+      push: test
+    - match: '@'
+      push: scope:source.missing
+  test:
+    # pops back to main without consuming anything, so the parser skips a character
+    - match: (?!world)
+      pop: true
+    - match: \d+
+      scope: constant.test
+",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_plain_text_syntax();
+        builder.add(syntax);
+        let ss = builder.build();
+        let syntax = ss.find_syntax_by_name("Test").unwrap();
+        let theme = Theme::default();
+        let mut report = HighlightFileReport::new(HighlightLines::new(syntax, &theme));
+        let parse_state = &mut report.highlight_lines.parse_state;
+        parse_state.set_unresolved_context_policy(UnresolvedContextPolicy::PlainText);
+
+        report.highlight_line("hello\n", &ss).unwrap();
+        let parse_state = &mut report.highlight_lines.parse_state;
+        parse_state.set_max_stack_depth(Some(2));
+        assert!(report.highlight_line("hello\n", &ss).is_err());
+        let parse_state = &mut report.highlight_lines.parse_state;
+        parse_state.set_max_stack_depth(None);
+        report.highlight_line("x @ y\n", &ss).unwrap();
+
+        let lines = report.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].loop_skips, 1);
+        assert!(lines[0].error.is_none() && lines[0].fallbacks.is_empty());
+        assert!(lines[1].error.as_ref().unwrap().contains("deeper than 2"));
+        assert_eq!(lines[2].loop_skips, 0);
+        assert_eq!(
+            lines[2].fallbacks,
+            [ContextReference::ByScope {
+                scope: Scope::new("source.missing").unwrap(),
+                sub_context: None,
+                with_escape: false,
+            }]
+        );
+
+        let summary = report.to_string();
+        assert!(summary.starts_with("3 lines, parsed in "), "{}", summary);
+        assert!(summary.contains("line 1: skipped 1 characters to avoid looping\n"));
+        assert!(summary.contains("line 2: failed: "));
+        assert!(summary.contains("line 3: didn't find ByScope"));
+    }
+
+    #[test]
+    fn lru_drops_least_recently_used() {
+        let mut lru = Lru::new(2);
+        lru.insert(1, "a");
+        lru.insert(2, "b");
+        assert_eq!(lru.get(1), Some(&"a"));
+        lru.insert(3, "c");
+        assert_eq!(lru.get(2), None);
+        assert_eq!(lru.get(1), Some(&"a"));
+        lru.insert(1, "d");
+        assert_eq!(lru.get(1), Some(&"d"));
+        assert_eq!(lru.get(3), Some(&"c"));
+        assert_eq!(lru.entries.len(), 2);
+
+        let mut lru = Lru::new(0);
+        lru.insert(1, "a");
+        assert_eq!(lru.get(1), None);
+    }
 }
@@ -0,0 +1,314 @@
+//! Parsing of the CSS color forms used by `.sublime-color-scheme` files and converted VS Code
+//! themes: `rgb()`, `rgba()`, `hsl()`, `hsla()` and named colors
+
+use super::style::Color;
+
+/// Parses a color function like `rgb(255, 0, 0)`, `rgba(255 0 0 / 50%)` or
+/// `hsl(120deg, 100%, 50%)`
+pub(crate) fn parse_color_function(s: &str) -> Option<Color> {
+    let (name, args) = s.strip_suffix(')')?.split_once('(')?;
+    let args: Vec<&str> = args
+        .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+        .filter(|a| !a.is_empty())
+        .collect();
+    let (components, alpha) = match args[..] {
+        [a, b, c] => ([a, b, c], None),
+        [a, b, c, alpha] => ([a, b, c], Some(alpha)),
+        _ => return None,
+    };
+    let a = match alpha {
+        Some(alpha) => to_u8(parse_fraction(alpha)?),
+        None => 255,
+    };
+    match name.trim().to_ascii_lowercase().as_str() {
+        "rgb" | "rgba" => {
+            let mut rgb = [0; 3];
+            for (c, arg) in rgb.iter_mut().zip(components) {
+                *c = match arg.strip_suffix('%') {
+                    Some(percent) => to_u8(percent.parse::<f64>().ok()? / 100.0),
+                    None => arg.parse::<f64>().ok()?.round().clamp(0.0, 255.0) as u8,
+                };
+            }
+            let [r, g, b] = rgb;
+            Some(Color { r, g, b, a })
+        }
+        "hsl" | "hsla" => {
+            let [h, s, l] = components;
+            let h = h.strip_suffix("deg").unwrap_or(h).parse::<f64>().ok()?;
+            let s = s.strip_suffix('%')?.parse::<f64>().ok()? / 100.0;
+            let l = l.strip_suffix('%')?.parse::<f64>().ok()? / 100.0;
+            let (r, g, b) = hsl_to_rgb(h, s.clamp(0.0, 1.0), l.clamp(0.0, 1.0));
+            Some(Color {
+                r: to_u8(r),
+                g: to_u8(g),
+                b: to_u8(b),
+                a,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Parses an alpha value, either a number from 0 to 1 or a percentage
+fn parse_fraction(s: &str) -> Option<f64> {
+    match s.strip_suffix('%') {
+        Some(percent) => Some(percent.parse::<f64>().ok()? / 100.0),
+        None => s.parse().ok(),
+    }
+}
+
+fn to_u8(fraction: f64) -> u8 {
+    (fraction.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Converts a hue in degrees and saturation and lightness from 0 to 1 to RGB from 0 to 1, see
+/// <https://www.w3.org/TR/css-color-3/#hsl-color>
+fn hsl_to_rgb(h: f64, s: f64, l: f64) -> (f64, f64, f64) {
+    let h = h.rem_euclid(360.0) / 360.0;
+    let m2 = if l <= 0.5 {
+        l * (s + 1.0)
+    } else {
+        l + s - l * s
+    };
+    let m1 = l * 2.0 - m2;
+    let hue_to_rgb = |h: f64| {
+        let h = h.rem_euclid(1.0);
+        if h * 6.0 < 1.0 {
+            m1 + (m2 - m1) * h * 6.0
+        } else if h * 2.0 < 1.0 {
+            m2
+        } else if h * 3.0 < 2.0 {
+            m1 + (m2 - m1) * (2.0 / 3.0 - h) * 6.0
+        } else {
+            m1
+        }
+    };
+    (
+        hue_to_rgb(h + 1.0 / 3.0),
+        hue_to_rgb(h),
+        hue_to_rgb(h - 1.0 / 3.0),
+    )
+}
+
+/// Looks up a CSS named color, ignoring case
+pub(crate) fn named_color(name: &str) -> Option<Color> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        });
+    }
+    let i = NAMED_COLORS
+        .binary_search_by_key(&name.as_str(), |&(n, _)| n)
+        .ok()?;
+    let rgb = NAMED_COLORS[i].1;
+    Some(Color {
+        r: (rgb >> 16) as u8,
+        g: (rgb >> 8) as u8,
+        b: rgb as u8,
+        a: 255,
+    })
+}
+
+/// The named colors of CSS Color Module Level 4, sorted by name
+static NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgba(r: u8, g: u8, b: u8, a: u8) -> Option<Color> {
+        Some(Color { r, g, b, a })
+    }
+
+    #[test]
+    fn named_colors_are_sorted() {
+        assert!(NAMED_COLORS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert_eq!(named_color("RebeccaPurple"), rgba(0x66, 0x33, 0x99, 255));
+        assert_eq!(named_color("transparent"), rgba(0, 0, 0, 0));
+        assert_eq!(named_color("blurple"), None);
+    }
+
+    #[test]
+    fn can_parse_color_functions() {
+        assert_eq!(
+            parse_color_function("rgb(255, 0, 10)"),
+            rgba(255, 0, 10, 255)
+        );
+        assert_eq!(
+            parse_color_function("rgba(255,0,10,0.5)"),
+            rgba(255, 0, 10, 128)
+        );
+        assert_eq!(
+            parse_color_function("rgb(100% 0% 50% / 25%)"),
+            rgba(255, 0, 128, 64)
+        );
+        assert_eq!(
+            parse_color_function("hsl(120, 100%, 50%)"),
+            rgba(0, 255, 0, 255)
+        );
+        assert_eq!(
+            parse_color_function("hsla(0deg, 100%, 25%, 1)"),
+            rgba(128, 0, 0, 255)
+        );
+        assert_eq!(
+            parse_color_function("hsl(240, 50%, 75%)"),
+            rgba(159, 159, 223, 255)
+        );
+        assert_eq!(parse_color_function("rgb(1, 2)"), None);
+        assert_eq!(parse_color_function("hsl(1, 2, 3)"), None);
+        assert_eq!(parse_color_function("lab(1, 2, 3)"), None);
+    }
+}
@@ -3,11 +3,17 @@
 // Code based on https://github.com/defuz/sublimate/blob/master/src/core/syntax/highlighter.rs
 // released under the MIT license by @defuz
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::iter::Iterator;
-use std::ops::Range;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use super::selector::ScopeSelector;
-use super::style::{Color, FontStyle, Style, StyleModifier};
+use super::style::{
+    Color, CompactStyle, FontHint, FontStyle, Style, StyleModifier, UnderlineStyle,
+};
 use super::theme::{Theme, ThemeItem};
 use crate::parsing::{
     BasicScopeStackOp, MatchPower, Scope, ScopeStack, ScopeStackOp, ATOM_LEN_BITS,
@@ -19,20 +25,61 @@ use crate::parsing::{
 /// selectors of the theme on various scope paths or setting up some kind of
 /// accelerator structure.
 ///
-/// So for now this does very little but eventually if you keep it around between
-/// highlighting runs it will preserve its cache.
+/// Creating one indexes the selectors of the theme, so that only the ones which could match a
+/// scope path are tested. Keep it around between highlighting runs rather than creating a new one
+/// for each.
+///
+/// A highlighter either borrows its theme, see [`new`], or shares ownership of it, see
+/// [`from_shared`], which makes it `'static` so it can be kept next to the theme in a struct or
+/// moved to another thread.
+///
+/// Highlighters are `Send` and `Sync`, and highlighting only reads them, so one highlighter can
+/// be used by many threads at once. To share one between the tasks of a worker pool, see
+/// [`PreparedTheme`].
 ///
 /// [`Theme`]: struct.Theme.html
-#[derive(Debug)]
+/// [`new`]: #method.new
+/// [`from_shared`]: #method.from_shared
+/// [`PreparedTheme`]: struct.PreparedTheme.html
+#[derive(Debug, Clone)]
 pub struct Highlighter<'a> {
-    theme: &'a Theme,
+    theme: ThemeRef<'a>,
     /// Cache of the selectors in the theme that are only one scope
     /// In most themes this is the majority, hence the usefullness
     single_selectors: Vec<(Scope, StyleModifier)>,
     multi_selectors: Vec<(ScopeSelector, StyleModifier)>,
+    /// Indices into `multi_selectors`, keyed on a scope which has to be a prefix of one of the
+    /// scopes in a stack for the selector to match it
+    multi_index: HashMap<Scope, Vec<usize>>,
+    /// Indices of the multi selectors which have no such scope, so always have to be tested
+    unindexed_multis: Vec<usize>,
     // TODO single_cache: HashMap<Scope, StyleModifier, BuildHasherDefault<FnvHasher>>,
 }
 
+thread_local! {
+    /// The indices of the multi selectors to test for a stack, kept around so that finalizing a
+    /// style doesn't allocate
+    static MULTI_CANDIDATES: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The theme of a [`Highlighter`], either borrowed or shared
+#[derive(Debug, Clone)]
+enum ThemeRef<'a> {
+    Borrowed(&'a Theme),
+    Shared(Arc<Theme>),
+}
+
+impl Deref for ThemeRef<'_> {
+    type Target = Theme;
+
+    fn deref(&self) -> &Theme {
+        match self {
+            ThemeRef::Borrowed(theme) => theme,
+            ThemeRef::Shared(theme) => theme,
+        }
+    }
+}
+
 /// Keeps a stack of scopes and styles as state between highlighting different lines.
 ///
 /// If you are highlighting an entire file you create one of these at the start and use it
@@ -53,15 +100,36 @@ pub struct Highlighter<'a> {
 /// do so eventually. It is not recommended that you try caching the first time you implement
 /// highlighting.
 ///
+/// A [`HighlightIterator`] updates the state in place rather than cloning it, so copies are only
+/// made when you cache them. When caching the state for many lines, prefer `clone_from` over
+/// `clone` where possible, since it reuses the allocations of the state being overwritten.
+///
+/// [`HighlightIterator`]: struct.HighlightIterator.html
 /// [`ParseState`]: ../parsing/struct.ParseState.html
 /// [`new`]: #method.new
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct HighlightState {
     styles: Vec<Style>,
     single_caches: Vec<ScoredStyle>,
     pub path: ScopeStack,
 }
 
+impl Clone for HighlightState {
+    fn clone(&self) -> HighlightState {
+        HighlightState {
+            styles: self.styles.clone(),
+            single_caches: self.single_caches.clone(),
+            path: self.path.clone(),
+        }
+    }
+
+    fn clone_from(&mut self, source: &HighlightState) {
+        self.styles.clone_from(&source.styles);
+        self.single_caches.clone_from(&source.single_caches);
+        self.path.clone_from(&source.path);
+    }
+}
+
 /// Highlights a line of parsed code given a [`HighlightState`] and line of changes from the parser.
 ///
 /// Yields the [`Style`], the text and well as the `Range` of the text in the source string.
@@ -78,6 +146,8 @@ pub struct RangedHighlightIterator<'a, 'b> {
     text: &'b str,
     highlighter: &'a Highlighter<'a>,
     state: &'a mut HighlightState,
+    /// The time spent computing the styles of pushed scopes, if it's measured
+    pub(crate) style_time: Option<Duration>,
 }
 
 /// Highlights a line of parsed code given a [`HighlightState`] and line of changes from the parser.
@@ -120,6 +190,19 @@ impl HighlightState {
             path: initial_stack,
         }
     }
+
+    /// Applies the operations returned by [`ParseState::finalize`] at the end of a file, which
+    /// leaves the state with an empty `path` and the default style of the theme
+    ///
+    /// [`ParseState::finalize`]: ../parsing/struct.ParseState.html#method.finalize
+    pub fn finalize(
+        &mut self,
+        closing_ops: &[(usize, ScopeStackOp)],
+        highlighter: &Highlighter<'_>,
+    ) {
+        // there's no text, so this only applies the operations
+        RangedHighlightIterator::new(self, closing_ops, "", highlighter).for_each(drop);
+    }
 }
 
 impl<'a, 'b> RangedHighlightIterator<'a, 'b> {
@@ -136,6 +219,7 @@ impl<'a, 'b> RangedHighlightIterator<'a, 'b> {
             text,
             highlighter,
             state,
+            style_time: None,
         }
     }
 }
@@ -166,12 +250,14 @@ impl<'a, 'b> Iterator for RangedHighlightIterator<'a, 'b> {
             let m_path = &mut self.state.path;
             let m_styles = &mut self.state.styles;
             let m_caches = &mut self.state.single_caches;
+            let m_style_time = &mut self.style_time;
             let highlighter = &self.highlighter;
             m_path
                 .apply_with_hook(&command, |op, cur_stack| {
                     // println!("{:?} - {:?}", op, cur_stack);
                     match op {
                         BasicScopeStackOp::Push(_) => {
+                            let style_start = m_style_time.map(|_| Instant::now());
                             // we can push multiple times so this might have changed
                             let new_cache = {
                                 if let Some(prev_cache) = m_caches.last() {
@@ -187,6 +273,11 @@ impl<'a, 'b> Iterator for RangedHighlightIterator<'a, 'b> {
                                 highlighter.finalize_style_with_multis(&new_cache, cur_stack),
                             );
                             m_caches.push(new_cache);
+                            if let (Some(time), Some(style_start)) =
+                                (&mut *m_style_time, style_start)
+                            {
+                                *time += style_start.elapsed();
+                            }
                         }
                         BasicScopeStackOp::Pop => {
                             m_styles.pop();
@@ -220,9 +311,39 @@ impl<'a, 'b> HighlightIterator<'a, 'b> {
                 text,
                 highlighter,
                 state,
+                style_time: None,
             },
         }
     }
+
+    /// Yields [`CompactStyle`]s instead of [`Style`]s, for storing a style per cell of a grid
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, ThemeSet};
+    /// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let ts = ThemeSet::load_defaults();
+    /// let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+    /// let mut state = HighlightState::new(&highlighter, ScopeStack::new());
+    /// let mut parse_state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// let line = "let x = 1;\n";
+    /// let ops = parse_state.parse_line(line, &ss).unwrap();
+    ///
+    /// let mut cells = Vec::new();
+    /// for (style, text) in HighlightIterator::new(&mut state, &ops, line, &highlighter).compact() {
+    ///     cells.extend(text.chars().map(|c| (c, style)));
+    /// }
+    /// assert_eq!(cells.len(), line.len());
+    /// ```
+    ///
+    /// [`CompactStyle`]: struct.CompactStyle.html
+    /// [`Style`]: struct.Style.html
+    pub fn compact(self) -> CompactHighlightIterator<'a, 'b> {
+        CompactHighlightIterator { inner: self }
+    }
 }
 
 impl<'a, 'b> Iterator for HighlightIterator<'a, 'b> {
@@ -235,11 +356,34 @@ impl<'a, 'b> Iterator for HighlightIterator<'a, 'b> {
     }
 }
 
+/// A [`HighlightIterator`] yielding [`CompactStyle`]s, created by [`HighlightIterator::compact`]
+///
+/// [`HighlightIterator`]: struct.HighlightIterator.html
+/// [`CompactStyle`]: struct.CompactStyle.html
+/// [`HighlightIterator::compact`]: struct.HighlightIterator.html#method.compact
+#[derive(Debug)]
+pub struct CompactHighlightIterator<'a, 'b> {
+    inner: HighlightIterator<'a, 'b>,
+}
+
+impl<'a, 'b> Iterator for CompactHighlightIterator<'a, 'b> {
+    type Item = (CompactStyle, &'b str);
+
+    fn next(&mut self) -> Option<(CompactStyle, &'b str)> {
+        self.inner
+            .next()
+            .map(|(style, text)| (CompactStyle::from(style), text))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ScoredStyle {
     pub foreground: (MatchPower, Color),
     pub background: (MatchPower, Color),
     pub font_style: (MatchPower, FontStyle),
+    pub underline: (MatchPower, Option<UnderlineStyle>),
+    pub underline_color: (MatchPower, Option<Color>),
+    pub font_hint: (MatchPower, Option<FontHint>),
 }
 
 #[inline]
@@ -257,6 +401,13 @@ impl ScoredStyle {
         update_scored(&mut self.foreground, &other.foreground, score);
         update_scored(&mut self.background, &other.background, score);
         update_scored(&mut self.font_style, &other.font_style, score);
+        update_scored(&mut self.underline, &other.underline.map(Some), score);
+        update_scored(
+            &mut self.underline_color,
+            &other.underline_color.map(Some),
+            score,
+        );
+        update_scored(&mut self.font_hint, &other.font_hint.map(Some), score);
     }
 
     fn to_style(&self) -> Style {
@@ -264,6 +415,9 @@ impl ScoredStyle {
             foreground: self.foreground.1,
             background: self.background.1,
             font_style: self.font_style.1,
+            underline: self.underline.1,
+            underline_color: self.underline_color.1,
+            font_hint: self.font_hint.1,
         }
     }
 
@@ -272,12 +426,117 @@ impl ScoredStyle {
             foreground: (MatchPower(-1.0), style.foreground),
             background: (MatchPower(-1.0), style.background),
             font_style: (MatchPower(-1.0), style.font_style),
+            underline: (MatchPower(-1.0), style.underline),
+            underline_color: (MatchPower(-1.0), style.underline_color),
+            font_hint: (MatchPower(-1.0), style.font_hint),
         }
     }
 }
 
+impl Highlighter<'static> {
+    /// Creates a highlighter that keeps the theme alive itself, instead of borrowing it like
+    /// [`new`]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::Arc;
+    /// use syntect::highlighting::{Highlighter, Theme, ThemeSet};
+    ///
+    /// struct Editor {
+    ///     theme: Arc<Theme>,
+    ///     highlighter: Highlighter<'static>,
+    /// }
+    ///
+    /// let mut ts = ThemeSet::load_defaults();
+    /// let theme = Arc::new(ts.themes.remove("base16-ocean.dark").unwrap());
+    /// let editor = Editor { theme: theme.clone(), highlighter: Highlighter::from_shared(theme) };
+    /// assert_eq!(editor.highlighter.get_default().background, editor.theme.settings.background.unwrap());
+    /// ```
+    ///
+    /// [`new`]: #method.new
+    pub fn from_shared(theme: Arc<Theme>) -> Highlighter<'static> {
+        Highlighter::with_theme(ThemeRef::Shared(theme))
+    }
+}
+
+/// A theme together with the tables of its [`Highlighter`], prepared once and then shared
+///
+/// Creating a highlighter indexes the selectors of the theme, which is worth avoiding for every
+/// file when many are highlighted in parallel. Wrapped in an `Arc`, a prepared theme can be
+/// cloned cheaply into each task of a worker pool, and since it owns the theme the tasks don't
+/// borrow anything.
+///
+/// # Examples
+///
+/// ```
+/// use std::sync::Arc;
+/// use std::thread;
+/// use syntect::highlighting::{HighlightIterator, HighlightState, PreparedTheme, ThemeSet};
+/// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+///
+/// let ss = Arc::new(SyntaxSet::load_defaults_newlines());
+/// let mut ts = ThemeSet::load_defaults();
+/// let prepared = Arc::new(PreparedTheme::new(ts.themes.remove("base16-ocean.dark").unwrap()));
+///
+/// let workers: Vec<_> = ["fn main() {}\n", "let x = 1;\n"]
+///     .into_iter()
+///     .map(|line| {
+///         let (ss, prepared) = (ss.clone(), prepared.clone());
+///         thread::spawn(move || {
+///             let highlighter = prepared.highlighter();
+///             let mut parse_state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+///             let mut state = HighlightState::new(highlighter, ScopeStack::new());
+///             let ops = parse_state.parse_line(line, &ss).unwrap();
+///             HighlightIterator::new(&mut state, &ops, line, highlighter).count()
+///         })
+///     })
+///     .collect();
+/// for worker in workers {
+///     assert!(worker.join().unwrap() > 1);
+/// }
+/// ```
+///
+/// [`Highlighter`]: struct.Highlighter.html
+#[derive(Debug, Clone)]
+pub struct PreparedTheme {
+    highlighter: Highlighter<'static>,
+}
+
+impl PreparedTheme {
+    pub fn new(theme: Theme) -> PreparedTheme {
+        PreparedTheme::from_shared(Arc::new(theme))
+    }
+
+    /// Prepares a theme which is also shared with other parts of the program
+    pub fn from_shared(theme: Arc<Theme>) -> PreparedTheme {
+        PreparedTheme {
+            highlighter: Highlighter::from_shared(theme),
+        }
+    }
+
+    pub fn theme(&self) -> &Theme {
+        &self.highlighter.theme
+    }
+
+    /// The highlighter, whose selector tables were created along with the prepared theme
+    pub fn highlighter(&self) -> &Highlighter<'static> {
+        &self.highlighter
+    }
+}
+
 impl<'a> Highlighter<'a> {
     pub fn new(theme: &'a Theme) -> Highlighter<'a> {
+        Highlighter::with_theme(ThemeRef::Borrowed(theme))
+    }
+
+    /// The theme the styles come from, e.g. to look up their font hints
+    #[cfg(feature = "html")]
+    pub(crate) fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    fn with_theme(theme: ThemeRef<'a>) -> Highlighter<'a> {
         let mut single_selectors = Vec::new();
         let mut multi_selectors = Vec::new();
         for item in &theme.scopes {
@@ -292,10 +551,21 @@ impl<'a> Highlighter<'a> {
         // So that deeper matching selectors get checked first
         single_selectors.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
 
+        let mut multi_index: HashMap<Scope, Vec<usize>> = HashMap::new();
+        let mut unindexed_multis = Vec::new();
+        for (i, (sel, _)) in multi_selectors.iter().enumerate() {
+            match sel.required_scope() {
+                Some(scope) => multi_index.entry(scope).or_default().push(i),
+                None => unindexed_multis.push(i),
+            }
+        }
+
         Highlighter {
             theme,
             single_selectors,
             multi_selectors,
+            multi_index,
+            unindexed_multis,
         }
     }
 
@@ -306,6 +576,9 @@ impl<'a> Highlighter<'a> {
             foreground: self.theme.settings.foreground.unwrap_or(Color::BLACK),
             background: self.theme.settings.background.unwrap_or(Color::WHITE),
             font_style: FontStyle::empty(),
+            underline: None,
+            underline_color: None,
+            font_hint: None,
         }
     }
 
@@ -327,15 +600,33 @@ impl<'a> Highlighter<'a> {
     }
 
     fn finalize_style_with_multis(&self, cur: &ScoredStyle, path: &[Scope]) -> Style {
+        if self.multi_selectors.is_empty() {
+            return cur.to_style();
+        }
         let mut new_style = cur.clone();
 
-        let mult_iter = self
-            .multi_selectors
-            .iter()
-            .filter_map(|(sel, style)| sel.does_match(path).map(|score| (score, style)));
-        for (score, modif) in mult_iter {
-            new_style.apply(modif, score);
-        }
+        MULTI_CANDIDATES.with(|candidates| {
+            // Only test the selectors which could match, in the order of the theme so ties are
+            // resolved the same way as when testing all of them
+            let mut candidates = candidates.borrow_mut();
+            candidates.clone_from(&self.unindexed_multis);
+            for scope in path {
+                for len in 1..=scope.len() {
+                    if let Some(indices) = self.multi_index.get(&scope.prefix(len)) {
+                        candidates.extend_from_slice(indices);
+                    }
+                }
+            }
+            candidates.sort_unstable();
+            candidates.dedup();
+
+            for &i in candidates.iter() {
+                let (sel, modif) = &self.multi_selectors[i];
+                if let Some(score) = sel.does_match(path) {
+                    new_style.apply(modif, score);
+                }
+            }
+        });
 
         new_style.to_style()
     }
@@ -373,11 +664,7 @@ impl<'a> Highlighter<'a> {
         matching_items.sort_by_key(|&(score, _)| score);
         let sorted = matching_items.iter().map(|(_, item)| item);
 
-        let mut modifier = StyleModifier {
-            background: None,
-            foreground: None,
-            font_style: None,
-        };
+        let mut modifier = StyleModifier::default();
         for item in sorted {
             modifier = modifier.apply(item.style);
         }
@@ -390,7 +677,7 @@ impl<'a> Highlighter<'a> {
 mod tests {
     use super::*;
     use crate::highlighting::{Color, FontStyle, Style, ThemeSet};
-    use crate::parsing::{ParseState, ScopeStack, SyntaxSet};
+    use crate::parsing::{ParseState, ParsingError, ScopeStack, SyntaxSet};
 
     #[test]
     fn can_parse() {
@@ -425,6 +712,9 @@ mod tests {
                         a: 0xFF,
                     },
                     font_style: FontStyle::empty(),
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
                 "5"
             )
@@ -477,12 +767,45 @@ mod tests {
                         a: 0xFF,
                     },
                     font_style: FontStyle::empty(),
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
                 "multiline comment"
             )
         );
     }
 
+    #[test]
+    fn can_finalize_unterminated_file() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let mut state = ParseState::new(ss.find_syntax_by_extension("py").unwrap());
+        let ts = ThemeSet::load_defaults();
+        let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+
+        let mut highlight_state = HighlightState::new(&highlighter, ScopeStack::new());
+        for line in ["def f(x):\n", "    return [\"\"\"unterminated\n"] {
+            let ops = state.parse_line(line, &ss).expect("#[cfg(test)]");
+            HighlightIterator::new(&mut highlight_state, &ops[..], line, &highlighter)
+                .for_each(drop);
+        }
+        assert!(highlight_state.path.len() > 2);
+
+        let ops = state.finalize(&ss).expect("#[cfg(test)]");
+        highlight_state.finalize(&ops, &highlighter);
+        assert!(highlight_state.path.is_empty());
+        assert_eq!(
+            highlight_state,
+            HighlightState::new(&highlighter, ScopeStack::new())
+        );
+
+        assert!(state.finalize(&ss).expect("#[cfg(test)]").is_empty());
+        assert!(matches!(
+            state.parse_line("x\n", &ss),
+            Err(ParsingError::MissingMainContext)
+        ));
+    }
+
     // see issues #133 and #203, this test tests the fixes for those issues
     #[test]
     fn tricky_cases() {
@@ -518,7 +841,11 @@ mod tests {
                         foreground: Some(c1),
                         background: None,
                         font_style: None,
+                        underline: None,
+                        underline_color: None,
+                        font_hint: None,
                     },
+                    extras: Default::default(),
                 },
                 ThemeItem {
                     scope: ScopeSelectors::from_str("comment").unwrap(),
@@ -526,7 +853,11 @@ mod tests {
                         foreground: Some(c2),
                         background: None,
                         font_style: Some(FontStyle::ITALIC),
+                        underline: None,
+                        underline_color: None,
+                        font_hint: None,
                     },
+                    extras: Default::default(),
                 },
                 ThemeItem {
                     scope: ScopeSelectors::from_str("comment.line.rs - keyword").unwrap(),
@@ -534,7 +865,11 @@ mod tests {
                         foreground: None,
                         background: Some(c1),
                         font_style: None,
+                        underline: None,
+                        underline_color: None,
+                        font_hint: None,
                     },
+                    extras: Default::default(),
                 },
                 ThemeItem {
                     scope: ScopeSelectors::from_str("no.match").unwrap(),
@@ -542,9 +877,14 @@ mod tests {
                         foreground: None,
                         background: Some(c2),
                         font_style: Some(FontStyle::UNDERLINE),
+                        underline: None,
+                        underline_color: None,
+                        font_hint: None,
                     },
+                    extras: Default::default(),
                 },
             ],
+            font_hints: Vec::new(),
         };
         let highlighter = Highlighter::new(&test_color_scheme);
 
@@ -568,17 +908,26 @@ mod tests {
                 Style {
                     foreground: c1,
                     background: c1,
-                    font_style: FontStyle::ITALIC
+                    font_style: FontStyle::ITALIC,
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
                 Style {
                     foreground: c1,
                     background: def_bg,
-                    font_style: FontStyle::ITALIC
+                    font_style: FontStyle::ITALIC,
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
                 Style {
                     foreground: c1,
                     background: c1,
-                    font_style: FontStyle::ITALIC
+                    font_style: FontStyle::ITALIC,
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
             ]
         );
@@ -590,7 +939,10 @@ mod tests {
             Style {
                 foreground: c1,
                 background: def_bg,
-                font_style: FontStyle::ITALIC
+                font_style: FontStyle::ITALIC,
+                underline: None,
+                underline_color: None,
+                font_hint: None,
             }
         );
         let full_mod = highlighter.style_mod_for_stack(full_stack.as_slice());
@@ -599,11 +951,101 @@ mod tests {
             StyleModifier {
                 foreground: Some(c1),
                 background: None,
-                font_style: Some(FontStyle::ITALIC)
+                font_style: Some(FontStyle::ITALIC),
+                underline: None,
+                underline_color: None,
+                font_hint: None,
             }
         );
     }
 
+    #[test]
+    fn highlighters_can_be_shared_between_threads() {
+        fn check_send_sync<T: Send + Sync>() {}
+        check_send_sync::<Highlighter<'_>>();
+        check_send_sync::<HighlightState>();
+        check_send_sync::<PreparedTheme>();
+        check_send_sync::<crate::easy::HighlightLines<'static>>();
+
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let prepared = Arc::new(PreparedTheme::new(theme.clone()));
+        assert_eq!(prepared.theme(), theme);
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let highlight = |highlighter: &Highlighter<'_>, line: &str| -> Vec<Style> {
+            let mut parse_state = ParseState::new(syntax);
+            let mut state = HighlightState::new(highlighter, ScopeStack::new());
+            let ops = parse_state.parse_line(line, &ss).unwrap();
+            HighlightIterator::new(&mut state, &ops, line, highlighter)
+                .map(|(style, _)| style)
+                .collect()
+        };
+
+        let lines = ["fn main() {}\n", "let s = \"x\";\n", "// done\n"];
+        let styles: Vec<Vec<Style>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = lines
+                .iter()
+                .map(|line| {
+                    let prepared = prepared.clone();
+                    let highlight = &highlight;
+                    scope.spawn(move || highlight(prepared.highlighter(), line))
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        let highlighter = Highlighter::new(theme);
+        for (line, styles) in lines.iter().zip(styles) {
+            assert_eq!(styles, highlight(&highlighter, line));
+        }
+    }
+
+    #[test]
+    fn multi_selectors_are_found_through_index() {
+        use crate::highlighting::ThemeBuilder;
+        use std::str::FromStr;
+
+        let red = Color {
+            r: 0xff,
+            g: 0,
+            b: 0,
+            a: 0xff,
+        };
+        let green = Color {
+            r: 0,
+            g: 0xff,
+            b: 0,
+            a: 0xff,
+        };
+        let theme = ThemeBuilder::new()
+            .scope("source string.quoted", |s| s.foreground(red))
+            .scope("text string - comment", |s| s.foreground(green))
+            .scope("-comment & source", |s| s.background(red))
+            .scope("source > keyword", |s| s.italic())
+            .scope("(text | source) & markup", |s| s.bold())
+            .scope("source other.unused", |s| s.foreground(green))
+            .build()
+            .unwrap();
+        let highlighter = Highlighter::new(&theme);
+        let style = |stack: &str| {
+            let stack = ScopeStack::from_str(stack).unwrap();
+            highlighter.style_for_stack(stack.as_slice())
+        };
+
+        let s = style("source.rust string.quoted.double");
+        assert_eq!((s.foreground, s.background), (red, red));
+        let s = style("text.html string.unquoted");
+        assert_eq!((s.foreground, s.background), (green, Color::WHITE));
+        let s = style("text.html comment string");
+        assert_eq!(s.foreground, Color::BLACK);
+        let s = style("source.rust keyword.control");
+        assert_eq!((s.font_style, s.background), (FontStyle::ITALIC, red));
+        let s = style("source.rust meta.block keyword.control");
+        assert_eq!(s.font_style, FontStyle::empty());
+        let s = style("text.html markup.bold");
+        assert_eq!(s.font_style, FontStyle::BOLD);
+    }
+
     #[test]
     fn test_ranges() {
         let ps = SyntaxSet::load_from_folder("testdata/Packages").unwrap();
@@ -637,6 +1079,9 @@ mod tests {
                         a: 0xFF,
                     },
                     font_style: FontStyle::empty(),
+                    underline: None,
+                    underline_color: None,
+                    font_hint: None,
                 },
                 "5",
                 Range { start: 30, end: 31 }
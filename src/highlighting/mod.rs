@@ -6,12 +6,15 @@
 //!
 //! [`Theme`]: struct.Theme.html
 //! [`ThemeSet`]: struct.ThemeSet.html
+#[cfg(feature = "plist-load")]
+mod css_colors;
 mod highlighter;
 mod selector;
 #[cfg(feature = "plist-load")]
 pub(crate) mod settings;
 mod style;
 mod theme;
+mod theme_builder;
 #[cfg(feature = "plist-load")]
 mod theme_load;
 mod theme_set;
@@ -22,6 +25,7 @@ pub use self::selector::*;
 pub use self::settings::SettingsError;
 pub use self::style::*;
 pub use self::theme::*;
+pub use self::theme_builder::*;
 #[cfg(feature = "plist-load")]
 pub use self::theme_load::*;
 pub use self::theme_set::*;
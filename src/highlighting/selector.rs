@@ -1,19 +1,85 @@
 /// Code based on <https://github.com/defuz/sublimate/blob/master/src/core/syntax/scope.rs>
 /// released under the MIT license by @defuz
-use crate::parsing::{MatchPower, ParseScopeError, Scope, ScopeStack};
+use crate::parsing::{MatchPower, ParseScopeError, Scope, ScopeStack, ATOM_LEN_BITS};
 use serde_derive::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
 
 /// A single selector consisting of a stack to match and a possible stack to
 /// exclude from being matched.
 ///
+/// Selectors using the rest of the TextMate selector grammar, i.e. `&`, parenthesized groups,
+/// `>` or the `L:`/`R:`/`B:` prefixes, are stored in `expression` instead, and leave `path` and
+/// `excludes` empty.
+///
 /// You probably want [`ScopeSelectors`] which is this but with union support.
 ///
 /// [`ScopeSelectors`]: struct.ScopeSelectors.html
-#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct ScopeSelector {
     pub path: ScopeStack,
     pub excludes: Vec<ScopeStack>,
+    /// The whole selector, if it can't be expressed as a path with exclusions
+    pub expression: Option<SelectorExpression>,
+}
+
+/// A selector using the full TextMate selector grammar
+///
+/// [`ScopeSelectors`] first splits a selector into whole selectors at the `,` and `|` outside
+/// of parentheses, so `a | b - c` means `a | (b - c)`. Within a selector and inside parentheses
+/// all operators have the same precedence and are left-associative, so `a & b - c` means
+/// `(a & b) - c` and `(a | b - c)` means `((a | b) - c)`.
+///
+/// [`ScopeSelectors`]: struct.ScopeSelectors.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectorExpression {
+    /// Scopes which have to appear in the stack in this order
+    ///
+    /// `direct_child[i]` is `true` if `scopes[i]` has to come directly after `scopes[i - 1]`,
+    /// which is written `scopes[i - 1] > scopes[i]`.
+    Path {
+        scopes: Vec<Scope>,
+        direct_child: Vec<bool>,
+    },
+    /// `a & b`: both have to match
+    And(Box<SelectorExpression>, Box<SelectorExpression>),
+    /// `a | b`: either has to match
+    Or(Box<SelectorExpression>, Box<SelectorExpression>),
+    /// `a - b`: the first has to match, but not the second
+    Minus(Box<SelectorExpression>, Box<SelectorExpression>),
+    /// `-a`: matches when `a` doesn't
+    Not(Box<SelectorExpression>),
+    /// `L:a`, `R:a` or `B:a`
+    ///
+    /// In an editor these select which side of the caret the scopes have to be on. There is no
+    /// caret when highlighting, so these match just like the expression without the prefix.
+    Side(SelectorSide, Box<SelectorExpression>),
+}
+
+/// The side of the caret a [`SelectorExpression::Side`] applies to
+///
+/// [`SelectorExpression::Side`]: enum.SelectorExpression.html#variant.Side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectorSide {
+    /// `L:`
+    Left,
+    /// `R:`
+    Right,
+    /// `B:`
+    Both,
+}
+
+// Only shows `expression` when it's set, so simple selectors look the same as they always did
+impl fmt::Debug for ScopeSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("ScopeSelector");
+        s.field("path", &self.path)
+            .field("excludes", &self.excludes);
+        if let Some(expression) = &self.expression {
+            s.field("expression", expression);
+        }
+        s.finish()
+    }
 }
 
 /// A selector set that matches anything matched by any of its component selectors.
@@ -32,6 +98,9 @@ impl ScopeSelector {
     ///
     /// [`ScopeSelectors::does_match`]: struct.ScopeSelectors.html#method.does_match
     pub fn does_match(&self, stack: &[Scope]) -> Option<MatchPower> {
+        if let Some(expression) = &self.expression {
+            return expression.does_match(stack);
+        }
         // if there are any exclusions, and any one of them matches, then this selector doesn't match
         if self
             .excludes
@@ -50,15 +119,240 @@ impl ScopeSelector {
 
     /// If this selector is really just a single scope, return it
     pub fn extract_single_scope(&self) -> Option<Scope> {
-        if self.path.len() > 1 || !self.excludes.is_empty() || self.path.is_empty() {
+        if self.expression.is_some()
+            || self.path.len() > 1
+            || !self.excludes.is_empty()
+            || self.path.is_empty()
+        {
             return None;
         }
         Some(self.path.as_slice()[0])
     }
 
+    /// A scope which has to be a prefix of one of the scopes in a stack for this selector to
+    /// match it, used to skip selectors which can't match
+    pub(crate) fn required_scope(&self) -> Option<Scope> {
+        let scope = match &self.expression {
+            Some(expression) => expression.required_scope(),
+            None => self.path.as_slice().last().copied(),
+        };
+        scope.filter(|s| !s.is_empty())
+    }
+
     /// Extract all selectors for generating CSS
+    ///
+    /// For selectors with an `expression` this is the first path in it, which is only an
+    /// approximation of what the selector matches.
     pub fn extract_scopes(&self) -> Vec<Scope> {
-        self.path.scopes.clone()
+        match &self.expression {
+            Some(expression) => expression.first_path().to_vec(),
+            None => self.path.scopes.clone(),
+        }
+    }
+}
+
+impl SelectorExpression {
+    /// Checks if this expression matches a given scope stack.
+    ///
+    /// See [`ScopeSelectors::does_match`] for more info.
+    ///
+    /// [`ScopeSelectors::does_match`]: struct.ScopeSelectors.html#method.does_match
+    pub fn does_match(&self, stack: &[Scope]) -> Option<MatchPower> {
+        use self::SelectorExpression::*;
+        match self {
+            Path {
+                scopes,
+                direct_child,
+            } => match_path(scopes, direct_child, stack, 0).map(MatchPower),
+            And(a, b) => Some(a.does_match(stack)?.max(b.does_match(stack)?)),
+            Or(a, b) => a.does_match(stack).max(b.does_match(stack)),
+            Minus(a, b) => match b.does_match(stack) {
+                Some(_) => None,
+                None => a.does_match(stack),
+            },
+            Not(a) => match a.does_match(stack) {
+                Some(_) => None,
+                None => Some(MatchPower(0o1u64 as f64)),
+            },
+            Side(_, a) => a.does_match(stack),
+        }
+    }
+
+    fn required_scope(&self) -> Option<Scope> {
+        use self::SelectorExpression::*;
+        match self {
+            Path { scopes, .. } => scopes.last().copied(),
+            And(a, b) => a.required_scope().or_else(|| b.required_scope()),
+            Minus(a, _) | Side(_, a) => a.required_scope(),
+            Or(..) | Not(_) => None,
+        }
+    }
+
+    fn first_path(&self) -> &[Scope] {
+        use self::SelectorExpression::*;
+        match self {
+            Path { scopes, .. } => scopes,
+            And(a, _) | Or(a, _) | Minus(a, _) | Side(_, a) => a.first_path(),
+            Not(_) => &[],
+        }
+    }
+}
+
+/// Scores like `ScopeStack::does_match`, but backtracks since a `>` can make the first
+/// occurrence of a scope the wrong one to pick
+fn match_path(
+    scopes: &[Scope],
+    direct_child: &[bool],
+    stack: &[Scope],
+    start: usize,
+) -> Option<f64> {
+    let (sel_scope, rest) = match scopes.split_first() {
+        Some(split) => split,
+        None => return Some(0.0),
+    };
+    let end = if direct_child[0] {
+        (start + 1).min(stack.len())
+    } else {
+        stack.len()
+    };
+    for (i, scope) in stack.iter().enumerate().take(end).skip(start) {
+        if sel_scope.is_prefix_of(*scope) {
+            if let Some(score) = match_path(rest, &direct_child[1..], stack, i + 1) {
+                let len = sel_scope.len();
+                return Some(score + f64::from(len) * f64::from(ATOM_LEN_BITS * (i as u16)).exp2());
+            }
+        }
+    }
+    None
+}
+
+/// Whether a selector needs the full grammar rather than being a path with exclusions
+fn needs_expression(s: &str) -> bool {
+    s.contains(['&', '(', ')', '>'])
+        || s.match_indices(':').any(|(i, _)| {
+            let before = &s[..i];
+            before.ends_with(['L', 'R', 'B'])
+                && (i == 1 || before[..i - 1].ends_with(|c: char| c.is_whitespace() || c == '-'))
+        })
+}
+
+/// A recursive descent parser for the TextMate selector grammar
+struct ExpressionParser<'a> {
+    selector: &'a str,
+    rest: &'a str,
+}
+
+impl<'a> ExpressionParser<'a> {
+    fn parse(selector: &'a str) -> Result<SelectorExpression, ParseScopeError> {
+        let mut parser = ExpressionParser {
+            selector,
+            rest: selector,
+        };
+        let expression = parser.parse_composite()?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(_) => Err(parser.error()),
+        }
+    }
+
+    fn error(&self) -> ParseScopeError {
+        ParseScopeError::InvalidSelector(self.selector.to_owned())
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.rest = self.rest.trim_start();
+        self.rest.chars().next()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.rest = &self.rest[c.len_utf8()..];
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_composite(&mut self) -> Result<SelectorExpression, ParseScopeError> {
+        use self::SelectorExpression::*;
+        let mut expression = self.parse_expression()?;
+        loop {
+            let op = match self.peek() {
+                Some(op @ ('&' | '|' | ',' | '-')) => op,
+                _ => return Ok(expression),
+            };
+            self.rest = &self.rest[1..];
+            let lhs = Box::new(expression);
+            let rhs = Box::new(self.parse_expression()?);
+            expression = match op {
+                '&' => And(lhs, rhs),
+                '-' => Minus(lhs, rhs),
+                _ => Or(lhs, rhs),
+            };
+        }
+    }
+
+    fn parse_expression(&mut self) -> Result<SelectorExpression, ParseScopeError> {
+        if self.eat('-') {
+            return Ok(SelectorExpression::Not(Box::new(self.parse_expression()?)));
+        }
+        self.peek();
+        let side = match self.rest.get(..2) {
+            Some("L:") => SelectorSide::Left,
+            Some("R:") => SelectorSide::Right,
+            Some("B:") => SelectorSide::Both,
+            _ => return self.parse_operand(),
+        };
+        self.rest = &self.rest[2..];
+        Ok(SelectorExpression::Side(
+            side,
+            Box::new(self.parse_operand()?),
+        ))
+    }
+
+    fn parse_operand(&mut self) -> Result<SelectorExpression, ParseScopeError> {
+        if self.eat('(') {
+            let expression = self.parse_composite()?;
+            if !self.eat(')') {
+                return Err(self.error());
+            }
+            return Ok(expression);
+        }
+
+        let mut scopes = Vec::new();
+        let mut direct_child = Vec::new();
+        loop {
+            let direct = !scopes.is_empty() && self.eat('>');
+            match self.parse_name() {
+                Some(name) => {
+                    scopes.push(Scope::new(name)?);
+                    direct_child.push(direct);
+                }
+                None if direct || scopes.is_empty() => return Err(self.error()),
+                None => {
+                    return Ok(SelectorExpression::Path {
+                        scopes,
+                        direct_child,
+                    })
+                }
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Option<&'a str> {
+        if self.peek()? == '-' {
+            return None;
+        }
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || "&|,()>".contains(c))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return None;
+        }
+        let (name, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(name)
     }
 }
 
@@ -66,7 +360,15 @@ impl FromStr for ScopeSelector {
     type Err = ParseScopeError;
 
     /// Parses a scope stack followed optionally by (one or more) " -" and then a scope stack to exclude
+    ///
+    /// Selectors using any of the other TextMate operators are parsed into an `expression`.
     fn from_str(s: &str) -> Result<ScopeSelector, ParseScopeError> {
+        if needs_expression(s) {
+            return Ok(ScopeSelector {
+                expression: Some(ExpressionParser::parse(s)?),
+                ..ScopeSelector::default()
+            });
+        }
         let mut excludes = Vec::new();
         let mut path_str: &str = "";
         for (i, selector) in s.split(" -").enumerate() {
@@ -79,6 +381,7 @@ impl FromStr for ScopeSelector {
         Ok(ScopeSelector {
             path: ScopeStack::from_str(path_str)?,
             excludes,
+            expression: None,
         })
     }
 }
@@ -111,15 +414,97 @@ impl FromStr for ScopeSelectors {
     type Err = ParseScopeError;
 
     /// Parses a series of selectors separated by commas or pipes
+    ///
+    /// Commas and pipes inside parentheses are part of a single selector. Splitting comes before
+    /// any other operator, so `a | b - c` is the selectors `a` and `b - c`.
     fn from_str(s: &str) -> Result<ScopeSelectors, ParseScopeError> {
         let mut selectors = Vec::new();
-        for selector in s.split(&[',', '|'][..]) {
-            selectors.push(ScopeSelector::from_str(selector)?)
+        let mut depth = 0usize;
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                ',' | '|' if depth == 0 => {
+                    selectors.push(ScopeSelector::from_str(&s[start..i])?);
+                    start = i + 1;
+                }
+                _ => (),
+            }
         }
+        selectors.push(ScopeSelector::from_str(&s[start..])?);
         Ok(ScopeSelectors { selectors })
     }
 }
 
+/// A question about a scope stack, like "is this inside a comment or a string", asked with a
+/// scope selector
+///
+/// This uses the same selector engine as themes, so applications can check where something is,
+/// e.g. to only spell-check comments or to insert matching quotes outside of strings, without
+/// having to set up a theme to do it.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::ScopeQuery;
+/// use syntect::parsing::ScopeStack;
+/// use std::str::FromStr;
+///
+/// let query = ScopeQuery::new("string - string.source, comment").unwrap();
+/// let in_comment = ScopeStack::from_str("source.rust comment.line.double-slash.rust").unwrap();
+/// assert!(query.is_match(in_comment.as_slice()));
+/// let in_code = ScopeStack::from_str("source.rust meta.function.rust").unwrap();
+/// assert!(query.matches(in_code.as_slice()).is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeQuery {
+    selectors: ScopeSelectors,
+}
+
+impl ScopeQuery {
+    /// Parses a query with the syntax of theme selectors, see [`ScopeSelectors`]
+    ///
+    /// [`ScopeSelectors`]: struct.ScopeSelectors.html
+    pub fn new(query: &str) -> Result<ScopeQuery, ParseScopeError> {
+        Ok(ScopeQuery {
+            selectors: ScopeSelectors::from_str(query)?,
+        })
+    }
+
+    /// Checks if the query matches a scope stack, and how specifically, see
+    /// [`ScopeSelectors::does_match`]
+    ///
+    /// [`ScopeSelectors::does_match`]: struct.ScopeSelectors.html#method.does_match
+    pub fn matches(&self, stack: &[Scope]) -> Option<MatchPower> {
+        self.selectors.does_match(stack)
+    }
+
+    /// Checks if the query matches a scope stack
+    pub fn is_match(&self, stack: &[Scope]) -> bool {
+        self.matches(stack).is_some()
+    }
+
+    /// The selectors of the query
+    pub fn selectors(&self) -> &ScopeSelectors {
+        &self.selectors
+    }
+}
+
+impl FromStr for ScopeQuery {
+    type Err = ParseScopeError;
+
+    fn from_str(s: &str) -> Result<ScopeQuery, ParseScopeError> {
+        ScopeQuery::new(s)
+    }
+}
+
+impl From<ScopeSelectors> for ScopeQuery {
+    fn from(selectors: ScopeSelectors) -> ScopeQuery {
+        ScopeQuery { selectors }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,6 +568,92 @@ mod tests {
             "ScopeSelector { path: ScopeStack { clear_stack: [], scopes: [<j.g>] }, excludes: [] }"
         );
     }
+    #[test]
+    fn full_grammar_works() {
+        use crate::parsing::{MatchPower, ScopeStack};
+        use std::str::FromStr;
+        let score = |sel: &str, stack: &str| {
+            ScopeSelectors::from_str(sel)
+                .unwrap()
+                .does_match(ScopeStack::from_str(stack).unwrap().as_slice())
+        };
+        assert_eq!(score("a & b", "a b"), Some(MatchPower(0o10u64 as f64)));
+        assert_eq!(score("a & b", "a c"), None);
+        assert_eq!(
+            score("(a | b) & c", "b c"),
+            Some(MatchPower(0o10u64 as f64))
+        );
+        assert_eq!(score("(a | b) & c", "d c"), None);
+        assert_eq!(
+            score("a > b", "a b"),
+            Some(MatchPower(0o10u64 as f64 + 1.0))
+        );
+        assert_eq!(score("a > b", "a c b"), None);
+        assert_eq!(
+            score("a b", "a c b"),
+            Some(MatchPower(0o100u64 as f64 + 1.0))
+        );
+        // the first `a` can't be used, since `b` doesn't directly follow it
+        assert_eq!(score("a > b", "a a b"), Some(MatchPower(0o110u64 as f64)));
+        assert_eq!(
+            score("L:comment", "source comment.line"),
+            Some(MatchPower(0o10u64 as f64))
+        );
+        assert_eq!(score("source - (comment | string)", "source string"), None);
+        assert_eq!(
+            score("source - (comment | string)", "source"),
+            Some(MatchPower(1.0))
+        );
+        assert_eq!(
+            score("-(comment) & source", "source"),
+            Some(MatchPower(1.0))
+        );
+        // operators are left-associative
+        assert_eq!(score("a | b & c", "b"), None);
+        assert_eq!(score("a | (b & c)", "a"), Some(MatchPower(1.0)));
+    }
+
+    #[test]
+    fn full_grammar_parsing_works() {
+        use crate::parsing::ParseScopeError;
+        use std::str::FromStr;
+        let sels = ScopeSelectors::from_str("(a, b) & c, d | (e | f)").unwrap();
+        assert_eq!(sels.selectors.len(), 3);
+        assert!(sels.selectors[0].expression.is_some());
+        assert!(sels.selectors[1].expression.is_none());
+        assert!(sels.selectors[2].expression.is_some());
+        assert_eq!(
+            sels.selectors[1].extract_single_scope(),
+            Some(Scope::new("d").unwrap())
+        );
+        assert_eq!(sels.selectors[2].extract_single_scope(), None);
+        assert_eq!(
+            sels.selectors[0].extract_scopes(),
+            vec![Scope::new("a").unwrap()]
+        );
+
+        // `-` inside scope names isn't an operator
+        let sel = ScopeSelector::from_str("(punctuation.separator.key-value)").unwrap();
+        assert_eq!(
+            sel.expression,
+            Some(SelectorExpression::Path {
+                scopes: vec![Scope::new("punctuation.separator.key-value").unwrap()],
+                direct_child: vec![false],
+            })
+        );
+
+        for bad in ["(a", "a )", "a &", "a >", "& a", "()"] {
+            assert!(
+                matches!(
+                    ScopeSelector::from_str(bad),
+                    Err(ParseScopeError::InvalidSelector(_))
+                ),
+                "{:?} should be invalid",
+                bad
+            );
+        }
+    }
+
     #[test]
     fn matching_works() {
         use crate::parsing::{MatchPower, ScopeStack};
@@ -417,4 +888,49 @@ mod tests {
             Some(MatchPower(0o01u64 as f64))
         );
     }
+
+    #[test]
+    fn scope_query_matches() {
+        let query = ScopeQuery::new("string - string.source, comment").unwrap();
+        let stack = |s: &str| ScopeStack::from_str(s).unwrap();
+        assert!(query.is_match(stack("source.rust string.quoted.double.rust").as_slice()));
+        assert!(query.is_match(stack("source.rust comment.block.rust").as_slice()));
+        assert!(!query.is_match(stack("source.rust meta.block.rust").as_slice()));
+        assert!(!query.is_match(stack("text.html string.source.css").as_slice()));
+        assert_eq!(
+            query.matches(stack("source.rust comment.block.rust").as_slice()),
+            ScopeSelectors::from_str("comment")
+                .unwrap()
+                .does_match(stack("source.rust comment.block.rust").as_slice())
+        );
+        assert!(ScopeQuery::new("(comment").is_err());
+    }
+
+    #[test]
+    fn top_level_pipes_split_before_other_operators() {
+        let stack = |s: &str| ScopeStack::from_str(s).unwrap();
+        let matches = |selector: &str, scopes: &str| {
+            ScopeSelectors::from_str(selector)
+                .unwrap()
+                .does_match(stack(scopes).as_slice())
+                .is_some()
+        };
+        // `a | (b - c)`
+        assert_eq!(
+            ScopeSelectors::from_str("a | b - c")
+                .unwrap()
+                .selectors
+                .len(),
+            2
+        );
+        assert!(matches("a | b - c", "a c"));
+        assert!(!matches("a | b - c", "b c"));
+        // `((a | b) - c)`
+        assert!(!matches("(a | b - c)", "a c"));
+        assert!(!matches("(a | b - c)", "b c"));
+        assert!(matches("(a | b - c)", "b"));
+        // `(a & b) - c`
+        assert!(matches("a & b - c", "a b"));
+        assert!(!matches("a & b - c", "a b c"));
+    }
 }
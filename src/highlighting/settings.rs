@@ -17,6 +17,9 @@ pub enum SettingsError {
     /// Incorrect Plist syntax
     #[error("Incorrect Plist syntax: {0}")]
     Plist(PlistError),
+    /// Incorrect JSON syntax
+    #[error("Incorrect JSON syntax: {0}")]
+    Json(serde_json::Error),
 }
 
 impl From<PlistError> for SettingsError {
@@ -29,3 +32,95 @@ pub fn read_plist<R: Read + Seek>(reader: R) -> Result<Settings, SettingsError>
     let settings = plist::from_reader(reader)?;
     Ok(settings)
 }
+
+/// Reads the relaxed JSON of Sublime Text's settings files, which can have comments and trailing
+/// commas
+pub fn read_json<R: Read>(mut reader: R) -> Result<Settings, SettingsError> {
+    let mut text = String::new();
+    reader
+        .read_to_string(&mut text)
+        .map_err(|e| SettingsError::Json(serde_json::Error::io(e)))?;
+    serde_json::from_str(&strip_json_extensions(&text)).map_err(SettingsError::Json)
+}
+
+/// Replaces comments and trailing commas with spaces, so positions in errors stay the same
+fn strip_json_extensions(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    // where the last comma outside a string was written, if only whitespace came after it
+    let mut pending_comma = None;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                pending_comma = None;
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                out.push(' ');
+                while let Some(&c) = chars.peek() {
+                    if c == '\n' {
+                        break;
+                    }
+                    out.push(if c == '\t' { c } else { ' ' });
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                out.push_str("  ");
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    out.push(if c == '\n' { c } else { ' ' });
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+            }
+            ',' => {
+                pending_comma = Some(out.len());
+                out.push(c);
+            }
+            ']' | '}' => {
+                if let Some(i) = pending_comma.take() {
+                    out.replace_range(i..=i, " ");
+                }
+                out.push(c);
+            }
+            c if c.is_whitespace() => out.push(c),
+            c => {
+                pending_comma = None;
+                out.push(c);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_read_relaxed_json() {
+        let json = r#"{
+            // a comment
+            "a": "// not a comment", /*/ a block
+            comment */ "b": [1, 2,],
+            "c": "\\\"",
+        }"#;
+        let settings = read_json(json.as_bytes()).unwrap();
+        assert_eq!(settings["a"], "// not a comment");
+        assert_eq!(settings["b"], serde_json::json!([1, 2]));
+        assert_eq!(settings["c"], "\\\"");
+        assert!(read_json("{".as_bytes()).is_err());
+    }
+}
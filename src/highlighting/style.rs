@@ -1,6 +1,9 @@
 // Code based on [https://github.com/defuz/sublimate/blob/master/src/core/syntax/scope.rs](https://github.com/defuz/sublimate/blob/master/src/core/syntax/scope.rs)
 // released under the MIT license by @defuz
+use crate::util::blend_fg_color;
 use bitflags::bitflags;
+#[cfg(feature = "global-statics")]
+use once_cell::sync::Lazy;
 use serde_derive::{Deserialize, Serialize};
 
 /// Foreground and background colors, with font style
@@ -12,6 +15,17 @@ pub struct Style {
     pub background: Color,
     /// Style of the font
     pub font_style: FontStyle,
+    /// How the underline is drawn
+    ///
+    /// Only meaningful when `font_style` contains [`FontStyle::UNDERLINE`]. `None` means the
+    /// theme didn't ask for anything other than a plain solid underline.
+    ///
+    /// [`FontStyle::UNDERLINE`]: struct.FontStyle.html#associatedconstant.UNDERLINE
+    pub underline: Option<UnderlineStyle>,
+    /// Color of the underline, if it should differ from the foreground color
+    pub underline_color: Option<Color>,
+    /// A different font for the text, if the theme asks for one
+    pub font_hint: Option<FontHint>,
 }
 
 /// A change to a [`Style`] applied incrementally by a theme rule
@@ -27,6 +41,83 @@ pub struct StyleModifier {
     pub background: Option<Color>,
     /// Style of the font
     pub font_style: Option<FontStyle>,
+    /// How the underline is drawn
+    pub underline: Option<UnderlineStyle>,
+    /// Color of the underline
+    pub underline_color: Option<Color>,
+    /// A different font for the text
+    pub font_hint: Option<FontHint>,
+}
+
+/// A font family and size for some text, which themes can give besides colors, e.g. to show
+/// comments in a different font
+///
+/// Themes set these with the `syntectFontFamily` and `syntectFontSize` keys of a rule, spelled
+/// `syntect_font_family` and `syntect_font_size` in `.sublime-color-scheme` files. The HTML output
+/// uses them, and other consumers are free to ignore them.
+///
+/// A theme keeps its fonts in [`Theme::font_hints`], and styles refer to them with a [`FontHint`].
+///
+/// [`Theme::font_hints`]: struct.Theme.html#structfield.font_hints
+/// [`FontHint`]: struct.FontHint.html
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Font {
+    /// The font family, a CSS-like list of font names like `Georgia, serif`
+    pub family: Option<String>,
+    /// The size as a factor of the normal font size
+    pub size: Option<f32>,
+}
+
+/// Which of the [`Theme::font_hints`] some text should be in
+///
+/// A [`Style`] refers to the font of its theme by index, so that it stays `Copy` and comparing it
+/// is cheap. Look the font up with [`Theme::font_hint`] in the theme the style came from.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Font, Theme};
+///
+/// let mut theme = Theme::default();
+/// let serif = Font { family: Some("Georgia, serif".to_owned()), size: Some(0.9) };
+/// let hint = theme.add_font_hint(serif.clone());
+/// assert_eq!(theme.font_hint(hint), Some(&serif));
+/// assert_eq!(theme.add_font_hint(serif), hint);
+/// ```
+///
+/// [`Theme::font_hints`]: struct.Theme.html#structfield.font_hints
+/// [`Style`]: struct.Style.html
+/// [`Theme::font_hint`]: struct.Theme.html#method.font_hint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontHint(u32);
+
+impl FontHint {
+    /// The hint for the font at `index` in [`Theme::font_hints`]
+    ///
+    /// [`Theme::font_hints`]: struct.Theme.html#structfield.font_hints
+    pub fn new(index: usize) -> FontHint {
+        FontHint(u32::try_from(index).expect("a theme has fewer than 2^32 fonts"))
+    }
+
+    /// The index of the font in [`Theme::font_hints`]
+    ///
+    /// [`Theme::font_hints`]: struct.Theme.html#structfield.font_hints
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// The way an underline is drawn, as specified by the `fontStyle` of a theme rule
+///
+/// Sublime Text spells these `underline`, `stippled_underline` and `squiggly_underline`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UnderlineStyle {
+    /// A plain solid line
+    Solid,
+    /// A dotted line
+    Stippled,
+    /// A wavy line, like the ones used for spelling mistakes
+    Squiggly,
 }
 
 /// RGBA color, directly from the theme
@@ -67,7 +158,8 @@ impl std::fmt::Debug for Color {
 }
 
 bitflags! {
-    /// The color-independent styling of a font - i.e. bold, italicized, and/or underlined
+    /// The color-independent styling of a font - i.e. bold, italicized, underlined and/or struck
+    /// through
     #[derive(Serialize, Deserialize)]
     pub struct FontStyle: u8 {
         /// Bold font style
@@ -76,6 +168,10 @@ bitflags! {
         const UNDERLINE = 2;
         /// Italic font style
         const ITALIC = 4;
+        /// Glow effect around the text, as supported by Sublime Text 4
+        const GLOW = 8;
+        /// A line through the text, often used for deprecated symbols
+        const STRIKETHROUGH = 16;
     }
 }
 
@@ -95,6 +191,162 @@ impl Color {
         b: 0xFF,
         a: 0xFF,
     };
+
+    /// The closest color of the 256 color palette most terminals support, for terminals without
+    /// true color
+    ///
+    /// Only the 6×6×6 color cube and the gray ramp (indices 16 to 255) are considered, since
+    /// terminals let users change the first 16 colors. Colors are compared in the CIELAB color
+    /// space, which matches how different colors look better than comparing RGB values. The alpha
+    /// channel is ignored.
+    pub fn to_ansi256(self) -> u8 {
+        let lab = Lab::from(self);
+        #[cfg(feature = "global-statics")]
+        let palette = &*ANSI256_LAB;
+        #[cfg(not(feature = "global-statics"))]
+        let palette = &ansi256_lab();
+        let i = palette
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| lab.distance(a).total_cmp(&lab.distance(b)))
+            .map_or(0, |(i, _)| i);
+        i as u8 + 16
+    }
+
+    /// The index of the closest color in a 16 color terminal palette, like [`XTERM_PALETTE`]
+    ///
+    /// The alpha channel is ignored.
+    ///
+    /// [`XTERM_PALETTE`]: constant.XTERM_PALETTE.html
+    pub fn to_ansi16(self, palette: &[Color; 16]) -> u8 {
+        let lab = Lab::from(self);
+        let mut best = (0, f32::INFINITY);
+        for (i, &c) in palette.iter().enumerate() {
+            let distance = lab.distance(&Lab::from(c));
+            if distance < best.1 {
+                best = (i as u8, distance);
+            }
+        }
+        best.0
+    }
+
+    /// The relative luminance of the color, from 0.0 for black to 1.0 for white, as used for the
+    /// contrast ratios of the Web Content Accessibility Guidelines
+    ///
+    /// This is how light the color looks, e.g. to tell dark backgrounds from light ones. The alpha
+    /// channel is ignored.
+    pub fn luminance(self) -> f32 {
+        0.2126 * srgb_to_linear(self.r)
+            + 0.7152 * srgb_to_linear(self.g)
+            + 0.0722 * srgb_to_linear(self.b)
+    }
+
+    /// The color a fraction `t` of the way from this color to `other`, with `t` from 0.0 (this
+    /// color) to 1.0 (`other`)
+    ///
+    /// Every channel is interpolated linearly, including alpha. `t` is clamped to that range.
+    pub fn lerp(self, other: Color, t: f32) -> Color {
+        let t = t.clamp(0.0, 1.0);
+        let channel =
+            |a: u8, b: u8| (f32::from(a) + (f32::from(b) - f32::from(a)) * t).round() as u8;
+        Color {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+            a: channel(self.a, other.a),
+        }
+    }
+}
+
+/// The default colors of the 16 color palette of xterm, for use with [`Style::to_ansi16`]
+///
+/// Most terminals let users change these, so use the actual palette if you know it.
+///
+/// [`Style::to_ansi16`]: struct.Style.html#method.to_ansi16
+pub const XTERM_PALETTE: [Color; 16] = [
+    rgb(0x00, 0x00, 0x00),
+    rgb(0xcd, 0x00, 0x00),
+    rgb(0x00, 0xcd, 0x00),
+    rgb(0xcd, 0xcd, 0x00),
+    rgb(0x00, 0x00, 0xee),
+    rgb(0xcd, 0x00, 0xcd),
+    rgb(0x00, 0xcd, 0xcd),
+    rgb(0xe5, 0xe5, 0xe5),
+    rgb(0x7f, 0x7f, 0x7f),
+    rgb(0xff, 0x00, 0x00),
+    rgb(0x00, 0xff, 0x00),
+    rgb(0xff, 0xff, 0x00),
+    rgb(0x5c, 0x5c, 0xff),
+    rgb(0xff, 0x00, 0xff),
+    rgb(0x00, 0xff, 0xff),
+    rgb(0xff, 0xff, 0xff),
+];
+
+const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color { r, g, b, a: 0xFF }
+}
+
+/// The CIELAB values of the colors at indices 16 to 255 of the 256 color palette
+fn ansi256_lab() -> Vec<Lab> {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let cube = (0..216).map(|i| rgb(LEVELS[i / 36], LEVELS[i / 6 % 6], LEVELS[i % 6]));
+    let grays = (0..24).map(|i| rgb(8 + 10 * i, 8 + 10 * i, 8 + 10 * i));
+    cube.chain(grays).map(Lab::from).collect()
+}
+
+/// The palette of `ansi256_lab`, worked out once
+#[cfg(feature = "global-statics")]
+static ANSI256_LAB: Lazy<Vec<Lab>> = Lazy::new(ansi256_lab);
+
+/// A color in the CIELAB color space, with a D65 white point
+#[derive(Debug, Clone, Copy)]
+struct Lab {
+    l: f32,
+    a: f32,
+    b: f32,
+}
+
+impl Lab {
+    /// The squared CIE76 color difference
+    fn distance(&self, other: &Lab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+}
+
+/// Converts an sRGB channel to linear light, from 0.0 to 1.0
+fn srgb_to_linear(v: u8) -> f32 {
+    let v = v as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl From<Color> for Lab {
+    fn from(c: Color) -> Lab {
+        fn f(t: f32) -> f32 {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        }
+        let (r, g, b) = (
+            srgb_to_linear(c.r),
+            srgb_to_linear(c.g),
+            srgb_to_linear(c.b),
+        );
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
 }
 
 impl Style {
@@ -104,8 +356,25 @@ impl Style {
             foreground: modifier.foreground.unwrap_or(self.foreground),
             background: modifier.background.unwrap_or(self.background),
             font_style: modifier.font_style.unwrap_or(self.font_style),
+            underline: modifier.underline.or(self.underline),
+            underline_color: modifier.underline_color.or(self.underline_color),
+            font_hint: modifier.font_hint.or(self.font_hint),
         }
     }
+
+    /// The indices of the closest colors in a 16 color terminal palette to the foreground and
+    /// background, see [`Color::to_ansi16`]
+    ///
+    /// A translucent foreground is blended with the background first.
+    ///
+    /// [`Color::to_ansi16`]: struct.Color.html#method.to_ansi16
+    pub fn to_ansi16(&self, palette: &[Color; 16]) -> (u8, u8) {
+        let foreground = blend_fg_color(self.foreground, self.background);
+        (
+            foreground.to_ansi16(palette),
+            self.background.to_ansi16(palette),
+        )
+    }
 }
 
 impl Default for Style {
@@ -114,6 +383,9 @@ impl Default for Style {
             foreground: Color::BLACK,
             background: Color::WHITE,
             font_style: FontStyle::empty(),
+            underline: None,
+            underline_color: None,
+            font_hint: None,
         }
     }
 }
@@ -127,12 +399,246 @@ impl StyleModifier {
             foreground: other.foreground.or(self.foreground),
             background: other.background.or(self.background),
             font_style: other.font_style.or(self.font_style),
+            underline: other.underline.or(self.underline),
+            underline_color: other.underline_color.or(self.underline_color),
+            font_hint: other.font_hint.or(self.font_hint),
         }
     }
 }
 
+/// A [`Style`] packed into 8 bytes, for storing a style for each cell of a terminal UI
+///
+/// `Style` takes 24 bytes, which adds up for full-screen editors keeping millions of cells. The
+/// packed style keeps the foreground and background as RGB, the font style and the underline
+/// style, in that order from the least significant bits. Converting a style into one loses some of
+/// it, the same way drawing it on an opaque grid would:
+///
+/// * a translucent foreground is blended with the background, and the background is made opaque
+/// * the underline color and the font hint are dropped
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Color, CompactStyle, FontStyle, Style};
+///
+/// let style = Style {
+///     foreground: Color { r: 0xc0, g: 0xc5, b: 0xce, a: 0xff },
+///     font_style: FontStyle::BOLD,
+///     ..Style::default()
+/// };
+/// let compact = CompactStyle::from(style);
+/// assert_eq!(std::mem::size_of_val(&compact), 8);
+/// assert_eq!(compact.foreground(), style.foreground);
+/// assert_eq!(compact.font_style(), FontStyle::BOLD);
+/// assert_eq!(compact.to_style(), style);
+/// ```
+///
+/// [`Style`]: struct.Style.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CompactStyle(pub u64);
+
+impl CompactStyle {
+    const BACKGROUND_SHIFT: u32 = 24;
+    const FONT_STYLE_SHIFT: u32 = 48;
+    const UNDERLINE_SHIFT: u32 = 56;
+
+    /// The foreground color, always opaque
+    pub fn foreground(self) -> Color {
+        unpack_rgb(self.0)
+    }
+
+    /// The background color, always opaque
+    pub fn background(self) -> Color {
+        unpack_rgb(self.0 >> CompactStyle::BACKGROUND_SHIFT)
+    }
+
+    /// The font style, with all of its flags
+    pub fn font_style(self) -> FontStyle {
+        FontStyle::from_bits_truncate((self.0 >> CompactStyle::FONT_STYLE_SHIFT) as u8)
+    }
+
+    /// How the underline is drawn, see [`Style::underline`]
+    ///
+    /// [`Style::underline`]: struct.Style.html#structfield.underline
+    pub fn underline(self) -> Option<UnderlineStyle> {
+        match (self.0 >> CompactStyle::UNDERLINE_SHIFT) & 0b11 {
+            1 => Some(UnderlineStyle::Solid),
+            2 => Some(UnderlineStyle::Stippled),
+            3 => Some(UnderlineStyle::Squiggly),
+            _ => None,
+        }
+    }
+
+    /// Unpacks the style, with opaque colors and no underline color or font hint
+    pub fn to_style(self) -> Style {
+        Style {
+            foreground: self.foreground(),
+            background: self.background(),
+            font_style: self.font_style(),
+            underline: self.underline(),
+            underline_color: None,
+            font_hint: None,
+        }
+    }
+}
+
+impl From<Style> for CompactStyle {
+    fn from(style: Style) -> CompactStyle {
+        let underline: u64 = match style.underline {
+            None => 0,
+            Some(UnderlineStyle::Solid) => 1,
+            Some(UnderlineStyle::Stippled) => 2,
+            Some(UnderlineStyle::Squiggly) => 3,
+        };
+        CompactStyle(
+            pack_rgb(blend_fg_color(style.foreground, style.background))
+                | pack_rgb(style.background) << CompactStyle::BACKGROUND_SHIFT
+                | u64::from(style.font_style.bits()) << CompactStyle::FONT_STYLE_SHIFT
+                | underline << CompactStyle::UNDERLINE_SHIFT,
+        )
+    }
+}
+
+impl From<CompactStyle> for Style {
+    fn from(compact: CompactStyle) -> Style {
+        compact.to_style()
+    }
+}
+
+fn pack_rgb(color: Color) -> u64 {
+    u64::from(color.r) << 16 | u64::from(color.g) << 8 | u64::from(color.b)
+}
+
+fn unpack_rgb(bits: u64) -> Color {
+    Color {
+        r: (bits >> 16) as u8,
+        g: (bits >> 8) as u8,
+        b: bits as u8,
+        a: 0xFF,
+    }
+}
+
 impl Default for FontStyle {
     fn default() -> FontStyle {
         FontStyle::empty()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_relative_luminance() {
+        assert_eq!(Color::BLACK.luminance(), 0.0);
+        assert!((Color::WHITE.luminance() - 1.0).abs() < 1e-6);
+        // green looks much lighter than blue
+        assert!(rgb(0, 0x80, 0).luminance() > 5.0 * rgb(0, 0, 0x80).luminance());
+        let gray = rgb(0x77, 0x77, 0x77).luminance();
+        assert!((gray - 0.184).abs() < 0.001, "{}", gray);
+    }
+
+    #[test]
+    fn packs_styles_into_compact_styles() {
+        let style = Style {
+            foreground: Color {
+                r: 0xff,
+                g: 0xff,
+                b: 0xff,
+                a: 0x80,
+            },
+            background: rgb(0x12, 0x34, 0x56),
+            font_style: FontStyle::all(),
+            underline: Some(UnderlineStyle::Squiggly),
+            underline_color: Some(rgb(1, 2, 3)),
+            font_hint: None,
+        };
+        let compact = CompactStyle::from(style);
+        assert_eq!(compact.foreground(), rgb(0x88, 0x99, 0xaa));
+        assert_eq!(compact.background(), rgb(0x12, 0x34, 0x56));
+        assert_eq!(compact.font_style(), FontStyle::all());
+        assert_eq!(compact.underline(), Some(UnderlineStyle::Squiggly));
+        assert_eq!(compact.to_style().underline_color, None);
+
+        for underline in [
+            None,
+            Some(UnderlineStyle::Solid),
+            Some(UnderlineStyle::Stippled),
+        ] {
+            let style = Style {
+                underline,
+                ..Style::default()
+            };
+            assert_eq!(Style::from(CompactStyle::from(style)), style);
+        }
+        assert_eq!(
+            CompactStyle::from(Style::default()).0,
+            0xff_ffff << 24,
+            "black on white"
+        );
+    }
+
+    #[test]
+    fn interpolates_colors() {
+        let from = rgb(0, 0x80, 0xff);
+        let to = Color {
+            r: 0xff,
+            g: 0x80,
+            b: 0,
+            a: 0,
+        };
+        assert_eq!(from.lerp(to, 0.0), from);
+        assert_eq!(from.lerp(to, 1.0), to);
+        assert_eq!(
+            from.lerp(to, 0.5),
+            Color {
+                r: 0x80,
+                g: 0x80,
+                b: 0x80,
+                a: 0x80
+            }
+        );
+        assert_eq!(from.lerp(to, -1.0), from);
+        assert_eq!(from.lerp(to, 2.0), to);
+    }
+
+    #[test]
+    fn maps_to_ansi256() {
+        assert_eq!(Color::BLACK.to_ansi256(), 16);
+        assert_eq!(Color::WHITE.to_ansi256(), 231);
+        assert_eq!(rgb(0xff, 0, 0).to_ansi256(), 196);
+        assert_eq!(rgb(0x80, 0x80, 0x80).to_ansi256(), 244);
+        assert_eq!(rgb(0x5f, 0x87, 0xaf).to_ansi256(), 67);
+        // close to the blue of the cube, not the gray ramp
+        assert_eq!(rgb(0x2b, 0x30, 0x3b).to_ansi256(), 236);
+    }
+
+    #[test]
+    fn maps_to_ansi16() {
+        let style = Style {
+            foreground: rgb(0xd0, 0x20, 0x20),
+            background: rgb(0x10, 0x10, 0x10),
+            ..Style::default()
+        };
+        assert_eq!(style.to_ansi16(&XTERM_PALETTE), (1, 0));
+        let style = Style {
+            foreground: Color {
+                a: 0x10,
+                ..Color::WHITE
+            },
+            ..style
+        };
+        assert_eq!(style.to_ansi16(&XTERM_PALETTE), (0, 0));
+        assert_eq!(rgb(0xfa, 0xfa, 0x10).to_ansi16(&XTERM_PALETTE), 11);
+        assert_eq!(rgb(0xc0, 0xc0, 0x10).to_ansi16(&XTERM_PALETTE), 3);
+    }
+
+    #[test]
+    fn keeps_styles_small() {
+        // A style is copied for every token. The underline and font hint fields grew it from 9
+        // bytes without a difference in the `highlighting` benches beyond their noise, so make
+        // sure it doesn't grow further without measuring again.
+        assert_eq!(std::mem::size_of::<Style>(), 24);
+        assert_eq!(std::mem::size_of::<StyleModifier>(), 28);
+    }
+}
@@ -1,8 +1,13 @@
 // Code based on https://github.com/defuz/sublimate/blob/master/src/core/syntax/theme.rs
 // released under the MIT license by @defuz
+use super::highlighter::Highlighter;
 use super::selector::*;
 use super::style::*;
+use crate::parsing::Scope;
+use crate::util::blend_fg_color;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
 
 /// A theme parsed from a `.tmTheme` file.
 ///
@@ -15,6 +20,11 @@ pub struct Theme {
     pub settings: ThemeSettings,
     /// The styling rules for the viewed text
     pub scopes: Vec<ThemeItem>,
+    /// The fonts the rules ask for, which the [`FontHint`]s of their styles are indexes into
+    ///
+    /// [`FontHint`]: struct.FontHint.html
+    #[serde(default)]
+    pub font_hints: Vec<Font>,
 }
 
 /// Properties for styling the UI of a text editor
@@ -105,8 +115,844 @@ pub struct ThemeSettings {
 
     /// The color of the shadow used when a text area can be horizontally scrolled.
     pub shadow: Option<Color>,
+
+    /// Any other settings the theme defines which syntect doesn't interpret itself,
+    /// e.g. `shadowWidth` or `invisibles`, keyed by their name in the theme file.
+    ///
+    /// Values that aren't strings in the theme are stored in their JSON representation.
+    #[serde(default)]
+    pub extras: BTreeMap<String, String>,
+}
+
+impl Theme {
+    /// The font a style of this theme asks for with `hint`, if it's one of the [`font_hints`]
+    ///
+    /// [`font_hints`]: #structfield.font_hints
+    pub fn font_hint(&self, hint: FontHint) -> Option<&Font> {
+        self.font_hints.get(hint.index())
+    }
+
+    /// Adds a font to the [`font_hints`] and returns the hint for it, or returns the hint for the
+    /// same font if the theme already has it
+    ///
+    /// [`font_hints`]: #structfield.font_hints
+    pub fn add_font_hint(&mut self, font: Font) -> FontHint {
+        add_font(&mut self.font_hints, font)
+    }
+
+    /// A hash of the whole theme which is the same in every run and on every platform, e.g. for
+    /// the `ETag` of a stylesheet generated from it
+    ///
+    /// It's only meant to tell themes apart, and can change in new versions of syntect, e.g. when
+    /// `Theme` gets new fields.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::ThemeSet;
+    ///
+    /// let ts = ThemeSet::load_defaults();
+    /// let mut theme = ts.themes["InspiredGitHub"].clone();
+    /// let digest = theme.digest();
+    /// assert_eq!(theme.clone().digest(), digest);
+    /// theme.settings.background = None;
+    /// assert_ne!(theme.digest(), digest);
+    /// ```
+    pub fn digest(&self) -> u64 {
+        let mut hasher = Fnv1a(0xcbf2_9ce4_8422_2325);
+        // scopes are serialized as strings, unlike their numbers which depend on the process
+        serde_json::to_writer(&mut hasher, self).expect("themes can always be serialized");
+        hasher.0
+    }
+
+    /// How light the theme looks, from 0.0 for a black background to 1.0 for a white one
+    ///
+    /// This is the [`Color::luminance`] of the background, which covers most of a view of code.
+    /// A theme without a background leaves it to the terminal or editor, so then it's the inverse
+    /// of the luminance of the foreground, since light text is meant for a dark background. It's
+    /// `None` for a theme with neither.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::ThemeSet;
+    ///
+    /// let ts = ThemeSet::load_defaults();
+    /// assert!(ts.themes["base16-ocean.dark"].average_luminance().unwrap() < 0.1);
+    /// assert!(ts.themes["InspiredGitHub"].average_luminance().unwrap() > 0.9);
+    /// ```
+    ///
+    /// [`Color::luminance`]: struct.Color.html#method.luminance
+    pub fn average_luminance(&self) -> Option<f32> {
+        match (self.settings.background, self.settings.foreground) {
+            (Some(background), _) => Some(background.luminance()),
+            (None, Some(foreground)) => Some(1.0 - foreground.luminance()),
+            (None, None) => None,
+        }
+    }
+
+    /// Layers the settings and rules of `other` on top of this theme, e.g. to apply a user's
+    /// customizations to whichever theme is active.
+    ///
+    /// Settings set in `other` replace those of this theme. The rules of `other` are added after
+    /// the rules of this theme, and a rule of `other` with exactly the same selector as an existing
+    /// rule replaces that rule, keeping any properties the new rule doesn't set. So overlaying a
+    /// theme with a single green `comment` rule changes the color of comments but keeps them
+    /// italic if the base theme made them italic.
+    pub fn overlay(&self, other: &Theme) -> Theme {
+        let mut scopes = self.scopes.clone();
+        let mut added: Vec<ThemeItem> = Vec::new();
+        let mut font_hints = self.font_hints.clone();
+        for item in &other.scopes {
+            // the hints of `other` are indexes into its own fonts
+            let style = StyleModifier {
+                font_hint: item
+                    .style
+                    .font_hint
+                    .and_then(|hint| other.font_hint(hint))
+                    .map(|font| add_font(&mut font_hints, font.clone())),
+                ..item.style
+            };
+            let mut unmatched = Vec::new();
+            for sel in &item.scope.selectors {
+                let mut replaced: Option<ThemeItem> = None;
+                for existing in scopes.iter_mut().chain(added.iter_mut()) {
+                    let len = existing.scope.selectors.len();
+                    existing.scope.selectors.retain(|s| s != sel);
+                    if existing.scope.selectors.len() == len {
+                        continue;
+                    }
+                    let merged = replaced.get_or_insert_with(ThemeItem::default);
+                    merged.style = merged.style.apply(existing.style);
+                    merged.extras.extend(existing.extras.clone());
+                }
+                match replaced {
+                    Some(mut merged) => {
+                        merged.scope.selectors.push(sel.clone());
+                        merged.style = merged.style.apply(style);
+                        merged.extras.extend(item.extras.clone());
+                        added.push(merged);
+                    }
+                    None => unmatched.push(sel.clone()),
+                }
+            }
+            if !unmatched.is_empty() {
+                added.push(ThemeItem {
+                    scope: ScopeSelectors {
+                        selectors: unmatched,
+                    },
+                    style,
+                    extras: item.extras.clone(),
+                });
+            }
+        }
+        scopes.extend(added);
+        scopes.retain(|item| !item.scope.selectors.is_empty());
+
+        Theme {
+            name: self.name.clone(),
+            author: self.author.clone(),
+            settings: self.settings.overlay(&other.settings),
+            scopes,
+            font_hints,
+        }
+    }
+
+    /// Returns this theme with the backgrounds of all its rules removed, so every token is drawn
+    /// on the default background
+    ///
+    /// This is useful e.g. for terminals, where a theme's per-scope backgrounds tend to clash with
+    /// the terminal's own background. The default background in [`settings`] is kept.
+    ///
+    /// [`settings`]: #structfield.settings
+    pub fn without_backgrounds(&self) -> Theme {
+        let mut theme = self.clone();
+        for item in &mut theme.scopes {
+            item.style.background = None;
+        }
+        theme
+    }
+
+    /// Returns this theme with `f` applied to every color in it, both in the [`settings`] and in
+    /// the rules, e.g. to adjust a theme's contrast for a particular terminal
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{Color, Theme, ThemeSettings};
+    ///
+    /// let theme = Theme {
+    ///     settings: ThemeSettings {
+    ///         foreground: Some(Color { r: 0x80, g: 0x40, b: 0x20, a: 0xFF }),
+    ///         ..ThemeSettings::default()
+    ///     },
+    ///     ..Theme::default()
+    /// };
+    /// let inverted = theme.map_colors(|c| Color { r: !c.r, g: !c.g, b: !c.b, a: c.a });
+    /// assert_eq!(inverted.settings.foreground, Some(Color { r: 0x7F, g: 0xBF, b: 0xDF, a: 0xFF }));
+    /// ```
+    ///
+    /// [`settings`]: #structfield.settings
+    pub fn map_colors<F: FnMut(Color) -> Color>(&self, mut f: F) -> Theme {
+        let mut theme = self.clone();
+        theme.settings = theme.settings.map_colors(&mut f);
+        for item in &mut theme.scopes {
+            let style = &mut item.style;
+            for color in [
+                &mut style.foreground,
+                &mut style.background,
+                &mut style.underline_color,
+            ] {
+                *color = color.map(&mut f);
+            }
+        }
+        theme
+    }
+
+    /// Blends this theme with `other`, a fraction `t` of the way from this theme (0.0) to `other`
+    /// (1.0), e.g. to animate switching themes or to dim the panes that don't have focus
+    ///
+    /// Colors set in both themes are interpolated with [`Color::lerp`], both in the [`settings`]
+    /// and in rules with the same selectors in both themes. Everything else, like the other rules,
+    /// the font styles and the name, comes from the theme `t` is closer to, so it changes at 0.5.
+    /// Blending works best between variants of one theme, like a theme and a dimmed copy of it
+    /// made with [`map_colors`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{Color, ThemeSet};
+    ///
+    /// let ts = ThemeSet::load_defaults();
+    /// let theme = &ts.themes["base16-ocean.dark"];
+    /// let background = theme.settings.background.unwrap();
+    /// let dim = theme.map_colors(|c| c.lerp(background, 0.5));
+    ///
+    /// let half_dimmed = theme.lerp(&dim, 0.5);
+    /// assert_eq!(half_dimmed.scopes.len(), theme.scopes.len());
+    /// let (fg, dim_fg) = (theme.settings.foreground.unwrap(), dim.settings.foreground.unwrap());
+    /// assert_eq!(half_dimmed.settings.foreground, Some(fg.lerp(dim_fg, 0.5)));
+    /// ```
+    ///
+    /// [`Color::lerp`]: struct.Color.html#method.lerp
+    /// [`settings`]: #structfield.settings
+    /// [`map_colors`]: #method.map_colors
+    pub fn lerp(&self, other: &Theme, t: f32) -> Theme {
+        let (near, far) = if t < 0.5 {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let mut theme = near.clone();
+        theme.settings = self.settings.lerp(&other.settings, t);
+        for item in &mut theme.scopes {
+            let far_item = match far.scopes.iter().find(|far| far.scope == item.scope) {
+                Some(far_item) => far_item,
+                None => continue,
+            };
+            let style = &mut item.style;
+            for (color, far_color) in [
+                (&mut style.foreground, far_item.style.foreground),
+                (&mut style.background, far_item.style.background),
+                (&mut style.underline_color, far_item.style.underline_color),
+            ] {
+                if let (Some(near_color), Some(far_color)) = (*color, far_color) {
+                    // `t` is relative to this theme, not the nearer one
+                    *color = Some(if t < 0.5 {
+                        near_color.lerp(far_color, t)
+                    } else {
+                        far_color.lerp(near_color, t)
+                    });
+                }
+            }
+        }
+        theme
+    }
+
+    /// Returns this theme with rules added for common kinds of tokens it leaves unstyled, with
+    /// colors taken from similar kinds of tokens it does style
+    ///
+    /// Minimal themes often only style a few scopes, so e.g. HTML tags or function names come out
+    /// in the default color. For each kind of token in a fixed list which no rule of this theme
+    /// gives a color, a rule is added with the color of the first related scope that has one,
+    /// e.g. `entity.name.tag` gets the color of `keyword`. Comments and punctuation outside of
+    /// strings and comments get the default foreground faded towards the background instead.
+    /// Kinds without a related color are left alone, and existing rules are never changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{Color, Highlighter, ThemeBuilder};
+    /// use syntect::parsing::Scope;
+    ///
+    /// let red = Color { r: 0xFF, g: 0, b: 0, a: 0xFF };
+    /// let theme = ThemeBuilder::new()
+    ///     .scope("keyword", |s| s.foreground(red))
+    ///     .build()
+    ///     .unwrap();
+    /// let theme = theme.with_semantic_defaults();
+    /// let tag = [Scope::new("text.html").unwrap(), Scope::new("entity.name.tag").unwrap()];
+    /// assert_eq!(Highlighter::new(&theme).style_for_stack(&tag).foreground, red);
+    /// ```
+    pub fn with_semantic_defaults(&self) -> Theme {
+        let highlighter = Highlighter::new(self);
+        let foreground_of = |scope: &str| {
+            let scope = Scope::new(scope).expect("the default scopes are valid");
+            highlighter.style_mod_for_stack(&[scope]).foreground
+        };
+        let faded = match (self.settings.foreground, self.settings.background) {
+            (Some(fg), Some(bg)) => Some(blend_fg_color(Color { a: 0x99, ..fg }, bg)),
+            _ => None,
+        };
+        let mut theme = self.clone();
+        for &(kind, selector, related) in SEMANTIC_DEFAULTS {
+            if foreground_of(kind).is_some() {
+                continue;
+            }
+            let color = if related.is_empty() {
+                faded
+            } else {
+                related.iter().find_map(|&scope| foreground_of(scope))
+            };
+            if let Some(color) = color {
+                theme.scopes.push(ThemeItem {
+                    scope: selector.parse().expect("the default selectors are valid"),
+                    style: StyleModifier {
+                        foreground: Some(color),
+                        ..StyleModifier::default()
+                    },
+                    extras: BTreeMap::new(),
+                });
+            }
+        }
+        theme
+    }
+
+    /// Reports the selectors of this theme which the CSS generated by
+    /// [`css_for_theme_with_class_style`] can't represent, so classed HTML would be styled
+    /// differently than HTML with inline styles
+    ///
+    /// That CSS styles scopes with descendant class selectors, like `.source .string`, which
+    /// can't express exclusions, the `>` operator or alternatives within a selector.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{ClassedCssWarning, ThemeBuilder};
+    ///
+    /// let theme = ThemeBuilder::new()
+    ///     .scope("comment, string - string.quoted", |s| s.italic())
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     theme.validate_for_classed_css(),
+    ///     vec![ClassedCssWarning::Exclusion { rule: 0, selector: 1 }]
+    /// );
+    /// ```
+    ///
+    /// [`css_for_theme_with_class_style`]: ../html/fn.css_for_theme_with_class_style.html
+    pub fn validate_for_classed_css(&self) -> Vec<ClassedCssWarning> {
+        let mut warnings = Vec::new();
+        for (rule, item) in self.scopes.iter().enumerate() {
+            for (selector, sel) in item.scope.selectors.iter().enumerate() {
+                let mut unsupported = UnsupportedInCss::default();
+                match &sel.expression {
+                    Some(expression) => unsupported.add(expression),
+                    None => {
+                        unsupported.empty = sel.path.is_empty();
+                        unsupported.exclusion = !sel.excludes.is_empty();
+                    }
+                }
+                let found = [
+                    (
+                        unsupported.empty,
+                        ClassedCssWarning::Empty { rule, selector },
+                    ),
+                    (
+                        unsupported.exclusion,
+                        ClassedCssWarning::Exclusion { rule, selector },
+                    ),
+                    (
+                        unsupported.child,
+                        ClassedCssWarning::ChildOperator { rule, selector },
+                    ),
+                    (
+                        unsupported.combination,
+                        ClassedCssWarning::Combination { rule, selector },
+                    ),
+                ];
+                warnings.extend(found.into_iter().filter(|(f, _)| *f).map(|(_, w)| w));
+            }
+        }
+        warnings
+    }
+
+    /// Compares this theme with an edited version of it, e.g. for an editor that updates its UI
+    /// while a theme is being edited
+    ///
+    /// The rules are compared by their position, so editing a rule reports both its old and its
+    /// new selectors, and so does adding, removing or moving rules for every position after the
+    /// change. The name and author aren't compared.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::str::FromStr;
+    /// use syntect::highlighting::{Color, SettingValue, ThemeBuilder};
+    /// use syntect::parsing::ScopeStack;
+    ///
+    /// let red = Color { r: 0xFF, g: 0, b: 0, a: 0xFF };
+    /// let old = ThemeBuilder::new()
+    ///     .scope("comment", |s| s.italic())
+    ///     .scope("string", |s| s.foreground(red))
+    ///     .build()
+    ///     .unwrap();
+    /// let mut new = old.clone();
+    /// new.scopes[1].style.foreground = Some(Color::BLACK);
+    /// new.settings.caret = Some(red);
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.settings[0].key, "caret");
+    /// assert_eq!(diff.settings[0].new, Some(SettingValue::Color(red)));
+    /// let string = ScopeStack::from_str("source.rust string.quoted").unwrap();
+    /// let comment = ScopeStack::from_str("source.rust comment.line").unwrap();
+    /// assert!(diff.affects(string.as_slice()));
+    /// assert!(!diff.affects(comment.as_slice()));
+    /// ```
+    pub fn diff(&self, other: &Theme) -> ThemeDiff {
+        // the font hints are compared by the fonts they stand for in their themes
+        fn same_rule(
+            old_theme: &Theme,
+            old: &ThemeItem,
+            new_theme: &Theme,
+            new: &ThemeItem,
+        ) -> bool {
+            let font = |theme: &Theme, item: &ThemeItem| {
+                item.style
+                    .font_hint
+                    .and_then(|hint| theme.font_hint(hint))
+                    .cloned()
+            };
+            let without_font = |item: &ThemeItem| StyleModifier {
+                font_hint: None,
+                ..item.style
+            };
+            old.scope == new.scope
+                && old.extras == new.extras
+                && without_font(old) == without_font(new)
+                && font(old_theme, old) == font(new_theme, new)
+        }
+
+        let mut rules = Vec::new();
+        let len = self.scopes.len().max(other.scopes.len());
+        for i in 0..len {
+            match (self.scopes.get(i), other.scopes.get(i)) {
+                (Some(old), Some(new)) if same_rule(self, old, other, new) => {}
+                (old, new) => {
+                    for item in old.into_iter().chain(new) {
+                        if !rules.contains(&item.scope) {
+                            rules.push(item.scope.clone());
+                        }
+                    }
+                }
+            }
+        }
+        ThemeDiff {
+            settings: self.settings.diff(&other.settings),
+            rules,
+        }
+    }
+}
+
+/// Adds `font` to `fonts` if it isn't in there yet, see [`Theme::add_font_hint`]
+///
+/// [`Theme::add_font_hint`]: struct.Theme.html#method.add_font_hint
+fn add_font(fonts: &mut Vec<Font>, font: Font) -> FontHint {
+    let same = |f: &Font| {
+        f.family == font.family && f.size.map(f32::to_bits) == font.size.map(f32::to_bits)
+    };
+    match fonts.iter().position(same) {
+        Some(index) => FontHint::new(index),
+        None => {
+            fonts.push(font);
+            FontHint::new(fonts.len() - 1)
+        }
+    }
+}
+
+/// The differences between two themes, see [`Theme::diff`]
+///
+/// [`Theme::diff`]: struct.Theme.html#method.diff
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ThemeDiff {
+    /// The settings that changed
+    pub settings: Vec<SettingChange>,
+    /// The selectors of the rules that were added, removed or changed, so text matching them
+    /// may be styled differently
+    pub rules: Vec<ScopeSelectors>,
+}
+
+impl ThemeDiff {
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty() && self.rules.is_empty()
+    }
+
+    /// Whether text with the given scopes may be styled differently, because a rule that was
+    /// changed matches them
+    pub fn affects(&self, stack: &[Scope]) -> bool {
+        self.rules.iter().any(|sel| sel.does_match(stack).is_some())
+    }
+}
+
+/// A setting that differs between two [`ThemeSettings`], see [`ThemeSettings::diff`]
+///
+/// [`ThemeSettings`]: struct.ThemeSettings.html
+/// [`ThemeSettings::diff`]: struct.ThemeSettings.html#method.diff
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    /// The name of the setting in theme files, like `lineHighlight`, which for extras is their
+    /// key in [`ThemeSettings::extras`]
+    ///
+    /// [`ThemeSettings::extras`]: struct.ThemeSettings.html#structfield.extras
+    pub key: String,
+    /// The value before the change, or `None` if it wasn't set
+    pub old: Option<SettingValue>,
+    /// The value after the change, or `None` if it was removed
+    pub new: Option<SettingValue>,
+}
+
+/// The value of a setting in [`ThemeSettings`]
+///
+/// [`ThemeSettings`]: struct.ThemeSettings.html
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum SettingValue {
+    Color(Color),
+    /// CSS for popups or phantoms
+    Css(String),
+    UnderlineOption(UnderlineOption),
+    /// A setting in [`ThemeSettings::extras`]
+    ///
+    /// [`ThemeSettings::extras`]: struct.ThemeSettings.html#structfield.extras
+    Extra(String),
+}
+
+/// The parts of a selector the classed CSS can't represent
+#[derive(Default)]
+struct UnsupportedInCss {
+    empty: bool,
+    exclusion: bool,
+    child: bool,
+    combination: bool,
 }
 
+impl UnsupportedInCss {
+    fn add(&mut self, expression: &SelectorExpression) {
+        match expression {
+            SelectorExpression::Path { direct_child, .. } => {
+                self.child |= direct_child.iter().any(|&c| c);
+            }
+            SelectorExpression::And(a, b) | SelectorExpression::Or(a, b) => {
+                self.combination = true;
+                self.add(a);
+                self.add(b);
+            }
+            SelectorExpression::Minus(a, b) => {
+                self.exclusion = true;
+                self.add(a);
+                self.add(b);
+            }
+            SelectorExpression::Not(a) => {
+                self.exclusion = true;
+                self.add(a);
+            }
+            SelectorExpression::Side(_, a) => self.add(a),
+        }
+    }
+}
+
+/// A selector of a [`Theme`] that the CSS generated by [`css_for_theme_with_class_style`]
+/// matches differently, see [`Theme::validate_for_classed_css`]
+///
+/// `rule` is the index of the rule in [`Theme::scopes`], and `selector` the index of the selector
+/// in the comma-separated selectors of the rule.
+///
+/// [`Theme`]: struct.Theme.html
+/// [`css_for_theme_with_class_style`]: ../html/fn.css_for_theme_with_class_style.html
+/// [`Theme::validate_for_classed_css`]: struct.Theme.html#method.validate_for_classed_css
+/// [`Theme::scopes`]: struct.Theme.html#structfield.scopes
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClassedCssWarning {
+    /// The selector is empty, so it matches everything, but the CSS matches nothing
+    Empty { rule: usize, selector: usize },
+    /// The selector excludes scopes, like `string - string.quoted`, or negates them. The CSS
+    /// doesn't, so it also styles what the selector excludes.
+    Exclusion { rule: usize, selector: usize },
+    /// The selector uses `>` to require a scope to be directly inside another. The CSS also
+    /// matches it further inside.
+    ChildOperator { rule: usize, selector: usize },
+    /// The selector combines alternatives with `|` or `&` in a group. The CSS only uses the first
+    /// path in the selector.
+    Combination { rule: usize, selector: usize },
+}
+
+impl std::fmt::Display for ClassedCssWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (rule, selector, problem) = match *self {
+            ClassedCssWarning::Empty { rule, selector } => (rule, selector, "is empty"),
+            ClassedCssWarning::Exclusion { rule, selector } => (rule, selector, "excludes scopes"),
+            ClassedCssWarning::ChildOperator { rule, selector } => {
+                (rule, selector, "uses the `>` operator")
+            }
+            ClassedCssWarning::Combination { rule, selector } => {
+                (rule, selector, "combines alternatives")
+            }
+        };
+        write!(
+            f,
+            "Selector {} of rule {} {}, which classed CSS can't represent",
+            selector, rule, problem
+        )
+    }
+}
+
+/// Expands the macro named by `$callback` with every setting of [`ThemeSettings`] besides the
+/// extras, in the order of the fields, as `field: "key",` where the key is the name of the setting
+/// in theme files
+///
+/// The methods that go through all the settings are written against this list, so a new setting
+/// only has to be added here.
+macro_rules! for_each_setting {
+    ($callback:ident) => {
+        $callback! {
+            foreground: "foreground",
+            background: "background",
+            caret: "caret",
+            line_highlight: "lineHighlight",
+            misspelling: "misspelling",
+            minimap_border: "minimapBorder",
+            accent: "accent",
+            popup_css: "popupCss",
+            phantom_css: "phantomCss",
+            bracket_contents_foreground: "bracketContentsForeground",
+            bracket_contents_options: "bracketContentsOptions",
+            brackets_foreground: "bracketsForeground",
+            brackets_background: "bracketsBackground",
+            brackets_options: "bracketsOptions",
+            tags_foreground: "tagsForeground",
+            tags_options: "tagsOptions",
+            highlight: "highlight",
+            find_highlight: "findHighlight",
+            find_highlight_foreground: "findHighlightForeground",
+            gutter: "gutter",
+            gutter_foreground: "gutterForeground",
+            selection: "selection",
+            selection_foreground: "selectionForeground",
+            selection_border: "selectionBorder",
+            inactive_selection: "inactiveSelection",
+            inactive_selection_foreground: "inactiveSelectionForeground",
+            guide: "guide",
+            active_guide: "activeGuide",
+            stack_guide: "stackGuide",
+            shadow: "shadow",
+        }
+    };
+}
+
+/// The types of the values in [`ThemeSettings`], and what the methods that go through all the
+/// settings do with each of them
+trait Setting: Clone {
+    fn into_value(self) -> SettingValue;
+
+    /// Blends two values of the setting, if it's a color
+    fn lerp_setting(&self, _other: &Self, _t: f32) -> Option<Self> {
+        None
+    }
+
+    /// Applies `f` to the value of the setting, if it's a color
+    fn map_setting_colors(&mut self, _f: &mut dyn FnMut(Color) -> Color) {}
+}
+
+impl Setting for Color {
+    fn into_value(self) -> SettingValue {
+        SettingValue::Color(self)
+    }
+
+    fn lerp_setting(&self, other: &Color, t: f32) -> Option<Color> {
+        Some(self.lerp(*other, t))
+    }
+
+    fn map_setting_colors(&mut self, f: &mut dyn FnMut(Color) -> Color) {
+        *self = f(*self);
+    }
+}
+
+impl Setting for String {
+    fn into_value(self) -> SettingValue {
+        SettingValue::Css(self)
+    }
+}
+
+impl Setting for UnderlineOption {
+    fn into_value(self) -> SettingValue {
+        SettingValue::UnderlineOption(self)
+    }
+}
+
+impl ThemeSettings {
+    /// Returns these settings with every setting present in `other` replaced by its value there
+    pub fn overlay(&self, other: &ThemeSettings) -> ThemeSettings {
+        fn overlay<T: Setting>(value: &Option<T>, other: &Option<T>) -> Option<T> {
+            other.as_ref().or(value.as_ref()).cloned()
+        }
+
+        let mut extras = self.extras.clone();
+        extras.extend(other.extras.clone());
+        macro_rules! overlay_settings {
+            ($($field:ident: $key:literal,)*) => {
+                ThemeSettings {
+                    $($field: overlay(&self.$field, &other.$field),)*
+                    extras,
+                }
+            };
+        }
+        for_each_setting!(overlay_settings)
+    }
+
+    /// Lists the settings that differ in `other`, in the order of the fields of
+    /// `ThemeSettings`, followed by the changed extras in the order of their keys
+    pub fn diff(&self, other: &ThemeSettings) -> Vec<SettingChange> {
+        fn change<T: PartialEq + Clone>(
+            changes: &mut Vec<SettingChange>,
+            key: &str,
+            old: &Option<T>,
+            new: &Option<T>,
+            value: fn(T) -> SettingValue,
+        ) {
+            if old != new {
+                changes.push(SettingChange {
+                    key: key.to_owned(),
+                    old: old.clone().map(value),
+                    new: new.clone().map(value),
+                });
+            }
+        }
+
+        let mut changes = Vec::new();
+        macro_rules! diff_settings {
+            ($($field:ident: $key:literal,)*) => {
+                $(change(
+                    &mut changes,
+                    $key,
+                    &self.$field,
+                    &other.$field,
+                    Setting::into_value,
+                );)*
+            };
+        }
+        for_each_setting!(diff_settings);
+
+        let mut keys: Vec<&String> = self.extras.keys().chain(other.extras.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            change(
+                &mut changes,
+                key,
+                &self.extras.get(key).cloned(),
+                &other.extras.get(key).cloned(),
+                SettingValue::Extra,
+            );
+        }
+        changes
+    }
+
+    /// Blends these settings with `other`, see [`Theme::lerp`]
+    ///
+    /// [`Theme::lerp`]: struct.Theme.html#method.lerp
+    pub fn lerp(&self, other: &ThemeSettings, t: f32) -> ThemeSettings {
+        let mut settings = if t < 0.5 { self.clone() } else { other.clone() };
+        macro_rules! lerp_settings {
+            ($($field:ident: $key:literal,)*) => {
+                $(if let (Some(from), Some(to)) = (&self.$field, &other.$field) {
+                    if let Some(value) = from.lerp_setting(to, t) {
+                        settings.$field = Some(value);
+                    }
+                })*
+            };
+        }
+        for_each_setting!(lerp_settings);
+        settings
+    }
+
+    /// Returns these settings with `f` applied to every color setting
+    pub fn map_colors<F: FnMut(Color) -> Color>(&self, mut f: F) -> ThemeSettings {
+        let mut settings = self.clone();
+        macro_rules! map_settings {
+            ($($field:ident: $key:literal,)*) => {
+                $(if let Some(value) = &mut settings.$field {
+                    value.map_setting_colors(&mut f);
+                })*
+            };
+        }
+        for_each_setting!(map_settings);
+        settings
+    }
+}
+
+/// The kinds of tokens [`Theme::with_semantic_defaults`] adds rules for: the scope checked for a
+/// color, the selector of the rule and the scopes to take the color from, in order of preference.
+/// No related scopes means the faded default foreground.
+const SEMANTIC_DEFAULTS: &[(&str, &str, &[&str])] = &[
+    ("comment", "comment", &[]),
+    ("string", "string", &["constant", "markup.raw"]),
+    (
+        "constant.numeric",
+        "constant.numeric",
+        &["constant", "string"],
+    ),
+    ("keyword", "keyword", &["storage", "entity.name.tag"]),
+    ("storage", "storage", &["keyword"]),
+    (
+        "entity.name.function",
+        "entity.name.function",
+        &["support.function", "entity.name", "keyword"],
+    ),
+    (
+        "support.function",
+        "support.function",
+        &["entity.name.function"],
+    ),
+    (
+        "entity.name.type",
+        "entity.name.type",
+        &["storage.type", "support.type", "entity.name"],
+    ),
+    (
+        "entity.name.tag",
+        "entity.name.tag",
+        &["keyword", "storage"],
+    ),
+    (
+        "entity.other.attribute-name",
+        "entity.other.attribute-name",
+        &[
+            "entity.other.inherited-class",
+            "variable.parameter",
+            "entity.name.function",
+        ],
+    ),
+    ("variable.parameter", "variable.parameter", &["variable"]),
+    (
+        "markup.heading",
+        "markup.heading",
+        &["entity.name.section", "keyword"],
+    ),
+    ("punctuation", "punctuation - string - comment", &[]),
+];
+
 /// A component of a theme meant to highlight a specific thing (e.g string literals)
 /// in a certain way.
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -115,6 +961,10 @@ pub struct ThemeItem {
     pub scope: ScopeSelectors,
     /// The style to use for this component
     pub style: StyleModifier,
+    /// Settings of this rule which syntect doesn't interpret itself,
+    /// e.g. `selectionForeground`, keyed by their name in the theme file.
+    #[serde(default)]
+    pub extras: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
@@ -125,3 +975,464 @@ pub enum UnderlineOption {
     StippledUnderline,
     SquigglyUnderline,
 }
+
+/// The 64-bit FNV-1a hash of the bytes written to it
+struct Fnv1a(u64);
+
+impl io::Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::ScopeStack;
+    use std::str::FromStr;
+
+    fn item(selector: &str, style: StyleModifier) -> ThemeItem {
+        ThemeItem {
+            scope: ScopeSelectors::from_str(selector).unwrap(),
+            style,
+            extras: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn overlay_replaces_rules_with_the_same_selector() {
+        let green = Color {
+            r: 0,
+            g: 0xff,
+            b: 0,
+            a: 0xff,
+        };
+        let base = Theme {
+            name: Some("Base".to_owned()),
+            settings: ThemeSettings {
+                foreground: Some(Color::BLACK),
+                background: Some(Color::WHITE),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![
+                item(
+                    "comment, string",
+                    StyleModifier {
+                        foreground: Some(Color::BLACK),
+                        font_style: Some(FontStyle::ITALIC),
+                        ..StyleModifier::default()
+                    },
+                ),
+                item(
+                    "keyword",
+                    StyleModifier {
+                        font_style: Some(FontStyle::BOLD),
+                        ..StyleModifier::default()
+                    },
+                ),
+            ],
+            ..Theme::default()
+        };
+        let user = Theme {
+            settings: ThemeSettings {
+                background: Some(green),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![item(
+                "comment, constant",
+                StyleModifier {
+                    foreground: Some(green),
+                    ..StyleModifier::default()
+                },
+            )],
+            ..Theme::default()
+        };
+
+        let theme = base.overlay(&user);
+        assert_eq!(theme.name.as_deref(), Some("Base"));
+        assert_eq!(theme.settings.foreground, Some(Color::BLACK));
+        assert_eq!(theme.settings.background, Some(green));
+
+        let selectors: Vec<ScopeSelectors> = theme.scopes.iter().map(|i| i.scope.clone()).collect();
+        assert_eq!(
+            selectors,
+            vec![
+                ScopeSelectors::from_str("string").unwrap(),
+                ScopeSelectors::from_str("keyword").unwrap(),
+                ScopeSelectors::from_str("comment").unwrap(),
+                ScopeSelectors::from_str("constant").unwrap(),
+            ]
+        );
+        assert_eq!(
+            theme.scopes[2].style,
+            StyleModifier {
+                foreground: Some(green),
+                font_style: Some(FontStyle::ITALIC),
+                ..StyleModifier::default()
+            }
+        );
+
+        let stack = ScopeStack::from_str("source comment").unwrap();
+        let style = crate::highlighting::Highlighter::new(&theme).style_for_stack(stack.as_slice());
+        assert_eq!(style.foreground, green);
+        assert_eq!(style.font_style, FontStyle::ITALIC);
+    }
+
+    #[test]
+    fn fills_in_missing_rules_from_related_colors() {
+        let red = Color {
+            r: 0xff,
+            g: 0,
+            b: 0,
+            a: 0xff,
+        };
+        let blue = Color {
+            r: 0,
+            g: 0,
+            b: 0xff,
+            a: 0xff,
+        };
+        let theme = Theme {
+            settings: ThemeSettings {
+                foreground: Some(Color::BLACK),
+                background: Some(Color::WHITE),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![
+                item(
+                    "storage",
+                    StyleModifier {
+                        foreground: Some(red),
+                        ..StyleModifier::default()
+                    },
+                ),
+                item(
+                    "string",
+                    StyleModifier {
+                        foreground: Some(blue),
+                        font_style: Some(FontStyle::ITALIC),
+                        ..StyleModifier::default()
+                    },
+                ),
+            ],
+            ..Theme::default()
+        };
+
+        let filled = theme.with_semantic_defaults();
+        assert_eq!(&filled.scopes[..2], &theme.scopes[..]);
+        let highlighter = crate::highlighting::Highlighter::new(&filled);
+        let foreground = |stack: &str| {
+            let stack = ScopeStack::from_str(stack).unwrap();
+            highlighter.style_for_stack(stack.as_slice()).foreground
+        };
+        let gray = Color {
+            r: 0x66,
+            g: 0x66,
+            b: 0x66,
+            a: 0xff,
+        };
+        assert_eq!(foreground("source keyword.control"), red);
+        assert_eq!(foreground("text.html entity.name.tag"), red);
+        assert_eq!(foreground("source constant.numeric"), blue);
+        assert_eq!(foreground("source comment.line"), gray);
+        assert_eq!(foreground("source punctuation.terminator"), gray);
+        // quotes keep the color of the string
+        assert_eq!(foreground("source string punctuation.definition"), blue);
+        // nothing to take a color from
+        assert_eq!(foreground("source variable.parameter"), Color::BLACK);
+
+        // no colors at all to start from
+        assert_eq!(Theme::default().with_semantic_defaults(), Theme::default());
+    }
+
+    #[test]
+    fn can_blend_themes() {
+        let gray = Color {
+            r: 0x80,
+            g: 0x80,
+            b: 0x80,
+            a: 0xff,
+        };
+        let light = Theme {
+            name: Some("Light".to_owned()),
+            settings: ThemeSettings {
+                foreground: Some(Color::BLACK),
+                background: Some(Color::WHITE),
+                caret: Some(Color::BLACK),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![
+                item(
+                    "comment",
+                    StyleModifier {
+                        foreground: Some(Color::BLACK),
+                        font_style: Some(FontStyle::ITALIC),
+                        ..StyleModifier::default()
+                    },
+                ),
+                item(
+                    "keyword",
+                    StyleModifier {
+                        font_style: Some(FontStyle::BOLD),
+                        ..StyleModifier::default()
+                    },
+                ),
+            ],
+            ..Theme::default()
+        };
+        let dark = Theme {
+            name: Some("Dark".to_owned()),
+            settings: ThemeSettings {
+                foreground: Some(Color::WHITE),
+                background: Some(Color::BLACK),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![item(
+                "comment",
+                StyleModifier {
+                    foreground: Some(Color::WHITE),
+                    background: Some(Color::BLACK),
+                    ..StyleModifier::default()
+                },
+            )],
+            ..Theme::default()
+        };
+
+        assert_eq!(light.lerp(&dark, 0.0), light);
+        assert_eq!(light.lerp(&dark, 1.0), dark);
+
+        let early = light.lerp(&dark, 0.25);
+        assert_eq!(early.name.as_deref(), Some("Light"));
+        assert_eq!(
+            early.settings.foreground,
+            Some(Color::BLACK.lerp(Color::WHITE, 0.25))
+        );
+        // only set in one of the themes
+        assert_eq!(early.settings.caret, Some(Color::BLACK));
+        assert_eq!(early.scopes.len(), 2);
+        let comment = early.scopes[0].style;
+        assert_eq!(
+            comment.foreground,
+            Some(Color::BLACK.lerp(Color::WHITE, 0.25))
+        );
+        assert_eq!(comment.background, None);
+        assert_eq!(comment.font_style, Some(FontStyle::ITALIC));
+
+        let half = light.lerp(&dark, 0.5);
+        assert_eq!(half.name.as_deref(), Some("Dark"));
+        assert_eq!(half.settings.foreground, Some(gray));
+        assert_eq!(half.settings.caret, None);
+        assert_eq!(half.scopes.len(), 1);
+        assert_eq!(half.scopes[0].style.foreground, Some(gray));
+        assert_eq!(half.scopes[0].style.background, Some(Color::BLACK));
+    }
+
+    #[test]
+    fn can_transform_colors() {
+        let gray = Color {
+            r: 0x80,
+            g: 0x80,
+            b: 0x80,
+            a: 0xff,
+        };
+        let theme = Theme {
+            settings: ThemeSettings {
+                foreground: Some(Color::BLACK),
+                background: Some(Color::WHITE),
+                selection: Some(Color::BLACK),
+                ..ThemeSettings::default()
+            },
+            scopes: vec![item(
+                "markup.deleted",
+                StyleModifier {
+                    foreground: Some(Color::BLACK),
+                    background: Some(gray),
+                    underline_color: Some(Color::WHITE),
+                    font_style: Some(FontStyle::UNDERLINE),
+                    ..StyleModifier::default()
+                },
+            )],
+            ..Theme::default()
+        };
+
+        let plain = theme.without_backgrounds();
+        assert_eq!(plain.settings, theme.settings);
+        assert_eq!(plain.scopes[0].style.background, None);
+        assert_eq!(plain.scopes[0].style.foreground, Some(Color::BLACK));
+
+        let mut count = 0;
+        let mapped = theme.map_colors(|c| {
+            count += 1;
+            if c == Color::BLACK {
+                gray
+            } else {
+                c
+            }
+        });
+        assert_eq!(count, 6);
+        assert_eq!(mapped.settings.foreground, Some(gray));
+        assert_eq!(mapped.settings.background, Some(Color::WHITE));
+        assert_eq!(mapped.settings.selection, Some(gray));
+        assert_eq!(mapped.settings.caret, None);
+        assert_eq!(
+            mapped.scopes[0].style,
+            StyleModifier {
+                foreground: Some(gray),
+                ..theme.scopes[0].style
+            }
+        );
+    }
+
+    #[test]
+    fn reports_selectors_classed_css_cant_represent() {
+        let theme = Theme {
+            scopes: vec![
+                item("comment, string - string.quoted", StyleModifier::default()),
+                item("source > comment", StyleModifier::default()),
+                item(
+                    "(string | comment) - (meta > string)",
+                    StyleModifier::default(),
+                ),
+            ],
+            ..Theme::default()
+        };
+        use ClassedCssWarning::*;
+        assert_eq!(
+            theme.validate_for_classed_css(),
+            vec![
+                Exclusion {
+                    rule: 0,
+                    selector: 1
+                },
+                ChildOperator {
+                    rule: 1,
+                    selector: 0
+                },
+                Exclusion {
+                    rule: 2,
+                    selector: 0
+                },
+                ChildOperator {
+                    rule: 2,
+                    selector: 0
+                },
+                Combination {
+                    rule: 2,
+                    selector: 0
+                },
+            ]
+        );
+        assert_eq!(
+            theme.validate_for_classed_css()[0].to_string(),
+            "Selector 1 of rule 0 excludes scopes, which classed CSS can't represent"
+        );
+    }
+
+    #[test]
+    fn can_diff_settings() {
+        let mut old = ThemeSettings {
+            foreground: Some(Color::BLACK),
+            popup_css: Some("a {}".to_owned()),
+            ..ThemeSettings::default()
+        };
+        old.extras.insert("shadowWidth".to_owned(), "4".to_owned());
+        old.extras
+            .insert("invisibles".to_owned(), "#888".to_owned());
+        let mut new = old.clone();
+        assert!(old.diff(&new).is_empty());
+
+        new.foreground = None;
+        new.tags_options = Some(UnderlineOption::Underline);
+        new.extras.insert("shadowWidth".to_owned(), "8".to_owned());
+        new.extras
+            .insert("accentColor".to_owned(), "#fff".to_owned());
+        assert_eq!(
+            old.diff(&new),
+            vec![
+                SettingChange {
+                    key: "foreground".to_owned(),
+                    old: Some(SettingValue::Color(Color::BLACK)),
+                    new: None,
+                },
+                SettingChange {
+                    key: "tagsOptions".to_owned(),
+                    old: None,
+                    new: Some(SettingValue::UnderlineOption(UnderlineOption::Underline)),
+                },
+                SettingChange {
+                    key: "accentColor".to_owned(),
+                    old: None,
+                    new: Some(SettingValue::Extra("#fff".to_owned())),
+                },
+                SettingChange {
+                    key: "shadowWidth".to_owned(),
+                    old: Some(SettingValue::Extra("4".to_owned())),
+                    new: Some(SettingValue::Extra("8".to_owned())),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn digest_is_stable() {
+        let mut theme = Theme {
+            name: Some("Digest".to_owned()),
+            scopes: vec![item(
+                "comment",
+                StyleModifier {
+                    foreground: Some(Color::BLACK),
+                    ..StyleModifier::default()
+                },
+            )],
+            ..Theme::default()
+        };
+        let digest = theme.digest();
+        // the same in every run, so it can't depend on the numbers of the scopes
+        assert_eq!(digest, theme.clone().digest());
+        theme.scopes[0].style.foreground = Some(Color::WHITE);
+        assert_ne!(theme.digest(), digest);
+    }
+
+    #[test]
+    fn can_diff_rules() {
+        let bold = StyleModifier {
+            font_style: Some(FontStyle::BOLD),
+            ..StyleModifier::default()
+        };
+        let old = Theme {
+            scopes: vec![item("comment", bold), item("string", bold)],
+            ..Theme::default()
+        };
+        assert!(old.diff(&old).is_empty());
+
+        let new = Theme {
+            scopes: vec![
+                item("comment", bold),
+                item("keyword", bold),
+                item("constant", bold),
+            ],
+            ..Theme::default()
+        };
+        let diff = old.diff(&new);
+        assert!(diff.settings.is_empty());
+        assert_eq!(
+            diff.rules,
+            vec![
+                ScopeSelectors::from_str("string").unwrap(),
+                ScopeSelectors::from_str("keyword").unwrap(),
+                ScopeSelectors::from_str("constant").unwrap(),
+            ]
+        );
+        let stack = ScopeStack::from_str("source comment").unwrap();
+        assert!(!diff.affects(stack.as_slice()));
+        let stack = ScopeStack::from_str("source string.quoted").unwrap();
+        assert!(diff.affects(stack.as_slice()));
+    }
+}
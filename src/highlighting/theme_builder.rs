@@ -0,0 +1,273 @@
+use super::selector::ScopeSelectors;
+use super::style::*;
+use super::theme::*;
+use crate::parsing::ParseScopeError;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// An error building a [`Theme`] with a [`ThemeBuilder`]
+///
+/// [`Theme`]: struct.Theme.html
+/// [`ThemeBuilder`]: struct.ThemeBuilder.html
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ThemeBuilderError {
+    /// A rule was given a selector that couldn't be parsed
+    #[error("Invalid scope selector {selector:?}: {error}")]
+    InvalidSelector {
+        selector: String,
+        error: ParseScopeError,
+    },
+    /// A rule was given a selector that doesn't name any scope
+    #[error("Empty scope selector")]
+    EmptySelector,
+}
+
+/// Constructs a [`Theme`] in code
+///
+/// Selectors are parsed when a rule is added, and the first one that fails to parse is reported
+/// by [`build`].
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Color, ThemeBuilder};
+///
+/// let green = Color { r: 0, g: 0x80, b: 0, a: 0xFF };
+/// let theme = ThemeBuilder::new()
+///     .name("Example")
+///     .settings(|s| s.background = Some(Color::WHITE))
+///     .scope("comment", |s| s.foreground(green).italic())
+///     .scope("keyword, storage.type", |s| s.bold())
+///     .build()
+///     .unwrap();
+/// assert_eq!(theme.scopes.len(), 2);
+/// ```
+///
+/// [`Theme`]: struct.Theme.html
+/// [`build`]: #method.build
+#[derive(Debug, Default)]
+pub struct ThemeBuilder {
+    theme: Theme,
+    error: Option<ThemeBuilderError>,
+}
+
+/// The style of a single rule added with [`ThemeBuilder::scope`]
+///
+/// [`ThemeBuilder::scope`]: struct.ThemeBuilder.html#method.scope
+#[derive(Debug, Default, Clone)]
+pub struct ThemeItemBuilder {
+    style: StyleModifier,
+    /// Added to the fonts of the theme along with the rule
+    font: Option<Font>,
+    extras: BTreeMap<String, String>,
+}
+
+impl ThemeBuilder {
+    pub fn new() -> ThemeBuilder {
+        ThemeBuilder::default()
+    }
+
+    /// Starts from an existing theme, e.g. to add a few rules to it
+    pub fn from_theme(theme: Theme) -> ThemeBuilder {
+        ThemeBuilder { theme, error: None }
+    }
+
+    /// Sets the name of the theme
+    pub fn name(mut self, name: impl Into<String>) -> ThemeBuilder {
+        self.theme.name = Some(name.into());
+        self
+    }
+
+    /// Sets the author of the theme
+    pub fn author(mut self, author: impl Into<String>) -> ThemeBuilder {
+        self.theme.author = Some(author.into());
+        self
+    }
+
+    /// Changes the editor settings of the theme, like the default foreground and background
+    pub fn settings<F: FnOnce(&mut ThemeSettings)>(mut self, f: F) -> ThemeBuilder {
+        f(&mut self.theme.settings);
+        self
+    }
+
+    /// Adds a rule styling everything matched by `selector`
+    ///
+    /// Like in a `.tmTheme` file, `selector` can contain several selectors separated by commas.
+    pub fn scope<F>(mut self, selector: &str, f: F) -> ThemeBuilder
+    where
+        F: FnOnce(ThemeItemBuilder) -> ThemeItemBuilder,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let scope = match ScopeSelectors::from_str(selector) {
+            Ok(scope) => scope,
+            Err(error) => {
+                self.error = Some(ThemeBuilderError::InvalidSelector {
+                    selector: selector.to_owned(),
+                    error,
+                });
+                return self;
+            }
+        };
+        if scope
+            .selectors
+            .iter()
+            .any(|sel| sel.path.is_empty() && sel.excludes.is_empty() && sel.expression.is_none())
+        {
+            self.error = Some(ThemeBuilderError::EmptySelector);
+            return self;
+        }
+        let mut item = f(ThemeItemBuilder::default());
+        if let Some(font) = item.font {
+            item.style.font_hint = Some(self.theme.add_font_hint(font));
+        }
+        self.theme.scopes.push(ThemeItem {
+            scope,
+            style: item.style,
+            extras: item.extras,
+        });
+        self
+    }
+
+    /// Returns the theme, or the first error encountered while adding rules
+    pub fn build(self) -> Result<Theme, ThemeBuilderError> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.theme),
+        }
+    }
+}
+
+impl ThemeItemBuilder {
+    /// Sets the foreground color
+    pub fn foreground(mut self, color: Color) -> ThemeItemBuilder {
+        self.style.foreground = Some(color);
+        self
+    }
+
+    /// Sets the background color
+    pub fn background(mut self, color: Color) -> ThemeItemBuilder {
+        self.style.background = Some(color);
+        self
+    }
+
+    /// Replaces the font style, e.g. with [`FontStyle::empty()`] to undo the style of a less
+    /// specific rule
+    ///
+    /// [`FontStyle::empty()`]: struct.FontStyle.html#method.empty
+    pub fn font_style(mut self, font_style: FontStyle) -> ThemeItemBuilder {
+        self.style.font_style = Some(font_style);
+        self
+    }
+
+    /// Makes the text bold
+    pub fn bold(self) -> ThemeItemBuilder {
+        self.add_font_style(FontStyle::BOLD)
+    }
+
+    /// Makes the text italic
+    pub fn italic(self) -> ThemeItemBuilder {
+        self.add_font_style(FontStyle::ITALIC)
+    }
+
+    /// Draws a line through the text
+    pub fn strikethrough(self) -> ThemeItemBuilder {
+        self.add_font_style(FontStyle::STRIKETHROUGH)
+    }
+
+    /// Underlines the text with a solid line
+    pub fn underline(self) -> ThemeItemBuilder {
+        self.underline_style(UnderlineStyle::Solid)
+    }
+
+    /// Underlines the text with the given kind of line
+    pub fn underline_style(mut self, underline: UnderlineStyle) -> ThemeItemBuilder {
+        self.style.underline = Some(underline);
+        self.add_font_style(FontStyle::UNDERLINE)
+    }
+
+    /// Sets the color of the underline
+    pub fn underline_color(mut self, color: Color) -> ThemeItemBuilder {
+        self.style.underline_color = Some(color);
+        self
+    }
+
+    /// Asks for the text to be in a different font, which is added to the
+    /// [`Theme::font_hints`]
+    ///
+    /// [`Theme::font_hints`]: struct.Theme.html#structfield.font_hints
+    pub fn font(mut self, font: Font) -> ThemeItemBuilder {
+        self.font = Some(font);
+        self
+    }
+
+    /// Stores a setting syntect doesn't interpret, see [`ThemeItem::extras`]
+    ///
+    /// [`ThemeItem::extras`]: struct.ThemeItem.html#structfield.extras
+    pub fn extra(mut self, key: impl Into<String>, value: impl Into<String>) -> ThemeItemBuilder {
+        self.extras.insert(key.into(), value.into());
+        self
+    }
+
+    fn add_font_style(mut self, font_style: FontStyle) -> ThemeItemBuilder {
+        self.style.font_style = Some(self.style.font_style.unwrap_or_default() | font_style);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Highlighter;
+    use crate::parsing::ScopeStack;
+
+    #[test]
+    fn can_build_theme() {
+        let red = Color {
+            r: 0xff,
+            g: 0,
+            b: 0,
+            a: 0xff,
+        };
+        let theme = ThemeBuilder::new()
+            .name("Test")
+            .settings(|s| s.foreground = Some(Color::BLACK))
+            .scope("comment, string - string.quoted", |s| {
+                s.foreground(red).italic().bold()
+            })
+            .build()
+            .unwrap();
+        assert_eq!(theme.name.as_deref(), Some("Test"));
+        assert_eq!(theme.scopes[0].scope.selectors.len(), 2);
+
+        let highlighter = Highlighter::new(&theme);
+        let stack = ScopeStack::from_str("source comment.line").unwrap();
+        let style = highlighter.style_for_stack(stack.as_slice());
+        assert_eq!(style.foreground, red);
+        assert_eq!(style.font_style, FontStyle::ITALIC | FontStyle::BOLD);
+        let stack = ScopeStack::from_str("source string.quoted").unwrap();
+        let style = highlighter.style_for_stack(stack.as_slice());
+        assert_eq!(style.foreground, Color::BLACK);
+    }
+
+    #[test]
+    fn reports_empty_selectors() {
+        let err = ThemeBuilder::new()
+            .scope("comment, ", |s| s.bold())
+            .scope("string", |s| s.italic())
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, ThemeBuilderError::EmptySelector));
+
+        let err = ThemeBuilder::new()
+            .scope("(comment | string", |s| s.bold())
+            .build()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ThemeBuilderError::InvalidSelector { ref selector, .. } if selector == "(comment | string"
+        ));
+    }
+}
@@ -1,13 +1,16 @@
 // Code based on https://github.com/defuz/sublimate/blob/master/src/core/syntax/theme.rs
 // released under the MIT license by @defuz
 
+use std::collections::HashMap;
 use std::str::FromStr;
 
+use super::css_colors::{named_color, parse_color_function};
 use super::selector::*;
 use super::settings::{ParseSettings, Settings};
 use super::style::*;
 use super::theme::*;
 use crate::parsing::ParseScopeError;
+use serde_json::Map;
 
 use self::ParseThemeError::*;
 
@@ -20,6 +23,8 @@ pub enum ParseThemeError {
     IncorrectFontStyle(String),
     #[error("Incorrect color")]
     IncorrectColor,
+    #[error("Incorrect font family or size")]
+    IncorrectFontHint,
     #[error("Incorrect syntax")]
     IncorrectSyntax,
     #[error("Incorrect settings")]
@@ -38,6 +43,8 @@ pub enum ParseThemeError {
     DuplicateSettings,
     #[error("Scope parse error: {0}")]
     ScopeParse(#[from] ParseScopeError),
+    #[error("Undefined variable: {0}")]
+    UndefinedVariable(String),
 }
 
 impl FromStr for UnderlineOption {
@@ -72,8 +79,10 @@ impl FromStr for FontStyle {
         for i in s.split_whitespace() {
             font_style.insert(match i {
                 "bold" => FontStyle::BOLD,
-                "underline" => FontStyle::UNDERLINE,
+                "underline" | "stippled_underline" | "squiggly_underline" => FontStyle::UNDERLINE,
                 "italic" => FontStyle::ITALIC,
+                "glow" => FontStyle::GLOW,
+                "strikethrough" => FontStyle::STRIKETHROUGH,
                 "normal" | "regular" => FontStyle::empty(),
                 s => return Err(IncorrectFontStyle(s.to_owned())),
             })
@@ -82,6 +91,16 @@ impl FromStr for FontStyle {
     }
 }
 
+/// Picks the kind of underline out of a `fontStyle` string, the last one mentioned wins
+fn parse_underline_style(s: &str) -> Option<UnderlineStyle> {
+    s.split_whitespace().rev().find_map(|i| match i {
+        "underline" => Some(UnderlineStyle::Solid),
+        "stippled_underline" => Some(UnderlineStyle::Stippled),
+        "squiggly_underline" => Some(UnderlineStyle::Squiggly),
+        _ => None,
+    })
+}
+
 impl ParseSettings for FontStyle {
     type Error = ParseThemeError;
 
@@ -96,10 +115,16 @@ impl ParseSettings for FontStyle {
 impl FromStr for Color {
     type Err = ParseThemeError;
 
+    /// Parses a hex color like `#ff0000` or `#ff000080`, a CSS color function like
+    /// `rgb(255, 0, 0)`, `rgba(255, 0, 0, 0.5)`, `hsl(0, 100%, 50%)` or `hsla(...)`, or a CSS
+    /// named color like `red`
     fn from_str(s: &str) -> Result<Color, Self::Err> {
+        let s = s.trim();
         let mut chars = s.chars();
         if chars.next() != Some('#') {
-            return Err(IncorrectColor);
+            return parse_color_function(s)
+                .or_else(|| named_color(s))
+                .ok_or(IncorrectColor);
         }
         let mut d = Vec::new();
         for char in chars {
@@ -129,6 +154,37 @@ impl FromStr for Color {
     }
 }
 
+impl Color {
+    /// Parses a color like [`from_str`] does, after replacing each `var(name)` in it with the
+    /// value of that variable
+    ///
+    /// Variables can refer to other variables, like in the `variables` section of a
+    /// `.sublime-color-scheme` file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::Color;
+    /// use std::collections::HashMap;
+    ///
+    /// let mut variables = HashMap::new();
+    /// variables.insert("red".to_owned(), "rgb(255, 0, 0)".to_owned());
+    /// variables.insert("error".to_owned(), "var(red)".to_owned());
+    /// let color = Color::from_str_with_variables("var(error)", &variables).unwrap();
+    /// assert_eq!(color, Color { r: 255, g: 0, b: 0, a: 255 });
+    /// assert!(Color::from_str_with_variables("var(missing)", &variables).is_err());
+    /// ```
+    ///
+    /// [`from_str`]: #method.from_str
+    pub fn from_str_with_variables<S: std::hash::BuildHasher>(
+        s: &str,
+        variables: &HashMap<String, String, S>,
+    ) -> Result<Color, ParseThemeError> {
+        let lookup = |name: &str| variables.get(name).map(String::as_str);
+        Color::from_str(&resolve_variables(s, &lookup, 0)?)
+    }
+}
+
 impl ParseSettings for Color {
     type Error = ParseThemeError;
 
@@ -144,31 +200,82 @@ impl ParseSettings for StyleModifier {
     type Error = ParseThemeError;
 
     fn parse_settings(settings: Settings) -> Result<StyleModifier, Self::Error> {
-        let mut obj = match settings {
-            Settings::Object(obj) => obj,
-            _ => return Err(ColorShemeScopeIsNotObject),
-        };
-        let font_style = match obj.remove("fontStyle") {
-            Some(Settings::String(value)) => Some(FontStyle::from_str(&value)?),
-            None => None,
-            Some(c) => return Err(IncorrectFontStyle(c.to_string())),
-        };
-        let foreground = match obj.remove("foreground") {
-            Some(Settings::String(value)) => Some(Color::from_str(&value)?),
-            None => None,
-            _ => return Err(IncorrectColor),
-        };
-        let background = match obj.remove("background") {
-            Some(Settings::String(value)) => Some(Color::from_str(&value)?),
-            None => None,
-            _ => return Err(IncorrectColor),
-        };
+        match settings {
+            Settings::Object(mut obj) => parse_style_modifier(&mut obj),
+            _ => Err(ColorShemeScopeIsNotObject),
+        }
+    }
+}
 
-        Ok(StyleModifier {
-            foreground,
-            background,
-            font_style,
-        })
+/// Removes the keys making up a `StyleModifier` from `obj`, leaving any others in place
+fn parse_style_modifier(
+    obj: &mut serde_json::Map<String, Settings>,
+) -> Result<StyleModifier, ParseThemeError> {
+    let (font_style, underline) = match obj.remove("fontStyle") {
+        Some(Settings::String(value)) => (
+            Some(FontStyle::from_str(&value)?),
+            parse_underline_style(&value),
+        ),
+        None => (None, None),
+        Some(c) => return Err(IncorrectFontStyle(c.to_string())),
+    };
+    let foreground = match obj.remove("foreground") {
+        Some(Settings::String(value)) => Some(Color::from_str(&value)?),
+        None => None,
+        _ => return Err(IncorrectColor),
+    };
+    let background = match obj.remove("background") {
+        Some(Settings::String(value)) => Some(Color::from_str(&value)?),
+        None => None,
+        _ => return Err(IncorrectColor),
+    };
+    let underline_color = match obj.remove("underlineColor") {
+        Some(Settings::String(value)) => Some(Color::from_str(&value)?),
+        None => None,
+        _ => return Err(IncorrectColor),
+    };
+
+    Ok(StyleModifier {
+        foreground,
+        background,
+        font_style,
+        underline,
+        underline_color,
+        font_hint: None,
+    })
+}
+
+/// Removes the font keys from the settings of a theme rule, which are kept in the theme
+/// rather than the `StyleModifier`, see [`Font`]
+///
+/// [`Font`]: struct.Font.html
+fn take_font(rule: &mut Settings) -> Result<Option<Font>, ParseThemeError> {
+    let settings = match rule.get_mut("settings") {
+        Some(Settings::Object(settings)) => settings,
+        _ => return Ok(None),
+    };
+    let family = match settings.remove("syntectFontFamily") {
+        Some(Settings::String(value)) => Some(value),
+        None => None,
+        _ => return Err(IncorrectFontHint),
+    };
+    let size = match settings.remove("syntectFontSize") {
+        Some(Settings::Number(value)) => value.as_f64().map(|size| size as f32),
+        Some(Settings::String(value)) => Some(value.parse().map_err(|_| IncorrectFontHint)?),
+        None => None,
+        _ => return Err(IncorrectFontHint),
+    };
+    Ok(match (family, size) {
+        (None, None) => None,
+        (family, size) => Some(Font { family, size }),
+    })
+}
+
+/// Converts a settings value we don't interpret into the string stored in an `extras` map
+fn extra_value(value: Settings) -> String {
+    match value {
+        Settings::String(s) => s,
+        v => v.to_string(),
     }
 }
 
@@ -184,11 +291,21 @@ impl ParseSettings for ThemeItem {
             Some(Settings::String(value)) => ScopeSelectors::from_str(&value)?,
             _ => return Err(ScopeSelectorIsNotString(format!("{:?}", obj))),
         };
-        let style = match obj.remove("settings") {
-            Some(settings) => StyleModifier::parse_settings(settings)?,
+        let mut settings = match obj.remove("settings") {
+            Some(Settings::Object(settings)) => settings,
+            Some(_) => return Err(ColorShemeScopeIsNotObject),
             None => return Err(IncorrectSettings),
         };
-        Ok(ThemeItem { scope, style })
+        let style = parse_style_modifier(&mut settings)?;
+        let extras = settings
+            .into_iter()
+            .map(|(key, value)| (key, extra_value(value)))
+            .collect();
+        Ok(ThemeItem {
+            scope,
+            style,
+            extras,
+        })
     }
 }
 
@@ -259,7 +376,10 @@ impl ParseSettings for ThemeSettings {
                 "activeGuide" => settings.active_guide = Color::parse_settings(value).ok(),
                 "stackGuide" => settings.stack_guide = Color::parse_settings(value).ok(),
                 "shadow" => settings.shadow = Color::parse_settings(value).ok(),
-                _ => (), // E.g. "shadowWidth" and "invisibles" are ignored
+                _ => {
+                    // E.g. "shadowWidth" and "invisibles"
+                    settings.extras.insert(key, extra_value(value));
+                }
             }
         }
         Ok(settings)
@@ -308,18 +428,229 @@ impl ParseSettings for Theme {
                 }
             }
         }
-        let mut scopes = Vec::new();
-        for json in iter {
-            // TODO option to disable best effort parsing and bubble up warnings
-            if let Ok(item) = ThemeItem::parse_settings(json) {
-                scopes.push(item);
-            }
-        }
-        Ok(Theme {
+        let mut theme = Theme {
             name,
             author,
             settings,
-            scopes,
+            ..Theme::default()
+        };
+        for mut json in iter {
+            // TODO option to disable best effort parsing and bubble up warnings
+            let font = match take_font(&mut json) {
+                Ok(font) => font,
+                Err(_) => continue,
+            };
+            if let Ok(mut item) = ThemeItem::parse_settings(json) {
+                item.style.font_hint = font.map(|font| theme.add_font_hint(font));
+                theme.scopes.push(item);
+            }
+        }
+        Ok(theme)
+    }
+}
+
+/// How deeply variables can refer to other variables, to stop on cycles
+const MAX_VARIABLE_DEPTH: usize = 16;
+
+/// Parses the JSON of a `.sublime-color-scheme` file, by converting it to the structure of a
+/// `.tmTheme` file
+///
+/// The `globals` become the theme settings and the `rules` become the scope items, with keys
+/// converted to the camelCase names of `.tmTheme` files, so e.g. `line_highlight` is read like
+/// `lineHighlight` and unknown keys end up in the `extras`. References to the `variables` are
+/// resolved, and like in `.tmTheme` files, invalid values and rules are skipped.
+pub(crate) fn parse_color_scheme(settings: Settings) -> Result<Theme, ParseThemeError> {
+    let mut obj = match settings {
+        Settings::Object(obj) => obj,
+        _ => return Err(IncorrectSyntax),
+    };
+    let variables = match obj.remove("variables") {
+        Some(Settings::Object(variables)) => variables,
+        None => Map::new(),
+        Some(_) => return Err(IncorrectSyntax),
+    };
+    let globals = match obj.remove("globals") {
+        Some(Settings::Object(globals)) => globals,
+        None => Map::new(),
+        Some(_) => return Err(ColorShemeSettingsIsNotObject),
+    };
+    let rules = match obj.remove("rules") {
+        Some(Settings::Array(rules)) => rules,
+        None => Vec::new(),
+        Some(_) => return Err(IncorrectSyntax),
+    };
+
+    let mut global_item = Map::new();
+    global_item.insert(
+        "settings".to_owned(),
+        Settings::Object(convert_color_scheme_settings(globals, &variables)),
+    );
+    let mut items = vec![Settings::Object(global_item)];
+    for rule in rules {
+        let mut rule = match rule {
+            Settings::Object(rule) => rule,
+            _ => continue,
+        };
+        let mut item = Map::new();
+        if let Some(scope) = rule.remove("scope") {
+            item.insert("scope".to_owned(), scope);
+        }
+        rule.remove("name");
+        item.insert(
+            "settings".to_owned(),
+            Settings::Object(convert_color_scheme_settings(rule, &variables)),
+        );
+        items.push(Settings::Object(item));
+    }
+    obj.insert("settings".to_owned(), Settings::Array(items));
+    Theme::parse_settings(Settings::Object(obj))
+}
+
+fn convert_color_scheme_settings(
+    settings: Map<String, Settings>,
+    variables: &Map<String, Settings>,
+) -> Map<String, Settings> {
+    settings
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                // unresolved values are left for the parsing to skip, like invalid colors
+                Settings::String(s) => {
+                    let lookup = |name: &str| variables.get(name).and_then(Settings::as_str);
+                    Settings::String(resolve_variables(&s, &lookup, 0).unwrap_or(s))
+                }
+                v => v,
+            };
+            (snake_to_camel_case(&key), value)
         })
+        .collect()
+}
+
+fn snake_to_camel_case(key: &str) -> String {
+    let mut parts = key.split('_');
+    let mut camel = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let mut chars = part.chars();
+        camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        camel.push_str(chars.as_str());
+    }
+    camel
+}
+
+/// Replaces each `var(name)` in `value` with the value of the variable
+fn resolve_variables<'a, F: Fn(&str) -> Option<&'a str>>(
+    value: &str,
+    lookup: &F,
+    depth: usize,
+) -> Result<String, ParseThemeError> {
+    let mut resolved = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("var(") {
+        let end = match rest[start..].find(')') {
+            Some(end) => start + end,
+            None => break,
+        };
+        resolved.push_str(&rest[..start]);
+        let name = rest[start + 4..end].trim();
+        match lookup(name) {
+            Some(v) if depth < MAX_VARIABLE_DEPTH => {
+                resolved.push_str(&resolve_variables(v, lookup, depth + 1)?)
+            }
+            _ => return Err(UndefinedVariable(name.to_owned())),
+        }
+        rest = &rest[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::settings::read_json;
+
+    #[test]
+    fn can_parse_color_schemes() {
+        let scheme = r##"{
+            "name": "Test",
+            "variables": {
+                "blue": "#0000ff",
+                "accent": "var(blue)",
+                "loop": "var(loop)",
+            },
+            "globals": {
+                "foreground": "#111111",
+                "line_highlight": "var(accent)",
+                "selection": "var(loop)",
+                "shadow_width": "4",
+            },
+            "rules": [
+                {
+                    "name": "Comments",
+                    "scope": "comment, string",
+                    "foreground": "var(blue)",
+                    "font_style": "italic",
+                    "selection_foreground": "#ffffff",
+                },
+                { "scope": "invalid", "foreground": "var(missing)" },
+                { "scope": "keyword", "background": "#00ff00", "syntect_font_size": 1.5 },
+                { "scope": "markup", "syntect_font_family": "serif", "syntect_font_size": "x" },
+            ],
+        }"##;
+        let theme = parse_color_scheme(read_json(scheme.as_bytes()).unwrap()).unwrap();
+        let blue = Color {
+            r: 0,
+            g: 0,
+            b: 0xff,
+            a: 0xff,
+        };
+        assert_eq!(theme.name.as_deref(), Some("Test"));
+        assert_eq!(theme.settings.line_highlight, Some(blue));
+        assert_eq!(theme.settings.selection, None);
+        assert_eq!(theme.settings.extras["shadowWidth"], "4");
+        assert_eq!(theme.scopes.len(), 2);
+        assert_eq!(theme.scopes[0].style.foreground, Some(blue));
+        assert_eq!(theme.scopes[0].style.font_style, Some(FontStyle::ITALIC));
+        assert_eq!(theme.scopes[0].extras["selectionForeground"], "#ffffff");
+        assert!(theme.scopes[1].style.background.is_some());
+        let font = theme.font_hint(theme.scopes[1].style.font_hint.unwrap());
+        assert_eq!(
+            font,
+            Some(&Font {
+                family: None,
+                size: Some(1.5)
+            })
+        );
+        assert_eq!(theme.scopes[0].style.font_hint, None);
+        assert_eq!(theme.font_hints.len(), 1);
+    }
+
+    #[test]
+    fn can_parse_css_colors() {
+        let color = |r, g, b, a| Some(Color { r, g, b, a });
+        assert_eq!(Color::from_str("#ff000080").ok(), color(255, 0, 0, 128));
+        assert_eq!(
+            Color::from_str(" rgba(0, 128, 255, 0.5)").ok(),
+            color(0, 128, 255, 128)
+        );
+        assert_eq!(
+            Color::from_str("hsl(120, 100%, 25%)").ok(),
+            color(0, 128, 0, 255)
+        );
+        assert_eq!(
+            Color::from_str("CornflowerBlue").ok(),
+            color(100, 149, 237, 255)
+        );
+        assert!(matches!(Color::from_str("rgb(1, 2)"), Err(IncorrectColor)));
+        assert!(matches!(Color::from_str("#12345"), Err(IncorrectColor)));
+
+        let variables: HashMap<String, String> = [("a", "var(b)"), ("b", "var(a)")]
+            .iter()
+            .map(|&(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        assert!(matches!(
+            Color::from_str_with_variables("var(a)", &variables),
+            Err(UndefinedVariable(_))
+        ));
     }
 }
@@ -2,16 +2,38 @@ use super::super::LoadingError;
 #[cfg(feature = "plist-load")]
 use super::settings::*;
 use super::theme::Theme;
-use serde_derive::{Deserialize, Serialize};
+use crate::utils::DumpFormat;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
 use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
 pub struct ThemeSet {
     // This is a `BTreeMap` because they're faster than hashmaps on small sets
     pub themes: BTreeMap<String, Theme>,
 }
 
+// Serialized after the dump format, so that dumps of other versions of syntect are rejected
+impl Serialize for ThemeSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (DumpFormat, &self.themes).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (DumpFormat, themes) = Deserialize::deserialize(deserializer)?;
+        Ok(ThemeSet { themes })
+    }
+}
+
 /// A set of themes, includes convenient methods for loading and discovering themes.
 impl ThemeSet {
     /// Creates an empty set
@@ -19,31 +41,46 @@ impl ThemeSet {
         ThemeSet::default()
     }
 
-    /// Returns all the themes found in a folder
+    /// Returns all the themes found in a folder and its subfolders, both `.tmTheme` and
+    /// `.sublime-color-scheme` files
     ///
     /// This is good for enumerating before loading one with [`get_theme`](#method.get_theme)
     pub fn discover_theme_paths<P: AsRef<Path>>(folder: P) -> Result<Vec<PathBuf>, LoadingError> {
         let mut themes = Vec::new();
         for entry in crate::utils::walk_dir(folder) {
             let entry = entry.map_err(LoadingError::WalkDir)?;
-            if entry.path().is_file()
-                && entry
-                    .path()
-                    .extension()
-                    .map_or(false, |e| e.eq_ignore_ascii_case("tmTheme"))
-            {
+            if is_theme_file(entry.path()) {
                 themes.push(entry.path().to_owned());
             }
         }
         Ok(themes)
     }
 
-    /// Loads a theme given a path to a .tmTheme file
+    /// Loads a theme given a path to a .tmTheme or .sublime-color-scheme file
     #[cfg(feature = "plist-load")]
     pub fn get_theme<P: AsRef<Path>>(path: P) -> Result<Theme, LoadingError> {
+        let path = path.as_ref();
         let file = std::fs::File::open(path)?;
         let mut file = std::io::BufReader::new(file);
-        Self::load_from_reader(&mut file)
+        if has_extension(path, COLOR_SCHEME_EXTENSION) {
+            Self::load_color_scheme_from_reader(&mut file)
+        } else {
+            Self::load_from_reader(&mut file)
+        }
+    }
+
+    /// Loads a theme from the JSON of a .sublime-color-scheme file, resolving its variables
+    ///
+    /// Like in Sublime Text, the JSON can have comments and trailing commas. The keys of the
+    /// `globals` and `rules` are stored with the names used in .tmTheme files, e.g. an unknown
+    /// `line_diff_width` global ends up in the [`ThemeSettings::extras`] as `lineDiffWidth`.
+    ///
+    /// [`ThemeSettings::extras`]: struct.ThemeSettings.html#structfield.extras
+    #[cfg(feature = "plist-load")]
+    pub fn load_color_scheme_from_reader<R: std::io::Read>(
+        r: &mut R,
+    ) -> Result<Theme, LoadingError> {
+        Ok(super::theme_load::parse_color_scheme(read_json(r)?)?)
     }
 
     /// Loads a theme given a readable stream
@@ -54,6 +91,15 @@ impl ThemeSet {
         Ok(Theme::parse_settings(read_plist(r)?)?)
     }
 
+    /// Loads a theme from the contents of a .tmTheme file, e.g. one embedded with
+    /// [`include_bytes!`] or fetched where there's no filesystem
+    ///
+    /// [`include_bytes!`]: https://doc.rust-lang.org/std/macro.include_bytes.html
+    #[cfg(feature = "plist-load")]
+    pub fn load_from_bytes(bytes: &[u8]) -> Result<Theme, LoadingError> {
+        Self::load_from_reader(&mut std::io::Cursor::new(bytes))
+    }
+
     /// Generate a `ThemeSet` from all themes in a folder
     #[cfg(feature = "plist-load")]
     pub fn load_from_folder<P: AsRef<Path>>(folder: P) -> Result<ThemeSet, LoadingError> {
@@ -77,12 +123,189 @@ impl ThemeSet {
 
         Ok(())
     }
+
+    /// Generates a `ThemeSet` from the themes in a folder, skipping the ones that can't be loaded
+    ///
+    /// Unlike [`load_from_folder`], one broken theme doesn't make the whole folder fail, which is
+    /// useful for folders of user-installed themes. The themes that couldn't be loaded, or
+    /// folders that couldn't be read, are returned with their errors.
+    ///
+    /// [`load_from_folder`]: #method.load_from_folder
+    #[cfg(feature = "plist-load")]
+    pub fn load_from_folder_lenient<P: AsRef<Path>>(
+        folder: P,
+    ) -> (ThemeSet, Vec<(PathBuf, LoadingError)>) {
+        let mut theme_set = Self::new();
+        let errors = theme_set.add_from_folder_lenient(folder);
+        (theme_set, errors)
+    }
+
+    /// Picks the theme that fits a background best, e.g. to choose a dark or a light theme for
+    /// the terminal automatically like `bat --theme=auto` does
+    ///
+    /// `background_luminance` is how light the background is, from 0.0 for black to 1.0 for
+    /// white, which is the [`Color::luminance`] of its color if that's known, e.g. from the reply
+    /// to an `OSC 11` query. The theme with the closest [`Theme::average_luminance`] wins, and the
+    /// first by name between equally close ones. Returns the name and the theme, or `None` if no
+    /// theme has a background or foreground to tell.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::highlighting::{Color, ThemeSet};
+    ///
+    /// let ts = ThemeSet::load_defaults();
+    /// let (name, _) = ts.select_for_background(Color::WHITE.luminance()).unwrap();
+    /// assert!(name.contains("light") || name == "InspiredGitHub");
+    /// let (name, _) = ts.select_for_background(0.0).unwrap();
+    /// assert!(name.contains("dark"));
+    /// ```
+    ///
+    /// [`Color::luminance`]: struct.Color.html#method.luminance
+    /// [`Theme::average_luminance`]: struct.Theme.html#method.average_luminance
+    pub fn select_for_background(&self, background_luminance: f32) -> Option<(&str, &Theme)> {
+        let mut best: Option<(&str, &Theme, f32)> = None;
+        for (name, theme) in &self.themes {
+            let distance = match theme.average_luminance() {
+                Some(luminance) => (luminance - background_luminance).abs(),
+                None => continue,
+            };
+            if best.map_or(true, |(_, _, best)| distance < best) {
+                best = Some((name, theme, distance));
+            }
+        }
+        best.map(|(name, theme, _)| (name, theme))
+    }
+
+    /// Loads the themes in the folder into this `ThemeSet`, skipping the ones that can't be
+    /// loaded and returning them with their errors, see [`load_from_folder_lenient`]
+    ///
+    /// [`load_from_folder_lenient`]: #method.load_from_folder_lenient
+    #[cfg(feature = "plist-load")]
+    pub fn add_from_folder_lenient<P: AsRef<Path>>(
+        &mut self,
+        folder: P,
+    ) -> Vec<(PathBuf, LoadingError)> {
+        let folder = folder.as_ref();
+        let mut errors = Vec::new();
+        for entry in crate::utils::walk_dir(folder) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().unwrap_or(folder).to_owned();
+                    errors.push((path, LoadingError::WalkDir(e)));
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if !is_theme_file(path) {
+                continue;
+            }
+            let basename = match path.file_stem().and_then(|x| x.to_str()) {
+                Some(basename) => basename,
+                None => {
+                    errors.push((path.to_owned(), LoadingError::BadPath));
+                    continue;
+                }
+            };
+            match Self::get_theme(path) {
+                Ok(theme) => {
+                    self.themes.insert(basename.to_owned(), theme);
+                }
+                Err(e) => errors.push((path.to_owned(), e)),
+            }
+        }
+        errors
+    }
+}
+
+const COLOR_SCHEME_EXTENSION: &str = "sublime-color-scheme";
+
+/// The themes of [`ThemeSet::load_defaults`], to look them up without spelling out their keys
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{DefaultThemeName, ThemeSet};
+///
+/// let ts = ThemeSet::load_defaults();
+/// let theme = &ts.themes[DefaultThemeName::Base16OceanDark.as_str()];
+/// assert_eq!(theme.name.as_deref(), Some("Base16 Ocean Dark"));
+/// ```
+///
+/// [`ThemeSet::load_defaults`]: struct.ThemeSet.html#method.load_defaults
+#[cfg(feature = "default-themes")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DefaultThemeName {
+    /// `base16-ocean.dark`
+    Base16OceanDark,
+    /// `base16-eighties.dark`
+    Base16EightiesDark,
+    /// `base16-mocha.dark`
+    Base16MochaDark,
+    /// `base16-ocean.light`
+    Base16OceanLight,
+    /// `InspiredGitHub`
+    InspiredGitHub,
+    /// `Solarized (dark)`
+    SolarizedDark,
+    /// `Solarized (light)`
+    SolarizedLight,
+}
+
+#[cfg(feature = "default-themes")]
+impl DefaultThemeName {
+    /// All the default themes
+    pub const ALL: [DefaultThemeName; 7] = [
+        DefaultThemeName::Base16OceanDark,
+        DefaultThemeName::Base16EightiesDark,
+        DefaultThemeName::Base16MochaDark,
+        DefaultThemeName::Base16OceanLight,
+        DefaultThemeName::InspiredGitHub,
+        DefaultThemeName::SolarizedDark,
+        DefaultThemeName::SolarizedLight,
+    ];
+
+    /// The key of the theme in the `themes` of [`ThemeSet::load_defaults`]
+    ///
+    /// [`ThemeSet::load_defaults`]: struct.ThemeSet.html#method.load_defaults
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DefaultThemeName::Base16OceanDark => "base16-ocean.dark",
+            DefaultThemeName::Base16EightiesDark => "base16-eighties.dark",
+            DefaultThemeName::Base16MochaDark => "base16-mocha.dark",
+            DefaultThemeName::Base16OceanLight => "base16-ocean.light",
+            DefaultThemeName::InspiredGitHub => "InspiredGitHub",
+            DefaultThemeName::SolarizedDark => "Solarized (dark)",
+            DefaultThemeName::SolarizedLight => "Solarized (light)",
+        }
+    }
+}
+
+#[cfg(feature = "default-themes")]
+impl std::fmt::Display for DefaultThemeName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    matches!(path.extension(), Some(e) if e.eq_ignore_ascii_case(extension))
+}
+
+fn is_theme_file(path: &Path) -> bool {
+    (has_extension(path, "tmTheme") || has_extension(path, COLOR_SCHEME_EXTENSION))
+        && path.is_file()
 }
 
 #[cfg(test)]
 mod tests {
     use crate::highlighting::{Color, ThemeSet};
     #[cfg(feature = "plist-load")]
+    use crate::utils::TempDir;
+    #[cfg(feature = "plist-load")]
+    use crate::LoadingError;
+    #[cfg(feature = "plist-load")]
     #[test]
     fn can_parse_common_themes() {
         let themes = ThemeSet::load_from_folder("testdata").unwrap();
@@ -131,4 +354,220 @@ mod tests {
         );
         // unreachable!();
     }
+
+    #[cfg(feature = "plist-load")]
+    #[test]
+    fn lenient_loading_skips_broken_themes() {
+        let dir = TempDir::new("themes");
+        dir.write(
+            "Good.tmTheme",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict/>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#,
+        );
+        let broken = dir.write("Broken.tmTheme", "<plist>");
+
+        assert!(ThemeSet::load_from_folder(dir.path()).is_err());
+        let (themes, errors) = ThemeSet::load_from_folder_lenient(dir.path());
+        assert_eq!(themes.themes.keys().collect::<Vec<_>>(), ["Good"]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, broken);
+
+        let path = dir.path().to_owned();
+        drop(dir);
+        let (themes, errors) = ThemeSet::load_from_folder_lenient(&path);
+        assert!(themes.themes.is_empty());
+        assert!(matches!(errors[..], [(_, LoadingError::WalkDir(_))]));
+    }
+
+    #[cfg(feature = "plist-load")]
+    #[test]
+    fn finds_color_schemes_in_subfolders() {
+        let dir = TempDir::new("schemes");
+        let nested = dir.write(
+            "Package/schemes/Nested.sublime-color-scheme",
+            r##"{
+    // Sublime allows comments
+    "name": "Nested",
+    "variables": { "fg": "#123456" },
+    "globals": { "foreground": "var(fg)" },
+    "rules": [],
+}"##,
+        );
+        dir.write("notes.txt", "not a theme");
+
+        let paths = ThemeSet::discover_theme_paths(dir.path()).unwrap();
+        let themes = ThemeSet::load_from_folder(dir.path());
+        assert_eq!(paths, [nested]);
+        let theme = &themes.unwrap().themes["Nested"];
+        assert_eq!(theme.name.as_deref(), Some("Nested"));
+        assert_eq!(
+            theme.settings.foreground,
+            Some(Color {
+                r: 0x12,
+                g: 0x34,
+                b: 0x56,
+                a: 0xff,
+            })
+        );
+    }
+
+    #[cfg(feature = "plist-load")]
+    #[test]
+    fn can_parse_underline_styles() {
+        use crate::highlighting::{FontStyle, UnderlineStyle};
+
+        let theme = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict/>
+        </dict>
+        <dict>
+            <key>scope</key>
+            <string>invalid</string>
+            <key>settings</key>
+            <dict>
+                <key>fontStyle</key>
+                <string>bold squiggly_underline glow strikethrough</string>
+                <key>underlineColor</key>
+                <string>#ff0000</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>"#;
+        let theme = ThemeSet::load_from_reader(&mut std::io::Cursor::new(theme)).unwrap();
+        let style = theme.scopes[0].style;
+        assert_eq!(
+            style.font_style,
+            Some(
+                FontStyle::BOLD | FontStyle::UNDERLINE | FontStyle::GLOW | FontStyle::STRIKETHROUGH
+            )
+        );
+        assert_eq!(style.underline, Some(UnderlineStyle::Squiggly));
+        assert_eq!(
+            style.underline_color,
+            Some(Color {
+                r: 0xff,
+                g: 0,
+                b: 0,
+                a: 0xff,
+            })
+        );
+    }
+
+    #[cfg(feature = "plist-load")]
+    #[test]
+    fn keeps_unknown_settings_as_extras() {
+        let theme = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>selectionForeground</key>
+                <string>#ffffff</string>
+                <key>shadowWidth</key>
+                <string>4</string>
+                <key>invisibles</key>
+                <integer>2</integer>
+            </dict>
+        </dict>
+        <dict>
+            <key>scope</key>
+            <string>string</string>
+            <key>settings</key>
+            <dict>
+                <key>foreground</key>
+                <string>#00ff00</string>
+                <key>selectionForeground</key>
+                <string>#000000</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>"#;
+        let theme = ThemeSet::load_from_reader(&mut std::io::Cursor::new(theme)).unwrap();
+        assert_eq!(theme.settings.selection_foreground, Some(Color::WHITE));
+        assert_eq!(theme.settings.extras["shadowWidth"], "4");
+        assert_eq!(theme.settings.extras["invisibles"], "2");
+        assert!(!theme.settings.extras.contains_key("selectionForeground"));
+
+        let item = &theme.scopes[0];
+        assert!(item.style.foreground.is_some());
+        assert_eq!(item.extras["selectionForeground"], "#000000");
+        assert!(!item.extras.contains_key("foreground"));
+    }
+
+    #[cfg(feature = "default-themes")]
+    #[test]
+    fn names_all_the_default_themes() {
+        use crate::highlighting::DefaultThemeName;
+        let ts = ThemeSet::load_defaults();
+        let mut names: Vec<&str> = DefaultThemeName::ALL.iter().map(|n| n.as_str()).collect();
+        names.sort();
+        let keys: Vec<&str> = ts.themes.keys().map(|key| &**key).collect();
+        assert_eq!(names, keys);
+        let theme = ThemeSet::get_default(DefaultThemeName::SolarizedLight);
+        assert_eq!(theme, ts.themes["Solarized (light)"]);
+    }
+
+    #[test]
+    fn selects_the_theme_closest_to_the_background() {
+        use crate::highlighting::{Theme, ThemeBuilder};
+        let theme = |background: Option<Color>, foreground: Option<Color>| {
+            ThemeBuilder::new()
+                .settings(|s| {
+                    s.background = background;
+                    s.foreground = foreground;
+                })
+                .build()
+                .unwrap()
+        };
+        let gray = Color {
+            r: 0x80,
+            g: 0x80,
+            b: 0x80,
+            a: 0xff,
+        };
+        let mut ts = ThemeSet::new();
+        ts.themes
+            .insert("black".to_owned(), theme(Some(Color::BLACK), None));
+        ts.themes
+            .insert("gray".to_owned(), theme(Some(gray), Some(Color::BLACK)));
+        // no background, but light text
+        ts.themes
+            .insert("light text".to_owned(), theme(None, Some(Color::WHITE)));
+        ts.themes.insert("unknown".to_owned(), Theme::default());
+        assert_eq!(ts.themes["light text"].average_luminance(), Some(0.0));
+        assert_eq!(ts.themes["unknown"].average_luminance(), None);
+
+        let select = |luminance| ts.select_for_background(luminance).map(|(name, _)| name);
+        // equally close, so the first by name
+        assert_eq!(select(0.0), Some("black"));
+        assert_eq!(select(0.1), Some("black"));
+        assert_eq!(select(Color::WHITE.luminance()), Some("gray"));
+        assert_eq!(
+            ThemeSet::new()
+                .select_for_background(0.5)
+                .map(|(name, _)| name),
+            None
+        );
+    }
 }
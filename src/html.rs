@@ -1,17 +1,128 @@
 //! Rendering highlighted code as HTML+CSS
 use crate::easy::{HighlightFile, HighlightLines};
 use crate::escape::Escape;
-use crate::highlighting::{Color, FontStyle, Style, Theme};
+use crate::highlighting::{Color, Font, FontStyle, Style, Theme, UnderlineStyle};
 use crate::parsing::{
-    BasicScopeStackOp, ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet,
-    SCOPE_REPO,
+    with_repo_for, BasicScopeStackOp, ParseState, Scope, ScopeRepository, ScopeStack, ScopeStackOp,
+    SyntaxReference, SyntaxSet,
 };
-use crate::util::LinesWithEndings;
+use crate::util::{blend_fg_color, LinesWithEndings};
 use crate::Error;
+use std::collections::BTreeSet;
 use std::fmt::Write;
 
-use std::io::BufRead;
+use std::io::{self, BufRead};
 use std::path::Path;
+#[cfg(feature = "global-statics")]
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Lines to emphasize or leave out of generated HTML
+///
+/// Line numbers start at 1. Hidden lines are still highlighted, so they can e.g. set up context
+/// like mdBook's `# ` lines in Rust examples, they just don't show up in the output.
+///
+/// # Example
+///
+/// ```
+/// use syntect::html::LineOptions;
+///
+/// let options = LineOptions::new().emphasize(2..=3).hide([1]);
+/// assert!(options.emphasized.contains(&3));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineOptions {
+    /// Lines drawn with the theme's `line_highlight` background
+    pub emphasized: BTreeSet<usize>,
+    /// Lines left out of the output
+    pub hidden: BTreeSet<usize>,
+}
+
+impl LineOptions {
+    pub fn new() -> LineOptions {
+        LineOptions::default()
+    }
+
+    /// Adds lines to emphasize
+    pub fn emphasize<I: IntoIterator<Item = usize>>(mut self, lines: I) -> LineOptions {
+        self.emphasized.extend(lines);
+        self
+    }
+
+    /// Adds lines to leave out
+    pub fn hide<I: IntoIterator<Item = usize>>(mut self, lines: I) -> LineOptions {
+        self.hidden.extend(lines);
+        self
+    }
+}
+
+/// Defenses for highlighting untrusted code, see [`highlighted_html_for_string_sanitized`]
+///
+/// The default strips control characters and isolates bidirectional formatting characters,
+/// without limiting the size of the output.
+///
+/// # Example
+///
+/// ```
+/// use syntect::html::SanitizeOptions;
+///
+/// let options = SanitizeOptions::new().max_output_size(1 << 20);
+/// assert!(options.isolate_bidi);
+/// ```
+///
+/// [`highlighted_html_for_string_sanitized`]: fn.highlighted_html_for_string_sanitized.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizeOptions {
+    /// The most bytes of HTML to generate before failing with [`Error::HtmlTooLarge`], since
+    /// highlighting can make the code many times larger
+    ///
+    /// [`Error::HtmlTooLarge`]: ../enum.Error.html#variant.HtmlTooLarge
+    pub max_output_size: Option<usize>,
+    /// Removes control characters other than tabs and line breaks from the code
+    pub strip_control_characters: bool,
+    /// Wraps tokens with bidirectional embedding, override or isolate characters in `<bdi>`
+    /// elements, so they can't reorder the code around them like in [Trojan Source] attacks
+    ///
+    /// [Trojan Source]: https://trojansource.codes/
+    pub isolate_bidi: bool,
+}
+
+impl SanitizeOptions {
+    pub fn new() -> SanitizeOptions {
+        SanitizeOptions::default()
+    }
+
+    /// Sets the most bytes of HTML to generate
+    pub fn max_output_size(mut self, max: usize) -> SanitizeOptions {
+        self.max_output_size = Some(max);
+        self
+    }
+}
+
+impl Default for SanitizeOptions {
+    fn default() -> SanitizeOptions {
+        SanitizeOptions {
+            max_output_size: None,
+            strip_control_characters: true,
+            isolate_bidi: true,
+        }
+    }
+}
+
+/// The options of the functions that don't sanitize
+const NO_SANITIZING: SanitizeOptions = SanitizeOptions {
+    max_output_size: None,
+    strip_control_characters: false,
+    isolate_bidi: false,
+};
+
+fn is_stripped_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r')
+}
+
+/// Embeddings, overrides and isolates, which affect the text after them
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
 
 /// Output HTML for a line of code with `<span>` elements using class names
 ///
@@ -56,6 +167,8 @@ pub struct ClassedHTMLGenerator<'a> {
     scope_stack: ScopeStack,
     html: String,
     style: ClassStyle,
+    line_options: LineOptions,
+    line_number: usize,
 }
 
 impl<'a> ClassedHTMLGenerator<'a> {
@@ -83,27 +196,88 @@ impl<'a> ClassedHTMLGenerator<'a> {
             scope_stack,
             html,
             style,
+            line_options: LineOptions::default(),
+            line_number: 0,
         }
     }
 
+    /// Emphasizes or hides the lines given in `options`, see [`LineOptions`]
+    ///
+    /// Emphasized lines are wrapped in a `<span>` with the `line-highlight` class (prefixed like
+    /// the other classes), which [`css_for_theme_with_class_style`] styles with the theme's
+    /// `line_highlight` color. For themes without one the CSS has no rule for it, so you can
+    /// style it yourself.
+    ///
+    /// [`LineOptions`]: struct.LineOptions.html
+    /// [`css_for_theme_with_class_style`]: fn.css_for_theme_with_class_style.html
+    pub fn with_line_options(mut self, options: LineOptions) -> ClassedHTMLGenerator<'a> {
+        self.line_options = options;
+        self
+    }
+
     /// Parse the line of code and update the internal HTML buffer with tagged HTML
     ///
     /// *Note:* This function requires `line` to include a newline at the end and
     /// also use of the `load_defaults_newlines` version of the syntaxes.
     pub fn parse_html_for_line_which_includes_newline(&mut self, line: &str) -> Result<(), Error> {
+        self.line_number += 1;
+        let hidden = self.line_options.hidden.contains(&self.line_number);
+        let emphasized = !hidden && self.line_options.emphasized.contains(&self.line_number);
+        if emphasized {
+            // spans can't cross the edges of the line's block, so the open ones are reopened in it
+            self.close_spans();
+            self.html.push_str("<span class=\"");
+            push_class(&mut self.html, "line-highlight", self.style);
+            self.html.push_str("\">");
+        }
+        if !hidden && self.open_spans == 0 {
+            // the spans may have been closed by a hidden or emphasized line
+            self.open_stack_spans();
+        }
+
         let parsed_line = self.parse_state.parse_line(line, self.syntax_set)?;
-        let (formatted_line, delta) = line_tokens_to_classed_spans(
+        let (formatted_line, delta) = line_tokens_to_classed_spans_impl(
             line,
             parsed_line.as_slice(),
             self.style,
             &mut self.scope_stack,
+            Some(self.syntax_set.scope_repo()),
         )?;
+
+        if hidden {
+            // the line is dropped, and the spans for the scopes it changed are opened by the next
+            // line shown
+            self.close_spans();
+            return Ok(());
+        }
         self.open_spans += delta;
         self.html.push_str(formatted_line.as_str());
+        if emphasized {
+            self.close_spans();
+            self.html.push_str("</span>");
+        }
 
         Ok(())
     }
 
+    fn close_spans(&mut self) {
+        for _ in 0..self.open_spans {
+            self.html.push_str("</span>");
+        }
+        self.open_spans = 0;
+    }
+
+    /// Opens a span for each scope on the stack, like the ones closed by `close_spans`
+    fn open_stack_spans(&mut self) {
+        let repo = Some(self.syntax_set.scope_repo());
+        for &scope in self.scope_stack.as_slice() {
+            self.html.push_str("<span class=\"");
+            scope_to_classes(&mut self.html, scope, self.style, repo);
+            self.html.push_str("\">");
+            self.open_spans += 1;
+        }
+    }
+
     /// Parse the line of code and update the internal HTML buffer with tagged HTML
     ///
     /// ## Warning
@@ -124,6 +298,19 @@ impl<'a> ClassedHTMLGenerator<'a> {
         self.html.push('\n');
     }
 
+    /// Writes the HTML generated so far to `w` and clears it, so long files can be written as
+    /// they're highlighted instead of being kept in memory
+    ///
+    /// Spans that are still open are closed by later HTML, so the last part has to be written
+    /// after [`finalize()`].
+    ///
+    /// [`finalize()`]: #method.finalize
+    pub fn write_html_to<W: io::Write>(&mut self, w: &mut W) -> Result<(), Error> {
+        w.write_all(self.html.as_bytes())?;
+        self.html.clear();
+        Ok(())
+    }
+
     /// Close all open `<span>` tags and return the finished HTML string
     pub fn finalize(mut self) -> String {
         for _ in 0..self.open_spans {
@@ -142,8 +329,74 @@ pub fn css_for_theme(theme: &Theme) -> String {
         .expect("Please use `css_for_theme_with_class_style` instead.")
 }
 
+/// Like [`css_for_theme_with_class_style`], but writes the CSS to `w`
+///
+/// [`css_for_theme_with_class_style`]: fn.css_for_theme_with_class_style.html
+pub fn css_for_theme_with_class_style_to_writer<W: io::Write>(
+    theme: &Theme,
+    style: ClassStyle,
+    w: &mut W,
+) -> Result<(), Error> {
+    let css = css_for_theme_with_class_style(theme, style)?;
+    w.write_all(css.as_bytes())?;
+    Ok(())
+}
+
+/// The stylesheets generated most recently, keyed on the digest of the theme, oldest first
+#[cfg(feature = "global-statics")]
+static CSS_CACHE: Mutex<Vec<((u64, ClassStyle), String)>> = Mutex::new(Vec::new());
+#[cfg(feature = "global-statics")]
+const CSS_CACHE_SIZE: usize = 16;
+
 /// Create a complete CSS for a given theme. Can be used inline, or written to a CSS file.
+///
+/// With the `global-statics` feature, the stylesheets of the last few themes are kept, keyed on
+/// [`Theme::digest`], so calling this for every page or request doesn't generate the same CSS
+/// again.
+///
+/// [`Theme::digest`]: ../highlighting/struct.Theme.html#method.digest
 pub fn css_for_theme_with_class_style(theme: &Theme, style: ClassStyle) -> Result<String, Error> {
+    let key = (theme.digest(), style);
+    if let Some(css) = cached_css(key) {
+        return Ok(css);
+    }
+    let css = generate_css_for_theme(theme, style)?;
+    cache_css(key, &css);
+    Ok(css)
+}
+
+#[cfg(feature = "global-statics")]
+fn lock_css_cache() -> MutexGuard<'static, Vec<((u64, ClassStyle), String)>> {
+    CSS_CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(feature = "global-statics")]
+fn cached_css(key: (u64, ClassStyle)) -> Option<String> {
+    let cache = lock_css_cache();
+    cache
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, css)| css.clone())
+}
+
+#[cfg(not(feature = "global-statics"))]
+fn cached_css(_key: (u64, ClassStyle)) -> Option<String> {
+    None
+}
+
+#[cfg(feature = "global-statics")]
+fn cache_css(key: (u64, ClassStyle), css: &str) {
+    let mut cache = lock_css_cache();
+    if cache.len() >= CSS_CACHE_SIZE {
+        cache.remove(0);
+    }
+    cache.push((key, css.to_owned()));
+}
+
+#[cfg(not(feature = "global-statics"))]
+fn cache_css(_key: (u64, ClassStyle), _css: &str) {}
+
+fn generate_css_for_theme(theme: &Theme, style: ClassStyle) -> Result<String, Error> {
     let mut css = String::new();
 
     css.push_str("/*\n");
@@ -176,6 +429,16 @@ pub fn css_for_theme_with_class_style(theme: &Theme, style: ClassStyle) -> Resul
     }
     css.push_str("}\n\n");
 
+    if let Some(c) = theme.settings.line_highlight {
+        let c = blend_fg_color(c, theme.settings.background.unwrap_or(Color::WHITE));
+        css.push('.');
+        push_class(&mut css, "line-highlight", style);
+        css.push_str(&format!(
+            " {{\n display: block;\n background-color: #{:02x}{:02x}{:02x};\n}}\n\n",
+            c.r, c.g, c.b
+        ));
+    }
+
     for i in &theme.scopes {
         for scope_selector in &i.scope.selectors {
             let scopes = scope_selector.extract_scopes();
@@ -202,8 +465,19 @@ pub fn css_for_theme_with_class_style(theme: &Theme, style: ClassStyle) -> Resul
         }
 
         if let Some(fs) = i.style.font_style {
+            if let Some(decoration_line) = text_decoration_line(fs) {
+                css.push_str(&format!("text-decoration: {};\n", decoration_line));
+            }
             if fs.contains(FontStyle::UNDERLINE) {
-                css.push_str("text-decoration: underline;\n");
+                if let Some(decoration_style) = text_decoration_style(i.style.underline) {
+                    css.push_str(&format!("text-decoration-style: {};\n", decoration_style));
+                }
+                if let Some(c) = i.style.underline_color {
+                    css.push_str(&format!(
+                        "text-decoration-color: #{:02x}{:02x}{:02x};\n",
+                        c.r, c.g, c.b
+                    ));
+                }
             }
             if fs.contains(FontStyle::BOLD) {
                 css.push_str("font-weight: bold;\n");
@@ -212,6 +486,15 @@ pub fn css_for_theme_with_class_style(theme: &Theme, style: ClassStyle) -> Resul
                 css.push_str("font-style: italic;\n");
             }
         }
+
+        if let Some(font) = i.style.font_hint.and_then(|hint| theme.font_hint(hint)) {
+            if let Some(family) = &font.family {
+                css.push_str(&format!("font-family: {};\n", family));
+            }
+            if let Some(size) = font.size {
+                css.push_str(&format!("font-size: {}em;\n", size));
+            }
+        }
         css.push_str("}\n");
     }
 
@@ -240,37 +523,81 @@ pub enum ClassStyle {
     SpacedPrefixed { prefix: &'static str },
 }
 
-fn scope_to_classes(s: &mut String, scope: Scope, style: ClassStyle) {
-    let repo = SCOPE_REPO.lock().unwrap();
+/// Uses `repo` for looking up the atoms if it has all of them, otherwise this thread's copy of the
+/// global repo.
+fn scope_to_classes(
+    s: &mut String,
+    scope: Scope,
+    style: ClassStyle,
+    repo: Option<&ScopeRepository>,
+) {
+    match repo {
+        Some(repo) if repo.contains(scope) => scope_to_classes_with_repo(s, scope, style, repo),
+        _ => with_repo_for(scope, |repo| {
+            scope_to_classes_with_repo(s, scope, style, repo)
+        }),
+    }
+}
+
+fn scope_to_classes_with_repo(
+    s: &mut String,
+    scope: Scope,
+    style: ClassStyle,
+    repo: &ScopeRepository,
+) {
     for i in 0..(scope.len()) {
         let atom = scope.atom_at(i as usize);
-        let atom_s = repo.atom_str(atom);
-        if i != 0 {
-            s.push(' ')
-        }
-        match style {
-            ClassStyle::Spaced => {}
-            ClassStyle::SpacedPrefixed { prefix } => {
-                s.push_str(prefix);
+        // overflow atoms of long scopes contain dots and become multiple classes
+        for (j, atom_s) in repo.atom_str(atom).split('.').enumerate() {
+            if i != 0 || j != 0 {
+                s.push(' ')
             }
+            match style {
+                ClassStyle::Spaced => {}
+                ClassStyle::SpacedPrefixed { prefix } => {
+                    s.push_str(prefix);
+                }
+            }
+            s.push_str(atom_s);
+        }
+    }
+}
+
+/// Pushes a class syntect uses itself, like `line-highlight`, with the prefix of the class style
+fn push_class(s: &mut String, class: &str, style: ClassStyle) {
+    match style {
+        ClassStyle::Spaced => {}
+        ClassStyle::SpacedPrefixed { prefix } => {
+            s.push_str(prefix);
         }
-        s.push_str(atom_s);
     }
+    s.push_str(class);
 }
 
 fn scope_to_selector(s: &mut String, scope: Scope, style: ClassStyle) {
-    let repo = SCOPE_REPO.lock().unwrap();
+    with_repo_for(scope, |repo| {
+        scope_to_selector_with_repo(s, scope, style, repo)
+    })
+}
+
+fn scope_to_selector_with_repo(
+    s: &mut String,
+    scope: Scope,
+    style: ClassStyle,
+    repo: &ScopeRepository,
+) {
     for i in 0..(scope.len()) {
         let atom = scope.atom_at(i as usize);
-        let atom_s = repo.atom_str(atom);
-        s.push('.');
-        match style {
-            ClassStyle::Spaced => {}
-            ClassStyle::SpacedPrefixed { prefix } => {
-                s.push_str(prefix);
+        for atom_s in repo.atom_str(atom).split('.') {
+            s.push('.');
+            match style {
+                ClassStyle::Spaced => {}
+                ClassStyle::SpacedPrefixed { prefix } => {
+                    s.push_str(prefix);
+                }
             }
+            s.push_str(atom_s);
         }
-        s.push_str(atom_s);
     }
 }
 
@@ -286,19 +613,212 @@ pub fn highlighted_html_for_string(
     syntax: &SyntaxReference,
     theme: &Theme,
 ) -> Result<String, Error> {
+    highlighted_html_for_string_with_lines(s, ss, syntax, theme, &LineOptions::default())
+}
+
+/// Like `highlighted_html_for_string`, but emphasizes or hides the lines given in `options`.
+///
+/// Emphasized lines are wrapped in a `display:block` `<span>` with the theme's `line_highlight`
+/// color as background, or a faint tint of the foreground color if the theme doesn't have one.
+///
+/// # Example
+///
+/// ```
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::html::{highlighted_html_for_string_with_lines, LineOptions};
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let code = "fn main() {\n    let x = 5;\n}\n";
+/// let options = LineOptions::new().emphasize([2]);
+/// let html = highlighted_html_for_string_with_lines(code, &ss, syntax, &ts.themes["base16-ocean.dark"], &options).unwrap();
+/// assert!(html.contains("display:block"));
+/// ```
+pub fn highlighted_html_for_string_with_lines(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    options: &LineOptions,
+) -> Result<String, Error> {
+    let mut output = String::new();
+    highlighted_html_for_string_with_lines_impl(
+        s,
+        ss,
+        syntax,
+        theme,
+        options,
+        &NO_SANITIZING,
+        |html| {
+            output.push_str(html);
+            Ok(())
+        },
+    )?;
+    Ok(output)
+}
+
+/// Like [`highlighted_html_for_string`], but defends against untrusted code as set in `options`
+///
+/// # Example
+///
+/// ```
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::html::{highlighted_html_for_string_sanitized, SanitizeOptions};
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let code = "let s = \"\u{202E}evil\";\u{7}\n";
+/// let theme = &ts.themes["base16-ocean.dark"];
+/// let html = highlighted_html_for_string_sanitized(code, &ss, syntax, theme, &SanitizeOptions::new()).unwrap();
+/// assert!(html.contains("<bdi>\u{202E}evil</bdi>"));
+/// assert!(!html.contains('\u{7}'));
+///
+/// let options = SanitizeOptions::new().max_output_size(100);
+/// assert!(highlighted_html_for_string_sanitized(code, &ss, syntax, theme, &options).is_err());
+/// ```
+///
+/// [`highlighted_html_for_string`]: fn.highlighted_html_for_string.html
+pub fn highlighted_html_for_string_sanitized(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    options: &SanitizeOptions,
+) -> Result<String, Error> {
+    let mut output = String::new();
+    let lines = LineOptions::default();
+    highlighted_html_for_string_with_lines_impl(s, ss, syntax, theme, &lines, options, |html| {
+        output.push_str(html);
+        Ok(())
+    })?;
+    Ok(output)
+}
+
+/// Like [`highlighted_html_for_string_sanitized`], but writes the HTML to `w` a line at a time
+///
+/// When the output gets too large, the HTML written so far is left in `w`.
+///
+/// [`highlighted_html_for_string_sanitized`]: fn.highlighted_html_for_string_sanitized.html
+pub fn highlighted_html_for_string_sanitized_to_writer<W: io::Write>(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    options: &SanitizeOptions,
+    w: &mut W,
+) -> Result<(), Error> {
+    let lines = LineOptions::default();
+    highlighted_html_for_string_with_lines_impl(s, ss, syntax, theme, &lines, options, |html| {
+        Ok(w.write_all(html.as_bytes())?)
+    })
+}
+
+/// Like [`highlighted_html_for_string`], but writes the HTML to `w` a line at a time
+///
+/// [`highlighted_html_for_string`]: fn.highlighted_html_for_string.html
+pub fn highlighted_html_for_string_to_writer<W: io::Write>(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    w: &mut W,
+) -> Result<(), Error> {
+    highlighted_html_for_string_with_lines_to_writer(
+        s,
+        ss,
+        syntax,
+        theme,
+        &LineOptions::default(),
+        w,
+    )
+}
+
+/// Like [`highlighted_html_for_string_with_lines`], but writes the HTML to `w` a line at a time
+///
+/// [`highlighted_html_for_string_with_lines`]: fn.highlighted_html_for_string_with_lines.html
+pub fn highlighted_html_for_string_with_lines_to_writer<W: io::Write>(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    options: &LineOptions,
+    w: &mut W,
+) -> Result<(), Error> {
+    highlighted_html_for_string_with_lines_impl(
+        s,
+        ss,
+        syntax,
+        theme,
+        options,
+        &NO_SANITIZING,
+        |html| Ok(w.write_all(html.as_bytes())?),
+    )
+}
+
+/// Passes the HTML to `emit` in pieces, at most a line long
+fn highlighted_html_for_string_with_lines_impl<F>(
+    s: &str,
+    ss: &SyntaxSet,
+    syntax: &SyntaxReference,
+    theme: &Theme,
+    options: &LineOptions,
+    sanitize: &SanitizeOptions,
+    mut emit: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<(), Error>,
+{
     let mut highlighter = HighlightLines::new(syntax, theme);
     let (mut output, bg) = start_highlighted_html_snippet(theme);
+    let line_bg = line_highlight_color(theme, bg);
+    let mut size = 0;
+    let mut emit = |html: &str| {
+        size += html.len();
+        match sanitize.max_output_size {
+            Some(max) if size > max => Err(Error::HtmlTooLarge(max)),
+            _ => emit(html),
+        }
+    };
 
-    for line in LinesWithEndings::from(s) {
+    for (i, line) in LinesWithEndings::from(s).enumerate() {
+        let stripped: String;
+        let line = if sanitize.strip_control_characters && line.contains(is_stripped_control) {
+            stripped = line.chars().filter(|&c| !is_stripped_control(c)).collect();
+            &stripped
+        } else {
+            line
+        };
         let regions = highlighter.highlight_line(line, ss)?;
-        append_highlighted_html_for_styled_line(
-            &regions[..],
-            IncludeBackground::IfDifferent(bg),
-            &mut output,
-        )?;
+        let line_number = i + 1;
+        if options.hidden.contains(&line_number) {
+            continue;
+        }
+        let emphasized = options.emphasized.contains(&line_number);
+        if emphasized {
+            output.push_str("<span style=\"display:block;background-color:");
+            write_css_color(&mut output, line_bg);
+            output.push_str(";\">");
+        }
+        let mut sink = InlineStyledSpans {
+            html: &mut output,
+            bg: IncludeBackground::IfDifferent(bg),
+            prev_style: None,
+            isolate_bidi: sanitize.isolate_bidi,
+            fonts: &theme.font_hints,
+        };
+        styled_line_to_html_sink(&regions[..], &mut sink)?;
+        if emphasized {
+            output.push_str("</span>");
+        }
+        emit(&output)?;
+        output.clear();
     }
     output.push_str("</pre>\n");
-    Ok(output)
+    emit(&output)
 }
 
 /// Convenience method that combines `start_highlighted_html_snippet`, `styled_line_to_highlighted_html`
@@ -312,6 +832,38 @@ pub fn highlighted_html_for_file<P: AsRef<Path>>(
     ss: &SyntaxSet,
     theme: &Theme,
 ) -> Result<String, Error> {
+    let mut output = String::new();
+    highlighted_html_for_file_impl(path, ss, theme, |html| {
+        output.push_str(html);
+        Ok(())
+    })?;
+    Ok(output)
+}
+
+/// Like [`highlighted_html_for_file`], but writes the HTML to `w` a line at a time, so the file
+/// doesn't have to fit in memory
+///
+/// [`highlighted_html_for_file`]: fn.highlighted_html_for_file.html
+pub fn highlighted_html_for_file_to_writer<P: AsRef<Path>, W: io::Write>(
+    path: P,
+    ss: &SyntaxSet,
+    theme: &Theme,
+    w: &mut W,
+) -> Result<(), Error> {
+    highlighted_html_for_file_impl(path, ss, theme, |html| Ok(w.write_all(html.as_bytes())?))
+}
+
+/// Passes the HTML to `emit` in pieces, at most a line long
+fn highlighted_html_for_file_impl<P, F>(
+    path: P,
+    ss: &SyntaxSet,
+    theme: &Theme,
+    mut emit: F,
+) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: FnMut(&str) -> Result<(), Error>,
+{
     let mut highlighter = HighlightFile::new(path, ss, theme)?;
     let (mut output, bg) = start_highlighted_html_snippet(theme);
 
@@ -325,10 +877,12 @@ pub fn highlighted_html_for_file<P: AsRef<Path>>(
                 &mut output,
             )?;
         }
+        emit(&output)?;
+        output.clear();
         line.clear();
     }
     output.push_str("</pre>\n");
-    Ok(output)
+    emit(&output)
 }
 
 /// Output HTML for a line of code with `<span>` elements
@@ -352,42 +906,87 @@ pub fn line_tokens_to_classed_spans(
     style: ClassStyle,
     stack: &mut ScopeStack,
 ) -> Result<(String, isize), Error> {
-    let mut s = String::with_capacity(line.len() + ops.len() * 8); // a guess
-    let mut cur_index = 0;
-    let mut span_delta = 0;
+    line_tokens_to_classed_spans_impl(line, ops, style, stack, None)
+}
 
-    // check and skip emty inner <span> tags
-    let mut span_empty = false;
-    let mut span_start = 0;
+/// Like [`line_tokens_to_classed_spans`], but writes the HTML to `w` and only returns the number
+/// of `<span>` tags opened
+///
+/// [`line_tokens_to_classed_spans`]: fn.line_tokens_to_classed_spans.html
+pub fn line_tokens_to_classed_spans_to_writer<W: io::Write>(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    style: ClassStyle,
+    stack: &mut ScopeStack,
+    w: &mut W,
+) -> Result<isize, Error> {
+    let (html, delta) = line_tokens_to_classed_spans(line, ops, style, stack)?;
+    w.write_all(html.as_bytes())?;
+    Ok(delta)
+}
+
+fn line_tokens_to_classed_spans_impl(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    style: ClassStyle,
+    stack: &mut ScopeStack,
+    repo: Option<&ScopeRepository>,
+) -> Result<(String, isize), Error> {
+    let mut s = String::with_capacity(line.len() + ops.len() * 8); // a guess
+    let mut sink = ClassedSpans {
+        html: &mut s,
+        style,
+        repo,
+        span_delta: 0,
+    };
+    line_tokens_to_html_sink(line, ops, stack, &mut sink)?;
+    let span_delta = sink.span_delta;
+    Ok((s, span_delta))
+}
 
+/// Feeds a line of code to an [`HtmlSink`] as tokens with the classes of the scopes they're in,
+/// like [`line_tokens_to_classed_spans`] does to build its HTML
+///
+/// The spans for the scopes on `stack` are expected to be open already, and spans that would
+/// be empty are left out.
+///
+/// [`HtmlSink`]: trait.HtmlSink.html
+/// [`line_tokens_to_classed_spans`]: fn.line_tokens_to_classed_spans.html
+pub fn line_tokens_to_html_sink<S: HtmlSink + ?Sized>(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    stack: &mut ScopeStack,
+    sink: &mut S,
+) -> Result<(), Error> {
+    sink.start_line()?;
+    let mut cur_index = 0;
+    // the spans to close and open before the next text, pushed scopes only get a span once
+    // there's text in them
+    let mut close = 0;
+    let mut open = Vec::new();
     for &(i, ref op) in ops {
         if i > cur_index {
-            span_empty = false;
-            write!(s, "{}", Escape(&line[cur_index..i]))?;
-            cur_index = i
+            let style = TokenStyle::Classed { close, open: &open };
+            sink.token(style, &line[cur_index..i])?;
+            close = 0;
+            open.clear();
+            cur_index = i;
         }
         stack.apply_with_hook(op, |basic_op, _| match basic_op {
-            BasicScopeStackOp::Push(scope) => {
-                span_start = s.len();
-                span_empty = true;
-                s.push_str("<span class=\"");
-                scope_to_classes(&mut s, scope, style);
-                s.push_str("\">");
-                span_delta += 1;
-            }
+            BasicScopeStackOp::Push(scope) => open.push(scope),
             BasicScopeStackOp::Pop => {
-                if !span_empty {
-                    s.push_str("</span>");
-                } else {
-                    s.truncate(span_start);
+                if open.pop().is_none() {
+                    close += 1;
                 }
-                span_delta -= 1;
-                span_empty = false;
             }
         })?;
     }
-    write!(s, "{}", Escape(&line[cur_index..line.len()]))?;
-    Ok((s, span_delta))
+    let rest = &line[cur_index..];
+    if !rest.is_empty() || close > 0 || !open.is_empty() {
+        let style = TokenStyle::Classed { close, open: &open };
+        sink.token(style, rest)?;
+    }
+    sink.end_line()
 }
 
 /// Preserved for compatibility, always use `line_tokens_to_classed_spans`
@@ -434,6 +1033,15 @@ pub enum IncludeBackground {
     IfDifferent(Color),
 }
 
+/// The theme's `line_highlight` color made opaque, or a faint tint of the foreground color
+fn line_highlight_color(theme: &Theme, bg: Color) -> Color {
+    let c = theme.settings.line_highlight.unwrap_or_else(|| {
+        let fg = theme.settings.foreground.unwrap_or(Color::BLACK);
+        Color { a: 0x20, ..fg }
+    });
+    blend_fg_color(c, bg)
+}
+
 fn write_css_color(s: &mut String, c: Color) {
     if c.a != 0xFF {
         write!(s, "#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a).unwrap();
@@ -442,12 +1050,39 @@ fn write_css_color(s: &mut String, c: Color) {
     }
 }
 
+/// The CSS `text-decoration-line` for underlined or struck through text
+fn text_decoration_line(font_style: FontStyle) -> Option<&'static str> {
+    match (
+        font_style.contains(FontStyle::UNDERLINE),
+        font_style.contains(FontStyle::STRIKETHROUGH),
+    ) {
+        (true, true) => Some("underline line-through"),
+        (true, false) => Some("underline"),
+        (false, true) => Some("line-through"),
+        (false, false) => None,
+    }
+}
+
+/// The CSS `text-decoration-style` for an underline, `None` for the default solid line
+fn text_decoration_style(underline: Option<UnderlineStyle>) -> Option<&'static str> {
+    match underline? {
+        UnderlineStyle::Solid => None,
+        UnderlineStyle::Stippled => Some("dotted"),
+        UnderlineStyle::Squiggly => Some("wavy"),
+    }
+}
+
 /// Output HTML for a line of code with `<span>` elements using inline
 /// `style` attributes to set the correct font attributes.
 /// The `bg` attribute determines if the spans will have the `background-color`
 /// attribute set. See the `IncludeBackground` enum's docs.
 ///
-/// The lines returned don't include a newline at the end.
+/// The lines returned don't include a newline at the end. The [`FontHint`]s of the styles are
+/// left out, because the fonts they refer to are in the theme, which
+/// [`highlighted_html_for_string`] has.
+///
+/// [`FontHint`]: ../highlighting/struct.FontHint.html
+/// [`highlighted_html_for_string`]: fn.highlighted_html_for_string.html
 /// # Examples
 ///
 /// ```
@@ -475,6 +1110,19 @@ pub fn styled_line_to_highlighted_html(
     Ok(s)
 }
 
+/// Like [`styled_line_to_highlighted_html`], but writes the HTML to `w`
+///
+/// [`styled_line_to_highlighted_html`]: fn.styled_line_to_highlighted_html.html
+pub fn styled_line_to_highlighted_html_to_writer<W: io::Write>(
+    v: &[(Style, &str)],
+    bg: IncludeBackground,
+    w: &mut W,
+) -> Result<(), Error> {
+    let html = styled_line_to_highlighted_html(v, bg)?;
+    w.write_all(html.as_bytes())?;
+    Ok(())
+}
+
 /// Like `styled_line_to_highlighted_html` but appends to a `String` for increased efficiency.
 /// In fact `styled_line_to_highlighted_html` is just a wrapper around this function.
 pub fn append_highlighted_html_for_styled_line(
@@ -482,22 +1130,163 @@ pub fn append_highlighted_html_for_styled_line(
     bg: IncludeBackground,
     s: &mut String,
 ) -> Result<(), Error> {
-    let mut prev_style: Option<&Style> = None;
-    for &(ref style, text) in v.iter() {
-        let unify_style = if let Some(ps) = prev_style {
+    append_highlighted_html_for_styled_tokens(v.iter().copied(), bg, &[], s)
+}
+
+/// Like [`append_highlighted_html_for_styled_line`], but for tokens straight from an iterator,
+/// which saves collecting them first
+///
+/// [`append_highlighted_html_for_styled_line`]: fn.append_highlighted_html_for_styled_line.html
+pub(crate) fn append_highlighted_html_for_styled_tokens<'t>(
+    tokens: impl IntoIterator<Item = (Style, &'t str)>,
+    bg: IncludeBackground,
+    fonts: &[Font],
+    s: &mut String,
+) -> Result<(), Error> {
+    let mut sink = InlineStyledSpans {
+        html: s,
+        bg,
+        prev_style: None,
+        isolate_bidi: false,
+        fonts,
+    };
+    sink.start_line()?;
+    for (style, text) in tokens {
+        sink.token(TokenStyle::Inline(&style), text)?;
+    }
+    sink.end_line()
+}
+
+/// Feeds a line of styled tokens, e.g. from [`HighlightLines`], to an [`HtmlSink`], like
+/// [`append_highlighted_html_for_styled_line`] does to build its HTML
+///
+/// [`HighlightLines`]: ../easy/struct.HighlightLines.html
+/// [`HtmlSink`]: trait.HtmlSink.html
+/// [`append_highlighted_html_for_styled_line`]: fn.append_highlighted_html_for_styled_line.html
+pub fn styled_line_to_html_sink<S: HtmlSink + ?Sized>(
+    v: &[(Style, &str)],
+    sink: &mut S,
+) -> Result<(), Error> {
+    sink.start_line()?;
+    for (style, text) in v {
+        sink.token(TokenStyle::Inline(style), text)?;
+    }
+    sink.end_line()
+}
+
+/// Receives the tokens of highlighted code, so HTML can be built with something other than
+/// string concatenation, like a template engine or a DOM builder
+///
+/// The text of the tokens isn't escaped. The functions feeding a sink, like
+/// [`styled_line_to_html_sink`] and [`line_tokens_to_html_sink`], call [`start_line`] and
+/// [`end_line`] around the tokens of each line.
+///
+/// # Examples
+///
+/// Collecting the text of a line with its colors:
+///
+/// ```
+/// use syntect::easy::HighlightLines;
+/// use syntect::highlighting::{Color, ThemeSet};
+/// use syntect::html::{styled_line_to_html_sink, HtmlSink, TokenStyle};
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::Error;
+///
+/// struct Colored(Vec<(Color, String)>);
+///
+/// impl HtmlSink for Colored {
+///     fn token(&mut self, style: TokenStyle<'_>, text: &str) -> Result<(), Error> {
+///         if let TokenStyle::Inline(style) = style {
+///             self.0.push((style.foreground, text.to_owned()));
+///         }
+///         Ok(())
+///     }
+/// }
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+/// let regions = h.highlight_line("let x = 1;\n", &ss).unwrap();
+/// let mut sink = Colored(Vec::new());
+/// styled_line_to_html_sink(&regions, &mut sink).unwrap();
+/// assert_eq!(sink.0[0].1, "let");
+/// ```
+///
+/// [`styled_line_to_html_sink`]: fn.styled_line_to_html_sink.html
+/// [`line_tokens_to_html_sink`]: fn.line_tokens_to_html_sink.html
+/// [`start_line`]: #method.start_line
+/// [`end_line`]: #method.end_line
+pub trait HtmlSink {
+    /// Called before the tokens of a line
+    fn start_line(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called with each piece of text and how it's styled
+    fn token(&mut self, style: TokenStyle<'_>, text: &str) -> Result<(), Error>;
+
+    /// Called after the tokens of a line
+    fn end_line(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// How the text of a token given to an [`HtmlSink`] is styled
+///
+/// [`HtmlSink`]: trait.HtmlSink.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenStyle<'a> {
+    /// The style from a theme, to be set inline
+    Inline(&'a Style),
+    /// Changes to the nested spans with classes for the scopes the text is in
+    ///
+    /// Before the text, the `close` innermost open spans are closed, then spans are opened for
+    /// the scopes in `open`, outermost first. Spans stay open across tokens and lines until
+    /// they're closed. The text can be empty for the spans changed at the end of a line.
+    Classed { close: usize, open: &'a [Scope] },
+}
+
+/// Builds the HTML of `append_highlighted_html_for_styled_line`
+struct InlineStyledSpans<'a> {
+    html: &'a mut String,
+    bg: IncludeBackground,
+    prev_style: Option<Style>,
+    isolate_bidi: bool,
+    /// The fonts of the theme, which the font hints of the styles refer to
+    fonts: &'a [Font],
+}
+
+impl InlineStyledSpans<'_> {
+    fn write_text(&mut self, text: &str) -> Result<(), Error> {
+        if self.isolate_bidi && text.contains(is_bidi_control) {
+            write!(self.html, "<bdi>{}</bdi>", Escape(text))?;
+        } else {
+            write!(self.html, "{}", Escape(text))?;
+        }
+        Ok(())
+    }
+}
+
+impl HtmlSink for InlineStyledSpans<'_> {
+    fn token(&mut self, style: TokenStyle<'_>, text: &str) -> Result<(), Error> {
+        let style = match style {
+            TokenStyle::Inline(style) => style,
+            TokenStyle::Classed { .. } => return self.write_text(text),
+        };
+        let unify_style = if let Some(ps) = &self.prev_style {
             style == ps || (style.background == ps.background && text.trim().is_empty())
         } else {
             false
         };
-        if unify_style {
-            write!(s, "{}", Escape(text))?;
-        } else {
-            if prev_style.is_some() {
+        if !unify_style {
+            let s = &mut *self.html;
+            if self.prev_style.is_some() {
                 write!(s, "</span>")?;
             }
-            prev_style = Some(style);
+            self.prev_style = Some(*style);
             write!(s, "<span style=\"")?;
-            let include_bg = match bg {
+            let include_bg = match self.bg {
                 IncludeBackground::Yes => true,
                 IncludeBackground::No => false,
                 IncludeBackground::IfDifferent(c) => style.background != c,
@@ -507,8 +1296,18 @@ pub fn append_highlighted_html_for_styled_line(
                 write_css_color(s, style.background);
                 write!(s, ";")?;
             }
+            if let Some(decoration_line) = text_decoration_line(style.font_style) {
+                write!(s, "text-decoration:{};", decoration_line)?;
+            }
             if style.font_style.contains(FontStyle::UNDERLINE) {
-                write!(s, "text-decoration:underline;")?;
+                if let Some(decoration_style) = text_decoration_style(style.underline) {
+                    write!(s, "text-decoration-style:{};", decoration_style)?;
+                }
+                if let Some(c) = style.underline_color {
+                    write!(s, "text-decoration-color:")?;
+                    write_css_color(s, c);
+                    write!(s, ";")?;
+                }
             }
             if style.font_style.contains(FontStyle::BOLD) {
                 write!(s, "font-weight:bold;")?;
@@ -516,16 +1315,56 @@ pub fn append_highlighted_html_for_styled_line(
             if style.font_style.contains(FontStyle::ITALIC) {
                 write!(s, "font-style:italic;")?;
             }
+            if let Some(font) = style
+                .font_hint
+                .and_then(|hint| self.fonts.get(hint.index()))
+            {
+                if let Some(family) = &font.family {
+                    write!(s, "font-family:{};", Escape(family))?;
+                }
+                if let Some(size) = font.size {
+                    write!(s, "font-size:{}em;", size)?;
+                }
+            }
             write!(s, "color:")?;
             write_css_color(s, style.foreground);
-            write!(s, ";\">{}", Escape(text))?;
+            write!(s, ";\">")?;
         }
+        self.write_text(text)
     }
-    if prev_style.is_some() {
-        write!(s, "</span>")?;
+
+    fn end_line(&mut self) -> Result<(), Error> {
+        if self.prev_style.take().is_some() {
+            write!(self.html, "</span>")?;
+        }
+        Ok(())
     }
+}
 
-    Ok(())
+/// Builds the HTML of `line_tokens_to_classed_spans`
+struct ClassedSpans<'a> {
+    html: &'a mut String,
+    style: ClassStyle,
+    repo: Option<&'a ScopeRepository>,
+    span_delta: isize,
+}
+
+impl HtmlSink for ClassedSpans<'_> {
+    fn token(&mut self, style: TokenStyle<'_>, text: &str) -> Result<(), Error> {
+        if let TokenStyle::Classed { close, open } = style {
+            for _ in 0..close {
+                self.html.push_str("</span>");
+            }
+            for &scope in open {
+                self.html.push_str("<span class=\"");
+                scope_to_classes(self.html, scope, self.style, self.repo);
+                self.html.push_str("\">");
+            }
+            self.span_delta += open.len() as isize - close as isize;
+        }
+        write!(self.html, "{}", Escape(text))?;
+        Ok(())
+    }
 }
 
 /// Returns a `<pre style="...">\n` tag with the correct background color for the given theme.
@@ -551,13 +1390,253 @@ pub fn start_highlighted_html_snippet(t: &Theme) -> (String, Color) {
     )
 }
 
+/// Like [`start_highlighted_html_snippet`], but writes the `<pre>` tag to `w` and only returns
+/// the background color
+///
+/// [`start_highlighted_html_snippet`]: fn.start_highlighted_html_snippet.html
+pub fn start_highlighted_html_snippet_to_writer<W: io::Write>(
+    t: &Theme,
+    w: &mut W,
+) -> Result<Color, Error> {
+    let (html, c) = start_highlighted_html_snippet(t);
+    w.write_all(html.as_bytes())?;
+    Ok(c)
+}
+
 #[cfg(all(feature = "default-syntaxes", feature = "default-themes",))]
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, ThemeSet};
+    use crate::highlighting::{
+        Font, HighlightIterator, HighlightState, Highlighter, Style, ThemeBuilder, ThemeSet,
+    };
     use crate::parsing::{ParseState, ScopeStack, SyntaxDefinition, SyntaxSet, SyntaxSetBuilder};
     use crate::util::LinesWithEndings;
+    use crate::utils::TempDir;
+    #[test]
+    fn long_scopes_become_one_class_per_atom() {
+        let scope = Scope::new("a.b.c.d.e.f.g.h.i").unwrap();
+        let ops = [(0, ScopeStackOp::Push(scope)), (1, ScopeStackOp::Pop(1))];
+        let (html, _) =
+            line_tokens_to_classed_spans("x", &ops, ClassStyle::Spaced, &mut ScopeStack::new())
+                .expect("#[cfg(test)]");
+        assert_eq!(html, "<span class=\"a b c d e f g h i\">x</span>");
+    }
+
+    #[test]
+    fn writers_match_strings() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let code = "/* a\ncomment */\nfn main() {\n    let x = 5;\n}\n";
+
+        let mut out = Vec::new();
+        highlighted_html_for_string_to_writer(code, &ss, syntax, theme, &mut out).unwrap();
+        let html = highlighted_html_for_string(code, &ss, syntax, theme).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), html);
+
+        let dir = TempDir::new("html");
+        let path = dir.write("code.rs", code);
+        let mut out = Vec::new();
+        highlighted_html_for_file_to_writer(&path, &ss, theme, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), html);
+
+        let mut streamed =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &ss, ClassStyle::Spaced);
+        let mut buffered =
+            ClassedHTMLGenerator::new_with_class_style(syntax, &ss, ClassStyle::Spaced);
+        let mut out = Vec::new();
+        for line in LinesWithEndings::from(code) {
+            streamed
+                .parse_html_for_line_which_includes_newline(line)
+                .unwrap();
+            streamed.write_html_to(&mut out).unwrap();
+            buffered
+                .parse_html_for_line_which_includes_newline(line)
+                .unwrap();
+        }
+        out.extend(streamed.finalize().bytes());
+        assert_eq!(String::from_utf8(out).unwrap(), buffered.finalize());
+    }
+
+    #[test]
+    fn cached_css_follows_theme_changes() {
+        let ts = ThemeSet::load_defaults();
+        let mut theme = ts.themes["InspiredGitHub"].clone();
+        let css = css_for_theme_with_class_style(&theme, ClassStyle::Spaced).unwrap();
+        assert_eq!(
+            css_for_theme_with_class_style(&theme, ClassStyle::Spaced).unwrap(),
+            css
+        );
+        assert_eq!(
+            generate_css_for_theme(&theme, ClassStyle::Spaced).unwrap(),
+            css
+        );
+
+        theme.settings.background = Some(Color {
+            r: 0x12,
+            g: 0x34,
+            b: 0x56,
+            a: 0xFF,
+        });
+        let changed = css_for_theme_with_class_style(&theme, ClassStyle::Spaced).unwrap();
+        assert!(changed.contains("background-color: #123456;"));
+        let prefixed = ClassStyle::SpacedPrefixed { prefix: "cached-" };
+        let changed = css_for_theme_with_class_style(&theme, prefixed).unwrap();
+        assert!(changed.contains(".cached-code {"));
+    }
+
+    #[test]
+    fn sanitizing_keeps_benign_code() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let code = "fn main() {\r\n\tlet s = \"\u{5D0}\u{5D1}\";\r\n}\r\n";
+        let options = SanitizeOptions::new();
+        let html = highlighted_html_for_string_sanitized(code, &ss, syntax, theme, &options);
+        assert_eq!(
+            html.unwrap(),
+            highlighted_html_for_string(code, &ss, syntax, theme).unwrap()
+        );
+
+        let html = highlighted_html_for_string(code, &ss, syntax, theme).unwrap();
+        let options = SanitizeOptions::new().max_output_size(html.len());
+        assert!(highlighted_html_for_string_sanitized(code, &ss, syntax, theme, &options).is_ok());
+        let options = SanitizeOptions::new().max_output_size(html.len() - 1);
+        let mut out = Vec::new();
+        let result = highlighted_html_for_string_sanitized_to_writer(
+            code, &ss, syntax, theme, &options, &mut out,
+        );
+        assert!(matches!(result, Err(Error::HtmlTooLarge(_))));
+        assert!(!out.is_empty());
+    }
+
+    #[test]
+    fn sinks_get_classed_tokens() {
+        #[derive(Default)]
+        struct Recorder(Vec<String>);
+
+        impl HtmlSink for Recorder {
+            fn start_line(&mut self) -> Result<(), Error> {
+                self.0.push("start".to_owned());
+                Ok(())
+            }
+
+            fn token(&mut self, style: TokenStyle<'_>, text: &str) -> Result<(), Error> {
+                if let TokenStyle::Classed { close, open } = style {
+                    let open: Vec<String> = open.iter().map(|s| s.build_string()).collect();
+                    self.0.push(format!("-{} +{:?} {:?}", close, open, text));
+                }
+                Ok(())
+            }
+
+            fn end_line(&mut self) -> Result<(), Error> {
+                self.0.push("end".to_owned());
+                Ok(())
+            }
+        }
+
+        let a = Scope::new("a").unwrap();
+        let b = Scope::new("b").unwrap();
+        let ops = [
+            (0, ScopeStackOp::Push(a)),
+            (1, ScopeStackOp::Push(b)),
+            (1, ScopeStackOp::Pop(1)),
+            (1, ScopeStackOp::Push(b)),
+            (2, ScopeStackOp::Pop(2)),
+            (3, ScopeStackOp::Push(b)),
+        ];
+        let mut sink = Recorder::default();
+        line_tokens_to_html_sink("xyz", &ops, &mut ScopeStack::new(), &mut sink).unwrap();
+        assert_eq!(
+            sink.0,
+            [
+                "start",
+                "-0 +[\"a\"] \"x\"",
+                "-0 +[\"b\"] \"y\"",
+                "-2 +[] \"z\"",
+                "-0 +[\"b\"] \"\"",
+                "end"
+            ]
+        );
+    }
+
+    #[test]
+    fn underline_styles_become_text_decoration() {
+        let style = Style {
+            font_style: FontStyle::UNDERLINE,
+            underline: Some(UnderlineStyle::Squiggly),
+            underline_color: Some(Color {
+                r: 0xff,
+                g: 0,
+                b: 0,
+                a: 0xff,
+            }),
+            ..Style::default()
+        };
+        let html = styled_line_to_highlighted_html(&[(style, "x")], IncludeBackground::No)
+            .expect("#[cfg(test)]");
+        assert_eq!(html, "<span style=\"text-decoration:underline;text-decoration-style:wavy;text-decoration-color:#ff0000;color:#000000;\">x</span>");
+
+        let style = Style {
+            font_style: FontStyle::UNDERLINE | FontStyle::STRIKETHROUGH,
+            ..Style::default()
+        };
+        let html = styled_line_to_highlighted_html(&[(style, "x")], IncludeBackground::No)
+            .expect("#[cfg(test)]");
+        assert_eq!(
+            html,
+            "<span style=\"text-decoration:underline line-through;color:#000000;\">x</span>"
+        );
+    }
+
+    #[test]
+    fn font_hints_become_font_family_and_size() {
+        let fira = Font {
+            family: Some("\"Fira Sans\", sans-serif".to_owned()),
+            size: Some(0.9),
+        };
+        let theme = ThemeBuilder::new()
+            .scope("comment", |s| s.font(fira))
+            .build()
+            .unwrap();
+        let style = Style {
+            font_hint: theme.scopes[0].style.font_hint,
+            ..Style::default()
+        };
+        let mut html = String::new();
+        append_highlighted_html_for_styled_tokens(
+            [(style, "x")],
+            IncludeBackground::No,
+            &theme.font_hints,
+            &mut html,
+        )
+        .unwrap();
+        assert_eq!(html, "<span style=\"font-family:&quot;Fira Sans&quot;, sans-serif;font-size:0.9em;color:#000000;\">x</span>");
+        // the fonts are in the theme, which this doesn't have
+        let html = styled_line_to_highlighted_html(&[(style, "x")], IncludeBackground::No)
+            .expect("#[cfg(test)]");
+        assert_eq!(html, "<span style=\"color:#000000;\">x</span>");
+
+        let theme = ThemeBuilder::new()
+            .scope("comment", |s| {
+                s.font(Font {
+                    family: Some("serif".to_owned()),
+                    size: None,
+                })
+            })
+            .build()
+            .unwrap();
+        let css = css_for_theme_with_class_style(&theme, ClassStyle::Spaced).unwrap();
+        assert!(
+            css.contains(".comment {\nfont-family: serif;\n}"),
+            "{}",
+            css
+        );
+    }
+
     #[test]
     fn tokens() {
         let ss = SyntaxSet::load_defaults_newlines();
@@ -714,4 +1793,88 @@ fn main() {
         let html = html_generator.finalize();
         assert_eq!(html, "<span class=\"source rust\"><span class=\"comment line double-slash rust\"><span class=\"punctuation definition comment rust\">//</span> Rust source\n</span><span class=\"meta function rust\"><span class=\"meta function rust\"><span class=\"storage type function rust\">fn</span> </span><span class=\"entity name function rust\">main</span></span><span class=\"meta function rust\"><span class=\"meta function parameters rust\"><span class=\"punctuation section parameters begin rust\">(</span></span><span class=\"meta function rust\"><span class=\"meta function parameters rust\"><span class=\"punctuation section parameters end rust\">)</span></span></span></span><span class=\"meta function rust\"> </span><span class=\"meta function rust\"><span class=\"meta block rust\"><span class=\"punctuation section block begin rust\">{</span>\n    <span class=\"support macro rust\">println!</span><span class=\"meta group rust\"><span class=\"punctuation section group begin rust\">(</span></span><span class=\"meta group rust\"><span class=\"string quoted double rust\"><span class=\"punctuation definition string begin rust\">&quot;</span>Hello World!<span class=\"punctuation definition string end rust\">&quot;</span></span></span><span class=\"meta group rust\"><span class=\"punctuation section group end rust\">)</span></span><span class=\"punctuation terminator rust\">;</span>\n</span><span class=\"meta block rust\"><span class=\"punctuation section block end rust\">}</span></span></span>\n</span>");
     }
+
+    #[test]
+    fn emphasized_and_hidden_lines_inline() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let code = "/* hidden\nstill a comment */\nfn main() {}\nfn other() {}\n";
+        let options = LineOptions::new().hide([1]).emphasize([4]);
+        let html =
+            highlighted_html_for_string_with_lines(code, &ss, syntax, theme, &options).unwrap();
+        assert!(!html.contains("hidden"));
+        // the hidden line still affects the highlighting of later lines
+        let plain = highlighted_html_for_string(code, &ss, syntax, theme).unwrap();
+        let comment_line = plain.lines().find(|l| l.contains("still")).unwrap();
+        assert!(html.contains(comment_line.trim_start_matches("</span>")));
+
+        let c = line_highlight_color(theme, theme.settings.background.unwrap());
+        let open = format!(
+            "<span style=\"display:block;background-color:#{:02x}{:02x}{:02x};\">",
+            c.r, c.g, c.b
+        );
+        let emphasized = html.split(&open).nth(1).unwrap();
+        assert!(emphasized.contains("other"));
+        assert!(!emphasized.contains("main"));
+    }
+
+    #[test]
+    fn emphasized_and_hidden_lines_classed() {
+        let code = "fn main() {\n    hidden();\n    shown();\n}\n";
+        let syntax_set = SyntaxSet::load_defaults_newlines();
+        let syntax = syntax_set.find_syntax_by_extension("rs").unwrap();
+        let mut html_generator = ClassedHTMLGenerator::new_with_class_style(
+            syntax,
+            &syntax_set,
+            ClassStyle::SpacedPrefixed { prefix: "foo-" },
+        )
+        .with_line_options(LineOptions::new().hide([2]).emphasize([3]));
+        for line in LinesWithEndings::from(code) {
+            html_generator
+                .parse_html_for_line_which_includes_newline(line)
+                .expect("#[cfg(test)]");
+        }
+        let html = html_generator.finalize();
+        assert!(!html.contains("hidden"));
+        assert_eq!(
+            html.matches("<span").count(),
+            html.matches("</span>").count()
+        );
+
+        // the emphasized block only holds its own line and reopens the enclosing scopes
+        let start = html.find("<span class=\"foo-line-highlight\">").unwrap();
+        let block = &html[start..];
+        let mut depth = 0;
+        let mut end = 0;
+        for (i, _) in block.match_indices('<') {
+            if block[i..].starts_with("</span>") {
+                depth -= 1;
+            } else if block[i..].starts_with("<span") {
+                depth += 1;
+            }
+            if depth == 0 {
+                end = i + "</span>".len();
+                break;
+            }
+        }
+        let block = &block[..end];
+        assert!(block.contains("shown"));
+        assert!(!block.contains("main"));
+        assert!(block.contains("<span class=\"foo-source foo-rust\">"));
+
+        let ts = ThemeSet::load_defaults();
+        let css = css_for_theme_with_class_style(
+            &ts.themes["base16-ocean.dark"],
+            ClassStyle::SpacedPrefixed { prefix: "foo-" },
+        )
+        .unwrap();
+        assert!(css.contains(".foo-line-highlight {\n display: block;\n background-color: #"));
+
+        let mut theme = ts.themes["base16-ocean.dark"].clone();
+        theme.settings.line_highlight = None;
+        let css = css_for_theme_with_class_style(&theme, ClassStyle::Spaced).unwrap();
+        assert!(!css.contains("line-highlight"));
+    }
 }
@@ -0,0 +1,10 @@
+//! Adapters for showing highlighted text with other libraries, without going through syntect's
+//! HTML or terminal output
+//!
+//! * Turning highlighted regions into spans for the text layouts of GUI toolkits like egui or
+//!   iced, in the [`text_layout`] module
+//!
+//! [`text_layout`]: text_layout/index.html
+
+#[cfg(feature = "text-layout")]
+pub mod text_layout;
@@ -0,0 +1,200 @@
+//! Highlighted text as spans for the text layouts of GUI toolkits
+//!
+//! GUI toolkits lay out rich text as a list of spans, each with its text and a few attributes,
+//! rather than as HTML or terminal escapes. [`text_spans`] turns the regions returned by
+//! [`HighlightLines::highlight_line`] into such spans, with only the attributes that toolkits
+//! commonly support, so adapting them to a toolkit is a short loop.
+//!
+//! With [egui], the spans can be appended to a `LayoutJob`. It has no bold attribute, since that
+//! needs a bold font to be registered:
+//!
+//! ```ignore
+//! use egui::{text::LayoutJob, Color32, FontId, Stroke, TextFormat};
+//!
+//! let mut job = LayoutJob::default();
+//! for span in text_spans(&regions) {
+//!     let [r, g, b, a] = span.rgba8();
+//!     let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+//!     let underline = if span.underline { Stroke::new(1.0, color) } else { Stroke::NONE };
+//!     let format = TextFormat {
+//!         font_id: FontId::monospace(14.0),
+//!         color,
+//!         italics: span.italic,
+//!         underline,
+//!         ..TextFormat::default()
+//!     };
+//!     job.append(span.text, 0.0, format);
+//! }
+//! ```
+//!
+//! With [iced], the spans become the spans of a `rich_text` widget:
+//!
+//! ```ignore
+//! use iced::font::{Style, Weight};
+//! use iced::widget::{rich_text, span};
+//! use iced::{Color, Font};
+//!
+//! let spans: Vec<_> = text_spans(&regions)
+//!     .into_iter()
+//!     .map(|s| {
+//!         let [r, g, b, a] = s.rgba_f32();
+//!         let font = Font {
+//!             weight: if s.bold { Weight::Bold } else { Weight::Normal },
+//!             style: if s.italic { Style::Italic } else { Style::Normal },
+//!             ..Font::MONOSPACE
+//!         };
+//!         span(s.text.to_owned())
+//!             .color(Color::from_rgba(r, g, b, a))
+//!             .font(font)
+//!             .underline(s.underline)
+//!     })
+//!     .collect();
+//! let widget = rich_text(spans);
+//! ```
+//!
+//! [`text_spans`]: fn.text_spans.html
+//! [`HighlightLines::highlight_line`]: ../../easy/struct.HighlightLines.html#method.highlight_line
+//! [egui]: https://github.com/emilk/egui
+//! [iced]: https://github.com/iced-rs/iced
+
+use crate::highlighting::{Color, FontStyle, Style};
+use crate::util::blend_fg_color;
+
+/// A piece of highlighted text with the attributes GUI toolkits commonly support, created by
+/// [`text_spans`]
+///
+/// [`text_spans`]: fn.text_spans.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSpan<'a> {
+    /// The text, borrowed from the highlighted regions
+    pub text: &'a str,
+    /// The color of the text, always opaque
+    ///
+    /// Themes can give the foreground some transparency, which is blended with the background of
+    /// the region here, like in syntect's terminal output, since the background of a GUI widget
+    /// may be a different one.
+    pub color: Color,
+    /// Whether the theme made the text bold
+    pub bold: bool,
+    /// Whether the theme made the text italic
+    pub italic: bool,
+    /// Whether the theme underlined the text, in any underline style
+    pub underline: bool,
+}
+
+impl<'a> TextSpan<'a> {
+    /// The color as `[r, g, b, a]` bytes, like egui's `Color32::from_rgba_unmultiplied` takes
+    pub fn rgba8(&self) -> [u8; 4] {
+        [self.color.r, self.color.g, self.color.b, self.color.a]
+    }
+
+    /// The color as `[r, g, b, a]` from 0.0 to 1.0, like iced's `Color::from_rgba` takes
+    pub fn rgba_f32(&self) -> [f32; 4] {
+        self.rgba8().map(|c| f32::from(c) / 255.0)
+    }
+}
+
+impl<'a> From<(Style, &'a str)> for TextSpan<'a> {
+    fn from((style, text): (Style, &'a str)) -> Self {
+        TextSpan {
+            text,
+            color: blend_fg_color(style.foreground, style.background),
+            bold: style.font_style.contains(FontStyle::BOLD),
+            italic: style.font_style.contains(FontStyle::ITALIC),
+            underline: style.font_style.contains(FontStyle::UNDERLINE),
+        }
+    }
+}
+
+/// Turns highlighted regions into spans for a GUI text layout
+///
+/// Each region becomes one span, except for empty ones, which are left out.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::HighlightLines;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::integration::text_layout::text_spans;
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+/// let regions = h.highlight_line("fn main() {}\n", &ss).unwrap();
+///
+/// let spans = text_spans(&regions);
+/// assert_eq!(spans[0].text, "fn");
+/// assert!(spans.iter().all(|span| span.color.a == 0xFF));
+/// ```
+pub fn text_spans<'a>(regions: &[(Style, &'a str)]) -> Vec<TextSpan<'a>> {
+    regions
+        .iter()
+        .filter(|(_, text)| !text.is_empty())
+        .map(|&region| TextSpan::from(region))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(r: u8, font_style: FontStyle) -> Style {
+        Style {
+            foreground: Color {
+                r,
+                g: 0,
+                b: 0,
+                a: 0xFF,
+            },
+            font_style,
+            ..Style::default()
+        }
+    }
+
+    #[test]
+    fn converts_font_styles_and_skips_empty_regions() {
+        let regions = [
+            (style(1, FontStyle::BOLD), "let"),
+            (style(2, FontStyle::empty()), ""),
+            (style(1, FontStyle::ITALIC | FontStyle::UNDERLINE), " x"),
+        ];
+        let spans = text_spans(&regions);
+        let texts: Vec<_> = spans.iter().map(|span| span.text).collect();
+        assert_eq!(texts, ["let", " x"]);
+        assert!(spans[0].bold && !spans[0].italic && !spans[0].underline);
+        assert!(!spans[1].bold && spans[1].italic && spans[1].underline);
+        assert_eq!(spans[0].rgba8(), [1, 0, 0, 0xFF]);
+        assert_eq!(spans[0].rgba_f32(), [1.0 / 255.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn blends_transparent_colors_with_the_background() {
+        let style = Style {
+            foreground: Color {
+                r: 255,
+                g: 255,
+                b: 255,
+                a: 0x80,
+            },
+            background: Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0xFF,
+            },
+            ..Style::default()
+        };
+        let span = TextSpan::from((style, "a"));
+        assert_eq!(
+            span.color,
+            Color {
+                r: 128,
+                g: 128,
+                b: 128,
+                a: 0xFF
+            }
+        );
+    }
+}
@@ -25,6 +25,10 @@
 #[macro_use]
 extern crate pretty_assertions;
 
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "pulldown-cmark")]
+pub mod cmark;
 #[cfg(any(feature = "dump-load", feature = "dump-create"))]
 pub mod dumps;
 #[cfg(feature = "parsing")]
@@ -34,9 +38,15 @@ mod escape;
 pub mod highlighting;
 #[cfg(feature = "html")]
 pub mod html;
+#[cfg(feature = "text-layout")]
+pub mod integration;
 pub mod parsing;
+#[cfg(any(feature = "test-support", all(test, feature = "yaml-load")))]
+pub mod test_utils;
 pub mod util;
 mod utils;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 use std::io::Error as IoError;
 
@@ -63,6 +73,10 @@ pub enum Error {
     /// IO Error
     #[error("IO Error: {0}")]
     Io(#[from] IoError),
+    /// The generated HTML got larger than the limit, see `html::SanitizeOptions`
+    #[cfg(feature = "html")]
+    #[error("Generated HTML is larger than {0} bytes")]
+    HtmlTooLarge(usize),
 }
 
 /// Common error type used by syntax and theme loading
@@ -0,0 +1,259 @@
+//! Searching consecutive patterns of a context with one regex, see
+//! [`ParseState::set_combined_search`]
+//!
+//! The patterns are joined into an alternation like `(p0)|(p1)|(p2)`. A backtracking search of it
+//! finds the earliest position where one of the patterns matches, and at that position the first
+//! of them in order, which is the match the parser would pick after searching each pattern on its
+//! own. Only patterns that mean the same inside the alternation are combined, so no backreferences
+//! or the like, and the parser still checks the match of the pattern that won with a search of
+//! the pattern itself.
+//!
+//! [`ParseState::set_combined_search`]: struct.ParseState.html#method.set_combined_search
+
+use super::regex::{BacktrackLimitExceeded, Regex, Region};
+use super::syntax_definition::{context_iter, Context, MatchPattern};
+use super::SyntaxSet;
+
+/// The patterns of a context, in the order of [`context_iter`], split into units that are
+/// searched together
+#[derive(Clone, Debug)]
+pub(crate) struct CombinedPatterns {
+    units: Vec<Unit>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Unit {
+    /// A pattern that is searched on its own
+    Single,
+    /// Consecutive patterns that are searched with one regex
+    Run(Box<Run>),
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Run {
+    regex: Regex,
+    /// The capture group around each pattern in the alternation
+    groups: Vec<usize>,
+}
+
+impl CombinedPatterns {
+    /// Returns `None` if there are no patterns in the context that can be combined
+    pub(crate) fn new(syntax_set: &SyntaxSet, context: &Context) -> Option<CombinedPatterns> {
+        let mut units = Vec::new();
+        let mut pending = Vec::new();
+        for (pat_context, pat_index) in context_iter(syntax_set, context) {
+            let pattern = pat_context.match_at(pat_index).ok()?;
+            match combinable_source(pattern) {
+                Some(source) => pending.push((source, pattern.regex.captures_len())),
+                None => {
+                    flush(&mut units, &mut pending);
+                    units.push(Unit::Single);
+                }
+            }
+        }
+        flush(&mut units, &mut pending);
+        if units.iter().any(|unit| matches!(unit, Unit::Run(_))) {
+            Some(CombinedPatterns { units })
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn units(&self) -> &[Unit] {
+        &self.units
+    }
+}
+
+impl Run {
+    /// The number of patterns in the run
+    pub(crate) fn len(&self) -> usize {
+        self.groups.len()
+    }
+
+    /// Returns the start of the earliest match of the patterns and the index of the pattern that
+    /// matched there
+    pub(crate) fn search(
+        &self,
+        line: &str,
+        start: usize,
+        regions: &mut Region,
+        limit: Option<u32>,
+    ) -> Result<Option<(usize, usize)>, BacktrackLimitExceeded> {
+        if !self
+            .regex
+            .search_with_limit(line, start, line.len(), Some(regions), limit)?
+        {
+            return Ok(None);
+        }
+        let index = self.groups.iter().position(|&g| regions.pos(g).is_some());
+        Ok(index
+            .zip(regions.pos(0))
+            .map(|(index, (match_start, _))| (match_start, index)))
+    }
+
+    /// The alternation the patterns are searched with
+    pub(crate) fn regex_str(&self) -> &str {
+        self.regex.regex_str()
+    }
+}
+
+/// Turns the patterns waiting to be combined into a run, or into single patterns if there's only
+/// one of them or the alternation doesn't compile
+fn flush(units: &mut Vec<Unit>, pending: &mut Vec<(String, usize)>) {
+    if pending.len() >= 2 {
+        let mut source = String::new();
+        let mut groups = Vec::with_capacity(pending.len());
+        let mut group = 1;
+        for (i, (pattern, captures_len)) in pending.iter().enumerate() {
+            if i > 0 {
+                source.push('|');
+            }
+            source.push('(');
+            source.push_str(pattern);
+            source.push(')');
+            groups.push(group);
+            group += 1 + captures_len;
+        }
+        // e.g. fancy-regex doesn't allow the same group name in two patterns
+        if let Some(regex) = Regex::try_new(source) {
+            units.push(Unit::Run(Box::new(Run { regex, groups })));
+            pending.clear();
+            return;
+        }
+    }
+    units.extend(pending.drain(..).map(|_| Unit::Single));
+}
+
+/// Returns the regex of the pattern to put in the alternation, or `None` if it can't be combined
+/// with others
+///
+/// Backreferences, subexpression calls and conditionals refer to groups by number, which changes
+/// in the alternation, `\K` would affect the whole match, and flags set in the middle of a pattern
+/// could leave extended mode on for the patterns after it. A pattern that starts in extended mode
+/// gets a newline at the end, to end a comment on its last line before the closing parenthesis.
+fn combinable_source(pattern: &MatchPattern) -> Option<String> {
+    if pattern.has_captures {
+        return None;
+    }
+    let source = pattern.regex.regex_str();
+    let bytes = source.as_bytes();
+    let extended = match flag_group(bytes) {
+        // the flags before a `-` are turned on
+        Some((flags, true)) => flags.iter().take_while(|&&b| b != b'-').any(|&b| b == b'x'),
+        _ => false,
+    };
+    let mut class_depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => {
+                let escaped = *bytes.get(i + 1)?;
+                if escaped.is_ascii_digit() || b"kgKG".contains(&escaped) {
+                    return None;
+                }
+                i += 1;
+            }
+            b'[' => {
+                class_depth += 1;
+                // a `]` right at the start of a class is a literal
+                if bytes.get(i + 1) == Some(&b'^') {
+                    i += 1;
+                }
+                if bytes.get(i + 1) == Some(&b']') {
+                    i += 1;
+                }
+            }
+            b']' if class_depth > 0 => class_depth -= 1,
+            b'#' if extended && class_depth == 0 => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'(' if class_depth == 0 && bytes.get(i + 1) == Some(&b'?') => {
+                let rest = &bytes[i + 2..];
+                match rest.first() {
+                    Some(b'(' | b'R' | b'&' | b'+' | b'0'..=b'9') => return None,
+                    Some(b'P') if rest.get(1) == Some(&b'>') => return None,
+                    Some(b'-') if rest.get(1).is_some_and(u8::is_ascii_digit) => return None,
+                    _ => {}
+                }
+                if let Some((flags, _)) = flag_group(&bytes[i..]) {
+                    if flags.contains(&b'x') && i > 0 {
+                        return None;
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if extended {
+        Some(format!("{}\n", source))
+    } else {
+        Some(source.to_owned())
+    }
+}
+
+/// Returns the flags of a flag group like `(?ix)` or `(?i-x:` at the start of `bytes`, and whether
+/// it sets them for the rest of the enclosing group rather than for a group of its own
+pub(super) fn flag_group(bytes: &[u8]) -> Option<(&[u8], bool)> {
+    let rest = bytes.strip_prefix(b"(?")?;
+    let len = rest
+        .iter()
+        .take_while(|b| b.is_ascii_alphabetic() || **b == b'-')
+        .count();
+    match rest.get(len) {
+        Some(b')') if len > 0 => Some((&rest[..len], true)),
+        Some(b':') if len > 0 => Some((&rest[..len], false)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::syntax_definition::MatchOperation;
+
+    fn source(regex: &str) -> Option<String> {
+        let pattern = MatchPattern::new(
+            false,
+            regex.to_owned(),
+            vec![],
+            None,
+            MatchOperation::None,
+            None,
+        );
+        combinable_source(&pattern)
+    }
+
+    #[test]
+    fn only_combines_patterns_that_keep_their_meaning() {
+        assert_eq!(
+            source(r"\b(fn)\s+[\]\\(?]"),
+            Some(r"\b(fn)\s+[\]\\(?]".to_owned())
+        );
+        assert_eq!(
+            source("(?i:x)(?<name>a)"),
+            Some("(?i:x)(?<name>a)".to_owned())
+        );
+        assert_eq!(source("(?x) a # c\n \\1"), None);
+        assert_eq!(
+            source("(?x) a # comment"),
+            Some("(?x) a # comment\n".to_owned())
+        );
+        assert_eq!(
+            source("(?x) a # \\1 (?i)"),
+            Some("(?x) a # \\1 (?i)\n".to_owned())
+        );
+        assert_eq!(source(r"(\w)\1"), None);
+        assert_eq!(source(r"\k<name>"), None);
+        assert_eq!(source(r"a\Kb"), None);
+        assert_eq!(source("(?(1)a|b)"), None);
+        assert_eq!(source("(?R)"), None);
+        assert_eq!(source("a(?x)b"), None);
+        assert_eq!(source("(?-x)a"), Some("(?-x)a".to_owned()));
+        assert_eq!(source("(?x-i)a#"), Some("(?x-i)a#\n".to_owned()));
+        assert_eq!(source("a(?x: b)"), None);
+        assert_eq!(source("\\"), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! Plugging language detectors into syntax lookup, see [`Detection`]
+//!
+//! [`Detection`]: trait.Detection.html
+
+use super::{Scope, SyntaxReference, SyntaxSet};
+use std::path::Path;
+
+/// A language found by a [`Detection`], which [`SyntaxSet::find_syntax_for_language`] turns into
+/// a syntax
+///
+/// [`Detection`]: trait.Detection.html
+/// [`SyntaxSet::find_syntax_for_language`]: struct.SyntaxSet.html#method.find_syntax_for_language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Language {
+    /// The name of a language, like the `Rust` or `C++` of GitHub's Linguist
+    Name(String),
+    /// The scope of a syntax, like `source.rust`
+    Scope(Scope),
+}
+
+/// Guesses the language of a file
+///
+/// [`SyntaxSet::detect_syntax`] resolves what a detector returns to a syntax of the set. The
+/// default, [`ExtensionAndFirstLine`], looks at what [`SyntaxSet::find_syntax_for_file`] does,
+/// and other detectors can look at the content with heuristics or a model, like Linguist or
+/// hyperpolyglot do. Closures taking the path and the content are detectors too.
+///
+/// # Examples
+///
+/// ```
+/// use std::path::Path;
+/// use syntect::parsing::{ExtensionAndFirstLine, Language, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// // e.g. a classifier that recognizes Rust by its `fn`s
+/// let classifier = |_: Option<&Path>, content: &str| {
+///     content.contains("fn ").then(|| Language::Name("Rust".to_owned()))
+/// };
+///
+/// let content = "fn main() {}\n";
+/// let syntax = ss
+///     .detect_syntax(&ExtensionAndFirstLine, Some(Path::new("main")), content)
+///     .or_else(|| ss.detect_syntax(&classifier, None, content))
+///     .unwrap();
+/// assert_eq!(syntax.name, "Rust");
+/// ```
+///
+/// [`SyntaxSet::detect_syntax`]: struct.SyntaxSet.html#method.detect_syntax
+/// [`ExtensionAndFirstLine`]: struct.ExtensionAndFirstLine.html
+/// [`SyntaxSet::find_syntax_for_file`]: struct.SyntaxSet.html#method.find_syntax_for_file
+pub trait Detection {
+    /// Returns the language of a file with the given path, if it's known, and content, which can
+    /// be the whole file or only its start, or `None` if the detector can't tell
+    ///
+    /// `syntax_set` is the set the language will be looked up in, for detectors that only pick
+    /// among its syntaxes.
+    fn detect(
+        &self,
+        path: Option<&Path>,
+        content: &str,
+        syntax_set: &SyntaxSet,
+    ) -> Option<Language>;
+}
+
+/// Detects the language from the file name or extension, and otherwise from the first line,
+/// like [`SyntaxSet::find_syntax_for_file`]
+///
+/// [`SyntaxSet::find_syntax_for_file`]: struct.SyntaxSet.html#method.find_syntax_for_file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtensionAndFirstLine;
+
+impl Detection for ExtensionAndFirstLine {
+    fn detect(
+        &self,
+        path: Option<&Path>,
+        content: &str,
+        syntax_set: &SyntaxSet,
+    ) -> Option<Language> {
+        let by_path = path.and_then(|path| {
+            let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+            syntax_set
+                .find_syntax_by_extension(file_name)
+                .or_else(|| syntax_set.find_syntax_by_extension(extension))
+        });
+        let first_line = content.lines().next().unwrap_or("");
+        by_path
+            .or_else(|| syntax_set.find_syntax_by_first_line(first_line))
+            .map(|syntax| Language::Name(syntax.name.clone()))
+    }
+}
+
+impl<F> Detection for F
+where
+    F: Fn(Option<&Path>, &str) -> Option<Language>,
+{
+    fn detect(&self, path: Option<&Path>, content: &str, _: &SyntaxSet) -> Option<Language> {
+        self(path, content)
+    }
+}
+
+impl SyntaxSet {
+    /// Finds the syntax for a language returned by a [`Detection`]
+    ///
+    /// A name is looked up as the exact name of a syntax first, and then like
+    /// [`find_syntax_by_token`] does, which also finds names differing in case and file
+    /// extensions like `rs`. A scope is looked up with [`find_syntax_by_scope`].
+    ///
+    /// [`Detection`]: trait.Detection.html
+    /// [`find_syntax_by_token`]: #method.find_syntax_by_token
+    /// [`find_syntax_by_scope`]: #method.find_syntax_by_scope
+    pub fn find_syntax_for_language(&self, language: &Language) -> Option<&SyntaxReference> {
+        match language {
+            Language::Name(name) => self
+                .find_syntax_by_name(name)
+                .or_else(|| self.find_syntax_by_token(name)),
+            Language::Scope(scope) => self.find_syntax_by_scope(*scope),
+        }
+    }
+
+    /// Asks a [`Detection`] for the language of a file and finds its syntax
+    ///
+    /// Returns `None` if the detector can't tell, or if the language it found has no syntax in
+    /// this set, so detectors can be chained with `or_else`, ending with a fallback like
+    /// [`find_syntax_plain_text`].
+    ///
+    /// [`Detection`]: trait.Detection.html
+    /// [`find_syntax_plain_text`]: #method.find_syntax_plain_text
+    pub fn detect_syntax(
+        &self,
+        detector: &dyn Detection,
+        path: Option<&Path>,
+        content: &str,
+    ) -> Option<&SyntaxReference> {
+        let language = detector.detect(path, content, self)?;
+        self.find_syntax_for_language(&language)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    fn syntax_set() -> SyntaxSet {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_plain_text_syntax();
+        for (name, scope, extra) in [
+            ("Shell", "source.shell", "first_line_match: ^#!.*sh\n"),
+            (
+                "Make",
+                "source.makefile",
+                "file_extensions: [mk, Makefile]\n",
+            ),
+        ] {
+            let yaml = format!(
+                "name: {}\nscope: {}\n{}contexts: {{main: []}}\n",
+                name, scope, extra
+            );
+            builder.add(SyntaxDefinition::load_from_str(&yaml, true, None).unwrap());
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn detects_by_file_name_extension_and_first_line() {
+        let ss = syntax_set();
+        let detect = |path: Option<&str>, content| {
+            ss.detect_syntax(&ExtensionAndFirstLine, path.map(Path::new), content)
+                .map(|syntax| syntax.name.as_str())
+        };
+        assert_eq!(detect(Some("dir/Makefile"), ""), Some("Make"));
+        assert_eq!(detect(Some("rules.mk"), "#!/bin/sh\n"), Some("Make"));
+        assert_eq!(detect(Some("build"), "#!/bin/sh\necho\n"), Some("Shell"));
+        assert_eq!(detect(None, "#!/usr/bin/env bash"), Some("Shell"));
+        assert_eq!(detect(Some("notes.md"), "hello\n"), None);
+    }
+
+    #[test]
+    fn resolves_languages_of_other_detectors() {
+        let ss = syntax_set();
+        let by_name = |_: Option<&Path>, _: &str| Some(Language::Name("make".to_owned()));
+        let by_scope =
+            |_: Option<&Path>, _: &str| Some(Language::Scope(Scope::new("source.shell").unwrap()));
+        let unknown = |_: Option<&Path>, _: &str| Some(Language::Name("Rust".to_owned()));
+        assert_eq!(ss.detect_syntax(&by_name, None, "").unwrap().name, "Make");
+        assert_eq!(ss.detect_syntax(&by_scope, None, "").unwrap().name, "Shell");
+        assert!(ss.detect_syntax(&unknown, None, "").is_none());
+        assert_eq!(
+            ss.find_syntax_for_language(&Language::Name("mk".to_owned()))
+                .unwrap()
+                .name,
+            "Make"
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! A small approximation of extended grapheme cluster segmentation ([UAX #29]), used by the
+//! parser to avoid skipping into the middle of a user-perceived character
+//!
+//! This isn't a full implementation: it keeps combining marks of the common scripts, variation
+//! selectors, emoji modifiers and tags with their base character, joins emoji ZWJ sequences and
+//! pairs of regional indicators (flags), and keeps `\r\n` together. Hangul syllable sequences and
+//! spacing marks of most Indic scripts aren't handled.
+//!
+//! [UAX #29]: https://www.unicode.org/reports/tr29/
+
+const ZWJ: char = '\u{200D}';
+
+/// Returns the length in bytes of the grapheme cluster at the start of `s`
+pub(crate) fn cluster_len(s: &str) -> usize {
+    let mut chars = s.char_indices();
+    let first = match chars.next() {
+        Some((_, c)) => c,
+        None => return 0,
+    };
+    if first == '\r' {
+        return if s[1..].starts_with('\n') { 2 } else { 1 };
+    }
+    let mut end = first.len_utf8();
+    let mut prev = first;
+    let mut unpaired_flag = is_regional_indicator(first);
+    for (i, c) in chars {
+        let flag = unpaired_flag && is_regional_indicator(c);
+        if !(is_extend(c) || c == ZWJ || (prev == ZWJ && is_pictographic(c)) || flag) {
+            break;
+        }
+        unpaired_flag = false;
+        end = i + c.len_utf8();
+        prev = c;
+    }
+    end
+}
+
+/// Characters that belong to the cluster of the character before them
+fn is_extend(c: char) -> bool {
+    matches!(c as u32,
+        // Combining Diacritical Marks, and their extensions and supplement
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF
+        // Cyrillic, Hebrew and Arabic marks
+        | 0x0483..=0x0489 | 0x0591..=0x05BD | 0x05BF | 0x05C1..=0x05C2 | 0x05C4..=0x05C5
+        | 0x05C7 | 0x0610..=0x061A | 0x064B..=0x065F | 0x0670 | 0x06D6..=0x06DC
+        | 0x06DF..=0x06E4 | 0x06E7..=0x06E8 | 0x06EA..=0x06ED
+        // Devanagari signs and vowel signs
+        | 0x0900..=0x0903 | 0x093A..=0x093C | 0x093E..=0x094F | 0x0951..=0x0957 | 0x0962..=0x0963
+        // Thai vowels and tone marks
+        | 0x0E31 | 0x0E34..=0x0E3A | 0x0E47..=0x0E4E
+        // Zero width non-joiner, combining marks for symbols and half marks
+        | 0x200C | 0x20D0..=0x20FF | 0xFE20..=0xFE2F
+        // Variation selectors, emoji modifiers and tags
+        | 0xFE00..=0xFE0F | 0xE0100..=0xE01EF | 0x1F3FB..=0x1F3FF | 0xE0020..=0xE007F
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c as u32, 0x1F1E6..=0x1F1FF)
+}
+
+/// Characters that can follow a zero width joiner in an emoji sequence
+fn is_pictographic(c: char) -> bool {
+    matches!(c as u32,
+        0x00A9 | 0x00AE | 0x203C | 0x2049 | 0x2122 | 0x2139 | 0x2194..=0x21AA
+        | 0x231A..=0x23FF | 0x24C2 | 0x25AA..=0x27BF | 0x2934..=0x2935 | 0x2B05..=0x2B55
+        | 0x3030 | 0x303D | 0x3297 | 0x3299 | 0x1F000..=0x1FAFF
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_cluster_lengths() {
+        assert_eq!(cluster_len(""), 0);
+        assert_eq!(cluster_len("ab"), 1);
+        assert_eq!(cluster_len("\u{03C0}x"), 2);
+        assert_eq!(cluster_len("\r\n"), 2);
+        // e + COMBINING ACUTE ACCENT + COMBINING DOT BELOW
+        assert_eq!(cluster_len("e\u{0301}\u{0323}x"), 5);
+        // family: woman, ZWJ, woman, ZWJ, girl
+        let family = "\u{1F469}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(cluster_len(&format!("{}x", family)), family.len());
+        // waving hand with a skin tone modifier
+        assert_eq!(cluster_len("\u{1F44B}\u{1F3FD}x"), 8);
+        // heart with the emoji variation selector
+        assert_eq!(cluster_len("\u{2764}\u{FE0F}x"), 6);
+        // two flags, each a pair of regional indicators
+        assert_eq!(cluster_len("\u{1F1E9}\u{1F1EA}\u{1F1EB}\u{1F1F7}"), 8);
+        // a ZWJ followed by a letter doesn't join it
+        assert_eq!(cluster_len("a\u{200D}b"), 4);
+    }
+}
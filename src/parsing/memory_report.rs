@@ -0,0 +1,268 @@
+//! Estimating how much memory a [`SyntaxSet`] takes, see [`SyntaxSet::memory_footprint`]
+//!
+//! The contexts of each syntax are kept compressed until the syntax is first used, so a syntax set
+//! grows as it parses text in more languages. The sizes here are estimates of the heap memory of
+//! the data structures, from the capacities of their vectors, strings and maps. They leave out the
+//! allocator's overhead and the compiled regexes, whose size the regex engines don't report, which
+//! is why the number of compiled regexes is given instead.
+//!
+//! [`SyntaxSet`]: ../struct.SyntaxSet.html
+//! [`SyntaxSet::memory_footprint`]: ../struct.SyntaxSet.html#method.memory_footprint
+
+use super::syntax_definition::{Context, ContextReference, MatchOperation, Pattern};
+use super::syntax_set::LazyContexts;
+use super::{SyntaxReference, SyntaxSet, SCOPE_REPO};
+use std::collections::HashMap;
+use std::mem::size_of;
+
+/// How much memory a syntax set takes, returned by [`SyntaxSet::memory_footprint`]
+///
+/// [`SyntaxSet::memory_footprint`]: ../struct.SyntaxSet.html#method.memory_footprint
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct MemoryReport {
+    /// One entry for each syntax, in the order of [`SyntaxSet::syntaxes`]
+    ///
+    /// [`SyntaxSet::syntaxes`]: ../struct.SyntaxSet.html#method.syntaxes
+    pub syntaxes: Vec<SyntaxMemory>,
+    /// The number of atoms in the global scope repository, which is shared by all syntax sets
+    pub scope_atoms: usize,
+    /// The estimated size of the global scope repository in bytes
+    pub scope_repo_bytes: usize,
+}
+
+/// How much memory the contexts of one syntax take, see [`MemoryReport`]
+///
+/// [`MemoryReport`]: struct.MemoryReport.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SyntaxMemory {
+    /// The name of the syntax
+    pub name: String,
+    /// The size of the compressed contexts in bytes, which are kept after loading them too
+    pub serialized_bytes: usize,
+    /// The estimated size of the contexts in bytes once they're loaded, or `None` if the syntax
+    /// hasn't been used yet
+    pub deserialized_bytes: Option<usize>,
+    /// The number of match patterns in the contexts, 0 if they aren't loaded
+    pub patterns: usize,
+    /// How many of the patterns have had their regex compiled, which happens when the parser first
+    /// searches with them
+    pub compiled_regexes: usize,
+}
+
+impl MemoryReport {
+    /// The size of the compressed contexts of all syntaxes in bytes
+    pub fn serialized_bytes(&self) -> usize {
+        self.syntaxes.iter().map(|s| s.serialized_bytes).sum()
+    }
+
+    /// The estimated size of the loaded contexts of all syntaxes in bytes
+    pub fn deserialized_bytes(&self) -> usize {
+        self.syntaxes
+            .iter()
+            .filter_map(|s| s.deserialized_bytes)
+            .sum()
+    }
+
+    /// The number of syntaxes whose contexts are loaded
+    pub fn loaded_syntaxes(&self) -> usize {
+        self.syntaxes
+            .iter()
+            .filter(|s| s.deserialized_bytes.is_some())
+            .count()
+    }
+
+    /// The number of compiled regexes in all syntaxes
+    pub fn compiled_regexes(&self) -> usize {
+        self.syntaxes.iter().map(|s| s.compiled_regexes).sum()
+    }
+
+    /// The estimated total in bytes, of the contexts in both forms and of the scope repository
+    pub fn total_bytes(&self) -> usize {
+        self.serialized_bytes() + self.deserialized_bytes() + self.scope_repo_bytes
+    }
+}
+
+impl SyntaxSet {
+    /// Estimates how much memory the syntaxes take, without loading any of them
+    ///
+    /// This is meant for applications on platforms with little memory, to see what loading more
+    /// syntaxes costs and whether it's worth building a smaller syntax set with only the ones they
+    /// need. See the [`memory_report`] module for what the estimates include.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let before = ss.memory_footprint();
+    /// assert_eq!(before.loaded_syntaxes(), 0);
+    ///
+    /// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    /// ParseState::new(syntax).parse_line("fn main() {}\n", &ss).unwrap();
+    ///
+    /// let after = ss.memory_footprint();
+    /// assert!(after.loaded_syntaxes() >= 1);
+    /// assert!(after.compiled_regexes() > 0);
+    /// assert!(after.total_bytes() > before.total_bytes());
+    /// ```
+    ///
+    /// [`memory_report`]: memory_report/index.html
+    pub fn memory_footprint(&self) -> MemoryReport {
+        let (scope_atoms, scope_repo_bytes) = {
+            let repo = SCOPE_REPO.lock().unwrap();
+            (repo.len(), repo.heap_size())
+        };
+        MemoryReport {
+            syntaxes: self.syntaxes().iter().map(syntax_memory).collect(),
+            scope_atoms,
+            scope_repo_bytes,
+        }
+    }
+}
+
+fn syntax_memory(syntax: &SyntaxReference) -> SyntaxMemory {
+    let mut memory = SyntaxMemory {
+        name: syntax.name.clone(),
+        serialized_bytes: syntax.serialized_lazy_contexts.len(),
+        ..SyntaxMemory::default()
+    };
+    if let Some(lazy_contexts) = syntax.lazy_contexts.get() {
+        memory.deserialized_bytes = Some(lazy_contexts_size(lazy_contexts));
+        for pattern in lazy_contexts.contexts.iter().flat_map(|c| &c.patterns) {
+            if let Pattern::Match(match_pat) = pattern {
+                memory.patterns += 1;
+                if match_pat.regex.is_compiled() {
+                    memory.compiled_regexes += 1;
+                }
+            }
+        }
+    }
+    memory
+}
+
+fn lazy_contexts_size(lazy_contexts: &LazyContexts) -> usize {
+    let names: usize = lazy_contexts.context_ids.keys().map(String::capacity).sum();
+    map_size(&lazy_contexts.context_ids)
+        + names
+        + vec_size(&lazy_contexts.contexts)
+        + lazy_contexts
+            .contexts
+            .iter()
+            .map(context_size)
+            .sum::<usize>()
+}
+
+fn context_size(context: &Context) -> usize {
+    let patterns: usize = context
+        .patterns
+        .iter()
+        .map(|pattern| match pattern {
+            Pattern::Match(match_pat) => {
+                let captures = match_pat.captures.as_ref().map_or(0, |captures| {
+                    vec_size(captures)
+                        + captures
+                            .iter()
+                            .map(|(_, scopes)| vec_size(scopes))
+                            .sum::<usize>()
+                });
+                let operation = match &match_pat.operation {
+                    MatchOperation::Push(refs) | MatchOperation::Set(refs) => {
+                        vec_size(refs) + refs.iter().map(reference_size).sum::<usize>()
+                    }
+                    MatchOperation::Pop | MatchOperation::None => 0,
+                };
+                match_pat.regex.regex_str().len()
+                    + vec_size(&match_pat.scope)
+                    + captures
+                    + operation
+                    + match_pat.with_prototype.as_ref().map_or(0, reference_size)
+            }
+            Pattern::Include(reference) => reference_size(reference),
+        })
+        .sum();
+    vec_size(&context.meta_scope)
+        + vec_size(&context.meta_content_scope)
+        + vec_size(&context.patterns)
+        + patterns
+}
+
+fn reference_size(reference: &ContextReference) -> usize {
+    match reference {
+        ContextReference::Named(name) | ContextReference::Inline(name) => name.capacity(),
+        ContextReference::ByScope { sub_context, .. } => {
+            sub_context.as_ref().map_or(0, String::capacity)
+        }
+        ContextReference::File {
+            name, sub_context, ..
+        } => name.capacity() + sub_context.as_ref().map_or(0, String::capacity),
+        ContextReference::Direct(_) => 0,
+        ContextReference::Prototype(prototype) => {
+            size_of::<ContextReference>() + reference_size(prototype)
+        }
+    }
+}
+
+fn vec_size<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+/// The buckets of a `HashMap`, with one byte of control data each
+pub(crate) fn map_size<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parsing::{ParseState, SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn reports_loaded_contexts_and_compiled_regexes() {
+        let yaml = r"
+name: words
+scope: source.words
+file_extensions: [words]
+contexts:
+  main:
+    - match: '\d+'
+      scope: constant.numeric
+    - match: '\('
+      push: group
+  group:
+    - meta_scope: meta.group
+    - match: '\)'
+      pop: true
+";
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(SyntaxDefinition::load_from_str(yaml, true, None).unwrap());
+        let ss = builder.build();
+
+        let report = ss.memory_footprint();
+        assert_eq!(report.syntaxes.len(), 1);
+        let words = &report.syntaxes[0];
+        assert_eq!(words.name, "words");
+        assert!(words.serialized_bytes > 0);
+        assert_eq!(words.deserialized_bytes, None);
+        assert_eq!(words.patterns, 0);
+        assert_eq!(report.loaded_syntaxes(), 0);
+        assert!(report.scope_atoms > 0);
+        assert!(report.scope_repo_bytes > 0);
+
+        let syntax = ss.find_syntax_by_name("words").unwrap();
+        ParseState::new(syntax).parse_line("12 (\n", &ss).unwrap();
+        let report = ss.memory_footprint();
+        let words = &report.syntaxes[0];
+        assert!(words.deserialized_bytes.unwrap() > words.serialized_bytes);
+        // with the empty match that pushes `main` from the generated `__start` context
+        assert_eq!(words.patterns, 4);
+        // the line has no `)`, so the pattern that pops isn't searched with
+        assert_eq!(words.compiled_regexes, 3);
+        assert_eq!(report.loaded_syntaxes(), 1);
+        assert_eq!(
+            report.total_bytes(),
+            report.serialized_bytes() + report.deserialized_bytes() + report.scope_repo_bytes
+        );
+    }
+}
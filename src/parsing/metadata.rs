@@ -4,6 +4,7 @@
 use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::ops::Range;
 use std::path::PathBuf;
 use std::str::FromStr;
 
@@ -16,6 +17,7 @@ use super::super::highlighting::ScopeSelectors;
 use super::super::LoadingError;
 use super::regex::Regex;
 use super::scope::{MatchPower, Scope};
+use crate::utils::DumpFormat;
 
 type Dict = serde_json::Map<String, Settings>;
 
@@ -25,7 +27,7 @@ type Dict = serde_json::Map<String, Settings>;
 type SelectorString = String;
 
 /// A collection of all loaded metadata
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone)]
 pub struct Metadata {
     pub scoped_metadata: Vec<MetadataSet>,
 }
@@ -66,6 +68,13 @@ pub struct MetadataItems {
     /// The first pair of `TM_COMMENT_START` and `TM_COMMENT_END` items in
     /// `shell_variables`, if they exist.
     pub block_comment: Option<(String, String)>,
+    /// Characters that are part of words even if they're separators, like `-` in CSS
+    pub word_characters: Option<String>,
+    /// The characters that separate words, instead of [`DEFAULT_WORD_SEPARATORS`]
+    ///
+    /// [`DEFAULT_WORD_SEPARATORS`]: constant.DEFAULT_WORD_SEPARATORS.html
+    #[serde(alias = "word_separators")]
+    pub word_separators: Option<String>,
 }
 
 /// A type that can be deserialized from a `.tmPreferences` file.
@@ -93,8 +102,15 @@ const KEYS_WE_USE: &[&str] = &[
     "unIndentedLinePattern",
     "indentParens",
     "shellVariables",
+    "wordCharacters",
+    "wordSeparators",
+    "word_separators",
 ];
 
+/// The characters that separate words when the metadata doesn't say otherwise, the same as
+/// Sublime Text's default `word_separators` setting
+pub const DEFAULT_WORD_SEPARATORS: &str = "./\\()\"'-:,.;<>~!@#$%^&*|+=[]{}`~?";
+
 impl LoadMetadata {
     /// Adds the provided `RawMetadataEntry`
     ///
@@ -210,6 +226,35 @@ impl Metadata {
         }
     }
 
+    /// For a given stack of scopes, returns what separates words in it, for selecting a word on
+    /// double click or moving the cursor by words the way the language expects
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use] extern crate serde_json;
+    /// use syntect::parsing::{Metadata, MetadataSet, Scope};
+    ///
+    /// let items = json!({ "wordCharacters": "-" });
+    /// # let items = items.as_object().cloned().unwrap();
+    /// let css = MetadataSet::from_raw(("source.css".into(), items)).unwrap();
+    /// let metadata = Metadata { scoped_metadata: vec![css] };
+    ///
+    /// let scope = [Scope::new("source.css").unwrap()];
+    /// let boundaries = metadata.word_boundaries_for_scope(&scope);
+    /// assert_eq!(boundaries.split_words("font-size: 2px;"), [0..9, 11..14]);
+    /// ```
+    pub fn word_boundaries_for_scope(&self, scope: &[Scope]) -> WordBoundaries {
+        let scoped = self.metadata_for_scope(scope);
+        WordBoundaries {
+            separators: scoped
+                .word_separators()
+                .unwrap_or(DEFAULT_WORD_SEPARATORS)
+                .to_owned(),
+            word_characters: scoped.word_characters().unwrap_or("").to_owned(),
+        }
+    }
+
     pub(crate) fn merged_with_raw(self, raw: LoadMetadata) -> Metadata {
         let Metadata {
             mut scoped_metadata,
@@ -407,6 +452,22 @@ impl<'a> ScopedMetadata<'a> {
             .map(|(a, b)| (a.as_str(), b.as_str()))
     }
 
+    pub fn word_characters(&self) -> Option<&str> {
+        let idx = self
+            .items
+            .iter()
+            .position(|m| m.1.items.word_characters.is_some())?;
+        self.items[idx].1.items.word_characters.as_deref()
+    }
+
+    pub fn word_separators(&self) -> Option<&str> {
+        let idx = self
+            .items
+            .iter()
+            .position(|m| m.1.items.word_separators.is_some())?;
+        self.items[idx].1.items.word_separators.as_deref()
+    }
+
     fn best_match<T, F>(&self, f: F) -> Option<T>
     where
         F: FnMut(&MetadataItems) -> Option<T>,
@@ -423,6 +484,55 @@ impl<'a> ScopedMetadata<'a> {
     }
 }
 
+/// What separates words in some scope, returned by [`Metadata::word_boundaries_for_scope`]
+///
+/// Like in Sublime Text, words are runs of characters that are neither whitespace nor word
+/// separators, so e.g. `foo_bar` and `über` are one word each. Characters listed in
+/// `wordCharacters` are part of words even if they're also separators.
+///
+/// [`Metadata::word_boundaries_for_scope`]: struct.Metadata.html#method.word_boundaries_for_scope
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordBoundaries {
+    pub separators: String,
+    pub word_characters: String,
+}
+
+impl Default for WordBoundaries {
+    fn default() -> WordBoundaries {
+        WordBoundaries {
+            separators: DEFAULT_WORD_SEPARATORS.to_owned(),
+            word_characters: String::new(),
+        }
+    }
+}
+
+impl WordBoundaries {
+    /// Whether the character is part of words
+    pub fn is_word_char(&self, c: char) -> bool {
+        self.word_characters.contains(c) || !(c.is_whitespace() || self.separators.contains(c))
+    }
+
+    /// The byte ranges of the words in a line, in order
+    pub fn split_words(&self, line: &str) -> Vec<Range<usize>> {
+        let mut words = Vec::new();
+        let mut start = None;
+        for (i, c) in line.char_indices() {
+            match (self.is_word_char(c), start) {
+                (true, None) => start = Some(i),
+                (false, Some(s)) => {
+                    words.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(s) = start {
+            words.push(s..line.len());
+        }
+        words
+    }
+}
+
 impl RawMetadataEntry {
     pub fn load<P: Into<PathBuf>>(path: P) -> Result<Self, LoadingError> {
         let path: PathBuf = path.into();
@@ -439,6 +549,26 @@ impl RawMetadataEntry {
     }
 }
 
+// Serialized after the dump format, so that dumps of other versions of syntect are rejected
+impl Serialize for Metadata {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        (DumpFormat, &self.scoped_metadata).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Metadata {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let (DumpFormat, scoped_metadata) = Deserialize::deserialize(deserializer)?;
+        Ok(Metadata { scoped_metadata })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct MetaSetSerializable {
     selector_string: String,
@@ -594,4 +724,41 @@ mod tests {
         assert!(!indent_ctx.decrease_indent("struct This {}"));
         assert!(!indent_ctx.increase_indent("struct This {}"));
     }
+
+    #[test]
+    fn word_boundaries_follow_the_best_match() {
+        let set = |selector: &str, items: serde_json::Value| {
+            MetadataSet::from_raw((selector.into(), items.as_object().cloned().unwrap())).unwrap()
+        };
+        let metadata = Metadata {
+            scoped_metadata: vec![
+                set("source.css", serde_json::json!({ "wordCharacters": "-" })),
+                set(
+                    "source.css string",
+                    serde_json::json!({ "word_separators": " ./" }),
+                ),
+            ],
+        };
+        let scopes = |s: &str| -> Vec<Scope> {
+            s.split(' ')
+                .map(|scope| Scope::new(scope).unwrap())
+                .collect()
+        };
+
+        let plain = metadata.word_boundaries_for_scope(&scopes("source.rust"));
+        assert_eq!(plain, WordBoundaries::default());
+        assert_eq!(
+            plain.split_words("a.b_c(é1) -x"),
+            [0..1, 2..5, 6..9, 12..13]
+        );
+
+        let css = metadata.word_boundaries_for_scope(&scopes("source.css"));
+        assert_eq!(css.split_words(".a-b{x:-1}"), [1..4, 5..6, 7..9]);
+
+        // the string sets its own separators and still gets `-` from the less specific match
+        let string = metadata.word_boundaries_for_scope(&scopes("source.css string.quoted"));
+        assert_eq!(string.separators, " ./");
+        assert_eq!(string.word_characters, "-");
+        assert_eq!(string.split_words("a/b(c) d-e"), [0..1, 2..6, 7..10]);
+    }
 }
@@ -4,11 +4,28 @@
 //!
 //! [`SyntaxSet`]: struct.SyntaxSet.html
 
+#[cfg(feature = "parsing")]
+mod combined;
+#[cfg(feature = "parsing")]
+mod detection;
+#[cfg(feature = "parsing")]
+mod grapheme;
+#[cfg(feature = "memory-report")]
+pub mod memory_report;
 #[cfg(feature = "metadata")]
 pub mod metadata;
+pub mod ops_codec;
 #[cfg(feature = "parsing")]
 mod parser;
 #[cfg(feature = "parsing")]
+mod prefilter;
+#[cfg(feature = "parsing")]
+mod scope_source;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
+pub mod simple;
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
+mod syntax_builder;
+#[cfg(feature = "parsing")]
 pub mod syntax_definition;
 #[cfg(feature = "parsing")]
 mod syntax_set;
@@ -18,12 +35,21 @@ mod yaml_load;
 #[cfg(any(feature = "parsing", feature = "yaml-load", feature = "metadata"))]
 mod regex;
 mod scope;
+mod token_kind;
 
+#[cfg(feature = "parsing")]
+pub use self::detection::{Detection, ExtensionAndFirstLine, Language};
+#[cfg(feature = "memory-report")]
+pub use self::memory_report::{MemoryReport, SyntaxMemory};
 #[cfg(feature = "metadata")]
 pub use self::metadata::*;
 #[cfg(feature = "parsing")]
 pub use self::parser::*;
 #[cfg(feature = "parsing")]
+pub use self::scope_source::{locate_scope_source, ScopeSource};
+#[cfg(all(feature = "parsing", feature = "yaml-load"))]
+pub use self::syntax_builder::{ContextBuilder, MatchPatternBuilder, SyntaxDefinitionBuilder};
+#[cfg(feature = "parsing")]
 pub use self::syntax_definition::SyntaxDefinition;
 #[cfg(feature = "parsing")]
 pub use self::syntax_set::*;
@@ -34,3 +60,4 @@ pub use self::yaml_load::*;
 pub use self::regex::*;
 
 pub use self::scope::*;
+pub use self::token_kind::*;
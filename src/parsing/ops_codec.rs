@@ -0,0 +1,294 @@
+//! A compact binary encoding of the operations returned by [`ParseState::parse_line`], for
+//! applications that store parse results, like code search indexes
+//!
+//! Scopes are stored once each, as strings, since the numbers a [`Scope`] is made of depend on
+//! the order a process first sees the scopes in. Everything else is a varint: the distance of each
+//! operation from the previous one on its line, and the kind of operation together with its
+//! argument, so most operations take two or three bytes.
+//!
+//! [`ParseState::parse_line`]: ../struct.ParseState.html#method.parse_line
+//! [`Scope`]: ../struct.Scope.html
+
+use super::{ClearAmount, ParseScopeError, Scope, ScopeStackOp};
+use std::collections::HashMap;
+
+/// An error decoding operations, when the data wasn't created by [`encode`] or [`encode_lines`]
+///
+/// [`encode`]: fn.encode.html
+/// [`encode_lines`]: fn.encode_lines.html
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DecodeError {
+    /// The data ended in the middle of a value
+    #[error("Unexpected end of data")]
+    UnexpectedEnd,
+    /// The value starting at this byte offset is invalid
+    #[error("Invalid data at byte {0}")]
+    Invalid(usize),
+    /// A scope couldn't be added to the scope repository
+    #[error("Invalid scope: {0}")]
+    InvalidScope(#[from] ParseScopeError),
+}
+
+const PUSH: u64 = 0;
+const POP: u64 = 1;
+const CLEAR: u64 = 2;
+const CLEAR_ALL: u64 = 3;
+const RESTORE: u64 = 4;
+const NOOP: u64 = 5;
+
+/// Encodes the operations of one line
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ops_codec, ParseState, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let ops = state.parse_line("fn main() { println!(\"hi\"); }\n", &ss).unwrap();
+///
+/// let bytes = ops_codec::encode(&ops);
+/// assert_eq!(ops_codec::decode(&bytes).unwrap(), ops);
+/// ```
+pub fn encode(ops: &[(usize, ScopeStackOp)]) -> Vec<u8> {
+    let (mut out, table) = start_encoding(&[ops]);
+    encode_ops(&mut out, &table, ops);
+    out
+}
+
+/// Encodes the operations of several lines, like those of a whole file, storing each scope only
+/// once for all of them
+pub fn encode_lines<L: AsRef<[(usize, ScopeStackOp)]>>(lines: &[L]) -> Vec<u8> {
+    let lines: Vec<&[(usize, ScopeStackOp)]> = lines.iter().map(AsRef::as_ref).collect();
+    let (mut out, table) = start_encoding(&lines);
+    write_varint(&mut out, lines.len() as u64);
+    for ops in lines {
+        encode_ops(&mut out, &table, ops);
+    }
+    out
+}
+
+/// Decodes the operations of a line encoded with [`encode`]
+///
+/// [`encode`]: fn.encode.html
+pub fn decode(bytes: &[u8]) -> Result<Vec<(usize, ScopeStackOp)>, DecodeError> {
+    let mut decoder = Decoder::new(bytes)?;
+    let ops = decoder.ops()?;
+    decoder.finish()?;
+    Ok(ops)
+}
+
+/// Decodes the operations of lines encoded with [`encode_lines`]
+///
+/// [`encode_lines`]: fn.encode_lines.html
+pub fn decode_lines(bytes: &[u8]) -> Result<Vec<Vec<(usize, ScopeStackOp)>>, DecodeError> {
+    let mut decoder = Decoder::new(bytes)?;
+    let count = decoder.varint()?;
+    let lines = (0..count)
+        .map(|_| decoder.ops())
+        .collect::<Result<_, _>>()?;
+    decoder.finish()?;
+    Ok(lines)
+}
+
+/// Writes the table of the scopes pushed in the lines, and returns their indices in it
+fn start_encoding(lines: &[&[(usize, ScopeStackOp)]]) -> (Vec<u8>, HashMap<Scope, u64>) {
+    let mut table = HashMap::new();
+    let mut names = Vec::new();
+    for (_, op) in lines.iter().copied().flatten() {
+        if let ScopeStackOp::Push(scope) = op {
+            table.entry(*scope).or_insert_with(|| {
+                names.push(scope.build_string());
+                names.len() as u64 - 1
+            });
+        }
+    }
+    let mut out = Vec::new();
+    write_varint(&mut out, names.len() as u64);
+    for name in names {
+        write_varint(&mut out, name.len() as u64);
+        out.extend_from_slice(name.as_bytes());
+    }
+    (out, table)
+}
+
+fn encode_ops(out: &mut Vec<u8>, table: &HashMap<Scope, u64>, ops: &[(usize, ScopeStackOp)]) {
+    write_varint(out, ops.len() as u64);
+    let mut pos = 0u64;
+    for (i, op) in ops {
+        // the parser's positions only go up, but wrapping keeps any others decodable
+        write_varint(out, (*i as u64).wrapping_sub(pos));
+        pos = *i as u64;
+        let (kind, arg) = match op {
+            ScopeStackOp::Push(scope) => (PUSH, table[scope]),
+            ScopeStackOp::Pop(n) => (POP, *n as u64),
+            ScopeStackOp::Clear(ClearAmount::TopN(n)) => (CLEAR, *n as u64),
+            ScopeStackOp::Clear(ClearAmount::All) => (CLEAR_ALL, 0),
+            ScopeStackOp::Restore => (RESTORE, 0),
+            ScopeStackOp::Noop => (NOOP, 0),
+        };
+        write_varint(out, arg << 3 | kind);
+    }
+}
+
+/// Writes 7 bits at a time, lowest first, with the high bit set on all bytes but the last
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    scopes: Vec<Scope>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Reads the table of scopes at the start of the data
+    fn new(bytes: &'a [u8]) -> Result<Decoder<'a>, DecodeError> {
+        let mut decoder = Decoder {
+            bytes,
+            pos: 0,
+            scopes: Vec::new(),
+        };
+        let count = decoder.varint()?;
+        for _ in 0..count {
+            let len = decoder.varint()?;
+            let start = decoder.pos;
+            let name = decoder.take(len)?;
+            let name = std::str::from_utf8(name).map_err(|_| DecodeError::Invalid(start))?;
+            decoder.scopes.push(Scope::new(name)?);
+        }
+        Ok(decoder)
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let start = self.pos;
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = *self.bytes.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+            self.pos += 1;
+            if shift >= 64 {
+                return Err(DecodeError::Invalid(start));
+            }
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    fn take(&mut self, len: u64) -> Result<&'a [u8], DecodeError> {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.pos.checked_add(len))
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn ops(&mut self) -> Result<Vec<(usize, ScopeStackOp)>, DecodeError> {
+        let count = self.varint()?;
+        // not preallocated, since the count could be anything in invalid data
+        let mut ops = Vec::new();
+        let mut pos = 0u64;
+        for _ in 0..count {
+            pos = pos.wrapping_add(self.varint()?);
+            let start = self.pos;
+            let value = self.varint()?;
+            let arg = value >> 3;
+            let op = match value & 0b111 {
+                PUSH => {
+                    let scope = usize::try_from(arg).ok().and_then(|i| self.scopes.get(i));
+                    ScopeStackOp::Push(*scope.ok_or(DecodeError::Invalid(start))?)
+                }
+                POP => ScopeStackOp::Pop(arg as usize),
+                CLEAR => ScopeStackOp::Clear(ClearAmount::TopN(arg as usize)),
+                CLEAR_ALL => ScopeStackOp::Clear(ClearAmount::All),
+                RESTORE => ScopeStackOp::Restore,
+                NOOP => ScopeStackOp::Noop,
+                _ => return Err(DecodeError::Invalid(start)),
+            };
+            ops.push((pos as usize, op));
+        }
+        Ok(ops)
+    }
+
+    fn finish(&self) -> Result<(), DecodeError> {
+        if self.pos == self.bytes.len() {
+            Ok(())
+        } else {
+            Err(DecodeError::Invalid(self.pos))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_all_operations() {
+        let a = Scope::new("source.codec").unwrap();
+        let b = Scope::new("string.quoted.double.codec").unwrap();
+        let lines = vec![
+            vec![
+                (0, ScopeStackOp::Push(a)),
+                (3, ScopeStackOp::Push(b)),
+                (300, ScopeStackOp::Pop(1)),
+                (300, ScopeStackOp::Clear(ClearAmount::TopN(2))),
+                (301, ScopeStackOp::Restore),
+                (2, ScopeStackOp::Noop),
+            ],
+            vec![],
+            vec![
+                (0, ScopeStackOp::Clear(ClearAmount::All)),
+                (1, ScopeStackOp::Push(b)),
+            ],
+        ];
+        let bytes = encode_lines(&lines);
+        assert_eq!(decode_lines(&bytes).unwrap(), lines);
+        // the scope pushed on two lines is stored once
+        let name = b"string.quoted.double.codec";
+        let count = bytes.windows(name.len()).filter(|w| w == name).count();
+        assert_eq!(count, 1);
+
+        let bytes = encode(&lines[0]);
+        assert_eq!(decode(&bytes).unwrap(), lines[0]);
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        let scope = Scope::new("source.codec").unwrap();
+        let bytes = encode(&[(5, ScopeStackOp::Push(scope))]);
+        for len in 0..bytes.len() {
+            assert!(matches!(
+                decode(&bytes[..len]),
+                Err(DecodeError::UnexpectedEnd)
+            ));
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            decode(&trailing),
+            Err(DecodeError::Invalid(pos)) if pos == bytes.len()
+        ));
+        // a push of a scope that isn't in the table
+        assert!(matches!(
+            decode(&[0, 1, 0, 0b1000]),
+            Err(DecodeError::Invalid(3))
+        ));
+        assert!(matches!(
+            decode(&[0, 1, 0, 7]),
+            Err(DecodeError::Invalid(3))
+        ));
+    }
+}
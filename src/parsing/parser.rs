@@ -10,15 +10,21 @@
 // See https://github.com/rust-lang/rust/blob/1.54.0/library/core/src/hash/mod.rs#L717-L725
 #![allow(clippy::mutable_key_type)]
 
-use super::regex::Region;
+use super::combined::{Run, Unit};
+use super::grapheme;
+use super::regex::{Regex, Region};
 use super::scope::*;
 use super::syntax_definition::*;
 use crate::parsing::syntax_definition::ContextId;
 use crate::parsing::syntax_set::{SyntaxReference, SyntaxSet};
 use fnv::FnvHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::hash::BuildHasherDefault;
 use std::i32;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::usize;
 
 /// Errors that can occur while parsing.
@@ -33,8 +39,96 @@ pub enum ParsingError {
     MissingContext(ContextId),
     #[error("Bad index to match_at: {0}")]
     BadMatchIndex(usize),
+    /// A match pushes or sets a context that wasn't found when the syntax set was built, see
+    /// [`ParseState::set_unresolved_context_policy`] for parsing on without it
+    ///
+    /// [`ParseState::set_unresolved_context_policy`]: struct.ParseState.html#method.set_unresolved_context_policy
     #[error("Tried to use a ContextReference that has not bee resolved yet: {0:?}")]
     UnresolvedContextReference(ContextReference),
+    /// The operations returned by the parser don't make sense, e.g. they pop more scopes than
+    /// were pushed. Only checked with the `paranoid` feature, see [`ParseState::check_balanced`].
+    ///
+    /// [`ParseState::check_balanced`]: struct.ParseState.html#method.check_balanced
+    #[error("Parser invariant violated: {0}")]
+    BrokenInvariant(String),
+    /// A regex backtracked more than the limit set with [`ParseState::set_backtrack_limit`] while
+    /// parsing the line. Holds the pattern of the regex.
+    ///
+    /// [`ParseState::set_backtrack_limit`]: struct.ParseState.html#method.set_backtrack_limit
+    #[error("The regex '{0}' backtracked too much")]
+    BacktrackLimitExceeded(String),
+    /// The syntax set passed to the parser doesn't have the syntax the [`ParseState`] was created
+    /// for at the same place, so it isn't the syntax set of that syntax or an extended version of
+    /// it
+    ///
+    /// [`ParseState`]: struct.ParseState.html
+    #[error("The syntax set doesn't contain the syntax the parse state was created for")]
+    SyntaxSetMismatch,
+    /// A match would have pushed more contexts on the stack than the limit set with
+    /// [`ParseState::set_max_stack_depth`]
+    ///
+    /// [`ParseState::set_max_stack_depth`]: struct.ParseState.html#method.set_max_stack_depth
+    #[error("The context stack grew deeper than {0}")]
+    StackTooDeep(usize),
+    /// Parsing the line took longer than the timeout set with [`ParseState::set_regex_timeout`]
+    ///
+    /// [`ParseState::set_regex_timeout`]: struct.ParseState.html#method.set_regex_timeout
+    #[error("Parsing the line took longer than {0:?}")]
+    Timeout(Duration),
+}
+
+/// What the parser does with a match that pushes or sets a context that wasn't found when the
+/// syntax set was built, see [`ParseState::set_unresolved_context_policy`]
+///
+/// [`ParseState::set_unresolved_context_policy`]: struct.ParseState.html#method.set_unresolved_context_policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnresolvedContextPolicy {
+    /// Fail with [`ParsingError::UnresolvedContextReference`], the default
+    ///
+    /// [`ParsingError::UnresolvedContextReference`]: enum.ParsingError.html#variant.UnresolvedContextReference
+    #[default]
+    Error,
+    /// Push the `main` context of the Plain Text syntax instead, like an `embed` with an `escape`
+    /// does when the embedded syntax is missing, and leave out a `with_prototype` that wasn't
+    /// found. Fails like `Error` if the syntax set has no Plain Text syntax.
+    PlainText,
+    /// Pass over the pattern, as if it didn't match
+    SkipPattern,
+}
+
+/// Settings that change how a [`ParseState`] parses, see the setters of [`ParseState`]
+///
+/// They're not part of the state of the parser, so two states with different settings compare
+/// equal when they're at the same place in the syntax. This makes it possible to e.g. compare a
+/// cached state with one that parses with a limit.
+///
+/// [`ParseState`]: struct.ParseState.html
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParseSettings {
+    /// See [`ParseState::set_skip_by_grapheme_cluster`]
+    ///
+    /// [`ParseState::set_skip_by_grapheme_cluster`]: struct.ParseState.html#method.set_skip_by_grapheme_cluster
+    pub skip_by_grapheme_cluster: bool,
+    /// See [`ParseState::set_backtrack_limit`]
+    ///
+    /// [`ParseState::set_backtrack_limit`]: struct.ParseState.html#method.set_backtrack_limit
+    pub backtrack_limit: Option<u32>,
+    /// See [`ParseState::set_regex_timeout`]
+    ///
+    /// [`ParseState::set_regex_timeout`]: struct.ParseState.html#method.set_regex_timeout
+    pub regex_timeout: Option<Duration>,
+    /// See [`ParseState::set_max_stack_depth`]
+    ///
+    /// [`ParseState::set_max_stack_depth`]: struct.ParseState.html#method.set_max_stack_depth
+    pub max_stack_depth: Option<usize>,
+    /// See [`ParseState::set_combined_search`]
+    ///
+    /// [`ParseState::set_combined_search`]: struct.ParseState.html#method.set_combined_search
+    pub combined_search: bool,
+    /// See [`ParseState::set_unresolved_context_policy`]
+    ///
+    /// [`ParseState::set_unresolved_context_policy`]: struct.ParseState.html#method.set_unresolved_context_policy
+    pub unresolved_context_policy: UnresolvedContextPolicy,
 }
 
 /// Keeps the current parser state (the internal syntax interpreter stack) between lines of parsing.
@@ -55,21 +149,163 @@ pub enum ParsingError {
 /// **Note:** Caching is for advanced users who have tons of time to maximize performance or want to do so eventually.
 /// It is not recommended that you try caching the first time you implement highlighting.
 ///
+/// States compare equal when they're at the same place in the syntax, whatever their
+/// [`ParseSettings`] are and whatever [`unresolved_contexts`] they came across to get there.
+///
 /// [`HighlightState`]: ../highlighting/struct.HighlightState.html
-#[derive(Debug, Clone, Eq, PartialEq)]
+/// [`ParseSettings`]: struct.ParseSettings.html
+/// [`unresolved_contexts`]: #method.unresolved_contexts
+#[derive(Debug, Clone)]
 pub struct ParseState {
     stack: Vec<StateLevel>,
     first_line: bool,
     // See issue #101. Contains indices of frames pushed by `with_prototype`s.
     // Doesn't look at `with_prototype`s below top of stack.
     proto_starts: Vec<usize>,
+    settings: ParseSettings,
+    // What parsing the current line changed, to go back to the state before it when the line
+    // fails part way through
+    undo: LineUndo,
+    // The unresolved references the parser came across, each once, in the order it did
+    unresolved_contexts: Vec<ContextReference>,
+    // Where the syntax the state was created for is in its syntax set, and its id, to notice when
+    // another syntax set is passed in
+    syntax_index: usize,
+    syntax_id: u64,
+    // The scopes the returned ops result in so far, to check the ops of each line against
+    #[cfg(feature = "paranoid")]
+    checked_scopes: ScopeStack,
+}
+
+impl PartialEq for ParseState {
+    fn eq(&self, other: &ParseState) -> bool {
+        let ParseState {
+            stack,
+            first_line,
+            proto_starts,
+            settings: _,
+            undo: _,
+            unresolved_contexts: _,
+            syntax_index: _,
+            syntax_id: _,
+            #[cfg(feature = "paranoid")]
+            checked_scopes,
+        } = self;
+        *stack == other.stack
+            && *first_line == other.first_line
+            && *proto_starts == other.proto_starts
+            && {
+                #[cfg(feature = "paranoid")]
+                {
+                    *checked_scopes == other.checked_scopes
+                }
+                #[cfg(not(feature = "paranoid"))]
+                true
+            }
+    }
+}
+
+impl Eq for ParseState {}
+
+/// The changes to a stack since [`start`] was called, for undoing them. Only the elements that
+/// were on the stack before and got popped are kept, since the ones above the lowest the stack
+/// got to were all pushed since.
+///
+/// [`start`]: #method.start
+#[derive(Debug, Clone)]
+struct StackUndo<T> {
+    /// How many elements at the bottom of the stack haven't been popped since
+    untouched: usize,
+    /// The popped elements from before, the lowest one last
+    popped: Vec<T>,
+}
+
+impl<T> Default for StackUndo<T> {
+    fn default() -> StackUndo<T> {
+        StackUndo {
+            untouched: 0,
+            popped: Vec::new(),
+        }
+    }
+}
+
+impl<T: Clone> StackUndo<T> {
+    fn start(&mut self, stack: &[T]) {
+        self.untouched = stack.len();
+        self.popped.clear();
+    }
+
+    fn pop(&mut self, stack: &mut Vec<T>) -> Option<T> {
+        let value = stack.pop()?;
+        if stack.len() < self.untouched {
+            self.untouched = stack.len();
+            self.popped.push(value.clone());
+        }
+        Some(value)
+    }
+
+    fn undo(&mut self, stack: &mut Vec<T>) {
+        stack.truncate(self.untouched);
+        stack.extend(self.popped.drain(..).rev());
+    }
+}
+
+/// What parsing a line changed in a [`ParseState`], see [`StackUndo`]
+#[derive(Debug, Clone, Default)]
+struct LineUndo {
+    stack: StackUndo<StateLevel>,
+    proto_starts: StackUndo<usize>,
+    first_line: bool,
+}
+
+/// Formats the context stack of a [`ParseState`], see [`ParseState::context_stack_display`]
+///
+/// [`ParseState`]: struct.ParseState.html
+/// [`ParseState::context_stack_display`]: struct.ParseState.html#method.context_stack_display
+pub struct ContextStackDisplay<'a> {
+    state: &'a ParseState,
+    syntax_set: &'a SyntaxSet,
+}
+
+impl ContextStackDisplay<'_> {
+    fn level(&self, level: &StateLevel) -> String {
+        let mut s = self.syntax_set.context_name(&level.context);
+        if !level.prototypes.is_empty() {
+            let prototypes: Vec<String> = level
+                .prototypes
+                .iter()
+                .map(|id| self.syntax_set.context_name(id))
+                .collect();
+            s.push_str(&format!(" (with_prototype: {})", prototypes.join(", ")));
+        }
+        s
+    }
+}
+
+impl fmt::Display for ContextStackDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for level in &self.state.stack {
+            writeln!(f, "{}", self.level(level))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ContextStackDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.state.stack.iter().map(|level| self.level(level)))
+            .finish()
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct StateLevel {
     context: ContextId,
     prototypes: Vec<ContextId>,
-    captures: Option<(Region, String)>,
+    /// The regions and the line of the match that pushed the context, for backreferences. The
+    /// levels pushed by the same match share them.
+    captures: Option<Arc<(Region, String)>>,
 }
 
 #[derive(Debug)]
@@ -81,8 +317,116 @@ struct RegexMatch<'a> {
     would_loop: bool,
 }
 
-/// Maps the pattern to the start index, which is -1 if not found.
-type SearchCache = HashMap<*const MatchPattern, Option<Region>, BuildHasherDefault<FnvHasher>>;
+/// The rule that produced a token, returned by [`ParseState::parse_line_traced`]
+///
+/// [`ParseState::parse_line_traced`]: struct.ParseState.html#method.parse_line_traced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchTrace {
+    /// The part of the line that was matched, which is empty for a match that doesn't consume
+    /// anything, like a lookahead that pops a context
+    pub range: Range<usize>,
+    /// The context containing the pattern, formatted like the names of
+    /// [`ParseState::context_stack_names`]. This is the context the pattern is written in, so for
+    /// a pattern from an `include` it's the included context.
+    ///
+    /// [`ParseState::context_stack_names`]: struct.ParseState.html#method.context_stack_names
+    pub context: String,
+    /// The index of the pattern in its context
+    pub pattern_index: usize,
+    /// The regex of the pattern, before substituting the captures of the context for
+    /// backreferences like `\1`
+    pub regex: String,
+    /// Whether the pattern came from a `with_prototype` of a context on the stack
+    pub from_with_prototype: bool,
+    /// The indices of the operations of the token in the returned operations
+    pub ops: Range<usize>,
+}
+
+/// Search results of the patterns on the current line, so that they don't have to be run again
+/// for the following tokens as long as the match is still ahead.
+struct SearchCache {
+    /// Maps the pattern to the region it matched, which is `None` if not found.
+    regions: HashMap<*const MatchPattern, Option<Region>, BuildHasherDefault<FnvHasher>>,
+    /// Maps a run of combined patterns to the start of its match and the pattern that matched
+    runs: HashMap<*const Run, Option<(usize, usize)>, BuildHasherDefault<FnvHasher>>,
+    /// Regions of outdated matches, to search with instead of allocating new ones
+    spare_regions: Vec<Region>,
+    /// The time spent in regex searches, if it's measured
+    search_time: Option<Duration>,
+    /// The unresolved references of the patterns that were skipped
+    unresolved: Vec<ContextReference>,
+    /// How often a character was skipped to avoid looping
+    loop_skips: usize,
+    /// When the line has to be parsed by, and the timeout that was worked out from
+    deadline: Option<(Instant, Duration)>,
+    #[cfg(feature = "tracing")]
+    stats: SearchStats,
+}
+
+/// What parsing a line took, filled in by [`ParseState::parse_line_profiled`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct LineStats {
+    /// The time spent in regex searches
+    pub(crate) search_time: Duration,
+    /// How often a character was skipped to avoid looping, see the comment on preventing loops
+    pub(crate) loop_skips: usize,
+}
+
+/// Counts reported by the `tracing` event at the end of each line
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default)]
+struct SearchStats {
+    tokens: usize,
+    searches: usize,
+    cache_hits: usize,
+}
+
+impl SearchCache {
+    fn new() -> SearchCache {
+        let fnv = BuildHasherDefault::<FnvHasher>::default();
+        SearchCache {
+            regions: HashMap::with_capacity_and_hasher(128, fnv),
+            runs: HashMap::default(),
+            spare_regions: Vec::new(),
+            search_time: None,
+            unresolved: Vec::new(),
+            loop_skips: 0,
+            deadline: None,
+            #[cfg(feature = "tracing")]
+            stats: SearchStats::default(),
+        }
+    }
+
+    /// Fails once the line has taken longer than its timeout, checked before each regex search
+    fn check_deadline(&self) -> Result<(), ParsingError> {
+        match self.deadline {
+            Some((deadline, timeout)) if Instant::now() >= deadline => {
+                Err(ParsingError::Timeout(timeout))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Caches the result of searching a pattern, keeping the region of the match it replaces for
+    /// reuse if nothing else refers to it
+    fn insert(&mut self, match_pat: &MatchPattern, region: Option<Region>) {
+        if let Some(Some(old)) = self.regions.insert(match_pat, region) {
+            if !old.is_shared() {
+                self.spare_regions.push(old);
+            }
+        }
+    }
+
+    /// Swaps a spare region in for `regions` if searching with it would have to copy it first,
+    /// because the last match in it was cached or returned
+    fn prepare_region(&mut self, regions: &mut Region) {
+        if regions.is_shared() {
+            if let Some(spare) = self.spare_regions.pop() {
+                *regions = spare;
+            }
+        }
+    }
+}
 
 // To understand the implementation of this, here's an introduction to how
 // Sublime Text syntax definitions work.
@@ -180,8 +524,9 @@ impl ParseState {
     /// Creates a state from a syntax definition, keeping its own reference-counted point to the
     /// main context of the syntax
     pub fn new(syntax: &SyntaxReference) -> ParseState {
+        let start = syntax.context_ids()["__start"];
         let start_state = StateLevel {
-            context: syntax.context_ids()["__start"],
+            context: start,
             prototypes: Vec::new(),
             captures: None,
         };
@@ -189,6 +534,405 @@ impl ParseState {
             stack: vec![start_state],
             first_line: true,
             proto_starts: Vec::new(),
+            settings: ParseSettings::default(),
+            undo: LineUndo::default(),
+            unresolved_contexts: Vec::new(),
+            syntax_index: start.syntax_index,
+            syntax_id: syntax.id,
+            #[cfg(feature = "paranoid")]
+            checked_scopes: ScopeStack::new(),
+        }
+    }
+
+    /// Creates a state for highlighting an excerpt of a file, starting inside the contexts that
+    /// produce the scopes of `initial_stack_guess`
+    ///
+    /// Snippet renderers often only have a few lines out of the middle of a file, which come out
+    /// wrong when parsed from the start of the syntax, e.g. when they begin inside a block comment.
+    /// Given a guess of the scopes at the start of the excerpt, like `source.rust comment.block`,
+    /// this looks for contexts that the syntax pushes directly from the one on top of the stack
+    /// and whose `meta_scope` or `meta_content_scope` has each guessed scope as a prefix, in
+    /// order. Guessed scopes that no such context has are skipped, so the state is plausible
+    /// rather than exact, and captures for backreferences aren't known.
+    ///
+    /// Returns the state with the scope stack it starts with, which is what the operations
+    /// returned for the first line apply to, e.g. as the initial stack of a [`HighlightState`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    /// let guess: ScopeStack = "source.rust comment.block".parse().unwrap();
+    /// let (mut state, stack) = ParseState::new_at_scope_stack(syntax, &guess);
+    /// assert_eq!(stack, "source.rust comment.block.rust".parse().unwrap());
+    ///
+    /// // the excerpt starts in the middle of a comment, which ends on its first line
+    /// let ops = state.parse_line("still a comment */ fn f() {}\n", &ss).unwrap();
+    /// let mut stack = stack;
+    /// for (_, op) in &ops {
+    ///     stack.apply(op).unwrap();
+    /// }
+    /// assert_eq!(stack, "source.rust".parse().unwrap());
+    /// ```
+    ///
+    /// [`HighlightState`]: ../highlighting/struct.HighlightState.html
+    pub fn new_at_scope_stack(
+        syntax: &SyntaxReference,
+        initial_stack_guess: &ScopeStack,
+    ) -> (ParseState, ScopeStack) {
+        let mut state = ParseState::new(syntax);
+        // what the first line does before matching anything: push `main` from `__start`
+        state.stack.push(StateLevel {
+            context: syntax.context_ids()["__main"],
+            prototypes: Vec::new(),
+            captures: None,
+        });
+        state.first_line = false;
+
+        let context = |id: &ContextId| {
+            (id.syntax_index == state.syntax_index).then(|| &syntax.contexts()[id.context_index])
+        };
+        let level_scopes = |id: &ContextId| -> Vec<Scope> {
+            context(id).map_or_else(Vec::new, |c| {
+                c.meta_scope
+                    .iter()
+                    .chain(&c.meta_content_scope)
+                    .cloned()
+                    .collect()
+            })
+        };
+
+        let mut scopes: Vec<Scope> = state
+            .stack
+            .iter()
+            .flat_map(|l| level_scopes(&l.context))
+            .collect();
+        // the guessed scopes already on the stack
+        let mut matched = 0;
+        for &guess in initial_stack_guess.as_slice() {
+            if let Some(pos) = scopes[matched..]
+                .iter()
+                .position(|s| guess.is_prefix_of(*s))
+            {
+                matched += pos + 1;
+                continue;
+            }
+            let top = state.stack[state.stack.len() - 1].context;
+            let mut pushes = Vec::new();
+            let mut visited = HashSet::new();
+            collect_pushes(top, &context, &mut visited, &mut pushes);
+            // the prototype of the syntax applies to the contexts that include it
+            if let Some(prototype) = context(&top).and_then(|c| c.prototype) {
+                collect_pushes(prototype, &context, &mut visited, &mut pushes);
+            }
+            // prefer the closest match, e.g. `comment.block.rust` over
+            // `comment.block.documentation.rust` for `comment.block`
+            let found = pushes
+                .into_iter()
+                .filter_map(|(is_set, ids)| {
+                    let closest = ids
+                        .iter()
+                        .flat_map(&level_scopes)
+                        .filter(|s| guess.is_prefix_of(*s))
+                        .map(Scope::len)
+                        .min()?;
+                    Some((closest, is_set, ids))
+                })
+                .min_by_key(|(closest, _, _)| *closest);
+            if let Some((_, is_set, ids)) = found {
+                if is_set {
+                    state.stack.pop();
+                }
+                state
+                    .stack
+                    .extend(ids.into_iter().map(|context| StateLevel {
+                        context,
+                        prototypes: Vec::new(),
+                        captures: None,
+                    }));
+                scopes = state
+                    .stack
+                    .iter()
+                    .flat_map(|l| level_scopes(&l.context))
+                    .collect();
+                matched = scopes.iter().rposition(|s| guess.is_prefix_of(*s)).unwrap() + 1;
+            }
+        }
+
+        let stack = ScopeStack::from_vec(scopes);
+        #[cfg(feature = "paranoid")]
+        {
+            state.checked_scopes = stack.clone();
+        }
+        (state, stack)
+    }
+
+    /// Makes the parser skip a whole grapheme cluster instead of a single character when it has
+    /// to advance to prevent an infinite loop, see the comment on preventing loops in the source
+    ///
+    /// Skipping single characters is what Sublime Text does, but it can put a scope boundary
+    /// inside a user-perceived character, e.g. between a letter and a combining accent or inside
+    /// an emoji ZWJ sequence. The segmentation is an approximation, which handles combining marks,
+    /// emoji sequences and flags but not every script.
+    pub fn set_skip_by_grapheme_cluster(&mut self, enabled: bool) {
+        self.settings.skip_by_grapheme_cluster = enabled;
+    }
+
+    /// Makes parsing a line fail with [`ParsingError::BacktrackLimitExceeded`] when a regex
+    /// backtracks more than `limit` times in one search, instead of hanging on lines that make
+    /// the regexes of a syntax backtrack catastrophically
+    ///
+    /// The limit is enforced by the regex engine in the middle of a search. Oniguruma counts the
+    /// retries of each match attempt, and fancy-regex the backtracking steps of the whole search.
+    /// fancy-regex only takes a limit when compiling a regex, so a regex is compiled again for
+    /// the limit the first time it's searched with one. Without a limit, the default, searches
+    /// that backtrack too much for the engine are treated as not matching. See
+    /// [`set_regex_timeout`] for bounding the time a line takes instead.
+    ///
+    /// When a line fails, the state is left as it was before the line, so the caller can e.g.
+    /// show the line as plain text and go on with the next one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, ParsingError, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.set_backtrack_limit(Some(100_000));
+    /// match state.parse_line("fn main() {}\n", &ss) {
+    ///     Ok(ops) => assert!(!ops.is_empty()),
+    ///     Err(ParsingError::BacktrackLimitExceeded(_)) => { /* show the line without highlighting */ }
+    ///     Err(e) => panic!("{}", e),
+    /// }
+    /// ```
+    ///
+    /// [`ParsingError::BacktrackLimitExceeded`]: enum.ParsingError.html#variant.BacktrackLimitExceeded
+    /// [`set_regex_timeout`]: #method.set_regex_timeout
+    pub fn set_backtrack_limit(&mut self, limit: Option<u32>) {
+        self.settings.backtrack_limit = limit;
+    }
+
+    /// Makes parsing a line fail with [`ParsingError::Timeout`] once its regex searches have
+    /// taken longer than `timeout`
+    ///
+    /// The time is checked before each regex search of the line, so a single search can still
+    /// run past the timeout. Pair it with [`set_backtrack_limit`] to bound those too. Like with
+    /// the backtrack limit, the state is left as it was before the line. `None`, the default,
+    /// turns the timeout off.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use syntect::parsing::{ParseState, ParsingError, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.set_regex_timeout(Some(Duration::from_millis(100)));
+    /// state.set_backtrack_limit(Some(100_000));
+    /// match state.parse_line("fn main() {}\n", &ss) {
+    ///     Ok(ops) => assert!(!ops.is_empty()),
+    ///     Err(ParsingError::Timeout(_) | ParsingError::BacktrackLimitExceeded(_)) => {
+    ///         /* show the line without highlighting */
+    ///     }
+    ///     Err(e) => panic!("{}", e),
+    /// }
+    /// ```
+    ///
+    /// [`ParsingError::Timeout`]: enum.ParsingError.html#variant.Timeout
+    /// [`set_backtrack_limit`]: #method.set_backtrack_limit
+    pub fn set_regex_timeout(&mut self, timeout: Option<Duration>) {
+        self.settings.regex_timeout = timeout;
+    }
+
+    /// Makes parsing a line fail with [`ParsingError::StackTooDeep`] when a match would push the
+    /// context stack past `depth` contexts
+    ///
+    /// Every context on the stack that uses backreferences keeps the line that pushed it, so
+    /// syntaxes that nest contexts without bounds, like heredocs or templates in templates, can
+    /// use a lot of memory on adversarial input. Like with [`set_backtrack_limit`], the state is
+    /// left as it was before the line. `None`, the default, allows any depth.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, ParsingError, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.set_max_stack_depth(Some(64));
+    /// let line = format!("fn f() {}\n", "{".repeat(100));
+    /// assert!(matches!(
+    ///     state.parse_line(&line, &ss),
+    ///     Err(ParsingError::StackTooDeep(64))
+    /// ));
+    /// assert!(state.parse_line("fn g() {}\n", &ss).is_ok());
+    /// ```
+    ///
+    /// [`ParsingError::StackTooDeep`]: enum.ParsingError.html#variant.StackTooDeep
+    /// [`set_backtrack_limit`]: #method.set_backtrack_limit
+    pub fn set_max_stack_depth(&mut self, depth: Option<usize>) {
+        self.settings.max_stack_depth = depth;
+    }
+
+    /// Makes the parser search runs of consecutive patterns of a context with a single regex,
+    /// instead of one regex search per pattern
+    ///
+    /// The patterns are joined into an alternation, which finds the earliest match of all of them
+    /// in one scan of the line. That can pay off for contexts with dozens of patterns, but how
+    /// much depends on the syntax, so it's worth measuring on typical files. Patterns that can't
+    /// be combined without changing what they match, e.g. ones with backreferences, are still
+    /// searched on their own, and the parse results are the same either way. The alternations are
+    /// built and compiled the first time a context is searched with this turned on.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    /// let line = "pub fn main() -> i32 { let x = 42; x }\n";
+    ///
+    /// let mut state = ParseState::new(syntax);
+    /// state.set_combined_search(true);
+    /// let ops = state.parse_line(line, &ss).unwrap();
+    /// assert_eq!(ops, ParseState::new(syntax).parse_line(line, &ss).unwrap());
+    /// ```
+    pub fn set_combined_search(&mut self, enabled: bool) {
+        self.settings.combined_search = enabled;
+    }
+
+    /// Sets what the parser does with a match that pushes or sets a context which wasn't found
+    /// when the syntax set was built
+    ///
+    /// Such references are left in a syntax set that was assembled from only some of the syntaxes
+    /// a syntax refers to, e.g. when a syntax embeds another one without an `escape`. By default
+    /// parsing fails when a line reaches one, so an application that loads syntaxes from users
+    /// can parse on with [`UnresolvedContextPolicy::PlainText`] or
+    /// [`UnresolvedContextPolicy::SkipPattern`] instead, and report the references that were
+    /// missing from [`unresolved_contexts`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{
+    ///     ParseState, SyntaxDefinition, SyntaxSetBuilder, UnresolvedContextPolicy,
+    /// };
+    ///
+    /// let syntax = SyntaxDefinition::load_from_str(r"
+    /// name: Test
+    /// scope: source.test
+    /// contexts:
+    ///   main:
+    ///     - match: '<%'
+    ///       push: scope:source.missing
+    ///     - match: '\w+'
+    ///       scope: word.test
+    /// ", true, None).unwrap();
+    /// let mut builder = SyntaxSetBuilder::new();
+    /// builder.add(syntax);
+    /// let ss = builder.build();
+    ///
+    /// let mut state = ParseState::new(ss.find_syntax_by_name("Test").unwrap());
+    /// assert!(state.parse_line("a <% b\n", &ss).is_err());
+    ///
+    /// let mut state = ParseState::new(ss.find_syntax_by_name("Test").unwrap());
+    /// state.set_unresolved_context_policy(UnresolvedContextPolicy::SkipPattern);
+    /// let ops = state.parse_line("a <% b\n", &ss).unwrap();
+    /// assert_eq!(ops.len(), 5);
+    /// assert_eq!(state.unresolved_contexts().len(), 1);
+    /// ```
+    ///
+    /// [`UnresolvedContextPolicy::PlainText`]: enum.UnresolvedContextPolicy.html#variant.PlainText
+    /// [`UnresolvedContextPolicy::SkipPattern`]: enum.UnresolvedContextPolicy.html#variant.SkipPattern
+    /// [`unresolved_contexts`]: #method.unresolved_contexts
+    pub fn set_unresolved_context_policy(&mut self, policy: UnresolvedContextPolicy) {
+        self.settings.unresolved_context_policy = policy;
+    }
+
+    /// The settings the parser uses, which are all set through the setters of this struct or
+    /// [`set_settings`]
+    ///
+    /// [`set_settings`]: #method.set_settings
+    pub fn settings(&self) -> &ParseSettings {
+        &self.settings
+    }
+
+    /// Replaces all the settings at once, e.g. to parse with the same settings as another state
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.set_max_stack_depth(Some(64));
+    ///
+    /// let mut other = ParseState::new(ss.find_syntax_by_extension("py").unwrap());
+    /// other.set_settings(*state.settings());
+    /// assert_eq!(other.settings().max_stack_depth, Some(64));
+    /// ```
+    pub fn set_settings(&mut self, settings: ParseSettings) {
+        self.settings = settings;
+    }
+
+    /// Returns the unresolved context references that the parser fell back on the
+    /// [`UnresolvedContextPolicy`] for, each once, in the order it came across them
+    ///
+    /// With [`UnresolvedContextPolicy::SkipPattern`] these are the references of every pattern
+    /// that was passed over, whether or not it would have matched.
+    ///
+    /// [`UnresolvedContextPolicy`]: enum.UnresolvedContextPolicy.html
+    /// [`UnresolvedContextPolicy::SkipPattern`]: enum.UnresolvedContextPolicy.html#variant.SkipPattern
+    pub fn unresolved_contexts(&self) -> &[ContextReference] {
+        &self.unresolved_contexts
+    }
+
+    /// Returns the names of the contexts on the stack of the parser, outermost first
+    ///
+    /// The names are formatted as `syntax#context`, and anonymous contexts, like the ones of
+    /// `push`es with a list of patterns, have names starting with `#anon_`. This is meant for
+    /// debugging syntaxes, e.g. by printing the stack between lines. The [`SyntaxSet`] has to be
+    /// the one used for parsing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.parse_line("/* unfinished\n", &ss).unwrap();
+    /// let names = state.context_stack_names(&ss);
+    /// assert_eq!(names[0], "Rust#__start");
+    /// assert!(names.last().unwrap().starts_with("Rust#"));
+    /// ```
+    ///
+    /// [`SyntaxSet`]: struct.SyntaxSet.html
+    pub fn context_stack_names(&self, syntax_set: &SyntaxSet) -> Vec<String> {
+        self.stack
+            .iter()
+            .map(|level| syntax_set.context_name(&level.context))
+            .collect()
+    }
+
+    /// Returns a value that formats the stack of the parser with one context per line, outermost
+    /// first, like [`context_stack_names`], and the `with_prototype` contexts pushed with each
+    ///
+    /// [`context_stack_names`]: #method.context_stack_names
+    pub fn context_stack_display<'a>(
+        &'a self,
+        syntax_set: &'a SyntaxSet,
+    ) -> ContextStackDisplay<'a> {
+        ContextStackDisplay {
+            state: self,
+            syntax_set,
         }
     }
 
@@ -215,12 +959,130 @@ impl ParseState {
         &mut self,
         line: &str,
         syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        self.parse_line_impl(line, syntax_set, None, None)
+    }
+
+    /// Returns an error if the syntax set doesn't have the syntax the state was created for at the
+    /// same index, which would make the context IDs on the stack point at the wrong contexts
+    fn check_syntax_set(&self, syntax_set: &SyntaxSet) -> Result<(), ParsingError> {
+        let syntax = syntax_set.syntaxes().get(self.syntax_index);
+        if syntax.map(|syntax| syntax.id) == Some(self.syntax_id) {
+            Ok(())
+        } else {
+            Err(ParsingError::SyntaxSetMismatch)
+        }
+    }
+
+    /// Like [`parse_line`], but for a line split into chunks, like the chunks of a rope
+    ///
+    /// The positions of the operations are in the whole line. The regex engines can only search
+    /// contiguous text, so the chunks are copied into one string if there are several of them.
+    /// A line in a single chunk, which is most of them in ropes like the one of `ropey`, is parsed
+    /// without copying it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+    /// let ops = ParseState::new(syntax)
+    ///     .parse_line_chunks(["fn ma", "in() {}\n"], &ss)
+    ///     .unwrap();
+    /// let expected = ParseState::new(syntax)
+    ///     .parse_line("fn main() {}\n", &ss)
+    ///     .unwrap();
+    /// assert_eq!(ops, expected);
+    /// ```
+    ///
+    /// [`parse_line`]: #method.parse_line
+    pub fn parse_line_chunks<'a, I>(
+        &mut self,
+        chunks: I,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut chunks = chunks.into_iter();
+        let first = chunks.next().unwrap_or("");
+        match chunks.next() {
+            None => self.parse_line(first, syntax_set),
+            Some(second) => {
+                let mut line = String::from(first);
+                line.push_str(second);
+                line.extend(chunks);
+                self.parse_line(&line, syntax_set)
+            }
+        }
+    }
+
+    /// Like [`parse_line`], but also returns which pattern matched each token
+    ///
+    /// This is like the scope inspector of Sublime Text, but showing the rule that produced the
+    /// scopes, for debugging the precedence of patterns in a syntax. It's slower than
+    /// [`parse_line`], so it isn't meant for highlighting. Positions where the parser had to skip
+    /// a character to avoid looping aren't included.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// let (ops, traces) = state.parse_line_traced("fn f() {}\n", &ss).unwrap();
+    /// let paren = traces.iter().find(|t| t.range == (4..5)).unwrap();
+    /// assert_eq!(paren.context, "Rust#fn-parameters");
+    /// assert_eq!(paren.regex, r"\(");
+    /// assert!(paren.ops.end <= ops.len());
+    /// ```
+    ///
+    /// [`parse_line`]: #method.parse_line
+    #[allow(clippy::type_complexity)]
+    pub fn parse_line_traced(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+    ) -> Result<(Vec<(usize, ScopeStackOp)>, Vec<MatchTrace>), ParsingError> {
+        let mut traces = Vec::new();
+        let ops = self.parse_line_impl(line, syntax_set, Some(&mut traces), None)?;
+        Ok((ops, traces))
+    }
+
+    /// Like [`parse_line`], but adds the time spent in regex searches and the characters skipped
+    /// to avoid loops to `stats`
+    ///
+    /// [`parse_line`]: #method.parse_line
+    pub(crate) fn parse_line_profiled(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+        stats: &mut LineStats,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        self.parse_line_impl(line, syntax_set, None, Some(stats))
+    }
+
+    fn parse_line_impl(
+        &mut self,
+        line: &str,
+        syntax_set: &SyntaxSet,
+        mut traces: Option<&mut Vec<MatchTrace>>,
+        stats: Option<&mut LineStats>,
     ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
         if self.stack.is_empty() {
             return Err(ParsingError::MissingMainContext);
         }
+        self.check_syntax_set(syntax_set)?;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("parse_line", len = line.len()).entered();
         let mut match_start = 0;
         let mut res = Vec::new();
+        self.undo.stack.start(&self.stack);
+        self.undo.proto_starts.start(&self.proto_starts);
+        self.undo.first_line = self.first_line;
 
         if self.first_line {
             let cur_level = &self.stack[self.stack.len() - 1];
@@ -232,24 +1094,211 @@ impl ParseState {
         }
 
         let mut regions = Region::new();
-        let fnv = BuildHasherDefault::<FnvHasher>::default();
-        let mut search_cache: SearchCache = HashMap::with_capacity_and_hasher(128, fnv);
+        let mut search_cache = SearchCache::new();
+        if stats.is_some() {
+            search_cache.search_time = Some(Duration::ZERO);
+        }
+        search_cache.deadline = self
+            .settings
+            .regex_timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
         // Used for detecting loops with push/pop, see long comment above.
         let mut non_consuming_push_at = (0, 0);
 
-        while self.parse_next_token(
-            line,
-            syntax_set,
-            &mut match_start,
-            &mut search_cache,
-            &mut regions,
-            &mut non_consuming_push_at,
-            &mut res,
-        )? {}
+        loop {
+            match self.parse_next_token(
+                line,
+                syntax_set,
+                &mut match_start,
+                &mut search_cache,
+                &mut regions,
+                &mut non_consuming_push_at,
+                &mut res,
+                traces.as_deref_mut(),
+            ) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(
+                    e @ (ParsingError::BacktrackLimitExceeded(_)
+                    | ParsingError::Timeout(_)
+                    | ParsingError::StackTooDeep(_)),
+                ) => {
+                    self.undo.stack.undo(&mut self.stack);
+                    self.undo.proto_starts.undo(&mut self.proto_starts);
+                    self.first_line = self.undo.first_line;
+                    return Err(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // the popped levels aren't needed anymore, and may keep captures alive
+        self.undo.stack.popped.clear();
+
+        for reference in &search_cache.unresolved {
+            note_unresolved(&mut self.unresolved_contexts, reference);
+        }
+        if let (Some(stats), Some(time)) = (stats, search_cache.search_time) {
+            stats.search_time += time;
+            stats.loop_skips += search_cache.loop_skips;
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let stats = &search_cache.stats;
+            tracing::trace!(
+                ops = res.len(),
+                tokens = stats.tokens,
+                searches = stats.searches,
+                cache_hits = stats.cache_hits,
+                stack_depth = self.stack.len(),
+                "parsed line"
+            );
+        }
+
+        #[cfg(feature = "paranoid")]
+        self.check_ops(line, &res)?;
 
         Ok(res)
     }
 
+    /// Ends the parsing of a file, returning the operations that close the scopes still open
+    ///
+    /// A file can end inside contexts that would normally be popped later, like an unterminated
+    /// string or comment. This pops every context on the stack, so applying the operations to
+    /// the scope stack of the file leaves it empty, and renderers can close everything they
+    /// opened for the scopes, e.g. by passing them to [`HighlightState::finalize`] or to
+    /// [`line_tokens_to_classed_spans`] with an empty line. The operations are all at index 0.
+    ///
+    /// Afterwards the stack is empty, so parsing more lines fails with
+    /// [`ParsingError::MissingMainContext`], and finalizing again returns no operations.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// let mut stack = ScopeStack::new();
+    /// for line in ["fn main() {\n", "    let s = \"unterminated\n"] {
+    ///     for (_, op) in state.parse_line(line, &ss).unwrap() {
+    ///         stack.apply(&op).unwrap();
+    ///     }
+    /// }
+    /// assert!(stack.len() > 3);
+    /// for (_, op) in state.finalize(&ss).unwrap() {
+    ///     stack.apply(&op).unwrap();
+    /// }
+    /// assert!(stack.is_empty());
+    /// ```
+    ///
+    /// [`HighlightState::finalize`]: ../highlighting/struct.HighlightState.html#method.finalize
+    /// [`line_tokens_to_classed_spans`]: ../html/fn.line_tokens_to_classed_spans.html
+    /// [`ParsingError::MissingMainContext`]: enum.ParsingError.html#variant.MissingMainContext
+    pub fn finalize(
+        &mut self,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        #[cfg(feature = "paranoid")]
+        {
+            self.check_balanced(syntax_set)?;
+            self.checked_scopes = ScopeStack::new();
+        }
+        let ops = self.closing_ops(syntax_set)?;
+        self.stack.clear();
+        self.proto_starts.clear();
+        Ok(ops)
+    }
+
+    /// The operations for popping every context on the stack
+    fn closing_ops(
+        &self,
+        syntax_set: &SyntaxSet,
+    ) -> Result<Vec<(usize, ScopeStackOp)>, ParsingError> {
+        self.check_syntax_set(syntax_set)?;
+        let mut ops = Vec::new();
+        if self.first_line {
+            // the scope of the syntax isn't pushed until the first line
+            return Ok(ops);
+        }
+        for level in self.stack.iter().rev() {
+            let context = syntax_set.get_context(&level.context)?;
+            for initial in [true, false] {
+                self.push_meta_ops(
+                    initial,
+                    0,
+                    context,
+                    &MatchOperation::Pop,
+                    syntax_set,
+                    &mut ops,
+                )?;
+            }
+        }
+        Ok(ops)
+    }
+
+    /// Checks that the scopes pushed by the lines parsed so far are exactly the ones popped by
+    /// popping every context on the stack, like [`finalize`] does at the end of a file
+    ///
+    /// Together with the checks done on the operations of each line, that they're in order, at
+    /// character boundaries within the line and never pop more scopes than are on the stack,
+    /// this catches bugs in the parser and in syntaxes close to where they happen, instead of
+    /// as confusing scopes or panics further down. It's only available with the `paranoid`
+    /// feature, which makes parsing slower, and is meant for tests and for processing untrusted
+    /// syntaxes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{ParseState, SyntaxSet};
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+    /// state.parse_line("fn main() {\n", &ss).unwrap();
+    /// state.parse_line("    /* unfinished\n", &ss).unwrap();
+    /// state.check_balanced(&ss).unwrap();
+    /// ```
+    ///
+    /// [`finalize`]: #method.finalize
+    #[cfg(feature = "paranoid")]
+    pub fn check_balanced(&self, syntax_set: &SyntaxSet) -> Result<(), ParsingError> {
+        let ops = self.closing_ops(syntax_set)?;
+        let mut scopes = self.checked_scopes.clone();
+        for (_, op) in &ops {
+            apply_checked(&mut scopes, op)?;
+        }
+        if !scopes.is_empty() {
+            return Err(ParsingError::BrokenInvariant(format!(
+                "scopes {} are left over after popping every context",
+                scopes
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "paranoid")]
+    fn check_ops(&mut self, line: &str, ops: &[(usize, ScopeStackOp)]) -> Result<(), ParsingError> {
+        let mut last = 0;
+        for (index, op) in ops {
+            if *index < last {
+                return Err(ParsingError::BrokenInvariant(format!(
+                    "operation at {} comes after one at {}",
+                    index, last
+                )));
+            }
+            if !line.is_char_boundary(*index) {
+                return Err(ParsingError::BrokenInvariant(format!(
+                    "operation at {} isn't at a character boundary of a line of length {}",
+                    index,
+                    line.len()
+                )));
+            }
+            last = *index;
+            apply_checked(&mut self.checked_scopes, op)?;
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn parse_next_token(
         &mut self,
@@ -260,6 +1309,7 @@ impl ParseState {
         regions: &mut Region,
         non_consuming_push_at: &mut (usize, usize),
         ops: &mut Vec<(usize, ScopeStackOp)>,
+        traces: Option<&mut Vec<MatchTrace>>,
     ) -> Result<bool, ParsingError> {
         let check_pop_loop = {
             let (pos, stack_depth) = *non_consuming_push_at;
@@ -273,7 +1323,7 @@ impl ParseState {
             .map(|start| *start >= self.stack.len())
             .unwrap_or(false)
         {
-            self.proto_starts.pop();
+            self.undo.proto_starts.pop(&mut self.proto_starts);
         }
 
         let best_match = self.find_best_match(
@@ -297,11 +1347,17 @@ impl ParseState {
 
                 // println!("pop_would_loop for match {:?}, start {}", reg_match, *start);
 
-                // nth(1) gets the next character if there is one. Need to do
-                // this instead of just += 1 because we have byte indices and
-                // unicode characters can be more than 1 byte.
-                if let Some((i, _)) = line[*start..].char_indices().nth(1) {
-                    *start += i;
+                // Need to do this instead of just += 1 because we have byte
+                // indices and unicode characters can be more than 1 byte.
+                let rest = &line[*start..];
+                let skip = if self.settings.skip_by_grapheme_cluster {
+                    grapheme::cluster_len(rest)
+                } else {
+                    rest.chars().next().map_or(0, char::len_utf8)
+                };
+                if skip < rest.len() {
+                    *start += skip;
+                    search_cache.loop_skips += 1;
                     return Ok(true);
                 } else {
                     // End of line, no character to advance and no point trying
@@ -326,6 +1382,10 @@ impl ParseState {
             }
 
             *start = match_end;
+            #[cfg(feature = "tracing")]
+            {
+                search_cache.stats.tokens += 1;
+            }
 
             // ignore `with_prototype`s below this if a context is pushed
             if reg_match.from_with_prototype {
@@ -337,8 +1397,25 @@ impl ParseState {
                 let id = &self.stack[self.stack.len() - 1].context;
                 syntax_set.get_context(id)?
             };
+            let ops_start = ops.len();
             self.exec_pattern(line, &reg_match, level_context, syntax_set, ops)?;
 
+            if let Some(traces) = traces {
+                let match_start = reg_match.regions.pos(0).unwrap().0;
+                let context = syntax_set
+                    .loaded_context_name(reg_match.context)
+                    .unwrap_or_else(|| "?".to_owned());
+                let match_pattern = reg_match.context.match_at(reg_match.pat_index)?;
+                traces.push(MatchTrace {
+                    range: match_start..match_end,
+                    context,
+                    pattern_index: reg_match.pat_index,
+                    regex: match_pattern.regex.regex_str().to_owned(),
+                    from_with_prototype: reg_match.from_with_prototype,
+                    ops: ops_start..ops.len(),
+                });
+            }
+
             Ok(true)
         } else {
             Ok(false)
@@ -354,6 +1431,8 @@ impl ParseState {
         regions: &mut Region,
         check_pop_loop: bool,
     ) -> Result<Option<RegexMatch<'a>>, ParsingError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("find_best_match", start).entered();
         let cur_level = &self.stack[self.stack.len() - 1];
         let context = syntax_set.get_context(&cur_level.context)?;
         let prototype = if let Some(ref p) = context.prototype {
@@ -369,11 +1448,11 @@ impl ParseState {
             let with_prototypes = self.stack[proto_start..].iter().flat_map(|lvl| {
                 lvl.prototypes
                     .iter()
-                    .map(move |ctx| (true, ctx, lvl.captures.as_ref()))
+                    .map(move |ctx| (true, ctx, lvl.captures.as_deref()))
             });
             let cur_prototype = prototype.into_iter().map(|ctx| (false, ctx, None));
             let cur_context =
-                Some((false, &cur_level.context, cur_level.captures.as_ref())).into_iter();
+                Some((false, &cur_level.context, cur_level.captures.as_deref())).into_iter();
             with_prototypes.chain(cur_prototype).chain(cur_context)
         };
 
@@ -384,42 +1463,121 @@ impl ParseState {
         let mut best_match: Option<RegexMatch<'_>> = None;
         let mut pop_would_loop = false;
 
-        for (from_with_proto, ctx, captures) in context_chain {
-            for (pat_context, pat_index) in context_iter(syntax_set, syntax_set.get_context(ctx)?) {
-                let match_pat = pat_context.match_at(pat_index)?;
-
-                if let Some(match_region) =
-                    self.search(line, start, match_pat, captures, search_cache, regions)
-                {
-                    let (match_start, match_end) = match_region.pos(0).unwrap();
-
-                    // println!("matched pattern {:?} at start {} end {}", match_pat.regex_str, match_start, match_end);
-
-                    if match_start < min_start || (match_start == min_start && pop_would_loop) {
-                        // New match is earlier in text than old match,
-                        // or old match was a looping pop at the same
-                        // position.
-
-                        // println!("setting as current match");
-
-                        min_start = match_start;
+        // Returns true if the match can't be beaten by the ones of the patterns after it
+        let mut consider = |match_region: Region,
+                            match_pat: &MatchPattern,
+                            pat_context: &'a Context,
+                            pat_index: usize,
+                            from_with_proto: bool| {
+            let (match_start, match_end) = match_region.pos(0).unwrap();
+
+            // println!("matched pattern {:?} at start {} end {}", match_pat.regex_str, match_start, match_end);
+
+            if match_start < min_start || (match_start == min_start && pop_would_loop) {
+                // New match is earlier in text than old match,
+                // or old match was a looping pop at the same
+                // position.
+
+                // println!("setting as current match");
+
+                min_start = match_start;
+
+                let consuming = match_end > start;
+                pop_would_loop = check_pop_loop
+                    && !consuming
+                    && matches!(match_pat.operation, MatchOperation::Pop);
+
+                best_match = Some(RegexMatch {
+                    regions: match_region,
+                    context: pat_context,
+                    pat_index,
+                    from_with_prototype: from_with_proto,
+                    would_loop: pop_would_loop,
+                });
+
+                // We're not gonna find a better match after this,
+                // so as an optimization we can stop matching now.
+                return match_start == start && !pop_would_loop;
+            }
+            false
+        };
 
-                        let consuming = match_end > start;
-                        pop_would_loop = check_pop_loop
-                            && !consuming
-                            && matches!(match_pat.operation, MatchOperation::Pop);
+        for (from_with_proto, ctx, captures) in context_chain {
+            let mut patterns = context_iter(syntax_set, syntax_set.get_context(ctx)?);
+            let mut units = if self.settings.combined_search {
+                syntax_set
+                    .combined_patterns(ctx)
+                    .map(|combined| combined.units().iter())
+            } else {
+                None
+            };
+            'units: loop {
+                // The number of patterns to search one by one
+                let mut singles = 1;
+                if let Some(Unit::Run(run)) = units.as_mut().and_then(Iterator::next) {
+                    singles = run.len();
+                    let run_match = self.search_run(line, start, run, search_cache, regions)?;
+                    let won = match run_match {
+                        Some((match_start, index)) => {
+                            let (pat_context, pat_index) = patterns.clone().nth(index).unwrap();
+                            let match_pat = pat_context.match_at(pat_index)?;
+                            // The pattern on its own has to match at the same place. A looping
+                            // pop or a skipped pattern is passed over for a later pattern at the
+                            // same position, so the patterns of the run are searched one by one
+                            // then.
+                            if self.skips(match_pat, search_cache) {
+                                None
+                            } else {
+                                self.search(
+                                    line,
+                                    start,
+                                    match_pat,
+                                    captures,
+                                    search_cache,
+                                    regions,
+                                )?
+                                .filter(|region| {
+                                    let (s, e) = region.pos(0).unwrap();
+                                    s == match_start
+                                        && !(check_pop_loop
+                                            && e == start
+                                            && matches!(match_pat.operation, MatchOperation::Pop))
+                                })
+                                .map(|region| (region, match_pat, pat_context, pat_index))
+                            }
+                        }
+                        None => None,
+                    };
+                    if run_match.is_none() || won.is_some() {
+                        singles = 0;
+                        patterns.nth(run.len() - 1);
+                    }
+                    if let Some((region, match_pat, pat_context, pat_index)) = won {
+                        if consider(region, match_pat, pat_context, pat_index, from_with_proto) {
+                            return Ok(best_match);
+                        }
+                    }
+                }
+                for _ in 0..singles {
+                    let (pat_context, pat_index) = match patterns.next() {
+                        Some(pattern) => pattern,
+                        None => break 'units,
+                    };
+                    let match_pat = pat_context.match_at(pat_index)?;
+                    if self.skips(match_pat, search_cache) {
+                        continue;
+                    }
 
-                        best_match = Some(RegexMatch {
-                            regions: match_region,
-                            context: pat_context,
+                    if let Some(match_region) =
+                        self.search(line, start, match_pat, captures, search_cache, regions)?
+                    {
+                        if consider(
+                            match_region,
+                            match_pat,
+                            pat_context,
                             pat_index,
-                            from_with_prototype: from_with_proto,
-                            would_loop: pop_would_loop,
-                        });
-
-                        if match_start == start && !pop_would_loop {
-                            // We're not gonna find a better match after this,
-                            // so as an optimization we can stop matching now.
+                            from_with_proto,
+                        ) {
                             return Ok(best_match);
                         }
                     }
@@ -429,6 +1587,104 @@ impl ParseState {
         Ok(best_match)
     }
 
+    /// Whether a pattern is passed over because it refers to a context that wasn't found, which
+    /// is remembered in the search cache
+    fn skips(&self, match_pat: &MatchPattern, search_cache: &mut SearchCache) -> bool {
+        if self.settings.unresolved_context_policy != UnresolvedContextPolicy::SkipPattern {
+            return false;
+        }
+        match unresolved_reference(match_pat) {
+            Some(reference) => {
+                note_unresolved(&mut search_cache.unresolved, reference);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the context a reference of a match points to, which is the `main` context of the
+    /// Plain Text syntax for one that wasn't found if the policy says so
+    fn resolve_id(
+        &self,
+        reference: &ContextReference,
+        syntax_set: &SyntaxSet,
+    ) -> Result<ContextId, ParsingError> {
+        match reference.id() {
+            Err(e)
+                if self.settings.unresolved_context_policy
+                    == UnresolvedContextPolicy::PlainText =>
+            {
+                syntax_set
+                    .find_syntax_by_name("Plain Text")
+                    .and_then(|syntax| syntax.context_ids().get("main").copied())
+                    .ok_or(e)
+            }
+            result => result,
+        }
+    }
+
+    fn resolve_context<'a>(
+        &self,
+        reference: &ContextReference,
+        syntax_set: &'a SyntaxSet,
+    ) -> Result<&'a Context, ParsingError> {
+        syntax_set.get_context(&self.resolve_id(reference, syntax_set)?)
+    }
+
+    /// Searches the patterns of a run together, returning the start of the earliest match and the
+    /// index of the pattern in the run that matched there
+    fn search_run(
+        &self,
+        line: &str,
+        start: usize,
+        run: &Run,
+        search_cache: &mut SearchCache,
+        regions: &mut Region,
+    ) -> Result<Option<(usize, usize)>, ParsingError> {
+        let run_ptr = run as *const Run;
+        if let Some(cached) = search_cache.runs.get(&run_ptr) {
+            match *cached {
+                Some((match_start, _)) if match_start < start => {}
+                cached => {
+                    #[cfg(feature = "tracing")]
+                    {
+                        search_cache.stats.cache_hits += 1;
+                    }
+                    return Ok(cached);
+                }
+            }
+        }
+        #[cfg(feature = "tracing")]
+        {
+            search_cache.stats.searches += 1;
+        }
+
+        search_cache.check_deadline()?;
+        search_cache.prepare_region(regions);
+        let search_start = search_cache.search_time.map(|_| Instant::now());
+        let matched = run
+            .search(line, start, regions, self.settings.backtrack_limit)
+            .map_err(|_| ParsingError::BacktrackLimitExceeded(run.regex_str().to_owned()))?;
+        if let (Some(time), Some(search_start)) = (&mut search_cache.search_time, search_start) {
+            *time += search_start.elapsed();
+        }
+        search_cache.runs.insert(run_ptr, matched);
+        Ok(matched)
+    }
+
+    fn search_regex(
+        &self,
+        line: &str,
+        start: usize,
+        regex: &Regex,
+        regions: &mut Region,
+    ) -> Result<bool, ParsingError> {
+        let limit = self.settings.backtrack_limit;
+        regex
+            .search_with_limit(line, start, line.len(), Some(regions), limit)
+            .map_err(|_| ParsingError::BacktrackLimitExceeded(regex.regex_str().to_owned()))
+    }
+
     fn search(
         &self,
         line: &str,
@@ -437,37 +1693,60 @@ impl ParseState {
         captures: Option<&(Region, String)>,
         search_cache: &mut SearchCache,
         regions: &mut Region,
-    ) -> Option<Region> {
+    ) -> Result<Option<Region>, ParsingError> {
         // println!("{} - {:?} - {:?}", match_pat.regex_str, match_pat.has_captures, cur_level.captures.is_some());
         let match_ptr = match_pat as *const MatchPattern;
 
-        if let Some(maybe_region) = search_cache.get(&match_ptr) {
+        if let Some(maybe_region) = search_cache.regions.get(&match_ptr) {
             if let Some(ref region) = *maybe_region {
                 let match_start = region.pos(0).unwrap().0;
                 if match_start >= start {
                     // Cached match is valid, return it. Otherwise do another
                     // search below.
-                    return Some(region.clone());
+                    #[cfg(feature = "tracing")]
+                    {
+                        search_cache.stats.cache_hits += 1;
+                    }
+                    return Ok(Some(region.clone()));
                 }
             } else {
                 // Didn't find a match earlier, so no point trying to match it again
-                return None;
+                #[cfg(feature = "tracing")]
+                {
+                    search_cache.stats.cache_hits += 1;
+                }
+                return Ok(None);
+            }
+        }
+        // patterns that can't match in the rest of the line aren't searched at all
+        if !match_pat.regex.prefilter().may_match(line, start) {
+            if !(match_pat.has_captures && captures.is_some()) {
+                search_cache.insert(match_pat, None);
             }
+            return Ok(None);
+        }
+        #[cfg(feature = "tracing")]
+        {
+            search_cache.stats.searches += 1;
         }
 
+        search_cache.check_deadline()?;
+        search_cache.prepare_region(regions);
+        let search_start = search_cache.search_time.map(|_| Instant::now());
         let (matched, can_cache) = match (match_pat.has_captures, captures) {
             (true, Some(captures)) => {
                 let (region, s) = captures;
                 let regex = match_pat.regex_with_refs(region, s);
-                let matched = regex.search(line, start, line.len(), Some(regions));
-                (matched, false)
-            }
-            _ => {
-                let regex = match_pat.regex();
-                let matched = regex.search(line, start, line.len(), Some(regions));
-                (matched, true)
+                (self.search_regex(line, start, &regex, regions)?, false)
             }
+            _ => (
+                self.search_regex(line, start, match_pat.regex(), regions)?,
+                true,
+            ),
         };
+        if let (Some(time), Some(search_start)) = (&mut search_cache.search_time, search_start) {
+            *time += search_start.elapsed();
+        }
 
         if matched {
             let (match_start, match_end) = regions.pos(0).unwrap();
@@ -481,12 +1760,12 @@ impl ParseState {
             }
             if does_something {
                 // print!("catch {} at {} on {}", match_pat.regex_str, match_start, line);
-                return Some(regions.clone());
+                return Ok(Some(regions.clone()));
             }
         } else if can_cache {
             search_cache.insert(match_pat, None);
         }
-        None
+        Ok(None)
     }
 
     /// Returns true if the stack was changed
@@ -522,6 +1801,10 @@ impl ParseState {
             let mut map: Vec<((usize, i32), ScopeStackOp)> = Vec::new();
             for &(cap_index, ref scopes) in capture_map.iter() {
                 if let Some((cap_start, cap_end)) = reg_match.regions.pos(cap_index) {
+                    // groups inside lookaheads, kept with `LoadOptions::lookahead_captures`,
+                    // only get the scopes on the part of them that was matched
+                    let cap_start = cap_start.clamp(match_start, match_end);
+                    let cap_end = cap_end.clamp(match_start, match_end);
                     // marking up empty captures causes pops to be sorted wrong
                     if cap_start == cap_end {
                         continue;
@@ -600,7 +1883,7 @@ impl ParseState {
                     }
                     // add each context's meta scope
                     for r in context_refs.iter() {
-                        let ctx = r.resolve(syntax_set)?;
+                        let ctx = self.resolve_context(r, syntax_set)?;
 
                         if !is_set {
                             if let Some(clear_amount) = ctx.clear_scopes {
@@ -617,7 +1900,7 @@ impl ParseState {
                         && (!cur_context.meta_scope.is_empty()
                             || !cur_context.meta_content_scope.is_empty()))
                         || context_refs.iter().any(|r| {
-                            let ctx = r.resolve(syntax_set).unwrap();
+                            let ctx = self.resolve_context(r, syntax_set).unwrap();
 
                             !ctx.meta_content_scope.is_empty()
                                 || (ctx.clear_scopes.is_some() && is_set)
@@ -627,7 +1910,7 @@ impl ParseState {
                         let mut num_to_pop: usize = context_refs
                             .iter()
                             .map(|r| {
-                                let ctx = r.resolve(syntax_set).unwrap();
+                                let ctx = self.resolve_context(r, syntax_set).unwrap();
                                 ctx.meta_scope.len()
                             })
                             .sum();
@@ -645,7 +1928,7 @@ impl ParseState {
 
                         // now we push meta scope and meta context scope for each context pushed
                         for r in context_refs {
-                            let ctx = r.resolve(syntax_set)?;
+                            let ctx = self.resolve_context(r, syntax_set)?;
 
                             // for some reason, contrary to my reading of the docs, set does this after the token
                             if is_set {
@@ -678,20 +1961,31 @@ impl ParseState {
         pat: &MatchPattern,
         syntax_set: &SyntaxSet,
     ) -> Result<bool, ParsingError> {
-        let (ctx_refs, old_proto_ids) = match pat.operation {
-            MatchOperation::Push(ref ctx_refs) => (ctx_refs, None),
-            MatchOperation::Set(ref ctx_refs) => {
-                // a `with_prototype` stays active when the context is `set`
-                // until the context layer in the stack (where the `with_prototype`
-                // was initially applied) is popped off.
-                (ctx_refs, self.stack.pop().map(|s| s.prototypes))
-            }
+        let (ctx_refs, is_set) = match pat.operation {
+            MatchOperation::Push(ref ctx_refs) => (ctx_refs, false),
+            MatchOperation::Set(ref ctx_refs) => (ctx_refs, true),
             MatchOperation::Pop => {
-                self.stack.pop();
+                self.undo.stack.pop(&mut self.stack);
                 return Ok(true);
             }
             MatchOperation::None => return Ok(false),
         };
+        if let Some(max_depth) = self.settings.max_stack_depth {
+            // `set` replaces the context on top, so the stack is checked before changing it
+            let replaced = usize::from(is_set && !self.stack.is_empty());
+            if self.stack.len() - replaced + ctx_refs.len() > max_depth {
+                return Err(ParsingError::StackTooDeep(max_depth));
+            }
+        }
+        // a `with_prototype` stays active when the context is `set`
+        // until the context layer in the stack (where the `with_prototype`
+        // was initially applied) is popped off.
+        let old_proto_ids = if is_set {
+            self.undo.stack.pop(&mut self.stack).map(|s| s.prototypes)
+        } else {
+            None
+        };
+        let mut shared_captures = None;
         for (i, r) in ctx_refs.iter().enumerate() {
             let mut proto_ids = if i == 0 {
                 // it is only necessary to preserve the old prototypes
@@ -707,10 +2001,22 @@ impl ParseState {
                 // referred to as the "target" of the push by sublimehq - see
                 // https://forum.sublimetext.com/t/dev-build-3111/19240/17 for more info
                 if let Some(ref p) = pat.with_prototype {
-                    proto_ids.push(p.id()?);
+                    match p.id() {
+                        Ok(id) => proto_ids.push(id),
+                        Err(_)
+                            if self.settings.unresolved_context_policy
+                                == UnresolvedContextPolicy::PlainText =>
+                        {
+                            note_unresolved(&mut self.unresolved_contexts, p)
+                        }
+                        Err(e) => return Err(e),
+                    }
                 }
             }
-            let context_id = r.id()?;
+            let context_id = self.resolve_id(r, syntax_set)?;
+            if !matches!(r, ContextReference::Direct(_)) {
+                note_unresolved(&mut self.unresolved_contexts, r);
+            }
             let context = syntax_set.get_context(&context_id)?;
             let captures = {
                 let mut uses_backrefs = context.uses_backrefs;
@@ -721,7 +2027,9 @@ impl ParseState {
                             .any(|id| syntax_set.get_context(id).unwrap().uses_backrefs);
                 }
                 if uses_backrefs {
-                    Some((regions.clone(), line.to_owned()))
+                    let captures = shared_captures
+                        .get_or_insert_with(|| Arc::new((regions.clone(), line.to_owned())));
+                    Some(Arc::clone(captures))
                 } else {
                     None
                 }
@@ -736,12 +2044,89 @@ impl ParseState {
     }
 }
 
+/// Returns a context that a match pattern pushes, sets or uses as `with_prototype` but that
+/// wasn't found when the syntax set was built
+fn unresolved_reference(match_pat: &MatchPattern) -> Option<&ContextReference> {
+    let refs = match match_pat.operation {
+        MatchOperation::Push(ref refs) | MatchOperation::Set(ref refs) => &refs[..],
+        MatchOperation::Pop | MatchOperation::None => &[],
+    };
+    refs.iter()
+        .chain(&match_pat.with_prototype)
+        .find(|r| !matches!(r, ContextReference::Direct(_)))
+}
+
+fn note_unresolved(unresolved: &mut Vec<ContextReference>, reference: &ContextReference) {
+    if !unresolved.contains(reference) {
+        unresolved.push(reference.clone());
+    }
+}
+
+/// Collects the contexts that the patterns of a context push or set, following includes within
+/// the syntax, as `(is_set, contexts)`
+fn collect_pushes<'a, F>(
+    id: ContextId,
+    context: &F,
+    visited: &mut HashSet<ContextId>,
+    pushes: &mut Vec<(bool, Vec<ContextId>)>,
+) where
+    F: Fn(&ContextId) -> Option<&'a Context>,
+{
+    if !visited.insert(id) {
+        return;
+    }
+    let ctx = match context(&id) {
+        Some(ctx) => ctx,
+        None => return,
+    };
+    for pattern in &ctx.patterns {
+        match pattern {
+            Pattern::Match(match_pat) => {
+                let (is_set, refs) = match match_pat.operation {
+                    MatchOperation::Push(ref refs) => (false, refs),
+                    MatchOperation::Set(ref refs) => (true, refs),
+                    MatchOperation::Pop | MatchOperation::None => continue,
+                };
+                let ids: Option<Vec<ContextId>> = refs.iter().map(|r| r.id().ok()).collect();
+                if let Some(ids) = ids {
+                    pushes.push((is_set, ids));
+                }
+            }
+            Pattern::Include(reference) => {
+                if let Ok(included) = reference.id() {
+                    collect_pushes(included, context, visited, pushes);
+                }
+            }
+        }
+    }
+}
+
+/// Applies an operation, failing instead of ignoring pops of more scopes than there are
+#[cfg(feature = "paranoid")]
+fn apply_checked(scopes: &mut ScopeStack, op: &ScopeStackOp) -> Result<(), ParsingError> {
+    if let ScopeStackOp::Pop(count) = *op {
+        if count > scopes.len() {
+            return Err(ParsingError::BrokenInvariant(format!(
+                "popping {} scopes from {}",
+                count, scopes
+            )));
+        }
+    }
+    scopes
+        .apply(op)
+        .map_err(|e| ParsingError::BrokenInvariant(e.to_string()))
+}
+
 #[cfg(feature = "yaml-load")]
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parsing::ScopeStackOp::{Clear, Pop, Push, Restore};
     use crate::parsing::{Scope, ScopeStack, SyntaxSet, SyntaxSetBuilder};
+    use crate::test_utils::{
+        expect_scope_stacks, expect_scope_stacks_for_ops, expect_scope_stacks_with_syntax,
+        stack_states,
+    };
     use crate::util::debug_print_ops;
 
     const TEST_SYNTAX: &str = include_str!("../../testdata/parser_tests.sublime-syntax");
@@ -1040,6 +2425,375 @@ mod tests {
         expect_scope_stacks(line, &expect, TEST_SYNTAX);
     }
 
+    #[cfg(feature = "paranoid")]
+    #[test]
+    fn can_check_balanced_scopes() {
+        let syntax = SyntaxDefinition::load_from_str(TEST_SYNTAX, true, None).unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        state.check_balanced(&syntax_set).unwrap();
+        // stops inside the contexts that clear scopes
+        ops(
+            &mut state,
+            "'hello #nested_clear_scopes_test world\n",
+            &syntax_set,
+        );
+        state.check_balanced(&syntax_set).unwrap();
+        ops(&mut state, "foo bar test '\n", &syntax_set);
+        state.check_balanced(&syntax_set).unwrap();
+
+        let extra = Scope::new("extra").unwrap();
+        state.checked_scopes.push(extra);
+        assert!(matches!(
+            state.check_balanced(&syntax_set),
+            Err(ParsingError::BrokenInvariant(_))
+        ));
+        // popping the scope of the syntax fails when it's missing
+        state.checked_scopes = ScopeStack::new();
+        assert!(matches!(
+            state.check_balanced(&syntax_set),
+            Err(ParsingError::BrokenInvariant(_))
+        ));
+    }
+
+    #[test]
+    fn can_limit_backtracking() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+                name: Backtracking
+                scope: source.backtracking
+                contexts:
+                  main:
+                    - match: \(
+                      push: parens
+                  parens:
+                    - match: (a|b|ab)*(?=\d)
+                      scope: keyword.backtracking
+                    - match: \)
+                      pop: true
+                "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut state, "(\n", &syntax_set);
+
+        let catastrophic = format!(") ({}\n", "ab".repeat(12));
+        state.set_backtrack_limit(Some(1000));
+        let before = state.clone();
+        assert!(matches!(
+            state.parse_line(&catastrophic, &syntax_set),
+            Err(ParsingError::BacktrackLimitExceeded(regex)) if regex == r"(a|b|ab)*(?=\d)"
+        ));
+        assert_eq!(state.stack, before.stack);
+        assert_eq!(state.proto_starts, before.proto_starts);
+
+        state.set_backtrack_limit(None);
+        assert_eq!(state, before);
+        ops(&mut state, ") (ab\n", &syntax_set);
+    }
+
+    #[test]
+    fn can_time_out_line() {
+        let syntax = SyntaxDefinition::load_from_str(TEST_SYNTAX, true, None).unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut state, "'hello\n", &syntax_set);
+
+        state.set_regex_timeout(Some(Duration::ZERO));
+        let before = state.clone();
+        assert!(matches!(
+            state.parse_line("world' #nested_clear_scopes_test\n", &syntax_set),
+            Err(ParsingError::Timeout(timeout)) if timeout == Duration::ZERO
+        ));
+        assert_eq!(state.stack, before.stack);
+
+        state.set_regex_timeout(Some(Duration::from_secs(60)));
+        let timed = ops(
+            &mut state,
+            "world' #nested_clear_scopes_test\n",
+            &syntax_set,
+        );
+        let mut untimed_state = before;
+        untimed_state.set_regex_timeout(None);
+        let untimed = ops(
+            &mut untimed_state,
+            "world' #nested_clear_scopes_test\n",
+            &syntax_set,
+        );
+        assert_eq!(timed, untimed);
+    }
+
+    #[test]
+    fn shares_captures_and_limits_stack_depth() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+                name: Heredocs
+                scope: source.heredocs
+                contexts:
+                  main:
+                    - match: <<(\w+)
+                      push: [heredoc, heredoc]
+                  heredoc:
+                    - match: ^\1$
+                      pop: true
+                    - include: main
+                "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut state, "<<A\n", &syntax_set);
+        let top = &state.stack[state.stack.len() - 2..];
+        assert!(Arc::ptr_eq(
+            top[0].captures.as_ref().unwrap(),
+            top[1].captures.as_ref().unwrap()
+        ));
+
+        let depth = state.stack.len();
+        state.set_max_stack_depth(Some(depth + 3));
+        ops(&mut state, "<<B\n", &syntax_set);
+        let before = state.clone();
+        assert!(matches!(
+            state.parse_line("<<C\n", &syntax_set),
+            Err(ParsingError::StackTooDeep(max)) if max == depth + 3
+        ));
+        assert_eq!(state, before);
+        ops(&mut state, "B\n", &syntax_set);
+        assert_eq!(state.stack.len(), depth + 1);
+    }
+
+    #[test]
+    fn set_at_the_stack_depth_limit_replaces_the_top_context() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+                name: Sets
+                scope: source.sets
+                contexts:
+                  main:
+                    - match: a
+                      push: first
+                  first:
+                    - match: b
+                      set: second
+                    - match: c
+                      set: [second, second]
+                  second:
+                    - meta_scope: meta.second.sets
+                "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut state, "a", &syntax_set);
+        let depth = state.stack.len();
+        state.set_max_stack_depth(Some(depth));
+        let before = state.clone();
+        assert!(matches!(
+            state.parse_line("c", &syntax_set),
+            Err(ParsingError::StackTooDeep(max)) if max == depth
+        ));
+        assert_eq!(state, before);
+        ops(&mut state, "b", &syntax_set);
+        assert_eq!(state.stack.len(), depth);
+    }
+
+    #[test]
+    fn can_start_at_a_guessed_scope_stack() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+                name: Guess
+                scope: source.guess
+                contexts:
+                  main:
+                    - include: values
+                    - match: \[
+                      push: list
+                  values:
+                    - match: '"'
+                      push: string
+                  list:
+                    - meta_scope: meta.list.guess
+                    - match: \]
+                      pop: true
+                    - include: values
+                  string:
+                    - meta_scope: string.quoted.guess
+                    - match: \\
+                      set: [string, escape]
+                    - match: '"'
+                      pop: true
+                  escape:
+                    - meta_content_scope: constant.character.escape.guess
+                    - match: .
+                      pop: true
+                "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let syntax = &syntax_set.syntaxes()[0];
+        let start = |guess: &str| {
+            let (state, stack) = ParseState::new_at_scope_stack(syntax, &guess.parse().unwrap());
+            let names: Vec<String> = state
+                .context_stack_names(&syntax_set)
+                .into_iter()
+                .skip(2)
+                .collect();
+            (stack.to_string(), names)
+        };
+
+        assert_eq!(start(""), ("source.guess ".to_owned(), vec![]));
+        assert_eq!(
+            start("source.guess meta.list string"),
+            (
+                "source.guess meta.list.guess string.quoted.guess ".to_owned(),
+                vec!["Guess#list".to_owned(), "Guess#string".to_owned()]
+            )
+        );
+        // scopes the syntax can't get to are skipped, and `set` replaces the context on top
+        assert_eq!(
+            start("source.guess keyword string constant.character"),
+            (
+                "source.guess string.quoted.guess constant.character.escape.guess ".to_owned(),
+                vec!["Guess#string".to_owned(), "Guess#escape".to_owned()]
+            )
+        );
+
+        let (mut state, mut stack) =
+            ParseState::new_at_scope_stack(syntax, &"meta.list string".parse().unwrap());
+        for (_, op) in ops(&mut state, r#"a" "b""#, &syntax_set) {
+            stack.apply(&op).unwrap();
+        }
+        assert_eq!(stack.to_string(), "source.guess meta.list.guess ");
+    }
+
+    #[test]
+    fn rejects_another_syntax_set() {
+        let syntax = SyntaxDefinition::load_from_str(TEST_SYNTAX, true, None).unwrap();
+        let syntax_set = link(syntax);
+        let other = link(
+            SyntaxDefinition::load_from_str(
+                "name: Other\nscope: source.other\ncontexts: {main: []}\n",
+                true,
+                None,
+            )
+            .unwrap(),
+        );
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        assert!(matches!(
+            state.parse_line("'hello\n", &other),
+            Err(ParsingError::SyntaxSetMismatch)
+        ));
+        assert!(matches!(
+            state.clone().finalize(&SyntaxSet::new()),
+            Err(ParsingError::SyntaxSetMismatch)
+        ));
+        // a set built from the same syntax again is another set
+        let rebuilt = link(SyntaxDefinition::load_from_str(TEST_SYNTAX, true, None).unwrap());
+        assert!(matches!(
+            state.clone().parse_line("'hello\n", &rebuilt),
+            Err(ParsingError::SyntaxSetMismatch)
+        ));
+        ops(&mut state, "'hello\n", &syntax_set);
+
+        // the same place in the same syntax is equal, whatever set the states were made with
+        let mut fresh = ParseState::new(&rebuilt.syntaxes()[0]);
+        ops(&mut fresh, "'hello\n", &rebuilt);
+        let mut other_state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut other_state, "'hello\n", &syntax_set);
+        assert_eq!(fresh, other_state);
+
+        // an extended syntax set is fine, whether it's merged or built again
+        let extended = syntax_set.merge(other);
+        ops(&mut state, "world\n", &extended);
+        let mut builder = extended.into_builder();
+        builder.add(
+            SyntaxDefinition::load_from_str(
+                "name: Third\nscope: source.third\ncontexts: {main: []}\n",
+                true,
+                None,
+            )
+            .unwrap(),
+        );
+        ops(&mut state, "world'\n", &builder.build());
+    }
+
+    #[test]
+    fn can_fall_back_on_unresolved_contexts() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r"
+name: Test
+scope: source.test
+contexts:
+  main:
+    - match: '<%'
+      scope: punctuation.test
+      push: scope:source.missing
+    - match: '<'
+      scope: tag.test
+      push: Missing.sublime-syntax
+      with_prototype:
+        - include: scope:source.other-missing
+    - match: '\w+'
+      scope: word.test
+",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        builder.add_plain_text_syntax();
+        let ss = builder.build();
+        let syntax = ss.find_syntax_by_name("Test").unwrap();
+        let line = "a <% b\n";
+
+        let mut state = ParseState::new(syntax);
+        assert!(matches!(
+            state.parse_line(line, &ss),
+            Err(ParsingError::UnresolvedContextReference(_))
+        ));
+
+        // both patterns with unresolved references are skipped, so `<` and `%` are left unscoped
+        let mut state = ParseState::new(syntax);
+        state.set_unresolved_context_policy(UnresolvedContextPolicy::SkipPattern);
+        expect_scope_stacks_for_ops(&ops(&mut state, line, &ss), &["<source.test>, <word.test>"]);
+        assert_eq!(state.unresolved_contexts().len(), 2);
+        assert!(matches!(
+            state.unresolved_contexts()[0],
+            ContextReference::ByScope { .. }
+        ));
+        // which doesn't make the state differ from one that got to the same place without them
+        let mut unskipped = ParseState::new(syntax);
+        unskipped.set_unresolved_context_policy(UnresolvedContextPolicy::PlainText);
+        ops(&mut unskipped, "word\n", &ss);
+        assert!(unskipped.unresolved_contexts().is_empty());
+        assert_eq!(state, unskipped);
+
+        // the rest of the line is plain text after `<%`
+        let mut state = ParseState::new(syntax);
+        state.set_unresolved_context_policy(UnresolvedContextPolicy::PlainText);
+        let states = stack_states(&ops(&mut state, line, &ss));
+        assert!(states.contains(&"<source.test>, <punctuation.test>".to_owned()));
+        assert_eq!(
+            states.iter().filter(|s| s.ends_with("<word.test>")).count(),
+            1
+        );
+        assert_eq!(state.unresolved_contexts().len(), 1);
+        assert_eq!(
+            state.context_stack_names(&ss).last().unwrap(),
+            "Plain Text#main"
+        );
+    }
+
     #[test]
     fn can_parse_infinite_loop() {
         let line = "#infinite_loop_test 123";
@@ -1136,6 +2890,48 @@ contexts:
         expect_scope_stacks_with_syntax(line2, &expect2, syntax);
     }
 
+    #[test]
+    fn can_scope_captures_inside_lookaheads() {
+        let syntax = r#"
+name: test
+scope: source.test
+contexts:
+  main:
+    - match: '(?=(\[)(\w+)\]:)\[\w'
+      captures:
+        1: punctuation.definition.begin.test
+        2: entity.name.test
+"#;
+        let parse = |lookahead_captures| {
+            let options = crate::parsing::LoadOptions {
+                lookahead_captures,
+                ..Default::default()
+            };
+            let syntax =
+                SyntaxDefinition::load_from_str_with_options(syntax, true, None, &options).unwrap();
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(syntax);
+            let ss = builder.build();
+            let mut state = ParseState::new(&ss.syntaxes()[0]);
+            state.parse_line("[foo]: url", &ss).unwrap()
+        };
+        let begin = Scope::new("punctuation.definition.begin.test").unwrap();
+        let name = Scope::new("entity.name.test").unwrap();
+        let source = Scope::new("source.test").unwrap();
+        assert_eq!(parse(false), vec![(0, Push(source))]);
+        // the name only gets its scope on the `f` the pattern matched
+        assert_eq!(
+            parse(true),
+            vec![
+                (0, Push(source)),
+                (0, Push(begin)),
+                (1, Pop(1)),
+                (1, Push(name)),
+                (2, Pop(1)),
+            ]
+        );
+    }
+
     #[test]
     fn can_parse_non_consuming_pop_that_would_loop() {
         // See https://github.com/trishume/syntect/issues/127
@@ -1474,7 +3270,7 @@ contexts:
       scope: test.good
 "#;
 
-        let stack_states = stack_states(parse("aa b", syntax));
+        let stack_states = stack_states(&parse("aa b", syntax));
         assert_eq!(
             stack_states,
             vec![
@@ -1862,6 +3658,49 @@ contexts:
         expect_scope_stacks("\u{1F600}x", &["<source.test>, <test.good>"], syntax);
     }
 
+    #[test]
+    fn can_skip_by_grapheme_cluster() {
+        let syntax = r#"
+name: test
+scope: source.test
+contexts:
+  main:
+    - match: (?=.)
+      push: test
+  test:
+    - match: (?=.)
+      pop: true
+    - match: '[\x{0300}-\x{036F}\x{200D}\x{FE0F}]'
+      scope: test.split
+    - match: x
+      scope: test.good
+"#;
+        let syntax = SyntaxDefinition::load_from_str(syntax, true, None).unwrap();
+        let syntax_set = link(syntax);
+        let lines = [
+            // e + COMBINING ACUTE ACCENT
+            "e\u{0301}x\n",
+            // family: woman, ZWJ, woman, ZWJ, girl
+            "\u{1F469}\u{200D}\u{1F469}\u{200D}\u{1F467}x\n",
+            // heart with the emoji variation selector
+            "\u{2764}\u{FE0F}x\n",
+        ];
+        for line in lines {
+            let cluster_end = line.find('x').unwrap();
+
+            let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+            let by_char = ops(&mut state, line, &syntax_set);
+            assert!(by_char.iter().any(|&(i, _)| i > 0 && i < cluster_end));
+            expect_scope_stacks_for_ops(&by_char, &["<source.test>, <test.split>"]);
+
+            let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+            state.set_skip_by_grapheme_cluster(true);
+            let by_cluster = ops(&mut state, line, &syntax_set);
+            assert!(by_cluster.iter().all(|&(i, _)| i == 0 || i >= cluster_end));
+            expect_scope_stacks_for_ops(&by_cluster, &["<source.test>, <test.good>"]);
+        }
+    }
+
     #[test]
     fn can_include_backrefs() {
         let syntax = SyntaxDefinition::load_from_str(
@@ -1916,39 +3755,199 @@ contexts:
         expect_scope_stacks_with_syntax("aa", &["<a>", "<b>"], syntax);
     }
 
-    fn expect_scope_stacks(line_without_newline: &str, expect: &[&str], syntax: &str) {
-        println!("Parsing with newlines");
-        let line_with_newline = format!("{}\n", line_without_newline);
-        let syntax_newlines = SyntaxDefinition::load_from_str(syntax, true, None).unwrap();
-        expect_scope_stacks_with_syntax(&line_with_newline, expect, syntax_newlines);
-
-        println!("Parsing without newlines");
-        let syntax_nonewlines = SyntaxDefinition::load_from_str(syntax, false, None).unwrap();
-        expect_scope_stacks_with_syntax(line_without_newline, expect, syntax_nonewlines);
+    #[test]
+    fn can_show_context_stack() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+name: Example
+scope: source.example
+contexts:
+  main:
+    - match: a
+      push: inner
+      with_prototype:
+        - match: '1'
+          scope: '1'
+  inner:
+    - match: b
+      push:
+        - match: c
+          pop: true
+"#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        ops(&mut state, "ab", &syntax_set);
+        assert_eq!(
+            state.context_stack_names(&syntax_set),
+            [
+                "Example#__start",
+                "Example#__main",
+                "Example#inner",
+                "Example##anon_inner_0"
+            ]
+        );
+        assert_eq!(
+            state.context_stack_display(&syntax_set).to_string(),
+            "Example#__start\nExample#__main\n\
+             Example#inner (with_prototype: Example##anon_main_0)\n\
+             Example##anon_inner_0\n"
+        );
     }
 
-    fn expect_scope_stacks_with_syntax(line: &str, expect: &[&str], syntax: SyntaxDefinition) {
-        // check that each expected scope stack appears at least once while parsing the given test line
-
+    #[test]
+    fn can_trace_matches() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+name: Example
+scope: source.example
+contexts:
+  main:
+    - include: keywords
+    - match: '"'
+      scope: punctuation
+      push:
+        - meta_scope: string
+        - match: '"'
+          pop: true
+  keywords:
+    - match: '\bif\b'
+      scope: keyword
+"#,
+            true,
+            None,
+        )
+        .unwrap();
         let syntax_set = link(syntax);
         let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
-        let ops = ops(&mut state, line, &syntax_set);
-        expect_scope_stacks_for_ops(ops, expect);
+        let (ops, traces) = state.parse_line_traced("x if \"a\"", &syntax_set).unwrap();
+        let summary: Vec<_> = traces
+            .iter()
+            .map(|t| (t.range.clone(), t.context.as_str(), t.pattern_index))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0..0, "Example#__start", 0),
+                (2..4, "Example#keywords", 0),
+                (5..6, "Example#main", 1),
+                (7..8, "Example##anon_main_0", 0),
+            ]
+        );
+        assert_eq!(traces[1].regex, "\\bif\\b");
+        assert!(traces.iter().all(|t| !t.from_with_prototype));
+        assert_eq!(traces.last().unwrap().ops.end, ops.len());
+        assert!(traces.windows(2).all(|w| w[0].ops.end == w[1].ops.start));
     }
 
-    fn expect_scope_stacks_for_ops(ops: Vec<(usize, ScopeStackOp)>, expect: &[&str]) {
-        let mut criteria_met = Vec::new();
-        for stack_str in stack_states(ops) {
-            println!("{}", stack_str);
-            for expectation in expect.iter() {
-                if stack_str.contains(expectation) {
-                    criteria_met.push(expectation);
-                }
+    #[test]
+    fn combined_search_gives_the_same_ops() {
+        use crate::util::LinesWithEndings;
+
+        let ss = SyntaxSet::load_defaults_newlines();
+        let files = [
+            ("testdata/parser.rs", "rs"),
+            ("testdata/issue25.c", "c"),
+            ("testdata/jquery.js", "js"),
+            ("testdata/test3.html", "html"),
+            ("testdata/highlight_test.erb", "erb"),
+            ("testdata/issue30.tex", "tex"),
+            ("src/parsing/yaml_load.rs", "rs"),
+        ];
+        let mut combined_runs = 0;
+        for (path, extension) in files {
+            let text = std::fs::read_to_string(path).unwrap();
+            let syntax = ss.find_syntax_by_extension(extension).unwrap();
+            let mut state = ParseState::new(syntax);
+            let mut combined = ParseState::new(syntax);
+            combined.set_combined_search(true);
+            for (i, line) in LinesWithEndings::from(&text).enumerate() {
+                let expected = state.parse_line(line, &ss).unwrap();
+                let ops = combined.parse_line(line, &ss).unwrap();
+                assert_eq!(ops, expected, "{} line {}", path, i + 1);
+            }
+            combined_runs += combined
+                .stack
+                .iter()
+                .filter(|level| ss.combined_patterns(&level.context).is_some())
+                .count();
+        }
+        // the contexts at the end of the files
+        assert!(combined_runs > 0);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn can_trace_line_stats() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Collects the numeric fields of the events
+        #[derive(Default)]
+        struct Collect(Arc<Mutex<Vec<(String, u64)>>>);
+
+        impl Visit for Collect {
+            fn record_u64(&mut self, field: &Field, value: u64) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push((field.name().to_owned(), value));
             }
+            fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
         }
-        if let Some(missing) = expect.iter().find(|e| !criteria_met.contains(e)) {
-            panic!("expected scope stack '{}' missing", missing);
+
+        impl Subscriber for Collect {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _span: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                event.record(&mut Collect(self.0.clone()));
+            }
+            fn enter(&self, _span: &Id) {}
+            fn exit(&self, _span: &Id) {}
         }
+
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+name: Example
+scope: source.example
+contexts:
+  main:
+    - match: a
+      scope: a
+    - match: b
+      scope: b
+"#,
+            true,
+            None,
+        )
+        .unwrap();
+        let syntax_set = link(syntax);
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        let collect = Collect::default();
+        let fields = collect.0.clone();
+        tracing::subscriber::with_default(collect, || {
+            state.parse_line("ba", &syntax_set).unwrap();
+        });
+
+        let fields = fields.lock().unwrap();
+        let field = |name: &str| fields.iter().find(|(n, _)| n == name).unwrap().1;
+        // Pushing `main` from `__start` counts as a token too
+        assert_eq!(field("tokens"), 3);
+        // The match of `a` found while looking for the first token is used for the second one,
+        // and `b` isn't searched again in the rest of the line, which doesn't contain it
+        assert_eq!(field("searches"), 3);
+        assert_eq!(field("cache_hits"), 1);
     }
 
     fn parse(line: &str, syntax: &str) -> Vec<(usize, ScopeStackOp)> {
@@ -1974,20 +3973,4 @@ contexts:
         debug_print_ops(line, &ops);
         ops
     }
-
-    fn stack_states(ops: Vec<(usize, ScopeStackOp)>) -> Vec<String> {
-        let mut states = Vec::new();
-        let mut stack = ScopeStack::new();
-        for (_, op) in ops.iter() {
-            stack.apply(op).expect("#[cfg(test)]");
-            let scopes: Vec<String> = stack
-                .as_slice()
-                .iter()
-                .map(|s| format!("{:?}", s))
-                .collect();
-            let stack_str = scopes.join(", ");
-            states.push(stack_str);
-        }
-        states
-    }
 }
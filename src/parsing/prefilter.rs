@@ -0,0 +1,202 @@
+//! Cheap checks for where the regex of a pattern can match, so that the parser can skip searching
+//! patterns that can't match in the rest of a line
+//!
+//! Patterns starting with `^` only match at the start of a line, ones starting with `\G` only
+//! where the search starts, and many others start with a fixed text like `/*` or `#include`. The
+//! analysis is conservative: anything it doesn't understand, like alternations at the top level,
+//! case-insensitive or extended mode, leaves the pattern without a prefilter.
+
+use super::combined::flag_group;
+
+/// Where a regex can start matching, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Prefilter {
+    anchor: Anchor,
+    /// Text that every match starts with, after the anchor
+    prefix: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Anchor {
+    #[default]
+    None,
+    /// `^` or `\A`, at the start of the line or after a newline in it
+    LineStart,
+    /// `\G`, where the search starts
+    SearchStart,
+}
+
+impl Prefilter {
+    pub(crate) fn new(regex: &str) -> Prefilter {
+        analyze(regex).unwrap_or_default()
+    }
+
+    /// Returns false if the regex can't match anywhere in `line` from `start` on
+    pub(crate) fn may_match(&self, line: &str, start: usize) -> bool {
+        let prefix = self.prefix.as_str();
+        match self.anchor {
+            Anchor::None => prefix.is_empty() || line[start..].contains(prefix),
+            Anchor::SearchStart => line[start..].starts_with(prefix),
+            Anchor::LineStart => {
+                let bytes = line.as_bytes();
+                (start..=bytes.len())
+                    .filter(|&pos| pos == 0 || bytes[pos - 1] == b'\n')
+                    .any(|pos| line[pos..].starts_with(prefix))
+            }
+        }
+    }
+}
+
+fn analyze(regex: &str) -> Option<Prefilter> {
+    if !without_top_level_alternation(regex) {
+        return None;
+    }
+    let mut rest = regex;
+    let mut case_insensitive = false;
+    while let Some((flags, true)) = flag_group(rest.as_bytes()) {
+        let (on, off) =
+            flags.split_at(flags.iter().position(|&b| b == b'-').unwrap_or(flags.len()));
+        case_insensitive = (case_insensitive || on.contains(&b'i')) && !off.contains(&b'i');
+        rest = &rest[flags.len() + 3..];
+    }
+    let anchor = if let Some(after) = rest.strip_prefix('^').or(rest.strip_prefix("\\A")) {
+        rest = after;
+        Anchor::LineStart
+    } else if let Some(after) = rest.strip_prefix("\\G") {
+        rest = after;
+        Anchor::SearchStart
+    } else {
+        Anchor::None
+    };
+    // word boundaries don't take up any text
+    while let Some(after) = rest.strip_prefix("\\b").or(rest.strip_prefix("\\B")) {
+        rest = after;
+    }
+    let mut prefix = String::new();
+    if !case_insensitive {
+        let mut chars = rest.chars().peekable();
+        while let Some(c) = chars.next() {
+            let literal = match c {
+                '\\' => match chars.next() {
+                    Some(escaped) if LITERAL_ESCAPES.contains(escaped) => escaped,
+                    _ => break,
+                },
+                '.' | '^' | '$' | '|' | '(' | ')' | '[' | ']' | '{' | '}' | '*' | '+' | '?' => {
+                    break
+                }
+                c => c,
+            };
+            match chars.peek() {
+                // the character is optional or repeated
+                Some('?' | '*' | '{') => break,
+                Some('+') => {
+                    prefix.push(literal);
+                    break;
+                }
+                _ => prefix.push(literal),
+            }
+        }
+    }
+    Some(Prefilter { anchor, prefix })
+}
+
+/// Punctuation that stands for itself when escaped in both regex engines, unlike e.g. `\<`
+const LITERAL_ESCAPES: &str = "\\.^$|()[]{}*+?/-\"#&~!@%,:;=_ ";
+
+/// Returns false if the regex has a `|` outside of groups, or uses extended mode, where `#`
+/// starts a comment
+fn without_top_level_alternation(regex: &str) -> bool {
+    let bytes = regex.as_bytes();
+    let mut depth = 0;
+    let mut class_depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => {
+                class_depth += 1;
+                // a `]` right at the start of a class is a literal
+                if bytes.get(i + 1) == Some(&b'^') {
+                    i += 1;
+                }
+                if bytes.get(i + 1) == Some(&b']') {
+                    i += 1;
+                }
+            }
+            b']' if class_depth > 0 => class_depth -= 1,
+            _ if class_depth > 0 => {}
+            b'(' => {
+                if let Some((flags, _)) = flag_group(&bytes[i..]) {
+                    if flags.contains(&b'x') {
+                        return false;
+                    }
+                }
+                depth += 1;
+            }
+            b')' => depth -= 1,
+            b'|' if depth == 0 => return false,
+            _ => {}
+        }
+        i += 1;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefilter(anchor: Anchor, prefix: &str) -> Prefilter {
+        Prefilter {
+            anchor,
+            prefix: prefix.to_owned(),
+        }
+    }
+
+    #[test]
+    fn finds_anchors_and_prefixes() {
+        assert_eq!(Prefilter::new(r"/\*"), prefilter(Anchor::None, "/*"));
+        assert_eq!(
+            Prefilter::new(r"^\s*#\s*include"),
+            prefilter(Anchor::LineStart, "")
+        );
+        assert_eq!(
+            Prefilter::new(r"^#include\b"),
+            prefilter(Anchor::LineStart, "#include")
+        );
+        assert_eq!(
+            Prefilter::new(r"\G\bfn\b"),
+            prefilter(Anchor::SearchStart, "fn")
+        );
+        assert_eq!(Prefilter::new(r"(?-i)ab?c"), prefilter(Anchor::None, "a"));
+        assert_eq!(Prefilter::new(r#"\"+x"#), prefilter(Anchor::None, "\""));
+        assert_eq!(Prefilter::new("(?i)abc"), Prefilter::default());
+        assert_eq!(Prefilter::new("^a|b"), Prefilter::default());
+        assert_eq!(Prefilter::new("(?x)^ a"), Prefilter::default());
+        assert_eq!(
+            Prefilter::new(r"^(a|b)[|(]"),
+            prefilter(Anchor::LineStart, "")
+        );
+        assert_eq!(Prefilter::new(r"\<a"), Prefilter::default());
+    }
+
+    #[test]
+    fn skips_lines_that_cant_match() {
+        let line_start = prefilter(Anchor::LineStart, "#");
+        assert!(line_start.may_match("#if\n", 0));
+        assert!(!line_start.may_match("#if\n", 1));
+        assert!(line_start.may_match("a\n#if", 1));
+        assert!(line_start.may_match("a\n#if", 2));
+        assert!(!line_start.may_match("a\n#if", 3));
+        assert!(!line_start.may_match("a#if\n", 0));
+
+        let search_start = prefilter(Anchor::SearchStart, "fn");
+        assert!(search_start.may_match("pub fn", 4));
+        assert!(!search_start.may_match("pub fn", 3));
+
+        let literal = prefilter(Anchor::None, "*/");
+        assert!(literal.may_match("a */ b", 2));
+        assert!(!literal.may_match("a */ b", 3));
+        assert!(Prefilter::default().may_match("", 0));
+    }
+}
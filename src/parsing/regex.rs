@@ -1,7 +1,10 @@
+#[cfg(feature = "parsing")]
+use super::prefilter::Prefilter;
 use once_cell::sync::OnceCell;
 use serde::de::{Deserialize, Deserializer};
 use serde::ser::{Serialize, Serializer};
 use std::error::Error;
+use std::sync::Arc;
 
 /// An abstraction for regex patterns.
 ///
@@ -12,12 +15,22 @@ use std::error::Error;
 pub struct Regex {
     regex_str: String,
     regex: OnceCell<regex_impl::Regex>,
+    #[cfg(feature = "parsing")]
+    prefilter: OnceCell<Prefilter>,
 }
 
+/// A search gave up because the regex backtracked more than the limit it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct BacktrackLimitExceeded;
+
 /// A region contains text positions for capture groups in a match result.
+///
+/// Cloning a region is cheap: clones share the positions until one of them is used for another
+/// search, which copies them first. The parser keeps clones of the regions of matches for its
+/// search cache and for the captures of contexts, which mostly aren't searched with again.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Region {
-    region: regex_impl::Region,
+    positions: Arc<Vec<Option<(usize, usize)>>>,
 }
 
 impl Regex {
@@ -29,6 +42,8 @@ impl Regex {
         Self {
             regex_str,
             regex: OnceCell::new(),
+            #[cfg(feature = "parsing")]
+            prefilter: OnceCell::new(),
         }
     }
 
@@ -37,6 +52,28 @@ impl Regex {
         regex_impl::Regex::new(regex_str).err()
     }
 
+    /// Compiles the pattern right away, returning `None` if it isn't a valid regex
+    pub(crate) fn try_new(regex_str: String) -> Option<Self> {
+        let regex = regex_impl::Regex::new(&regex_str).ok()?;
+        Some(Self {
+            regex_str,
+            regex: OnceCell::from(regex),
+            #[cfg(feature = "parsing")]
+            prefilter: OnceCell::new(),
+        })
+    }
+
+    /// The number of capture groups in the pattern, not counting the whole match
+    pub(crate) fn captures_len(&self) -> usize {
+        self.regex().captures_len()
+    }
+
+    /// Whether the regex has been compiled, which happens on first use
+    #[cfg(feature = "memory-report")]
+    pub(crate) fn is_compiled(&self) -> bool {
+        self.regex.get().is_some()
+    }
+
     /// Return the regex pattern.
     pub fn regex_str(&self) -> &str {
         &self.regex_str
@@ -61,12 +98,46 @@ impl Regex {
         end: usize,
         region: Option<&mut Region>,
     ) -> bool {
-        self.regex()
-            .search(text, begin, end, region.map(|r| &mut r.region))
+        matches!(
+            self.search_with_limit(text, begin, end, region, None),
+            Ok(true)
+        )
+    }
+
+    /// Like [`search`](#method.search), but fails instead of treating it as no match when the
+    /// regex backtracks more than `limit` times
+    ///
+    /// Oniguruma counts the retries of each match attempt, and fancy-regex the backtracking steps
+    /// of the whole search. The regex crate, used with the `regex-onepass` feature, never
+    /// backtracks.
+    pub(crate) fn search_with_limit(
+        &self,
+        text: &str,
+        begin: usize,
+        end: usize,
+        region: Option<&mut Region>,
+        limit: Option<u32>,
+    ) -> Result<bool, BacktrackLimitExceeded> {
+        self.regex().search(
+            text,
+            begin,
+            end,
+            region.map(|r| Arc::make_mut(&mut r.positions)),
+            limit,
+        )
+    }
+
+    /// Where the regex can start matching, worked out from the pattern the first time it's needed
+    #[cfg(feature = "parsing")]
+    pub(crate) fn prefilter(&self) -> &Prefilter {
+        self.prefilter
+            .get_or_init(|| Prefilter::new(&self.regex_str))
     }
 
     fn regex(&self) -> &regex_impl::Regex {
         self.regex.get_or_init(|| {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("compile_regex", pattern = %self.regex_str).entered();
             regex_impl::Regex::new(&self.regex_str).expect("regex string should be pre-tested")
         })
     }
@@ -77,6 +148,8 @@ impl Clone for Regex {
         Regex {
             regex_str: self.regex_str.clone(),
             regex: OnceCell::new(),
+            #[cfg(feature = "parsing")]
+            prefilter: self.prefilter.clone(),
         }
     }
 }
@@ -111,7 +184,7 @@ impl<'de> Deserialize<'de> for Regex {
 impl Region {
     pub fn new() -> Self {
         Self {
-            region: regex_impl::new_region(),
+            positions: Arc::new(Vec::with_capacity(8)),
         }
     }
 
@@ -120,7 +193,12 @@ impl Region {
     /// If there is no match for that group or the index does not correspond to a group, `None` is
     /// returned. The index 0 returns the whole match.
     pub fn pos(&self, index: usize) -> Option<(usize, usize)> {
-        self.region.pos(index)
+        self.positions.get(index).copied().flatten()
+    }
+
+    /// Whether the positions are shared with a clone, so a search would have to copy them first
+    pub(crate) fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.positions) > 1
     }
 }
 
@@ -132,17 +210,23 @@ impl Default for Region {
 
 #[cfg(feature = "regex-onig")]
 mod regex_impl {
-    pub use onig::Region;
-    use onig::{MatchParam, RegexOptions, SearchOptions, Syntax};
+    use super::BacktrackLimitExceeded;
+    use onig::{MatchParam, RegexOptions, Region, SearchOptions, Syntax};
+    use std::cell::RefCell;
     use std::error::Error;
 
+    /// `ONIGERR_RETRY_LIMIT_IN_MATCH_OVER`, which isn't exported by the bindings
+    const RETRY_LIMIT_IN_MATCH_OVER: i32 = -17;
+
     #[derive(Debug)]
     pub struct Regex {
         regex: onig::Regex,
     }
 
-    pub fn new_region() -> Region {
-        Region::with_capacity(8)
+    thread_local! {
+        /// Oniguruma's regions aren't `Send`, so searches write the positions to a region of
+        /// the thread and copy them out of it
+        static REGION: RefCell<Region> = RefCell::new(Region::with_capacity(8));
     }
 
     impl Regex {
@@ -158,6 +242,10 @@ mod regex_impl {
             }
         }
 
+        pub fn captures_len(&self) -> usize {
+            self.regex.captures_len()
+        }
+
         pub fn is_match(&self, text: &str) -> bool {
             self.regex
                 .match_with_options(text, 0, SearchOptions::SEARCH_OPTION_NONE, None)
@@ -169,21 +257,46 @@ mod regex_impl {
             text: &str,
             begin: usize,
             end: usize,
-            region: Option<&mut Region>,
-        ) -> bool {
-            let matched = self.regex.search_with_param(
-                text,
-                begin,
-                end,
-                SearchOptions::SEARCH_OPTION_NONE,
-                region,
-                MatchParam::default(),
-            );
-
-            // If there's an error during search, treat it as non-matching.
-            // For example, in case of catastrophic backtracking, onig should
-            // fail with a "retry-limit-in-match over" error eventually.
-            matches!(matched, Ok(Some(_)))
+            positions: Option<&mut Vec<Option<(usize, usize)>>>,
+            limit: Option<u32>,
+        ) -> Result<bool, BacktrackLimitExceeded> {
+            let mut param = MatchParam::default();
+            if let Some(limit) = limit {
+                param.set_retry_limit_in_match(limit);
+            }
+            let search = |region: Option<&mut Region>| {
+                self.regex.search_with_param(
+                    text,
+                    begin,
+                    end,
+                    SearchOptions::SEARCH_OPTION_NONE,
+                    region,
+                    param,
+                )
+            };
+            let matched = match positions {
+                Some(positions) => REGION.with(|region| {
+                    let mut region = region.borrow_mut();
+                    let matched = search(Some(&mut *region));
+                    if let Ok(Some(_)) = matched {
+                        positions.clear();
+                        positions.extend((0..region.len()).map(|i| region.pos(i)));
+                    }
+                    matched
+                }),
+                None => search(None),
+            };
+
+            match matched {
+                Ok(matched) => Ok(matched.is_some()),
+                Err(e) if limit.is_some() && e.code() == RETRY_LIMIT_IN_MATCH_OVER => {
+                    Err(BacktrackLimitExceeded)
+                }
+                // If there's an error during search, treat it as non-matching.
+                // For example, in case of catastrophic backtracking, onig should
+                // fail with a "retry-limit-in-match over" error eventually.
+                Err(_) => Ok(false),
+            }
         }
     }
 }
@@ -191,36 +304,57 @@ mod regex_impl {
 // If both regex-fancy and regex-onig are requested, this condition makes regex-onig win.
 #[cfg(all(feature = "regex-fancy", not(feature = "regex-onig")))]
 mod regex_impl {
+    use super::BacktrackLimitExceeded;
+    use fancy_regex::{RegexBuilder, RuntimeError};
+    use once_cell::sync::OnceCell;
     use std::error::Error;
 
     #[derive(Debug)]
-    pub struct Regex {
-        regex: fancy_regex::Regex,
-    }
-
-    #[derive(Clone, Debug, Eq, PartialEq)]
-    pub struct Region {
-        positions: Vec<Option<(usize, usize)>>,
-    }
-
-    pub fn new_region() -> Region {
-        Region {
-            positions: Vec::with_capacity(8),
-        }
+    pub enum Regex {
+        /// A pattern without lookaround or backreferences, which the regex crate matches in
+        /// linear time. Only used with the `regex-onepass` feature.
+        #[cfg(feature = "regex-onepass")]
+        Linear(regex::Regex),
+        Fancy {
+            regex: fancy_regex::Regex,
+            /// The regex compiled again with the first backtrack limit it was searched with, since
+            /// fancy-regex only takes a limit when compiling
+            limited: OnceCell<(u32, fancy_regex::Regex)>,
+        },
     }
 
     impl Regex {
         pub fn new(regex_str: &str) -> Result<Regex, Box<dyn Error + Send + Sync + 'static>> {
+            #[cfg(feature = "regex-onepass")]
+            if let Some(regex) = linear_regex(regex_str) {
+                return Ok(Regex::Linear(regex));
+            }
             let result = fancy_regex::Regex::new(regex_str);
             match result {
-                Ok(regex) => Ok(Regex { regex }),
+                Ok(regex) => Ok(Regex::Fancy {
+                    regex,
+                    limited: OnceCell::new(),
+                }),
                 Err(error) => Err(Box::new(error)),
             }
         }
 
+        pub fn captures_len(&self) -> usize {
+            // both count the whole match as a group
+            match self {
+                #[cfg(feature = "regex-onepass")]
+                Regex::Linear(regex) => regex.captures_len() - 1,
+                Regex::Fancy { regex, .. } => regex.captures_len() - 1,
+            }
+        }
+
         pub fn is_match(&self, text: &str) -> bool {
-            // Errors are treated as non-matches
-            self.regex.is_match(text).unwrap_or(false)
+            match self {
+                #[cfg(feature = "regex-onepass")]
+                Regex::Linear(regex) => regex.is_match(text),
+                // Errors are treated as non-matches
+                Regex::Fancy { regex, .. } => regex.is_match(text).unwrap_or(false),
+            }
         }
 
         pub fn search(
@@ -228,37 +362,122 @@ mod regex_impl {
             text: &str,
             begin: usize,
             end: usize,
-            region: Option<&mut Region>,
-        ) -> bool {
-            // If there's an error during search, treat it as non-matching.
-            // For example, in case of catastrophic backtracking, fancy-regex should
-            // fail with an error eventually.
-            if let Ok(Some(captures)) = self.regex.captures_from_pos(&text[..end], begin) {
-                if let Some(region) = region {
-                    region.init_from_captures(&captures);
+            positions: Option<&mut Vec<Option<(usize, usize)>>>,
+            limit: Option<u32>,
+        ) -> Result<bool, BacktrackLimitExceeded> {
+            match self {
+                #[cfg(feature = "regex-onepass")]
+                Regex::Linear(regex) => Ok(search_linear(regex, &text[..end], begin, positions)),
+                Regex::Fancy { regex, limited } => {
+                    let text = &text[..end];
+                    match limit {
+                        Some(limit) => {
+                            let (cached, limited) =
+                                limited.get_or_init(|| (limit, with_limit(regex, limit)));
+                            if *cached == limit {
+                                search_fancy(limited, text, begin, positions, true)
+                            } else {
+                                // only the first limit is kept, others are compiled every time
+                                let regex = with_limit(regex, limit);
+                                search_fancy(&regex, text, begin, positions, true)
+                            }
+                        }
+                        None => search_fancy(regex, text, begin, positions, false),
+                    }
                 }
-                true
-            } else {
-                false
             }
         }
     }
 
-    impl Region {
-        fn init_from_captures(&mut self, captures: &fancy_regex::Captures) {
-            self.positions.clear();
-            for i in 0..captures.len() {
-                let pos = captures.get(i).map(|m| (m.start(), m.end()));
-                self.positions.push(pos);
+    /// Compiles the regex again with a backtrack limit
+    fn with_limit(regex: &fancy_regex::Regex, limit: u32) -> fancy_regex::Regex {
+        RegexBuilder::new(regex.as_str())
+            .backtrack_limit(limit as usize)
+            .build()
+            // it compiled without the limit, so this doesn't happen
+            .unwrap_or_else(|_| regex.clone())
+    }
+
+    fn search_fancy(
+        regex: &fancy_regex::Regex,
+        text: &str,
+        begin: usize,
+        positions: Option<&mut Vec<Option<(usize, usize)>>>,
+        limited: bool,
+    ) -> Result<bool, BacktrackLimitExceeded> {
+        match regex.captures_from_pos(text, begin) {
+            Ok(Some(captures)) => {
+                if let Some(positions) = positions {
+                    positions.clear();
+                    positions.extend(captures.iter().map(|m| m.map(|m| (m.start(), m.end()))));
+                }
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(fancy_regex::Error::RuntimeError(RuntimeError::BacktrackLimitExceeded))
+                if limited =>
+            {
+                Err(BacktrackLimitExceeded)
             }
+            // If there's an error during search, treat it as non-matching.
+            // For example, in case of catastrophic backtracking, fancy-regex should
+            // fail with an error eventually.
+            Err(_) => Ok(false),
         }
+    }
+
+    /// Compiles the pattern with the regex crate if it doesn't need any of the features of
+    /// fancy-regex. It's translated to the syntax of the regex crate the same way fancy-regex does
+    /// for such patterns, since escapes like `\<` mean different things in the two.
+    #[cfg(feature = "regex-onepass")]
+    fn linear_regex(regex_str: &str) -> Option<regex::Regex> {
+        let tree = fancy_regex::Expr::parse_tree(regex_str).ok()?;
+        if !is_linear(&tree.expr) {
+            return None;
+        }
+        let mut translated = String::new();
+        tree.expr.to_str(&mut translated, 0);
+        // e.g. patterns with huge Unicode classes can be over the size limit of the regex crate
+        regex::Regex::new(&translated).ok()
+    }
 
-        pub fn pos(&self, i: usize) -> Option<(usize, usize)> {
-            if i < self.positions.len() {
-                self.positions[i]
-            } else {
-                None
+    #[cfg(feature = "regex-onepass")]
+    fn is_linear(expr: &fancy_regex::Expr) -> bool {
+        use fancy_regex::Expr;
+        match expr {
+            Expr::Empty
+            | Expr::Any { .. }
+            | Expr::Literal { .. }
+            | Expr::StartText
+            | Expr::EndText
+            | Expr::StartLine
+            | Expr::EndLine
+            | Expr::Delegate { .. } => true,
+            Expr::Concat(children) | Expr::Alt(children) => children.iter().all(is_linear),
+            Expr::Group(child) | Expr::Repeat { child, .. } => is_linear(child),
+            // lookaround, backreferences, atomic groups and so on
+            _ => false,
+        }
+    }
+
+    #[cfg(feature = "regex-onepass")]
+    fn search_linear(
+        regex: &regex::Regex,
+        text: &str,
+        begin: usize,
+        positions: Option<&mut Vec<Option<(usize, usize)>>>,
+    ) -> bool {
+        let positions = match positions {
+            Some(positions) => positions,
+            None => return regex.find_at(text, begin).is_some(),
+        };
+        match regex.captures_at(text, begin) {
+            Some(captures) => {
+                positions.clear();
+                positions.extend(captures.iter().map(|m| m.map(|m| (m.start(), m.end()))));
+                true
             }
+            None => false,
         }
     }
 }
@@ -267,6 +486,13 @@ mod regex_impl {
 mod tests {
     use super::*;
 
+    #[test]
+    fn regions_can_be_sent_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Region>();
+        assert_send_sync::<crate::parsing::ParseState>();
+    }
+
     #[test]
     fn caches_compiled_regex() {
         let regex = Regex::new(String::from(r"\w+"));
@@ -283,4 +509,30 @@ mod tests {
         let back_to_str = serde_json::to_string(&pattern).unwrap();
         assert_eq!(back_to_str, "\"just a string\"");
     }
+
+    #[cfg(all(feature = "regex-onepass", not(feature = "regex-onig")))]
+    #[test]
+    fn routes_simple_patterns_to_linear_engine() {
+        let linear = |pattern: &str| {
+            let regex = Regex::new(pattern.to_owned());
+            matches!(regex.regex(), regex_impl::Regex::Linear(_))
+        };
+        assert!(linear(r"\b(fn|let)\s+([a-z_]\w*)"));
+        assert!(linear(r"^\s*#\[?[[:alpha:]]+"));
+        assert!(!linear(r"(?<=\.)\w+"));
+        assert!(!linear(r#"(['"])(.*?)\1"#));
+        assert!(!linear(r"(?>a+)b"));
+
+        let mut region = Region::new();
+        let regex = Regex::new(r"(\w+)|(\d)".to_owned());
+        assert!(regex.search("  abc def", 3, 9, Some(&mut region)));
+        assert_eq!(region.pos(0), Some((3, 5)));
+        assert_eq!(region.pos(1), Some((3, 5)));
+        assert_eq!(region.pos(2), None);
+        assert!(!regex.search("  abc def", 5, 6, None));
+        // anchors see the whole text, not just the part from `begin`
+        assert!(!Regex::new("^abc".to_owned()).search("xabc", 1, 4, None));
+        // an escape that means a word boundary in the regex crate, but `<` for Oniguruma
+        assert!(Regex::new(r"\<a".to_owned()).is_match("<a"));
+    }
 }
@@ -1,8 +1,8 @@
 // see DESIGN.md
+use std::cell::RefCell;
 use std::cmp::{min, Ordering};
 use std::collections::HashMap;
 use std::fmt;
-use std::mem;
 use std::str::FromStr;
 use std::sync::Mutex;
 use std::u16;
@@ -12,6 +12,7 @@ use once_cell::sync::Lazy;
 use serde::de::{Deserialize, Deserializer, Error, Visitor};
 use serde::ser::{Serialize, Serializer};
 use serde_derive::{Deserialize, Serialize};
+use smallvec::SmallVec;
 
 /// Scope related errors
 #[derive(Debug, thiserror::Error)]
@@ -35,6 +36,31 @@ pub const ATOM_LEN_BITS: u16 = 3;
 pub static SCOPE_REPO: Lazy<Mutex<ScopeRepository>> =
     Lazy::new(|| Mutex::new(ScopeRepository::new()));
 
+thread_local! {
+    /// This thread's copy of the atoms in [`SCOPE_REPO`] it has used so far, so that looking up
+    /// scopes it has seen before doesn't contend for the global lock
+    static LOCAL_REPO: RefCell<ScopeRepository> = RefCell::new(ScopeRepository::new());
+}
+
+/// Runs `f` with a repository that knows all the atoms of `scope`, which is this thread's copy of
+/// [`SCOPE_REPO`] unless the scope has atoms it hasn't seen yet. Only then the global repository is
+/// locked to bring the copy up to date.
+pub(crate) fn with_repo_for<T>(scope: Scope, f: impl FnOnce(&ScopeRepository) -> T) -> T {
+    let mut f = Some(f);
+    let local = LOCAL_REPO.try_with(|local| {
+        let mut local = local.borrow_mut();
+        if !local.contains(scope) {
+            local.catch_up(&SCOPE_REPO.lock().unwrap());
+        }
+        (f.take().unwrap())(&local)
+    });
+    match local {
+        Ok(res) => res,
+        // the thread is shutting down, so there is no copy anymore
+        Err(_) => (f.take().unwrap())(&SCOPE_REPO.lock().unwrap()),
+    }
+}
+
 /// A hierarchy of atoms with semi-standardized names used to accord semantic information to a
 /// specific piece of text.
 ///
@@ -50,6 +76,10 @@ pub static SCOPE_REPO: Lazy<Mutex<ScopeRepository>> =
 /// bit numbers to represent and compare atoms. Like "atoms" or "symbols" in other languages. This
 /// means that while comparing and prefix are fast, extracting a string is relatively slower but
 /// ideally should be very rare.
+///
+/// There's only room for 8 atoms. Scopes with more atoms than that store everything from the 8th
+/// atom onwards as a single overflow atom (e.g. `h.i.j` for `a.b.c.d.e.f.g.h.i.j`), so they can
+/// still be used, but prefix checks treat the overflow atom as a whole.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Default, Hash)]
 pub struct Scope {
     a: u64,
@@ -60,14 +90,31 @@ pub struct Scope {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum ParseScopeError {
-    /// Due to a limitation of the current optimized internal representation
-    /// scopes can be at most 8 atoms long
+    /// Scopes used to be limited to 8 atoms, this is no longer returned for scope strings since
+    /// longer scopes are stored with an overflow atom, only by [`Scope::from_atoms`]
+    ///
+    /// [`Scope::from_atoms`]: struct.Scope.html#method.from_atoms
     #[error("Too long scope. Scopes can be at most 8 atoms long.")]
     TooLong,
     /// The internal representation uses 16 bits per atom, so if all scopes ever
     /// used by the program have more than 2^16-2 atoms, things break
     #[error("Too many atoms. Max 2^16-2 atoms allowed.")]
     TooManyAtoms,
+    /// A scope selector that doesn't follow the TextMate selector grammar, e.g. with unbalanced
+    /// parentheses or an operator missing an operand
+    #[error("Invalid scope selector: {0}")]
+    InvalidSelector(String),
+    /// An atom number passed to [`Scope::from_atoms`] that no repository can return, i.e. 0 or
+    /// more than 2^16-3
+    ///
+    /// [`Scope::from_atoms`]: struct.Scope.html#method.from_atoms
+    #[error("Invalid atom number: {0}")]
+    InvalidAtom(u16),
+    /// A stack of scopes that isn't in a format [`parse_scope_stack`] reads back exactly
+    ///
+    /// [`parse_scope_stack`]: ../util/fn.parse_scope_stack.html
+    #[error("Invalid scope stack: {0}")]
+    InvalidScopeStack(String),
 }
 
 /// The structure used to keep track of the mapping between scope atom numbers and their string
@@ -78,10 +125,15 @@ pub enum ParseScopeError {
 ///
 /// Only [`Scope`]s created by the same repository have valid comparison results.
 ///
+/// Repositories can also be cloned to get a read-only snapshot that can be used without locking,
+/// which is what [`SyntaxSet::scope_repo`] does. Because atoms are only ever added to the global
+/// repository, scopes created before taking the snapshot can be used with it as well.
+///
 /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
 /// [`Scope::new()`]: struct.Scope.html#method.new
 /// [`Scope`]: struct.Scope.html
-#[derive(Debug)]
+/// [`SyntaxSet::scope_repo`]: struct.SyntaxSet.html#method.scope_repo
+#[derive(Debug, Clone, Default)]
 pub struct ScopeRepository {
     atoms: Vec<String>,
     atom_index_map: HashMap<String, usize>,
@@ -98,12 +150,29 @@ pub struct ScopeRepository {
 /// `text.html.ruby text.html.basic source.js.embedded.html string.quoted.double.js`
 ///
 /// [`ScopeSelectors`]: ../highlighting/struct.ScopeSelectors.html
-#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct ScopeStack {
-    clear_stack: Vec<Vec<Scope>>,
+    /// The scopes removed by each `clear_scopes`, which are few enough to usually be kept inline
+    clear_stack: Vec<SmallVec<[Scope; 4]>>,
     pub scopes: Vec<Scope>,
 }
 
+impl Clone for ScopeStack {
+    fn clone(&self) -> ScopeStack {
+        ScopeStack {
+            clear_stack: self.clear_stack.clone(),
+            scopes: self.scopes.clone(),
+        }
+    }
+
+    /// Reuses the allocations of `self`, which is a lot cheaper than `clone` when repeatedly
+    /// copying stacks of similar depth, e.g. when caching the state at every line.
+    fn clone_from(&mut self, source: &ScopeStack) {
+        self.clear_stack.clone_from(&source.clear_stack);
+        self.scopes.clone_from(&source.scopes);
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ClearAmount {
     TopN(usize),
@@ -138,6 +207,24 @@ pub enum BasicScopeStackOp {
     Pop,
 }
 
+/// Splits a scope string into its atoms, with everything from the 8th atom onwards kept together
+/// as a single overflow atom.
+fn split_atoms(s: &str) -> Vec<&str> {
+    let mut atoms = Vec::with_capacity(8);
+    let mut rest = s.trim_end_matches('.');
+    while atoms.len() < 7 {
+        match rest.find('.') {
+            Some(i) => {
+                atoms.push(&rest[..i]);
+                rest = &rest[i + 1..];
+            }
+            None => break,
+        }
+    }
+    atoms.push(rest);
+    atoms
+}
+
 fn pack_as_u16s(atoms: &[usize]) -> Result<Scope, ParseScopeError> {
     let mut res = Scope { a: 0, b: 0 };
 
@@ -159,28 +246,105 @@ fn pack_as_u16s(atoms: &[usize]) -> Result<Scope, ParseScopeError> {
 }
 
 impl ScopeRepository {
-    fn new() -> ScopeRepository {
+    /// Creates an empty repository.
+    ///
+    /// Scopes built with it can only be compared with other scopes from the same repository, so
+    /// you probably want [`SCOPE_REPO`] instead.
+    ///
+    /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
+    pub fn new() -> ScopeRepository {
         ScopeRepository {
             atoms: Vec::new(),
             atom_index_map: HashMap::new(),
         }
     }
 
+    /// Like [`build`] but without adding new atoms, so it only needs a shared reference.
+    ///
+    /// Returns `None` if any of the atoms in the scope aren't in this repository yet.
+    ///
+    /// [`build`]: #method.build
+    pub fn lookup(&self, s: &str) -> Option<Scope> {
+        if s.is_empty() {
+            return Some(Scope { a: 0, b: 0 });
+        }
+        let parts: Option<Vec<usize>> = split_atoms(s)
+            .into_iter()
+            .map(|a| self.atom_index_map.get(a).copied())
+            .collect();
+        pack_as_u16s(&parts?[..]).ok()
+    }
+
+    /// Checks whether all the atoms of a scope are known to this repository, i.e. whether
+    /// [`to_string`] and [`atom_str`] can be used with it.
+    ///
+    /// [`to_string`]: #method.to_string
+    /// [`atom_str`]: #method.atom_str
+    pub fn contains(&self, scope: Scope) -> bool {
+        (0..scope.len() as usize).all(|i| (scope.atom_at(i) as usize) <= self.atoms.len())
+    }
+
+    /// The number of distinct atoms in this repository
+    pub fn len(&self) -> usize {
+        self.atoms.len()
+    }
+
+    /// Whether no atoms have been added to this repository yet, so only the empty scope can be
+    /// looked up in it
+    pub fn is_empty(&self) -> bool {
+        self.atoms.is_empty()
+    }
+
+    /// Adds the atoms `other` got since this repository was cloned from it, which is cheaper than
+    /// cloning it again because atoms are only ever appended.
+    fn catch_up(&mut self, other: &ScopeRepository) {
+        for atom in &other.atoms[self.atoms.len().min(other.atoms.len())..] {
+            self.atom_to_index(atom);
+        }
+    }
+
+    /// The estimated size of the atoms and the map of them in bytes
+    #[cfg(feature = "memory-report")]
+    pub(crate) fn heap_size(&self) -> usize {
+        let atoms: usize = self.atoms.iter().map(String::capacity).sum();
+        let keys: usize = self.atom_index_map.keys().map(String::capacity).sum();
+        self.atoms.capacity() * std::mem::size_of::<String>()
+            + atoms
+            + super::memory_report::map_size(&self.atom_index_map)
+            + keys
+    }
+
     pub fn build(&mut self, s: &str) -> Result<Scope, ParseScopeError> {
         if s.is_empty() {
             return Ok(Scope { a: 0, b: 0 });
         }
-        let parts: Vec<usize> = s
-            .trim_end_matches('.')
-            .split('.')
+        let parts: Vec<usize> = split_atoms(s)
+            .into_iter()
             .map(|a| self.atom_to_index(a))
             .collect();
-        if parts.len() > 8 {
-            return Err(ParseScopeError::TooManyAtoms);
-        }
         pack_as_u16s(&parts[..])
     }
 
+    /// Builds several scopes at once, e.g. with [`SCOPE_REPO`] locked only once for all of them
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{Scope, SCOPE_REPO};
+    ///
+    /// let scopes = SCOPE_REPO
+    ///     .lock()
+    ///     .unwrap()
+    ///     .build_many(&["source.rust", "keyword.control.rust"])
+    ///     .unwrap();
+    /// assert_eq!(scopes[1], Scope::new("keyword.control.rust").unwrap());
+    /// ```
+    ///
+    /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
+    pub fn build_many(&mut self, scopes: &[&str]) -> Result<Vec<Scope>, ParseScopeError> {
+        scopes.iter().map(|s| self.build(s)).collect()
+    }
+
     pub fn to_string(&self, scope: Scope) -> String {
         let mut s = String::new();
         for i in 0..8 {
@@ -222,9 +386,59 @@ impl Scope {
     /// Parses a `Scope` from a series of atoms separated by dot (`.`) characters
     ///
     /// Example: `Scope::new("meta.rails.controller")`
+    ///
+    /// The global [`SCOPE_REPO`] is only locked when the scope has atoms this thread hasn't used
+    /// before, so creating scopes from many threads doesn't contend for it.
+    ///
+    /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
     pub fn new(s: &str) -> Result<Scope, ParseScopeError> {
+        let s = s.trim();
+        let local = LOCAL_REPO.try_with(|local| local.borrow().lookup(s));
+        if let Ok(Some(scope)) = local {
+            return Ok(scope);
+        }
         let mut repo = SCOPE_REPO.lock().unwrap();
-        repo.build(s.trim())
+        let scope = repo.build(s);
+        let _ = LOCAL_REPO.try_with(|local| local.borrow_mut().catch_up(&repo));
+        scope
+    }
+
+    /// Creates a scope from atom numbers, like the ones returned by [`atom_at`], without locking
+    /// the global repository
+    ///
+    /// This is for loaders that create lots of scopes and have looked up their atoms before, e.g.
+    /// with [`ScopeRepository::build_many`], or that cache them. The scope is only meaningful with
+    /// the repository the atoms came from, which has to be [`SCOPE_REPO`] to compare it with
+    /// scopes created by [`Scope::new`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::Scope;
+    ///
+    /// let scope = Scope::new("string.quoted.double").unwrap();
+    /// let atoms: Vec<u16> = (0..scope.len() as usize).map(|i| scope.atom_at(i)).collect();
+    /// assert_eq!(Scope::from_atoms(&atoms).unwrap(), scope);
+    /// assert!(Scope::from_atoms(&atoms[..1]).unwrap().is_prefix_of(scope));
+    /// ```
+    ///
+    /// [`atom_at`]: #method.atom_at
+    /// [`ScopeRepository::build_many`]: struct.ScopeRepository.html#method.build_many
+    /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
+    /// [`Scope::new`]: #method.new
+    pub fn from_atoms(atoms: &[u16]) -> Result<Scope, ParseScopeError> {
+        if atoms.len() > 8 {
+            return Err(ParseScopeError::TooLong);
+        }
+        let mut indices = Vec::with_capacity(atoms.len());
+        for &atom in atoms {
+            // `pack_as_u16s` takes indices into the atoms of a repository
+            if atom == 0 || atom >= u16::MAX - 1 {
+                return Err(ParseScopeError::InvalidAtom(atom));
+            }
+            indices.push(atom as usize - 1);
+        }
+        pack_as_u16s(&indices)
     }
 
     /// Gets the atom number at a given index.
@@ -266,10 +480,12 @@ impl Scope {
 
     /// Returns a string representation of this scope
     ///
-    /// This requires locking a global repo and shouldn't be done frequently.
+    /// This allocates, so it shouldn't be done frequently. Like [`Scope::new`], it only locks the
+    /// global repo for atoms this thread hasn't used before.
+    ///
+    /// [`Scope::new`]: #method.new
     pub fn build_string(self) -> String {
-        let repo = SCOPE_REPO.lock().unwrap();
-        repo.to_string(self)
+        with_repo_for(self, |repo| repo.to_string(self))
     }
 
     /// Tests if this scope is a prefix of another scope. Note that the empty scope is always a
@@ -296,18 +512,7 @@ impl Scope {
     ///         .is_prefix_of(Scope::new("source.php.wow").unwrap()));
     /// ```
     pub fn is_prefix_of(self, s: Scope) -> bool {
-        let pref_missing = self.missing_atoms();
-
-        // TODO: test optimization - use checked shl and then mult carry flag as int by -1
-        let mask: (u64, u64) = if pref_missing == 8 {
-            (0, 0)
-        } else if pref_missing == 4 {
-            (u64::MAX, 0)
-        } else if pref_missing > 4 {
-            (u64::MAX << ((pref_missing - 4) * 16), 0)
-        } else {
-            (u64::MAX, u64::MAX << (pref_missing * 16))
-        };
+        let mask = prefix_mask(self.missing_atoms());
 
         // xor to find the difference
         let ax = (self.a ^ s.a) & mask.0;
@@ -317,6 +522,30 @@ impl Scope {
 
         ax == 0 && bx == 0
     }
+
+    /// The scope made of the first `len` atoms of this one
+    pub(crate) fn prefix(self, len: u32) -> Scope {
+        let mask = prefix_mask(8 - len.min(8));
+        Scope {
+            a: self.a & mask.0,
+            b: self.b & mask.1,
+        }
+    }
+}
+
+/// Masks the bits of the atoms in a scope with `missing` atoms missing at the end
+#[inline]
+fn prefix_mask(missing: u32) -> (u64, u64) {
+    // TODO: test optimization - use checked shl and then mult carry flag as int by -1
+    if missing == 8 {
+        (0, 0)
+    } else if missing == 4 {
+        (u64::MAX, 0)
+    } else if missing > 4 {
+        (u64::MAX << ((missing - 4) * 16), 0)
+    } else {
+        (u64::MAX, u64::MAX << (missing * 16))
+    }
 }
 
 impl FromStr for Scope {
@@ -449,18 +678,15 @@ impl ScopeStack {
                 }
             }
             ScopeStackOp::Clear(amount) => {
-                let cleared = match amount {
-                    ClearAmount::TopN(n) => {
-                        // don't try to clear more scopes than are on the stack
-                        let to_leave = self.scopes.len() - min(n, self.scopes.len());
-                        self.scopes.split_off(to_leave)
-                    }
-                    ClearAmount::All => {
-                        let mut cleared = Vec::new();
-                        mem::swap(&mut cleared, &mut self.scopes);
-                        cleared
-                    }
+                let to_leave = match amount {
+                    // don't try to clear more scopes than are on the stack
+                    ClearAmount::TopN(n) => self.scopes.len() - min(n, self.scopes.len()),
+                    ClearAmount::All => 0,
                 };
+                // copying the cleared scopes out keeps the capacity of the stack for the scopes
+                // pushed until they're restored
+                let cleared = SmallVec::from_slice(&self.scopes[to_leave..]);
+                self.scopes.truncate(to_leave);
                 let clear_amount = cleared.len();
                 self.clear_stack.push(cleared);
                 for _ in 0..clear_amount {
@@ -468,10 +694,10 @@ impl ScopeStack {
                 }
             }
             ScopeStackOp::Restore => match self.clear_stack.pop() {
-                Some(ref mut to_push) => {
+                Some(to_push) => {
                     for s in to_push {
-                        self.scopes.push(*s);
-                        hook(BasicScopeStackOp::Push(*s), self.as_slice());
+                        self.scopes.push(s);
+                        hook(BasicScopeStackOp::Push(s), self.as_slice());
                     }
                 }
                 None => return Err(ScopeError::NoClearedScopesToRestore),
@@ -581,6 +807,35 @@ impl fmt::Display for ScopeStack {
 mod tests {
     use super::*;
 
+    #[test]
+    fn can_build_scopes_from_atoms() {
+        let scopes = SCOPE_REPO
+            .lock()
+            .unwrap()
+            .build_many(&["a.b.c.d.e.f.g.h.i", "", "a.b"])
+            .unwrap();
+        assert_eq!(scopes[1], Scope::default());
+        for scope in scopes {
+            let atoms: Vec<u16> = (0..scope.len() as usize)
+                .map(|i| scope.atom_at(i))
+                .collect();
+            assert_eq!(Scope::from_atoms(&atoms).unwrap(), scope);
+        }
+        assert!(matches!(
+            Scope::from_atoms(&[1; 9]),
+            Err(ParseScopeError::TooLong)
+        ));
+        assert!(matches!(
+            Scope::from_atoms(&[1, 0]),
+            Err(ParseScopeError::InvalidAtom(0))
+        ));
+        assert!(matches!(
+            Scope::from_atoms(&[u16::MAX - 1]),
+            Err(ParseScopeError::InvalidAtom(_))
+        ));
+        assert!(Scope::from_atoms(&[u16::MAX - 2]).is_ok());
+    }
+
     #[test]
     fn misc() {
         // use std::mem;
@@ -614,6 +869,50 @@ mod tests {
         );
     }
 
+    #[test]
+    fn repo_lookup_works() {
+        let mut repo = ScopeRepository::new();
+        let scope = repo.build("source.php").unwrap();
+        assert_eq!(repo.lookup("source.php"), Some(scope));
+        assert_eq!(repo.lookup("source"), Some(repo.build("source").unwrap()));
+        assert_eq!(repo.lookup("source.perl"), None);
+        assert_eq!(repo.lookup(""), Some(Scope::default()));
+
+        let snapshot = repo.clone();
+        let newer = repo.build("source.perl").unwrap();
+        assert!(snapshot.contains(scope));
+        assert!(!snapshot.contains(newer));
+        assert_eq!(snapshot.len() + 1, repo.len());
+    }
+
+    #[test]
+    fn clone_from_reuses_stack() {
+        let deep = ScopeStack::from_str("a b c d e f").unwrap();
+        let mut stack = ScopeStack::from_str("x").unwrap();
+        stack.clone_from(&deep);
+        assert_eq!(stack, deep);
+
+        let ptr = stack.scopes.as_ptr();
+        stack.clone_from(&ScopeStack::from_str("y z").unwrap());
+        assert_eq!(stack, ScopeStack::from_str("y z").unwrap());
+        assert_eq!(stack.scopes.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn clearing_all_scopes_keeps_the_stack_allocation() {
+        let mut stack = ScopeStack::from_str("a b c").unwrap();
+        let ptr = stack.scopes.as_ptr();
+        stack.apply(&ScopeStackOp::Clear(ClearAmount::All)).unwrap();
+        assert!(stack.is_empty());
+        stack
+            .apply(&ScopeStackOp::Push(Scope::new("d").unwrap()))
+            .unwrap();
+        assert_eq!(stack.scopes.as_ptr(), ptr);
+        stack.apply(&ScopeStackOp::Pop(1)).unwrap();
+        stack.apply(&ScopeStackOp::Restore).unwrap();
+        assert_eq!(stack, ScopeStack::from_str("a b c").unwrap());
+    }
+
     #[test]
     fn global_repo_works() {
         use std::str::FromStr;
@@ -622,7 +921,43 @@ mod tests {
             Scope::new("source.php").unwrap()
         );
         assert!(Scope::from_str("1.2.3.4.5.6.7.8").is_ok());
-        assert!(Scope::from_str("1.2.3.4.5.6.7.8.9").is_err());
+        assert!(Scope::from_str("1.2.3.4.5.6.7.8.9").is_ok());
+    }
+
+    #[test]
+    fn scopes_from_other_threads_work() {
+        let other = std::thread::spawn(|| Scope::new("other.thread.scope").unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(other.build_string(), "other.thread.scope");
+        assert_eq!(Scope::new("other.thread.scope").unwrap(), other);
+        assert!(LOCAL_REPO.with(|local| local.borrow().contains(other)));
+    }
+
+    #[test]
+    fn repo_catches_up_with_newer_atoms() {
+        let mut repo = ScopeRepository::new();
+        repo.build("source.php").unwrap();
+        let mut snapshot = repo.clone();
+        let newer = repo.build("source.perl.embedded").unwrap();
+        snapshot.catch_up(&repo);
+        assert_eq!(snapshot.len(), repo.len());
+        assert_eq!(snapshot.lookup("source.perl.embedded"), Some(newer));
+    }
+
+    #[test]
+    fn long_scopes_work() {
+        let long = Scope::new("a.b.c.d.e.f.g.h.i.j").unwrap();
+        assert_eq!(long.build_string(), "a.b.c.d.e.f.g.h.i.j");
+        assert_eq!(long.len(), 8);
+        assert_ne!(long, Scope::new("a.b.c.d.e.f.g.h.i").unwrap());
+        assert_ne!(long, Scope::new("a.b.c.d.e.f.g.h").unwrap());
+        assert!(Scope::new("a.b.c.d.e.f.g").unwrap().is_prefix_of(long));
+        assert_eq!(
+            Scope::new("a.b.c.d.e.f.g.h.i.j.").unwrap(),
+            long,
+            "trailing dots are ignored"
+        );
     }
 
     #[test]
@@ -648,6 +983,13 @@ mod tests {
         assert!(!Scope::new("1.2.a")
             .unwrap()
             .is_prefix_of(Scope::new("1.2.3.4.5.6.7.8").unwrap()));
+
+        let scope = Scope::new("1.2.3.4.5.6.7.8").unwrap();
+        assert_eq!(scope.prefix(0), Scope::new("").unwrap());
+        assert_eq!(scope.prefix(3), Scope::new("1.2.3").unwrap());
+        assert_eq!(scope.prefix(4), Scope::new("1.2.3.4").unwrap());
+        assert_eq!(scope.prefix(6), Scope::new("1.2.3.4.5.6").unwrap());
+        assert_eq!(scope.prefix(8), scope);
     }
 
     #[test]
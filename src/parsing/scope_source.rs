@@ -0,0 +1,161 @@
+//! Finding out which patterns produced the scopes at a position, see [`locate_scope_source`]
+//!
+//! [`locate_scope_source`]: fn.locate_scope_source.html
+
+use super::{
+    ClearAmount, MatchTrace, ParseState, ParsingError, Scope, ScopeStackOp, SyntaxReference,
+    SyntaxSet,
+};
+use crate::util::LinesWithEndings;
+
+/// A scope at a position and the pattern it came from, returned by [`locate_scope_source`]
+///
+/// [`locate_scope_source`]: fn.locate_scope_source.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScopeSource {
+    /// The scope
+    pub scope: Scope,
+    /// The line of the match that pushed the scope, counting from 0
+    pub line: usize,
+    /// The match that pushed the scope, either with its `scope` or `captures`, or with the
+    /// `meta_scope` or `meta_content_scope` of a context it pushed. Its `context` names both the
+    /// syntax and the context of the pattern, like `Rust#fn-parameters`.
+    ///
+    /// This is `None` for the scope of the syntax itself, which is there from the start.
+    pub trace: Option<MatchTrace>,
+}
+
+/// Parses `text` up to a position and returns where each of the scopes there came from
+///
+/// The position is a line, counting from 0, and a byte offset in that line. The scopes are the
+/// ones of the character starting at that offset, from the outermost to the innermost, so they
+/// are the same as the ones a highlighter would use for it. This is meant for tools that help
+/// with writing grammars, to go from a wrongly highlighted character to the patterns responsible.
+/// The text is split into lines like [`LinesWithEndings`] does, so the syntax set should be
+/// loaded with newlines.
+///
+/// Returns no scopes if the text doesn't have that many lines.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{locate_scope_source, SyntaxSet};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let text = "fn main() {\n    let s = \"hi\";\n}\n";
+/// let sources = locate_scope_source(text, 1, 13, syntax, &ss).unwrap();
+///
+/// let string = sources.last().unwrap();
+/// assert_eq!(string.scope.build_string(), "string.quoted.double.rust");
+/// let trace = string.trace.as_ref().unwrap();
+/// assert_eq!(trace.regex, "\"");
+/// assert_eq!(trace.range, 12..13);
+/// ```
+///
+/// [`LinesWithEndings`]: ../util/struct.LinesWithEndings.html
+pub fn locate_scope_source(
+    text: &str,
+    line: usize,
+    col: usize,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<ScopeSource>, ParsingError> {
+    let mut state = ParseState::new(syntax);
+    let mut stack: Vec<ScopeSource> = Vec::new();
+    let mut cleared: Vec<Vec<ScopeSource>> = Vec::new();
+    for (line_index, line_str) in LinesWithEndings::from(text).enumerate().take(line + 1) {
+        let (ops, traces) = state.parse_line_traced(line_str, syntax_set)?;
+        for (op_index, (index, op)) in ops.iter().enumerate() {
+            if line_index == line && *index > col {
+                break;
+            }
+            match *op {
+                ScopeStackOp::Push(scope) => stack.push(ScopeSource {
+                    scope,
+                    line: line_index,
+                    trace: traces.iter().find(|t| t.ops.contains(&op_index)).cloned(),
+                }),
+                ScopeStackOp::Pop(count) => {
+                    stack.truncate(stack.len().saturating_sub(count));
+                }
+                ScopeStackOp::Clear(amount) => {
+                    let keep = match amount {
+                        ClearAmount::TopN(n) => stack.len().saturating_sub(n),
+                        ClearAmount::All => 0,
+                    };
+                    cleared.push(stack.split_off(keep));
+                }
+                ScopeStackOp::Restore => {
+                    if let Some(restored) = cleared.pop() {
+                        stack.extend(restored);
+                    }
+                }
+                ScopeStackOp::Noop => {}
+            }
+        }
+        if line_index == line {
+            return Ok(stack);
+        }
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn follows_pushes_clears_and_restores() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r"
+name: Test
+scope: source.test
+contexts:
+  main:
+    - match: '\('
+      scope: punctuation.open.test
+      push: group
+  group:
+    - meta_scope: meta.group.test
+    - clear_scopes: 1
+    - match: '\)'
+      pop: true
+    - match: '\w+'
+      scope: word.test
+",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+        let syntax = ss.find_syntax_by_name("Test").unwrap();
+        let text = "x\n(a\nb) c\n";
+
+        let describe = |line, col| -> Vec<(String, Option<(usize, String)>)> {
+            locate_scope_source(text, line, col, syntax, &ss)
+                .unwrap()
+                .into_iter()
+                .map(|source| {
+                    let trace = source.trace.map(|t| (source.line, t.regex));
+                    (source.scope.build_string(), trace)
+                })
+                .collect()
+        };
+        assert_eq!(describe(0, 0), [("source.test".to_owned(), None)]);
+        // the group clears the scope of the syntax
+        assert_eq!(
+            describe(2, 0),
+            [
+                ("meta.group.test".to_owned(), Some((1, r"\(".to_owned()))),
+                ("word.test".to_owned(), Some((2, r"\w+".to_owned()))),
+            ]
+        );
+        // and restores it when it's popped
+        assert_eq!(describe(2, 3), [("source.test".to_owned(), None)]);
+        assert!(describe(3, 0).is_empty());
+    }
+}
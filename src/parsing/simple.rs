@@ -0,0 +1,141 @@
+//! Syntaxes made of a list of regexes and the scopes they give, for highlighting logs and other
+//! text without nested structure
+//!
+//! A syntax made with [`syntax_from_rules`] has a single context, so it can't tell e.g. a string
+//! from a keyword inside it unless one regex matches the whole string. That's often all a log
+//! viewer or a REPL needs, and the result is highlighted with themes and rendered as HTML or
+//! terminal escapes like any other syntax. For more, see [`SyntaxDefinitionBuilder`].
+//!
+//! [`syntax_from_rules`]: fn.syntax_from_rules.html
+//! [`SyntaxDefinitionBuilder`]: ../struct.SyntaxDefinitionBuilder.html
+
+use super::syntax_builder::{ContextBuilder, MatchPatternBuilder, SyntaxDefinitionBuilder};
+use super::syntax_definition::SyntaxDefinition;
+use super::{ParseSyntaxError, SyntaxSet, SyntaxSetBuilder};
+
+/// Turns `(regex, scope)` rules into a syntax named `name` whose text has the scope `scope`
+///
+/// At each position the rule that matches earliest wins, and the first of them if several match
+/// there, like the patterns of a context. The scopes of a rule are separated by spaces, and
+/// regexes can use capture groups but not backreferences to other lines. The regexes are
+/// rewritten for lines with or without newlines like [`SyntaxDefinition::load_from_str`] does.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::simple::syntax_from_rules;
+///
+/// let syntax = syntax_from_rules(
+///     "Log",
+///     "text.log",
+///     &[(r"\bERROR\b", "invalid.log"), (r"^\d{2}:\d{2}:\d{2}", "constant.numeric.time.log")],
+///     true,
+/// )
+/// .unwrap();
+/// assert_eq!(syntax.name, "Log");
+/// ```
+///
+/// [`SyntaxDefinition::load_from_str`]: ../syntax_definition/struct.SyntaxDefinition.html#method.load_from_str
+pub fn syntax_from_rules(
+    name: &str,
+    scope: &str,
+    rules: &[(&str, &str)],
+    lines_include_newline: bool,
+) -> Result<SyntaxDefinition, ParseSyntaxError> {
+    let main = rules
+        .iter()
+        .fold(ContextBuilder::new(), |context, &(regex, scope)| {
+            context.pattern(MatchPatternBuilder::new(regex).scope(scope))
+        });
+    SyntaxDefinitionBuilder::new(name, scope)
+        .context("main", main)
+        .build(lines_include_newline)
+}
+
+/// Like [`syntax_from_rules`], but returns a syntax set with the syntax and the Plain Text
+/// syntax, ready for highlighting
+///
+/// # Examples
+///
+/// ```
+/// use syntect::easy::HighlightLines;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::simple::syntax_set_from_rules;
+/// use syntect::util::as_24_bit_terminal_escaped;
+///
+/// let ss = syntax_set_from_rules("Log", "text.log", &[(r"\bWARN\b", "keyword.log")], true)
+///     .unwrap();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_name("Log").unwrap();
+/// let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+/// let regions = h.highlight_line("12:00:00 WARN disk full\n", &ss).unwrap();
+/// assert_eq!(regions[1].1, "WARN");
+/// print!("{}", as_24_bit_terminal_escaped(&regions[..], false));
+/// ```
+///
+/// [`syntax_from_rules`]: fn.syntax_from_rules.html
+pub fn syntax_set_from_rules(
+    name: &str,
+    scope: &str,
+    rules: &[(&str, &str)],
+    lines_include_newline: bool,
+) -> Result<SyntaxSet, ParseSyntaxError> {
+    let mut builder = SyntaxSetBuilder::new();
+    builder.add_plain_text_syntax();
+    builder.add(syntax_from_rules(
+        name,
+        scope,
+        rules,
+        lines_include_newline,
+    )?);
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{ParseState, ScopeStack};
+
+    #[test]
+    fn applies_the_earliest_and_first_rule() {
+        let rules = [
+            (r"\d+", "constant.numeric.log"),
+            (r"\d+\.\d+", "constant.numeric.float.log"),
+            (r"(ERROR|WARN):", "keyword.level.log"),
+        ];
+        for lines_include_newline in [true, false] {
+            let ss =
+                syntax_set_from_rules("Log", "text.log", &rules, lines_include_newline).unwrap();
+            let mut state = ParseState::new(ss.find_syntax_by_name("Log").unwrap());
+            let line = if lines_include_newline {
+                "WARN: 1.5\n"
+            } else {
+                "WARN: 1.5"
+            };
+            let ops = state.parse_line(line, &ss).unwrap();
+            let mut stack = ScopeStack::new();
+            let mut scopes_at = Vec::new();
+            let mut ops = ops.into_iter().peekable();
+            for (i, _) in line.char_indices() {
+                while let Some((_, op)) = ops.next_if(|(index, _)| *index <= i) {
+                    stack.apply(&op).unwrap();
+                }
+                scopes_at.push(stack.to_string().trim_end().to_owned());
+            }
+            assert_eq!(scopes_at[0], "text.log keyword.level.log");
+            assert_eq!(scopes_at[4], "text.log keyword.level.log");
+            assert_eq!(scopes_at[5], "text.log");
+            // `\d+` comes first, so `1.5` is two integers
+            assert_eq!(scopes_at[6], "text.log constant.numeric.log");
+            assert_eq!(scopes_at[7], "text.log");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_regexes() {
+        assert!(matches!(
+            syntax_from_rules("Log", "text.log", &[("(", "invalid.log")], true),
+            Err(ParseSyntaxError::RegexCompileError(..))
+        ));
+    }
+}
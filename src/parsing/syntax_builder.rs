@@ -0,0 +1,651 @@
+//! Constructing syntax definitions in code, see [`SyntaxDefinitionBuilder`]
+//!
+//! [`SyntaxDefinitionBuilder`]: struct.SyntaxDefinitionBuilder.html
+
+use super::scope::ClearAmount;
+use super::syntax_definition::{ContextReference, SyntaxDefinition};
+use super::ParseSyntaxError;
+use yaml_rust::yaml::Hash;
+use yaml_rust::Yaml;
+
+/// Constructs a [`SyntaxDefinition`] in code, e.g. for a log format or a small DSL configured at
+/// runtime
+///
+/// The builder assembles the same structure as a `.sublime-syntax` file and [`build`] loads it
+/// like [`SyntaxDefinition::load_from_str_with_options`] does with [`LoadOptions::strict`], so
+/// variables, captures and anonymous contexts work the same way and the definition is validated:
+/// scopes have to parse, regexes have to compile, there has to be a `main` context, and named
+/// references have to point at a context of the syntax. Adding a context with a name that was
+/// already added replaces it.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{
+///     ContextBuilder, MatchPatternBuilder, ParseState, SyntaxDefinitionBuilder,
+///     SyntaxSetBuilder,
+/// };
+///
+/// let syntax = SyntaxDefinitionBuilder::new("Log", "text.log")
+///     .file_extension("log")
+///     .variable("level", "INFO|WARN|ERROR")
+///     .context(
+///         "main",
+///         ContextBuilder::new()
+///             .pattern(MatchPatternBuilder::new(r"\b(?:{{level}})\b").scope("keyword.level.log"))
+///             .pattern(
+///                 MatchPatternBuilder::new("\"")
+///                     .scope("punctuation.definition.string.begin.log")
+///                     .push_context(
+///                         ContextBuilder::new()
+///                             .meta_scope("string.quoted.double.log")
+///                             .pattern(MatchPatternBuilder::new("\"").pop()),
+///                     ),
+///             ),
+///     )
+///     .build(true)
+///     .unwrap();
+///
+/// let mut builder = SyntaxSetBuilder::new();
+/// builder.add(syntax);
+/// let ss = builder.build();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("log").unwrap());
+/// let ops = state.parse_line("WARN \"disk full\"\n", &ss).unwrap();
+/// assert!(!ops.is_empty());
+/// ```
+///
+/// [`SyntaxDefinition`]: syntax_definition/struct.SyntaxDefinition.html
+/// [`build`]: #method.build
+/// [`SyntaxDefinition::load_from_str_with_options`]: syntax_definition/struct.SyntaxDefinition.html#method.load_from_str_with_options
+/// [`LoadOptions::strict`]: struct.LoadOptions.html#method.strict
+#[derive(Debug, Clone)]
+pub struct SyntaxDefinitionBuilder {
+    name: String,
+    scope: String,
+    file_extensions: Vec<String>,
+    first_line_match: Option<String>,
+    hidden: bool,
+    variables: Vec<(String, String)>,
+    contexts: Vec<(String, ContextBuilder)>,
+}
+
+/// A context of a [`SyntaxDefinitionBuilder`], or an anonymous one pushed by a
+/// [`MatchPatternBuilder`]
+///
+/// [`SyntaxDefinitionBuilder`]: struct.SyntaxDefinitionBuilder.html
+/// [`MatchPatternBuilder`]: struct.MatchPatternBuilder.html
+#[derive(Debug, Clone, Default)]
+pub struct ContextBuilder {
+    meta_scope: Option<String>,
+    meta_content_scope: Option<String>,
+    meta_include_prototype: Option<bool>,
+    clear_scopes: Option<ClearAmount>,
+    patterns: Vec<PatternEntry>,
+}
+
+#[derive(Debug, Clone)]
+enum PatternEntry {
+    Match(Box<MatchPatternBuilder>),
+    Include(ContextReference),
+}
+
+/// A `match` pattern of a [`ContextBuilder`]
+///
+/// [`ContextBuilder`]: struct.ContextBuilder.html
+#[derive(Debug, Clone)]
+pub struct MatchPatternBuilder {
+    regex: String,
+    scope: Option<String>,
+    captures: Vec<(usize, String)>,
+    operation: Operation,
+    embed_scope: Option<String>,
+    escape_captures: Vec<(usize, String)>,
+    with_prototype: Option<ContextBuilder>,
+}
+
+#[derive(Debug, Clone)]
+enum Operation {
+    None,
+    Pop,
+    Push(Vec<Target>),
+    Set(Vec<Target>),
+    Embed(ContextReference, String),
+}
+
+#[derive(Debug, Clone)]
+enum Target {
+    Reference(ContextReference),
+    Context(ContextBuilder),
+}
+
+impl SyntaxDefinitionBuilder {
+    /// Starts a syntax with a name to show in syntax lists and the scope of its whole text, like
+    /// `source.rust`
+    pub fn new(name: impl Into<String>, scope: impl Into<String>) -> SyntaxDefinitionBuilder {
+        SyntaxDefinitionBuilder {
+            name: name.into(),
+            scope: scope.into(),
+            file_extensions: Vec::new(),
+            first_line_match: None,
+            hidden: false,
+            variables: Vec::new(),
+            contexts: Vec::new(),
+        }
+    }
+
+    /// Adds a file extension, or a whole file name like `Makefile`, to use the syntax for
+    pub fn file_extension(mut self, extension: impl Into<String>) -> SyntaxDefinitionBuilder {
+        self.file_extensions.push(extension.into());
+        self
+    }
+
+    /// Sets the regex that recognizes files of this syntax by their first line
+    pub fn first_line_match(mut self, regex: impl Into<String>) -> SyntaxDefinitionBuilder {
+        self.first_line_match = Some(regex.into());
+        self
+    }
+
+    /// Hides the syntax from syntax lists
+    pub fn hidden(mut self, hidden: bool) -> SyntaxDefinitionBuilder {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Adds a variable, which regexes can use as `{{name}}`
+    pub fn variable(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> SyntaxDefinitionBuilder {
+        self.variables.push((name.into(), value.into()));
+        self
+    }
+
+    /// Adds a context, where `main` is the one the syntax starts in and `prototype` is included
+    /// in every other context
+    pub fn context(
+        mut self,
+        name: impl Into<String>,
+        context: ContextBuilder,
+    ) -> SyntaxDefinitionBuilder {
+        let name = name.into();
+        self.contexts.retain(|(existing, _)| *existing != name);
+        self.contexts.push((name, context));
+        self
+    }
+
+    /// Validates and returns the definition, with its regexes rewritten for lines with or
+    /// without newlines like [`SyntaxDefinition::load_from_str`] does
+    ///
+    /// Fails with [`ParseSyntaxError::DirectReference`] if a reference is a
+    /// [`ContextReference::Direct`], which only exists in a built syntax set.
+    ///
+    /// [`SyntaxDefinition::load_from_str`]: syntax_definition/struct.SyntaxDefinition.html#method.load_from_str
+    /// [`ParseSyntaxError::DirectReference`]: enum.ParseSyntaxError.html#variant.DirectReference
+    /// [`ContextReference::Direct`]: syntax_definition/enum.ContextReference.html#variant.Direct
+    pub fn build(self, lines_include_newline: bool) -> Result<SyntaxDefinition, ParseSyntaxError> {
+        let mut doc = Hash::new();
+        doc.insert(string("name"), string(&self.name));
+        doc.insert(string("scope"), string(&self.scope));
+        doc.insert(
+            string("file_extensions"),
+            Yaml::Array(self.file_extensions.iter().map(|e| string(e)).collect()),
+        );
+        if let Some(ref regex) = self.first_line_match {
+            doc.insert(string("first_line_match"), string(regex));
+        }
+        doc.insert(string("hidden"), Yaml::Boolean(self.hidden));
+        let mut variables = Hash::new();
+        for (name, value) in &self.variables {
+            variables.insert(string(name), string(value));
+        }
+        doc.insert(string("variables"), Yaml::Hash(variables));
+        let mut contexts = Hash::new();
+        for (name, context) in &self.contexts {
+            contexts.insert(string(name), context.to_yaml()?);
+        }
+        doc.insert(string("contexts"), Yaml::Hash(contexts));
+        SyntaxDefinition::load_from_yaml(&Yaml::Hash(doc), lines_include_newline)
+    }
+}
+
+impl ContextBuilder {
+    pub fn new() -> ContextBuilder {
+        ContextBuilder::default()
+    }
+
+    /// Sets the scopes of everything matched while the context is on the stack, including the
+    /// match that pushed it, separated by spaces
+    pub fn meta_scope(mut self, scope: impl Into<String>) -> ContextBuilder {
+        self.meta_scope = Some(scope.into());
+        self
+    }
+
+    /// Like [`meta_scope`], but without the match that pushed the context
+    ///
+    /// [`meta_scope`]: #method.meta_scope
+    pub fn meta_content_scope(mut self, scope: impl Into<String>) -> ContextBuilder {
+        self.meta_content_scope = Some(scope.into());
+        self
+    }
+
+    /// Sets whether the `prototype` context of the syntax is included, which it is by default
+    pub fn meta_include_prototype(mut self, include: bool) -> ContextBuilder {
+        self.meta_include_prototype = Some(include);
+        self
+    }
+
+    /// Removes scopes from the stack while the context is on it
+    pub fn clear_scopes(mut self, amount: ClearAmount) -> ContextBuilder {
+        self.clear_scopes = Some(amount);
+        self
+    }
+
+    /// Adds a pattern, which is tried after the ones added before it
+    pub fn pattern(mut self, pattern: MatchPatternBuilder) -> ContextBuilder {
+        self.patterns.push(PatternEntry::Match(Box::new(pattern)));
+        self
+    }
+
+    /// Adds the patterns of another context, like `include` does
+    pub fn include(mut self, reference: ContextReference) -> ContextBuilder {
+        self.patterns.push(PatternEntry::Include(reference));
+        self
+    }
+
+    fn to_yaml(&self) -> Result<Yaml, ParseSyntaxError> {
+        let mut entries = Vec::new();
+        if let Some(ref scope) = self.meta_scope {
+            entries.push(entry("meta_scope", string(scope)));
+        }
+        if let Some(ref scope) = self.meta_content_scope {
+            entries.push(entry("meta_content_scope", string(scope)));
+        }
+        if let Some(include) = self.meta_include_prototype {
+            entries.push(entry("meta_include_prototype", Yaml::Boolean(include)));
+        }
+        match self.clear_scopes {
+            Some(ClearAmount::All) => entries.push(entry("clear_scopes", Yaml::Boolean(true))),
+            Some(ClearAmount::TopN(n)) => {
+                entries.push(entry("clear_scopes", Yaml::Integer(n as i64)))
+            }
+            None => {}
+        }
+        for pattern in &self.patterns {
+            entries.push(match pattern {
+                PatternEntry::Match(match_pat) => match_pat.to_yaml()?,
+                PatternEntry::Include(reference) => entry("include", reference_to_yaml(reference)?),
+            });
+        }
+        Ok(Yaml::Array(entries))
+    }
+}
+
+impl MatchPatternBuilder {
+    /// Starts a pattern matching `regex`, which can use the variables of the syntax
+    pub fn new(regex: impl Into<String>) -> MatchPatternBuilder {
+        MatchPatternBuilder {
+            regex: regex.into(),
+            scope: None,
+            captures: Vec::new(),
+            operation: Operation::None,
+            embed_scope: None,
+            escape_captures: Vec::new(),
+            with_prototype: None,
+        }
+    }
+
+    /// Sets the scopes of the matched text, separated by spaces
+    pub fn scope(mut self, scope: impl Into<String>) -> MatchPatternBuilder {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Sets the scopes of the text matched by a capture group
+    pub fn capture(mut self, group: usize, scope: impl Into<String>) -> MatchPatternBuilder {
+        self.captures.push((group, scope.into()));
+        self
+    }
+
+    /// Pushes a context after the match
+    ///
+    /// Calling this again pushes several contexts, with the last one on top of the stack.
+    pub fn push(self, reference: ContextReference) -> MatchPatternBuilder {
+        self.push_target(Target::Reference(reference))
+    }
+
+    /// Pushes an anonymous context after the match, see [`push`]
+    ///
+    /// [`push`]: #method.push
+    pub fn push_context(self, context: ContextBuilder) -> MatchPatternBuilder {
+        self.push_target(Target::Context(context))
+    }
+
+    /// Replaces the current context with another one after the match
+    ///
+    /// Calling this again sets several contexts, with the last one on top of the stack.
+    pub fn set(self, reference: ContextReference) -> MatchPatternBuilder {
+        self.set_target(Target::Reference(reference))
+    }
+
+    /// Replaces the current context with an anonymous one after the match, see [`set`]
+    ///
+    /// [`set`]: #method.set
+    pub fn set_context(self, context: ContextBuilder) -> MatchPatternBuilder {
+        self.set_target(Target::Context(context))
+    }
+
+    /// Pops the current context after the match
+    pub fn pop(mut self) -> MatchPatternBuilder {
+        self.operation = Operation::Pop;
+        self
+    }
+
+    /// Pushes the context of another syntax after the match, until `escape` matches, like
+    /// `embed` does
+    pub fn embed(
+        mut self,
+        reference: ContextReference,
+        escape: impl Into<String>,
+    ) -> MatchPatternBuilder {
+        self.operation = Operation::Embed(reference, escape.into());
+        self
+    }
+
+    /// Sets the scopes of the text of an [`embed`] up to the escape, separated by spaces
+    ///
+    /// [`embed`]: #method.embed
+    pub fn embed_scope(mut self, scope: impl Into<String>) -> MatchPatternBuilder {
+        self.embed_scope = Some(scope.into());
+        self
+    }
+
+    /// Sets the scopes of the text matched by a capture group of the escape of an [`embed`]
+    ///
+    /// [`embed`]: #method.embed
+    pub fn escape_capture(mut self, group: usize, scope: impl Into<String>) -> MatchPatternBuilder {
+        self.escape_captures.push((group, scope.into()));
+        self
+    }
+
+    /// Adds the patterns of `context` to the contexts pushed by the match and to everything they
+    /// push in turn
+    pub fn with_prototype(mut self, context: ContextBuilder) -> MatchPatternBuilder {
+        self.with_prototype = Some(context);
+        self
+    }
+
+    fn push_target(mut self, target: Target) -> MatchPatternBuilder {
+        match self.operation {
+            Operation::Push(ref mut targets) => targets.push(target),
+            _ => self.operation = Operation::Push(vec![target]),
+        }
+        self
+    }
+
+    fn set_target(mut self, target: Target) -> MatchPatternBuilder {
+        match self.operation {
+            Operation::Set(ref mut targets) => targets.push(target),
+            _ => self.operation = Operation::Set(vec![target]),
+        }
+        self
+    }
+
+    fn to_yaml(&self) -> Result<Yaml, ParseSyntaxError> {
+        let mut map = Hash::new();
+        map.insert(string("match"), string(&self.regex));
+        if let Some(ref scope) = self.scope {
+            map.insert(string("scope"), string(scope));
+        }
+        if !self.captures.is_empty() {
+            map.insert(string("captures"), captures_to_yaml(&self.captures));
+        }
+        match self.operation {
+            Operation::None => {}
+            Operation::Pop => {
+                map.insert(string("pop"), Yaml::Boolean(true));
+            }
+            Operation::Push(ref targets) => {
+                map.insert(string("push"), targets_to_yaml(targets)?);
+            }
+            Operation::Set(ref targets) => {
+                map.insert(string("set"), targets_to_yaml(targets)?);
+            }
+            Operation::Embed(ref reference, ref escape) => {
+                map.insert(string("embed"), reference_to_yaml(reference)?);
+                map.insert(string("escape"), string(escape));
+            }
+        }
+        if let Some(ref scope) = self.embed_scope {
+            map.insert(string("embed_scope"), string(scope));
+        }
+        if !self.escape_captures.is_empty() {
+            map.insert(
+                string("escape_captures"),
+                captures_to_yaml(&self.escape_captures),
+            );
+        }
+        if let Some(ref context) = self.with_prototype {
+            map.insert(string("with_prototype"), context.to_yaml()?);
+        }
+        Ok(Yaml::Hash(map))
+    }
+}
+
+fn string(s: &str) -> Yaml {
+    Yaml::String(s.to_owned())
+}
+
+fn entry(key: &str, value: Yaml) -> Yaml {
+    let mut map = Hash::new();
+    map.insert(string(key), value);
+    Yaml::Hash(map)
+}
+
+fn captures_to_yaml(captures: &[(usize, String)]) -> Yaml {
+    let mut map = Hash::new();
+    for (group, scope) in captures {
+        map.insert(Yaml::Integer(*group as i64), string(scope));
+    }
+    Yaml::Hash(map)
+}
+
+/// Writes a reference the way a `.sublime-syntax` file does
+fn reference_to_yaml(reference: &ContextReference) -> Result<Yaml, ParseSyntaxError> {
+    let (target, sub_context) = match reference {
+        ContextReference::Named(name) | ContextReference::Inline(name) => return Ok(string(name)),
+        ContextReference::ByScope {
+            scope, sub_context, ..
+        } => (format!("scope:{}", scope.build_string()), sub_context),
+        ContextReference::File {
+            name, sub_context, ..
+        } => (format!("{}.sublime-syntax", name), sub_context),
+        ContextReference::Direct(_) => return Err(ParseSyntaxError::DirectReference),
+        ContextReference::Prototype(prototype) => return reference_to_yaml(prototype),
+    };
+    Ok(match sub_context {
+        Some(sub_context) => Yaml::String(format!("{}#{}", target, sub_context)),
+        None => Yaml::String(target),
+    })
+}
+
+fn targets_to_yaml(targets: &[Target]) -> Result<Yaml, ParseSyntaxError> {
+    let mut yaml = Vec::new();
+    for target in targets {
+        yaml.push(match target {
+            Target::Reference(reference) => reference_to_yaml(reference)?,
+            // the loader tells a list of contexts from an anonymous one by its first entry, so
+            // an empty context gets one that changes nothing
+            Target::Context(context) => match context.to_yaml()? {
+                Yaml::Array(entries) if entries.is_empty() => {
+                    Yaml::Array(vec![entry("meta_include_prototype", Yaml::Boolean(true))])
+                }
+                yaml => yaml,
+            },
+        });
+    }
+    Ok(if yaml.len() == 1 {
+        yaml.pop().unwrap()
+    } else {
+        Yaml::Array(yaml)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::syntax_definition::ContextId;
+    use crate::parsing::Scope;
+
+    #[test]
+    fn builds_the_same_definition_as_yaml() {
+        let yaml = r#"
+name: Config
+scope: source.config
+file_extensions: [conf]
+variables:
+  ident: '[a-z]+'
+contexts:
+  prototype:
+    - match: '#.*'
+      scope: comment.line.config
+  main:
+    - match: '({{ident}})\s*(=)'
+      captures:
+        1: variable.other.config
+        2: keyword.operator.config
+      push: [value, space]
+    - match: '```json'
+      embed: scope:source.json
+      embed_scope: meta.embedded.config
+      escape: '```'
+      escape_captures:
+        0: punctuation.config
+  space:
+    - match: '\s*'
+      pop: true
+  value:
+    - meta_scope: meta.value.config
+    - clear_scopes: 1
+    - match: '"'
+      set:
+        - meta_include_prototype: false
+        - match: '"'
+          pop: true
+    - match: '\['
+      push: value
+      with_prototype:
+        - match: '(?=\])'
+          pop: true
+    - include: Numbers.sublime-syntax#numbers
+"#;
+        let built = SyntaxDefinitionBuilder::new("Config", "source.config")
+            .file_extension("conf")
+            .variable("ident", "[a-z]+")
+            .context(
+                "prototype",
+                ContextBuilder::new()
+                    .pattern(MatchPatternBuilder::new("#.*").scope("comment.line.config")),
+            )
+            .context(
+                "main",
+                ContextBuilder::new()
+                    .pattern(
+                        MatchPatternBuilder::new(r"({{ident}})\s*(=)")
+                            .capture(1, "variable.other.config")
+                            .capture(2, "keyword.operator.config")
+                            .push(ContextReference::named("value"))
+                            .push(ContextReference::named("space")),
+                    )
+                    .pattern(
+                        MatchPatternBuilder::new("```json")
+                            .embed(
+                                ContextReference::by_scope(
+                                    Scope::new("source.json").unwrap(),
+                                    None,
+                                ),
+                                "```",
+                            )
+                            .embed_scope("meta.embedded.config")
+                            .escape_capture(0, "punctuation.config"),
+                    ),
+            )
+            .context(
+                "space",
+                ContextBuilder::new().pattern(MatchPatternBuilder::new(r"\s*").pop()),
+            )
+            .context(
+                "value",
+                ContextBuilder::new()
+                    .meta_scope("meta.value.config")
+                    .clear_scopes(ClearAmount::TopN(1))
+                    .pattern(
+                        MatchPatternBuilder::new("\"").set_context(
+                            ContextBuilder::new()
+                                .meta_include_prototype(false)
+                                .pattern(MatchPatternBuilder::new("\"").pop()),
+                        ),
+                    )
+                    .pattern(
+                        MatchPatternBuilder::new(r"\[")
+                            .push(ContextReference::named("value"))
+                            .with_prototype(
+                                ContextBuilder::new()
+                                    .pattern(MatchPatternBuilder::new(r"(?=\])").pop()),
+                            ),
+                    )
+                    .include(ContextReference::file("Numbers", Some("numbers"))),
+            )
+            .build(true)
+            .unwrap();
+        let loaded = SyntaxDefinition::load_from_str(yaml, true, None).unwrap();
+        assert_eq!(built, loaded);
+    }
+
+    #[test]
+    fn validates_the_definition() {
+        let main = |pattern| ContextBuilder::new().pattern(pattern);
+        let build = |context| {
+            SyntaxDefinitionBuilder::new("Test", "source.test")
+                .context("main", context)
+                .build(true)
+        };
+
+        assert!(build(main(MatchPatternBuilder::new("a"))).is_ok());
+        assert!(matches!(
+            build(main(MatchPatternBuilder::new("("))),
+            Err(ParseSyntaxError::RegexCompileError(..))
+        ));
+        assert!(matches!(
+            build(main(
+                MatchPatternBuilder::new("a").push(ContextReference::named("b"))
+            )),
+            Err(ParseSyntaxError::MissingContext(..))
+        ));
+        assert!(build(main(
+            MatchPatternBuilder::new("a").push_context(ContextBuilder::new())
+        ))
+        .is_ok());
+        assert!(matches!(
+            SyntaxDefinitionBuilder::new("Test", "source.test")
+                .context("other", ContextBuilder::new())
+                .build(true),
+            Err(ParseSyntaxError::MainMissing)
+        ));
+        let direct = ContextReference::Direct(ContextId {
+            syntax_index: 0,
+            context_index: 0,
+        });
+        assert!(matches!(
+            build(main(MatchPatternBuilder::new("a").push(direct))),
+            Err(ParseSyntaxError::DirectReference)
+        ));
+        // adding `main` again replaces it
+        let defn = SyntaxDefinitionBuilder::new("Test", "source.test")
+            .context("main", ContextBuilder::new())
+            .context("main", main(MatchPatternBuilder::new("b")))
+            .build(true)
+            .unwrap();
+        assert_eq!(defn.contexts["main"].patterns.len(), 1);
+    }
+}
@@ -33,6 +33,10 @@ pub struct ContextId {
 /// Some useful public fields are the `name` field which is a human readable name to display in
 /// syntax lists, and the `hidden` field which means hide this syntax from any lists because it is
 /// for internal use.
+///
+/// To define a syntax in code instead of loading it, see [`SyntaxDefinitionBuilder`].
+///
+/// [`SyntaxDefinitionBuilder`]: ../struct.SyntaxDefinitionBuilder.html
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SyntaxDefinition {
     pub name: String,
@@ -63,6 +67,52 @@ pub struct Context {
     pub patterns: Vec<Pattern>,
 }
 
+impl SyntaxDefinition {
+    /// The syntax for plain text, with no highlighting rules, which is what
+    /// [`SyntaxSetBuilder::add_plain_text_syntax`] adds
+    ///
+    /// It's named `Plain Text`, claims the `txt` extension and gives its text the scope
+    /// `text.plain`, so [`SyntaxSet::find_syntax_plain_text`] finds it. This is built in code, so
+    /// it's also available without the `yaml-load` feature, e.g. to add a fallback to a set of
+    /// syntaxes loaded from a dump.
+    ///
+    /// [`SyntaxSetBuilder::add_plain_text_syntax`]: ../struct.SyntaxSetBuilder.html#method.add_plain_text_syntax
+    /// [`SyntaxSet::find_syntax_plain_text`]: ../struct.SyntaxSet.html#method.find_syntax_plain_text
+    pub fn plain_text() -> SyntaxDefinition {
+        let scope = Scope::new("text.plain").unwrap();
+        // the same contexts as loading a syntax with an empty `main` creates
+        let mut main = Context::new(true);
+        main.meta_content_scope = vec![scope];
+        let mut start = Context::new(false);
+        start.meta_content_scope = vec![scope];
+        start.patterns.push(Pattern::Match(MatchPattern::new(
+            false,
+            String::new(),
+            Vec::new(),
+            None,
+            MatchOperation::Push(vec![ContextReference::Named("__main".to_owned())]),
+            None,
+        )));
+        let mut outer_main = Context::new(true);
+        outer_main
+            .patterns
+            .push(Pattern::Include(ContextReference::Named("main".to_owned())));
+
+        SyntaxDefinition {
+            name: "Plain Text".to_owned(),
+            file_extensions: vec!["txt".to_owned()],
+            scope,
+            first_line_match: None,
+            hidden: false,
+            variables: HashMap::new(),
+            contexts: [("main", main), ("__start", start), ("__main", outer_main)]
+                .into_iter()
+                .map(|(name, context)| (name.to_owned(), context))
+                .collect(),
+        }
+    }
+}
+
 impl Context {
     pub fn new(meta_include_prototype: bool) -> Context {
         Context {
@@ -86,7 +136,7 @@ pub enum Pattern {
 /// Used to iterate over all the match patterns in a context
 ///
 /// Basically walks the tree of patterns and include directives in the correct order.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MatchIter<'a> {
     syntax_set: &'a SyntaxSet,
     ctx_stack: Vec<&'a Context>,
@@ -129,6 +179,11 @@ pub enum ContextReference {
     Inline(String),
     #[non_exhaustive]
     Direct(ContextId),
+    /// The `prototype` context of another syntax, which `apply_prototype` includes along with
+    /// that syntax. Unlike other references, it's left out when the syntax is found without a
+    /// prototype.
+    #[non_exhaustive]
+    Prototype(Box<ContextReference>),
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -202,6 +257,32 @@ impl Context {
 }
 
 impl ContextReference {
+    /// A reference to a context of the same syntax, like `push: string` in a `.sublime-syntax`
+    /// file
+    pub fn named(name: impl Into<String>) -> ContextReference {
+        ContextReference::Named(name.into())
+    }
+
+    /// A reference to a context of the syntax with the scope `scope`, its `main` one if
+    /// `sub_context` is `None`, like `scope:source.json` or `scope:source.json#value`
+    pub fn by_scope(scope: Scope, sub_context: Option<&str>) -> ContextReference {
+        ContextReference::ByScope {
+            scope,
+            sub_context: sub_context.map(str::to_owned),
+            with_escape: false,
+        }
+    }
+
+    /// A reference to a context of the syntax from the file `name.sublime-syntax`, its `main`
+    /// one if `sub_context` is `None`, like `JSON.sublime-syntax`
+    pub fn file(name: impl Into<String>, sub_context: Option<&str>) -> ContextReference {
+        ContextReference::File {
+            name: name.into(),
+            sub_context: sub_context.map(str::to_owned),
+            with_escape: false,
+        }
+    }
+
     /// find the pointed to context
     pub fn resolve<'a>(&self, syntax_set: &'a SyntaxSet) -> Result<&'a Context, ParsingError> {
         match *self {
@@ -293,6 +374,18 @@ where
 mod tests {
     use super::*;
 
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn plain_text_is_the_same_as_loading_it() {
+        let yaml =
+            "name: Plain Text\nfile_extensions: [txt]\nscope: text.plain\ncontexts: {main: []}";
+        for lines_include_newline in [false, true] {
+            let loaded =
+                SyntaxDefinition::load_from_str(yaml, lines_include_newline, None).unwrap();
+            assert_eq!(SyntaxDefinition::plain_text(), loaded);
+        }
+    }
+
     #[test]
     fn can_compile_refs() {
         let pat = MatchPattern {
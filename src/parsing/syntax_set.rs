@@ -1,3 +1,4 @@
+use super::combined::CombinedPatterns;
 use super::scope::*;
 use super::syntax_definition::*;
 use super::ParsingError;
@@ -5,17 +6,22 @@ use super::ParsingError;
 #[cfg(feature = "metadata")]
 use super::metadata::{LoadMetadata, Metadata, RawMetadataEntry};
 
-#[cfg(feature = "yaml-load")]
 use super::super::LoadingError;
+#[cfg(feature = "yaml-load")]
+use super::{LoadOptions, LoadWarning, ParseSyntaxError};
+#[cfg(feature = "yaml-load")]
+use std::path::PathBuf;
 
 use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 use std::mem;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use super::regex::Regex;
 use crate::parsing::syntax_definition::ContextId;
+use crate::utils::DumpFormat;
 use once_cell::sync::OnceCell;
 use serde_derive::{Deserialize, Serialize};
 
@@ -30,16 +36,21 @@ use serde_derive::{Deserialize, Serialize};
 /// [`into_builder`]: #method.into_builder
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SyntaxSet {
+    /// Rejects dumps of other versions of syntect, so it has to be the first field
+    format: DumpFormat,
     syntaxes: Vec<SyntaxReference>,
     /// Stores the syntax index for every path that was loaded
     path_syntaxes: Vec<(String, usize)>,
 
     #[serde(skip_serializing, skip_deserializing, default = "OnceCell::new")]
     first_line_cache: OnceCell<FirstLineCache>,
+    /// Snapshot of the global scope repository, see `scope_repo()`
+    #[serde(skip_serializing, skip_deserializing, default = "OnceCell::new")]
+    scope_repo: OnceCell<ScopeRepository>,
     /// Metadata, e.g. indent and commenting information.
     ///
     /// NOTE: if serializing, you should handle metadata manually; that is, you should serialize and
-    /// deserialize it separately. See `examples/gendata.rs` for an example.
+    /// deserialize it separately. See `dumps::build_syntax_dump_from_folders` for an example.
     #[cfg(feature = "metadata")]
     #[serde(skip, default)]
     pub(crate) metadata: Metadata,
@@ -55,11 +66,88 @@ pub struct SyntaxReference {
     pub scope: Scope,
     pub first_line_match: Option<String>,
     pub hidden: bool,
+    /// The priority the syntax was added with, see [`SyntaxSetBuilder::add_with_priority`]
+    ///
+    /// [`SyntaxSetBuilder::add_with_priority`]: struct.SyntaxSetBuilder.html#method.add_with_priority
+    pub priority: i32,
     #[serde(serialize_with = "ordered_map")]
     pub variables: HashMap<String, String>,
     #[serde(skip)]
     pub(crate) lazy_contexts: OnceCell<LazyContexts>,
     pub(crate) serialized_lazy_contexts: Vec<u8>,
+    /// Tells the syntax apart from the syntaxes of other syntax sets, so parse states notice when
+    /// they're given the wrong set. It's new for every built or deserialized syntax, and kept when
+    /// the set is extended.
+    #[serde(skip, default = "next_syntax_id")]
+    pub(crate) id: u64,
+}
+
+fn next_syntax_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// The default for [`SyntaxSetBuilder::set_expansion_limit`].
+///
+/// [`SyntaxSetBuilder::set_expansion_limit`]: struct.SyntaxSetBuilder.html#method.set_expansion_limit
+pub const DEFAULT_EXPANSION_LIMIT: usize = 10_000;
+
+/// A problem with the syntaxes in a [`SyntaxSetBuilder`] that was found while building.
+///
+/// See [`SyntaxSetBuilder::build_with_lints`].
+///
+/// [`SyntaxSetBuilder`]: struct.SyntaxSetBuilder.html
+/// [`SyntaxSetBuilder::build_with_lints`]: struct.SyntaxSetBuilder.html#method.build_with_lints
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LintWarning {
+    /// Contexts that include each other in a cycle, which would make parsing loop forever.
+    ///
+    /// Each context is given as `(syntax name, context name)`, in include order, starting and
+    /// ending with the same context. The include that closes the cycle is removed.
+    IncludeCycle { contexts: Vec<(String, String)> },
+    /// A context that expands to more match patterns than the expansion limit after following
+    /// all of its includes.
+    TooManyPatterns {
+        syntax: String,
+        context: String,
+        count: usize,
+    },
+    /// A syntax file that `extends` a base syntax which failed to load, even with the syntax files
+    /// of all the folders added to the builder. [`SyntaxSetBuilder::try_build`] returns these as
+    /// errors instead, unless the file was added with non-strict options.
+    ///
+    /// [`SyntaxSetBuilder::try_build`]: struct.SyntaxSetBuilder.html#method.try_build
+    SkippedFile { path: String, error: String },
+}
+
+impl std::fmt::Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::IncludeCycle { contexts } => {
+                write!(f, "Include cycle: ")?;
+                for (i, (syntax, context)) in contexts.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " -> ")?;
+                    }
+                    write!(f, "{}#{}", syntax, context)?;
+                }
+                Ok(())
+            }
+            LintWarning::TooManyPatterns {
+                syntax,
+                context,
+                count,
+            } => write!(
+                f,
+                "Context {}#{} expands to {} patterns",
+                syntax, context, count
+            ),
+            LintWarning::SkippedFile { path, error } => {
+                write!(f, "Skipped syntax file {}: {}", path, error)
+            }
+        }
+    }
 }
 
 /// The lazy-loaded parts of a [`SyntaxReference`].
@@ -68,6 +156,9 @@ pub(crate) struct LazyContexts {
     #[serde(serialize_with = "ordered_map")]
     pub(crate) context_ids: HashMap<String, ContextId>,
     pub(crate) contexts: Vec<Context>,
+    /// The patterns of each context combined for searching, see `SyntaxSet::combined_patterns`
+    #[serde(skip)]
+    pub(crate) combined: OnceCell<Box<[OnceCell<Option<CombinedPatterns>>]>>,
 }
 
 /// A syntax set builder is used for loading syntax definitions from the file
@@ -82,7 +173,19 @@ pub(crate) struct LazyContexts {
 #[derive(Clone, Default)]
 pub struct SyntaxSetBuilder {
     syntaxes: Vec<SyntaxDefinition>,
+    /// The priorities of the syntaxes by index, see [`add_with_priority`]. It stops after the
+    /// last syntax that doesn't have priority 0.
+    ///
+    /// [`add_with_priority`]: #method.add_with_priority
+    priorities: Vec<i32>,
+    /// The ids of the syntaxes added with [`add_syntax_set`] by index, kept so that states parsing
+    /// with the old set keep working with the new one. It stops after the last of them.
+    ///
+    /// [`add_syntax_set`]: #method.add_syntax_set
+    syntax_ids: Vec<Option<u64>>,
     path_syntaxes: Vec<(String, usize)>,
+    /// See [`set_expansion_limit`](#method.set_expansion_limit), `None` means the default
+    expansion_limit: Option<usize>,
     #[cfg(feature = "metadata")]
     raw_metadata: LoadMetadata,
 
@@ -91,30 +194,71 @@ pub struct SyntaxSetBuilder {
     /// merging it with newly loaded metadata.
     #[cfg(feature = "metadata")]
     existing_metadata: Option<Metadata>,
+
+    /// The syntax files of all the folders added so far, to find the syntaxes others extend
+    #[cfg(feature = "yaml-load")]
+    syntax_files: Vec<PathBuf>,
+    /// Syntax files that extend a syntax that wasn't in their folder, loaded in `build`
+    #[cfg(feature = "yaml-load")]
+    extending_files: Vec<ExtendingFile>,
+}
+
+/// A syntax file whose base syntax wasn't found when its folder was added, with how it was added
+#[cfg(feature = "yaml-load")]
+#[derive(Clone)]
+struct ExtendingFile {
+    path: PathBuf,
+    lines_include_newline: bool,
+    options: LoadOptions,
+    /// Whether it's left out if the base is still missing, instead of failing `try_build`
+    skip_invalid: bool,
 }
 
 #[cfg(feature = "yaml-load")]
 fn load_syntax_file(
     p: &Path,
     lines_include_newline: bool,
+    options: &LoadOptions,
+    syntax_paths: &[PathBuf],
 ) -> Result<SyntaxDefinition, LoadingError> {
     let s = std::fs::read_to_string(p)?;
 
-    SyntaxDefinition::load_from_str(
+    // Base syntaxes are given as `Packages/<package>/<file>`, so look for a file at the end of
+    // the same path
+    let find_base = |base: &str| {
+        let base = Path::new(base.strip_prefix("Packages/").unwrap_or(base));
+        syntax_paths
+            .iter()
+            .find(|path| path.ends_with(base))
+            .and_then(|path| std::fs::read_to_string(path).ok())
+    };
+    SyntaxDefinition::load_from_str_with_bases(
         &s,
         lines_include_newline,
         p.file_stem().and_then(|x| x.to_str()),
+        options,
+        find_base,
     )
     .map_err(|e| LoadingError::ParseSyntax(e, format!("{}", p.display())))
 }
 
+/// Splits the path up and rejoins it with slashes so that syntaxes loaded on Windows can still be
+/// found the same way
+#[cfg(feature = "yaml-load")]
+fn slash_path(p: &Path) -> Option<String> {
+    let parts: Option<Vec<_>> = p.iter().map(|c| c.to_str()).collect();
+    parts.map(|parts| parts.join("/"))
+}
+
 impl Clone for SyntaxSet {
     fn clone(&self) -> SyntaxSet {
         SyntaxSet {
+            format: DumpFormat,
             syntaxes: self.syntaxes.clone(),
             path_syntaxes: self.path_syntaxes.clone(),
             // Will need to be re-initialized
             first_line_cache: OnceCell::new(),
+            scope_repo: OnceCell::new(),
             #[cfg(feature = "metadata")]
             metadata: self.metadata.clone(),
         }
@@ -124,9 +268,11 @@ impl Clone for SyntaxSet {
 impl Default for SyntaxSet {
     fn default() -> Self {
         SyntaxSet {
+            format: DumpFormat,
             syntaxes: Vec::new(),
             path_syntaxes: Vec::new(),
             first_line_cache: OnceCell::new(),
+            scope_repo: OnceCell::new(),
             #[cfg(feature = "metadata")]
             metadata: Metadata::default(),
         }
@@ -170,6 +316,48 @@ impl SyntaxSet {
         &self.metadata
     }
 
+    /// A snapshot of the global [`SCOPE_REPO`], taken the first time this is called.
+    ///
+    /// The snapshot can be used to look up scope names without locking the global repository,
+    /// which matters when lots of threads highlight in parallel. Scopes from syntaxes that are
+    /// lazily loaded after the snapshot was taken may not be in it, so check with
+    /// [`ScopeRepository::contains`] or use [`scope_to_string`], which falls back to the global
+    /// repository.
+    ///
+    /// [`SCOPE_REPO`]: struct.SCOPE_REPO.html
+    /// [`ScopeRepository::contains`]: struct.ScopeRepository.html#method.contains
+    /// [`scope_to_string`]: #method.scope_to_string
+    pub fn scope_repo(&self) -> &ScopeRepository {
+        self.scope_repo
+            .get_or_init(|| SCOPE_REPO.lock().unwrap().clone())
+    }
+
+    /// Parses a scope like [`Scope::new`], but without locking the global repository if all of its
+    /// atoms are already in [`scope_repo`].
+    ///
+    /// [`Scope::new`]: struct.Scope.html#method.new
+    /// [`scope_repo`]: #method.scope_repo
+    pub fn scope(&self, s: &str) -> Result<Scope, ParseScopeError> {
+        match self.scope_repo().lookup(s.trim()) {
+            Some(scope) => Ok(scope),
+            None => Scope::new(s),
+        }
+    }
+
+    /// Returns the string representation of a scope like [`Scope::build_string`], but without
+    /// locking the global repository if all of its atoms are in [`scope_repo`].
+    ///
+    /// [`Scope::build_string`]: struct.Scope.html#method.build_string
+    /// [`scope_repo`]: #method.scope_repo
+    pub fn scope_to_string(&self, scope: Scope) -> String {
+        let repo = self.scope_repo();
+        if repo.contains(scope) {
+            repo.to_string(scope)
+        } else {
+            scope.build_string()
+        }
+    }
+
     /// Finds a syntax by its default scope, for example `source.regexp` finds the regex syntax.
     ///
     /// This and all similar methods below do a linear search of syntaxes, this should be fast
@@ -183,12 +371,47 @@ impl SyntaxSet {
         self.syntaxes.iter().rev().find(|&s| name == s.name)
     }
 
+    /// Finds the syntax for a file extension, or a whole file name like `Makefile`
+    ///
+    /// When several syntaxes claim the extension, the one with the highest [`priority`] wins, and
+    /// between the ones with the same priority the one added last. See
+    /// [`find_syntaxes_by_extension`] for all of them.
+    ///
+    /// [`priority`]: struct.SyntaxReference.html#structfield.priority
+    /// [`find_syntaxes_by_extension`]: #method.find_syntaxes_by_extension
     pub fn find_syntax_by_extension<'a>(&'a self, extension: &str) -> Option<&'a SyntaxReference> {
-        self.syntaxes.iter().rev().find(|&s| {
-            s.file_extensions
-                .iter()
-                .any(|e| e.eq_ignore_ascii_case(extension))
-        })
+        self.syntaxes
+            .iter()
+            .rev()
+            .filter(|&s| s.has_extension(extension))
+            // only a higher priority beats a syntax that was added later
+            .reduce(|best, s| if s.priority > best.priority { s } else { best })
+    }
+
+    /// Finds all the syntaxes claiming a file extension, in the order of precedence used by
+    /// [`find_syntax_by_extension`], so the first one is the one it returns
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::SyntaxSet;
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let candidates = ss.find_syntaxes_by_extension("h");
+    /// assert_eq!(candidates[0].name, ss.find_syntax_by_extension("h").unwrap().name);
+    /// ```
+    ///
+    /// [`find_syntax_by_extension`]: #method.find_syntax_by_extension
+    pub fn find_syntaxes_by_extension<'a>(&'a self, extension: &str) -> Vec<&'a SyntaxReference> {
+        let mut syntaxes: Vec<_> = self
+            .syntaxes
+            .iter()
+            .rev()
+            .filter(|&s| s.has_extension(extension))
+            .collect();
+        // stable, so the ones added last stay first between the same priorities
+        syntaxes.sort_by_key(|s| std::cmp::Reverse(s.priority));
+        syntaxes
     }
 
     /// Searches for a syntax first by extension and then by case-insensitive name
@@ -208,6 +431,42 @@ impl SyntaxSet {
             .find(|&syntax| syntax.name.eq_ignore_ascii_case(s))
     }
 
+    /// Finds all the syntaxes matching a token like [`find_syntax_by_token`] does, for letting
+    /// users pick one when it's ambiguous
+    ///
+    /// The syntaxes claiming the token as a file extension come first, in the order of
+    /// [`find_syntaxes_by_extension`], followed by the ones with the token as their name and
+    /// then the ones with it as their name ignoring case. Each syntax is only listed once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::SyntaxSet;
+    ///
+    /// let ss = SyntaxSet::load_defaults_newlines();
+    /// let names: Vec<_> = ss.find_syntaxes_by_token("h").iter().map(|s| &s.name).collect();
+    /// assert!(names.contains(&&"C".to_owned()));
+    /// assert!(names.contains(&&"Objective-C".to_owned()));
+    /// ```
+    ///
+    /// [`find_syntax_by_token`]: #method.find_syntax_by_token
+    /// [`find_syntaxes_by_extension`]: #method.find_syntaxes_by_extension
+    pub fn find_syntaxes_by_token<'a>(&'a self, s: &str) -> Vec<&'a SyntaxReference> {
+        let mut syntaxes = self.find_syntaxes_by_extension(s);
+        let by_name = self.syntaxes.iter().rev().filter(|syntax| syntax.name == s);
+        let by_name_ignoring_case = self
+            .syntaxes
+            .iter()
+            .rev()
+            .filter(|syntax| syntax.name.eq_ignore_ascii_case(s));
+        for syntax in by_name.chain(by_name_ignoring_case) {
+            if !syntaxes.iter().any(|&found| std::ptr::eq(found, syntax)) {
+                syntaxes.push(syntax);
+            }
+        }
+        syntaxes
+    }
+
     /// Try to find the syntax for a file based on its first line
     ///
     /// This uses regexes that come with some sublime syntax grammars for matching things like
@@ -243,7 +502,8 @@ impl SyntaxSet {
     /// Convenience method that tries to find the syntax for a file path, first by extension/name
     /// and then by first line of the file if that doesn't work.
     ///
-    /// May IO Error because it sometimes tries to read the first line of the file.
+    /// May IO Error because it sometimes tries to read the first line of the file. To detect the
+    /// language in other ways, e.g. from the content, see [`detect_syntax`].
     ///
     /// # Examples
     ///
@@ -258,6 +518,8 @@ impl SyntaxSet {
     ///     .unwrap_or_else(|| ss.find_syntax_plain_text());
     /// assert_eq!(syntax.name, "HTML (Rails)");
     /// ```
+    ///
+    /// [`detect_syntax`]: #method.detect_syntax
     pub fn find_syntax_for_file<P: AsRef<Path>>(
         &self,
         path_obj: P,
@@ -311,77 +573,163 @@ impl SyntaxSet {
     /// Note that newly added syntaxes can have references to existing syntaxes
     /// in the set, but not the other way around.
     pub fn into_builder(self) -> SyntaxSetBuilder {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_syntax_set(self);
+        builder
+    }
+
+    /// Combines two syntax sets into one, with the syntaxes of `other` after the ones of `self`
+    ///
+    /// References that couldn't be resolved when either set was built, like an `embed` of a
+    /// syntax that's only in the other set, are linked against the combined set. Unlike going
+    /// through [`SyntaxSetBuilder::add_syntax_set`], nothing else is linked again.
+    ///
+    /// This isn't lazy: the contexts of every syntax in both sets are deserialized to find the
+    /// references to link, and the ones of `other` to renumber them. Only the syntaxes that get
+    /// new links and the ones of `other` are serialized again though, and contexts that weren't
+    /// loaded before are dropped again afterwards, so merging a small set into a big one, like the
+    /// default syntaxes, costs about as much as loading all of them once.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{SyntaxDefinition, SyntaxSet, SyntaxSetBuilder};
+    ///
+    /// let mut builder = SyntaxSetBuilder::new();
+    /// builder.add(SyntaxDefinition::load_from_str(
+    ///     "name: Todo\nscope: text.todo\nfile_extensions: [todo]\ncontexts:\n  main: []",
+    ///     true,
+    ///     None,
+    /// ).unwrap());
+    /// let ss = SyntaxSet::load_defaults_newlines().merge(builder.build());
+    /// assert!(ss.find_syntax_by_extension("todo").is_some());
+    /// assert!(ss.find_syntax_by_extension("rs").is_some());
+    /// ```
+    ///
+    /// [`SyntaxSetBuilder::add_syntax_set`]: struct.SyntaxSetBuilder.html#method.add_syntax_set
+    pub fn merge(self, other: SyntaxSet) -> SyntaxSet {
+        let offset = self.syntaxes.len();
+        let mut syntaxes = self.syntaxes;
+        let mut path_syntaxes = self.path_syntaxes;
         #[cfg(feature = "metadata")]
-        let SyntaxSet {
-            syntaxes,
-            path_syntaxes,
-            metadata,
-            ..
-        } = self;
-        #[cfg(not(feature = "metadata"))]
-        let SyntaxSet {
-            syntaxes,
-            path_syntaxes,
-            ..
-        } = self;
+        let mut metadata = self.metadata;
 
-        let mut context_map = HashMap::new();
-        for (syntax_index, syntax) in syntaxes.iter().enumerate() {
-            for (context_index, context) in syntax.contexts().iter().enumerate() {
-                context_map.insert(
-                    ContextId {
-                        syntax_index,
-                        context_index,
-                    },
-                    context.clone(),
-                );
-            }
+        let mut all_context_ids = Vec::new();
+        let mut all_contexts = Vec::new();
+        // Whether the contexts of a syntax have to be serialized again, and whether they were
+        // loaded before
+        let mut changed = Vec::new();
+        let mut loaded = Vec::new();
+        for syntax in &mut syntaxes {
+            loaded.push(syntax.lazy_contexts.get().is_some());
+            let lazy_contexts = syntax.take_lazy_contexts();
+            all_context_ids.push(lazy_contexts.context_ids);
+            all_contexts.push(lazy_contexts.contexts);
+            changed.push(false);
         }
-
-        let mut builder_syntaxes = Vec::with_capacity(syntaxes.len());
-
-        for syntax in syntaxes {
-            let SyntaxReference {
-                name,
-                file_extensions,
-                scope,
-                first_line_match,
-                hidden,
-                variables,
-                serialized_lazy_contexts,
+        for mut syntax in other.syntaxes {
+            loaded.push(syntax.lazy_contexts.get().is_some());
+            let LazyContexts {
+                mut context_ids,
+                mut contexts,
                 ..
-            } = syntax;
-
-            let lazy_contexts = LazyContexts::deserialize(&serialized_lazy_contexts[..]);
-            let mut builder_contexts = HashMap::with_capacity(lazy_contexts.context_ids.len());
-            for (name, context_id) in lazy_contexts.context_ids {
-                if let Some(context) = context_map.remove(&context_id) {
-                    builder_contexts.insert(name, context);
+            } = syntax.take_lazy_contexts();
+            for context_id in context_ids.values_mut() {
+                context_id.syntax_index += offset;
+            }
+            for context in &mut contexts {
+                SyntaxSetBuilder::offset_context_ids(context, offset);
+            }
+            all_context_ids.push(context_ids);
+            all_contexts.push(contexts);
+            changed.push(true);
+            syntaxes.push(syntax);
+        }
+        path_syntaxes.extend(
+            other
+                .path_syntaxes
+                .into_iter()
+                .map(|(path, index)| (path, index + offset)),
+        );
+        #[cfg(feature = "metadata")]
+        metadata
+            .scoped_metadata
+            .extend(other.metadata.scoped_metadata);
+
+        for (syntax_index, contexts) in all_contexts.iter_mut().enumerate() {
+            for context in contexts {
+                if !SyntaxSetBuilder::has_unresolved_references(context) {
+                    continue;
+                }
+                let patterns = context.patterns.clone();
+                SyntaxSetBuilder::link_context(context, syntax_index, &all_context_ids, &syntaxes);
+                if context.patterns != patterns {
+                    changed[syntax_index] = true;
                 }
             }
+        }
+        for syntax_index in SyntaxSetBuilder::mark_backref_includes(&mut all_contexts) {
+            changed[syntax_index] = true;
+        }
 
-            let syntax_definition = SyntaxDefinition {
-                name,
-                file_extensions,
-                scope,
-                first_line_match,
-                hidden,
-                variables,
-                contexts: builder_contexts,
+        for (((syntax, context_ids), contexts), (changed, loaded)) in syntaxes
+            .iter_mut()
+            .zip(all_context_ids)
+            .zip(all_contexts)
+            .zip(changed.into_iter().zip(loaded))
+        {
+            let lazy_contexts = LazyContexts {
+                context_ids,
+                contexts,
+                combined: OnceCell::new(),
             };
-            builder_syntaxes.push(syntax_definition);
+            if changed {
+                syntax.serialized_lazy_contexts = crate::dumps::dump_binary(&lazy_contexts);
+            }
+            if loaded {
+                syntax.lazy_contexts = OnceCell::from(lazy_contexts);
+            }
         }
 
-        SyntaxSetBuilder {
-            syntaxes: builder_syntaxes,
+        SyntaxSet {
+            format: DumpFormat,
+            syntaxes,
             path_syntaxes,
+            first_line_cache: OnceCell::new(),
+            scope_repo: OnceCell::new(),
             #[cfg(feature = "metadata")]
-            existing_metadata: Some(metadata),
-            #[cfg(feature = "metadata")]
-            raw_metadata: LoadMetadata::default(),
+            metadata,
         }
     }
 
+    /// The name of a context as `syntax#context`, for debugging
+    pub(crate) fn context_name(&self, context_id: &ContextId) -> String {
+        let syntax = match self.syntaxes.get(context_id.syntax_index) {
+            Some(syntax) => syntax,
+            None => return format!("{:?}", context_id),
+        };
+        let context = syntax
+            .context_ids()
+            .iter()
+            .find(|(_, id)| *id == context_id)
+            .map_or("?", |(name, _)| name.as_str());
+        format!("{}#{}", syntax.name, context)
+    }
+
+    /// Finds the name of a context by its address, formatted like `context_name`. Only the
+    /// syntaxes whose contexts have been loaded are searched, which includes all the ones used for
+    /// parsing.
+    pub(crate) fn loaded_context_name(&self, context: &Context) -> Option<String> {
+        self.syntaxes.iter().find_map(|syntax| {
+            let lazy_contexts = syntax.lazy_contexts.get()?;
+            let (name, _) = lazy_contexts
+                .context_ids
+                .iter()
+                .find(|(_, id)| std::ptr::eq(&lazy_contexts.contexts[id.context_index], context))?;
+            Some(format!("{}#{}", syntax.name, name))
+        })
+    }
+
     #[inline(always)]
     pub(crate) fn get_context(&self, context_id: &ContextId) -> Result<&Context, ParsingError> {
         let syntax = &self
@@ -394,6 +742,24 @@ impl SyntaxSet {
             .ok_or(ParsingError::MissingContext(*context_id))
     }
 
+    /// The patterns of a context combined into as few regexes as possible, built the first time
+    /// they're needed. `None` if none of the patterns can be combined.
+    pub(crate) fn combined_patterns(&self, context_id: &ContextId) -> Option<&CombinedPatterns> {
+        let lazy_contexts = self.syntaxes.get(context_id.syntax_index)?.lazy_contexts();
+        let combined = lazy_contexts.combined.get_or_init(|| {
+            (0..lazy_contexts.contexts.len())
+                .map(|_| OnceCell::new())
+                .collect()
+        });
+        combined
+            .get(context_id.context_index)?
+            .get_or_init(|| {
+                let context = &lazy_contexts.contexts[context_id.context_index];
+                CombinedPatterns::new(self, context)
+            })
+            .as_ref()
+    }
+
     fn first_line_cache(&self) -> &FirstLineCache {
         self.first_line_cache
             .get_or_init(|| FirstLineCache::new(self.syntaxes()))
@@ -450,14 +816,27 @@ impl SyntaxSet {
 }
 
 impl SyntaxReference {
+    fn has_extension(&self, extension: &str) -> bool {
+        self.file_extensions
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(extension))
+    }
+
     pub(crate) fn context_ids(&self) -> &HashMap<String, ContextId> {
         &self.lazy_contexts().context_ids
     }
 
-    fn contexts(&self) -> &[Context] {
+    pub(crate) fn contexts(&self) -> &[Context] {
         &self.lazy_contexts().contexts
     }
 
+    /// Takes the contexts out of the syntax, leaving them to be loaded again
+    fn take_lazy_contexts(&mut self) -> LazyContexts {
+        self.lazy_contexts
+            .take()
+            .unwrap_or_else(|| LazyContexts::deserialize(&self.serialized_lazy_contexts[..]))
+    }
+
     fn lazy_contexts(&self) -> &LazyContexts {
         self.lazy_contexts
             .get_or_init(|| LazyContexts::deserialize(&self.serialized_lazy_contexts[..]))
@@ -480,21 +859,234 @@ impl SyntaxSetBuilder {
         self.syntaxes.push(syntax);
     }
 
+    /// Add a syntax to the set with a priority, which decides which syntax is used for a file
+    /// extension claimed by several syntaxes
+    ///
+    /// The highest priority wins. Between syntaxes with the same priority, the one added last
+    /// wins, like packages loaded later and user overrides do in Sublime Text. [`add`] adds
+    /// syntaxes with priority 0.
+    ///
+    /// [`add`]: #method.add
+    pub fn add_with_priority(&mut self, syntax: SyntaxDefinition, priority: i32) {
+        if priority != 0 {
+            self.priorities.resize(self.syntaxes.len(), 0);
+            self.priorities.push(priority);
+        }
+        self.syntaxes.push(syntax);
+    }
+
     /// The list of syntaxes added so far.
     pub fn syntaxes(&self) -> &[SyntaxDefinition] {
         &self.syntaxes[..]
     }
 
-    /// A rarely useful method that loads in a syntax with no highlighting rules for plain text
+    /// Adds all the syntaxes of an already built syntax set, e.g. one loaded from a dump
+    ///
+    /// This makes it possible to combine several sets, where [`SyntaxSet::into_builder`] only
+    /// starts from one. The references between the syntaxes of each set are kept, and references
+    /// that couldn't be resolved when the set was built, like ones to syntaxes that are only in
+    /// another set, are resolved again by [`build`].
+    ///
+    /// [`SyntaxSet::into_builder`]: struct.SyntaxSet.html#method.into_builder
+    /// [`build`]: #method.build
+    pub fn add_syntax_set(&mut self, syntax_set: SyntaxSet) {
+        #[cfg(feature = "metadata")]
+        let SyntaxSet {
+            syntaxes,
+            path_syntaxes,
+            metadata,
+            ..
+        } = syntax_set;
+        #[cfg(not(feature = "metadata"))]
+        let SyntaxSet {
+            syntaxes,
+            path_syntaxes,
+            ..
+        } = syntax_set;
+
+        // The contexts of the set refer to each other by syntax index, which shifts by the number
+        // of syntaxes already in the builder. Context indexes stay the same because `build` sorts
+        // the contexts of a syntax by name.
+        let offset = self.syntaxes.len();
+        for syntax in syntaxes {
+            let SyntaxReference {
+                name,
+                file_extensions,
+                scope,
+                first_line_match,
+                hidden,
+                priority,
+                variables,
+                lazy_contexts,
+                serialized_lazy_contexts,
+                id,
+            } = syntax;
+
+            let LazyContexts {
+                context_ids,
+                contexts,
+                ..
+            } = lazy_contexts
+                .into_inner()
+                .unwrap_or_else(|| LazyContexts::deserialize(&serialized_lazy_contexts[..]));
+            let mut contexts: Vec<Option<Context>> = contexts.into_iter().map(Some).collect();
+            let mut builder_contexts = HashMap::with_capacity(context_ids.len());
+            for (name, context_id) in context_ids {
+                if let Some(mut context) = contexts
+                    .get_mut(context_id.context_index)
+                    .and_then(Option::take)
+                {
+                    if offset > 0 {
+                        Self::offset_context_ids(&mut context, offset);
+                    }
+                    builder_contexts.insert(name, context);
+                }
+            }
+
+            self.syntax_ids.resize(self.syntaxes.len(), None);
+            self.syntax_ids.push(Some(id));
+            self.add_with_priority(
+                SyntaxDefinition {
+                    name,
+                    file_extensions,
+                    scope,
+                    first_line_match,
+                    hidden,
+                    variables,
+                    contexts: builder_contexts,
+                },
+                priority,
+            );
+        }
+        self.path_syntaxes.extend(
+            path_syntaxes
+                .into_iter()
+                .map(|(path, index)| (path, index + offset)),
+        );
+
+        #[cfg(feature = "metadata")]
+        match self.existing_metadata {
+            Some(ref mut existing) => existing.scoped_metadata.extend(metadata.scoped_metadata),
+            None => self.existing_metadata = Some(metadata),
+        }
+    }
+
+    /// Keeps only the syntaxes added so far for which `f` returns `true`
+    ///
+    /// This leaves out syntaxes that aren't wanted, like the experimental grammars of a bundled
+    /// Packages folder, without changing the folder. References to the removed syntaxes are left
+    /// unresolved, unless a syntax with the same scope is added before [`build`]. Syntaxes that
+    /// `extends` a base from a folder added later are only loaded by [`build`], so they aren't
+    /// filtered by this.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use syntect::parsing::{SyntaxSet, SyntaxSetBuilder};
+    ///
+    /// let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+    /// builder.retain(|syntax| !syntax.hidden && syntax.name != "LaTeX");
+    /// let ss = builder.build();
+    /// assert!(ss.find_syntax_by_name("LaTeX").is_none());
+    /// assert!(ss.syntaxes().iter().all(|syntax| !syntax.hidden));
+    /// ```
+    ///
+    /// [`build`]: #method.build
+    pub fn retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&SyntaxDefinition) -> bool,
+    {
+        let mut new_indices = Vec::with_capacity(self.syntaxes.len());
+        let mut kept = 0;
+        for syntax in &self.syntaxes {
+            if f(syntax) {
+                new_indices.push(Some(kept));
+                kept += 1;
+            } else {
+                new_indices.push(None);
+            }
+        }
+        if kept == self.syntaxes.len() {
+            return;
+        }
+
+        // The contexts of syntaxes added with `add_syntax_set` are linked by index. References
+        // to a removed syntax go back to its scope and the name of the context, which is at the
+        // same index in the sorted names, see `add_syntax_set`.
+        let mut removed = HashMap::new();
+        for (index, syntax) in mem::take(&mut self.syntaxes).into_iter().enumerate() {
+            if new_indices[index].is_some() {
+                self.syntaxes.push(syntax);
+            } else {
+                let mut names: Vec<String> = syntax.contexts.into_keys().collect();
+                names.sort();
+                removed.insert(index, (syntax.scope, names));
+            }
+        }
+        for syntax in &mut self.syntaxes {
+            for context in syntax.contexts.values_mut() {
+                if let Some(ref mut prototype) = context.prototype {
+                    if let Some(new_index) = new_indices[prototype.syntax_index] {
+                        prototype.syntax_index = new_index;
+                    }
+                }
+                Self::for_each_context_ref(context, |context_ref| {
+                    let id = match *context_ref {
+                        ContextReference::Direct(ref mut id) => id,
+                        _ => return,
+                    };
+                    match new_indices[id.syntax_index] {
+                        Some(new_index) => id.syntax_index = new_index,
+                        None => {
+                            let (scope, names) = &removed[&id.syntax_index];
+                            *context_ref = ContextReference::ByScope {
+                                scope: *scope,
+                                sub_context: names.get(id.context_index).cloned(),
+                                with_escape: false,
+                            };
+                        }
+                    }
+                });
+            }
+        }
+        self.path_syntaxes = mem::take(&mut self.path_syntaxes)
+            .into_iter()
+            .filter_map(|(path, index)| Some((path, new_indices[index]?)))
+            .collect();
+        self.syntax_ids = mem::take(&mut self.syntax_ids)
+            .into_iter()
+            .zip(&new_indices)
+            .filter_map(|(id, new_index)| new_index.map(|_| id))
+            .collect();
+        self.priorities = mem::take(&mut self.priorities)
+            .into_iter()
+            .zip(new_indices)
+            .filter_map(|(priority, new_index)| new_index.map(|_| priority))
+            .collect();
+    }
+
+    /// Set the number of match patterns a single context may expand to, after following all of
+    /// its includes, before [`build_with_lints`] reports it.
+    ///
+    /// The default is [`DEFAULT_EXPANSION_LIMIT`].
+    ///
+    /// [`build_with_lints`]: #method.build_with_lints
+    /// [`DEFAULT_EXPANSION_LIMIT`]: constant.DEFAULT_EXPANSION_LIMIT.html
+    pub fn set_expansion_limit(&mut self, limit: usize) {
+        self.expansion_limit = Some(limit);
+    }
+
+    /// Adds the syntax for plain text, [`SyntaxDefinition::plain_text`], which has no
+    /// highlighting rules
     ///
     /// Exists mainly for adding the plain text syntax to syntax set dumps, because for some reason
-    /// the default Sublime plain text syntax is still in `.tmLanguage` format.
-    #[cfg(feature = "yaml-load")]
+    /// the default Sublime plain text syntax is still in `.tmLanguage` format, and for adding a
+    /// fallback for [`SyntaxSet::find_syntax_plain_text`] to sets of custom syntaxes.
+    ///
+    /// [`SyntaxDefinition::plain_text`]: syntax_definition/struct.SyntaxDefinition.html#method.plain_text
+    /// [`SyntaxSet::find_syntax_plain_text`]: struct.SyntaxSet.html#method.find_syntax_plain_text
     pub fn add_plain_text_syntax(&mut self) {
-        let s = "---\nname: Plain Text\nfile_extensions: [txt]\nscope: text.plain\ncontexts: \
-                 {main: []}";
-        let syn = SyntaxDefinition::load_from_str(s, false, None).unwrap();
-        self.syntaxes.push(syn);
+        self.syntaxes.push(SyntaxDefinition::plain_text());
     }
 
     /// Loads all the `.sublime-syntax` files in a folder into this builder.
@@ -510,30 +1102,78 @@ impl SyntaxSetBuilder {
     /// In the future I might include a "slow mode" that copies the lines passed in and appends a
     /// newline if there isn't one, but in the interest of performance currently this hacky fix will
     /// have to do.
+    ///
+    /// Syntaxes that `extends` a base syntax, like `Packages/JavaScript/JavaScript.sublime-syntax`,
+    /// look for it at the end of the paths of the syntax files in all the folders added so far.
+    /// If it isn't there, the syntax file is read again and loaded by [`build`] instead, so the
+    /// base can also be in a folder that's added later. If the base still can't be found then,
+    /// [`try_build`] returns the error, like this method fails for other invalid syntax files.
+    /// [`build`] leaves the syntax out and reports it as [`LintWarning::SkippedFile`] from
+    /// [`build_with_lints`], which is what [`try_build`] does too for files added with
+    /// [`add_from_folder_with_options`] and non-strict options.
+    ///
+    /// [`build`]: #method.build
+    /// [`try_build`]: #method.try_build
+    /// [`add_from_folder_with_options`]: #method.add_from_folder_with_options
+    /// [`build_with_lints`]: #method.build_with_lints
+    /// [`LintWarning::SkippedFile`]: enum.LintWarning.html#variant.SkippedFile
     #[cfg(feature = "yaml-load")]
     pub fn add_from_folder<P: AsRef<Path>>(
         &mut self,
         folder: P,
         lines_include_newline: bool,
     ) -> Result<(), LoadingError> {
-        for entry in crate::utils::walk_dir(folder).sort_by(|a, b| a.file_name().cmp(b.file_name()))
-        {
+        self.add_from_folder_impl(
+            folder,
+            lines_include_newline,
+            &LoadOptions::default(),
+            false,
+        )
+    }
+
+    /// Same as [`add_from_folder`] but with control over how strictly syntaxes are validated.
+    ///
+    /// Unless `options.strict` is set, syntax files that fail to load are skipped and reported to
+    /// the `on_warning` callback as [`LoadWarning::SkippedFile`], so that a single malformed
+    /// grammar doesn't prevent all the others from loading. Errors walking the folder or reading
+    /// files are still returned.
+    ///
+    /// With `options.skip_hidden`, the hidden syntaxes of the folder are left out. They can still
+    /// be the base of a syntax that `extends` them, but references to their contexts are left
+    /// unresolved. For leaving out other syntaxes, see [`retain`].
+    ///
+    /// [`add_from_folder`]: #method.add_from_folder
+    /// [`LoadWarning::SkippedFile`]: enum.LoadWarning.html#variant.SkippedFile
+    /// [`retain`]: #method.retain
+    #[cfg(feature = "yaml-load")]
+    pub fn add_from_folder_with_options<P: AsRef<Path>>(
+        &mut self,
+        folder: P,
+        lines_include_newline: bool,
+        options: &LoadOptions,
+    ) -> Result<(), LoadingError> {
+        self.add_from_folder_impl(folder, lines_include_newline, options, !options.strict)
+    }
+
+    #[cfg(feature = "yaml-load")]
+    fn add_from_folder_impl<P: AsRef<Path>>(
+        &mut self,
+        folder: P,
+        lines_include_newline: bool,
+        options: &LoadOptions,
+        skip_invalid: bool,
+    ) -> Result<(), LoadingError> {
+        // All the syntax files are needed before loading any of them, to find the syntaxes they
+        // extend
+        let first_new_file = self.syntax_files.len();
+        for entry in crate::utils::walk_dir(folder) {
             let entry = entry.map_err(LoadingError::WalkDir)?;
             if entry
                 .path()
                 .extension()
                 .map_or(false, |e| e == "sublime-syntax")
             {
-                let syntax = load_syntax_file(entry.path(), lines_include_newline)?;
-                if let Some(path_str) = entry.path().to_str() {
-                    // Split the path up and rejoin with slashes so that syntaxes loaded on Windows
-                    // can still be loaded the same way.
-                    let path = Path::new(path_str);
-                    let path_parts: Vec<_> = path.iter().map(|c| c.to_str().unwrap()).collect();
-                    self.path_syntaxes
-                        .push((path_parts.join("/").to_string(), self.syntaxes.len()));
-                }
-                self.syntaxes.push(syntax);
+                self.syntax_files.push(entry.path().to_path_buf());
             }
 
             #[cfg(feature = "metadata")]
@@ -547,6 +1187,41 @@ impl SyntaxSetBuilder {
             }
         }
 
+        for index in first_new_file..self.syntax_files.len() {
+            let path = &self.syntax_files[index].clone();
+            let syntax =
+                match load_syntax_file(path, lines_include_newline, options, &self.syntax_files) {
+                    Ok(syntax) => syntax,
+                    // The base may be in a folder that's added later. Whether the syntax can be
+                    // left out if it isn't is decided now, so `build` still fails for files that
+                    // weren't allowed to be skipped.
+                    Err(LoadingError::ParseSyntax(ParseSyntaxError::MissingBaseSyntax(_), _)) => {
+                        self.extending_files.push(ExtendingFile {
+                            path: path.clone(),
+                            lines_include_newline,
+                            options: options.clone(),
+                            skip_invalid,
+                        });
+                        continue;
+                    }
+                    Err(LoadingError::ParseSyntax(err, path)) if skip_invalid => {
+                        options.warn(LoadWarning::SkippedFile {
+                            path,
+                            error: err.to_string(),
+                        });
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+            if options.skip_hidden && syntax.hidden {
+                continue;
+            }
+            if let Some(path) = slash_path(path) {
+                self.path_syntaxes.push((path, self.syntaxes.len()));
+            }
+            self.add_with_priority(syntax, options.priority);
+        }
+
         Ok(())
     }
 
@@ -570,21 +1245,75 @@ impl SyntaxSetBuilder {
     /// serialize a [`SyntaxSet`] for your program and when you run the program,
     /// directly load the [`SyntaxSet`].
     ///
+    /// A syntax file that `extends` a syntax from a folder that wasn't added yet, and still can't
+    /// find it, is left out, see [`add_from_folder`]. Use [`try_build`] to get an error for it
+    /// instead.
+    ///
     /// [`SyntaxSet`]: struct.SyntaxSet.html
+    /// [`add_from_folder`]: #method.add_from_folder
+    /// [`try_build`]: #method.try_build
     pub fn build(self) -> SyntaxSet {
+        self.build_with_lints().0
+    }
+
+    /// Same as [`build`], but returns an error when a syntax file added with strict options can't
+    /// find the syntax it `extends`, instead of leaving it out.
+    ///
+    /// [`build`]: #method.build
+    #[allow(unused_mut)]
+    pub fn try_build(mut self) -> Result<SyntaxSet, LoadingError> {
+        #[cfg(feature = "yaml-load")]
+        let lints = self.load_extending_files(true)?;
+        #[cfg(not(feature = "yaml-load"))]
+        let lints = Vec::new();
+        Ok(self.link(lints).0)
+    }
+
+    /// Same as [`build`], but also returns the problems found while linking.
+    ///
+    /// Contexts that include each other in a cycle would make parsing loop forever, so the
+    /// include closing each cycle is removed and reported as [`LintWarning::IncludeCycle`]. This
+    /// also happens in [`build`], just silently.
+    ///
+    /// [`build`]: #method.build
+    /// [`LintWarning::IncludeCycle`]: enum.LintWarning.html#variant.IncludeCycle
+    #[allow(unused_mut)]
+    pub fn build_with_lints(mut self) -> (SyntaxSet, Vec<LintWarning>) {
+        // Files that can't be loaded only become errors in strict mode
+        #[cfg(feature = "yaml-load")]
+        let lints = self.load_extending_files(false).unwrap_or_default();
+        #[cfg(not(feature = "yaml-load"))]
+        let lints = Vec::new();
+        self.link(lints)
+    }
+
+    /// Links the syntaxes into a set, adding the problems found while linking to `lints`
+    fn link(self, mut lints: Vec<LintWarning>) -> (SyntaxSet, Vec<LintWarning>) {
         #[cfg(not(feature = "metadata"))]
         let SyntaxSetBuilder {
             syntaxes: syntax_definitions,
+            priorities,
+            syntax_ids,
             path_syntaxes,
+            expansion_limit,
+            ..
         } = self;
         #[cfg(feature = "metadata")]
         let SyntaxSetBuilder {
             syntaxes: syntax_definitions,
+            priorities,
+            syntax_ids,
             path_syntaxes,
+            expansion_limit,
             raw_metadata,
             existing_metadata,
+            ..
         } = self;
 
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("build_syntax_set", syntaxes = syntax_definitions.len()).entered();
+
         let mut syntaxes = Vec::with_capacity(syntax_definitions.len());
         let mut all_context_ids = Vec::new();
         let mut all_contexts = vec![Vec::new(); syntax_definitions.len()];
@@ -626,15 +1355,20 @@ impl SyntaxSetBuilder {
                 scope,
                 first_line_match,
                 hidden,
+                priority: priorities.get(syntax_index).copied().unwrap_or(0),
                 variables,
                 lazy_contexts: OnceCell::new(),
                 serialized_lazy_contexts: Vec::new(), // initialized in the last step
+                id: syntax_ids
+                    .get(syntax_index)
+                    .copied()
+                    .flatten()
+                    .unwrap_or_else(next_syntax_id),
             };
             syntaxes.push(syntax);
             all_context_ids.push(context_ids);
         }
 
-        let mut found_more_backref_includes = true;
         for (syntax_index, _syntax) in syntaxes.iter().enumerate() {
             let mut no_prototype = HashSet::new();
             let prototype = all_context_ids[syntax_index].get("prototype");
@@ -656,38 +1390,29 @@ impl SyntaxSetBuilder {
                     }
                 }
                 Self::link_context(context, syntax_index, &all_context_ids, &syntaxes);
-
-                if context.uses_backrefs {
-                    found_more_backref_includes = true;
-                }
             }
         }
 
-        // We need to recursively mark contexts that include contexts which
-        // use backreferences as using backreferences. In theory we could use
-        // a more efficient method here like doing a toposort or constructing
-        // a representation with reversed edges and then tracing in the
-        // opposite direction, but I benchmarked this and it adds <2% to link
-        // time on the default syntax set, and linking doesn't even happen
-        // when loading from a binary dump.
-        while found_more_backref_includes {
-            found_more_backref_includes = false;
-            // find any contexts which include a context which uses backrefs
-            // and mark those as using backrefs - to support nested includes
-            for syntax_index in 0..syntaxes.len() {
-                for context_index in 0..all_contexts[syntax_index].len() {
-                    let context = &all_contexts[syntax_index][context_index];
-                    if !context.uses_backrefs && context.patterns.iter().any(|pattern| {
-                        matches!(pattern, Pattern::Include(ContextReference::Direct(id)) if all_contexts[id.syntax_index][id.context_index].uses_backrefs)
-                    }) {
-                        let context = &mut all_contexts[syntax_index][context_index];
-                        context.uses_backrefs = true;
-                        // look for contexts including this context
-                        found_more_backref_includes = true;
-                    }
-                }
-            }
-        }
+        Self::mark_backref_includes(&mut all_contexts);
+
+        lints.extend(Self::lint_includes(
+            &syntaxes,
+            &all_context_ids,
+            &mut all_contexts,
+            expansion_limit.unwrap_or(DEFAULT_EXPANSION_LIMIT),
+        ));
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            contexts = all_contexts.iter().map(Vec::len).sum::<usize>(),
+            patterns = all_contexts
+                .iter()
+                .flatten()
+                .map(|context| context.patterns.len())
+                .sum::<usize>(),
+            lints = lints.len(),
+            "linked syntax set"
+        );
 
         #[cfg(feature = "metadata")]
         let metadata = match existing_metadata {
@@ -703,18 +1428,184 @@ impl SyntaxSetBuilder {
             let lazy_contexts = LazyContexts {
                 context_ids: all_context_ids.remove(0),
                 contexts: all_contexts.remove(0),
+                combined: OnceCell::new(),
             };
 
             syntax.serialized_lazy_contexts = crate::dumps::dump_binary(&lazy_contexts);
         }
-
-        SyntaxSet {
-            syntaxes,
-            path_syntaxes,
-            first_line_cache: OnceCell::new(),
-            #[cfg(feature = "metadata")]
-            metadata,
+
+        let syntax_set = SyntaxSet {
+            format: DumpFormat,
+            syntaxes,
+            path_syntaxes,
+            first_line_cache: OnceCell::new(),
+            scope_repo: OnceCell::new(),
+            #[cfg(feature = "metadata")]
+            metadata,
+        };
+        (syntax_set, lints)
+    }
+
+    /// Loads the syntax files whose base syntaxes weren't in their folder, now that the syntax
+    /// files of all the folders are known.
+    ///
+    /// Files that still fail to load are skipped and returned as lints, except that with `strict`
+    /// the error of the first one added with strict options is returned.
+    #[cfg(feature = "yaml-load")]
+    fn load_extending_files(&mut self, strict: bool) -> Result<Vec<LintWarning>, LoadingError> {
+        let mut lints = Vec::new();
+        for file in mem::take(&mut self.extending_files) {
+            let ExtendingFile {
+                path,
+                lines_include_newline,
+                options,
+                skip_invalid,
+            } = file;
+            match load_syntax_file(&path, lines_include_newline, &options, &self.syntax_files) {
+                Ok(syntax) if options.skip_hidden && syntax.hidden => {}
+                Ok(syntax) => {
+                    if let Some(path) = slash_path(&path) {
+                        self.path_syntaxes.push((path, self.syntaxes.len()));
+                    }
+                    self.add_with_priority(syntax, options.priority);
+                }
+                Err(err) if strict && !skip_invalid => return Err(err),
+                Err(err) => {
+                    let (path, error) = match err {
+                        LoadingError::ParseSyntax(err, path) => (path, err.to_string()),
+                        err => (path.display().to_string(), err.to_string()),
+                    };
+                    options.warn(LoadWarning::SkippedFile {
+                        path: path.clone(),
+                        error: error.clone(),
+                    });
+                    lints.push(LintWarning::SkippedFile { path, error });
+                }
+            }
+        }
+        Ok(lints)
+    }
+
+    /// Break include cycles and find contexts that expand to too many patterns.
+    fn lint_includes(
+        syntaxes: &[SyntaxReference],
+        all_context_ids: &[HashMap<String, ContextId>],
+        all_contexts: &mut [Vec<Context>],
+        expansion_limit: usize,
+    ) -> Vec<LintWarning> {
+        let mut names = HashMap::new();
+        for (syntax_index, context_ids) in all_context_ids.iter().enumerate() {
+            for (name, id) in context_ids {
+                names.insert(*id, (syntaxes[syntax_index].name.clone(), name.clone()));
+            }
+        }
+        let name_of = |id: &ContextId| names.get(id).cloned().unwrap_or_default();
+
+        let mut lints = Vec::new();
+        let mut finished = HashSet::new();
+        for syntax_index in 0..all_contexts.len() {
+            for context_index in 0..all_contexts[syntax_index].len() {
+                let id = ContextId {
+                    syntax_index,
+                    context_index,
+                };
+                let mut path = Vec::new();
+                for cycle in Self::break_include_cycles(id, all_contexts, &mut path, &mut finished)
+                {
+                    lints.push(LintWarning::IncludeCycle {
+                        contexts: cycle.iter().map(name_of).collect(),
+                    });
+                }
+            }
+        }
+
+        // With the cycles gone, count how many patterns each context expands to
+        let mut counts = HashMap::new();
+        let mut ids: Vec<&ContextId> = names.keys().collect();
+        ids.sort_by_key(|id| (id.syntax_index, id.context_index));
+        for id in ids {
+            let count = Self::count_expanded_patterns(*id, all_contexts, &mut counts);
+            if count > expansion_limit {
+                let (syntax, context) = name_of(id);
+                lints.push(LintWarning::TooManyPatterns {
+                    syntax,
+                    context,
+                    count,
+                });
+            }
+        }
+        lints
+    }
+
+    /// Depth-first search along includes starting at `id`, removing includes that lead back to a
+    /// context on the current `path`. Returns the cycles that were broken.
+    fn break_include_cycles(
+        id: ContextId,
+        all_contexts: &mut [Vec<Context>],
+        path: &mut Vec<ContextId>,
+        finished: &mut HashSet<ContextId>,
+    ) -> Vec<Vec<ContextId>> {
+        if finished.contains(&id) {
+            return Vec::new();
+        }
+        path.push(id);
+        let mut cycles = Vec::new();
+        let mut pattern_index = 0;
+        while pattern_index
+            < all_contexts[id.syntax_index][id.context_index]
+                .patterns
+                .len()
+        {
+            let included =
+                match all_contexts[id.syntax_index][id.context_index].patterns[pattern_index] {
+                    Pattern::Include(ContextReference::Direct(included)) => included,
+                    _ => {
+                        pattern_index += 1;
+                        continue;
+                    }
+                };
+            if let Some(start) = path.iter().position(|&p| p == included) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(included);
+                cycles.push(cycle);
+                all_contexts[id.syntax_index][id.context_index]
+                    .patterns
+                    .remove(pattern_index);
+                continue;
+            }
+            cycles.extend(Self::break_include_cycles(
+                included,
+                all_contexts,
+                path,
+                finished,
+            ));
+            pattern_index += 1;
+        }
+        path.pop();
+        finished.insert(id);
+        cycles
+    }
+
+    fn count_expanded_patterns(
+        id: ContextId,
+        all_contexts: &[Vec<Context>],
+        counts: &mut HashMap<ContextId, usize>,
+    ) -> usize {
+        if let Some(&count) = counts.get(&id) {
+            return count;
+        }
+        let mut count: usize = 0;
+        for pattern in &all_contexts[id.syntax_index][id.context_index].patterns {
+            count = count.saturating_add(match pattern {
+                Pattern::Match(_) => 1,
+                Pattern::Include(ContextReference::Direct(included)) => {
+                    Self::count_expanded_patterns(*included, all_contexts, counts)
+                }
+                Pattern::Include(_) => 0,
+            });
         }
+        counts.insert(id, count);
+        count
     }
 
     /// Anything recursively included by the prototype shouldn't include the prototype.
@@ -792,6 +1683,86 @@ impl SyntaxSetBuilder {
         }
     }
 
+    /// Marks the contexts that include contexts which use backreferences as using
+    /// backreferences too, and returns the indexes of the syntaxes with contexts that were marked
+    fn mark_backref_includes(all_contexts: &mut [Vec<Context>]) -> HashSet<usize> {
+        // We need to recursively mark contexts that include contexts which
+        // use backreferences as using backreferences. In theory we could use
+        // a more efficient method here like doing a toposort or constructing
+        // a representation with reversed edges and then tracing in the
+        // opposite direction, but I benchmarked this and it adds <2% to link
+        // time on the default syntax set, and linking doesn't even happen
+        // when loading from a binary dump.
+        let mut marked = HashSet::new();
+        let mut found_more_backref_includes = true;
+        while found_more_backref_includes {
+            found_more_backref_includes = false;
+            // find any contexts which include a context which uses backrefs
+            // and mark those as using backrefs - to support nested includes
+            for syntax_index in 0..all_contexts.len() {
+                for context_index in 0..all_contexts[syntax_index].len() {
+                    let context = &all_contexts[syntax_index][context_index];
+                    if !context.uses_backrefs && context.patterns.iter().any(|pattern| {
+                        matches!(pattern, Pattern::Include(ContextReference::Direct(id)) if all_contexts[id.syntax_index][id.context_index].uses_backrefs)
+                    }) {
+                        let context = &mut all_contexts[syntax_index][context_index];
+                        context.uses_backrefs = true;
+                        marked.insert(syntax_index);
+                        // look for contexts including this context
+                        found_more_backref_includes = true;
+                    }
+                }
+            }
+        }
+        marked
+    }
+
+    fn offset_context_ids(context: &mut Context, offset: usize) {
+        if let Some(ref mut prototype) = context.prototype {
+            prototype.syntax_index += offset;
+        }
+        Self::for_each_context_ref(context, |context_ref| {
+            if let ContextReference::Direct(ref mut id) = *context_ref {
+                id.syntax_index += offset;
+            }
+        });
+    }
+
+    /// Calls `f` with each context reference of the patterns of a context
+    fn for_each_context_ref<F: FnMut(&mut ContextReference)>(context: &mut Context, mut f: F) {
+        for pattern in &mut context.patterns {
+            match *pattern {
+                Pattern::Match(ref mut match_pat) => {
+                    if let MatchOperation::Push(ref mut context_refs)
+                    | MatchOperation::Set(ref mut context_refs) = match_pat.operation
+                    {
+                        context_refs.iter_mut().for_each(&mut f);
+                    }
+                    if let Some(ref mut context_ref) = match_pat.with_prototype {
+                        f(context_ref);
+                    }
+                }
+                Pattern::Include(ref mut context_ref) => f(context_ref),
+            }
+        }
+    }
+
+    fn has_unresolved_references(context: &Context) -> bool {
+        let unresolved =
+            |context_ref: &ContextReference| !matches!(context_ref, ContextReference::Direct(_));
+        context.patterns.iter().any(|pattern| match pattern {
+            Pattern::Match(match_pat) => {
+                let pushed = match match_pat.operation {
+                    MatchOperation::Push(ref context_refs)
+                    | MatchOperation::Set(ref context_refs) => context_refs.iter().any(unresolved),
+                    MatchOperation::Pop | MatchOperation::None => false,
+                };
+                pushed || match_pat.with_prototype.as_ref().is_some_and(unresolved)
+            }
+            Pattern::Include(context_ref) => unresolved(context_ref),
+        })
+    }
+
     fn link_context(
         context: &mut Context,
         syntax_index: usize,
@@ -808,6 +1779,27 @@ impl SyntaxSetBuilder {
                 }
             }
         }
+        // Including the prototype of a syntax without one, as `apply_prototype` does, includes
+        // nothing, so it isn't kept as an unresolved reference
+        context.patterns.retain(|pattern| match pattern {
+            Pattern::Include(ContextReference::Prototype(prototype)) => {
+                !Self::has_target_syntax(prototype, syntaxes)
+            }
+            _ => true,
+        });
+    }
+
+    /// Whether the syntax a reference by scope or file points at is in the set
+    fn has_target_syntax(context_ref: &ContextReference, syntaxes: &[SyntaxReference]) -> bool {
+        match context_ref {
+            ContextReference::ByScope { scope, .. } => {
+                syntaxes.iter().any(|syntax| syntax.scope == *scope)
+            }
+            ContextReference::File { name, .. } => {
+                syntaxes.iter().any(|syntax| syntax.name == *name)
+            }
+            _ => false,
+        }
     }
 
     fn link_ref(
@@ -818,6 +1810,13 @@ impl SyntaxSetBuilder {
     ) {
         // println!("{:?}", context_ref);
         use super::syntax_definition::ContextReference::*;
+        if let Prototype(ref mut prototype) = *context_ref {
+            Self::link_ref(prototype, syntax_index, all_context_ids, syntaxes);
+            if let Direct(context_id) = **prototype {
+                *context_ref = Direct(context_id);
+            }
+            return;
+        }
         let linked_context_id = match *context_ref {
             Named(ref s) | Inline(ref s) => {
                 // This isn't actually correct, but it is better than nothing/crashing.
@@ -853,7 +1852,7 @@ impl SyntaxSetBuilder {
                     &index_and_syntax.1.name == name
                 }),
             ),
-            Direct(_) => None,
+            Direct(_) | Prototype(_) => None,
         };
         if let Some(context_id) = linked_context_id {
             let mut new_ref = Direct(*context_id);
@@ -945,6 +1944,7 @@ impl FirstLineCache {
 mod tests {
     use super::*;
     use crate::parsing::{syntax_definition, ParseState, Scope};
+    use crate::utils::TempDir;
     use std::collections::HashMap;
 
     #[test]
@@ -1110,6 +2110,249 @@ mod tests {
         assert_ops_contain(&ops, &expected);
     }
 
+    #[test]
+    fn can_combine_syntax_sets() {
+        // A pushes B, which is only in the other set
+        let first = {
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(syntax_a());
+            builder.build()
+        };
+        let syntax_c = SyntaxDefinition::load_from_str(
+            r#"
+        name: C
+        scope: source.c
+        file_extensions: [c]
+        contexts:
+          main:
+            - match: 'go_b'
+              push: scope:source.b#main
+        "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let second = {
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(syntax_b());
+            builder.add(syntax_c);
+            builder.build()
+        };
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_syntax_set(first);
+        builder.add_syntax_set(second);
+        let syntax_set = builder.build();
+        let names: Vec<&str> = syntax_set.syntaxes().iter().map(|s| &*s.name).collect();
+        assert_eq!(names, ["A", "B", "C"]);
+
+        let expected = (7, ScopeStackOp::Push(Scope::new("b").unwrap()));
+        for extension in ["a", "c"] {
+            let syntax = syntax_set.find_syntax_by_extension(extension).unwrap();
+            let mut parse_state = ParseState::new(syntax);
+            let ops = parse_state
+                .parse_line("x go_b b", &syntax_set)
+                .expect("#[cfg(test)]");
+            assert_ops_contain(&ops, &expected);
+        }
+    }
+
+    #[test]
+    fn retain_keeps_references_between_the_remaining_syntaxes() {
+        let mut hidden = syntax_b();
+        hidden.name = "Hidden".to_owned();
+        hidden.scope = Scope::new("source.hidden").unwrap();
+        hidden.hidden = true;
+        let built = {
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(hidden);
+            builder.add(syntax_b());
+            builder.add(syntax_a());
+            builder.build()
+        };
+        let parses_b = |ss: &SyntaxSet| {
+            let syntax = ss.find_syntax_by_extension("a").unwrap();
+            ParseState::new(syntax)
+                .parse_line("x go_b b", ss)
+                .map(|ops| ops.contains(&(7, ScopeStackOp::Push(Scope::new("b").unwrap()))))
+        };
+
+        // B moves to where the hidden syntax was
+        let mut builder = built.clone().into_builder();
+        builder.retain(|syntax| !syntax.hidden);
+        let ss = builder.build();
+        let names: Vec<&str> = ss.syntaxes().iter().map(|s| &*s.name).collect();
+        assert_eq!(names, ["B", "A"]);
+        assert!(parses_b(&ss).unwrap());
+
+        // without B the push is unresolved, until a syntax with its scope is added
+        let mut builder = built.into_builder();
+        builder.retain(|syntax| syntax.name == "A");
+        let mut with_new_b = builder.clone();
+        let ss = builder.build();
+        assert!(matches!(
+            parses_b(&ss),
+            Err(ParsingError::UnresolvedContextReference(_))
+        ));
+        with_new_b.add(syntax_b());
+        assert!(parses_b(&with_new_b.build()).unwrap());
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn can_skip_hidden_syntaxes_of_folders() {
+        let dir = TempDir::new("hidden");
+        for (file, hidden) in [("Shown", false), ("Helper", true)] {
+            let contents = format!(
+                "name: {}\nscope: source.{}\nhidden: {}\ncontexts:\n  main: []\n",
+                file,
+                file.to_lowercase(),
+                hidden
+            );
+            dir.write(format!("{}.sublime-syntax", file), contents);
+        }
+        let names = |skip_hidden| {
+            let mut builder = SyntaxSetBuilder::new();
+            let options = LoadOptions {
+                skip_hidden,
+                ..LoadOptions::default()
+            };
+            builder
+                .add_from_folder_with_options(dir.path(), true, &options)
+                .unwrap();
+            let mut names: Vec<String> =
+                builder.syntaxes().iter().map(|s| s.name.clone()).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(names(false), ["Helper", "Shown"]);
+        assert_eq!(names(true), ["Shown"]);
+    }
+
+    #[test]
+    fn extension_conflicts_are_resolved_by_priority() {
+        let with_extension = |mut syntax: SyntaxDefinition| {
+            syntax.file_extensions = vec!["ab".to_owned()];
+            syntax
+        };
+        let names = |ss: &SyntaxSet| -> Vec<String> {
+            ss.find_syntaxes_by_extension("AB")
+                .iter()
+                .map(|s| s.name.clone())
+                .collect()
+        };
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(with_extension(syntax_a()));
+        builder.add(with_extension(syntax_b()));
+        let ss = builder.build();
+        assert_eq!(ss.find_syntax_by_extension("ab").unwrap().name, "B");
+        assert_eq!(names(&ss), ["B", "A"]);
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add_with_priority(with_extension(syntax_a()), 1);
+        builder.add(with_extension(syntax_b()));
+        let ss = builder.build();
+        assert_eq!(ss.find_syntax_by_extension("ab").unwrap().name, "A");
+        assert_eq!(ss.find_syntax_by_token("ab").unwrap().name, "A");
+        assert_eq!(names(&ss), ["A", "B"]);
+
+        // the priority is kept when adding more syntaxes to a built set
+        let mut builder = ss.into_builder();
+        builder.add(with_extension(syntax_a()));
+        let ss = builder.build();
+        assert_eq!(ss.syntaxes()[0].priority, 1);
+        assert_eq!(ss.find_syntax_by_extension("ab").unwrap().priority, 1);
+        assert_eq!(names(&ss), ["A", "A", "B"]);
+
+        // and when some of the syntaxes are removed
+        let mut builder = ss.into_builder();
+        builder.retain(|syntax| syntax.name != "B");
+        let ss = builder.build();
+        assert_eq!(
+            ss.syntaxes().iter().map(|s| s.priority).collect::<Vec<_>>(),
+            [1, 0]
+        );
+    }
+
+    #[test]
+    fn can_find_all_syntaxes_for_token() {
+        let mut named_b = syntax_a();
+        named_b.name = "b".to_owned();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax_a());
+        builder.add(syntax_b());
+        builder.add(named_b);
+        let ss = builder.build();
+        let names = |token: &str| -> Vec<String> {
+            ss.find_syntaxes_by_token(token)
+                .iter()
+                .map(|s| s.name.clone())
+                .collect()
+        };
+        // `B` claims the extension, then `b` is the exact name
+        assert_eq!(names("b"), ["B", "b"]);
+        assert_eq!(names("B"), ["B", "b"]);
+        assert_eq!(names("a"), ["b", "A"]);
+        assert!(names("c").is_empty());
+        assert_eq!(ss.find_syntax_by_token("a").unwrap().name, "b");
+    }
+
+    #[test]
+    fn can_merge_syntax_sets() {
+        // A pushes B, which is only in the other set, D doesn't refer to anything
+        let syntax_d = SyntaxDefinition::load_from_str(
+            r#"
+        name: D
+        scope: source.d
+        file_extensions: [d]
+        contexts:
+          main:
+            - match: 'd'
+              scope: d
+        "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let first = {
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(syntax_a());
+            builder.add(syntax_d);
+            builder.build()
+        };
+        let serialized_d = first.syntaxes[1].serialized_lazy_contexts.clone();
+        let second = {
+            let mut builder = SyntaxSetBuilder::new();
+            builder.add(syntax_b());
+            builder.build()
+        };
+
+        let syntax_set = first.merge(second);
+        let names: Vec<&str> = syntax_set.syntaxes().iter().map(|s| &*s.name).collect();
+        assert_eq!(names, ["A", "D", "B"]);
+        assert_eq!(
+            syntax_set.syntaxes[1].serialized_lazy_contexts,
+            serialized_d
+        );
+
+        let syntax = syntax_set.find_syntax_by_extension("a").unwrap();
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("a go_b b", &syntax_set)
+            .expect("#[cfg(test)]");
+        let expected = (7, ScopeStackOp::Push(Scope::new("b").unwrap()));
+        assert_ops_contain(&ops, &expected);
+
+        let syntax = syntax_set.find_syntax_by_extension("b").unwrap();
+        let mut parse_state = ParseState::new(syntax);
+        let ops = parse_state
+            .parse_line("b", &syntax_set)
+            .expect("#[cfg(test)]");
+        let expected = (0, ScopeStackOp::Push(Scope::new("b").unwrap()));
+        assert_ops_contain(&ops, &expected);
+    }
+
     #[test]
     fn falls_back_to_plain_text_when_embedded_scope_is_missing() {
         test_plain_text_fallback(
@@ -1195,6 +2438,78 @@ mod tests {
         assert_eq!(unlinked_contexts[0], "Syntax 'A' with scope 'source.a' has unresolved context reference ByScope { scope: <source.b>, sub_context: Some(\"main\"), with_escape: false }");
     }
 
+    #[test]
+    fn can_use_scope_repo_snapshot() {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax_a());
+        let syntax_set = builder.build();
+
+        let scope = syntax_set.syntaxes()[0].scope;
+        assert!(syntax_set.scope_repo().contains(scope));
+        assert_eq!(syntax_set.scope_to_string(scope), "source.a");
+        assert_eq!(syntax_set.scope("source.a").unwrap(), scope);
+
+        // Atoms created after the snapshot fall back to the global repo
+        let newer = syntax_set.scope("source.a.snapshot-fallback").unwrap();
+        assert!(!syntax_set.scope_repo().contains(newer));
+        assert_eq!(
+            syntax_set.scope_to_string(newer),
+            "source.a.snapshot-fallback"
+        );
+    }
+
+    #[test]
+    fn breaks_include_cycles() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r#"
+            name: Cycle
+            scope: source.cycle
+            contexts:
+              main:
+                - include: a
+              a:
+                - match: 'a'
+                  scope: a
+                - include: b
+              b:
+                - include: a
+                - match: 'b'
+                  scope: b
+            "#,
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let (syntax_set, lints) = builder.build_with_lints();
+
+        let context = |name: &str| ("Cycle".to_owned(), name.to_owned());
+        assert_eq!(
+            lints,
+            vec![LintWarning::IncludeCycle {
+                contexts: vec![context("a"), context("b"), context("a")]
+            }]
+        );
+
+        let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+        let ops = state.parse_line("ab\n", &syntax_set).expect("#[cfg(test)]");
+        assert_ops_contain(&ops, &(1, ScopeStackOp::Push(Scope::new("b").unwrap())));
+    }
+
+    #[test]
+    fn reports_contexts_exceeding_expansion_limit() {
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax_a());
+        builder.set_expansion_limit(1);
+        let (_, lints) = builder.build_with_lints();
+        assert!(lints.contains(&LintWarning::TooManyPatterns {
+            syntax: "A".to_owned(),
+            context: "main".to_owned(),
+            count: 2,
+        }));
+    }
+
     #[test]
     fn can_use_in_multiple_threads() {
         use rayon::prelude::*;
@@ -1334,6 +2649,190 @@ mod tests {
         assert_ops_contain(&ops, &expected);
     }
 
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn can_load_extended_syntaxes_from_folder() {
+        let dir = TempDir::new("extends");
+        let files = [
+            (
+                "Base/Base.sublime-syntax",
+                "name: Base\nscope: source.base\ncontexts:\n  prototype:\n    - match: '#'\n      scope: comment.base\n  main:\n    - match: a\n      scope: keyword.base\n",
+            ),
+            (
+                "Derived/Derived.sublime-syntax",
+                "name: Derived\nscope: source.derived\nextends: Packages/Base/Base.sublime-syntax\ncontexts:\n  main:\n    - meta_prepend: true\n    - match: b\n      scope: keyword.derived\n",
+            ),
+            (
+                "Host.sublime-syntax",
+                "name: Host\nscope: source.host\ncontexts:\n  main:\n    - include: scope:source.base\n      apply_prototype: true\n",
+            ),
+            (
+                "Guest.sublime-syntax",
+                "name: Guest\nscope: source.guest\ncontexts:\n  main:\n    - include: scope:source.host\n      apply_prototype: true\n",
+            ),
+        ];
+        for (path, contents) in &files {
+            dir.write(path, contents);
+        }
+        let mut builder = SyntaxSetBuilder::new();
+        builder
+            .add_from_folder_with_options(dir.path(), true, &LoadOptions::strict())
+            .unwrap();
+        let ss = builder.build();
+
+        let scopes = |name: &str, line: &str| -> Vec<String> {
+            let syntax = ss.find_syntax_by_name(name).unwrap();
+            ParseState::new(syntax)
+                .parse_line(line, &ss)
+                .unwrap()
+                .into_iter()
+                .filter_map(|(_, op)| match op {
+                    ScopeStackOp::Push(scope) => Some(scope.build_string()),
+                    _ => None,
+                })
+                .collect()
+        };
+        // Derived has its own pattern first, then the ones and the prototype of Base
+        assert_eq!(
+            scopes("Derived", "ba#\n"),
+            [
+                "source.derived",
+                "keyword.derived",
+                "keyword.base",
+                "comment.base"
+            ]
+        );
+        // Base's prototype applies to its patterns included in Host
+        assert_eq!(
+            scopes("Host", "a#\n"),
+            ["source.host", "keyword.base", "comment.base"]
+        );
+        // Host has no prototype, which isn't an unresolved reference
+        assert!(ss.find_unlinked_contexts().is_empty());
+        assert_eq!(scopes("Guest", "a\n"), ["source.guest", "keyword.base"]);
+    }
+
+    #[test]
+    fn links_applied_prototypes_once_their_syntaxes_are_added() {
+        let load = |s: &str| SyntaxDefinition::load_from_str(s, true, None).unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(load(
+            "name: Host\nscope: source.host\ncontexts:\n  main:\n    - include: scope:source.base\n      apply_prototype: true\n    - include: scope:source.other#prototype\n    - include: scope:source.other\n      apply_prototype: true\n",
+        ));
+        let host = builder.build();
+        let main_patterns = |ss: &SyntaxSet| {
+            let syntax = ss.find_syntax_by_name("Host").unwrap();
+            syntax.contexts()[syntax.context_ids()["main"].context_index]
+                .patterns
+                .clone()
+        };
+        // Neither syntax is there yet, so the prototypes are kept to be linked later
+        assert_eq!(main_patterns(&host).len(), 5);
+
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(load(
+            "name: Base\nscope: source.base\ncontexts:\n  prototype:\n    - match: '#'\n      scope: comment.base\n  main:\n    - match: a\n      scope: keyword.base\n",
+        ));
+        builder.add(load(
+            "name: Other\nscope: source.other\ncontexts:\n  main: []\n",
+        ));
+        let ss = host.merge(builder.build());
+        // The prototype Other doesn't have is only left out where `apply_prototype` asked for it
+        assert!(matches!(
+            main_patterns(&ss)[..],
+            [
+                Pattern::Include(ContextReference::Direct(_)),
+                Pattern::Include(ContextReference::Direct(_)),
+                Pattern::Include(ContextReference::ByScope { .. }),
+                Pattern::Include(ContextReference::Direct(_)),
+            ]
+        ));
+    }
+
+    #[cfg(feature = "yaml-load")]
+    #[test]
+    fn resolves_base_syntaxes_in_other_folders_when_building() {
+        let dir = TempDir::new("bases");
+        let files = [
+            (
+                "user/TS/TS.sublime-syntax",
+                "name: TS\nscope: source.ts\nextends: Packages/JS/JS.sublime-syntax\ncontexts:\n  main:\n    - meta_append: true\n    - match: ':'\n      scope: punctuation.ts\n",
+            ),
+            (
+                "user/Orphan.sublime-syntax",
+                "name: Orphan\nscope: source.orphan\nextends: Packages/Missing/Missing.sublime-syntax\ncontexts: {}\n",
+            ),
+            (
+                "default/JS/JS.sublime-syntax",
+                "name: JS\nscope: source.js\nfile_extensions: [js]\ncontexts:\n  main:\n    - match: let\n      scope: keyword.js\n",
+            ),
+        ];
+        for (path, contents) in &files {
+            dir.write(path, contents);
+        }
+        let mut builder = SyntaxSetBuilder::new();
+        // The syntax extending JS is added before JS itself
+        builder
+            .add_from_folder(dir.path().join("user"), true)
+            .unwrap();
+        assert!(builder.syntaxes().is_empty());
+        builder
+            .add_from_folder(dir.path().join("default"), true)
+            .unwrap();
+        // Orphan's base is in none of the folders, which try_build reports like it does for other
+        // invalid syntax files
+        assert!(matches!(
+            builder.clone().try_build(),
+            Err(LoadingError::ParseSyntax(ParseSyntaxError::MissingBaseSyntax(ref base), ref path))
+                if base == "Packages/Missing/Missing.sublime-syntax"
+                    && path.ends_with("Orphan.sublime-syntax")
+        ));
+        // while build leaves it out
+        let (ss, lints) = builder.build_with_lints();
+        assert!(ss.find_syntax_by_name("Orphan").is_none());
+        assert!(matches!(
+            &lints[..],
+            [LintWarning::SkippedFile { path, .. }] if path.ends_with("Orphan.sublime-syntax")
+        ));
+
+        // unless the files can be skipped
+        let mut builder = SyntaxSetBuilder::new();
+        let options = LoadOptions::default();
+        builder
+            .add_from_folder_with_options(dir.path().join("user"), true, &options)
+            .unwrap();
+        builder
+            .add_from_folder_with_options(dir.path().join("default"), true, &options)
+            .unwrap();
+        let (ss, lints) = builder.build_with_lints();
+
+        let names: Vec<&str> = ss.syntaxes().iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["JS", "TS"]);
+        assert!(ss
+            .find_syntax_by_path(&format!(
+                "{}/user/TS/TS.sublime-syntax",
+                dir.path().display()
+            ))
+            .is_some());
+        let syntax = ss.find_syntax_by_name("TS").unwrap();
+        let ops = ParseState::new(syntax).parse_line("let:\n", &ss).unwrap();
+        let scopes: Vec<String> = ops
+            .into_iter()
+            .filter_map(|(_, op)| match op {
+                ScopeStackOp::Push(scope) => Some(scope.build_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(scopes, ["source.ts", "keyword.js", "punctuation.ts"]);
+
+        assert_eq!(lints.len(), 1);
+        assert!(matches!(
+            &lints[0],
+            LintWarning::SkippedFile { path, error }
+                if path.ends_with("Orphan.sublime-syntax") && error.contains("Missing.sublime-syntax")
+        ));
+    }
+
     #[test]
     fn no_prototype_for_contexts_included_from_prototype() {
         let mut builder = SyntaxSetBuilder::new();
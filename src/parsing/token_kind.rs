@@ -0,0 +1,188 @@
+use super::scope::Scope;
+#[cfg(feature = "global-statics")]
+use once_cell::sync::Lazy;
+
+/// A coarse category of a token, for consumers that don't want to deal with scopes
+///
+/// This is lossy: it's based on the usual naming conventions of Sublime Text syntaxes, which
+/// different syntaxes follow to different degrees, and themes can style tokens of the same kind
+/// quite differently. It's meant for things like terminal UIs with a small palette.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ScopeStack, TokenKind};
+/// use std::str::FromStr;
+///
+/// let stack = ScopeStack::from_str("source.rust string.quoted.double.rust").unwrap();
+/// assert_eq!(TokenKind::from_scope_stack(stack.as_slice()), TokenKind::String);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum TokenKind {
+    /// Keywords like `if`, and storage keywords like `fn` or `static`
+    Keyword,
+    /// Operators like `+` or `&&`, including keyword operators like `and`
+    Operator,
+    String,
+    /// An escape sequence in a string, like `\n`
+    Escape,
+    Number,
+    /// Other constants, like `true` or `null` and named constants
+    Constant,
+    Comment,
+    /// Names of functions and macros, both where they're defined and called
+    Function,
+    /// Names of types, classes and the like
+    Type,
+    Variable,
+    Punctuation,
+    /// Markup tags, like `div` in `<div>`
+    Tag,
+    /// Markup attributes, like `class` in `<div class="x">`
+    Attribute,
+    /// Markup headings
+    Heading,
+    /// Code marked as invalid by the syntax
+    Invalid,
+    /// Anything else, e.g. plain text
+    #[default]
+    Other,
+}
+
+/// How the innermost scope matching one of the prefixes is categorized, more specific prefixes
+/// first. `None` means the token gets the kind of an enclosing scope if there is one, like the
+/// quotes of a string.
+fn kind_prefixes() -> Vec<(Scope, Option<TokenKind>)> {
+    use TokenKind::*;
+    [
+        ("comment", Some(Comment)),
+        ("string", Some(String)),
+        ("constant.character.escape", Some(Escape)),
+        ("constant.numeric", Some(Number)),
+        ("constant", Some(Constant)),
+        ("support.constant", Some(Constant)),
+        ("entity.name.constant", Some(Constant)),
+        ("keyword.operator", Some(Operator)),
+        ("keyword", Some(Keyword)),
+        ("storage", Some(Keyword)),
+        ("entity.name.function", Some(Function)),
+        ("support.function", Some(Function)),
+        ("variable.function", Some(Function)),
+        ("support.macro", Some(Function)),
+        ("entity.name.tag", Some(Tag)),
+        ("entity.other.attribute-name", Some(Attribute)),
+        ("entity.name.section", Some(Heading)),
+        ("markup.heading", Some(Heading)),
+        ("entity.name", Some(Type)),
+        ("entity.other.inherited-class", Some(Type)),
+        ("support.type", Some(Type)),
+        ("support.class", Some(Type)),
+        ("variable", Some(Variable)),
+        ("punctuation.definition", None),
+        ("punctuation", Some(Punctuation)),
+        ("invalid", Some(Invalid)),
+    ]
+    .iter()
+    .map(|&(prefix, kind)| (Scope::new(prefix).unwrap(), kind))
+    .collect()
+}
+
+/// The prefixes of `kind_prefixes`, worked out once
+#[cfg(feature = "global-statics")]
+static KIND_PREFIXES: Lazy<Vec<(Scope, Option<TokenKind>)>> = Lazy::new(kind_prefixes);
+
+impl TokenKind {
+    /// Categorizes a token by the stack of scopes it's in, see [`ScopeStack::as_slice`]
+    ///
+    /// The innermost scope with a known kind decides, except that punctuation delimiting
+    /// something, like the quotes of a string, gets the kind of what it delimits.
+    ///
+    /// [`ScopeStack::as_slice`]: struct.ScopeStack.html#method.as_slice
+    pub fn from_scope_stack(stack: &[Scope]) -> TokenKind {
+        #[cfg(feature = "global-statics")]
+        let prefixes = &*KIND_PREFIXES;
+        #[cfg(not(feature = "global-statics"))]
+        let prefixes = &kind_prefixes();
+        let mut delimiter = false;
+        for &scope in stack.iter().rev() {
+            let kind = prefixes
+                .iter()
+                .find(|(prefix, _)| prefix.is_prefix_of(scope));
+            match kind {
+                Some(&(_, Some(kind))) => return kind,
+                Some(&(_, None)) => delimiter = true,
+                None => {}
+            }
+        }
+        if delimiter {
+            TokenKind::Punctuation
+        } else {
+            TokenKind::Other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::ScopeStack;
+    use std::str::FromStr;
+
+    fn kind(stack: &str) -> TokenKind {
+        TokenKind::from_scope_stack(ScopeStack::from_str(stack).unwrap().as_slice())
+    }
+
+    #[test]
+    fn classifies_common_scopes() {
+        assert_eq!(kind("source.rust"), TokenKind::Other);
+        assert_eq!(kind("source.rust meta.function.rust"), TokenKind::Other);
+        assert_eq!(
+            kind("source.c comment.line.double-slash.c"),
+            TokenKind::Comment
+        );
+        assert_eq!(
+            kind("source.rust keyword.operator.rust"),
+            TokenKind::Operator
+        );
+        assert_eq!(
+            kind("source.rust storage.type.function.rust"),
+            TokenKind::Keyword
+        );
+        assert_eq!(
+            kind("source.rust meta.function.rust entity.name.function.rust"),
+            TokenKind::Function
+        );
+        assert_eq!(kind("source.rust entity.name.struct.rust"), TokenKind::Type);
+        assert_eq!(
+            kind("source.js constant.numeric.integer.js"),
+            TokenKind::Number
+        );
+        assert_eq!(kind("source.js constant.language.js"), TokenKind::Constant);
+        assert_eq!(
+            kind("source.js string.quoted.double.js constant.character.escape.js"),
+            TokenKind::Escape
+        );
+        assert_eq!(kind("text.html.basic entity.name.tag.html"), TokenKind::Tag);
+    }
+
+    #[test]
+    fn delimiters_take_the_kind_of_what_they_delimit() {
+        assert_eq!(
+            kind("source.rust string.quoted.double.rust punctuation.definition.string.begin.rust"),
+            TokenKind::String
+        );
+        assert_eq!(
+            kind("source.rust comment.block.rust punctuation.definition.comment.rust"),
+            TokenKind::Comment
+        );
+        assert_eq!(
+            kind("source.rust meta.group.rust punctuation.definition.group.begin.rust"),
+            TokenKind::Punctuation
+        );
+        assert_eq!(
+            kind("source.rust punctuation.terminator.rust"),
+            TokenKind::Punctuation
+        );
+    }
+}
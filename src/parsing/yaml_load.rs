@@ -5,6 +5,7 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::ops::DerefMut;
 use std::path::Path;
+use std::sync::Arc;
 use yaml_rust::yaml::Hash;
 use yaml_rust::{ScanError, Yaml, YamlLoader};
 
@@ -37,6 +38,257 @@ pub enum ParseSyntaxError {
     /// Maybe use Sublime Text to figure it out.
     #[error("Type mismatch")]
     TypeMismatch,
+    /// A key that syntect doesn't know about, only reported with [`UnknownKeyPolicy::Error`]
+    ///
+    /// [`UnknownKeyPolicy::Error`]: enum.UnknownKeyPolicy.html#variant.Error
+    #[error("Unknown key in YAML file: {0}")]
+    UnknownKey(String),
+    /// A `captures` entry that isn't a capture group index mapped to a scope, only reported when
+    /// loading strictly
+    #[error("Invalid capture in context '{0}': {1}")]
+    InvalidCapture(String, String),
+    /// A reference to a context that doesn't exist in the same file, only reported when loading
+    /// strictly
+    #[error("Context '{0}' references missing context '{1}'")]
+    MissingContext(String, String),
+    /// A syntax `extends` a base syntax that couldn't be found
+    #[error("Base syntax not found: {0}")]
+    MissingBaseSyntax(String),
+    /// A syntax `extends` itself, directly or through its base syntaxes
+    #[error("Syntax extends itself through {0}")]
+    ExtendsCycle(String),
+    /// A [`SyntaxDefinitionBuilder`] was given a [`ContextReference::Direct`], which only exists
+    /// in a built syntax set
+    ///
+    /// [`SyntaxDefinitionBuilder`]: struct.SyntaxDefinitionBuilder.html
+    /// [`ContextReference::Direct`]: syntax_definition/enum.ContextReference.html#variant.Direct
+    #[error("Context IDs can't be referenced from a syntax definition")]
+    DirectReference,
+}
+
+/// What to do with keys in a `.sublime-syntax` file that syntect doesn't know about.
+///
+/// Sublime Text ignores them, which is also the default here, but grammar developers usually want
+/// to know about typos like `meta_scop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownKeyPolicy {
+    /// Silently ignore unknown keys
+    #[default]
+    Ignore,
+    /// Report unknown keys to the [`LoadOptions::on_warning`] callback
+    ///
+    /// [`LoadOptions::on_warning`]: struct.LoadOptions.html#structfield.on_warning
+    Warn,
+    /// Fail loading with [`ParseSyntaxError::UnknownKey`]
+    ///
+    /// [`ParseSyntaxError::UnknownKey`]: enum.ParseSyntaxError.html#variant.UnknownKey
+    Error,
+}
+
+/// A problem found while loading syntaxes that wasn't severe enough to fail loading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LoadWarning {
+    /// A key syntect doesn't know about, found in the given context (or at the top level if the
+    /// context is `None`)
+    UnknownKey {
+        context: Option<String>,
+        key: String,
+    },
+    /// A `captures` entry that isn't a capture group index mapped to a scope
+    InvalidCapture { context: String, capture: String },
+    /// A reference to a context that doesn't exist in the same file
+    MissingContext { context: String, name: String },
+    /// A syntax file that failed to load and was skipped
+    SkippedFile { path: String, error: String },
+}
+
+impl std::fmt::Display for LoadWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadWarning::UnknownKey {
+                context: Some(context),
+                key,
+            } => write!(f, "Unknown key '{}' in context '{}'", key, context),
+            LoadWarning::UnknownKey { context: None, key } => {
+                write!(f, "Unknown top level key '{}'", key)
+            }
+            LoadWarning::InvalidCapture { context, capture } => {
+                write!(f, "Invalid capture in context '{}': {}", context, capture)
+            }
+            LoadWarning::MissingContext { context, name } => write!(
+                f,
+                "Context '{}' references missing context '{}'",
+                context, name
+            ),
+            LoadWarning::SkippedFile { path, error } => {
+                write!(f, "Skipped syntax file {}: {}", path, error)
+            }
+        }
+    }
+}
+
+/// Options for how strictly syntax definitions are validated while loading.
+///
+/// The default options match the behaviour of [`SyntaxDefinition::load_from_str`]: anything
+/// syntect can't make sense of is silently ignored.
+///
+/// When `strict` is set, invalid captures and references to missing contexts are errors. When
+/// it's not, they are reported to `on_warning`, and [`SyntaxSetBuilder::add_from_folder_with_options`]
+/// skips files that fail to load instead of failing altogether.
+///
+/// [`SyntaxDefinition::load_from_str`]: syntax_definition/struct.SyntaxDefinition.html#method.load_from_str
+/// [`SyntaxSetBuilder::add_from_folder_with_options`]: struct.SyntaxSetBuilder.html#method.add_from_folder_with_options
+#[derive(Clone, Default)]
+pub struct LoadOptions {
+    /// Turn invalid captures and missing contexts into errors
+    pub strict: bool,
+    /// What to do with unknown keys
+    pub unknown_key_policy: UnknownKeyPolicy,
+    /// Called for every problem that doesn't fail loading
+    pub on_warning: Option<LoadWarningCallback>,
+    /// The priority the syntaxes of a folder are added with, e.g. to prefer the syntaxes of a
+    /// folder with user overrides over others claiming the same file extensions, see
+    /// [`SyntaxSetBuilder::add_with_priority`]
+    ///
+    /// [`SyntaxSetBuilder::add_with_priority`]: struct.SyntaxSetBuilder.html#method.add_with_priority
+    pub priority: i32,
+    /// Leave out the syntaxes marked `hidden` when loading a folder, like the helper syntaxes
+    /// of the Packages folder that are only meant to be embedded in others
+    pub skip_hidden: bool,
+    /// Keep the `captures` of groups inside lookaheads, like Sublime Text does, instead of
+    /// ignoring them
+    ///
+    /// Sublime Text scopes the part of such a group that lies within the text the pattern
+    /// matched, so e.g. a `(?=(\[[^]]*\]):)` at the start of a pattern can scope the label of a
+    /// Markdown link definition. Captures inside lookbehinds are always ignored.
+    pub lookahead_captures: bool,
+}
+
+/// A callback receiving the [`LoadWarning`]s produced while loading.
+///
+/// [`LoadWarning`]: enum.LoadWarning.html
+pub type LoadWarningCallback = Arc<dyn Fn(&LoadWarning) + Send + Sync>;
+
+impl LoadOptions {
+    /// Options for grammar development: everything suspicious is an error.
+    pub fn strict() -> LoadOptions {
+        LoadOptions {
+            strict: true,
+            unknown_key_policy: UnknownKeyPolicy::Error,
+            on_warning: None,
+            priority: 0,
+            skip_hidden: false,
+            lookahead_captures: false,
+        }
+    }
+
+    /// Options that skip over problems, reporting each of them to `on_warning`.
+    pub fn lenient<F>(on_warning: F) -> LoadOptions
+    where
+        F: Fn(&LoadWarning) + Send + Sync + 'static,
+    {
+        LoadOptions {
+            strict: false,
+            unknown_key_policy: UnknownKeyPolicy::Warn,
+            on_warning: Some(Arc::new(on_warning)),
+            priority: 0,
+            skip_hidden: false,
+            lookahead_captures: false,
+        }
+    }
+
+    pub(crate) fn warn(&self, warning: LoadWarning) {
+        if let Some(ref on_warning) = self.on_warning {
+            on_warning(&warning);
+        }
+    }
+}
+
+impl std::fmt::Debug for LoadOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoadOptions")
+            .field("strict", &self.strict)
+            .field("unknown_key_policy", &self.unknown_key_policy)
+            .field(
+                "on_warning",
+                &self.on_warning.as_ref().map(|_| "<callback>"),
+            )
+            .field("priority", &self.priority)
+            .field("skip_hidden", &self.skip_hidden)
+            .field("lookahead_captures", &self.lookahead_captures)
+            .finish()
+    }
+}
+
+static TOP_LEVEL_KEYS: &[&str] = &[
+    "name",
+    "scope",
+    "file_extensions",
+    "hidden_file_extensions",
+    "first_line_match",
+    "hidden",
+    "variables",
+    "contexts",
+    "version",
+];
+
+static CONTEXT_KEYS: &[&str] = &[
+    "meta_scope",
+    "meta_content_scope",
+    "meta_include_prototype",
+    "meta_prepend",
+    "meta_append",
+    "clear_scopes",
+    "include",
+    "apply_prototype",
+    "match",
+    "scope",
+    "captures",
+    "pop",
+    "push",
+    "set",
+    "embed",
+    "embed_scope",
+    "escape",
+    "escape_captures",
+    "with_prototype",
+];
+
+/// Keys of the context entries that set up the context instead of adding a pattern to it
+static META_KEYS: &[&str] = &[
+    "meta_scope",
+    "meta_content_scope",
+    "meta_include_prototype",
+    "meta_prepend",
+    "meta_append",
+    "clear_scopes",
+];
+
+fn check_keys(
+    map: &Hash,
+    known: &[&str],
+    context: Option<&str>,
+    options: &LoadOptions,
+) -> Result<(), ParseSyntaxError> {
+    if options.unknown_key_policy == UnknownKeyPolicy::Ignore {
+        return Ok(());
+    }
+    for key in map.keys() {
+        let key = match key.as_str() {
+            Some(key) if known.contains(&key) => continue,
+            Some(key) => key.to_owned(),
+            None => format!("{:?}", key),
+        };
+        if options.unknown_key_policy == UnknownKeyPolicy::Error {
+            return Err(ParseSyntaxError::UnknownKey(key));
+        }
+        options.warn(LoadWarning::UnknownKey {
+            context: context.map(str::to_owned),
+            key,
+        });
+    }
+    Ok(())
 }
 
 fn get_key<'a, R, F: FnOnce(&'a Yaml) -> Option<R>>(
@@ -55,12 +307,147 @@ fn str_to_scopes(s: &str, repo: &mut ScopeRepository) -> Result<Vec<Scope>, Pars
         .collect()
 }
 
+fn load_first_doc(s: &str) -> Result<Yaml, ParseSyntaxError> {
+    YamlLoader::load_from_str(s)?
+        .into_iter()
+        .next()
+        .ok_or(ParseSyntaxError::EmptyFile)
+}
+
+/// Merges the bases a syntax `extends` into it, returning `None` if it doesn't extend any.
+///
+/// `chain` holds the paths of the bases being extended, to detect cycles.
+fn extend_bases<F>(
+    doc: &Yaml,
+    find_base: &mut F,
+    chain: &mut Vec<String>,
+) -> Result<Option<Yaml>, ParseSyntaxError>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let h = doc.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+    let paths: Vec<&str> = match h.get(&Yaml::String("extends".to_owned())) {
+        None => return Ok(None),
+        Some(Yaml::String(path)) => vec![path],
+        Some(Yaml::Array(paths)) => paths
+            .iter()
+            .map(|path| path.as_str().ok_or(ParseSyntaxError::TypeMismatch))
+            .collect::<Result<_, _>>()?,
+        Some(_) => return Err(ParseSyntaxError::TypeMismatch),
+    };
+
+    let mut merged: Option<Hash> = None;
+    for path in paths {
+        if chain.iter().any(|p| p == path) {
+            return Err(ParseSyntaxError::ExtendsCycle(path.to_owned()));
+        }
+        let source =
+            find_base(path).ok_or_else(|| ParseSyntaxError::MissingBaseSyntax(path.to_owned()))?;
+        let base_doc = load_first_doc(&source)?;
+        chain.push(path.to_owned());
+        let base_doc = extend_bases(&base_doc, find_base, chain)?.unwrap_or(base_doc);
+        chain.pop();
+        let base = base_doc.into_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+        merged = Some(match merged {
+            Some(merged) => merge_syntax(merged, &base),
+            None => base,
+        });
+    }
+
+    let mut extended = merge_syntax(merged.unwrap_or_default(), h);
+    extended.remove(&Yaml::String("extends".to_owned()));
+    Ok(Some(Yaml::Hash(extended)))
+}
+
+/// Merges the variables and contexts of `base` into `syntax`, which takes the other keys from
+/// `syntax`.
+fn merge_syntax(base: Hash, syntax: &Hash) -> Hash {
+    let mut merged = syntax.clone();
+    let mut base = base;
+    for key in &["variables", "contexts"] {
+        let key = Yaml::String((*key).to_owned());
+        let mut entries = match base.remove(&key) {
+            Some(Yaml::Hash(entries)) => entries,
+            _ => continue,
+        };
+        if let Some(Yaml::Hash(overrides)) = syntax.get(&key) {
+            for (name, value) in overrides {
+                let value = match (entries.remove(name), value) {
+                    (Some(Yaml::Array(base_context)), Yaml::Array(context)) => {
+                        Yaml::Array(extend_context(base_context, context))
+                    }
+                    _ => value.clone(),
+                };
+                entries.insert(name.clone(), value);
+            }
+        }
+        merged.insert(key, Yaml::Hash(entries));
+    }
+    merged
+}
+
+/// The entries of a context that overrides `base`, which it replaces unless it's marked with
+/// `meta_prepend` or `meta_append`.
+fn extend_context(base: Vec<Yaml>, context: &[Yaml]) -> Vec<Yaml> {
+    let is_set = |key: &'static str| {
+        context
+            .iter()
+            .filter_map(Yaml::as_hash)
+            .any(|map| matches!(get_key(map, key, Yaml::as_bool), Ok(true)))
+    };
+    let prepend = is_set("meta_prepend");
+    if !prepend && !is_set("meta_append") {
+        return context.to_vec();
+    }
+    let is_meta = |entry: &Yaml| {
+        entry.as_hash().is_some_and(|map| {
+            map.keys()
+                .any(|key| key.as_str().is_some_and(|key| META_KEYS.contains(&key)))
+        })
+    };
+    let (base_meta, base_patterns): (Vec<Yaml>, Vec<Yaml>) = base.into_iter().partition(is_meta);
+    let (meta, patterns): (Vec<&Yaml>, Vec<&Yaml>) = context.iter().partition(|e| is_meta(e));
+    // The meta entries of the context come last so they override those of the base
+    let mut entries = base_meta;
+    entries.extend(meta.into_iter().cloned());
+    if prepend {
+        entries.extend(patterns.into_iter().cloned());
+        entries.extend(base_patterns);
+    } else {
+        entries.extend(base_patterns);
+        entries.extend(patterns.into_iter().cloned());
+    }
+    entries
+}
+
+/// The prototype of the syntax a reference to another syntax points at, for `apply_prototype`
+fn prototype_of(reference: &ContextReference) -> Option<ContextReference> {
+    let sub_context = Some("prototype".to_owned());
+    let prototype = match reference {
+        ContextReference::ByScope { scope, .. } => ContextReference::ByScope {
+            scope: *scope,
+            sub_context,
+            with_escape: false,
+        },
+        ContextReference::File { name, .. } => ContextReference::File {
+            name: name.clone(),
+            sub_context,
+            with_escape: false,
+        },
+        _ => return None,
+    };
+    Some(ContextReference::Prototype(Box::new(prototype)))
+}
+
 struct ParserState<'a> {
     scope_repo: &'a mut ScopeRepository,
     variables: HashMap<String, String>,
     variable_regex: Regex,
     backref_regex: Regex,
     lines_include_newline: bool,
+    options: &'a LoadOptions,
+    /// Names of the contexts currently being parsed, innermost last
+    context_names: Vec<String>,
 }
 
 // `__start` must not include prototypes from the actual syntax definition,
@@ -87,20 +474,89 @@ impl SyntaxDefinition {
         lines_include_newline: bool,
         fallback_name: Option<&str>,
     ) -> Result<SyntaxDefinition, ParseSyntaxError> {
-        let docs = match YamlLoader::load_from_str(s) {
-            Ok(x) => x,
-            Err(e) => return Err(ParseSyntaxError::InvalidYaml(e)),
-        };
-        if docs.is_empty() {
-            return Err(ParseSyntaxError::EmptyFile);
-        }
-        let doc = &docs[0];
+        SyntaxDefinition::load_from_str_with_options(
+            s,
+            lines_include_newline,
+            fallback_name,
+            &LoadOptions::default(),
+        )
+    }
+
+    /// Same as [`load_from_str`] but with control over how strictly the syntax is validated.
+    ///
+    /// See [`LoadOptions`] for what can be configured.
+    ///
+    /// A syntax that `extends` base syntaxes is loaded on its own, without what it inherits, and
+    /// its `extends` key is treated like any other key syntect doesn't know about. Use
+    /// [`load_from_str_with_bases`] to merge its bases into it.
+    ///
+    /// [`load_from_str`]: #method.load_from_str
+    /// [`LoadOptions`]: ../struct.LoadOptions.html
+    /// [`load_from_str_with_bases`]: #method.load_from_str_with_bases
+    pub fn load_from_str_with_options(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        options: &LoadOptions,
+    ) -> Result<SyntaxDefinition, ParseSyntaxError> {
+        let doc = load_first_doc(s)?;
         let mut scope_repo = SCOPE_REPO.lock().unwrap();
         SyntaxDefinition::parse_top_level(
-            doc,
+            &doc,
+            scope_repo.deref_mut(),
+            lines_include_newline,
+            fallback_name,
+            options,
+        )
+    }
+
+    /// Same as [`load_from_str_with_options`] for syntaxes that `extends` base syntaxes.
+    ///
+    /// `find_base` is called with each path given to `extends`, like
+    /// `Packages/JavaScript/JavaScript.sublime-syntax`, and returns the source of that syntax, or
+    /// `None` if there is no such syntax. The variables and contexts of the syntax are added to
+    /// those of its bases, replacing the ones with the same name, except for contexts with
+    /// `meta_prepend` or `meta_append`, whose patterns go before or after the ones of the base
+    /// context instead. The other keys, like `name` and `file_extensions`, aren't inherited.
+    ///
+    /// [`SyntaxSetBuilder::add_from_folder`] finds the bases among the files in the folder.
+    ///
+    /// [`load_from_str_with_options`]: #method.load_from_str_with_options
+    /// [`SyntaxSetBuilder::add_from_folder`]: ../struct.SyntaxSetBuilder.html#method.add_from_folder
+    pub fn load_from_str_with_bases<F>(
+        s: &str,
+        lines_include_newline: bool,
+        fallback_name: Option<&str>,
+        options: &LoadOptions,
+        mut find_base: F,
+    ) -> Result<SyntaxDefinition, ParseSyntaxError>
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let doc = load_first_doc(s)?;
+        let extended = extend_bases(&doc, &mut find_base, &mut Vec::new())?;
+        let mut scope_repo = SCOPE_REPO.lock().unwrap();
+        SyntaxDefinition::parse_top_level(
+            extended.as_ref().unwrap_or(&doc),
             scope_repo.deref_mut(),
             lines_include_newline,
             fallback_name,
+            options,
+        )
+    }
+
+    /// Loads a syntax from a YAML document that was built in code instead of parsed, strictly
+    pub(super) fn load_from_yaml(
+        doc: &Yaml,
+        lines_include_newline: bool,
+    ) -> Result<SyntaxDefinition, ParseSyntaxError> {
+        let mut scope_repo = SCOPE_REPO.lock().unwrap();
+        SyntaxDefinition::parse_top_level(
+            doc,
+            scope_repo.deref_mut(),
+            lines_include_newline,
+            None,
+            &LoadOptions::strict(),
         )
     }
 
@@ -109,8 +565,10 @@ impl SyntaxDefinition {
         scope_repo: &mut ScopeRepository,
         lines_include_newline: bool,
         fallback_name: Option<&str>,
+        options: &LoadOptions,
     ) -> Result<SyntaxDefinition, ParseSyntaxError> {
         let h = doc.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+        check_keys(h, TOP_LEVEL_KEYS, None, options)?;
 
         let mut variables = HashMap::new();
         if let Ok(map) = get_key(h, "variables", |x| x.as_hash()) {
@@ -130,12 +588,15 @@ impl SyntaxDefinition {
             variable_regex: Regex::new(r"\{\{([A-Za-z0-9_]+)\}\}".into()),
             backref_regex: Regex::new(r"\\\d".into()),
             lines_include_newline,
+            options,
+            context_names: Vec::new(),
         };
 
         let mut contexts = SyntaxDefinition::parse_contexts(contexts_hash, &mut state)?;
         if !contexts.contains_key("main") {
             return Err(ParseSyntaxError::MainMissing);
         }
+        SyntaxDefinition::check_missing_contexts(&contexts, options)?;
 
         SyntaxDefinition::add_initial_contexts(&mut contexts, &mut state, top_level_scope);
 
@@ -196,11 +657,15 @@ impl SyntaxDefinition {
     ) -> Result<String, ParseSyntaxError> {
         let mut context = Context::new(!is_prototype);
         let name = namer.next();
+        state.context_names.push(name.clone());
 
         for y in vec.iter() {
             let map = y.as_hash().ok_or(ParseSyntaxError::TypeMismatch)?;
+            check_keys(map, CONTEXT_KEYS, Some(&name), state.options)?;
 
-            let mut is_special = false;
+            // `meta_prepend` and `meta_append` were dealt with when extending the base syntax
+            let mut is_special = get_key(map, "meta_prepend", |x| x.as_bool()).is_ok()
+                || get_key(map, "meta_append", |x| x.as_bool()).is_ok();
             if let Ok(x) = get_key(map, "meta_scope", |x| x.as_str()) {
                 context.meta_scope = str_to_scopes(x, state.scope_repo)?;
                 is_special = true;
@@ -225,6 +690,11 @@ impl SyntaxDefinition {
                 if let Ok(x) = get_key(map, "include", Some) {
                     let reference =
                         SyntaxDefinition::parse_reference(x, state, contexts, namer, false)?;
+                    if let Ok(true) = get_key(map, "apply_prototype", |x| x.as_bool()) {
+                        if let Some(prototype) = prototype_of(&reference) {
+                            context.patterns.push(Pattern::Include(prototype));
+                        }
+                    }
                     context.patterns.push(Pattern::Include(reference));
                 } else {
                     let pattern =
@@ -237,10 +707,56 @@ impl SyntaxDefinition {
             }
         }
 
+        state.context_names.pop();
         contexts.insert(name.clone(), context);
         Ok(name)
     }
 
+    /// Check that named references only point at contexts that exist in this file.
+    fn check_missing_contexts(
+        contexts: &HashMap<String, Context>,
+        options: &LoadOptions,
+    ) -> Result<(), ParseSyntaxError> {
+        // Sort so that errors and warnings don't depend on HashMap iteration order
+        let mut names: Vec<&String> = contexts.keys().collect();
+        names.sort();
+        for context_name in names {
+            for pattern in &contexts[context_name].patterns {
+                let refs: Vec<&ContextReference> = match pattern {
+                    Pattern::Include(reference) => vec![reference],
+                    Pattern::Match(match_pat) => {
+                        let mut refs = match match_pat.operation {
+                            MatchOperation::Push(ref refs) | MatchOperation::Set(ref refs) => {
+                                refs.iter().collect()
+                            }
+                            MatchOperation::Pop | MatchOperation::None => vec![],
+                        };
+                        refs.extend(match_pat.with_prototype.iter());
+                        refs
+                    }
+                };
+                for reference in refs {
+                    if let ContextReference::Named(ref name) = *reference {
+                        if name == "$top_level_main" || contexts.contains_key(name) {
+                            continue;
+                        }
+                        if options.strict {
+                            return Err(ParseSyntaxError::MissingContext(
+                                context_name.clone(),
+                                name.clone(),
+                            ));
+                        }
+                        options.warn(LoadWarning::MissingContext {
+                            context: context_name.clone(),
+                            name: name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn parse_reference(
         y: &Yaml,
         state: &mut ParserState<'_>,
@@ -482,13 +998,23 @@ impl SyntaxDefinition {
         regex_str: &str,
         state: &mut ParserState<'_>,
     ) -> Result<CaptureMapping, ParseSyntaxError> {
-        let valid_indexes = get_consuming_capture_indexes(regex_str);
+        let valid_indexes =
+            get_consuming_capture_indexes(regex_str, state.options.lookahead_captures);
         let mut captures = Vec::new();
         for (key, value) in map.iter() {
             if let (Some(key_int), Some(val_str)) = (key.as_i64(), value.as_str()) {
                 if valid_indexes.contains(&(key_int as usize)) {
                     captures.push((key_int as usize, str_to_scopes(val_str, state.scope_repo)?));
                 }
+            } else {
+                let context = state.context_names.last().cloned().unwrap_or_default();
+                let capture = format!("{:?}: {:?}", key, value);
+                if state.options.strict {
+                    return Err(ParseSyntaxError::InvalidCapture(context, capture));
+                }
+                state
+                    .options
+                    .warn(LoadWarning::InvalidCapture { context, capture });
             }
         }
         Ok(captures)
@@ -714,15 +1240,17 @@ impl<'a> RegexRewriterForNoNewlines<'a> {
     }
 }
 
-fn get_consuming_capture_indexes(regex: &str) -> Vec<usize> {
+fn get_consuming_capture_indexes(regex: &str, keep_lookaheads: bool) -> Vec<usize> {
     let parser = ConsumingCaptureIndexParser {
         parser: Parser::new(regex.as_bytes()),
+        keep_lookaheads,
     };
     parser.get_consuming_capture_indexes()
 }
 
 struct ConsumingCaptureIndexParser<'a> {
     parser: Parser<'a>,
+    keep_lookaheads: bool,
 }
 
 impl<'a> ConsumingCaptureIndexParser<'a> {
@@ -731,7 +1259,7 @@ impl<'a> ConsumingCaptureIndexParser<'a> {
     /// If, in a YAML syntax definition, a scope stack is applied to a capture group inside a
     /// lookaround, (i.e. "captures:\n x: scope.stack goes.here", where "x" is the number of a
     /// capture group in a lookahead/behind), those those scopes are not applied, so no need to
-    /// even parse them.
+    /// even parse them. With `keep_lookaheads`, only lookbehinds are left out.
     fn get_consuming_capture_indexes(mut self) -> Vec<usize> {
         let mut result = Vec::new();
         let mut stack = Vec::new();
@@ -768,7 +1296,7 @@ impl<'a> ConsumingCaptureIndexParser<'a> {
                                 self.parser.next();
                                 if c3 == b'=' || c3 == b'!' {
                                     // lookahead
-                                    in_lookaround = true;
+                                    in_lookaround |= !self.keep_lookaheads;
                                 } else if c3 == b'<' {
                                     if let Some(c4) = self.parser.peek() {
                                         if c4 == b'=' || c4 == b'!' {
@@ -1235,6 +1763,186 @@ mod tests {
         assert_eq!(def.unwrap().name, "C");
     }
 
+    #[test]
+    fn strict_loading_errors_on_suspicious_syntax() {
+        let load = |s: &str| {
+            SyntaxDefinition::load_from_str_with_options(s, false, None, &LoadOptions::strict())
+        };
+        let unknown_key = "
+        scope: source.c
+        contexts:
+          main:
+            - match: a
+              scop: keyword.c
+        ";
+        assert!(
+            matches!(load(unknown_key), Err(ParseSyntaxError::UnknownKey(ref k)) if k == "scop")
+        );
+
+        let missing_context = "
+        scope: source.c
+        contexts:
+          main:
+            - match: a
+              push: strnig
+        ";
+        assert!(matches!(
+            load(missing_context),
+            Err(ParseSyntaxError::MissingContext(ref c, ref n)) if c == "main" && n == "strnig"
+        ));
+
+        let invalid_capture = "
+        scope: source.c
+        contexts:
+          main:
+            - match: (a)
+              captures:
+                one: keyword.c
+        ";
+        assert!(matches!(
+            load(invalid_capture),
+            Err(ParseSyntaxError::InvalidCapture(ref c, _)) if c == "main"
+        ));
+
+        // The same syntaxes load fine by default
+        for s in &[unknown_key, missing_context, invalid_capture] {
+            assert!(SyntaxDefinition::load_from_str(s, false, None).is_ok());
+        }
+    }
+
+    #[test]
+    fn lenient_loading_reports_warnings() {
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let collected = warnings.clone();
+        let options = LoadOptions::lenient(move |w| collected.lock().unwrap().push(w.clone()));
+        let def = SyntaxDefinition::load_from_str_with_options(
+            "
+        scope: source.c
+        colour: red
+        contexts:
+          main:
+            - match: a
+              push: strnig
+        ",
+            false,
+            None,
+            &options,
+        );
+        assert!(def.is_ok());
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![
+                LoadWarning::UnknownKey {
+                    context: None,
+                    key: "colour".to_owned()
+                },
+                LoadWarning::MissingContext {
+                    context: "main".to_owned(),
+                    name: "strnig".to_owned()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn can_extend_base_syntaxes() {
+        let base = r"
+        name: Base
+        scope: source.base
+        file_extensions: [base]
+        variables:
+          ident: '[a-z]+'
+        contexts:
+          main:
+            - match: '{{ident}}'
+          strings:
+            - meta_scope: string.base
+            - match: '\\.'
+          numbers:
+            - match: '\d+'
+        ";
+        let derived = r"
+        name: Derived
+        scope: source.derived
+        extends: Packages/Base/Base.sublime-syntax
+        variables:
+          ident: '[A-Z]+'
+        contexts:
+          main:
+            - meta_prepend: true
+            - match: '#'
+          strings:
+            - meta_append: true
+            - meta_scope: string.derived
+            - match: '%'
+          numbers:
+            - match: '0x\h+'
+        ";
+        let find_base = |path: &str| {
+            if path == "Packages/Base/Base.sublime-syntax" {
+                Some(base.to_owned())
+            } else {
+                None
+            }
+        };
+        let defn = SyntaxDefinition::load_from_str_with_bases(
+            derived,
+            false,
+            None,
+            &LoadOptions::strict(),
+            find_base,
+        )
+        .unwrap();
+        assert_eq!(defn.name, "Derived");
+        assert!(defn.file_extensions.is_empty());
+        let regexes = |name: &str| -> Vec<String> {
+            defn.contexts[name]
+                .patterns
+                .iter()
+                .filter_map(|pattern| match pattern {
+                    Pattern::Match(match_pat) => Some(match_pat.regex.regex_str().to_owned()),
+                    Pattern::Include(_) => None,
+                })
+                .collect()
+        };
+        assert_eq!(regexes("main"), ["#", "[A-Z]+"]);
+        assert_eq!(regexes("strings"), [r"\\.", "%"]);
+        assert_eq!(regexes("numbers"), [r"0x\h+"]);
+        assert_eq!(
+            defn.contexts["strings"].meta_scope,
+            vec![Scope::new("string.derived").unwrap()]
+        );
+
+        // without bases, the syntax is loaded on its own
+        let alone = SyntaxDefinition::load_from_str(derived, false, None).unwrap();
+        assert_eq!(alone.contexts["numbers"].patterns.len(), 1);
+        assert!(!alone.contexts["main"]
+            .patterns
+            .iter()
+            .any(|pattern| matches!(pattern, Pattern::Match(match_pat) if match_pat.regex.regex_str() == "[A-Z]+")));
+
+        let missing = SyntaxDefinition::load_from_str_with_bases(
+            derived,
+            false,
+            None,
+            &LoadOptions::default(),
+            |_| None,
+        );
+        assert!(matches!(
+            missing,
+            Err(ParseSyntaxError::MissingBaseSyntax(ref path))
+                if path == "Packages/Base/Base.sublime-syntax"
+        ));
+        let cycle = SyntaxDefinition::load_from_str_with_bases(
+            derived,
+            false,
+            None,
+            &LoadOptions::default(),
+            |_| Some(derived.to_owned()),
+        );
+        assert!(matches!(cycle, Err(ParseSyntaxError::ExtendsCycle(_))));
+    }
+
     #[test]
     fn can_rewrite_regex_for_newlines() {
         fn rewrite(s: &str) -> String {
@@ -1297,7 +2005,7 @@ mod tests {
     fn can_get_valid_captures_from_regex() {
         let regex = "hello(test)(?=(world))(foo(?P<named>bar))";
         println!("{:?}", regex);
-        let valid_indexes = get_consuming_capture_indexes(regex);
+        let valid_indexes = get_consuming_capture_indexes(regex, false);
         println!("{:?}", valid_indexes);
         assert_eq!(valid_indexes, [0, 1, 3, 4]);
     }
@@ -1306,7 +2014,7 @@ mod tests {
     fn can_get_valid_captures_from_regex2() {
         let regex = "hello(test)[(?=tricked](foo(bar))";
         println!("{:?}", regex);
-        let valid_indexes = get_consuming_capture_indexes(regex);
+        let valid_indexes = get_consuming_capture_indexes(regex, false);
         println!("{:?}", valid_indexes);
         assert_eq!(valid_indexes, [0, 1, 2, 3]);
     }
@@ -1315,8 +2023,12 @@ mod tests {
     fn can_get_valid_captures_from_nested_regex() {
         let regex = "hello(test)(?=(world(?!(te(?<=(st))))))(foo(bar))";
         println!("{:?}", regex);
-        let valid_indexes = get_consuming_capture_indexes(regex);
+        let valid_indexes = get_consuming_capture_indexes(regex, false);
         println!("{:?}", valid_indexes);
         assert_eq!(valid_indexes, [0, 1, 5, 6]);
+        assert_eq!(
+            get_consuming_capture_indexes(regex, true),
+            [0, 1, 2, 3, 5, 6]
+        );
     }
 }
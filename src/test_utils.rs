@@ -0,0 +1,460 @@
+//! Helpers for regression tests of syntaxes and themes, like the ones of syntect itself, enabled
+//! with the `test-support` feature
+//!
+//! Like `assert!`, the helpers panic with a message showing what differs when an expectation
+//! isn't met:
+//!
+//! * [`expect_scope_stacks`] checks that some scope stacks show up while parsing a line with a
+//!   syntax written inline, which is how syntect's parser tests are written
+//! * [`ops_snapshot`] lists the tokens of a text with their scopes, and [`assert_golden`]
+//!   compares it, or any other output, to a file checked in next to the tests
+//! * [`assert_highlights_eq!`] compares highlighted regions, listing their styles
+//!
+//! [`expect_scope_stacks`]: fn.expect_scope_stacks.html
+//! [`ops_snapshot`]: fn.ops_snapshot.html
+//! [`assert_golden`]: fn.assert_golden.html
+//! [`assert_highlights_eq!`]: ../macro.assert_highlights_eq.html
+#![allow(clippy::panic)]
+
+use crate::easy::ScopeRangeIterator;
+use crate::highlighting::{Color, Style};
+use crate::parsing::{
+    ParseState, ScopeStack, ScopeStackOp, SyntaxDefinition, SyntaxReference, SyntaxSet,
+    SyntaxSetBuilder,
+};
+use crate::util::{format_scope_stack, LinesWithEndings};
+use crate::Error;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The environment variable that makes [`assert_golden`] write the golden files instead of
+/// comparing them
+///
+/// [`assert_golden`]: fn.assert_golden.html
+pub const BLESS_VAR: &str = "SYNTECT_BLESS";
+
+/// Parses a line with a syntax given as the YAML of a `.sublime-syntax` file and checks that
+/// each of the expected scope stacks shows up
+///
+/// The syntax is loaded both for lines with and without newlines, and the line is parsed
+/// accordingly. Each expected stack is matched against the [`stack_states`] after each operation,
+/// so it can be the end of a stack like `<string.quoted.test>` or a part of it like
+/// `<source.test>, <meta.group.test>`.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::test_utils::expect_scope_stacks;
+///
+/// let syntax = r"
+/// name: Test
+/// scope: source.test
+/// contexts:
+///   main:
+///     - match: '\d+'
+///       scope: constant.numeric.test
+/// ";
+/// expect_scope_stacks("a 42", &["<source.test>, <constant.numeric.test>"], syntax);
+/// ```
+///
+/// # Panics
+///
+/// If the syntax doesn't load, the line doesn't parse, or an expected stack is missing. The
+/// message of a missing stack lists the operations the line was parsed into.
+///
+/// [`stack_states`]: fn.stack_states.html
+pub fn expect_scope_stacks(line_without_newline: &str, expect: &[&str], syntax: &str) {
+    let line_with_newline = format!("{}\n", line_without_newline);
+    let syntax_newlines = SyntaxDefinition::load_from_str(syntax, true, None).unwrap();
+    check_scope_stacks(
+        &line_with_newline,
+        expect,
+        syntax_newlines,
+        " with newlines",
+    );
+
+    let syntax_nonewlines = SyntaxDefinition::load_from_str(syntax, false, None).unwrap();
+    check_scope_stacks(
+        line_without_newline,
+        expect,
+        syntax_nonewlines,
+        " without newlines",
+    );
+}
+
+/// Like [`expect_scope_stacks`], but with a loaded syntax, which is linked into a syntax set of
+/// its own, and for the line as it is
+///
+/// [`expect_scope_stacks`]: fn.expect_scope_stacks.html
+pub fn expect_scope_stacks_with_syntax(line: &str, expect: &[&str], syntax: SyntaxDefinition) {
+    check_scope_stacks(line, expect, syntax, "");
+}
+
+/// Parses the line and panics with the operations if an expected stack is missing, saying how
+/// the syntax was loaded with `loaded`
+fn check_scope_stacks(line: &str, expect: &[&str], syntax: SyntaxDefinition, loaded: &str) {
+    let mut builder = SyntaxSetBuilder::new();
+    builder.add(syntax);
+    let syntax_set = builder.build();
+    let mut state = ParseState::new(&syntax_set.syntaxes()[0]);
+    let ops = state.parse_line(line, &syntax_set).unwrap();
+    if let Some(missing) = missing_stack(&stack_states(&ops), expect) {
+        panic!(
+            "expected scope stack '{}' missing parsing {:?}{}, the operations were:\n{}",
+            missing,
+            line,
+            loaded,
+            format_ops(line, &ops)
+        );
+    }
+}
+
+/// The operations under the line, each with a caret at its position, like
+/// [`debug_print_ops`] prints them
+///
+/// [`debug_print_ops`]: ../util/fn.debug_print_ops.html
+fn format_ops(line: &str, ops: &[(usize, ScopeStackOp)]) -> String {
+    let mut out = String::new();
+    for &(i, ref op) in ops {
+        writeln!(out, "{}", line.trim_end()).unwrap();
+        write!(out, "{: <1$}", "", i).unwrap();
+        match *op {
+            ScopeStackOp::Push(s) => writeln!(out, "^ +{}", s),
+            ScopeStackOp::Pop(count) => writeln!(out, "^ pop {}", count),
+            ScopeStackOp::Clear(amount) => writeln!(out, "^ clear {:?}", amount),
+            ScopeStackOp::Restore => writeln!(out, "^ restore"),
+            ScopeStackOp::Noop => writeln!(out, "noop"),
+        }
+        .unwrap();
+    }
+    out
+}
+
+/// Checks that each of the expected scope stacks shows up while applying the operations, see
+/// [`expect_scope_stacks`]
+///
+/// [`expect_scope_stacks`]: fn.expect_scope_stacks.html
+pub fn expect_scope_stacks_for_ops(ops: &[(usize, ScopeStackOp)], expect: &[&str]) {
+    let states = stack_states(ops);
+    if let Some(missing) = missing_stack(&states, expect) {
+        panic!(
+            "expected scope stack '{}' missing, the stacks were:\n{}",
+            missing,
+            states.join("\n")
+        );
+    }
+}
+
+/// The first expected stack that isn't part of any of the states
+fn missing_stack<'a>(states: &[String], expect: &[&'a str]) -> Option<&'a str> {
+    expect
+        .iter()
+        .find(|expected| !states.iter().any(|state| state.contains(*expected)))
+        .copied()
+}
+
+/// The scope stack after each of the operations, in the `Debug` format of the scopes joined by
+/// commas, like `<source.test>, <string.quoted.test>`
+///
+/// This format can be read back with [`parse_scope_stack`].
+///
+/// [`parse_scope_stack`]: ../util/fn.parse_scope_stack.html
+pub fn stack_states(ops: &[(usize, ScopeStackOp)]) -> Vec<String> {
+    let mut stack = ScopeStack::new();
+    ops.iter()
+        .map(|(_, op)| {
+            stack.apply(op).unwrap();
+            let scopes: Vec<String> = stack
+                .as_slice()
+                .iter()
+                .map(|s| format!("{:?}", s))
+                .collect();
+            scopes.join(", ")
+        })
+        .collect()
+}
+
+/// Parses a text and lists its tokens with their scopes, one per line, for [`assert_golden`]
+///
+/// Each line has the line number, counting from 1, the byte range of the token in its line, the
+/// text of the token and its scopes like [`format_scope_stack`] writes them, e.g.
+/// `1:3..7 "main" source.rust meta.function.rust entity.name.function.rust`. The text is split
+/// into lines like [`LinesWithEndings`] does, so the syntax set should be loaded with newlines.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::test_utils::ops_snapshot;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let snapshot = ops_snapshot("fn main() {}\n", syntax, &ss).unwrap();
+/// assert!(snapshot.contains("1:3..7 \"main\" source.rust meta.function.rust"));
+/// ```
+///
+/// [`assert_golden`]: fn.assert_golden.html
+/// [`format_scope_stack`]: ../util/fn.format_scope_stack.html
+/// [`LinesWithEndings`]: ../util/struct.LinesWithEndings.html
+pub fn ops_snapshot(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<String, Error> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut snapshot = String::new();
+    for (line_index, line) in LinesWithEndings::from(text).enumerate() {
+        let ops = state.parse_line(line, syntax_set)?;
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            stack.apply(op)?;
+            if range.is_empty() {
+                continue;
+            }
+            writeln!(
+                snapshot,
+                "{}:{}..{} {:?} {}",
+                line_index + 1,
+                range.start,
+                range.end,
+                &line[range.clone()],
+                format_scope_stack(stack.as_slice())
+            )?;
+        }
+    }
+    Ok(snapshot)
+}
+
+/// Compares an output to the contents of a golden file, or writes it to the file if the
+/// [`BLESS_VAR`] environment variable is set, e.g. with `SYNTECT_BLESS=1 cargo test`
+///
+/// Line endings are normalized, so the files can be checked out with `\r\n` on Windows.
+///
+/// # Panics
+///
+/// If the file can't be read or written, or differs from the output. The message lists the
+/// lines that differ.
+///
+/// [`BLESS_VAR`]: constant.BLESS_VAR.html
+pub fn assert_golden<P: AsRef<Path>>(actual: &str, path: P) {
+    let path = path.as_ref();
+    if std::env::var_os(BLESS_VAR).is_some() {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .unwrap_or_else(|e| panic!("couldn't create {}: {}", parent.display(), e));
+        }
+        std::fs::write(path, actual)
+            .unwrap_or_else(|e| panic!("couldn't write {}: {}", path.display(), e));
+        return;
+    }
+    let expected = match std::fs::read_to_string(path) {
+        Ok(expected) => expected.replace("\r\n", "\n"),
+        Err(e) => panic!(
+            "couldn't read {}: {}, set {} to create it",
+            path.display(),
+            e,
+            BLESS_VAR
+        ),
+    };
+    let actual = actual.replace("\r\n", "\n");
+    if expected != actual {
+        panic!(
+            "the output differs from {}, set {} to update it\n{}",
+            path.display(),
+            BLESS_VAR,
+            diff_lines(&expected, &actual)
+        );
+    }
+}
+
+/// The lines that differ between two texts, numbered from 1, with the expected one prefixed with
+/// `-` and the actual one with `+`
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut diff = String::new();
+    for i in 0..expected.len().max(actual.len()) {
+        let (expected, actual) = (expected.get(i), actual.get(i));
+        if expected == actual {
+            continue;
+        }
+        if let Some(line) = expected {
+            writeln!(diff, "{:>4} - {}", i + 1, line).unwrap();
+        }
+        if let Some(line) = actual {
+            writeln!(diff, "{:>4} + {}", i + 1, line).unwrap();
+        }
+    }
+    diff
+}
+
+/// Lists highlighted regions with their styles, one per line, like
+/// `"fn" #b48eadff on #2b303bff BOLD`, for comparing them in tests
+///
+/// The underline and the font hint are only listed if they're set.
+pub fn format_highlights(regions: &[(Style, &str)]) -> String {
+    fn hex(c: Color) -> String {
+        format!("#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a)
+    }
+    let mut s = String::new();
+    for (style, text) in regions {
+        write!(
+            s,
+            "{:?} {} on {}",
+            text,
+            hex(style.foreground),
+            hex(style.background)
+        )
+        .unwrap();
+        if !style.font_style.is_empty() {
+            write!(s, " {:?}", style.font_style).unwrap();
+        }
+        if let Some(underline) = style.underline {
+            write!(s, " {:?}", underline).unwrap();
+        }
+        if let Some(color) = style.underline_color {
+            write!(s, " underline {}", hex(color)).unwrap();
+        }
+        if let Some(ref hint) = style.font_hint {
+            write!(s, " {:?}", hint).unwrap();
+        }
+        s.push('\n');
+    }
+    s
+}
+
+/// Asserts that two lists of highlighted regions are equal, like `assert_eq!`, but lists the
+/// regions that differ with their styles
+///
+/// Both sides are anything that slices into `[(Style, &str)]`, like the result of
+/// [`HighlightLines::highlight_line`]. The regions are compared as they are, so the same text
+/// split into tokens differently doesn't match.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::assert_highlights_eq;
+/// use syntect::easy::HighlightLines;
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let theme = &ts.themes["base16-ocean.dark"];
+/// let first = HighlightLines::new(syntax, theme).highlight_line("let x = 1;\n", &ss).unwrap();
+/// let again = HighlightLines::new(syntax, theme).highlight_line("let x = 1;\n", &ss).unwrap();
+/// assert_highlights_eq!(first, again);
+/// ```
+///
+/// [`HighlightLines::highlight_line`]: easy/struct.HighlightLines.html#method.highlight_line
+#[macro_export]
+macro_rules! assert_highlights_eq {
+    ($actual:expr, $expected:expr $(,)?) => {
+        $crate::test_utils::__assert_highlights_eq(&$actual[..], &$expected[..])
+    };
+}
+
+#[doc(hidden)]
+pub fn __assert_highlights_eq(actual: &[(Style, &str)], expected: &[(Style, &str)]) {
+    if actual != expected {
+        panic!(
+            "the highlighted regions differ\n{}",
+            diff_lines(&format_highlights(expected), &format_highlights(actual))
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::FontStyle;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn lists_the_tokens_of_a_text_with_their_scopes() {
+        let syntax = SyntaxDefinition::load_from_str(
+            r"
+name: Test
+scope: source.test
+contexts:
+  main:
+    - match: '\d+'
+      scope: constant.numeric.test
+",
+            true,
+            None,
+        )
+        .unwrap();
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(syntax);
+        let ss = builder.build();
+        let snapshot = ops_snapshot("a 1\n22\n", &ss.syntaxes()[0], &ss).unwrap();
+        assert_eq!(
+            snapshot,
+            "1:0..2 \"a \" source.test\n\
+             1:2..3 \"1\" source.test constant.numeric.test\n\
+             1:3..4 \"\\n\" source.test\n\
+             2:0..2 \"22\" source.test constant.numeric.test\n\
+             2:2..3 \"\\n\" source.test\n"
+        );
+    }
+
+    #[test]
+    fn lists_the_operations_of_a_missing_stack() {
+        let syntax = "name: Test\nscope: source.test\ncontexts:\n  main:\n    - match: b\n      scope: b.test\n";
+        let message = std::panic::catch_unwind(|| {
+            expect_scope_stacks("ab", &["<source.test>, <c.test>"], syntax)
+        })
+        .unwrap_err()
+        .downcast::<String>()
+        .unwrap();
+        assert_eq!(
+            *message,
+            "expected scope stack '<source.test>, <c.test>' missing parsing \"ab\\n\" with \
+             newlines, the operations were:\n\
+             ab\n^ +source.test\n\
+             ab\n ^ +b.test\n\
+             ab\n  ^ pop 1\n"
+        );
+    }
+
+    #[test]
+    fn compares_golden_files_ignoring_line_endings() {
+        let dir = TempDir::new("golden");
+        let path = dir.write("snapshot.txt", "a\r\nb\r\nc\r\n");
+        assert_golden("a\nb\nc\n", &path);
+        let message = std::panic::catch_unwind(|| assert_golden("a\nx\nc\nd\n", &path))
+            .unwrap_err()
+            .downcast::<String>()
+            .unwrap();
+        assert!(
+            message.ends_with("   2 - b\n   2 + x\n   4 + d\n"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn lists_the_styles_of_differing_regions() {
+        let plain = Style::default();
+        let bold = Style {
+            font_style: FontStyle::BOLD,
+            ..plain
+        };
+        assert_highlights_eq!([(plain, "a"), (bold, "b")], vec![(plain, "a"), (bold, "b")]);
+        let message = std::panic::catch_unwind(|| {
+            assert_highlights_eq!([(plain, "a"), (bold, "b")], [(plain, "a"), (plain, "b")])
+        })
+        .unwrap_err()
+        .downcast::<String>()
+        .unwrap();
+        assert!(
+            message.contains(
+                "   2 - \"b\" #000000ff on #ffffffff\n   2 + \"b\" #000000ff on #ffffffff BOLD\n"
+            ),
+            "{}",
+            message
+        );
+    }
+}
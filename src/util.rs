@@ -1,16 +1,78 @@
 //! Convenient helper functions for common use cases:
 //! * Printing to terminal
 //! * Iterating lines with `\n`s
-//! * Modifying ranges of highlighted output
+//! * Modifying ranges of highlighted output, e.g. to overlay selections or to fade everything
+//!   but the code in focus
+//! * Expanding tabs, splitting and wrapping highlighted output by display width, for terminal UIs
+//! * Joining highlighted output back into plain text
+//! * Converting tokens to JSON for other programs
+//! * Highlighting diffs, in the [`diff`] module
+//! * Turning parsed text into a tree of nested scopes, in the [`span_tree`] module
+//! * Finding the parts of a text in embedded languages, in the [`language_regions`] module
+//! * Measuring how much of a text a syntax assigns scopes to, with [`scope_coverage`]
+//! * Reading text colored with ANSI escape sequences into styled fragments, in the [`ansi`] module
+//! * Converting byte offsets into char or UTF-16 offsets, with [`OffsetConverter`]
+//! * Collecting identifiers from a text for completion, with [`extract_identifiers`]
+//! * Finding matching brackets while skipping those in strings and comments, with
+//!   [`find_matching_bracket`]
+//! * Splitting a line into words the way its language's metadata says, with [`split_words`]
+//! * Formatting, parsing and diffing stacks of scopes for snapshot tests, with
+//!   [`format_scope_stack`], [`parse_scope_stack`] and [`diff_scope_stacks`]
+//!
+//! [`ansi`]: ansi/index.html
+//! [`diff`]: diff/index.html
+//! [`span_tree`]: span_tree/index.html
+//! [`language_regions`]: language_regions/index.html
+//! [`scope_coverage`]: fn.scope_coverage.html
+//! [`OffsetConverter`]: struct.OffsetConverter.html
+//! [`split_words`]: fn.split_words.html
+//! [`extract_identifiers`]: fn.extract_identifiers.html
+//! [`find_matching_bracket`]: fn.find_matching_bracket.html
+//! [`format_scope_stack`]: fn.format_scope_stack.html
+//! [`parse_scope_stack`]: fn.parse_scope_stack.html
+//! [`diff_scope_stacks`]: fn.diff_scope_stacks.html
 
-use crate::highlighting::{Color, Style, StyleModifier};
+use crate::highlighting::{Color, FontStyle, Style, StyleModifier, UnderlineStyle};
 #[cfg(feature = "parsing")]
-use crate::parsing::ScopeStackOp;
+use crate::highlighting::{HighlightIterator, HighlightState, Highlighter};
+#[cfg(feature = "metadata")]
+use crate::parsing::Metadata;
+#[cfg(feature = "parsing")]
+use crate::parsing::{Scope, ScopeError, ScopeStack, ScopeStackOp};
+use serde_json::{json, Value};
 use std::fmt::Write;
 use std::ops::Range;
+use unicode_width::UnicodeWidthChar;
+
+pub mod ansi;
+#[cfg(feature = "parsing")]
+mod brackets;
+#[cfg(feature = "parsing")]
+mod coverage;
+#[cfg(feature = "parsing")]
+pub mod diff;
+#[cfg(feature = "parsing")]
+mod identifiers;
+#[cfg(feature = "parsing")]
+pub mod language_regions;
+mod offsets;
+#[cfg(feature = "parsing")]
+mod scope_stacks;
+#[cfg(feature = "parsing")]
+pub mod span_tree;
+
+#[cfg(feature = "parsing")]
+pub use self::brackets::find_matching_bracket;
+#[cfg(feature = "parsing")]
+pub use self::coverage::{scope_coverage, ScopeCoverage};
+#[cfg(feature = "parsing")]
+pub use self::identifiers::extract_identifiers;
+pub use self::offsets::OffsetConverter;
+#[cfg(feature = "parsing")]
+pub use self::scope_stacks::{diff_scope_stacks, format_scope_stack, parse_scope_stack};
 
 #[inline]
-fn blend_fg_color(fg: Color, bg: Color) -> Color {
+pub(crate) fn blend_fg_color(fg: Color, bg: Color) -> Color {
     if fg.a == 0xff {
         return fg;
     }
@@ -39,21 +101,110 @@ fn blend_fg_color(fg: Color, bg: Color) -> Color {
 pub fn as_24_bit_terminal_escaped(v: &[(Style, &str)], bg: bool) -> String {
     let mut s: String = String::new();
     for &(ref style, text) in v.iter() {
-        if bg {
-            write!(
-                s,
-                "\x1b[48;2;{};{};{}m",
-                style.background.r, style.background.g, style.background.b
-            )
-            .unwrap();
-        }
-        let fg = blend_fg_color(style.foreground, style.background);
-        write!(s, "\x1b[38;2;{};{};{}m{}", fg.r, fg.g, fg.b, text).unwrap();
+        write_24_bit_terminal_escaped(&mut s, style, text, bg);
     }
     // s.push_str("\x1b[0m");
     s
 }
 
+fn write_24_bit_terminal_escaped(s: &mut String, style: &Style, text: &str, bg: bool) {
+    if bg {
+        write!(
+            s,
+            "\x1b[48;2;{};{};{}m",
+            style.background.r, style.background.g, style.background.b
+        )
+        .unwrap();
+    }
+    let fg = blend_fg_color(style.foreground, style.background);
+    write!(s, "\x1b[38;2;{};{};{}m{}", fg.r, fg.g, fg.b, text).unwrap();
+}
+
+/// Like [`as_24_bit_terminal_escaped`], but highlights the output of
+/// [`ParseState::parse_line`] and makes tokens clickable in terminals that support [OSC 8]
+/// hyperlinks
+///
+/// `link` is called for each stretch of the line with the same scopes, with its text and the
+/// scope stack at it, and returns the URL to link it to, if any. Neighboring tokens with the
+/// same URL become one link. Control characters are removed from the URLs so they can't end
+/// the escape sequence early.
+///
+/// Like when highlighting, `stack` has to be kept between the lines of a file.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Highlighter, ThemeSet};
+/// use syntect::parsing::{ParseState, Scope, ScopeStack, SyntaxSet};
+/// use syntect::util::ops_to_24_bit_terminal_escaped_with_links;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let line = "let url = \"https://example.com\";\n";
+/// let ops = state.parse_line(line, &ss).unwrap();
+///
+/// let string = Scope::new("string").unwrap();
+/// let link = |text: &str, scopes: &[Scope]| {
+///     let in_string = scopes.iter().any(|s| string.is_prefix_of(*s));
+///     (in_string && text.starts_with("https://")).then(|| text.to_owned())
+/// };
+/// let escaped = ops_to_24_bit_terminal_escaped_with_links(
+///     line, &ops, &mut ScopeStack::new(), &highlighter, false, link,
+/// ).unwrap();
+/// assert!(escaped.contains("\x1b]8;;https://example.com\x1b\\"));
+/// ```
+///
+/// [`as_24_bit_terminal_escaped`]: fn.as_24_bit_terminal_escaped.html
+/// [`ParseState::parse_line`]: ../parsing/struct.ParseState.html#method.parse_line
+/// [OSC 8]: https://gist.github.com/egmontkob/eb114294efbcd5adb1944c9f3cb5feda
+#[cfg(feature = "parsing")]
+pub fn ops_to_24_bit_terminal_escaped_with_links<F>(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    stack: &mut ScopeStack,
+    highlighter: &Highlighter,
+    bg: bool,
+    mut link: F,
+) -> Result<String, ScopeError>
+where
+    F: FnMut(&str, &[Scope]) -> Option<String>,
+{
+    let mut s = String::new();
+    let mut open_link: Option<String> = None;
+    let mut push_token = |s: &mut String, text: &str, stack: &ScopeStack| {
+        if text.is_empty() {
+            return;
+        }
+        let url = link(text, stack.as_slice())
+            .map(|url| url.chars().filter(|c| !c.is_control()).collect::<String>());
+        if url != open_link {
+            if open_link.is_some() {
+                s.push_str("\x1b]8;;\x1b\\");
+            }
+            if let Some(url) = &url {
+                write!(s, "\x1b]8;;{}\x1b\\", url).unwrap();
+            }
+            open_link = url;
+        }
+        let style = highlighter.style_for_stack(stack.as_slice());
+        write_24_bit_terminal_escaped(s, &style, text, bg);
+    };
+
+    let mut cur_index = 0;
+    for &(i, ref op) in ops {
+        push_token(&mut s, &line[cur_index..i], stack);
+        cur_index = i;
+        stack.apply(op)?;
+    }
+    push_token(&mut s, &line[cur_index..], stack);
+    if open_link.is_some() {
+        s.push_str("\x1b]8;;\x1b\\");
+    }
+    Ok(s)
+}
+
 const LATEX_REPLACE: [(&str, &str); 3] = [("\\", "\\\\"), ("{", "\\{"), ("}", "\\}")];
 
 /// Formats the styled fragments using LaTeX textcolor directive.
@@ -139,6 +290,142 @@ pub fn as_latex_escaped(v: &[(Style, &str)]) -> String {
     s
 }
 
+/// Converts the styled regions of a line to JSON, for passing highlighted code to other programs
+///
+/// Returns an array with an object per region:
+///
+/// ```json
+/// {
+///   "start": 0,
+///   "end": 2,
+///   "text": "fn",
+///   "style": {
+///     "foreground": "#b48eadff",
+///     "background": "#2b303bff",
+///     "font_style": ["bold"],
+///     "underline": null,
+///     "underline_color": null
+///   }
+/// }
+/// ```
+///
+/// * `start` and `end` are byte offsets into the line.
+/// * Colors are `#rrggbbaa` strings.
+/// * `font_style` holds any of `"bold"`, `"italic"`, `"underline"`, `"glow"` and
+///   `"strikethrough"`.
+/// * `underline` is `"solid"`, `"stippled"`, `"squiggly"` or `null`, and `underline_color` is a
+///   color or `null`.
+///
+/// [`ops_to_json_tokens`] produces the same tokens with the scopes of each one added.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::Style;
+/// use syntect::util::to_json_tokens;
+///
+/// let tokens = to_json_tokens(&[(Style::default(), "let"), (Style::default(), " x")]);
+/// assert_eq!(tokens[1]["start"], 3);
+/// assert_eq!(tokens[1]["text"], " x");
+/// assert_eq!(tokens[1]["style"]["foreground"], "#000000ff");
+/// ```
+///
+/// [`ops_to_json_tokens`]: fn.ops_to_json_tokens.html
+pub fn to_json_tokens(v: &[(Style, &str)]) -> Value {
+    let mut start = 0;
+    let tokens = v
+        .iter()
+        .map(|&(style, text)| {
+            let token = json!({
+                "start": start,
+                "end": start + text.len(),
+                "text": text,
+                "style": style_to_json(&style),
+            });
+            start += text.len();
+            token
+        })
+        .collect();
+    Value::Array(tokens)
+}
+
+/// Converts the output of [`ParseState::parse_line`] to JSON tokens, one for each stretch of
+/// the line with the same scopes
+///
+/// The tokens look like the ones from [`to_json_tokens`], with a `scopes` array holding the
+/// scope stack at the token, outermost scope first. They only have a `style` when a
+/// `highlighter` is given.
+///
+/// Like when highlighting, `stack` has to be kept between the lines of a file.
+///
+/// [`ParseState::parse_line`]: ../parsing/struct.ParseState.html#method.parse_line
+/// [`to_json_tokens`]: fn.to_json_tokens.html
+#[cfg(feature = "parsing")]
+pub fn ops_to_json_tokens(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    stack: &mut ScopeStack,
+    highlighter: Option<&Highlighter>,
+) -> Result<Value, ScopeError> {
+    let mut tokens = Vec::new();
+    let mut push_token = |start: usize, end: usize, stack: &ScopeStack| {
+        if start == end {
+            return;
+        }
+        let scopes: Vec<String> = stack.as_slice().iter().map(|s| s.build_string()).collect();
+        let mut token = json!({
+            "start": start,
+            "end": end,
+            "text": &line[start..end],
+            "scopes": scopes,
+        });
+        if let Some(highlighter) = highlighter {
+            token["style"] = style_to_json(&highlighter.style_for_stack(stack.as_slice()));
+        }
+        tokens.push(token);
+    };
+
+    let mut cur_index = 0;
+    for &(i, ref op) in ops {
+        push_token(cur_index, i, stack);
+        cur_index = i;
+        stack.apply(op)?;
+    }
+    push_token(cur_index, line.len(), stack);
+    Ok(Value::Array(tokens))
+}
+
+fn style_to_json(style: &Style) -> Value {
+    let font_styles = [
+        (FontStyle::BOLD, "bold"),
+        (FontStyle::ITALIC, "italic"),
+        (FontStyle::UNDERLINE, "underline"),
+        (FontStyle::GLOW, "glow"),
+        (FontStyle::STRIKETHROUGH, "strikethrough"),
+    ];
+    let font_style: Vec<&str> = font_styles
+        .iter()
+        .filter(|(flag, _)| style.font_style.contains(*flag))
+        .map(|&(_, name)| name)
+        .collect();
+    let underline = style.underline.map(|underline| match underline {
+        UnderlineStyle::Solid => "solid",
+        UnderlineStyle::Stippled => "stippled",
+        UnderlineStyle::Squiggly => "squiggly",
+    });
+    json!({
+        "foreground": color_to_json(style.foreground),
+        "background": color_to_json(style.background),
+        "font_style": font_style,
+        "underline": underline,
+        "underline_color": style.underline_color.map(color_to_json),
+    })
+}
+
+fn color_to_json(c: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}{:02x}", c.r, c.g, c.b, c.a)
+}
+
 /// Print out the various push and pop operations in a vector
 /// with visual alignment to the line. Obviously for debugging.
 #[cfg(feature = "parsing")]
@@ -267,6 +554,240 @@ pub fn split_at<'a, A: Clone>(
     (before, after)
 }
 
+/// Spaces to borrow from when expanding tabs
+const SPACES: &str = "                                ";
+
+/// Replaces the tabs in a highlighted line with spaces up to the next multiple of `tabstop`
+/// columns, keeping the style of each tab.
+///
+/// Columns are counted in display width, see [`split_at_display_width`], and start over after a
+/// newline. The expanded regions borrow their spaces from a static string, so the result still
+/// refers to the line. A `tabstop` of 0 removes tabs.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::expand_tabs_styled;
+/// use syntect::highlighting::Style;
+///
+/// let plain = Style::default();
+/// let l = &[(plain, "\tx"), (plain, "y\tz")];
+/// let expanded: String = expand_tabs_styled(l, 4).iter().map(|(_, s)| *s).collect();
+/// assert_eq!(expanded, "    xy  z");
+/// ```
+///
+/// [`split_at_display_width`]: fn.split_at_display_width.html
+pub fn expand_tabs_styled<'a, A: Clone>(v: &[(A, &'a str)], tabstop: usize) -> Vec<(A, &'a str)> {
+    let mut result = Vec::with_capacity(v.len());
+    let mut column = 0;
+    for (style, text) in v {
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            match c {
+                '\t' => {
+                    if start < i {
+                        result.push((style.clone(), &text[start..i]));
+                    }
+                    start = i + 1;
+                    if tabstop == 0 {
+                        continue;
+                    }
+                    let spaces = tabstop - column % tabstop;
+                    column += spaces;
+                    push_spaces(&mut result, style, spaces);
+                }
+                '\n' => column = 0,
+                c => column += c.width().unwrap_or(0),
+            }
+        }
+        if start < text.len() {
+            result.push((style.clone(), &text[start..]));
+        }
+    }
+    result
+}
+
+fn push_spaces<A: Clone>(v: &mut Vec<(A, &str)>, style: &A, mut count: usize) {
+    while count > 0 {
+        let n = count.min(SPACES.len());
+        v.push((style.clone(), &SPACES[..n]));
+        count -= n;
+    }
+}
+
+/// Splits a highlighted line into a part that fits in `width` columns and the rest, like
+/// [`split_at`] but by display width instead of bytes.
+///
+/// Wide characters, like most CJK characters and emoji, take two columns and are never split
+/// across the two parts, and zero-width characters like combining marks stay with the character
+/// before them. Control characters, including tabs, count as zero columns, so expand tabs with
+/// [`expand_tabs_styled`] first.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::split_at_display_width;
+/// use syntect::highlighting::Style;
+///
+/// let plain = Style::default();
+/// let l = &[(plain, "ab"), (plain, "\u{4F60}\u{597D}")];
+/// let (before, after) = split_at_display_width(l, 5);
+/// assert_eq!(before, &[(plain, "ab"), (plain, "\u{4F60}")]);
+/// assert_eq!(after, &[(plain, "\u{597D}")]);
+/// ```
+///
+/// [`split_at`]: fn.split_at.html
+/// [`expand_tabs_styled`]: fn.expand_tabs_styled.html
+#[allow(clippy::type_complexity)]
+pub fn split_at_display_width<'a, A: Clone>(
+    v: &[(A, &'a str)],
+    width: usize,
+) -> (Vec<(A, &'a str)>, Vec<(A, &'a str)>) {
+    let mut column = 0;
+    let mut offset = 0;
+    for (_, text) in v {
+        for (i, c) in text.char_indices() {
+            column += c.width().unwrap_or(0);
+            if column > width {
+                return split_at(v, offset + i);
+            }
+        }
+        offset += text.len();
+    }
+    (v.to_vec(), Vec::new())
+}
+
+/// Where [`wrap_styled_lines`] may break lines
+///
+/// [`wrap_styled_lines`]: fn.wrap_styled_lines.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WrapMode {
+    /// Break lines after whichever character fills the width
+    Char,
+    /// Break lines before words, only breaking words that don't fit on a line by themselves.
+    /// Whitespace at the end of a line may go past the width.
+    Word,
+}
+
+/// Soft-wraps a highlighted line into lines of at most `max_width` columns, keeping the styles.
+///
+/// Widths are display widths, see [`split_at_display_width`], so expand tabs first. The text of
+/// the returned lines adds up to the text of the line. A `max_width` of 0 means no wrapping.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::{wrap_styled_lines, WrapMode};
+/// use syntect::highlighting::Style;
+///
+/// let plain = Style::default();
+/// let l = &[(plain, "let x = "), (plain, "\"some text\";")];
+/// let lines = wrap_styled_lines(l, 10, WrapMode::Word);
+/// assert_eq!(lines, vec![
+///     vec![(plain, "let x = ")],
+///     vec![(plain, "\"some ")],
+///     vec![(plain, "text\";")],
+/// ]);
+/// ```
+///
+/// [`split_at_display_width`]: fn.split_at_display_width.html
+pub fn wrap_styled_lines<'a, A: Clone>(
+    v: &[(A, &'a str)],
+    max_width: usize,
+    mode: WrapMode,
+) -> Vec<Vec<(A, &'a str)>> {
+    wrap_styled_lines_with_indent(v, max_width, mode, 0)
+}
+
+/// Like [`wrap_styled_lines`], but starts each continuation line with `indent` spaces, which
+/// count towards the width and get the style of the text after them.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::{wrap_styled_lines_with_indent, WrapMode};
+/// use syntect::highlighting::Style;
+///
+/// let plain = Style::default();
+/// let lines = wrap_styled_lines_with_indent(&[(plain, "abcdefgh")], 5, WrapMode::Char, 2);
+/// assert_eq!(lines, vec![
+///     vec![(plain, "abcde")],
+///     vec![(plain, "  "), (plain, "fgh")],
+/// ]);
+/// ```
+///
+/// [`wrap_styled_lines`]: fn.wrap_styled_lines.html
+pub fn wrap_styled_lines_with_indent<'a, A: Clone>(
+    v: &[(A, &'a str)],
+    max_width: usize,
+    mode: WrapMode,
+    indent: usize,
+) -> Vec<Vec<(A, &'a str)>> {
+    if max_width == 0 {
+        return vec![v.to_vec()];
+    }
+    // Leave room for at least one column of text after the indent
+    let indent = indent.min(max_width - 1);
+    let mut lines: Vec<Vec<(A, &'a str)>> = Vec::new();
+    let mut rest = v.to_vec();
+    loop {
+        let width = if lines.is_empty() {
+            max_width
+        } else {
+            max_width - indent
+        };
+        let (text, after) = match wrap_point(&rest, width, mode) {
+            Some(i) => split_at(&rest, i),
+            None => (rest, Vec::new()),
+        };
+        let mut line = Vec::with_capacity(text.len() + 1);
+        if let (false, Some((style, _))) = (lines.is_empty(), text.first()) {
+            push_spaces(&mut line, style, indent);
+        }
+        line.extend(text);
+        lines.push(line);
+        if after.is_empty() {
+            return lines;
+        }
+        rest = after;
+    }
+}
+
+/// The byte index to break a line at so the first part fits in `width` columns, or `None` if
+/// the whole line fits. Always makes progress, so a character wider than `width` is put on a
+/// line of its own.
+fn wrap_point<A>(v: &[(A, &str)], width: usize, mode: WrapMode) -> Option<usize> {
+    let total: usize = v.iter().map(|(_, s)| s.len()).sum();
+    let mut column = 0;
+    let mut offset = 0;
+    let mut word_start = None;
+    let mut prev_whitespace = false;
+    for (_, text) in v {
+        for (i, c) in text.char_indices() {
+            let pos = offset + i;
+            let whitespace = c.is_whitespace();
+            if prev_whitespace && !whitespace {
+                word_start = Some(pos);
+            }
+            prev_whitespace = whitespace;
+            column += c.width().unwrap_or(0);
+            match mode {
+                WrapMode::Word if whitespace => {}
+                _ if column <= width => {}
+                WrapMode::Word if word_start.is_some() => return word_start,
+                _ if pos > 0 => return Some(pos),
+                _ => {
+                    let end = c.len_utf8();
+                    return if end < total { Some(end) } else { None };
+                }
+            }
+        }
+        offset += text.len();
+    }
+    None
+}
+
 /// Modify part of a highlighted line using a style modifier, useful for highlighting sections of a line.
 ///
 /// # Examples
@@ -276,7 +797,7 @@ pub fn split_at<'a, A: Clone>(
 /// use syntect::highlighting::{Style, StyleModifier, FontStyle};
 ///
 /// let plain = Style::default();
-/// let boldmod = StyleModifier { foreground: None, background: None, font_style: Some(FontStyle::BOLD) };
+/// let boldmod = StyleModifier { font_style: Some(FontStyle::BOLD), ..StyleModifier::default() };
 /// let bold = plain.apply(boldmod);
 ///
 /// let l = &[(plain, "abc"), (plain, "def"), (plain, "ghi")];
@@ -296,11 +817,246 @@ pub fn modify_range<'a>(
     result
 }
 
+/// Paints style modifiers over parts of a highlighted line, e.g. search matches, selections or
+/// diagnostics on top of the syntax highlighting.
+///
+/// The ranges are byte offsets into the line. The overlays are applied in order, so where they
+/// overlap the later ones take precedence. Regions are only split where an overlay starts or ends,
+/// and empty ranges are ignored.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::overlay_styles;
+/// use syntect::highlighting::{Color, FontStyle, Style, StyleModifier};
+///
+/// let plain = Style::default();
+/// let selection = StyleModifier { background: Some(Color::BLACK), ..StyleModifier::default() };
+/// let search_match = StyleModifier { font_style: Some(FontStyle::BOLD), ..StyleModifier::default() };
+///
+/// let l = &[(plain, "let x"), (plain, " = 1;")];
+/// let l2 = overlay_styles(l, &[(0..7, selection), (4..5, search_match)]);
+/// let selected = plain.apply(selection);
+/// assert_eq!(l2, &[
+///     (selected, "let "),
+///     (selected.apply(search_match), "x"),
+///     (selected, " ="),
+///     (plain, " 1;"),
+/// ]);
+/// ```
+pub fn overlay_styles<'a>(
+    v: &[(Style, &'a str)],
+    overlays: &[(Range<usize>, StyleModifier)],
+) -> Vec<(Style, &'a str)> {
+    let mut result = v.to_vec();
+    for (range, modifier) in overlays {
+        if range.start < range.end {
+            result = modify_range(&result, range.clone(), *modifier);
+        }
+    }
+    result
+}
+
+/// Fades the parts of a highlighted line outside of a focus range towards their background, e.g.
+/// to draw attention to the current block of code
+///
+/// `amount` goes from 0.0, which changes nothing, to 1.0, which makes the text outside of the
+/// focus invisible. Foreground and underline colors are faded, while backgrounds and font styles
+/// are kept. Like with [`modify_range`], the focus range is byte offsets into the line, and
+/// regions are split where it starts and ends.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::fade_outside;
+/// use syntect::highlighting::{Color, Style};
+///
+/// let plain = Style::default();
+/// let faded = fade_outside(&[(plain, "let x"), (plain, " = 1;")], 4..5, 0.5);
+/// let gray = Style { foreground: Color { r: 0x80, g: 0x80, b: 0x80, a: 0xFF }, ..plain };
+/// assert_eq!(faded, &[(gray, "let "), (plain, "x"), (gray, " = 1;")]);
+/// ```
+///
+/// [`modify_range`]: fn.modify_range.html
+pub fn fade_outside<'a>(
+    v: &[(Style, &'a str)],
+    focus: Range<usize>,
+    amount: f32,
+) -> Vec<(Style, &'a str)> {
+    let fade = |&(style, text): &(Style, &'a str)| {
+        let background = style.background;
+        let style = Style {
+            foreground: blend_fg_color(style.foreground, background).lerp(background, amount),
+            underline_color: style
+                .underline_color
+                .map(|color| blend_fg_color(color, background).lerp(background, amount)),
+            ..style
+        };
+        (style, text)
+    };
+    let (before, in_and_after) = split_at(v, focus.start);
+    let (inside, after) = split_at(&in_and_after, focus.end.saturating_sub(focus.start));
+    let mut result: Vec<_> = before.iter().map(fade).collect();
+    result.extend(inside);
+    result.extend(after.iter().map(fade));
+    result
+}
+
+/// Highlights the lines of a text from the operations returned by [`ParseState::parse_line`] for
+/// each of them, fading everything outside of a focus range towards the background
+///
+/// This is for presentations and distraction-free editors which draw attention to one block of
+/// code, see [`fade_outside`]. The focus is a range of byte offsets into `text`, and `ops` has
+/// the operations of each line of `text`, split like [`LinesWithEndings`] does. Returns the
+/// regions of each line.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Highlighter, ThemeSet};
+/// use syntect::parsing::{ParseState, SyntaxSet};
+/// use syntect::util::{highlight_with_focus, LinesWithEndings};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let theme = &ts.themes["base16-ocean.dark"];
+/// let text = "fn main() {\n    let x = 1;\n}\n";
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let ops: Vec<_> = LinesWithEndings::from(text)
+///     .map(|line| state.parse_line(line, &ss).unwrap())
+///     .collect();
+///
+/// let body = text.find("let").unwrap()..text.find('}').unwrap();
+/// let lines = highlight_with_focus(text, &ops, &Highlighter::new(theme), body, 0.6);
+/// let background = theme.settings.background.unwrap();
+/// let (brace_style, _) = lines[2][0];
+/// assert_eq!(brace_style.foreground, theme.settings.foreground.unwrap().lerp(background, 0.6));
+/// ```
+///
+/// [`ParseState::parse_line`]: ../parsing/struct.ParseState.html#method.parse_line
+/// [`fade_outside`]: fn.fade_outside.html
+/// [`LinesWithEndings`]: struct.LinesWithEndings.html
+#[cfg(feature = "parsing")]
+pub fn highlight_with_focus<'a>(
+    text: &'a str,
+    ops: &[Vec<(usize, ScopeStackOp)>],
+    highlighter: &Highlighter,
+    focus: Range<usize>,
+    amount: f32,
+) -> Vec<Vec<(Style, &'a str)>> {
+    let mut state = HighlightState::new(highlighter, ScopeStack::new());
+    let mut offset = 0;
+    let mut lines = Vec::new();
+    for (line, line_ops) in LinesWithEndings::from(text).zip(ops) {
+        let regions: Vec<_> =
+            HighlightIterator::new(&mut state, line_ops, line, highlighter).collect();
+        let start = focus.start.saturating_sub(offset).min(line.len());
+        let end = focus.end.saturating_sub(offset).min(line.len());
+        lines.push(fade_outside(&regions, start..end, amount));
+        offset += line.len();
+    }
+    lines
+}
+
+/// Joins the text of highlighted regions back together, with the byte range of each region in
+/// the joined text, e.g. for copying highlighted code to the clipboard.
+///
+/// Since highlighting only splits the text, the joined text of a highlighted line is the line
+/// itself, so this is also a way to check that nothing was dropped or duplicated.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::strip_styles;
+/// use syntect::highlighting::Style;
+///
+/// let plain = Style::default();
+/// let (text, ranges) = strip_styles(&[(plain, "let"), (plain, " x"), (plain, ";")]);
+/// assert_eq!(text, "let x;");
+/// assert_eq!(ranges, [0..3, 3..5, 5..6]);
+/// ```
+pub fn strip_styles<A>(v: &[(A, &str)]) -> (String, Vec<Range<usize>>) {
+    let mut text = String::with_capacity(v.iter().map(|(_, s)| s.len()).sum());
+    let ranges = v
+        .iter()
+        .map(|(_, s)| {
+            let start = text.len();
+            text.push_str(s);
+            start..text.len()
+        })
+        .collect();
+    (text, ranges)
+}
+
+/// Returns the byte ranges of the words in a line, with the word separators and word characters
+/// the metadata gives for the scope stack there
+///
+/// Editors can use this to select a word on double click, or to move the cursor by words, the
+/// same way for a language as Sublime Text does. See [`WordBoundaries`] for what a word is; to
+/// split many lines in the same scope, get it once with [`Metadata::word_boundaries_for_scope`].
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ScopeStack, SyntaxSet};
+/// use syntect::util::split_words;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let scope: ScopeStack = "source.rust".parse().unwrap();
+/// let words = split_words("let v = x.y_z;", scope.as_slice(), ss.metadata());
+/// assert_eq!(words, [0..3, 4..5, 8..9, 10..13]);
+/// ```
+///
+/// [`WordBoundaries`]: ../parsing/struct.WordBoundaries.html
+/// [`Metadata::word_boundaries_for_scope`]: ../parsing/struct.Metadata.html#method.word_boundaries_for_scope
+#[cfg(feature = "metadata")]
+pub fn split_words(line: &str, scope: &[Scope], metadata: &Metadata) -> Vec<Range<usize>> {
+    metadata.word_boundaries_for_scope(scope).split_words(line)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::highlighting::FontStyle;
 
+    #[cfg(feature = "parsing")]
+    #[test]
+    fn links_neighboring_tokens_once() {
+        use crate::highlighting::Theme;
+
+        let string = Scope::new("string").unwrap();
+        let escape = Scope::new("constant.character.escape").unwrap();
+        let ops = [
+            (2, ScopeStackOp::Push(string)),
+            (3, ScopeStackOp::Push(escape)),
+            (4, ScopeStackOp::Pop(2)),
+        ];
+        let theme = Theme::default();
+        let highlighter = Highlighter::new(&theme);
+        let link = |_: &str, scopes: &[Scope]| {
+            scopes
+                .contains(&string)
+                .then(|| "https://x\x1b\\y".to_owned())
+        };
+        let escaped = ops_to_24_bit_terminal_escaped_with_links(
+            "a bc d",
+            &ops,
+            &mut ScopeStack::new(),
+            &highlighter,
+            false,
+            link,
+        )
+        .unwrap();
+        let plain = "\x1b[38;2;0;0;0m";
+        assert_eq!(
+            escaped,
+            format!(
+                "{0}a \x1b]8;;https://x\\y\x1b\\{0}b{0}c\x1b]8;;\x1b\\{0} d",
+                plain
+            )
+        );
+    }
+
     #[test]
     fn test_lines_with_endings() {
         fn lines(s: &str) -> Vec<&str> {
@@ -386,12 +1142,195 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_expand_tabs_styled() {
+        let l = &[(0u8, "\ta"), (1u8, "b\t\tc\n"), (2u8, "\u{4F60}\t")];
+        assert_eq!(
+            expand_tabs_styled(l, 4),
+            &[
+                (0u8, "    "),
+                (0u8, "a"),
+                (1u8, "b"),
+                (1u8, "  "),
+                (1u8, "    "),
+                (1u8, "c\n"),
+                (2u8, "\u{4F60}"),
+                (2u8, "  "),
+            ]
+        );
+        assert_eq!(
+            expand_tabs_styled(&[(0u8, "\t")], 40),
+            &[(0u8, SPACES), (0u8, "        ")]
+        );
+        assert_eq!(expand_tabs_styled(l, 0)[0], (0u8, "a"));
+    }
+
+    #[test]
+    fn test_split_at_display_width() {
+        let l = &[(0u8, "ab"), (1u8, "\u{4F60}\u{597D}"), (2u8, "e\u{0301}f")];
+
+        let (before, after) = split_at_display_width(l, 2); // between tokens
+        assert_eq!(before, &[(0u8, "ab")]);
+        assert_eq!(after, &[(1u8, "\u{4F60}\u{597D}"), (2u8, "e\u{0301}f")]);
+
+        // a wide character that doesn't fit goes to the second part
+        let (before, after) = split_at_display_width(l, 3);
+        assert_eq!(before, &[(0u8, "ab")]);
+        assert_eq!(after, &[(1u8, "\u{4F60}\u{597D}"), (2u8, "e\u{0301}f")]);
+
+        let (before, after) = split_at_display_width(l, 4); // inside token
+        assert_eq!(before, &[(0u8, "ab"), (1u8, "\u{4F60}")]);
+        assert_eq!(after, &[(1u8, "\u{597D}"), (2u8, "e\u{0301}f")]);
+
+        // the combining accent stays with the "e"
+        let (before, after) = split_at_display_width(l, 7);
+        assert_eq!(
+            before,
+            &[(0u8, "ab"), (1u8, "\u{4F60}\u{597D}"), (2u8, "e\u{0301}")]
+        );
+        assert_eq!(after, &[(2u8, "f")]);
+
+        let (before, after) = split_at_display_width(l, 8);
+        assert_eq!(before, l);
+        assert!(after.is_empty());
+    }
+
+    #[test]
+    fn test_wrap_styled_lines() {
+        fn texts(lines: Vec<Vec<(u8, &str)>>) -> Vec<String> {
+            lines
+                .iter()
+                .map(|l| l.iter().map(|(_, s)| *s).collect())
+                .collect()
+        }
+
+        let l = &[(0u8, "fn main() "), (1u8, "{ println!(\"hi\"); }\n")];
+        assert_eq!(
+            texts(wrap_styled_lines(l, 10, WrapMode::Char)),
+            vec!["fn main() ", "{ println!", "(\"hi\"); }\n"]
+        );
+        assert_eq!(
+            texts(wrap_styled_lines(l, 12, WrapMode::Word)),
+            vec!["fn main() { ", "println!(\"hi", "\"); }\n"]
+        );
+        // words longer than the width are broken
+        assert_eq!(
+            texts(wrap_styled_lines(l, 6, WrapMode::Word)),
+            vec!["fn ", "main() ", "{ ", "printl", "n!(\"hi", "\"); }\n"]
+        );
+        assert_eq!(
+            texts(wrap_styled_lines_with_indent(l, 12, WrapMode::Word, 4)),
+            vec!["fn main() { ", "    println!", "    (\"hi\"); ", "    }\n"]
+        );
+        assert_eq!(texts(wrap_styled_lines(l, 0, WrapMode::Word)).len(), 1);
+
+        // the indent gets the style of the text after it
+        let lines = wrap_styled_lines_with_indent(l, 10, WrapMode::Word, 2);
+        assert_eq!(&lines[1][..2], &[(1u8, "  "), (1u8, "{ ")]);
+
+        // wide characters get a line of their own if needed
+        let l = &[(0u8, "\u{4F60}\u{597D}")];
+        assert_eq!(
+            texts(wrap_styled_lines(l, 1, WrapMode::Char)),
+            vec!["\u{4F60}", "\u{597D}"]
+        );
+        assert_eq!(
+            texts(wrap_styled_lines(l, 3, WrapMode::Word)),
+            vec!["\u{4F60}", "\u{597D}"]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_overlay_styles() {
+        let plain = Style::default();
+        let bold = StyleModifier {
+            font_style: Some(FontStyle::BOLD),
+            ..StyleModifier::default()
+        };
+        let white = StyleModifier {
+            foreground: Some(Color::WHITE),
+            ..StyleModifier::default()
+        };
+        let l = &[(plain, "abc"), (plain, "def")];
+
+        assert_eq!(overlay_styles(l, &[]), l);
+        assert_eq!(overlay_styles(l, &[(2..2, bold), (4..3, bold)]), l);
+        // overlays reaching past the end of the line are cut off
+        assert_eq!(
+            overlay_styles(l, &[(5..10, bold)]),
+            &[(plain, "abc"), (plain, "de"), (plain.apply(bold), "f")]
+        );
+        // later overlays win where they overlap
+        let l2 = overlay_styles(l, &[(1..4, white), (0..2, bold), (2..5, white)]);
+        assert_eq!(
+            l2,
+            &[
+                (plain.apply(bold), "a"),
+                (plain.apply(white).apply(bold), "b"),
+                (plain.apply(white), "c"),
+                (plain.apply(white), "d"),
+                (plain.apply(white), "e"),
+                (plain, "f"),
+            ]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::reversed_empty_ranges)]
+    fn fades_text_outside_the_focus() {
+        let translucent = Color {
+            r: 0xff,
+            g: 0,
+            b: 0,
+            a: 0x80,
+        };
+        let style = Style {
+            foreground: translucent,
+            background: Color::BLACK,
+            underline_color: Some(Color::WHITE),
+            ..Style::default()
+        };
+        let l = &[(style, "abc"), (style, "def")];
+
+        assert_eq!(fade_outside(l, 0..6, 1.0), l);
+        assert_eq!(fade_outside(l, 0..10, 1.0), l);
+        let hidden = Style {
+            foreground: Color::BLACK,
+            underline_color: Some(Color::BLACK),
+            ..style
+        };
+        assert_eq!(
+            fade_outside(l, 4..3, 1.0),
+            &[(hidden, "abc"), (hidden, "d"), (hidden, "ef")]
+        );
+        // the translucent foreground is blended with the background before fading
+        let faded = fade_outside(l, 2..4, 0.5);
+        let half = Color {
+            r: 0x40,
+            g: 0,
+            b: 0,
+            a: 0xff,
+        };
+        assert_eq!(faded.len(), 4);
+        assert_eq!(faded[0].0.foreground, half);
+        assert_eq!(
+            faded[0].0.underline_color,
+            Some(Color::WHITE.lerp(Color::BLACK, 0.5))
+        );
+        assert_eq!(&faded[1..3], &[(style, "c"), (style, "d")]);
+        assert_eq!(faded[3].1, "ef");
+    }
+
     #[test]
     fn test_as_24_bit_terminal_escaped() {
         let style = Style {
             foreground: Color::WHITE,
             background: Color::BLACK,
             font_style: FontStyle::default(),
+            underline: None,
+            underline_color: None,
+            font_hint: None,
         };
 
         // With background
@@ -409,8 +1348,105 @@ mod tests {
             foreground,
             background: Color::BLACK,
             font_style: FontStyle::default(),
+            underline: None,
+            underline_color: None,
+            font_hint: None,
         };
         let s = as_24_bit_terminal_escaped(&[(style, "hello")], true);
         assert_eq!(s, "\x1b[48;2;0;0;0m\x1b[38;2;128;128;128mhello");
     }
+
+    #[test]
+    fn test_to_json_tokens() {
+        let style = Style {
+            foreground: Color::WHITE,
+            background: Color::BLACK,
+            font_style: FontStyle::BOLD | FontStyle::UNDERLINE,
+            underline: Some(UnderlineStyle::Squiggly),
+            underline_color: None,
+            font_hint: None,
+        };
+        let tokens = to_json_tokens(&[(style, "fn"), (Style::default(), " é")]);
+        assert_eq!(
+            tokens,
+            json!([
+                {
+                    "start": 0,
+                    "end": 2,
+                    "text": "fn",
+                    "style": {
+                        "foreground": "#ffffffff",
+                        "background": "#000000ff",
+                        "font_style": ["bold", "underline"],
+                        "underline": "squiggly",
+                        "underline_color": null,
+                    },
+                },
+                {
+                    "start": 2,
+                    "end": 5,
+                    "text": " é",
+                    "style": style_to_json(&Style::default()),
+                },
+            ])
+        );
+    }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn test_ops_to_json_tokens() {
+        use crate::highlighting::ThemeSet;
+        use crate::parsing::{ParseState, SyntaxSet};
+
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+        let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+        let mut stack = ScopeStack::new();
+
+        let line = "let x = 1;\n";
+        let ops = state.parse_line(line, &ss).unwrap();
+        let tokens = ops_to_json_tokens(line, &ops, &mut stack, None).unwrap();
+        let tokens = tokens.as_array().unwrap();
+        assert_eq!(tokens[0]["text"], "let");
+        assert_eq!(
+            tokens[0]["scopes"],
+            json!(["source.rust", "storage.type.rust"])
+        );
+        assert!(tokens[0].get("style").is_none());
+        let text: String = tokens.iter().map(|t| t["text"].as_str().unwrap()).collect();
+        assert_eq!(text, line);
+
+        let line = "// done\n";
+        let ops = state.parse_line(line, &ss).unwrap();
+        let tokens = ops_to_json_tokens(line, &ops, &mut stack, Some(&highlighter)).unwrap();
+        let comment = &tokens[1];
+        assert_eq!(comment["start"], 2);
+        assert_eq!(comment["text"], " done\n");
+        assert_eq!(comment["style"]["foreground"], "#65737eff");
+    }
+
+    #[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+    #[test]
+    fn strips_styles_of_highlighted_text() {
+        use crate::easy::HighlightLines;
+        use crate::highlighting::ThemeSet;
+        use crate::parsing::SyntaxSet;
+
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let mut h = HighlightLines::new(syntax, &ts.themes["base16-ocean.dark"]);
+        let text = "fn caf\u{e9}() {\n    \"\u{1F600}\" // \u{2713}\n}\n";
+        let mut regions = Vec::new();
+        for line in LinesWithEndings::from(text) {
+            regions.extend(h.highlight_line(line, &ss).unwrap());
+        }
+        let (stripped, ranges) = strip_styles(&regions);
+        assert_eq!(stripped, text);
+        assert_eq!(ranges.len(), regions.len());
+        for (range, (_, s)) in ranges.iter().zip(&regions) {
+            assert_eq!(&text[range.clone()], *s);
+        }
+    }
 }
@@ -0,0 +1,290 @@
+//! Reading text colored with ANSI escape sequences, like the output of other command line tools,
+//! into styled fragments that can be passed to the same output functions as highlighted code
+//!
+//! Only SGR sequences (`ESC [ ... m`) change the style: bold, italic, underline and strikethrough
+//! and their resets, the 16 standard colors, 256-color and 24-bit colors for the foreground,
+//! background and underline, and the underline styles of `4:n`. The standard colors have the
+//! values of xterm. Other escape sequences, like cursor movement or OSC hyperlinks, are dropped.
+
+use crate::highlighting::{Color, FontStyle, Style, UnderlineStyle};
+
+/// Splits text colored with ANSI escape sequences into styled fragments without the escapes
+///
+/// The text starts with, and `ESC [ 0 m` resets to, [`Style::default`]. See
+/// [`parse_ansi_with_style`] to use another style, like the default style of a theme.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{Color, FontStyle};
+/// use syntect::util::ansi::parse_ansi;
+///
+/// let regions = parse_ansi("plain \x1b[1;31mbold red\x1b[0m");
+/// assert_eq!(regions.len(), 2);
+/// assert_eq!(regions[1].1, "bold red");
+/// assert_eq!(regions[1].0.font_style, FontStyle::BOLD);
+/// assert_eq!(regions[1].0.foreground, Color { r: 205, g: 0, b: 0, a: 0xFF });
+/// ```
+///
+/// [`Style::default`]: ../../highlighting/struct.Style.html
+/// [`parse_ansi_with_style`]: fn.parse_ansi_with_style.html
+pub fn parse_ansi(text: &str) -> Vec<(Style, &str)> {
+    parse_ansi_with_style(text, Style::default())
+}
+
+/// Like [`parse_ansi`], but with the style the text starts with and that resets go back to
+///
+/// [`parse_ansi`]: fn.parse_ansi.html
+pub fn parse_ansi_with_style(text: &str, default: Style) -> Vec<(Style, &str)> {
+    let mut regions = Vec::new();
+    let mut style = default;
+    let mut rest = text;
+    while let Some(esc) = rest.find('\x1b') {
+        if esc > 0 {
+            regions.push((style, &rest[..esc]));
+        }
+        let (len, sgr) = escape_sequence(&rest[esc..]);
+        if let Some(params) = sgr {
+            apply_sgr(&mut style, &default, params);
+        }
+        rest = &rest[esc + len..];
+    }
+    if !rest.is_empty() {
+        regions.push((style, rest));
+    }
+    regions
+}
+
+/// Returns the length of the escape sequence at the start of `s`, and its parameters if it's an
+/// SGR sequence
+fn escape_sequence(s: &str) -> (usize, Option<&str>) {
+    let bytes = s.as_bytes();
+    match bytes.get(1) {
+        Some(b'[') => {
+            // parameter and intermediate bytes, then a final byte
+            let end = bytes[2..]
+                .iter()
+                .position(|b| (0x40..=0x7E).contains(b))
+                .map_or(s.len(), |i| i + 2);
+            match bytes.get(end) {
+                Some(b'm') => (end + 1, Some(&s[2..end])),
+                Some(_) => (end + 1, None),
+                None => (end, None),
+            }
+        }
+        Some(b']') => {
+            // OSC, ended by BEL or ST (`ESC \`)
+            let end = match s[2..].find(['\x07', '\x1b']) {
+                Some(i) if bytes[i + 2] == 0x07 => i + 3,
+                Some(i) if bytes.get(i + 3) == Some(&b'\\') => i + 4,
+                Some(i) => i + 2,
+                None => s.len(),
+            };
+            (end, None)
+        }
+        Some(_) => {
+            // intermediate bytes, like the `(` of `ESC ( B`, then a final character
+            let start = 1 + bytes[1..]
+                .iter()
+                .take_while(|b| (0x20..=0x2F).contains(*b))
+                .count();
+            (
+                start + s[start..].chars().next().map_or(0, char::len_utf8),
+                None,
+            )
+        }
+        None => (1, None),
+    }
+}
+
+fn apply_sgr(style: &mut Style, default: &Style, params: &str) {
+    let mut params = params.split(';');
+    while let Some(param) = params.next() {
+        let mut sub = param.split(':');
+        let code: u8 = match sub.next() {
+            Some("") | None => 0,
+            Some(code) => match code.parse() {
+                Ok(code) => code,
+                Err(_) => continue,
+            },
+        };
+        match code {
+            0 => *style = *default,
+            1 => style.font_style.insert(FontStyle::BOLD),
+            3 => style.font_style.insert(FontStyle::ITALIC),
+            4 => {
+                let underline = match sub.next() {
+                    None | Some("1") | Some("2") => Some(None),
+                    Some("3") => Some(Some(UnderlineStyle::Squiggly)),
+                    Some("4") | Some("5") => Some(Some(UnderlineStyle::Stippled)),
+                    Some(_) => None,
+                };
+                match underline {
+                    Some(underline) => {
+                        style.font_style.insert(FontStyle::UNDERLINE);
+                        style.underline = underline;
+                    }
+                    None => style.font_style.remove(FontStyle::UNDERLINE),
+                }
+            }
+            9 => style.font_style.insert(FontStyle::STRIKETHROUGH),
+            22 => style.font_style.remove(FontStyle::BOLD),
+            23 => style.font_style.remove(FontStyle::ITALIC),
+            24 => style.font_style.remove(FontStyle::UNDERLINE),
+            29 => style.font_style.remove(FontStyle::STRIKETHROUGH),
+            30..=37 => style.foreground = ansi_color(code - 30),
+            39 => style.foreground = default.foreground,
+            40..=47 => style.background = ansi_color(code - 40),
+            49 => style.background = default.background,
+            90..=97 => style.foreground = ansi_color(code - 90 + 8),
+            100..=107 => style.background = ansi_color(code - 100 + 8),
+            38 | 48 | 58 => {
+                let sub: Vec<&str> = sub.collect();
+                let color = match sub.split_first() {
+                    None => extended_color(&mut params),
+                    Some((&mode, values)) => {
+                        // `38:2:r:g:b`, or `38:2:colorspace:r:g:b` as in ITU T.416
+                        let skip = usize::from(mode == "2" && values.len() > 3);
+                        extended_color_of(mode, &mut values[skip..].iter().copied())
+                    }
+                };
+                if let Some(color) = color {
+                    match code {
+                        38 => style.foreground = color,
+                        48 => style.background = color,
+                        _ => style.underline_color = Some(color),
+                    }
+                }
+            }
+            59 => style.underline_color = default.underline_color,
+            _ => {}
+        }
+    }
+}
+
+/// Reads a semicolon separated extended color, like the `5;n` or `2;r;g;b` after `38`
+fn extended_color<'a>(params: &mut impl Iterator<Item = &'a str>) -> Option<Color> {
+    let mode = params.next()?;
+    extended_color_of(mode, params)
+}
+
+fn extended_color_of<'a>(mode: &str, values: &mut impl Iterator<Item = &'a str>) -> Option<Color> {
+    let mut value = || values.next().and_then(|v| v.parse::<u8>().ok());
+    match mode {
+        "5" => value().map(color_256),
+        "2" => Some(Color {
+            r: value()?,
+            g: value()?,
+            b: value()?,
+            a: 0xFF,
+        }),
+        _ => None,
+    }
+}
+
+/// The xterm values of the 16 standard colors
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+fn ansi_color(index: u8) -> Color {
+    let (r, g, b) = ANSI_COLORS[index as usize];
+    Color { r, g, b, a: 0xFF }
+}
+
+/// The color of an index of the 256-color palette: the standard colors, a 6x6x6 color cube and a
+/// grayscale ramp
+fn color_256(index: u8) -> Color {
+    match index {
+        0..=15 => ansi_color(index),
+        16..=231 => {
+            let level = |i: u8| if i == 0 { 0 } else { 55 + 40 * i };
+            let i = index - 16;
+            Color {
+                r: level(i / 36),
+                g: level(i / 6 % 6),
+                b: level(i % 6),
+                a: 0xFF,
+            }
+        }
+        _ => {
+            let v = 8 + 10 * (index - 232);
+            Color {
+                r: v,
+                g: v,
+                b: v,
+                a: 0xFF,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 0xFF }
+    }
+
+    #[test]
+    fn parses_sgr_sequences() {
+        let text = "a\x1b[1;3;4:3;38;5;196;48;2;1;2;3mb\x1b[22;23;24;39mc\x1b[mdef";
+        let regions = parse_ansi(text);
+        let texts: Vec<&str> = regions.iter().map(|r| r.1).collect();
+        assert_eq!(texts, ["a", "b", "c", "def"]);
+        assert_eq!(regions[0].0, Style::default());
+
+        let b = regions[1].0;
+        assert_eq!(
+            b.font_style,
+            FontStyle::BOLD | FontStyle::ITALIC | FontStyle::UNDERLINE
+        );
+        assert_eq!(b.underline, Some(UnderlineStyle::Squiggly));
+        assert_eq!(b.foreground, rgb(255, 0, 0));
+        assert_eq!(b.background, rgb(1, 2, 3));
+
+        let c = regions[2].0;
+        assert_eq!(c.font_style, FontStyle::empty());
+        assert_eq!(c.foreground, Style::default().foreground);
+        assert_eq!(c.background, rgb(1, 2, 3));
+
+        assert_eq!(regions[3].0, Style::default());
+    }
+
+    #[test]
+    fn parses_colon_separated_colors() {
+        let regions = parse_ansi("\x1b[38:2::10:20:30;58:5:232;97mx");
+        assert_eq!(regions[0].0.foreground, rgb(255, 255, 255));
+        assert_eq!(regions[0].0.underline_color, Some(rgb(8, 8, 8)));
+        let regions = parse_ansi("\x1b[38:2:10:20:30mx");
+        assert_eq!(regions[0].0.foreground, rgb(10, 20, 30));
+        let regions = parse_ansi("\x1b[48;5;16;38;5;231mx");
+        assert_eq!(regions[0].0.background, rgb(0, 0, 0));
+        assert_eq!(regions[0].0.foreground, rgb(255, 255, 255));
+    }
+
+    #[test]
+    fn drops_other_escape_sequences() {
+        let text = "\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x07\x1b[2K\x1b(Bdone\x1b[";
+        let regions = parse_ansi(text);
+        let texts: Vec<&str> = regions.iter().map(|r| r.1).collect();
+        assert_eq!(texts, ["link", "done"]);
+        assert!(regions.iter().all(|r| r.0 == Style::default()));
+    }
+}
@@ -0,0 +1,169 @@
+use super::LinesWithEndings;
+use crate::parsing::{Scope, ScopeError, ScopeStack, ScopeStackOp};
+
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Finds the bracket matching the one at a byte offset of a text, from the operations returned by
+/// [`ParseState::parse_line`] for each of its lines
+///
+/// The brackets are `()`, `[]` and `{}`. Brackets in strings and comments are skipped when
+/// looking for the match of a bracket in code, so a `(` in a string doesn't break matching the
+/// parentheses around it. The other way around, a bracket in a string or a comment is matched
+/// only with brackets that are in strings or comments too.
+///
+/// `ops` has the operations of each line of `text`, split like [`LinesWithEndings`] does. Returns
+/// the byte offset of the matching bracket, or `None` if there's no bracket at `pos` or it has no
+/// match.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ParseState, SyntaxSet};
+/// use syntect::util::{find_matching_bracket, LinesWithEndings};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let text = "f(\")\", g(x)) // )\n";
+/// let ops: Vec<_> = LinesWithEndings::from(text)
+///     .map(|line| state.parse_line(line, &ss).unwrap())
+///     .collect();
+///
+/// assert_eq!(find_matching_bracket(text, 1, &ops).unwrap(), Some(11));
+/// assert_eq!(find_matching_bracket(text, 11, &ops).unwrap(), Some(1));
+/// assert_eq!(find_matching_bracket(text, 0, &ops).unwrap(), None);
+/// ```
+///
+/// [`ParseState::parse_line`]: ../parsing/struct.ParseState.html#method.parse_line
+/// [`LinesWithEndings`]: struct.LinesWithEndings.html
+pub fn find_matching_bracket(
+    text: &str,
+    pos: usize,
+    ops: &[Vec<(usize, ScopeStackOp)>],
+) -> Result<Option<usize>, ScopeError> {
+    let brackets = find_brackets(text, ops)?;
+    let start = match brackets.iter().position(|b| b.pos == pos) {
+        Some(start) => start,
+        None => return Ok(None),
+    };
+    let bracket = &brackets[start];
+    let (open, close) = PAIRS
+        .iter()
+        .copied()
+        .find(|&(open, close)| bracket.c == open || bracket.c == close)
+        .unwrap();
+    let same_kind = |b: &&Bracket| b.skipped == bracket.skipped && (b.c == open || b.c == close);
+    let mut depth = 0usize;
+    let matching = if bracket.c == open {
+        brackets[start..]
+            .iter()
+            .filter(same_kind)
+            .find(|b| matches_at_depth(&mut depth, b.c == open))
+    } else {
+        brackets[..=start]
+            .iter()
+            .rev()
+            .filter(same_kind)
+            .find(|b| matches_at_depth(&mut depth, b.c == close))
+    };
+    Ok(matching.map(|b| b.pos))
+}
+
+struct Bracket {
+    pos: usize,
+    c: char,
+    /// Whether the bracket is in a string or a comment
+    skipped: bool,
+}
+
+/// Counts a bracket going away from the one being matched, returning true once it's the match
+fn matches_at_depth(depth: &mut usize, same_as_start: bool) -> bool {
+    if same_as_start {
+        *depth += 1;
+        false
+    } else {
+        *depth -= 1;
+        *depth == 0
+    }
+}
+
+fn find_brackets(
+    text: &str,
+    ops: &[Vec<(usize, ScopeStackOp)>],
+) -> Result<Vec<Bracket>, ScopeError> {
+    let string = Scope::new("string").unwrap();
+    let comment = Scope::new("comment").unwrap();
+    let mut stack = ScopeStack::new();
+    let mut brackets = Vec::new();
+    let mut offset = 0;
+    for (line, line_ops) in LinesWithEndings::from(text).zip(ops) {
+        let mut pos = 0;
+        // the text before each operation, and after the last one
+        let segments = line_ops.iter().map(|(i, op)| (*i, Some(op)));
+        for (end, op) in segments.chain(Some((line.len(), None))) {
+            let skipped = stack
+                .as_slice()
+                .iter()
+                .any(|&s| string.is_prefix_of(s) || comment.is_prefix_of(s));
+            for (i, c) in line[pos..end].char_indices() {
+                if PAIRS.iter().any(|&(open, close)| c == open || c == close) {
+                    brackets.push(Bracket {
+                        pos: offset + pos + i,
+                        c,
+                        skipped,
+                    });
+                }
+            }
+            pos = end;
+            if let Some(op) = op {
+                stack.apply(op)?;
+            }
+        }
+        offset += line.len();
+    }
+    Ok(brackets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{ParseState, SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn skips_brackets_in_strings_and_comments() {
+        let yaml = r#"
+name: brackets
+scope: source.brackets
+file_extensions: [brackets]
+contexts:
+  main:
+    - match: '"'
+      push:
+        - meta_scope: string.quoted.double
+        - match: '"'
+          pop: true
+    - match: '#.*'
+      scope: comment.line
+"#;
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(SyntaxDefinition::load_from_str(yaml, true, None).unwrap());
+        let ss = builder.build();
+        let mut state = ParseState::new(ss.find_syntax_by_name("brackets").unwrap());
+        let text = "{ [\"}(\" # ]\n( ) ] \"(x)\" }\n";
+        let ops: Vec<_> = LinesWithEndings::from(text)
+            .map(|line| state.parse_line(line, &ss).unwrap())
+            .collect();
+        let find = |c: &str| {
+            let pos = text.find(c).unwrap();
+            find_matching_bracket(text, pos, &ops).unwrap()
+        };
+
+        assert_eq!(find("{"), Some(text.rfind('}').unwrap()));
+        assert_eq!(find("["), Some(text.rfind(']').unwrap()));
+        assert_eq!(find("( )"), Some(text.find(" )").unwrap() + 1));
+        // the `}` in the first string has no match in strings, whatever is around it
+        assert_eq!(find("}("), None);
+        assert_eq!(find("(x"), Some(text.rfind(')').unwrap()));
+        assert_eq!(find("x"), None);
+        assert_eq!(find_matching_bracket(text, text.len(), &ops).unwrap(), None);
+    }
+}
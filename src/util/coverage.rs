@@ -0,0 +1,140 @@
+use super::LinesWithEndings;
+use crate::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+use crate::Error;
+use std::collections::{BTreeMap, HashMap};
+
+/// How much of a text a syntax assigns scopes to, returned by [`scope_coverage`]
+///
+/// [`scope_coverage`]: fn.scope_coverage.html
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeCoverage {
+    /// The length of the text in bytes
+    pub total_bytes: usize,
+    /// The number of bytes that are in some scope other than the top-level scope of the syntax
+    pub covered_bytes: usize,
+    /// How many times each scope was pushed, by the full name of the scope
+    pub scope_counts: BTreeMap<String, usize>,
+    /// The lines, starting at 1, which have text other than whitespace but none of it in a scope
+    /// other than the top-level one, i.e. that came out as plain text
+    pub plain_lines: Vec<usize>,
+}
+
+impl ScopeCoverage {
+    /// The percentage of bytes that are covered, or 100 for an empty text
+    pub fn percentage(&self) -> f64 {
+        if self.total_bytes == 0 {
+            return 100.0;
+        }
+        self.covered_bytes as f64 * 100.0 / self.total_bytes as f64
+    }
+}
+
+/// Parses a text and measures how much of it gets scopes beyond the top-level scope of the syntax
+///
+/// This is meant for maintainers of syntax definitions, to see how complete a syntax is on
+/// sample files and to notice when a change makes parts of them fall back to plain text. The
+/// text is split into lines like [`LinesWithEndings`] does, so the syntax set should be loaded
+/// with newlines.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::util::scope_coverage;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let syntax = ss.find_syntax_by_extension("rs").unwrap();
+/// let coverage = scope_coverage("fn main() {\n    42\n}\n", syntax, &ss).unwrap();
+/// assert!(coverage.percentage() > 50.0);
+/// assert_eq!(coverage.scope_counts["constant.numeric.integer.decimal.rust"], 1);
+/// assert!(coverage.plain_lines.is_empty());
+/// ```
+///
+/// [`LinesWithEndings`]: struct.LinesWithEndings.html
+pub fn scope_coverage(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<ScopeCoverage, Error> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut counts: HashMap<Scope, usize> = HashMap::new();
+    let mut coverage = ScopeCoverage {
+        total_bytes: text.len(),
+        ..ScopeCoverage::default()
+    };
+    for (index, line) in LinesWithEndings::from(text).enumerate() {
+        let ops = state.parse_line(line, syntax_set)?;
+        let mut has_text = false;
+        let mut has_covered_text = false;
+        let mut pos = 0;
+        // the text before each operation, and after the last one
+        let segments = ops.iter().map(|(i, op)| (*i, Some(op)));
+        for (end, op) in segments.chain(Some((line.len(), None))) {
+            let segment = &line[pos..end];
+            let is_text = segment.chars().any(|c| !c.is_whitespace());
+            has_text |= is_text;
+            if stack.len() > 1 {
+                coverage.covered_bytes += segment.len();
+                has_covered_text |= is_text;
+            }
+            pos = end;
+            if let Some(op) = op {
+                if let ScopeStackOp::Push(scope) = op {
+                    *counts.entry(*scope).or_default() += 1;
+                }
+                stack.apply(op)?;
+            }
+        }
+        if has_text && !has_covered_text {
+            coverage.plain_lines.push(index + 1);
+        }
+    }
+    coverage.scope_counts = counts
+        .into_iter()
+        .map(|(scope, count)| (scope.build_string(), count))
+        .collect();
+    Ok(coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn measures_scope_coverage() {
+        let yaml = r"
+name: words
+scope: source.words
+file_extensions: [words]
+contexts:
+  main:
+    - match: '\d+'
+      scope: constant.numeric
+    - match: '\(\w+'
+      push:
+        - meta_scope: meta.group
+        - match: '\)'
+          pop: true
+";
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(SyntaxDefinition::load_from_str(yaml, true, None).unwrap());
+        let ss = builder.build();
+        let syntax = ss.find_syntax_by_name("words").unwrap();
+
+        let text = "a 12\n(b\nc)\n\nplain\n";
+        let coverage = scope_coverage(text, syntax, &ss).unwrap();
+        assert_eq!(coverage.total_bytes, text.len());
+        // "12", "(b\nc)"
+        assert_eq!(coverage.covered_bytes, 7);
+        assert_eq!(coverage.scope_counts["constant.numeric"], 1);
+        assert_eq!(coverage.scope_counts["meta.group"], 1);
+        assert_eq!(coverage.scope_counts["source.words"], 1);
+        assert_eq!(coverage.plain_lines, [5]);
+        assert!((coverage.percentage() - 7.0 * 100.0 / 18.0).abs() < 1e-9);
+
+        let empty = scope_coverage("", syntax, &ss).unwrap();
+        assert_eq!(empty.percentage(), 100.0);
+    }
+}
@@ -0,0 +1,263 @@
+//! Highlighting of unified diffs, like the output of `git diff`, using the syntax of the files
+//! being changed
+
+use super::{blend_fg_color, LinesWithEndings};
+use crate::easy::HighlightLines;
+use crate::highlighting::{Color, Highlighter, Style, Theme};
+use crate::parsing::{ScopeStack, SyntaxReference, SyntaxSet};
+use crate::Error;
+use std::path::Path;
+use std::str::FromStr;
+
+/// What a line of a unified diff is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DiffLineKind {
+    /// A line which isn't part of either file, like the `diff --git`, `---` and `+++` headers,
+    /// or `\ No newline at end of file`
+    Header,
+    /// The `@@ -1,2 +1,3 @@` line starting a hunk
+    Hunk,
+    /// A line only in the new file, starting with `+`
+    Added,
+    /// A line only in the old file, starting with `-`
+    Removed,
+    /// An unchanged line, starting with a space
+    Context,
+}
+
+/// A highlighted line of a unified diff
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffLine<'a> {
+    pub kind: DiffLineKind,
+    /// The styled regions of the line, including the leading `+`, `-` or space
+    pub regions: Vec<(Style, &'a str)>,
+}
+
+/// Highlights a unified diff, using the syntax of each changed file for the lines of the file.
+///
+/// The syntax is picked based on the file name in the `+++` header, or the `---` one for removed
+/// files, falling back to plain text. Added and removed lines get a background tinted with the
+/// color the theme uses for `markup.inserted` and `markup.deleted`. The old and new versions of
+/// each hunk are highlighted separately, so that e.g. a removed line opening a string doesn't
+/// affect the highlighting of the added lines.
+///
+/// Like [`highlighted_html_for_string`], this expects a syntax set loaded with newlines, see
+/// [`SyntaxSet::load_defaults_newlines`].
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::ThemeSet;
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::util::diff::{highlight_unified_diff, DiffLineKind};
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let diff = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1 +1 @@\n-fn main() {}\n+fn main() { run() }\n";
+/// let lines = highlight_unified_diff(diff, &ss, &ts.themes["base16-ocean.dark"]).unwrap();
+/// assert_eq!(lines[4].kind, DiffLineKind::Added);
+/// ```
+///
+/// [`highlighted_html_for_string`]: ../../html/fn.highlighted_html_for_string.html
+/// [`SyntaxSet::load_defaults_newlines`]: ../../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+pub fn highlight_unified_diff<'a>(
+    diff: &'a str,
+    syntax_set: &SyntaxSet,
+    theme: &Theme,
+) -> Result<Vec<DiffLine<'a>>, Error> {
+    let highlighter = Highlighter::new(theme);
+    let default = highlighter.get_default();
+    let header_style = scope_style(&highlighter, "meta.diff.header");
+    let hunk_style = scope_style(&highlighter, "meta.diff.range");
+    let added_bg = diff_background(&highlighter, "markup.inserted", default.background);
+    let removed_bg = diff_background(&highlighter, "markup.deleted", default.background);
+
+    let mut syntax = syntax_set.find_syntax_plain_text();
+    let mut old_path = None;
+    let mut old = HighlightLines::new(syntax, theme);
+    let mut new = HighlightLines::new(syntax, theme);
+    // The lines of each side left in the current hunk
+    let mut old_remaining: usize = 0;
+    let mut new_remaining: usize = 0;
+
+    let mut lines = Vec::new();
+    for line in LinesWithEndings::from(diff) {
+        let in_hunk = old_remaining > 0 || new_remaining > 0;
+        let (kind, regions) = if in_hunk && line.starts_with([' ', '\n', '\r']) {
+            old_remaining = old_remaining.saturating_sub(1);
+            new_remaining = new_remaining.saturating_sub(1);
+            let content = line.get(1..).unwrap_or("");
+            old.highlight_line(content, syntax_set)?;
+            let regions = marked(
+                default,
+                &line[..1],
+                new.highlight_line(content, syntax_set)?,
+            );
+            (DiffLineKind::Context, regions)
+        } else if in_hunk && line.starts_with('+') {
+            new_remaining = new_remaining.saturating_sub(1);
+            let mut regions = new.highlight_line(&line[1..], syntax_set)?;
+            set_background(&mut regions, added_bg);
+            let marker = Style {
+                background: added_bg,
+                ..default
+            };
+            (DiffLineKind::Added, marked(marker, &line[..1], regions))
+        } else if in_hunk && line.starts_with('-') {
+            old_remaining = old_remaining.saturating_sub(1);
+            let mut regions = old.highlight_line(&line[1..], syntax_set)?;
+            set_background(&mut regions, removed_bg);
+            let marker = Style {
+                background: removed_bg,
+                ..default
+            };
+            (DiffLineKind::Removed, marked(marker, &line[..1], regions))
+        } else if let Some((old_len, new_len)) = parse_hunk_header(line) {
+            old_remaining = old_len;
+            new_remaining = new_len;
+            old = HighlightLines::new(syntax, theme);
+            new = HighlightLines::new(syntax, theme);
+            (DiffLineKind::Hunk, vec![(hunk_style, line)])
+        } else {
+            if let Some(path) = line.strip_prefix("--- ") {
+                old_path = Some(header_path(path));
+            } else if let Some(path) = line.strip_prefix("+++ ") {
+                let path = match header_path(path) {
+                    "/dev/null" => old_path.unwrap_or("/dev/null"),
+                    path => path,
+                };
+                syntax = syntax_for_path(syntax_set, path);
+            }
+            (DiffLineKind::Header, vec![(header_style, line)])
+        };
+        lines.push(DiffLine { kind, regions });
+    }
+    Ok(lines)
+}
+
+fn scope_style(highlighter: &Highlighter, scope: &str) -> Style {
+    match ScopeStack::from_str(&format!("source.diff {}", scope)) {
+        Ok(stack) => highlighter.style_for_stack(stack.as_slice()),
+        // Only happens when the scope repository is full
+        Err(_) => highlighter.get_default(),
+    }
+}
+
+/// The theme's background for the scope if it has one, otherwise a tint of its foreground
+fn diff_background(highlighter: &Highlighter, scope: &str, default_bg: Color) -> Color {
+    let style = scope_style(highlighter, scope);
+    if style.background != default_bg {
+        return style.background;
+    }
+    blend_fg_color(
+        Color {
+            a: 0x40,
+            ..style.foreground
+        },
+        default_bg,
+    )
+}
+
+fn set_background(regions: &mut [(Style, &str)], background: Color) {
+    for (style, _) in regions {
+        style.background = background;
+    }
+}
+
+fn marked<'a>(
+    style: Style,
+    marker: &'a str,
+    mut regions: Vec<(Style, &'a str)>,
+) -> Vec<(Style, &'a str)> {
+    regions.insert(0, (style, marker));
+    regions
+}
+
+/// Returns the number of old and new lines in the hunk a `@@ -1,2 +1,3 @@` line starts
+fn parse_hunk_header(line: &str) -> Option<(usize, usize)> {
+    let mut parts = line.strip_prefix("@@ ")?.split(' ');
+    let old = parts.next()?.strip_prefix('-')?;
+    let new = parts.next()?.strip_prefix('+')?;
+    let len = |range: &str| match range.split_once(',') {
+        Some((_, len)) => len.parse().ok(),
+        None => range.parse::<usize>().ok().map(|_| 1),
+    };
+    Some((len(old)?, len(new)?))
+}
+
+/// Strips the `a/` or `b/` prefix git adds, and any timestamp after a tab
+fn header_path(path: &str) -> &str {
+    let path = path.split('\t').next().unwrap_or(path).trim_end();
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path)
+}
+
+fn syntax_for_path<'a>(syntax_set: &'a SyntaxSet, path: &str) -> &'a SyntaxReference {
+    let path = Path::new(path);
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    syntax_set
+        .find_syntax_by_extension(file_name)
+        .or_else(|| syntax_set.find_syntax_by_extension(extension))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text())
+}
+
+#[cfg(all(feature = "default-syntaxes", feature = "default-themes"))]
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::ThemeSet;
+
+    #[test]
+    fn parses_hunk_headers() {
+        assert_eq!(
+            parse_hunk_header("@@ -1,2 +3,4 @@ fn main() {\n"),
+            Some((2, 4))
+        );
+        assert_eq!(parse_hunk_header("@@ -1 +1 @@\n"), Some((1, 1)));
+        assert_eq!(parse_hunk_header("@@ -0,0 +1,3 @@\n"), Some((0, 3)));
+        assert_eq!(parse_hunk_header("--- a/file\n"), None);
+    }
+
+    #[test]
+    fn highlights_with_the_file_syntax() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let ts = ThemeSet::load_defaults();
+        let theme = &ts.themes["base16-ocean.dark"];
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,3 @@
+ fn main() {
+--- removed comment
++    let s = \"added\";
+ }
+";
+        let lines = highlight_unified_diff(diff, &ss, theme).unwrap();
+        let kinds: Vec<DiffLineKind> = lines.iter().map(|l| l.kind).collect();
+        use DiffLineKind::*;
+        assert_eq!(
+            kinds,
+            vec![Header, Header, Header, Hunk, Context, Removed, Added, Context]
+        );
+        for line in &lines {
+            let text: String = line.regions.iter().map(|(_, s)| *s).collect();
+            assert!(diff.contains(&text));
+        }
+
+        // the Rust syntax is used, so the string gets its own region
+        let added = &lines[6];
+        assert_eq!(added.regions[0].1, "+");
+        let string = added.regions.iter().find(|(_, s)| *s == "added").unwrap();
+        assert_ne!(string.0.foreground, added.regions[1].0.foreground);
+        let background = added.regions[0].0.background;
+        assert_ne!(background, theme.settings.background.unwrap());
+        assert!(added
+            .regions
+            .iter()
+            .all(|(s, _)| s.background == background));
+    }
+}
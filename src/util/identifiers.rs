@@ -0,0 +1,117 @@
+use super::LinesWithEndings;
+use crate::highlighting::ScopeSelectors;
+use crate::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use crate::Error;
+use std::collections::HashSet;
+
+/// Collects the tokens of a text that are in the scopes matched by a selector, like the names of
+/// variables and functions, for completing words from the same buffer
+///
+/// Text under the selector that isn't interrupted by text outside of it makes one token, so e.g.
+/// `$name` in PHP is one token even though the `$` has a scope of its own. Tokens are trimmed of
+/// whitespace and don't span lines. The text is split into lines like [`LinesWithEndings`] does,
+/// so the syntax set should be loaded with newlines.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::ScopeSelectors;
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::util::extract_identifiers;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let syntax = ss.find_syntax_by_extension("py").unwrap();
+/// let filter: ScopeSelectors = "entity.name.function, variable.parameter".parse().unwrap();
+/// let text = "def area(width, height):\n    return width * height\n";
+/// let identifiers = extract_identifiers(text, syntax, &ss, &filter).unwrap();
+///
+/// let mut sorted: Vec<_> = identifiers.into_iter().collect();
+/// sorted.sort();
+/// assert_eq!(sorted, ["area", "height", "width"]);
+/// ```
+///
+/// [`LinesWithEndings`]: struct.LinesWithEndings.html
+pub fn extract_identifiers(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+    scope_filter: &ScopeSelectors,
+) -> Result<HashSet<String>, Error> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut identifiers = HashSet::new();
+    for line in LinesWithEndings::from(text) {
+        let ops = state.parse_line(line, syntax_set)?;
+        let mut token = String::new();
+        let mut pos = 0;
+        // the text before each operation, and after the last one
+        let segments = ops.iter().map(|(i, op)| (*i, Some(op)));
+        for (end, op) in segments.chain(Some((line.len(), None))) {
+            let segment = &line[pos..end];
+            if !segment.is_empty() {
+                if scope_filter.does_match(stack.as_slice()).is_some() {
+                    token.push_str(segment);
+                } else {
+                    add_token(&mut identifiers, &mut token);
+                }
+            }
+            pos = end;
+            if let Some(op) = op {
+                stack.apply(op)?;
+            }
+        }
+        add_token(&mut identifiers, &mut token);
+    }
+    Ok(identifiers)
+}
+
+fn add_token(identifiers: &mut HashSet<String>, token: &mut String) {
+    let trimmed = token.trim();
+    if !trimmed.is_empty() && !identifiers.contains(trimmed) {
+        identifiers.insert(trimmed.to_owned());
+    }
+    token.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::{SyntaxDefinition, SyntaxSetBuilder};
+
+    #[test]
+    fn joins_nested_scopes_and_splits_at_other_text() {
+        let yaml = r"
+name: vars
+scope: source.vars
+file_extensions: [vars]
+contexts:
+  main:
+    - match: '(\$)(\w+)'
+      scope: variable.other
+      captures:
+        1: punctuation.definition.variable
+    - match: '\w+(?=\()'
+      scope: entity.name.function
+    - match: '#.*'
+      scope: comment.line
+";
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(SyntaxDefinition::load_from_str(yaml, true, None).unwrap());
+        let ss = builder.build();
+        let syntax = ss.find_syntax_by_name("vars").unwrap();
+        let text = "$a = f($b, $a) # $c\ng($b)$d\n";
+
+        let filter = "variable.other, entity.name".parse().unwrap();
+        let mut identifiers: Vec<_> = extract_identifiers(text, syntax, &ss, &filter)
+            .unwrap()
+            .into_iter()
+            .collect();
+        identifiers.sort();
+        assert_eq!(identifiers, ["$a", "$b", "$d", "f", "g"]);
+
+        let filter = "nothing.matches".parse().unwrap();
+        assert!(extract_identifiers(text, syntax, &ss, &filter)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+//! Finding the parts of a text in each language, for files that embed other languages like
+//! JavaScript in HTML, so that tools like formatters or linters can be run on each part
+
+use super::span_tree::{SpanNode, SpanTreeBuilder};
+use super::LinesWithEndings;
+use crate::parsing::{ParseState, Scope, ScopeError, ScopeStack, ScopeStackOp};
+use crate::parsing::{SyntaxReference, SyntaxSet};
+use crate::Error;
+use std::collections::HashSet;
+use std::ops::{Range, RangeInclusive};
+
+/// A contiguous part of a text in one language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageRegion {
+    /// The scope of the syntax of the language, like `source.js`
+    pub scope: Scope,
+    /// The scopes of the languages the region is embedded in, outermost first, e.g.
+    /// `[text.html.basic]` for JavaScript in HTML
+    pub parents: Vec<Scope>,
+    /// Byte offsets into the text
+    pub range: Range<usize>,
+    /// The lines the region is on, starting at 1 for the first line
+    pub lines: RangeInclusive<usize>,
+}
+
+/// Splits a text into the parts in each language, from the operations returned by
+/// [`ParseState::parse_line`] for each of its lines
+///
+/// A language starts where the scope of a syntax in the set is pushed, like `source.js` when
+/// HTML embeds JavaScript, and the regions are in the order of the text. An embedded language
+/// splits the region it's in, so the HTML before and after a `<script>` tag are separate regions.
+/// Parts of the text in no language, which only happens with unusual syntaxes, aren't included.
+///
+/// `ops` has the operations of each line of `text`, split like [`LinesWithEndings`] does.
+///
+/// [`ParseState::parse_line`]: ../../parsing/struct.ParseState.html#method.parse_line
+/// [`LinesWithEndings`]: ../struct.LinesWithEndings.html
+pub fn language_regions(
+    text: &str,
+    ops: &[Vec<(usize, ScopeStackOp)>],
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<LanguageRegion>, ScopeError> {
+    let mut stack = ScopeStack::new();
+    let mut builder = SpanTreeBuilder::new(&[], 0);
+    let mut offset = 0;
+    for (line, line_ops) in LinesWithEndings::from(text).zip(ops) {
+        for (i, op) in line_ops {
+            builder.apply(&mut stack, op, offset + i)?;
+        }
+        offset += line.len();
+    }
+    let tree = builder.finish(text.len());
+    Ok(regions_from_tree(text, &tree, syntax_set))
+}
+
+/// Parses a whole text and splits it into the parts in each language, see
+/// [`language_regions`]
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::SyntaxSet;
+/// use syntect::util::language_regions::language_regions_for_text;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let html = "<p>hi</p>\n<script>\nvar x = 1;\n</script>\n";
+/// let syntax = ss.find_syntax_by_extension("html").unwrap();
+/// let regions = language_regions_for_text(html, syntax, &ss).unwrap();
+/// let scopes: Vec<_> = regions.iter().map(|r| r.scope.build_string()).collect();
+/// assert_eq!(scopes, ["text.html.basic", "source.js", "text.html.basic"]);
+/// assert_eq!(regions[1].lines, 3..=3);
+/// assert_eq!(&html[regions[1].range.clone()], "var x = 1;\n");
+/// ```
+///
+/// [`language_regions`]: fn.language_regions.html
+pub fn language_regions_for_text(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<LanguageRegion>, Error> {
+    let mut state = ParseState::new(syntax);
+    let ops = LinesWithEndings::from(text)
+        .map(|line| state.parse_line(line, syntax_set))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(language_regions(text, &ops, syntax_set)?)
+}
+
+fn regions_from_tree(text: &str, tree: &[SpanNode], syntax_set: &SyntaxSet) -> Vec<LanguageRegion> {
+    let mut walker = RegionWalker {
+        language_scopes: syntax_set.syntaxes().iter().map(|s| s.scope).collect(),
+        languages: Vec::new(),
+        pos: 0,
+        regions: Vec::new(),
+    };
+    walker.walk(tree);
+    walker.emit_until(text.len());
+
+    let line_starts: Vec<usize> = LinesWithEndings::from(text)
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len();
+            Some(line_start)
+        })
+        .collect();
+    let line_of = |pos: usize| line_starts.partition_point(|&start| start <= pos);
+    for region in &mut walker.regions {
+        region.lines = line_of(region.range.start)..=line_of(region.range.end - 1);
+    }
+    walker.regions
+}
+
+struct RegionWalker {
+    language_scopes: HashSet<Scope>,
+    /// The languages around the current position, outermost first
+    languages: Vec<Scope>,
+    /// The end of the text covered by the regions so far
+    pos: usize,
+    regions: Vec<LanguageRegion>,
+}
+
+impl RegionWalker {
+    fn walk(&mut self, nodes: &[SpanNode]) {
+        for node in nodes {
+            if self.language_scopes.contains(&node.scope) {
+                self.emit_until(node.range.start);
+                self.languages.push(node.scope);
+                self.walk(&node.children);
+                self.emit_until(node.range.end);
+                self.languages.pop();
+            } else {
+                self.walk(&node.children);
+            }
+        }
+    }
+
+    /// Adds the text from the current position to `end` to a region of the innermost language
+    fn emit_until(&mut self, end: usize) {
+        if end <= self.pos {
+            return;
+        }
+        if let Some((&scope, parents)) = self.languages.split_last() {
+            match self.regions.last_mut() {
+                Some(last)
+                    if last.range.end == self.pos
+                        && last.scope == scope
+                        && last.parents == parents =>
+                {
+                    last.range.end = end;
+                }
+                _ => self.regions.push(LanguageRegion {
+                    scope,
+                    parents: parents.to_vec(),
+                    range: self.pos..end,
+                    lines: 0..=0, // set once all the regions are known
+                }),
+            }
+        }
+        self.pos = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsing::SyntaxDefinition;
+    use crate::parsing::SyntaxSetBuilder;
+
+    fn syntax(name: &str, scope: &str, contexts: &str) -> SyntaxDefinition {
+        let yaml = format!(
+            "name: {}\nscope: {}\nfile_extensions: [{}]\ncontexts:\n{}",
+            name, scope, name, contexts
+        );
+        SyntaxDefinition::load_from_str(&yaml, true, None).unwrap()
+    }
+
+    #[test]
+    fn finds_embedded_languages() {
+        let outer = syntax(
+            "outer",
+            "source.outer",
+            r"
+  main:
+    - match: '\{'
+      push: scope:source.inner
+      with_prototype:
+        - match: '(?=\})'
+          pop: true
+",
+        );
+        let inner = syntax(
+            "inner",
+            "source.inner",
+            r"
+  main:
+    - match: '\w+'
+      scope: word
+",
+        );
+        let mut builder = SyntaxSetBuilder::new();
+        builder.add(outer);
+        builder.add(inner);
+        let ss = builder.build();
+        let text = "a {\nb\nc}\nd\n";
+        let syntax = ss.find_syntax_by_name("outer").unwrap();
+        let regions = language_regions_for_text(text, syntax, &ss).unwrap();
+        let outer = Scope::new("source.outer").unwrap();
+        let inner = Scope::new("source.inner").unwrap();
+        assert_eq!(
+            regions,
+            [
+                LanguageRegion {
+                    scope: outer,
+                    parents: vec![],
+                    range: 0..3,
+                    lines: 1..=1,
+                },
+                LanguageRegion {
+                    scope: inner,
+                    parents: vec![outer],
+                    range: 3..7,
+                    lines: 1..=3,
+                },
+                LanguageRegion {
+                    scope: outer,
+                    parents: vec![],
+                    range: 7..text.len(),
+                    lines: 3..=4,
+                },
+            ]
+        );
+    }
+}
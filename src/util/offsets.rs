@@ -0,0 +1,121 @@
+use std::ops::Range;
+
+/// Converts byte offsets in a text into offsets in chars or in UTF-16 code units
+///
+/// syntect's positions, like the ranges of [`RangedHighlightIterator`], are byte offsets in UTF-8
+/// text, while e.g. the Language Server Protocol and JavaScript strings count UTF-16 code units
+/// and many editors count chars. The converter remembers where the last conversion was, so
+/// converting the offsets of a line in order only goes over the line once. Converting an offset
+/// before the last one starts again from the start of the text.
+///
+/// # Panics
+///
+/// Converting an offset panics if it's past the end of the text or in the middle of a char, like
+/// slicing the text there would.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::highlighting::{HighlightState, Highlighter, RangedHighlightIterator, ThemeSet};
+/// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+/// use syntect::util::OffsetConverter;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let ts = ThemeSet::load_defaults();
+/// let highlighter = Highlighter::new(&ts.themes["base16-ocean.dark"]);
+/// let mut state = HighlightState::new(&highlighter, ScopeStack::new());
+/// let mut parse_state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let line = "let s = \"😀\"; 1\n";
+/// let ops = parse_state.parse_line(line, &ss).unwrap();
+///
+/// let mut converter = OffsetConverter::new(line);
+/// let utf16_ranges: Vec<_> =
+///     RangedHighlightIterator::new(&mut state, &ops, line, &highlighter)
+///         .map(|(style, text, range)| (style, text, converter.utf16_range(range)))
+///         .collect();
+/// let (_, _, one) = utf16_ranges.iter().find(|(_, text, _)| *text == "1").unwrap();
+/// // the emoji is 4 bytes but 2 UTF-16 code units
+/// assert_eq!(line.find('1'), Some(16));
+/// assert_eq!(*one, 14..15);
+/// ```
+///
+/// [`RangedHighlightIterator`]: ../highlighting/struct.RangedHighlightIterator.html
+#[derive(Debug, Clone)]
+pub struct OffsetConverter<'a> {
+    text: &'a str,
+    byte: usize,
+    chars: usize,
+    utf16: usize,
+}
+
+impl<'a> OffsetConverter<'a> {
+    pub fn new(text: &'a str) -> OffsetConverter<'a> {
+        OffsetConverter {
+            text,
+            byte: 0,
+            chars: 0,
+            utf16: 0,
+        }
+    }
+
+    /// The number of chars before the byte offset
+    pub fn char_offset(&mut self, byte: usize) -> usize {
+        self.advance(byte);
+        self.chars
+    }
+
+    /// The number of UTF-16 code units before the byte offset
+    pub fn utf16_offset(&mut self, byte: usize) -> usize {
+        self.advance(byte);
+        self.utf16
+    }
+
+    /// Converts a range of bytes into a range of chars
+    pub fn char_range(&mut self, bytes: Range<usize>) -> Range<usize> {
+        let start = self.char_offset(bytes.start);
+        start..self.char_offset(bytes.end)
+    }
+
+    /// Converts a range of bytes into a range of UTF-16 code units
+    pub fn utf16_range(&mut self, bytes: Range<usize>) -> Range<usize> {
+        let start = self.utf16_offset(bytes.start);
+        start..self.utf16_offset(bytes.end)
+    }
+
+    fn advance(&mut self, byte: usize) {
+        if byte < self.byte {
+            *self = OffsetConverter::new(self.text);
+        }
+        for c in self.text[self.byte..byte].chars() {
+            self.chars += 1;
+            self.utf16 += c.len_utf16();
+        }
+        self.byte = byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_offsets_in_any_order() {
+        // 1, 2, 3 and 4 bytes, where only the last is 2 UTF-16 code units
+        let text = "aé€😀b";
+        let mut converter = OffsetConverter::new(text);
+        assert_eq!(converter.char_range(0..1), 0..1);
+        assert_eq!(converter.utf16_range(1..6), 1..3);
+        assert_eq!(converter.char_range(6..10), 3..4);
+        assert_eq!(converter.utf16_range(6..10), 3..5);
+        assert_eq!(converter.utf16_offset(11), 6);
+        // going back starts over
+        assert_eq!(converter.char_offset(3), 2);
+        assert_eq!(converter.utf16_range(0..text.len()), 0..6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_in_the_middle_of_a_char() {
+        OffsetConverter::new("é").char_offset(1);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::parsing::{ParseScopeError, Scope};
+use std::fmt::Write;
+
+/// Formats a stack of scopes as their names separated by spaces, from the outermost to the
+/// innermost, like `source.rust meta.function.rust`
+///
+/// This is the format of the `Display` of [`ScopeStack`] without the trailing space, and
+/// [`parse_scope_stack`] reads it back.
+///
+/// [`ScopeStack`]: ../parsing/struct.ScopeStack.html
+/// [`parse_scope_stack`]: fn.parse_scope_stack.html
+pub fn format_scope_stack(scopes: &[Scope]) -> String {
+    let mut s = String::new();
+    for (i, scope) in scopes.iter().enumerate() {
+        if i > 0 {
+            s.push(' ');
+        }
+        s.push_str(&scope.build_string());
+    }
+    s
+}
+
+/// Parses a stack of scopes written by [`format_scope_stack`], or in the `Debug` format of the
+/// scopes joined by commas, like `<source.rust>, <meta.function.rust>`
+///
+/// Unlike the `FromStr` of [`ScopeStack`], this is strict, so a snapshot that doesn't round trip
+/// is an error instead of a different stack: the scopes have to be separated by a single space or
+/// by a comma and a space, without mixing the two formats, and the names can't contain `<`, `>`
+/// or commas. Whitespace around the whole stack is ignored, and an empty string is an empty
+/// stack.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::Scope;
+/// use syntect::util::{format_scope_stack, parse_scope_stack};
+///
+/// let stack = parse_scope_stack("<source.rust>, <meta.function.rust>").unwrap();
+/// assert_eq!(stack[1], Scope::new("meta.function.rust").unwrap());
+/// let formatted = format_scope_stack(&stack);
+/// assert_eq!(formatted, "source.rust meta.function.rust");
+/// assert_eq!(parse_scope_stack(&formatted).unwrap(), stack);
+/// assert!(parse_scope_stack("source.rust  meta.function.rust").is_err());
+/// ```
+///
+/// [`format_scope_stack`]: fn.format_scope_stack.html
+/// [`ScopeStack`]: ../parsing/struct.ScopeStack.html
+pub fn parse_scope_stack(s: &str) -> Result<Vec<Scope>, ParseScopeError> {
+    let stack = s.trim();
+    if stack.is_empty() {
+        return Ok(Vec::new());
+    }
+    let invalid = || ParseScopeError::InvalidScopeStack(stack.to_owned());
+    let names: Vec<&str> = if stack.starts_with('<') {
+        stack
+            .split(", ")
+            .map(|name| name.strip_prefix('<')?.strip_suffix('>'))
+            .collect::<Option<_>>()
+            .ok_or_else(invalid)?
+    } else {
+        stack.split(' ').collect()
+    };
+    names
+        .into_iter()
+        .map(|name| {
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "<>,".contains(c)) {
+                return Err(invalid());
+            }
+            Scope::new(name)
+        })
+        .collect()
+}
+
+/// Formats the difference between an expected and an actual stack of scopes, one scope per line
+///
+/// The scopes both stacks start with are prefixed with two spaces, followed by the rest of the
+/// expected stack prefixed with `- ` and the rest of the actual one prefixed with `+ `. Once the
+/// stacks differ, the scopes after that are nested differently, so they are listed even if some
+/// of them are the same. Equal stacks have no `-` or `+` lines, which makes this readable in the
+/// failure message of a snapshot test.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::util::{diff_scope_stacks, parse_scope_stack};
+///
+/// let expected = parse_scope_stack("source.rust meta.block.rust").unwrap();
+/// let actual = parse_scope_stack("source.rust string.quoted.rust").unwrap();
+/// assert_eq!(
+///     diff_scope_stacks(&expected, &actual),
+///     "  source.rust\n- meta.block.rust\n+ string.quoted.rust\n"
+/// );
+/// ```
+pub fn diff_scope_stacks(expected: &[Scope], actual: &[Scope]) -> String {
+    let common = expected
+        .iter()
+        .zip(actual)
+        .take_while(|(expected, actual)| expected == actual)
+        .count();
+    let mut diff = String::new();
+    for (prefix, scopes) in [
+        ("  ", &expected[..common]),
+        ("- ", &expected[common..]),
+        ("+ ", &actual[common..]),
+    ] {
+        for scope in scopes {
+            writeln!(diff, "{}{}", prefix, scope).unwrap();
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(names: &[&str]) -> Vec<Scope> {
+        names.iter().map(|name| Scope::new(name).unwrap()).collect()
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        let stack = scopes(&[
+            "source.test",
+            "meta.group.test",
+            "string.quoted.double.test",
+        ]);
+        let formatted = format_scope_stack(&stack);
+        assert_eq!(parse_scope_stack(&formatted).unwrap(), stack);
+        // how the tests of the parser print stacks
+        let debug: Vec<String> = stack.iter().map(|s| format!("{:?}", s)).collect();
+        assert_eq!(parse_scope_stack(&debug.join(", ")).unwrap(), stack);
+        assert_eq!(parse_scope_stack(" source.test\n").unwrap(), &stack[..1]);
+        assert_eq!(format_scope_stack(&[]), "");
+        assert!(parse_scope_stack("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_stacks_that_would_not_round_trip() {
+        for invalid in [
+            "source.test  meta.test",
+            "<source.test> <meta.test>",
+            "<source.test>, meta.test",
+            "<source.test>,<meta.test>",
+            "<source.test meta.test>",
+            "source.test, meta.test",
+            "<>",
+        ] {
+            assert!(
+                matches!(
+                    parse_scope_stack(invalid),
+                    Err(ParseScopeError::InvalidScopeStack(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn diffs_stacks_after_their_common_start() {
+        let expected = scopes(&["source.test", "meta.a.test", "meta.b.test"]);
+        let actual = scopes(&["source.test", "meta.b.test"]);
+        assert_eq!(
+            diff_scope_stacks(&expected, &actual),
+            "  source.test\n- meta.a.test\n- meta.b.test\n+ meta.b.test\n"
+        );
+        assert_eq!(
+            diff_scope_stacks(&actual, &actual),
+            "  source.test\n  meta.b.test\n"
+        );
+    }
+}
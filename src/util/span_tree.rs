@@ -0,0 +1,207 @@
+//! Converting parsed text into a tree of nested scopes, for tools that want to fold, analyze or
+//! transform the structure instead of a flat list of tokens
+
+use super::LinesWithEndings;
+use crate::parsing::{
+    BasicScopeStackOp, ParseState, Scope, ScopeError, ScopeStack, ScopeStackOp, SyntaxReference,
+    SyntaxSet,
+};
+use crate::Error;
+use std::ops::Range;
+
+/// A scope and the part of the text it covers, with the scopes nested inside it
+///
+/// The ranges are byte offsets into the text, and the children are ordered by position and lie
+/// within the range of their parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanNode {
+    pub scope: Scope,
+    pub range: Range<usize>,
+    pub children: Vec<SpanNode>,
+}
+
+/// Builds the tree of the scopes of a line from the operations returned by
+/// [`ParseState::parse_line`]
+///
+/// `stack` holds the scopes open at the start of the line, which start at 0 in the tree, and is
+/// updated to the scopes open at its end, which end at the end of the line. Like when
+/// highlighting, it has to be kept between the lines of a file. Scopes that don't cover any text
+/// are left out.
+///
+/// # Examples
+///
+/// ```
+/// use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+/// use syntect::util::span_tree::ops_to_span_tree;
+///
+/// let ss = SyntaxSet::load_defaults_newlines();
+/// let mut state = ParseState::new(ss.find_syntax_by_extension("rs").unwrap());
+/// let line = "let s = \"hi\";\n";
+/// let ops = state.parse_line(line, &ss).unwrap();
+/// let tree = ops_to_span_tree(line, &ops, &mut ScopeStack::new()).unwrap();
+/// assert_eq!(tree.len(), 1);
+/// assert_eq!(tree[0].scope.build_string(), "source.rust");
+/// let string = tree[0].children.iter().find(|n| n.range == (8..12)).unwrap();
+/// assert!(string.scope.build_string().starts_with("string.quoted.double"));
+/// ```
+///
+/// [`ParseState::parse_line`]: ../../parsing/struct.ParseState.html#method.parse_line
+pub fn ops_to_span_tree(
+    line: &str,
+    ops: &[(usize, ScopeStackOp)],
+    stack: &mut ScopeStack,
+) -> Result<Vec<SpanNode>, ScopeError> {
+    let mut builder = SpanTreeBuilder::new(stack.as_slice(), 0);
+    for (i, op) in ops {
+        builder.apply(stack, op, *i)?;
+    }
+    Ok(builder.finish(line.len()))
+}
+
+/// Parses a whole text and builds the tree of its scopes, with ranges that are byte offsets into
+/// the text, so scopes can span several lines
+///
+/// Like [`highlighted_html_for_string`], this expects a syntax set loaded with newlines, see
+/// [`SyntaxSet::load_defaults_newlines`].
+///
+/// [`highlighted_html_for_string`]: ../../html/fn.highlighted_html_for_string.html
+/// [`SyntaxSet::load_defaults_newlines`]: ../../parsing/struct.SyntaxSet.html#method.load_defaults_newlines
+pub fn span_tree_for_text(
+    text: &str,
+    syntax: &SyntaxReference,
+    syntax_set: &SyntaxSet,
+) -> Result<Vec<SpanNode>, Error> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut builder = SpanTreeBuilder::new(&[], 0);
+    let mut offset = 0;
+    for line in LinesWithEndings::from(text) {
+        for (i, op) in state.parse_line(line, syntax_set)? {
+            builder.apply(&mut stack, &op, offset + i)?;
+        }
+        offset += line.len();
+    }
+    Ok(builder.finish(text.len()))
+}
+
+pub(crate) struct SpanTreeBuilder {
+    /// The nodes of the scopes that are still open, outermost first
+    open: Vec<SpanNode>,
+    roots: Vec<SpanNode>,
+}
+
+impl SpanTreeBuilder {
+    pub(crate) fn new(scopes: &[Scope], start: usize) -> SpanTreeBuilder {
+        let mut builder = SpanTreeBuilder {
+            open: Vec::new(),
+            roots: Vec::new(),
+        };
+        for &scope in scopes {
+            builder.push(scope, start);
+        }
+        builder
+    }
+
+    pub(crate) fn apply(
+        &mut self,
+        stack: &mut ScopeStack,
+        op: &ScopeStackOp,
+        pos: usize,
+    ) -> Result<(), ScopeError> {
+        stack.apply_with_hook(op, |basic_op, _| match basic_op {
+            BasicScopeStackOp::Push(scope) => self.push(scope, pos),
+            BasicScopeStackOp::Pop => self.pop(pos),
+        })
+    }
+
+    fn push(&mut self, scope: Scope, start: usize) {
+        self.open.push(SpanNode {
+            scope,
+            range: start..start,
+            children: Vec::new(),
+        });
+    }
+
+    fn pop(&mut self, end: usize) {
+        let mut node = match self.open.pop() {
+            Some(node) => node,
+            None => return,
+        };
+        node.range.end = end;
+        if node.range.is_empty() {
+            return;
+        }
+        match self.open.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.roots.push(node),
+        }
+    }
+
+    pub(crate) fn finish(mut self, end: usize) -> Vec<SpanNode> {
+        while !self.open.is_empty() {
+            self.pop(end);
+        }
+        self.roots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn node(scope: &str, range: Range<usize>, children: Vec<SpanNode>) -> SpanNode {
+        SpanNode {
+            scope: Scope::new(scope).unwrap(),
+            range,
+            children,
+        }
+    }
+
+    #[test]
+    fn builds_nested_spans() {
+        let a = Scope::new("a").unwrap();
+        let b = Scope::new("b").unwrap();
+        let c = Scope::new("c").unwrap();
+        let ops = [
+            (1, ScopeStackOp::Push(b)),
+            (2, ScopeStackOp::Push(c)),
+            (3, ScopeStackOp::Pop(1)),
+            (3, ScopeStackOp::Push(c)),
+            (3, ScopeStackOp::Pop(2)),
+            (4, ScopeStackOp::Push(b)),
+        ];
+        let mut stack = ScopeStack::from_str("a").unwrap();
+        let tree = ops_to_span_tree("abcdef", &ops, &mut stack).unwrap();
+        assert_eq!(
+            tree,
+            vec![node(
+                "a",
+                0..6,
+                vec![
+                    node("b", 1..3, vec![node("c", 2..3, vec![])]),
+                    node("b", 4..6, vec![]),
+                ]
+            )]
+        );
+        assert_eq!(stack.as_slice(), &[a, b]);
+    }
+
+    #[cfg(feature = "default-syntaxes")]
+    #[test]
+    fn spans_cross_lines_in_text() {
+        let ss = SyntaxSet::load_defaults_newlines();
+        let syntax = ss.find_syntax_by_extension("rs").unwrap();
+        let text = "/* a\nb */\nfn f() {}\n";
+        let tree = span_tree_for_text(text, syntax, &ss).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].range, 0..text.len());
+        let comment = &tree[0].children[0];
+        assert_eq!(comment.range, 0..9);
+        assert!(comment.scope.build_string().starts_with("comment.block"));
+        assert!(tree[0]
+            .children
+            .windows(2)
+            .all(|w| w[0].range.end <= w[1].range.start));
+    }
+}
@@ -1,11 +1,93 @@
 //! Private library utilities that are not exposed to clients since we don't
 //! want to make semver guarantees about them
 
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
 use std::path::Path;
 
 use walkdir::WalkDir;
 
 /// Private helper to walk a dir and also follow symbolic links.
+///
+/// Entries are sorted by file name, so that what's loaded from a folder, and the dumps made
+/// from it, don't depend on the order the file system lists them in.
 pub fn walk_dir<P: AsRef<Path>>(folder: P) -> WalkDir {
-    WalkDir::new(folder).follow_links(true)
+    WalkDir::new(folder).follow_links(true).sort_by_file_name()
+}
+
+/// The tag that serialized syntax sets, theme sets and metadata start with
+///
+/// Dumps are bincode, which doesn't describe the fields it encodes, so without the tag a dump made
+/// by a version of syntect with different fields would be read as garbage, or fail somewhere in
+/// the middle with an unhelpful error. The last byte is bumped whenever one of the serialized
+/// structs changes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DumpFormat;
+
+/// The bytes of the current [`DumpFormat`], which an uncompressed dump starts with
+pub(crate) const DUMP_FORMAT_TAG: [u8; 8] = *b"syntect\x01";
+
+impl Serialize for DumpFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        DUMP_FORMAT_TAG.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for DumpFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if <[u8; 8]>::deserialize(deserializer)? == DUMP_FORMAT_TAG {
+            Ok(DumpFormat)
+        } else {
+            Err(D::Error::custom(
+                "the dump was made by a different version of syntect and has to be made again",
+            ))
+        }
+    }
+}
+
+/// A folder in the temporary folder of the system for the files of a test, removed when dropped
+#[cfg(test)]
+pub(crate) struct TempDir {
+    path: std::path::PathBuf,
+}
+
+#[cfg(test)]
+impl TempDir {
+    /// Creates an empty folder, named after the test and the process running it
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("syntect-{}-{}", name, std::process::id()));
+        // Left over from a run that was killed
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes a file at a path relative to the folder, creating the folders it's in
+    pub(crate) fn write(
+        &self,
+        path: impl AsRef<Path>,
+        contents: impl AsRef<[u8]>,
+    ) -> std::path::PathBuf {
+        let path = self.path.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
 }
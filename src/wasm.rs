@@ -0,0 +1,83 @@
+//! A small API for highlighting code from WebAssembly, enabled with the `wasm` feature
+//!
+//! Nothing here touches the filesystem: syntaxes come from the dump embedded in syntect and
+//! themes are passed in as the contents of a `.tmTheme` file, e.g. fetched by JavaScript. The
+//! functions only take and return strings and byte slices, so they're easy to expose with
+//! `wasm-bindgen`.
+//!
+//! Build with `default-features = false, features = ["wasm"]`, which uses the pure Rust
+//! `fancy-regex` engine since Oniguruma doesn't compile for `wasm32-unknown-unknown`.
+use crate::dumps::Defaults;
+use crate::highlighting::ThemeSet;
+use crate::html::highlighted_html_for_string;
+use crate::parsing::SyntaxSet;
+use crate::Error;
+
+/// The embedded default syntaxes, loaded on first use
+pub fn syntax_set() -> &'static SyntaxSet {
+    Defaults::global().syntax_set_newlines()
+}
+
+/// Highlights `code` as a `<pre>` snippet with inline styles
+///
+/// `lang` is a language name or file extension, as accepted by
+/// [`SyntaxSet::find_syntax_by_token`], and unknown languages are shown as plain text.
+/// `theme_bytes` is the contents of a `.tmTheme` file.
+///
+/// [`SyntaxSet::find_syntax_by_token`]: ../parsing/struct.SyntaxSet.html#method.find_syntax_by_token
+pub fn highlight_to_html(code: &str, lang: &str, theme_bytes: &[u8]) -> Result<String, Error> {
+    let theme = ThemeSet::load_from_bytes(theme_bytes)?;
+    let ss = syntax_set();
+    let syntax = ss
+        .find_syntax_by_token(lang)
+        .unwrap_or_else(|| ss.find_syntax_plain_text());
+    highlighted_html_for_string(code, ss, syntax, &theme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Test</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>background</key>
+                <string>#FFFFFF</string>
+                <key>foreground</key>
+                <string>#000000</string>
+            </dict>
+        </dict>
+        <dict>
+            <key>scope</key>
+            <string>keyword, storage</string>
+            <key>settings</key>
+            <dict>
+                <key>foreground</key>
+                <string>#FF0000</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn highlights_with_theme_bytes() {
+        let html = highlight_to_html("fn main() {}\n", "rs", THEME.as_bytes()).unwrap();
+        assert!(html.starts_with("<pre style=\"background-color:#ffffff;\">"));
+        assert!(html.contains("<span style=\"color:#ff0000;\">fn"));
+
+        let html = highlight_to_html("fn main() {}\n", "no such language", THEME.as_bytes());
+        assert!(!html.unwrap().contains("#ff0000"));
+
+        assert!(highlight_to_html("", "rs", b"not a theme").is_err());
+    }
+}